@@ -0,0 +1,224 @@
+use crate::compdb::CompileCommand;
+use crate::lint::{parse_diagnostics, Diagnostic, Severity};
+use crate::runner::spawn_error;
+use crate::toolchain;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tracing::{debug, info};
+
+/// Directory under the build directory that receives analyzer reports.
+pub const ANALYSIS_DIR: &str = "analysis";
+
+/// Static analyzer built into the compiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Analyzer {
+    /// `clang --analyze`, which writes a plist report per source.
+    Clang,
+    /// `gcc -fanalyzer`, which reports through ordinary diagnostics.
+    Gcc,
+}
+
+impl Analyzer {
+    /// Picks the analyzer matching `compiler`, looking at `--version` when
+    /// the name alone (e.g. `cc`) does not say which compiler it is.
+    pub fn detect(compiler: &str) -> Analyzer {
+        let name = Path::new(compiler)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if name.contains("clang") {
+            return Analyzer::Clang;
+        }
+        let version = toolchain::find_program(compiler)
+            .and_then(|path| toolchain::program_version(&path))
+            .unwrap_or_default();
+        if version.to_lowercase().contains("clang") {
+            Analyzer::Clang
+        } else {
+            Analyzer::Gcc
+        }
+    }
+
+    /// Rewrites a compile command so it analyzes the source instead of
+    /// producing the normal object, writing its output below `report_dir`.
+    pub fn command(&self, entry: &CompileCommand, report_dir: &Path) -> Command {
+        let stem = report_stem(entry);
+        let mut args = entry.arguments.iter();
+        let mut cmd = Command::new(args.next().map_or("cc", String::as_str));
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" => {}
+                "-o" => {
+                    args.next();
+                }
+                _ => {
+                    cmd.arg(arg);
+                }
+            }
+        }
+        match self {
+            Analyzer::Clang => cmd
+                .arg("--analyze")
+                .arg("-o")
+                .arg(report_dir.join(format!("{stem}.plist"))),
+            Analyzer::Gcc => cmd
+                .arg("-fanalyzer")
+                .arg("-c")
+                .arg("-o")
+                .arg(report_dir.join(format!("{stem}.o"))),
+        };
+        cmd.current_dir(&entry.directory);
+        cmd
+    }
+}
+
+/// Name shared by the reports of one source, taken from its object file.
+fn report_stem(entry: &CompileCommand) -> String {
+    entry
+        .output
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "source".into())
+}
+
+#[derive(Debug, Default)]
+pub struct AnalysisReport {
+    pub files: usize,
+    pub diagnostics: BTreeSet<Diagnostic>,
+    /// Sources the compiler could not analyze at all.
+    pub failures: Vec<String>,
+}
+
+impl AnalysisReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+}
+
+/// Analyzes every entry of a compilation database into `builddir/analysis/`.
+/// Each source also gets a `.log` with the analyzer's full output; normal
+/// objects in the build directory are never written.
+pub fn run(
+    commands: &[CompileCommand],
+    builddir: &Path,
+    parallelism: Option<usize>,
+) -> Result<AnalysisReport> {
+    let report_dir = std::path::absolute(builddir.join(ANALYSIS_DIR))
+        .context("Failed to resolve analysis directory")?;
+    fs::create_dir_all(&report_dir)
+        .with_context(|| format!("Failed to create {}", report_dir.display()))?;
+    let mut analyzers = HashMap::new();
+    let jobs: Vec<(Analyzer, &CompileCommand)> = commands
+        .iter()
+        .filter_map(|entry| {
+            let compiler = entry.arguments.first()?.clone();
+            let analyzer = *analyzers
+                .entry(compiler.clone())
+                .or_insert_with(|| Analyzer::detect(&compiler));
+            Some((analyzer, entry))
+        })
+        .collect();
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(parallelism.unwrap_or_else(num_cpus::get).max(1))
+        .build()
+        .context("Failed to create analysis thread pool")?;
+    let results: Vec<Result<(Vec<Diagnostic>, Option<String>)>> = pool.install(|| {
+        jobs.par_iter()
+            .map(|(analyzer, entry)| {
+                info!(file = %entry.file.display(), "Analyzing {}", entry.file.display());
+                let mut cmd = analyzer.command(entry, &report_dir);
+                let output = cmd
+                    .output()
+                    .map_err(|err| spawn_error(cmd.get_program(), err))?;
+                let text = format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                let log = report_dir.join(format!("{}.log", report_stem(entry)));
+                fs::write(&log, &text)
+                    .with_context(|| format!("Failed to write {}", log.display()))?;
+                let diagnostics = parse_diagnostics(&text);
+                debug!(file = %entry.file.display(), count = diagnostics.len(), "analysis finished");
+                let failure = (!output.status.success()).then(|| {
+                    format!(
+                        "{} exited with {} on {}",
+                        entry.arguments[0],
+                        output.status,
+                        entry.file.display()
+                    )
+                });
+                Ok((diagnostics, failure))
+            })
+            .collect()
+    });
+
+    let mut report = AnalysisReport::default();
+    for result in results {
+        let (diagnostics, failure) = result?;
+        report.files += 1;
+        report.diagnostics.extend(diagnostics);
+        report.failures.extend(failure);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_compile_command_for_each_analyzer() {
+        let entry = CompileCommand {
+            directory: "/work".into(),
+            file: "/work/src/main.c".into(),
+            arguments: vec![
+                "clang".into(),
+                "-fPIC".into(),
+                "-c".into(),
+                "/work/src/main.c".into(),
+                "-o".into(),
+                "/work/build/app_0.o".into(),
+            ],
+            output: "/work/build/app_0.o".into(),
+        };
+        assert_eq!(Analyzer::detect("/usr/bin/clang-17"), Analyzer::Clang);
+        let reports = Path::new("/work/build/analysis");
+
+        let clang = Analyzer::Clang.command(&entry, reports);
+        let args: Vec<_> = clang.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "-fPIC",
+                "/work/src/main.c",
+                "--analyze",
+                "-o",
+                "/work/build/analysis/app_0.plist"
+            ]
+        );
+
+        let gcc = Analyzer::Gcc.command(&entry, reports);
+        let args: Vec<_> = gcc.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "-fPIC",
+                "/work/src/main.c",
+                "-fanalyzer",
+                "-c",
+                "-o",
+                "/work/build/analysis/app_0.o"
+            ]
+        );
+    }
+}
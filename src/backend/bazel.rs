@@ -0,0 +1,343 @@
+use crate::backend::{declared_targets, Backend, BackendEmitResult};
+use crate::config;
+use crate::graph::{DependencyGraph, TargetKind, TargetNode};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Build system whose rules the export backend writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Bazel,
+    Buck2,
+}
+
+impl Dialect {
+    /// Name of the build file the dialect reads in each package.
+    pub fn build_file(self) -> &'static str {
+        match self {
+            Dialect::Bazel => "BUILD.bazel",
+            Dialect::Buck2 => "BUCK",
+        }
+    }
+
+    fn rule(self, kind: &TargetKind, test: bool) -> Option<&'static str> {
+        let rule = match (self, kind) {
+            (Dialect::Bazel, TargetKind::Executable) if test => "cc_test",
+            (Dialect::Bazel, TargetKind::Executable) => "cc_binary",
+            (Dialect::Bazel, TargetKind::StaticLibrary | TargetKind::SharedLibrary) => "cc_library",
+            (Dialect::Buck2, TargetKind::Executable) if test => "cxx_test",
+            (Dialect::Buck2, TargetKind::Executable) => "cxx_binary",
+            (Dialect::Buck2, TargetKind::StaticLibrary | TargetKind::SharedLibrary) => {
+                "cxx_library"
+            }
+            (_, TargetKind::CustomCommand) => "genrule",
+            _ => return None,
+        };
+        Some(rule)
+    }
+
+    fn public(self) -> &'static str {
+        match self {
+            Dialect::Bazel => "//visibility:public",
+            Dialect::Buck2 => "PUBLIC",
+        }
+    }
+}
+
+/// Exports the graph as a Bazel `BUILD.bazel` or Buck2 `BUCK` file with a
+/// C/C++ rule per compiled target and a `genrule` per custom command.
+/// Nothing is built; the file is meant to sit next to `crust.build`, so
+/// paths in it are relative to the manifest directory.
+pub struct BazelBackend {
+    dialect: Dialect,
+}
+
+impl BazelBackend {
+    pub fn new(dialect: Dialect) -> Self {
+        BazelBackend { dialect }
+    }
+}
+
+/// Starlark string literal.
+fn string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Starlark list, on one line when it has a single element and one element
+/// per line otherwise, the way buildifier lays out `srcs`.
+fn list(values: &[String]) -> String {
+    match values {
+        [value] => format!("[{}]", string(value)),
+        values => {
+            let items: String = values
+                .iter()
+                .map(|value| format!("        {},\n", string(value)))
+                .collect();
+            format!("[\n{items}    ]")
+        }
+    }
+}
+
+fn is_header(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|e| e.to_str()),
+        Some("h" | "hh" | "hpp" | "hxx" | "inc")
+    )
+}
+
+/// The shell command of a custom command target as a `genrule` `cmd`.
+/// crust exports `CRUST_BUILDDIR`; the closest equivalent is the rule's
+/// output directory.
+fn genrule_command(dialect: Dialect, command: &str) -> String {
+    match dialect {
+        // `$` starts a Make variable in Bazel; `$$` is a literal one.
+        Dialect::Bazel => format!(
+            "export CRUST_BUILDDIR=$(RULEDIR); {}",
+            command.replace('$', "$$")
+        ),
+        // Buck2 only expands `$(...)` macros; `\$(` is a shell substitution.
+        Dialect::Buck2 => format!(
+            "export CRUST_BUILDDIR=$OUT; {}",
+            command.replace("$(", "\\$(")
+        ),
+    }
+}
+
+fn rule(dialect: Dialect, node: &TargetNode, exported: &BTreeMap<&str, &TargetKind>) -> String {
+    let Some(kind) = dialect.rule(&node.kind, node.options.test) else {
+        return format!(
+            "# '{}' has no {:?} equivalent; build it with `crust build {}`.\n",
+            node.name, dialect, node.name
+        );
+    };
+    // Outputs of custom commands are files compiled into (or consumed by)
+    // the target; everything else it depends on is linked.
+    // Targets of subdir manifests keep their directory in the name, since
+    // everything is declared in one build file.
+    let mut deps = Vec::new();
+    let mut generated = Vec::new();
+    for dep in &node.dependencies {
+        let label = format!(":{}", config::label_path(dep));
+        match exported.get(dep.as_str()) {
+            Some(TargetKind::CustomCommand) => generated.push(label),
+            Some(_) if node.kind == TargetKind::CustomCommand => generated.push(label),
+            Some(_) => deps.push(label),
+            None => {}
+        }
+    }
+
+    let mut attrs = vec![("name", string(&config::label_path(&node.name)))];
+    if node.kind == TargetKind::CustomCommand {
+        let srcs: Vec<String> = node.sources.iter().cloned().chain(generated).collect();
+        if !srcs.is_empty() {
+            attrs.push(("srcs", list(&srcs)));
+        }
+        match dialect {
+            Dialect::Bazel => attrs.push(("outs", list(&node.outputs))),
+            Dialect::Buck2 => {
+                let outs: String = node
+                    .outputs
+                    .iter()
+                    .map(|out| format!("        {}: [{}],\n", string(out), string(out)))
+                    .collect();
+                attrs.push(("outs", format!("{{\n{outs}    }}")));
+            }
+        }
+        let command = node
+            .command
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        attrs.push(("cmd", string(&genrule_command(dialect, &command))));
+    } else {
+        // Headers of a library are its interface; an executable's are
+        // private and stay in `srcs`.
+        let (hdrs, mut srcs): (Vec<String>, Vec<String>) = if node.kind == TargetKind::Executable {
+            (Vec::new(), node.sources.clone())
+        } else {
+            node.sources.iter().cloned().partition(|s| is_header(s))
+        };
+        srcs.extend(generated);
+        if !srcs.is_empty() {
+            attrs.push(("srcs", list(&srcs)));
+        }
+        if !hdrs.is_empty() {
+            attrs.push(("hdrs", list(&hdrs)));
+        }
+        if !deps.is_empty() {
+            attrs.push(("deps", list(&deps)));
+        }
+        match (dialect, &node.kind) {
+            (Dialect::Bazel, TargetKind::StaticLibrary) => {
+                attrs.push(("linkstatic", "True".into()))
+            }
+            (Dialect::Buck2, TargetKind::StaticLibrary) => {
+                attrs.push(("preferred_linkage", string("static")))
+            }
+            (Dialect::Buck2, TargetKind::SharedLibrary) => {
+                attrs.push(("preferred_linkage", string("shared")))
+            }
+            _ => {}
+        }
+    }
+    attrs.push(("visibility", list(&[dialect.public().to_string()])));
+
+    let body: String = attrs
+        .iter()
+        .map(|(key, value)| format!("    {key} = {value},\n"))
+        .collect();
+    format!("{kind}(\n{body})\n")
+}
+
+/// Renders the build file for the declared targets of `graph`.
+pub fn render(graph: &DependencyGraph, dialect: Dialect) -> Result<String> {
+    let targets = declared_targets(graph)?;
+    let exported: BTreeMap<&str, &TargetKind> = targets
+        .iter()
+        .filter(|node| dialect.rule(&node.kind, false).is_some())
+        .map(|node| (node.name.as_str(), &node.kind))
+        .collect();
+
+    let mut content =
+        String::from("# Generated by crust from crust.build; place it next to the manifest.\n");
+    if dialect == Dialect::Bazel {
+        let mut rules: Vec<&str> = targets
+            .iter()
+            .filter_map(|node| dialect.rule(&node.kind, node.options.test))
+            .filter(|rule| rule.starts_with("cc_"))
+            .collect();
+        rules.sort_unstable();
+        rules.dedup();
+        if !rules.is_empty() {
+            let names: Vec<String> = rules.iter().map(|rule| string(rule)).collect();
+            content.push_str(&format!(
+                "\nload(\"@rules_cc//cc:defs.bzl\", {})\n",
+                names.join(", ")
+            ));
+        }
+    }
+    for node in targets {
+        content.push('\n');
+        content.push_str(&rule(dialect, node, &exported));
+    }
+    Ok(content)
+}
+
+impl Backend for BazelBackend {
+    fn name(&self) -> &str {
+        match self.dialect {
+            Dialect::Bazel => "bazel",
+            Dialect::Buck2 => "buck2",
+        }
+    }
+
+    fn emit(
+        &self,
+        graph: &DependencyGraph,
+        out_dir: &Path,
+        _manifest_dir: &Path,
+    ) -> Result<BackendEmitResult> {
+        fs::create_dir_all(out_dir)?;
+        let path = out_dir.join(self.dialect.build_file());
+        fs::write(&path, render(graph, self.dialect)?)?;
+        Ok(BackendEmitResult {
+            files: vec![path],
+            target_summaries: Vec::new(),
+        })
+    }
+
+    fn primary_outputs(&self, _graph: &DependencyGraph, out_dir: &Path) -> Vec<PathBuf> {
+        vec![out_dir.join(self.dialect.build_file())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
+
+    #[test]
+    fn exports_bazel_and_buck2_rules() {
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::CustomCommand {
+                    name: "version".into(),
+                    command: "echo \"#define V $(date +%Y)\" > version.h".into(),
+                    outputs: vec!["version.h".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    inputs: vec![],
+                    worker: None,
+                    programs: Vec::new(),
+                },
+                Target::StaticLibrary {
+                    name: "core".into(),
+                    sources: vec!["core.c".into(), "core.h".into()],
+                    deps: vec!["version".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+                Target::Executable {
+                    name: "app".into(),
+                    sources: vec!["main.c".into()],
+                    deps: vec!["core".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+
+        let bazel = render(&graph, Dialect::Bazel).unwrap();
+        assert!(bazel.contains("load(\"@rules_cc//cc:defs.bzl\", \"cc_binary\", \"cc_library\")"));
+        assert!(bazel.contains(
+            "cc_library(\n    name = \"core\",\n    srcs = [\n        \"core.c\",\n        \
+             \":version\",\n    ],\n    hdrs = [\"core.h\"],\n    linkstatic = True,\n"
+        ));
+        assert!(bazel.contains("    deps = [\":core\"],\n"));
+        assert!(bazel.contains("$$(date +%Y)"));
+
+        let buck = render(&graph, Dialect::Buck2).unwrap();
+        assert!(buck.contains("cxx_binary(\n    name = \"app\","));
+        assert!(buck.contains("\"version.h\": [\"version.h\"],"));
+        assert!(buck.contains("\\\\$(date +%Y)"));
+        assert!(buck.contains("visibility = [\"PUBLIC\"],"));
+    }
+}
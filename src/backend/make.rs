@@ -1,4 +1,6 @@
-use crate::backend::{Backend, BackendEmitResult, TargetBuildSummary};
+use crate::backend::{
+    alias_inputs, alias_rule, dependency_inputs, Backend, BackendEmitResult, TargetBuildSummary,
+};
 use crate::graph::{DependencyGraph, TargetKind};
 use anyhow::Result;
 use std::fs;
@@ -25,23 +27,40 @@ impl Backend for MakeBackend {
         content.push_str("BUILDDIR := .\n\n");
 
         for node in graph.topo_order()? {
-            let outputs: Vec<String> = node
-                .outputs
-                .iter()
-                .map(|o| format!("$(BUILDDIR)/{o}"))
-                .collect();
-            let deps: Vec<String> = node
-                .dependencies
-                .iter()
-                .map(|d| format!("$(BUILDDIR)/{d}"))
-                .collect();
-            let sources: Vec<String> = node
-                .sources
-                .iter()
-                .map(|s| format!("$(SRCROOT)/{s}"))
-                .collect();
+            if node.kind == TargetKind::Alias {
+                let rule = escape_path(&alias_rule(node));
+                content.push_str(&format!(".PHONY: {rule}\n"));
+                content.push_str(&format!(
+                    "{rule}: {}\n\n",
+                    alias_inputs(graph, node, "$(BUILDDIR)", escape_path).join(" ")
+                ));
+                continue;
+            }
+            let in_rule = |dir: &str, paths: &[String]| -> Vec<String> {
+                paths
+                    .iter()
+                    .map(|path| format!("$({dir})/{}", escape_path(path)))
+                    .collect()
+            };
+            // Recipes go to the shell, so only `$` needs escaping there;
+            // the shell quoting of list commands takes care of spaces.
+            let in_recipe = |dir: &str, paths: &[String]| -> Vec<String> {
+                paths
+                    .iter()
+                    .map(|path| format!("$({dir})/{}", path.replace('$', "$$")))
+                    .collect()
+            };
+            let outputs = in_rule("BUILDDIR", &node.outputs);
+            let (mut deps, aliases) = dependency_inputs(graph, node, "$(BUILDDIR)", escape_path);
+            // Order-only: members of an alias are built first, but nothing
+            // is rebuilt for them.
+            if !aliases.is_empty() {
+                deps.push("|".to_string());
+                deps.extend(aliases);
+            }
+            let sources = in_rule("SRCROOT", &node.sources);
 
-            for output in outputs {
+            for output in &outputs {
                 let mut rule = format!(
                     "{output}: {}",
                     (sources
@@ -52,7 +71,7 @@ impl Backend for MakeBackend {
                     .join(" ")
                 );
                 if rule.ends_with(':') {
-                    rule.push_str(" ");
+                    rule.push(' ');
                 }
                 content.push_str(&rule);
                 content.push('\n');
@@ -61,9 +80,14 @@ impl Backend for MakeBackend {
                     desc = display_name(&node.kind, &node.name)
                 ));
                 if let Some(cmd) = &node.command {
+                    let cmd = cmd.expand(
+                        &in_recipe("SRCROOT", &node.sources),
+                        &in_recipe("BUILDDIR", &node.outputs),
+                        "$(BUILDDIR)",
+                    );
                     content.push_str(&format!("\t{}\n", cmd));
                 } else {
-                    content.push_str("\t@touch $@\n");
+                    content.push_str("\t@touch \"$@\"\n");
                 }
                 content.push('\n');
             }
@@ -80,6 +104,7 @@ impl Backend for MakeBackend {
                 built: false,
                 outputs: node.outputs.iter().map(|o| out_dir.join(o)).collect(),
                 duration: Duration::default(),
+                diagnostics: Default::default(),
             })
             .collect();
 
@@ -94,12 +119,34 @@ impl Backend for MakeBackend {
     }
 }
 
+/// `path` as one word of a rule's targets or prerequisites, where spaces
+/// separate words, `:` ends the targets, `#` starts a comment, and `$` a
+/// variable.
+fn escape_path(path: &str) -> String {
+    path.replace('$', "$$")
+        .replace(' ', "\\ ")
+        .replace(':', "\\:")
+        .replace('#', "\\#")
+}
+
 fn display_name(kind: &TargetKind, name: &str) -> String {
     match kind {
         TargetKind::Executable => format!("executable {name}"),
         TargetKind::StaticLibrary => format!("static {name}"),
         TargetKind::SharedLibrary => format!("shared {name}"),
         TargetKind::CustomCommand => format!("custom {name}"),
+        TargetKind::Docs => format!("docs {name}"),
+        TargetKind::Gettext => format!("gettext {name}"),
+        TargetKind::Man => format!("man {name}"),
+        TargetKind::VcsVersion => format!("vcs_version {name}"),
+        TargetKind::Fetch => format!("fetch {name}"),
+        TargetKind::Extract => format!("extract {name}"),
+        TargetKind::CopyFile => format!("copy {name}"),
+        TargetKind::PythonExtension => format!("python_extension {name}"),
+        TargetKind::PythonSources => format!("python_sources {name}"),
+        TargetKind::RustExecutable => format!("rust_executable {name}"),
+        TargetKind::RustLibrary => format!("rust_library {name}"),
+        TargetKind::Alias => format!("alias {name}"),
     }
 }
 
@@ -107,6 +154,7 @@ fn display_name(kind: &TargetKind, name: &str) -> String {
 mod tests {
     use super::*;
     use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
     use tempfile::tempdir;
 
     #[test]
@@ -115,19 +163,92 @@ mod tests {
             project: ProjectInfo {
                 name: "demo".into(),
                 version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
             },
-            targets: vec![Target::StaticLibrary {
-                name: "util".into(),
-                sources: vec!["src/util.c".into()],
-                deps: vec![],
-            }],
+            targets: vec![
+                Target::StaticLibrary {
+                    name: "util".into(),
+                    sources: vec!["src/util.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+                Target::Alias {
+                    name: "libs".into(),
+                    deps: vec!["util".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                },
+                Target::CustomCommand {
+                    name: "report".into(),
+                    command: "touch report.txt".into(),
+                    outputs: vec!["report.txt".into()],
+                    deps: vec!["libs".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    inputs: vec![],
+                    worker: None,
+                    programs: Vec::new(),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
         };
-        let graph = DependencyGraph::from_manifest(&manifest).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
         let dir = tempdir().unwrap();
         let backend = MakeBackend;
         let result = backend.emit(&graph, dir.path(), dir.path()).unwrap();
         let content = std::fs::read_to_string(&result.files[0]).unwrap();
         assert!(content.contains("util"));
-        assert!(content.contains("touch $@"));
+        assert!(content.contains("touch \"$@\""));
+        assert!(content.contains(".PHONY: libs\nlibs: $(BUILDDIR)/libutil.a\n"));
+        // A target depending on an alias runs after its members.
+        assert!(
+            content.contains("$(BUILDDIR)/report.txt: | libs\n"),
+            "{content}"
+        );
+
+        // Paths with spaces, colons, `$`, and non-ASCII characters stay one
+        // word each, and one argument each in the recipe.
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "custom_command"
+name = "gen"
+inputs = ["in dir/dätä$1.txt"]
+outputs = ["out: ü.txt"]
+command = ["cp", "@INPUT@", "@OUTPUT@"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let result = backend.emit(&graph, dir.path(), dir.path()).unwrap();
+        let content = std::fs::read_to_string(&result.files[0]).unwrap();
+        assert!(
+            content.contains("$(BUILDDIR)/out\\:\\ ü.txt: $(SRCROOT)/in\\ dir/dätä$$1.txt\n"),
+            "{content}"
+        );
+        assert!(
+            content.contains("\tcp '$(SRCROOT)/in dir/dätä$$1.txt' '$(BUILDDIR)/out: ü.txt'\n"),
+            "{content}"
+        );
     }
 }
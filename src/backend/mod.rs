@@ -1,13 +1,18 @@
-use crate::graph::DependencyGraph;
-use anyhow::Result;
+use crate::config;
+use crate::diagnostics::Counts;
+use crate::graph::{DependencyGraph, TargetKind, TargetNode};
+use anyhow::{Context, Result};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+pub mod bazel;
 pub mod make;
 pub mod native;
 pub mod ninja;
+pub mod vs;
+pub mod xcode;
 
-pub trait Backend {
+pub trait Backend: Send + Sync {
     fn name(&self) -> &str;
     fn emit(
         &self,
@@ -29,19 +34,88 @@ pub struct BackendEmitResult {
     pub target_summaries: Vec<TargetBuildSummary>,
 }
 
-impl BackendEmitResult {
-    pub fn single(path: PathBuf) -> Self {
-        BackendEmitResult {
-            files: vec![path],
-            target_summaries: Vec::new(),
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct TargetBuildSummary {
     pub name: String,
     pub built: bool,
     pub outputs: Vec<PathBuf>,
     pub duration: Duration,
+    pub diagnostics: Counts,
+}
+
+/// Absolute form of `dir`; an empty path (a manifest in the working
+/// directory) means the working directory.
+pub fn absolute_dir(dir: &Path) -> Result<PathBuf> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    std::path::absolute(dir).with_context(|| format!("Failed to resolve {}", dir.display()))
+}
+
+/// The running crust binary, which IDE projects call to build targets.
+pub fn crust_program() -> PathBuf {
+    std::env::current_exe().unwrap_or_else(|_| PathBuf::from("crust"))
+}
+
+/// Name of the phony rule an alias target becomes in a generated build file.
+pub fn alias_rule(node: &TargetNode) -> String {
+    config::label_path(&node.name)
+}
+
+/// Prerequisites of the phony rule of the alias `node`: the outputs of its
+/// members below `builddir`, and the rules of member aliases, with `escape`
+/// applied to each path as the build file's syntax requires.
+pub fn alias_inputs(
+    graph: &DependencyGraph,
+    node: &TargetNode,
+    builddir: &str,
+    escape: fn(&str) -> String,
+) -> Vec<String> {
+    node.dependencies
+        .iter()
+        .filter_map(|dep| graph.get(dep))
+        .flat_map(|member| match member.kind {
+            TargetKind::Alias => vec![escape(&alias_rule(member))],
+            _ => member
+                .outputs
+                .iter()
+                .map(|output| format!("{builddir}/{}", escape(output)))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Splits the dependencies of `node` into the paths its rules depend on,
+/// below `builddir`, and the phony rules of the aliases among them, which
+/// the rules only run after since aliases produce nothing to depend on.
+/// `escape` is applied to each path as the build file's syntax requires.
+pub fn dependency_inputs(
+    graph: &DependencyGraph,
+    node: &TargetNode,
+    builddir: &str,
+    escape: fn(&str) -> String,
+) -> (Vec<String>, Vec<String>) {
+    let mut inputs = Vec::new();
+    let mut aliases = Vec::new();
+    for dep in &node.dependencies {
+        match graph.get(dep) {
+            Some(alias) if alias.kind == TargetKind::Alias => {
+                aliases.push(escape(&alias_rule(alias)))
+            }
+            _ => inputs.push(format!("{builddir}/{}", escape(&config::label_path(dep)))),
+        }
+    }
+    (inputs, aliases)
+}
+
+/// Targets as declared in the manifest, in build order, without the
+/// per-architecture variants `crust build` does not accept by name.
+pub fn declared_targets(graph: &DependencyGraph) -> Result<Vec<&TargetNode>> {
+    Ok(graph
+        .topo_order()?
+        .into_iter()
+        .filter(|node| node.arch.is_none())
+        .collect())
 }
@@ -1,18 +1,96 @@
 use crate::backend::{Backend, BackendEmitResult, TargetBuildSummary};
-use crate::executor::{BuildExecutor, TargetRunResult};
-use crate::graph::{DependencyGraph, TargetKind};
+use crate::builddir::STATE_DIR;
+use crate::compdb::{self, CompileCommand};
+use crate::config::{self, CommandLine, Profile, Rule};
+use crate::cross::CrossFile;
+use crate::cuda;
+use crate::depcheck;
+use crate::deps::Resolution;
+use crate::diagnostics::{self, Counts};
+use crate::digest::{sha256_bytes, tree_files};
+use crate::docs;
+use crate::embed;
+use crate::error::CrustError;
+use crate::executor::cancel::Cancellation;
+use crate::executor::checkpoint::Checkpoint;
+use crate::executor::command::{self, Action, CommandRunner, LocalRunner, Sandboxed, Traced};
+use crate::executor::worker::{WorkRequest, WorkerPool};
+use crate::executor::{self, BuildExecutor, LoadLimit, TargetRunResult};
+use crate::extract;
+use crate::fetch;
+use crate::flags::{self, Layer, Merged, Origin};
+use crate::fortran;
+use crate::graph::{self, DependencyGraph, TargetKind, TargetNode};
+use crate::hermetic::HermeticEnv;
+use crate::i18n;
+use crate::languages;
+use crate::man;
+use crate::objc;
+use crate::outdated::Reason;
+use crate::priority::Priority;
+use crate::provenance::{write_output_manifest, OutputRecord};
+use crate::python::Interpreter;
+use crate::remote::{Remote, RemoteExecutor};
+use crate::rust;
+use crate::sandbox::Sandbox;
+use crate::standards::{Lang, StdFlags};
+use crate::state::StateDb;
+use crate::toolchain;
+use crate::ui;
+use crate::vcs;
 use anyhow::{anyhow, Context, Result};
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::{Instant, SystemTime};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn, Span};
 
 #[derive(Clone)]
 pub struct CrustBackend {
     manifest_dir: PathBuf,
     parallelism: Option<usize>,
+    max_link_jobs: Option<usize>,
+    max_custom_jobs: Option<usize>,
+    sandbox: bool,
+    check_deps: bool,
+    load: LoadLimit,
+    /// Set by `--watchdog`.
+    watchdog: Option<Duration>,
+    /// Spawns the commands of targets.
+    local: LocalRunner,
+    /// `[project] launchers`, which targets override per kind of command.
+    launchers: BTreeMap<Rule, String>,
+    /// Set by `--remote-executor`: runs C-family compiles remotely.
+    remote: Option<Arc<RemoteExecutor>>,
+    cross: CrossFile,
+    /// Paths of the programs custom commands declare, found before building.
+    programs: HashMap<String, PathBuf>,
+    /// Interpreter python_extension targets compile against.
+    python: Option<Interpreter>,
+    standards: HashMap<String, StdFlags>,
+    workers: WorkerPool,
+    checkpoint: Option<Arc<Checkpoint>>,
+    state: Option<Arc<StateDb>>,
+    /// State of the last build, which the next one of this backend starts
+    /// from when it runs in the same process, as with `crust daemon`.
+    last_state: Arc<Mutex<Option<Arc<StateDb>>>>,
+    /// Set by [`CrustBackend::outdated`]: rebuild reasons are collected here
+    /// and nothing runs.
+    dry_run: Option<Arc<Mutex<Vec<Reason>>>>,
+    undeclared: Arc<Mutex<HashMap<String, BTreeSet<PathBuf>>>>,
+    /// Set by `--profile`, with its name: flags for every compile and link.
+    profile: Option<(String, Profile)>,
+    /// Found `[dependencies]`, whose flags the targets listing them get.
+    dependencies: Resolution,
+    /// Warnings and errors counted in the stderr of each target's commands.
+    diagnostics: Arc<Mutex<HashMap<String, Counts>>>,
+    trace_seq: Arc<AtomicUsize>,
 }
 
 impl CrustBackend {
@@ -20,34 +98,304 @@ impl CrustBackend {
         CrustBackend {
             manifest_dir,
             parallelism,
+            max_link_jobs: None,
+            max_custom_jobs: None,
+            sandbox: false,
+            check_deps: false,
+            load: LoadLimit::default(),
+            watchdog: None,
+            local: LocalRunner::default(),
+            launchers: BTreeMap::new(),
+            remote: None,
+            cross: CrossFile::default(),
+            programs: HashMap::new(),
+            python: None,
+            standards: HashMap::new(),
+            workers: WorkerPool::default(),
+            checkpoint: None,
+            state: None,
+            last_state: Arc::default(),
+            dry_run: None,
+            undeclared: Arc::default(),
+            profile: None,
+            dependencies: Resolution::default(),
+            diagnostics: Arc::default(),
+            trace_seq: Arc::default(),
         }
     }
 
-    fn needs_rebuild(&self, inputs: &[PathBuf], outputs: &[PathBuf]) -> Result<bool> {
-        if outputs.is_empty() {
-            return Ok(true);
+    /// Runs at most `max_link_jobs` executables and shared libraries and
+    /// `max_custom_jobs` custom commands at once.
+    pub fn with_job_caps(
+        mut self,
+        max_link_jobs: Option<usize>,
+        max_custom_jobs: Option<usize>,
+    ) -> Self {
+        self.max_link_jobs = max_link_jobs;
+        self.max_custom_jobs = max_custom_jobs;
+        self
+    }
+
+    /// Runs every spawned command under [`Sandbox`] so only its declared inputs
+    /// are visible and only the build directory is writable.
+    pub fn with_sandbox(mut self, enabled: bool) -> Self {
+        self.sandbox = enabled;
+        self
+    }
+
+    /// Traces every spawned command with `strace` and warns about project files
+    /// that were read without being declared as sources or inputs.
+    pub fn with_check_deps(mut self, enabled: bool) -> Self {
+        self.check_deps = enabled;
+        self
+    }
+
+    /// Adds the flags of the named `profile` to every compile and link.
+    pub fn with_profile(mut self, profile: Option<(String, Profile)>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Compiles and links targets against the found `dependencies` they
+    /// list in `external_deps`.
+    pub fn with_dependencies(mut self, dependencies: Resolution) -> Self {
+        self.dependencies = dependencies;
+        self
+    }
+
+    /// Runs every spawned command in `hermetic`'s environment rather than
+    /// crust's own.
+    pub fn with_hermetic(mut self, hermetic: Option<HermeticEnv>) -> Self {
+        self.local.hermetic = hermetic;
+        self
+    }
+
+    /// Kills every running command as soon as one fails, rather than
+    /// letting them finish.
+    pub fn with_fail_fast(mut self, enabled: bool) -> Self {
+        self.local.cancel = Arc::new(if enabled {
+            Cancellation::fail_fast()
+        } else {
+            Cancellation::default()
+        });
+        self
+    }
+
+    /// Aborts the build when no target starts or finishes for `timeout`.
+    pub fn with_watchdog(mut self, timeout: Option<Duration>) -> Self {
+        self.watchdog = timeout;
+        self
+    }
+
+    /// Starts no new target or compile while the load average is at least
+    /// `max_load`.
+    pub fn with_load_average(mut self, max_load: Option<f64>) -> Self {
+        self.load = LoadLimit::new(max_load);
+        self
+    }
+
+    /// Runs every spawned command at `priority`.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.local.priority = priority;
+        self
+    }
+
+    /// Starts each kind of command through its launcher, such as `ccache`
+    /// for compiles, unless a target overrides it.
+    pub fn with_launchers(mut self, launchers: BTreeMap<Rule, String>) -> Self {
+        self.launchers = launchers;
+        self
+    }
+
+    /// Runs C-family compiles on `remote` rather than this machine.
+    pub fn with_remote(mut self, remote: Option<Arc<RemoteExecutor>>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    /// Uses the compiler, archiver, and size tool named in a cross file
+    /// instead of the host defaults.
+    pub fn with_cross_file(mut self, cross: CrossFile) -> Self {
+        self.cross = cross;
+        self
+    }
+
+    /// Runs command lists whose program is one of `programs` with the path
+    /// found for it.
+    pub fn with_programs(mut self, programs: HashMap<String, PathBuf>) -> Self {
+        self.programs = programs;
+        self
+    }
+
+    /// Compiles python_extension targets against `python`'s headers.
+    pub fn with_python(mut self, python: Option<Interpreter>) -> Self {
+        self.python = python;
+        self
+    }
+
+    /// Selects the `c_std` and `cpp_std` of each target with `standards`,
+    /// spelled for its compiler by `standards::check`.
+    pub fn with_standards(mut self, standards: HashMap<String, StdFlags>) -> Self {
+        self.standards = standards;
+        self
+    }
+
+    /// Whether each target of `graph`, in build order, is up to date in
+    /// `out_dir`, or why building would run it again. Every target is checked
+    /// the way a build checks it, against the state database, but nothing is
+    /// run and the state is left alone. A target depending on an out-of-date
+    /// one is out of date too.
+    pub fn outdated(
+        &self,
+        graph: &DependencyGraph,
+        out_dir: &Path,
+    ) -> Result<Vec<(String, Option<Reason>)>> {
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let mut backend = self.clone();
+        backend.state = Some(Arc::new(StateDb::load(out_dir)));
+        backend.dry_run = Some(Arc::clone(&found));
+        let mut statuses: Vec<(String, Option<Reason>)> = Vec::new();
+        for node in graph.topo_order()? {
+            let dep_outputs: Vec<PathBuf> = node
+                .dependencies
+                .iter()
+                .filter_map(|dep| graph.get(dep))
+                .flat_map(|dep| dep.products().map(|output| out_dir.join(output)))
+                .collect();
+            backend.execute_target(node, &dep_outputs, out_dir)?;
+            let own = found
+                .lock()
+                .expect("dry run mutex poisoned")
+                .drain(..)
+                .next();
+            let reason = own.or_else(|| {
+                node.dependencies
+                    .iter()
+                    .find(|dep| {
+                        statuses
+                            .iter()
+                            .any(|(name, reason)| name == *dep && reason.is_some())
+                    })
+                    .map(|dep| Reason::Dependency(dep.clone()))
+            });
+            statuses.push((node.name.clone(), reason));
         }
+        Ok(statuses)
+    }
 
-        for output in outputs {
-            if !output.exists() {
-                return Ok(true);
+    /// Compile commands for every C-family source in `graph`, with absolute
+    /// paths so clang tools can use them from any directory. Sources shared
+    /// by several targets are listed once.
+    pub fn compile_database(
+        &self,
+        graph: &DependencyGraph,
+        out_dir: &Path,
+    ) -> Result<Vec<CompileCommand>> {
+        let directory = std::env::current_dir().context("Failed to read working directory")?;
+        let manifest_dir = directory.join(&self.manifest_dir);
+        let out_dir = directory.join(out_dir);
+        let mut seen = HashSet::new();
+        let mut entries = Vec::new();
+        for node in graph.topo_order()? {
+            if node.command.is_some() {
+                continue;
+            }
+            for (idx, source) in node.sources.iter().enumerate() {
+                let file = manifest_dir.join(source);
+                if !compdb::is_c_family(&file) || !seen.insert(file.clone()) {
+                    continue;
+                }
+                let output = object_path(&out_dir, node, idx);
+                let cmd = self.compile_command(node, &out_dir, &file, &output);
+                let arguments = std::iter::once(cmd.get_program())
+                    .chain(cmd.get_args())
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect();
+                entries.push(CompileCommand {
+                    directory: directory.clone(),
+                    file,
+                    arguments,
+                    output,
+                });
             }
         }
+        Ok(entries)
+    }
+
+    /// Whether the edge running `command` must produce `outputs` again. In a
+    /// dry run the reason is collected instead, and the edge is treated as up
+    /// to date so nothing runs.
+    fn needs_rebuild(
+        &self,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        command: &str,
+    ) -> Result<bool> {
+        let reason = self.rebuild_reason(inputs, outputs, command)?;
+        if let Some(found) = &self.dry_run {
+            found.lock().expect("dry run mutex poisoned").extend(reason);
+            return Ok(false);
+        }
+        Ok(reason.is_some())
+    }
+
+    /// Why the edge running `command` must produce `outputs` again, if it
+    /// must. An edge recorded in the state database is stale when its command
+    /// line or the content of its inputs changed; one that is not yet
+    /// recorded compares modification times, and is recorded when found up
+    /// to date.
+    fn rebuild_reason(
+        &self,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        command: &str,
+    ) -> Result<Option<Reason>> {
+        if outputs.is_empty() {
+            return Ok(Some(Reason::NoOutputs));
+        }
 
-        let latest_input = self.latest_mod_time(inputs)?;
+        if let Some(output) = outputs.iter().find(|output| !output.exists()) {
+            return Ok(Some(Reason::MissingOutput(output.clone())));
+        }
+
+        if let Some(reason) = self
+            .state
+            .as_ref()
+            .and_then(|state| state.stale_reason(inputs, outputs, command))
+        {
+            return Ok(reason);
+        }
         let oldest_output = self.oldest_mod_time(outputs)?;
-        Ok(latest_input > oldest_output)
+        for input in inputs {
+            if self.latest_mod_time(std::slice::from_ref(input))? > oldest_output {
+                return Ok(Some(Reason::NewerInput(input.clone())));
+            }
+        }
+        self.record_edge(inputs, outputs, command);
+        Ok(None)
+    }
+
+    /// Records in the state database that `command` produced `outputs`.
+    fn record_edge(&self, inputs: &[PathBuf], outputs: &[PathBuf], command: &str) {
+        if let Some(state) = &self.state {
+            state.record(inputs, outputs, command);
+        }
     }
 
+    /// Newest modification time among `paths`, counting every file below a
+    /// directory as well as the directory itself.
     fn latest_mod_time(&self, paths: &[PathBuf]) -> Result<SystemTime> {
-        let mut latest = SystemTime::UNIX_EPOCH;
-        for path in paths {
-            if path.exists() {
-                let modified = fs::metadata(path)?.modified()?;
-                latest = latest.max(modified);
+        let mut files = Vec::new();
+        for path in paths.iter().filter(|path| path.exists()) {
+            if path.is_dir() {
+                files.extend(tree_files(path)?);
             }
+            files.push(path.clone());
         }
-        Ok(latest)
+        files
+            .par_iter()
+            .map(|path| Ok(fs::metadata(path)?.modified()?))
+            .try_reduce(|| SystemTime::UNIX_EPOCH, |a, b| Ok(a.max(b)))
     }
 
     fn oldest_mod_time(&self, paths: &[PathBuf]) -> Result<SystemTime> {
@@ -59,12 +407,8 @@ impl CrustBackend {
         oldest.ok_or_else(|| anyhow!("No paths provided for modification time check"))
     }
 
-    fn compile_objects(
-        &self,
-        sources: &[String],
-        out_dir: &Path,
-        target_name: &str,
-    ) -> Result<Vec<PathBuf>> {
+    fn compile_objects(&self, node: &TargetNode, out_dir: &Path) -> Result<Vec<PathBuf>> {
+        let (sources, target_name) = (&node.sources, &node.name);
         let threads = self.parallelism.unwrap_or_else(|| num_cpus::get().max(1));
         let manifest_dir = self.manifest_dir.clone();
         let pool = ThreadPoolBuilder::new()
@@ -72,53 +416,720 @@ impl CrustBackend {
             .build()
             .context("Failed to build compile thread pool")?;
 
-        pool.install(|| {
-            sources
-                .par_iter()
-                .enumerate()
-                .map(|(idx, source)| {
-                    let source_path = manifest_dir.join(source);
-                    let object_path = out_dir.join(format!("{target_name}_{idx}.o"));
-
-                    if !self.needs_rebuild(&[source_path.clone()], &[object_path.clone()])? {
-                        return Ok(object_path);
-                    }
-
-                    if let Some(parent) = object_path.parent() {
-                        fs::create_dir_all(parent)?;
-                    }
-
-                    println!(
-                        "Compiling {} -> {}",
-                        source_path.display(),
-                        object_path.display()
-                    );
-                    let status = Command::new("cc")
-                        .arg("-c")
-                        .arg(&source_path)
-                        .arg("-o")
-                        .arg(&object_path)
-                        .status()
-                        .with_context(|| format!("Failed to spawn compiler for {}", source))?;
-                    if !status.success() {
-                        return Err(anyhow!("Compilation failed for {}", source));
-                    }
-                    Ok(object_path)
+        let declared: Vec<PathBuf> = sources.iter().map(|s| manifest_dir.join(s)).collect();
+        // Objects are rebuilt when a generated version header changes too.
+        let headers: Vec<PathBuf> = node
+            .generated_headers
+            .iter()
+            .map(|header| out_dir.join(header))
+            .collect();
+        // Fortran sources wait for the sources defining the modules they use,
+        // and are rebuilt when those change.
+        let modules = self.scan_fortran_modules(node, out_dir)?;
+        let waves = fortran::compile_waves(&modules, sources)?;
+        let providers = fortran::providers(&modules);
+        let span = Span::current();
+        let mut objects = vec![PathBuf::new(); sources.len()];
+        for wave in waves {
+            let built: Vec<(usize, PathBuf)> = pool.install(|| {
+                wave.par_iter()
+                    .map(|&idx| {
+                        let _guard = span.enter();
+                        let source = &sources[idx];
+                        let source_path = manifest_dir.join(source);
+                        let mut inputs = vec![source_path.clone()];
+                        inputs.extend_from_slice(&headers);
+                        inputs.extend(
+                            modules[idx]
+                                .uses
+                                .iter()
+                                .filter_map(|module| providers.get(module.as_str()))
+                                .map(|&provider| object_path(out_dir, node, provider)),
+                        );
+                        let object_path = object_path(out_dir, node, idx);
+
+                        let cmd = self.compile_command(node, out_dir, &source_path, &object_path);
+                        let command = render_command(&cmd);
+                        let outputs = std::slice::from_ref(&object_path);
+                        if !self.needs_rebuild(&inputs, outputs, &command)? {
+                            return Ok((idx, object_path));
+                        }
+
+                        if let Some(parent) = object_path.parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+
+                        info!(
+                            source = %source_path.display(),
+                            object = %object_path.display(),
+                            "Compiling {} -> {}",
+                            source_path.display(),
+                            object_path.display()
+                        );
+                        self.load.run(|| {
+                            self.run_tool(
+                                node,
+                                Action {
+                                    rule: Rule::Compile,
+                                    cmd,
+                                    inputs: &declared,
+                                    out_dir,
+                                    target: target_name,
+                                    description: &format!("Compilation of {}", source),
+                                },
+                            )
+                        })?;
+                        self.record_edge(&inputs, outputs, &command);
+                        Ok((idx, object_path))
+                    })
+                    .collect::<Result<_>>()
+            })?;
+            for (idx, object) in built {
+                objects[idx] = object;
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Modules each source of `node` defines and uses; empty for sources
+    /// that are not Fortran. Creates the directory `-J` points the compiler
+    /// at, which it does not create itself.
+    fn scan_fortran_modules(
+        &self,
+        node: &TargetNode,
+        out_dir: &Path,
+    ) -> Result<Vec<fortran::ModuleInfo>> {
+        let mut modules = Vec::with_capacity(node.sources.len());
+        for source in &node.sources {
+            let path = self.manifest_dir.join(source);
+            if !fortran::is_fortran(&path) {
+                modules.push(fortran::ModuleInfo::default());
+                continue;
+            }
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            modules.push(fortran::ModuleInfo::scan(&text));
+        }
+        if node.has_fortran_sources() {
+            fs::create_dir_all(out_dir.join(fortran::module_dir(&config::label_path(&node.name))))?;
+        }
+        Ok(modules)
+    }
+
+    /// Generates a C source per `embed_files` entry and compiles it, returning
+    /// the objects to link. Both steps are skipped when up to date.
+    fn embed_objects(&self, node: &TargetNode, out_dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut objects = Vec::new();
+        for (idx, file) in node.options.embed_files.iter().enumerate() {
+            let input = self.manifest_dir.join(file);
+            let (source_path, object_path) = embed_paths(out_dir, &node.name, idx);
+
+            let inputs = std::slice::from_ref(&input);
+            let sources = std::slice::from_ref(&source_path);
+            let generate = format!("embed {}", embed::symbol_name(file));
+            if self.needs_rebuild(inputs, sources, &generate)? {
+                let bytes = fs::read(&input)
+                    .with_context(|| format!("Failed to read embedded file {}", input.display()))?;
+                info!(file = %file, "Embedding {}", file);
+                if let Some(parent) = source_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(
+                    &source_path,
+                    embed::render_c_source(&embed::symbol_name(file), &bytes),
+                )?;
+                self.record_edge(inputs, sources, &generate);
+            }
+
+            let cmd = self.compile_command(node, out_dir, &source_path, &object_path);
+            let command = render_command(&cmd);
+            let objects_built = std::slice::from_ref(&object_path);
+            if self.needs_rebuild(sources, objects_built, &command)? {
+                self.run_tool(
+                    node,
+                    Action {
+                        rule: Rule::Compile,
+                        cmd,
+                        inputs: sources,
+                        out_dir,
+                        target: &node.name,
+                        description: &format!("Compilation of embedded {}", file),
+                    },
+                )?;
+                self.record_edge(sources, objects_built, &command);
+            }
+            objects.push(object_path);
+        }
+        Ok(objects)
+    }
+
+    /// Flags compiling the C-family `source` of `node`, merged from every
+    /// origin in precedence order (see `crate::flags`).
+    fn c_flags(&self, node: &TargetNode, source: &Path) -> Merged {
+        let platform = self.cross.platform().compile_flags(&node.kind);
+        let mut layers = vec![
+            Layer::new(
+                Origin::Platform,
+                platform.iter().map(|flag| flag.to_string()),
+            ),
+            Layer::new(Origin::CrossFile, self.cross.compile_args()),
+        ];
+        if let Some((name, profile)) = &self.profile {
+            layers.push(Layer::new(
+                Origin::Profile(name.clone()),
+                profile.compile_args(),
+            ));
+        }
+        for name in &node.options.external_deps {
+            if let Some(found) = self.dependencies.found.get(name) {
+                layers.push(Layer::new(
+                    Origin::External(name.clone()),
+                    found.compile_flags(name),
+                ));
+            }
+        }
+        layers.extend(node.inherited_c_args.iter().cloned());
+        let options = &node.options;
+        layers.push(Layer::new(
+            Origin::Target,
+            options.c_args.iter().chain(&options.public_c_args).cloned(),
+        ));
+        layers.push(Layer::new(
+            Origin::Setting("objc_arc"),
+            objc::compile_flags(source, options.objc_arc),
+        ));
+        if let Some(lang) = Lang::of(source) {
+            let flag = self
+                .standards
+                .get(&node.name)
+                .and_then(|flags| flags.for_source(source));
+            layers.push(Layer::new(
+                Origin::Setting(lang.field()),
+                flag.map(str::to_string),
+            ));
+        }
+        flags::merge(&layers)
+    }
+
+    /// Warns, once per target and language, about compile flags that
+    /// override each other.
+    fn warn_flag_conflicts(&self, graph: &DependencyGraph) {
+        for node in graph.nodes() {
+            let mut langs = Vec::new();
+            for source in &node.sources {
+                let source = Path::new(source);
+                let lang = Lang::of(source);
+                if (lang.is_none() && !cuda::is_cuda(source)) || langs.contains(&lang) {
+                    continue;
+                }
+                langs.push(lang);
+                for conflict in self.c_flags(node, source).conflicts {
+                    warn!(target_name = %node.name, "target '{}': {}", node.name, conflict);
+                }
+            }
+        }
+    }
+
+    fn compile_command(
+        &self,
+        node: &TargetNode,
+        out_dir: &Path,
+        source_path: &Path,
+        object_path: &Path,
+    ) -> Command {
+        if let Some(language) = languages::for_source(source_path) {
+            let (compiler, profile) = language.resolve(self.cross.language_compiler(language.name));
+            let mut cmd = Command::new(compiler);
+            cmd.args(profile.compile_args(source_path, object_path));
+            return cmd;
+        }
+        let mut cmd = if fortran::is_fortran(source_path) {
+            let mut cmd = Command::new(self.cross.fc());
+            cmd.args(self.cross.platform().compile_flags(&node.kind))
+                .arg("-J")
+                .arg(out_dir.join(fortran::module_dir(&config::label_path(&node.name))));
+            for dir in &node.module_dirs {
+                cmd.arg("-I").arg(out_dir.join(dir));
+            }
+            cmd
+        } else if cuda::is_cuda(source_path) {
+            // nvcc compiles the host half of the source with the C compiler,
+            // which receives the flags it would get for a C source.
+            let (compiler, mut host_flags) = self.cross.compiler_for(node.arch.as_deref());
+            host_flags.extend(self.c_flags(node, source_path).flags);
+            let mut cmd = Command::new(self.cross.nvcc());
+            cmd.arg("-ccbin")
+                .arg(compiler)
+                .args(cuda::gencode_flags(&node.options.cuda_arch))
+                .args(cuda::forward_host_flags(&host_flags));
+            if node.options.cuda_separable {
+                cmd.arg("-rdc=true");
+            }
+            cmd
+        } else {
+            let (compiler, arch_flags) = self.cross.compiler_for(node.arch.as_deref());
+            let mut cmd = Command::new(compiler);
+            cmd.args(arch_flags)
+                .args(self.c_flags(node, source_path).flags);
+            cmd
+        };
+        let mut include_dirs: Vec<PathBuf> = node
+            .generated_headers
+            .iter()
+            .filter_map(|header| Some(out_dir.join(header).parent()?.to_path_buf()))
+            .collect();
+        include_dirs.dedup();
+        if node.kind == TargetKind::PythonExtension {
+            include_dirs.extend(
+                self.python
+                    .as_ref()
+                    .map(|python| python.include_dir.clone()),
+            );
+        }
+        for dir in include_dirs {
+            cmd.arg("-I").arg(dir);
+        }
+        cmd.arg("-c").arg(source_path).arg("-o").arg(object_path);
+        cmd
+    }
+
+    /// Builds the link (or archive) command producing `output` for `node`.
+    fn link_command(
+        &self,
+        node: &TargetNode,
+        output: &Path,
+        objects: &[PathBuf],
+        dep_outputs: &[PathBuf],
+    ) -> Command {
+        let (compiler, arch_flags) = self.cross.compiler_for(node.arch.as_deref());
+        // The Fortran driver adds its runtime library when Fortran code,
+        // here or in a dependency, is linked.
+        let compiler = if node.links_fortran() {
+            self.cross.fc()
+        } else {
+            compiler
+        };
+        let mut cmd = match node.kind {
+            TargetKind::StaticLibrary => {
+                let mut cmd = Command::new(self.cross.archiver_for(node.arch.as_deref()));
+                cmd.arg("rcs").arg(output);
+                cmd
+            }
+            TargetKind::SharedLibrary | TargetKind::PythonExtension => {
+                let mut cmd = Command::new(compiler);
+                cmd.args(&arch_flags).arg("-shared").arg("-o").arg(output);
+                // libpython symbols come from the interpreter loading the
+                // module; Linux leaves them undefined by default.
+                if node.kind == TargetKind::PythonExtension && cfg!(target_os = "macos") {
+                    cmd.arg("-Wl,-undefined,dynamic_lookup");
+                }
+                cmd
+            }
+            _ => {
+                let mut cmd = Command::new(compiler);
+                cmd.args(&arch_flags).arg("-o").arg(output);
+                cmd
+            }
+        };
+        cmd.args(objects);
+        if node.kind != TargetKind::StaticLibrary {
+            let links_shared = dep_outputs.iter().any(|o| {
+                o.extension()
+                    .is_some_and(|ext| ext == "so" || ext == "wasm")
+            });
+            cmd.args(
+                self.cross
+                    .platform()
+                    .link_flags(&node.kind, output, links_shared),
+            );
+            cmd.args(self.cross.link_args());
+            if let Some((_, profile)) = &self.profile {
+                cmd.args(profile.link_args());
+            }
+            cmd.args(dep_outputs);
+            for name in &node.external_deps {
+                if let Some(found) = self.dependencies.found.get(name) {
+                    cmd.args(&found.link_args);
+                }
+            }
+            cmd.args(objc::link_flags(
+                &node.frameworks,
+                node.objc_runtime,
+                cfg!(target_os = "macos"),
+            ));
+            for language in node
+                .languages
+                .iter()
+                .filter_map(|name| languages::named(name))
+            {
+                let (compiler, profile) =
+                    language.resolve(self.cross.language_compiler(language.name));
+                let compiler = toolchain::find_program(&compiler);
+                cmd.args(profile.link_flags(compiler.as_deref()));
+            }
+            if node.cuda_runtime {
+                let nvcc = toolchain::find_program(self.cross.nvcc());
+                cmd.args(cuda::runtime_link_flags(
+                    nvcc.as_deref(),
+                    node.cuda_device_link.is_some(),
+                ));
+            }
+        }
+        if let Some(script) = &node.options.linker_script {
+            cmd.arg("-T").arg(self.manifest_dir.join(script));
+        }
+        if node.options.generate_map {
+            let map = graph::map_file(&output.to_string_lossy());
+            cmd.arg(format!("-Wl,-Map={map}"));
+        }
+        if node.options.bolt.is_some() {
+            // BOLT can only move code around when relocations are kept.
+            cmd.arg("-Wl,--emit-relocs");
+        }
+        cmd
+    }
+
+    /// `nvcc -dlink` command resolving the relocatable device code of
+    /// `node`'s CUDA objects and of the static libraries it links, with the
+    /// object it writes, when `node` needs device linking.
+    fn device_link_command(
+        &self,
+        node: &TargetNode,
+        objects: &[PathBuf],
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Option<(Command, PathBuf)> {
+        let archs = node.cuda_device_link.as_ref()?;
+        let mut inputs: Vec<PathBuf> = node
+            .sources
+            .iter()
+            .zip(objects)
+            .filter(|(source, _)| cuda::is_cuda(Path::new(source)))
+            .map(|(_, object)| object.clone())
+            .collect();
+        inputs.extend(
+            dep_outputs
+                .iter()
+                .filter(|output| output.extension().is_some_and(|ext| ext == "a"))
+                .cloned(),
+        );
+        let output = out_dir.join(cuda::device_link_object(&config::label_path(&node.name)));
+        let (compiler, _) = self.cross.compiler_for(node.arch.as_deref());
+        let cmd = cuda::device_link_command(self.cross.nvcc(), compiler, archs, &inputs, &output);
+        Some((cmd, output))
+    }
+
+    /// Where the linked, unoptimized executable is kept when `bolt` is set.
+    fn prebolt_path(output: &Path) -> PathBuf {
+        PathBuf::from(graph::prebolt_file(&output.to_string_lossy()))
+    }
+
+    /// `llvm-bolt` optimizing the linked executable into `output`.
+    fn bolt_command(&self, node: &TargetNode, output: &Path) -> Option<Command> {
+        let settings = node.options.bolt.as_ref()?;
+        let mut cmd = Command::new(self.cross.bolt());
+        cmd.arg(Self::prebolt_path(output)).arg("-o").arg(output);
+        if let Some(profile) = &settings.profile {
+            let profile = self.manifest_dir.join(profile);
+            if profile.extension().is_some_and(|ext| ext == "fdata") {
+                cmd.arg(format!("-data={}", profile.display()));
+            } else {
+                cmd.arg("-p").arg(profile);
+            }
+        }
+        cmd.args(&settings.args);
+        Some(cmd)
+    }
+
+    /// `command` run by the platform's shell: `sh -c`, or `cmd /C` on
+    /// Windows.
+    fn shell_command(&self, command: &str, out_dir: &Path) -> Command {
+        let mut cmd = platform_shell(command);
+        cmd.env("CRUST_BUILDDIR", out_dir);
+        self.in_manifest_dir(&mut cmd);
+        cmd
+    }
+
+    /// The process of a custom command: a string goes through the shell,
+    /// while an argument list runs directly with its placeholders expanded.
+    /// A program the target declares in `programs` runs from the path found
+    /// for it, any other relative path is found from the manifest directory,
+    /// and a bare name is looked up on `PATH`.
+    fn custom_command(&self, node: &TargetNode, command: &CommandLine, out_dir: &Path) -> Command {
+        let absolute = |path: PathBuf| {
+            std::path::absolute(&path)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .into_owned()
+        };
+        let outputs: Vec<String> = node
+            .outputs
+            .iter()
+            .map(|output| absolute(out_dir.join(output)))
+            .collect();
+        let args = match command.expand(&node.sources, &outputs, &absolute(out_dir.to_path_buf())) {
+            CommandLine::Shell(command) => return self.shell_command(&command, out_dir),
+            CommandLine::Argv(args) => args,
+        };
+        let (program, args) = args.split_first().expect("argv commands are not empty");
+        let resolved = node
+            .programs
+            .iter()
+            .any(|declared| declared.name == *program)
+            .then(|| self.programs.get(program))
+            .flatten();
+        let program = Path::new(program);
+        let mut cmd = if let Some(resolved) = resolved {
+            Command::new(resolved)
+        } else if program.components().count() > 1 && program.is_relative() {
+            Command::new(self.manifest_dir.join(program))
+        } else {
+            Command::new(program)
+        };
+        cmd.args(args).env("CRUST_BUILDDIR", out_dir);
+        self.in_manifest_dir(&mut cmd);
+        cmd
+    }
+
+    /// Runs `cmd` from the manifest directory. A manifest given by bare file
+    /// name has an empty directory, which already is the working directory.
+    fn in_manifest_dir(&self, cmd: &mut Command) {
+        if !self.manifest_dir.as_os_str().is_empty() {
+            cmd.current_dir(&self.manifest_dir);
+        }
+    }
+
+    /// Builds a post-link hook command from `template`, with `${OUTPUT}`
+    /// replaced by the shell-quoted path of `output`.
+    pub fn hook_command(&self, template: &str, output: &Path, out_dir: &Path) -> Command {
+        let output = std::path::absolute(output).unwrap_or_else(|_| output.to_path_buf());
+        let command = template.replace("${OUTPUT}", &shell_quote(&output.to_string_lossy()));
+        self.shell_command(&command, out_dir)
+    }
+
+    /// Every command that produces `node`'s outputs, in execution order.
+    fn target_commands(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Vec<Command> {
+        if let Some(command) = &node.command {
+            return vec![self.custom_command(node, command, out_dir)];
+        }
+        if node.kind == TargetKind::Docs {
+            return vec![self.doxygen_command("doxygen", &docs_dir(node, out_dir))];
+        }
+        if node.kind == TargetKind::Gettext {
+            return catalog_pairs(node, &self.manifest_dir, out_dir)
+                .map(|(po, mo)| i18n::msgfmt_command(&po, &mo))
+                .collect();
+        }
+        if node.kind == TargetKind::Man {
+            let tool = node.man.as_ref().and_then(|man| man.tool.as_deref());
+            return node
+                .sources
+                .iter()
+                .zip(&node.outputs)
+                .filter_map(|(source, output)| {
+                    let (converter, program) = man::detect(source, tool).ok()?;
+                    Some(converter.command(
+                        &program,
+                        &self.manifest_dir.join(source),
+                        &out_dir.join(output),
+                    ))
                 })
-                .collect()
-        })
+                .collect();
+        }
+        if matches!(
+            node.kind,
+            TargetKind::VcsVersion
+                | TargetKind::Extract
+                | TargetKind::CopyFile
+                | TargetKind::PythonSources
+                | TargetKind::Alias
+        ) {
+            return Vec::new();
+        }
+        if let Some(settings) = &node.fetch {
+            return vec![settings.command(&settings.cached(&fetch::download_dir()))];
+        }
+        if let Some(settings) = &node.rust {
+            return vec![rust::command(
+                self.cross.rustc(),
+                settings,
+                config::split_label(&node.name).1,
+                &self.manifest_dir.join(&node.sources[0]),
+                &out_dir.join(&node.outputs[0]),
+                dep_outputs,
+                out_dir,
+            )];
+        }
+        if node.combines_architectures() {
+            if !node.options.universal {
+                return Vec::new();
+            }
+            let output = out_dir.join(&node.outputs[0]);
+            let mut commands = vec![self.lipo_command(node, &output, out_dir)];
+            commands.extend(self.hook_commands(node, &output, out_dir));
+            return commands;
+        }
+
+        let mut objects: Vec<PathBuf> = (0..node.sources.len())
+            .map(|idx| object_path(out_dir, node, idx))
+            .collect();
+        let mut commands: Vec<Command> = node
+            .sources
+            .iter()
+            .zip(&objects)
+            .map(|(source, object)| {
+                self.compile_command(node, out_dir, &self.manifest_dir.join(source), object)
+            })
+            .collect();
+        for idx in 0..node.options.embed_files.len() {
+            let (source, object) = embed_paths(out_dir, &node.name, idx);
+            commands.push(self.compile_command(node, out_dir, &source, &object));
+            objects.push(object);
+        }
+        if let Some((cmd, object)) = self.device_link_command(node, &objects, dep_outputs, out_dir)
+        {
+            commands.push(cmd);
+            objects.push(object);
+        }
+        if let Some(output) = node.outputs.first() {
+            let output = out_dir.join(output);
+            commands.push(self.link_command(node, &output, &objects, dep_outputs));
+            commands.extend(self.bolt_command(node, &output));
+            commands.extend(self.hook_commands(node, &output, out_dir));
+        }
+        commands
+    }
+
+    /// `post_build` hooks followed by the `sign` hook for `output`.
+    fn hook_commands(&self, node: &TargetNode, output: &Path, out_dir: &Path) -> Vec<Command> {
+        node.options
+            .post_build
+            .iter()
+            .chain(&node.options.sign)
+            .map(|template| self.hook_command(template, output, out_dir))
+            .collect()
+    }
+
+    /// Paths of the per-architecture artifacts a multi-architecture node combines.
+    fn variant_outputs(&self, node: &TargetNode, out_dir: &Path) -> Vec<PathBuf> {
+        node.options
+            .architectures
+            .iter()
+            .map(|arch| out_dir.join(arch).join(&node.outputs[0]))
+            .collect()
+    }
+
+    fn lipo_command(&self, node: &TargetNode, output: &Path, out_dir: &Path) -> Command {
+        let mut cmd = Command::new(self.cross.lipo());
+        cmd.arg("-create")
+            .arg("-output")
+            .arg(output)
+            .args(self.variant_outputs(node, out_dir));
+        cmd
     }
 
     fn run_custom_command(
         &self,
-        command: &str,
+        node: &TargetNode,
+        command: &CommandLine,
         inputs: &[PathBuf],
         outputs: &[PathBuf],
         out_dir: &Path,
     ) -> Result<TargetRunResult> {
         let start = Instant::now();
-        if !self.needs_rebuild(inputs, outputs)? {
+        let name = node.name.as_str();
+        let Some(rendered) = self.start_custom_command(node, command, inputs, outputs, out_dir)?
+        else {
             return Ok(TargetRunResult::skipped(outputs.to_vec(), start.elapsed()));
+        };
+
+        let shown = command.to_string();
+        let action = format!("Custom command `{}`", shown);
+        match node.worker.as_deref() {
+            Some(worker) => {
+                // The worker runs from the manifest directory, so paths are
+                // sent absolute.
+                let absolute = |paths: &[PathBuf]| -> Vec<PathBuf> {
+                    paths
+                        .iter()
+                        .map(|p| std::path::absolute(p).unwrap_or_else(|_| p.clone()))
+                        .collect()
+                };
+                let request = WorkRequest {
+                    target: name.to_string(),
+                    command: shown,
+                    inputs: absolute(inputs),
+                    outputs: absolute(outputs),
+                };
+                self.run_in_worker(worker, &request, out_dir, &action)?
+            }
+            None => self.run_tool(
+                node,
+                Action {
+                    rule: Rule::Custom,
+                    cmd: self.custom_command(node, command, out_dir),
+                    inputs,
+                    out_dir,
+                    target: name,
+                    description: &action,
+                },
+            )?,
+        }
+
+        self.finish_custom_command(inputs, outputs, out_dir, &rendered)?;
+        Ok(TargetRunResult::built(outputs.to_vec(), start.elapsed()))
+    }
+
+    /// Runs the `pending` custom command of `node` like
+    /// [`CrustBackend::run_custom_command`], for a command that needs neither
+    /// a worker nor a [`CommandRunner`] other than [`LocalRunner`]. Only the
+    /// command is awaited here, through `tokio::process`; checking and
+    /// recording its files is left to the blocking pool.
+    async fn run_custom_command_async(
+        &self,
+        node: &TargetNode,
+        command: &CommandLine,
+        pending: &PendingCommand,
+        out_dir: &Path,
+    ) -> Result<()> {
+        let action = format!("Custom command `{}`", command);
+        let mut action = self.launched(
+            node,
+            Action {
+                rule: Rule::Custom,
+                cmd: self.custom_command(node, command, out_dir),
+                inputs: &pending.inputs,
+                out_dir,
+                target: &node.name,
+                description: &action,
+            },
+        );
+        if self.sandbox {
+            action = command::sandboxed(action, false)?;
+        }
+        let stderr = self.local.run_async(action).await?;
+        self.count_diagnostics(&node.name, &stderr);
+        Ok(())
+    }
+
+    /// The rendered command of a custom command that must run, with the
+    /// directories of its outputs created, or `None` when it is up to date.
+    fn start_custom_command(
+        &self,
+        node: &TargetNode,
+        command: &CommandLine,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<Option<String>> {
+        let rendered = render_command(&self.custom_command(node, command, out_dir));
+        if !self.needs_rebuild(inputs, outputs, &rendered)? {
+            return Ok(None);
         }
 
         for output in outputs {
@@ -127,18 +1138,20 @@ impl CrustBackend {
             }
         }
 
-        println!("Running custom command: {}", command);
-        let status = Command::new("sh")
-            .arg("-c")
-            .arg(command)
-            .current_dir(&self.manifest_dir)
-            .env("CRUST_BUILDDIR", out_dir)
-            .status()
-            .context("Failed to spawn custom command")?;
-        if !status.success() {
-            return Err(anyhow!("Custom command failed: {}", command));
-        }
+        let shown = command.to_string();
+        info!(command = shown, "Running custom command: {}", shown);
+        Ok(Some(rendered))
+    }
 
+    /// Copies the outputs a custom command left in the manifest directory
+    /// into the build directory and records its edge.
+    fn finish_custom_command(
+        &self,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        out_dir: &Path,
+        rendered: &str,
+    ) -> Result<()> {
         for output in outputs {
             if output.exists() {
                 continue;
@@ -161,104 +1174,726 @@ impl CrustBackend {
             }
         }
 
-        Ok(TargetRunResult::built(outputs.to_vec(), start.elapsed()))
+        self.record_edge(inputs, outputs, rendered);
+        Ok(())
     }
 
-    fn link_executable(
+    fn doxygen_command(&self, program: &str, docs_dir: &Path) -> Command {
+        let mut cmd = Command::new(program);
+        cmd.arg(docs_dir.join(docs::DOXYFILE));
+        self.in_manifest_dir(&mut cmd);
+        cmd
+    }
+
+    /// Regenerates the Doxyfile of a docs target and reruns doxygen when it
+    /// or any documented file changed.
+    fn build_docs(
         &self,
-        name: &str,
-        sources: &[String],
+        node: &TargetNode,
         dep_outputs: &[PathBuf],
         out_dir: &Path,
     ) -> Result<TargetRunResult> {
-        let outputs = vec![out_dir.join(name)];
         let start = Instant::now();
-        if !self.needs_rebuild(&self.collect_inputs(sources, dep_outputs), &outputs)? {
-            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
-        }
-
-        let objects = self.compile_objects(sources, out_dir, name)?;
-        let mut cmd = Command::new("cc");
-        cmd.arg("-o").arg(&outputs[0]);
-        for obj in &objects {
-            cmd.arg(obj);
-        }
-        for dep in dep_outputs {
-            cmd.arg(dep);
-        }
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let docs_dir = docs_dir(node, out_dir);
+        let manifest_dir = std::env::current_dir()
+            .context("Failed to read working directory")?
+            .join(&self.manifest_dir);
+        let settings = node.docs.clone().unwrap_or_default();
+        let doxyfile = docs_dir.join(docs::DOXYFILE);
+        docs::write_if_changed(
+            &doxyfile,
+            &docs::doxyfile(&settings, &manifest_dir, &node.sources, &docs_dir),
+        )?;
 
-        println!("Linking executable {}", outputs[0].display());
-        let status = cmd.status().context("Failed to spawn linker")?;
-        if !status.success() {
-            return Err(anyhow!("Linking failed for executable {}", name));
+        let mut inputs = docs::input_files(&self.manifest_dir, &node.sources)?;
+        inputs.push(doxyfile);
+        inputs.extend_from_slice(dep_outputs);
+        let command = render_command(&self.doxygen_command("doxygen", &docs_dir));
+        if !self.needs_rebuild(&inputs, &outputs, &command)? {
+            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
         }
 
+        let doxygen =
+            toolchain::find_program("doxygen").ok_or_else(|| CrustError::CompilerMissing {
+                program: "doxygen".into(),
+            })?;
+        info!(target_name = %node.name, "Generating documentation for {}", node.name);
+        self.run_tool(
+            node,
+            Action {
+                rule: Rule::Tool,
+                cmd: self.doxygen_command(&doxygen.to_string_lossy(), &docs_dir),
+                inputs: &inputs,
+                out_dir,
+                target: &node.name,
+                description: "Doxygen",
+            },
+        )?;
+        self.record_edge(&inputs, &outputs, &command);
         Ok(TargetRunResult::built(outputs, start.elapsed()))
     }
 
-    fn link_shared_library(
+    /// Compiles each out-of-date `.po` file of a gettext target into its
+    /// `.mo` catalog.
+    fn build_catalogs(
         &self,
-        name: &str,
-        sources: &[String],
+        node: &TargetNode,
         dep_outputs: &[PathBuf],
         out_dir: &Path,
     ) -> Result<TargetRunResult> {
-        let outputs = vec![out_dir.join(format!("lib{name}.so"))];
         let start = Instant::now();
-        if !self.needs_rebuild(
-            &self.collect_inputs(sources, dep_outputs),
-            &[outputs[0].clone()],
-        )? {
-            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
-        }
-
-        let objects = self.compile_objects(sources, out_dir, name)?;
-        let mut cmd = Command::new("cc");
-        cmd.arg("-shared").arg("-o").arg(&outputs[0]);
-        for obj in &objects {
-            cmd.arg(obj);
-        }
-        for dep in dep_outputs {
-            cmd.arg(dep);
-        }
-
-        println!("Linking shared library {}", outputs[0].display());
-        let status = cmd.status().context("Failed to spawn shared linker")?;
-        if !status.success() {
-            return Err(anyhow!("Linking failed for shared library {}", name));
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let mut built = false;
+        for (po, mo) in catalog_pairs(node, &self.manifest_dir, out_dir) {
+            let mut inputs = vec![po.clone()];
+            inputs.extend_from_slice(dep_outputs);
+            let cmd = i18n::msgfmt_command(&po, &mo);
+            let command = render_command(&cmd);
+            let catalog = std::slice::from_ref(&mo);
+            if !self.needs_rebuild(&inputs, catalog, &command)? {
+                continue;
+            }
+            if let Some(parent) = mo.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            info!(target_name = %node.name, "Compiling catalog {}", po.display());
+            self.run_tool(
+                node,
+                Action {
+                    rule: Rule::Tool,
+                    cmd,
+                    inputs: &inputs,
+                    out_dir,
+                    target: &node.name,
+                    description: "msgfmt",
+                },
+            )?;
+            self.record_edge(&inputs, catalog, &command);
+            built = true;
         }
-
-        Ok(TargetRunResult::built(outputs, start.elapsed()))
+        Ok(if built {
+            TargetRunResult::built(outputs, start.elapsed())
+        } else {
+            TargetRunResult::skipped(outputs, start.elapsed())
+        })
     }
 
-    fn archive_static_library(
+    /// Converts each out-of-date manual source of a man target into roff.
+    fn build_man_pages(
         &self,
-        name: &str,
-        sources: &[String],
+        node: &TargetNode,
         dep_outputs: &[PathBuf],
         out_dir: &Path,
     ) -> Result<TargetRunResult> {
-        let outputs = vec![out_dir.join(format!("lib{name}.a"))];
-        let inputs = self.collect_inputs(sources, dep_outputs);
         let start = Instant::now();
-        if !self.needs_rebuild(&inputs, &[outputs[0].clone()])? {
-            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let tool = node.man.as_ref().and_then(|man| man.tool.as_deref());
+        let mut built = false;
+        for (source, output) in node.sources.iter().zip(&outputs) {
+            let mut inputs = vec![self.manifest_dir.join(source)];
+            inputs.extend_from_slice(dep_outputs);
+            let (converter, program) = man::detect(source, tool)?;
+            let cmd = converter.command(&program, &inputs[0], output);
+            let command = render_command(&cmd);
+            let page = std::slice::from_ref(output);
+            if !self.needs_rebuild(&inputs, page, &command)? {
+                continue;
+            }
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            info!(target_name = %node.name, "Generating man page {}", source);
+            self.run_tool(
+                node,
+                Action {
+                    rule: Rule::Tool,
+                    cmd,
+                    inputs: &inputs,
+                    out_dir,
+                    target: &node.name,
+                    description: converter.program(),
+                },
+            )?;
+            self.record_edge(&inputs, page, &command);
+            built = true;
         }
+        Ok(if built {
+            TargetRunResult::built(outputs, start.elapsed())
+        } else {
+            TargetRunResult::skipped(outputs, start.elapsed())
+        })
+    }
 
-        let objects = self.compile_objects(sources, out_dir, name)?;
-        let mut cmd = Command::new("ar");
-        cmd.arg("rcs").arg(&outputs[0]);
-        for obj in &objects {
-            cmd.arg(obj);
+    /// Writes the `git describe` output of a vcs_version target. Only the
+    /// git files naming HEAD and tags are inputs, so git runs again only
+    /// after a commit, checkout, or new tag.
+    fn build_vcs_version(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<TargetRunResult> {
+        let start = Instant::now();
+        let output = out_dir.join(&node.outputs[0]);
+        let settings = node.vcs.clone().unwrap_or_default();
+        let mut inputs = vcs::git_dir(&self.manifest_dir)
+            .map(|dir| vcs::tracked_files(&dir))
+            .unwrap_or_default();
+        let template = settings
+            .template
+            .as_ref()
+            .map(|path| self.manifest_dir.join(path));
+        inputs.extend(template.clone());
+        inputs.extend_from_slice(dep_outputs);
+        let command = format!("git describe, falling back to {}", settings.fallback);
+        if !self.needs_rebuild(&inputs, std::slice::from_ref(&output), &command)? {
+            return Ok(TargetRunResult::skipped(vec![output], start.elapsed()));
         }
 
-        println!("Archiving static library {}", outputs[0].display());
-        let status = cmd.status().context("Failed to spawn archiver")?;
-        if !status.success() {
-            return Err(anyhow!("Archiving failed for static library {}", name));
+        let template = template
+            .map(|path| {
+                fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))
+            })
+            .transpose()?;
+        let tag = vcs::describe(&self.manifest_dir).unwrap_or(settings.fallback);
+        info!(target_name = %node.name, "Version {}", tag);
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&output, vcs::render(template.as_deref(), &tag))
+            .with_context(|| format!("Failed to write {}", output.display()))?;
+        self.record_edge(&inputs, std::slice::from_ref(&output), &command);
+        Ok(TargetRunResult::built(vec![output], start.elapsed()))
+    }
+
+    /// Copies the download of a fetch target into the build directory,
+    /// downloading it into the shared download directory first unless a copy
+    /// with the right checksum is already there. A download with the wrong
+    /// checksum is discarded and fails the build.
+    fn build_fetch(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<TargetRunResult> {
+        let start = Instant::now();
+        let output = out_dir.join(&node.outputs[0]);
+        let settings = node.fetch.clone().unwrap_or_default();
+        let command = format!("fetch {} sha256 {}", settings.url, settings.sha256);
+        if !self.needs_rebuild(dep_outputs, std::slice::from_ref(&output), &command)? {
+            return Ok(TargetRunResult::skipped(vec![output], start.elapsed()));
+        }
+
+        let download_dir = fetch::download_dir();
+        let cached = settings.cached(&download_dir);
+        if settings.verify(&cached).is_err() {
+            fs::create_dir_all(&download_dir).with_context(|| {
+                format!(
+                    "Failed to create download directory {}",
+                    download_dir.display()
+                )
+            })?;
+            // Concurrent builds download to their own file and then rename,
+            // so neither sees a half-written copy.
+            let partial =
+                download_dir.join(format!("{}.{}.part", settings.sha256, std::process::id()));
+            info!(target_name = %node.name, "Downloading {}", settings.url);
+            // The download is the one command that needs the network, and
+            // writes only to the download directory.
+            let downloaded = self
+                .run_tool_with(
+                    node,
+                    Action {
+                        rule: Rule::Tool,
+                        cmd: settings.command(&partial),
+                        inputs: &[],
+                        out_dir: &download_dir,
+                        target: &node.name,
+                        description: "curl",
+                    },
+                    true,
+                )
+                .and_then(|_| settings.verify(&partial));
+            if let Err(err) = downloaded {
+                let _ = fs::remove_file(&partial);
+                return Err(err);
+            }
+            fs::rename(&partial, &cached)
+                .with_context(|| format!("Failed to store {}", cached.display()))?;
+        }
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&cached, &output)
+            .with_context(|| format!("Failed to copy download to {}", output.display()))?;
+        self.record_edge(dep_outputs, std::slice::from_ref(&output), &command);
+        Ok(TargetRunResult::built(vec![output], start.elapsed()))
+    }
+
+    /// Unpacks the archive of an extract target into its output directory,
+    /// replacing whatever an earlier build unpacked there.
+    fn build_extract(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<TargetRunResult> {
+        let start = Instant::now();
+        let output = out_dir.join(&node.outputs[0]);
+        let archive = match node.sources.first() {
+            Some(archive) => self.manifest_dir.join(archive),
+            None => dep_outputs.first().cloned().ok_or_else(|| {
+                anyhow!(
+                    "{} has no archive: its dependencies produced no files",
+                    node.name
+                )
+            })?,
+        };
+        let strip_components = node.extract.as_ref().map_or(0, |e| e.strip_components);
+        let mut inputs = vec![archive.clone()];
+        inputs.extend(dep_outputs.iter().filter(|dep| **dep != archive).cloned());
+        let command = format!(
+            "extract {} stripping {strip_components} components",
+            archive.display()
+        );
+        if !self.needs_rebuild(&inputs, std::slice::from_ref(&output), &command)? {
+            return Ok(TargetRunResult::skipped(vec![output], start.elapsed()));
+        }
+
+        info!(target_name = %node.name, "Extracting {}", archive.display());
+        extract::extract(&archive, &output, strip_components)?;
+        self.record_edge(&inputs, std::slice::from_ref(&output), &command);
+        Ok(TargetRunResult::built(vec![output], start.elapsed()))
+    }
+
+    /// Copies the input of a copy_file target into the build directory,
+    /// replacing its `@NAME@` placeholders when the target sets variables.
+    /// The copy keeps the input's permissions, so scripts stay executable.
+    fn build_copy_file(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<TargetRunResult> {
+        let start = Instant::now();
+        let output = out_dir.join(&node.outputs[0]);
+        let input = self.manifest_dir.join(&node.sources[0]);
+        let settings = node.copy.clone().unwrap_or_default();
+        let mut inputs = vec![input.clone()];
+        inputs.extend_from_slice(dep_outputs);
+        let mut command = format!("copy {}", input.display());
+        for (key, value) in &settings.variables {
+            command.push_str(&format!(" @{key}@={value}"));
+        }
+        if !self.needs_rebuild(&inputs, std::slice::from_ref(&output), &command)? {
+            return Ok(TargetRunResult::skipped(vec![output], start.elapsed()));
+        }
+
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if settings.variables.is_empty() {
+            fs::copy(&input, &output).with_context(|| {
+                format!("Failed to copy {} to {}", input.display(), output.display())
+            })?;
+        } else {
+            let text = fs::read_to_string(&input)
+                .with_context(|| format!("Failed to read {}", input.display()))?;
+            fs::write(&output, settings.substitute(&text))
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            fs::set_permissions(&output, fs::metadata(&input)?.permissions())?;
+        }
+        self.record_edge(&inputs, std::slice::from_ref(&output), &command);
+        Ok(TargetRunResult::built(vec![output], start.elapsed()))
+    }
+
+    /// Copies the modules of a python_sources target into the build
+    /// directory's module tree.
+    fn build_python_sources(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<TargetRunResult> {
+        let start = Instant::now();
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let inputs = self.collect_inputs(&node.sources, dep_outputs);
+        let command = format!("copy python sources {}", node.sources.join(" "));
+        if !self.needs_rebuild(&inputs, &outputs, &command)? {
+            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
+        }
+
+        for (source, output) in node.sources.iter().zip(&outputs) {
+            let source = self.manifest_dir.join(source);
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&source, output).with_context(|| {
+                format!(
+                    "Failed to copy {} to {}",
+                    source.display(),
+                    output.display()
+                )
+            })?;
+        }
+        self.record_edge(&inputs, &outputs, &command);
+        Ok(TargetRunResult::built(outputs, start.elapsed()))
+    }
+
+    /// Compiles a rust_executable or rust_library crate with one rustc run.
+    /// rustc reads the modules itself, so every listed source is an input.
+    fn build_rust(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<TargetRunResult> {
+        let start = Instant::now();
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let inputs = self.collect_inputs(&node.sources, dep_outputs);
+        let cmd = self
+            .target_commands(node, dep_outputs, out_dir)
+            .pop()
+            .ok_or_else(|| anyhow!("Missing rustc command for {}", node.name))?;
+        let command = render_command(&cmd);
+        if !self.needs_rebuild(&inputs, &outputs, &command)? {
+            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
+        }
+
+        if let Some(parent) = outputs[0].parent() {
+            fs::create_dir_all(parent)?;
+        }
+        info!(output = %outputs[0].display(), "Compiling Rust crate {}", outputs[0].display());
+        self.run_tool(
+            node,
+            Action {
+                rule: Rule::Compile,
+                cmd,
+                inputs: &inputs,
+                out_dir,
+                target: &node.name,
+                description: "rustc",
+            },
+        )?;
+        self.record_edge(&inputs, &outputs, &command);
+        Ok(TargetRunResult::built(outputs, start.elapsed()))
+    }
+
+    /// Compiles `node`'s sources and links (or archives) them into its output.
+    fn link_target(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<TargetRunResult> {
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let start = Instant::now();
+        let linker_script = node
+            .options
+            .linker_script
+            .as_ref()
+            .map(|s| self.manifest_dir.join(s));
+        let mut inputs = self.collect_inputs(&node.sources, dep_outputs);
+        inputs.extend(linker_script.clone());
+        inputs.extend(
+            node.options
+                .embed_files
+                .iter()
+                .map(|f| self.manifest_dir.join(f)),
+        );
+        let profile = node
+            .options
+            .bolt
+            .as_ref()
+            .and_then(|bolt| bolt.profile.as_ref())
+            .map(|profile| self.manifest_dir.join(profile));
+        inputs.extend(profile);
+        // Covers the compile commands too, so they are part of the decision.
+        let command = render_commands(&self.target_commands(node, dep_outputs, out_dir));
+        if !self.needs_rebuild(&inputs, &outputs, &command)? {
+            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
+        }
+
+        let mut objects = self.compile_objects(node, out_dir)?;
+        objects.extend(self.embed_objects(node, out_dir)?);
+        if let Some((cmd, object)) = self.device_link_command(node, &objects, dep_outputs, out_dir)
+        {
+            info!(output = %object.display(), "Device linking {}", object.display());
+            let mut dlink_inputs = objects.clone();
+            dlink_inputs.extend(dep_outputs.iter().cloned());
+            self.run_tool(
+                node,
+                Action {
+                    rule: Rule::Link,
+                    cmd,
+                    inputs: &dlink_inputs,
+                    out_dir,
+                    target: &node.name,
+                    description: "Device linking",
+                },
+            )?;
+            objects.push(object);
+        }
+        let action = match node.kind {
+            TargetKind::StaticLibrary => "Archiving static library",
+            TargetKind::SharedLibrary => "Linking shared library",
+            TargetKind::PythonExtension => "Linking Python extension",
+            _ => "Linking executable",
+        };
+        if let Some(parent) = outputs[0].parent() {
+            fs::create_dir_all(parent)?;
+        }
+        info!(output = %outputs[0].display(), "{} {}", action, outputs[0].display());
+        let mut link_inputs = objects.clone();
+        link_inputs.extend(linker_script);
+        let rule = match node.kind {
+            TargetKind::StaticLibrary => Rule::Archive,
+            _ => Rule::Link,
+        };
+        self.run_tool(
+            node,
+            Action {
+                rule,
+                cmd: self.link_command(node, &outputs[0], &objects, dep_outputs),
+                inputs: &link_inputs,
+                out_dir,
+                target: &node.name,
+                description: action,
+            },
+        )?;
+
+        self.finish_artifact(node, &outputs, out_dir)?;
+        self.record_edge(&inputs, &outputs, &command);
+        Ok(TargetRunResult::built(outputs, start.elapsed()))
+    }
+
+    /// Combines the per-architecture variants of `node` into one universal
+    /// artifact with `lipo`. Without `universal` the variants are the outputs
+    /// and nothing is left to do.
+    fn combine_architectures(&self, node: &TargetNode, out_dir: &Path) -> Result<TargetRunResult> {
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let start = Instant::now();
+        if !node.options.universal {
+            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
+        }
+        let variants = self.variant_outputs(node, out_dir);
+        let cmd = self.lipo_command(node, &outputs[0], out_dir);
+        let command = render_command(&cmd);
+        if !self.needs_rebuild(&variants, &outputs, &command)? {
+            return Ok(TargetRunResult::skipped(outputs, start.elapsed()));
+        }
+
+        info!(output = %outputs[0].display(), "Creating universal binary {}", outputs[0].display());
+        self.run_tool(
+            node,
+            Action {
+                rule: Rule::Tool,
+                cmd,
+                inputs: &variants,
+                out_dir,
+                target: &node.name,
+                description: "Creating universal binary",
+            },
+        )?;
+        self.finish_artifact(node, &outputs, out_dir)?;
+        self.record_edge(&variants, &outputs, &command);
+        Ok(TargetRunResult::built(outputs, start.elapsed()))
+    }
+
+    /// Runs the BOLT, post-build, signing, and size-report steps for a
+    /// freshly produced artifact `outputs[0]`.
+    fn finish_artifact(
+        &self,
+        node: &TargetNode,
+        outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<()> {
+        if let Some(cmd) = self.bolt_command(node, &outputs[0]) {
+            let prebolt = Self::prebolt_path(&outputs[0]);
+            fs::rename(&outputs[0], &prebolt).with_context(|| {
+                format!("Failed to move {} aside for BOLT", outputs[0].display())
+            })?;
+            info!(output = %outputs[0].display(), "Optimizing {} with BOLT", outputs[0].display());
+            let mut inputs = vec![prebolt];
+            inputs.extend(
+                node.options
+                    .bolt
+                    .iter()
+                    .filter_map(|bolt| bolt.profile.as_ref())
+                    .map(|profile| self.manifest_dir.join(profile)),
+            );
+            self.run_tool(
+                node,
+                Action {
+                    rule: Rule::Tool,
+                    cmd,
+                    inputs: &inputs,
+                    out_dir,
+                    target: &node.name,
+                    description: "BOLT optimization",
+                },
+            )?;
+        }
+
+        for hook in &node.options.post_build {
+            info!(command = %hook, "Running post-build step: {}", hook);
+            self.run_tool(
+                node,
+                Action {
+                    rule: Rule::Tool,
+                    cmd: self.hook_command(hook, &outputs[0], out_dir),
+                    inputs: &outputs[..1],
+                    out_dir,
+                    target: &node.name,
+                    description: &format!("Post-build step `{}`", hook),
+                },
+            )?;
+        }
+
+        if let Some(sign) = &node.options.sign {
+            info!(output = %outputs[0].display(), "Signing {}", outputs[0].display());
+            self.run_tool(
+                node,
+                Action {
+                    rule: Rule::Tool,
+                    cmd: self.hook_command(sign, &outputs[0], out_dir),
+                    inputs: &outputs[..1],
+                    out_dir,
+                    target: &node.name,
+                    description: "Signing",
+                },
+            )?;
+        }
+
+        if node.options.size_report {
+            report_memory_usage(self.cross.size(), &node.name, &outputs[0])?;
+        }
+        Ok(())
+    }
+
+    /// Runs `action` through its launcher on this machine or the remote
+    /// executor, in a sandbox exposing its inputs read-only and its build directory when
+    /// sandboxing is enabled, and traced under `--check-deps`. The launcher
+    /// is not part of the command recorded for rebuilds, so adding one does
+    /// not rebuild everything.
+    fn run_tool(&self, node: &TargetNode, action: Action<'_>) -> Result<()> {
+        self.run_tool_with(node, action, false)
+    }
+
+    /// [`Self::run_tool`], letting the command reach the network inside
+    /// the sandbox when `network` is set.
+    fn run_tool_with(&self, node: &TargetNode, action: Action<'_>, network: bool) -> Result<()> {
+        let action = self.launched(node, action);
+        let target = action.target;
+        let mut runner: &dyn CommandRunner = &self.local;
+        let remote;
+        if let Some(executor) = &self.remote {
+            remote = Remote {
+                inner: runner,
+                executor,
+            };
+            runner = &remote;
+        }
+        let sandboxed;
+        if self.sandbox {
+            sandboxed = Sandboxed {
+                inner: runner,
+                network,
+            };
+            runner = &sandboxed;
+        }
+        let traced;
+        if self.check_deps {
+            traced = Traced {
+                inner: runner,
+                project_dir: &self.manifest_dir,
+                seq: &self.trace_seq,
+                undeclared: &self.undeclared,
+            };
+            runner = &traced;
+        }
+        let stderr = runner.run(action)?;
+        self.count_diagnostics(target, &stderr);
+        Ok(())
+    }
+
+    /// `action` run through the launcher configured for its rule, if any.
+    fn launched<'a>(&self, node: &TargetNode, mut action: Action<'a>) -> Action<'a> {
+        let launcher = node
+            .options
+            .launchers
+            .get(&action.rule)
+            .or_else(|| self.launchers.get(&action.rule))
+            .filter(|launcher| !launcher.trim().is_empty());
+        if let Some(launcher) = launcher {
+            debug!(rule = action.rule.name(), launcher = %launcher, "using launcher");
+            action.cmd = command::launch(launcher, action.cmd);
+        }
+        action
+    }
+
+    /// Sends `request` to the persistent worker started by the shell command
+    /// `worker`, which runs from the manifest directory like any custom
+    /// command. The worker's output is passed through to stderr.
+    fn run_in_worker(
+        &self,
+        worker: &str,
+        request: &WorkRequest,
+        out_dir: &Path,
+        action: &str,
+    ) -> Result<()> {
+        let spawn = || self.local.finish(self.shell_command(worker, out_dir));
+        let response = self.workers.run(worker, spawn, request)?;
+        ui::command_output(&request.target, &response.output);
+        self.count_diagnostics(&request.target, &response.output);
+        if response.exit_code != 0 {
+            return Err(CrustError::CommandFailed {
+                target: request.target.clone(),
+                action: action.to_string(),
+                exit: Some(response.exit_code),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Reports the diagnostics in `stderr` of a command of `target` as
+    /// events and adds them to its counts.
+    fn count_diagnostics(&self, target: &str, stderr: &str) {
+        let parsed = diagnostics::parse(stderr);
+        diagnostics::emit(target, &parsed);
+        let counts = diagnostics::counts(&parsed);
+        if counts != Counts::default() {
+            *self
+                .diagnostics
+                .lock()
+                .expect("diagnostics mutex poisoned")
+                .entry(target.to_string())
+                .or_default() += counts;
+        }
+    }
+
+    /// Emits one warning per undeclared file recorded for `target`.
+    fn report_undeclared(&self, target: &str) {
+        let found = self
+            .undeclared
+            .lock()
+            .expect("undeclared mutex poisoned")
+            .remove(target)
+            .unwrap_or_default();
+        let root = self.manifest_dir.canonicalize().unwrap_or_default();
+        for path in found {
+            let relative = path.strip_prefix(&root).unwrap_or(&path);
+            warn!(
+                target_name = target,
+                path = %relative.display(),
+                "target '{}' read undeclared input {}",
+                target,
+                relative.display()
+            );
         }
-
-        Ok(TargetRunResult::built(outputs, start.elapsed()))
     }
 
     fn collect_inputs(&self, sources: &[String], dep_outputs: &[PathBuf]) -> Vec<PathBuf> {
@@ -267,37 +1902,412 @@ impl CrustBackend {
         inputs
     }
 
+    /// Builds `node` as a task of the build's scheduler: a custom command
+    /// run on this machine is awaited, anything else runs on the blocking
+    /// pool.
+    async fn execute_node(
+        self: Arc<Self>,
+        node: Arc<TargetNode>,
+        dep_outputs: Vec<PathBuf>,
+        out_dir: Arc<PathBuf>,
+    ) -> Result<TargetRunResult> {
+        let awaited = node.kind == TargetKind::CustomCommand
+            && !node.combines_architectures()
+            && node.worker.is_none()
+            && self.remote.is_none()
+            && !self.check_deps;
+        if !awaited {
+            return executor::blocking(move || self.execute_target(&node, &dep_outputs, &out_dir))
+                .await;
+        }
+
+        let Some(command) = node.command.clone() else {
+            return Err(anyhow!("Missing custom command for {}", node.name));
+        };
+        // Hashing inputs and outputs and writing the state may take a while
+        // for large files; it stays off the scheduler's thread.
+        let pending = {
+            let (backend, node, out_dir) =
+                (Arc::clone(&self), Arc::clone(&node), Arc::clone(&out_dir));
+            let command = command.clone();
+            executor::blocking(move || {
+                backend.prepare_custom_command(&node, &command, &dep_outputs, &out_dir)
+            })
+            .await?
+        };
+        let pending = match pending {
+            Prepared::Done(result) => return Ok(result),
+            Prepared::Pending(pending) => pending,
+        };
+        let ran = self
+            .run_custom_command_async(&node, &command, &pending, &out_dir)
+            .await;
+        executor::blocking(move || {
+            let result = ran.and_then(|()| {
+                self.finish_custom_command(
+                    &pending.inputs,
+                    &pending.outputs,
+                    &out_dir,
+                    &pending.rendered,
+                )?;
+                Ok(TargetRunResult::built(
+                    pending.outputs.clone(),
+                    pending.run.start.elapsed(),
+                ))
+            });
+            self.end_target(&node, pending.run, result)
+        })
+        .await
+    }
+
+    /// Everything before the custom command `node` of [`CrustBackend::execute_node`]
+    /// runs: its result when it is checkpointed or up to date, otherwise the
+    /// command to run, with its output directories created.
+    fn prepare_custom_command(
+        &self,
+        node: &TargetNode,
+        command: &CommandLine,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<Prepared> {
+        let run = self.begin_target(node, dep_outputs, out_dir);
+        if let Some(completed) = self.completed_before(node, &run) {
+            return Ok(Prepared::Done(completed));
+        }
+        let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
+        let inputs = self.collect_inputs(&node.sources, dep_outputs);
+        match self.start_custom_command(node, command, &inputs, &outputs, out_dir)? {
+            Some(rendered) => Ok(Prepared::Pending(PendingCommand {
+                run,
+                inputs,
+                outputs,
+                rendered,
+            })),
+            None => {
+                let skipped = TargetRunResult::skipped(outputs, run.start.elapsed());
+                self.end_target(node, run, Ok(skipped)).map(Prepared::Done)
+            }
+        }
+    }
+
     fn execute_target(
         &self,
-        node: &crate::graph::TargetNode,
+        node: &TargetNode,
         dep_outputs: &[PathBuf],
         out_dir: &Path,
     ) -> Result<TargetRunResult> {
+        let run = self.begin_target(node, dep_outputs, out_dir);
+        if let Some(completed) = self.completed_before(node, &run) {
+            return Ok(completed);
+        }
         let outputs: Vec<PathBuf> = node.outputs.iter().map(|o| out_dir.join(o)).collect();
 
-        match node.kind {
-            TargetKind::Executable => {
-                self.link_executable(&node.name, &node.sources, dep_outputs, out_dir)
-            }
-            TargetKind::StaticLibrary => {
-                self.archive_static_library(&node.name, &node.sources, dep_outputs, out_dir)
-            }
-            TargetKind::SharedLibrary => {
-                self.link_shared_library(&node.name, &node.sources, dep_outputs, out_dir)
+        let result = match node.kind {
+            _ if node.combines_architectures() => self.combine_architectures(node, out_dir),
+            TargetKind::Executable
+            | TargetKind::StaticLibrary
+            | TargetKind::SharedLibrary
+            | TargetKind::PythonExtension => self.link_target(node, dep_outputs, out_dir),
+            TargetKind::Docs => self.build_docs(node, dep_outputs, out_dir),
+            TargetKind::Gettext => self.build_catalogs(node, dep_outputs, out_dir),
+            TargetKind::Man => self.build_man_pages(node, dep_outputs, out_dir),
+            TargetKind::VcsVersion => self.build_vcs_version(node, dep_outputs, out_dir),
+            TargetKind::Fetch => self.build_fetch(node, dep_outputs, out_dir),
+            TargetKind::Extract => self.build_extract(node, dep_outputs, out_dir),
+            TargetKind::CopyFile => self.build_copy_file(node, dep_outputs, out_dir),
+            TargetKind::PythonSources => self.build_python_sources(node, dep_outputs, out_dir),
+            TargetKind::RustExecutable | TargetKind::RustLibrary => {
+                self.build_rust(node, dep_outputs, out_dir)
             }
+            TargetKind::Alias => Ok(TargetRunResult::skipped(Vec::new(), run.start.elapsed())),
             TargetKind::CustomCommand => {
                 let inputs = self.collect_inputs(&node.sources, dep_outputs);
                 self.run_custom_command(
+                    node,
                     node.command
-                        .as_deref()
+                        .as_ref()
                         .ok_or_else(|| anyhow!("Missing custom command for {}", node.name))?,
                     &inputs,
                     &outputs,
                     out_dir,
                 )
             }
+        };
+        self.end_target(node, run, result)
+    }
+
+    /// Starts timing `node` and digests the commands it runs.
+    fn begin_target(
+        &self,
+        node: &TargetNode,
+        dep_outputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> TargetRun {
+        let start = Instant::now();
+        let commands = self.target_commands(node, dep_outputs, out_dir);
+        let command_digest = sha256_bytes(render_commands(&commands).as_bytes());
+        let fingerprint = self.checkpoint.as_ref().map(|_| {
+            Checkpoint::fingerprint(
+                &command_digest,
+                &self.collect_inputs(&node.sources, dep_outputs),
+            )
+        });
+        TargetRun {
+            start,
+            command_digest,
+            fingerprint,
+        }
+    }
+
+    /// The result of `node` when it completed before the build was
+    /// interrupted and nothing it depends on changed since.
+    fn completed_before(&self, node: &TargetNode, run: &TargetRun) -> Option<TargetRunResult> {
+        let outputs = self
+            .checkpoint
+            .as_ref()?
+            .completed(&node.name, run.fingerprint.as_ref()?)?;
+        debug!(target_name = %node.name, "completed before the build was interrupted");
+        Some(
+            TargetRunResult::skipped(outputs, run.start.elapsed())
+                .with_command_digest(run.command_digest.clone()),
+        )
+    }
+
+    /// Reports the undeclared reads of `node` and checkpoints it once it
+    /// built.
+    fn end_target(
+        &self,
+        node: &TargetNode,
+        run: TargetRun,
+        result: Result<TargetRunResult>,
+    ) -> Result<TargetRunResult> {
+        if self.check_deps {
+            self.report_undeclared(&node.name);
+        }
+        if let (Ok(built), Some(checkpoint), Some(fingerprint)) =
+            (&result, &self.checkpoint, &run.fingerprint)
+        {
+            checkpoint.record(&node.name, fingerprint, &built.outputs)?;
+        }
+        result.map(|r| r.with_command_digest(run.command_digest))
+    }
+}
+
+/// A custom command of [`CrustBackend::execute_node`] before it runs.
+enum Prepared {
+    /// Checkpointed or up to date, with its result.
+    Done(TargetRunResult),
+    Pending(PendingCommand),
+}
+
+/// A custom command that must run, with its files and rendered command.
+struct PendingCommand {
+    run: TargetRun,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    rendered: String,
+}
+
+/// A target being built: when it started, the digest of its commands, and
+/// its checkpoint fingerprint under a checkpointed build.
+struct TargetRun {
+    start: Instant,
+    command_digest: String,
+    fingerprint: Option<String>,
+}
+
+/// Section totals reported by `size` in Berkeley format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MemoryUsage {
+    text: u64,
+    data: u64,
+    bss: u64,
+}
+
+impl MemoryUsage {
+    /// Parses the first data row of `size` output (`text data bss dec hex file`).
+    fn parse(output: &str) -> Option<Self> {
+        let row = output.lines().nth(1)?;
+        let mut fields = row.split_whitespace().map(|f| f.parse::<u64>().ok());
+        Some(MemoryUsage {
+            text: fields.next()??,
+            data: fields.next()??,
+            bss: fields.next()??,
+        })
+    }
+
+    /// Bytes stored in non-volatile memory: code plus initialized data.
+    fn flash(&self) -> u64 {
+        self.text + self.data
+    }
+
+    /// Bytes occupied at run time by initialized and zeroed data.
+    fn ram(&self) -> u64 {
+        self.data + self.bss
+    }
+}
+
+fn report_memory_usage(size: &str, target: &str, artifact: &Path) -> Result<()> {
+    let output = Command::new(size)
+        .arg(artifact)
+        .output()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => CrustError::CompilerMissing {
+                program: size.into(),
+            }
+            .into(),
+            _ => anyhow::Error::new(err).context(format!("Failed to spawn {size}")),
+        })?;
+    let usage = MemoryUsage::parse(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| anyhow!("Could not parse `size` output for {}", artifact.display()))?;
+    info!(
+        target_name = target,
+        text = usage.text,
+        data = usage.data,
+        bss = usage.bss,
+        "Memory usage for {}: flash {} B (text {} + data {}), RAM {} B (data {} + bss {})",
+        target,
+        usage.flash(),
+        usage.text,
+        usage.data,
+        usage.ram(),
+        usage.data,
+        usage.bss
+    );
+    Ok(())
+}
+
+/// `command` run by `sh -c`.
+#[cfg(not(windows))]
+fn platform_shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// `cmd.exe` parses its command line itself, so the command is passed
+/// verbatim instead of being quoted as one argument.
+#[cfg(windows)]
+fn platform_shell(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+    let shell = std::env::var_os("ComSpec").unwrap_or_else(|| "cmd.exe".into());
+    let mut cmd = Command::new(shell);
+    cmd.args(["/D", "/S", "/C"])
+        .raw_arg(format!("\"{command}\""));
+    cmd
+}
+
+/// Quotes `value` for safe interpolation into a POSIX shell command.
+#[cfg(not(windows))]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn shell_quote(value: &str) -> String {
+    format!("\"{value}\"")
+}
+
+fn object_path(out_dir: &Path, node: &TargetNode, idx: usize) -> PathBuf {
+    let extension = languages::for_source(Path::new(&node.sources[idx]))
+        .map_or("o", |language| language.object_extension);
+    intermediate_path(out_dir, &node.name, &format!("{idx}.{extension}"))
+}
+
+/// Longest intermediate file path kept readable. Windows limits paths to
+/// 260 characters (`MAX_PATH`), and compilers and linkers there often do not
+/// accept the `\\?\` form that lifts it, so the paths handed to them stay
+/// below the limit with room for the `.d` and `.pdb` files written next to
+/// objects. crust's own file operations are not limited, as the standard
+/// library adds the prefix to long paths itself.
+const INTERMEDIATE_PATH_LIMIT: usize = 200;
+
+/// `out_dir/<label path>_<suffix>` for an intermediate file of the target
+/// `name`. When that is longer than [`INTERMEDIATE_PATH_LIMIT`], as for
+/// targets deep in a large tree, the file goes to `.crust/objects` named
+/// after a hash of the label instead.
+fn intermediate_path(out_dir: &Path, name: &str, suffix: &str) -> PathBuf {
+    let label = config::label_path(name);
+    let path = out_dir.join(format!("{label}_{suffix}"));
+    let length = std::path::absolute(&path).map_or(0, |path| path.as_os_str().len());
+    if length <= INTERMEDIATE_PATH_LIMIT {
+        return path;
+    }
+    out_dir.join(STATE_DIR).join("objects").join(format!(
+        "{}_{suffix}",
+        &sha256_bytes(label.as_bytes())[..16]
+    ))
+}
+
+/// `(po, mo)` paths of every language of a gettext target; sources and
+/// outputs are listed in the same language order.
+fn catalog_pairs<'a>(
+    node: &'a TargetNode,
+    manifest_dir: &'a Path,
+    out_dir: &'a Path,
+) -> impl Iterator<Item = (PathBuf, PathBuf)> + 'a {
+    node.sources
+        .iter()
+        .zip(&node.outputs)
+        .map(|(po, mo)| (manifest_dir.join(po), out_dir.join(mo)))
+}
+
+/// Absolute directory holding a docs target's Doxyfile and `html/` tree.
+fn docs_dir(node: &TargetNode, out_dir: &Path) -> PathBuf {
+    let dir = out_dir.join(config::label_path(&node.name));
+    std::path::absolute(&dir).unwrap_or(dir)
+}
+
+/// Generated C source and object paths for the `idx`-th embedded file.
+fn embed_paths(out_dir: &Path, target_name: &str, idx: usize) -> (PathBuf, PathBuf) {
+    (
+        intermediate_path(out_dir, target_name, &format!("embed_{idx}.c")),
+        intermediate_path(out_dir, target_name, &format!("embed_{idx}.o")),
+    )
+}
+
+/// `commands` rendered one per line.
+fn render_commands(commands: &[Command]) -> String {
+    commands
+        .iter()
+        .map(render_command)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders the program, arguments, working directory, and environment
+/// overrides of `cmd` as one line for hashing. Arguments holding spaces are
+/// quoted, so `"a b"` and `a b` render differently.
+fn render_command(cmd: &Command) -> String {
+    let mut line = cmd.get_program().to_string_lossy().into_owned();
+    for arg in cmd.get_args() {
+        let arg = arg.to_string_lossy();
+        line.push(' ');
+        if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '\'') {
+            line.push_str(&shell_quote(&arg));
+        } else {
+            line.push_str(&arg);
         }
     }
+    if let Some(dir) = cmd.get_current_dir() {
+        line.push_str(&format!(" [cwd={}]", dir.display()));
+    }
+    let mut envs: Vec<String> = cmd
+        .get_envs()
+        .map(|(k, v)| {
+            format!(
+                "{}={}",
+                k.to_string_lossy(),
+                v.map(|v| v.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            )
+        })
+        .collect();
+    envs.sort();
+    for env in envs {
+        line.push_str(&format!(" [env {}]", env));
+    }
+    line
 }
 
 impl Backend for CrustBackend {
@@ -312,13 +2322,65 @@ impl Backend for CrustBackend {
         _manifest_dir: &Path,
     ) -> Result<BackendEmitResult> {
         fs::create_dir_all(out_dir)?;
-        let executor = BuildExecutor::new(self.parallelism);
-        let out_dir = out_dir.to_path_buf();
-        let backend = self.clone();
+        if self.sandbox {
+            Sandbox::check_available()?;
+        }
+        if self.check_deps {
+            depcheck::check_available()?;
+        }
+        man::check_tools(graph)?;
+        self.warn_flag_conflicts(graph);
+        let database = compdb::write(out_dir, &self.compile_database(graph, out_dir)?)?;
+        debug!(path = %database.display(), "wrote compilation database");
 
-        let result = executor.execute(graph, move |node, dep_outputs| {
-            backend.execute_target(node, &dep_outputs, &out_dir)
-        })?;
+        // A build cancelled by an earlier one's failure would not start.
+        let cancel = Arc::new(self.local.cancel.renewed());
+        let executor = BuildExecutor::new(self.parallelism)
+            .with_load_limit(self.load.clone())
+            .with_max_link_jobs(self.max_link_jobs)
+            .with_max_custom_jobs(self.max_custom_jobs)
+            .with_watchdog(self.watchdog)
+            .with_cancellation(Some(Arc::clone(&cancel)));
+        let worker_out_dir = Arc::new(out_dir.to_path_buf());
+        let checkpoint = Arc::new(Checkpoint::open(out_dir)?);
+        let last_state = self.last_state.lock().expect("state mutex poisoned").take();
+        let state = Arc::new(match last_state {
+            Some(last) => last.reload(out_dir),
+            None => StateDb::load(out_dir),
+        });
+        let mut backend = self.clone();
+        backend.local.cancel = cancel;
+        backend.checkpoint = Some(Arc::clone(&checkpoint));
+        backend.state = Some(Arc::clone(&state));
+
+        let backend = Arc::new(backend);
+        let result = executor.execute_async(graph, move |node, dep_outputs| {
+            Arc::clone(&backend).execute_node(node, dep_outputs, Arc::clone(&worker_out_dir))
+        });
+        // Edges that finished are kept even when the build failed.
+        state.save()?;
+        *self.last_state.lock().expect("state mutex poisoned") = Some(state);
+        let result = result?;
+        // Every target finished; the next build starts from scratch.
+        if let Some(checkpoint) = Arc::into_inner(checkpoint) {
+            checkpoint.finish()?;
+        }
+
+        let mut records = Vec::new();
+        for node in graph.topo_order()? {
+            if let Some(entry) = result.produced.get(&node.name) {
+                for output in entry.outputs.iter().filter(|o| o.is_file()) {
+                    records.push(OutputRecord::describe(
+                        out_dir,
+                        &node.name,
+                        output,
+                        entry.command_digest.as_deref(),
+                    )?);
+                }
+            }
+        }
+        let manifest_path = write_output_manifest(out_dir, records)?;
+        debug!(path = %manifest_path.display(), "wrote output manifest");
 
         let all_outputs: Vec<PathBuf> = result
             .produced
@@ -326,6 +2388,7 @@ impl Backend for CrustBackend {
             .flat_map(|outputs| outputs.outputs.iter().cloned())
             .collect();
 
+        let mut diagnostics = self.diagnostics.lock().expect("diagnostics mutex poisoned");
         let target_summaries = graph
             .topo_order()?
             .into_iter()
@@ -338,6 +2401,7 @@ impl Backend for CrustBackend {
                         built: entry.built,
                         outputs: entry.outputs.clone(),
                         duration: entry.duration,
+                        diagnostics: diagnostics.remove(&node.name).unwrap_or_default(),
                     })
             })
             .collect();
@@ -360,8 +2424,343 @@ impl Backend for CrustBackend {
 mod tests {
     use super::*;
     use crate::config::ProjectManifest;
+    use crate::cross::Platform;
     use tempfile::tempdir;
 
+    #[test]
+    fn links_embedded_files_as_c_arrays() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        fs::write(
+            &manifest_path,
+            r#"[project]
+name = "demo"
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+embed_files = ["assets/msg.txt"]
+"#,
+        )
+        .unwrap();
+        fs::create_dir_all(dir.path().join("assets")).unwrap();
+        fs::write(dir.path().join("assets/msg.txt"), "hello").unwrap();
+        fs::write(
+            dir.path().join("main.c"),
+            "#include <stddef.h>\nextern const size_t crust_embed_assets_msg_txt_size;\nint main(void){return (int)crust_embed_assets_msg_txt_size;}\n",
+        )
+        .unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let builddir = dir.path().join("build");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), None);
+        backend.emit(&graph, &builddir, dir.path()).unwrap();
+
+        let status = Command::new(builddir.join("app")).status().unwrap();
+        assert_eq!(status.code(), Some(5));
+    }
+
+    #[test]
+    fn parses_berkeley_size_output() {
+        let output = "   text\t   data\t    bss\t    dec\t    hex\tfilename\n   1372\t    600\t      8\t   1980\t    7bc\tapp\n";
+        let usage = MemoryUsage::parse(output).unwrap();
+        assert_eq!(usage.flash(), 1972);
+        assert_eq!(usage.ram(), 608);
+    }
+
+    #[test]
+    fn runs_post_build_and_sign_hooks_after_linking() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        fs::write(
+            &manifest_path,
+            r#"[project]
+name = "demo"
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+generate_map = true
+post_build = ["cp ${OUTPUT} ${OUTPUT}.bin"]
+post_build_outputs = ["app.bin"]
+sign = "cp ${OUTPUT} ${OUTPUT}.signed"
+"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("main.c"), "int main(){return 0;}").unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let builddir = dir.path().join("build dir");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), None);
+
+        let result = backend.emit(&graph, &builddir, dir.path()).unwrap();
+        assert!(builddir.join("app.signed").exists());
+        assert!(builddir.join("app.bin").exists());
+        assert!(builddir.join("app.map").exists());
+        assert_eq!(result.target_summaries[0].outputs.len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_link_maps_where_the_graph_declares_them() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        fs::write(
+            &manifest_path,
+            r#"[project]
+name = "demo"
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+generate_map = true
+"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("main.c"), "int main(){return 0;}").unwrap();
+        // Stands in for emcc: writes what `-o` and `-Wl,-Map=` name, and the
+        // `.wasm` next to a `.js`.
+        let emcc = dir.path().join("fake-emcc");
+        fs::write(
+            &emcc,
+            r#"#!/bin/sh
+for arg; do
+  case "$prev" in -o) out=$arg ;; esac
+  case "$arg" in -Wl,-Map=*) touch "${arg#-Wl,-Map=}" ;; esac
+  prev=$arg
+done
+touch "$out"
+case "$out" in *.js) touch "${out%.js}.wasm" ;; esac
+"#,
+        )
+        .unwrap();
+        fs::set_permissions(&emcc, fs::Permissions::from_mode(0o755)).unwrap();
+        let cross: CrossFile = toml::from_str(&format!(
+            "[binaries]\ncc = {:?}\n\n[host_machine]\nsystem = \"emscripten\"\ncpu = \"wasm32\"\n",
+            emcc.display().to_string()
+        ))
+        .unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, cross.platform()).unwrap();
+        let app = graph.get("app").unwrap();
+        assert_eq!(app.outputs, ["app.js", "app.wasm", "app.js.map"]);
+        assert_eq!(app.byproducts, ["app.js.map"]);
+        let builddir = dir.path().join("build");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), None).with_cross_file(cross);
+
+        backend.emit(&graph, &builddir, dir.path()).unwrap();
+        assert!(builddir.join("app.js.map").exists());
+        let again = backend.emit(&graph, &builddir, dir.path()).unwrap();
+        assert!(!again.target_summaries[0].built);
+    }
+
+    #[test]
+    fn links_dependencies_without_their_byproducts() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        fs::write(
+            &manifest_path,
+            r#"[project]
+name = "demo"
+
+[[targets]]
+type = "static_library"
+name = "core"
+sources = ["core.c"]
+post_build = ["echo core > ${OUTPUT}.syms"]
+post_build_outputs = ["libcore.a.syms"]
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+deps = ["core"]
+"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("core.c"), "int core(void){return 0;}").unwrap();
+        fs::write(
+            dir.path().join("main.c"),
+            "int core(void);\nint main(){return core();}",
+        )
+        .unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        assert_eq!(graph.get("core").unwrap().byproducts, ["libcore.a.syms"]);
+        let builddir = dir.path().join("build");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), None);
+
+        backend.emit(&graph, &builddir, dir.path()).unwrap();
+        assert!(builddir.join("libcore.a.syms").exists());
+        let status = Command::new(builddir.join("app")).status().unwrap();
+        assert!(status.success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_other_targets_while_a_custom_command_runs() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        // `slow` only finishes once the whole chain built while it ran.
+        fs::write(
+            &manifest_path,
+            r#"[project]
+name = "demo"
+
+[[targets]]
+type = "custom_command"
+name = "slow"
+outputs = ["slow.txt"]
+command = 'n=0; until [ -e "$CRUST_BUILDDIR/c.txt" ]; do n=$((n+1)); [ $n -gt 200 ] && exit 1; sleep 0.05; done; touch "$CRUST_BUILDDIR/slow.txt"'
+
+[[targets]]
+type = "custom_command"
+name = "a"
+outputs = ["a.txt"]
+command = 'touch "$CRUST_BUILDDIR/a.txt"'
+
+[[targets]]
+type = "custom_command"
+name = "b"
+outputs = ["b.txt"]
+command = 'touch "$CRUST_BUILDDIR/b.txt"'
+deps = ["a"]
+
+[[targets]]
+type = "custom_command"
+name = "c"
+outputs = ["c.txt"]
+command = 'touch "$CRUST_BUILDDIR/c.txt"'
+deps = ["b"]
+"#,
+        )
+        .unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let builddir = dir.path().join("build");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), Some(2));
+
+        backend.emit(&graph, &builddir, dir.path()).unwrap();
+        assert!(builddir.join("slow.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn combines_architecture_variants_into_universal_output() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        fs::write(
+            &manifest_path,
+            r#"[project]
+name = "demo"
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+architectures = ["a", "b"]
+universal = true
+sign = "cp ${OUTPUT} ${OUTPUT}.signed"
+"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("main.c"), "int main(){return 0;}").unwrap();
+        // Stands in for lipo: `-create -output OUT INPUTS...` concatenates.
+        let lipo = dir.path().join("fake-lipo");
+        fs::write(
+            &lipo,
+            "#!/bin/sh\nshift 2\nout=$1\nshift\ncat \"$@\" > \"$out\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&lipo, fs::Permissions::from_mode(0o755)).unwrap();
+        let cross: CrossFile = toml::from_str(&format!(
+            "[binaries]\nlipo = {:?}\n[architectures.a]\ncc = \"cc\"\n[architectures.b]\ncc = \"cc\"\n",
+            lipo.display().to_string()
+        ))
+        .unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let builddir = dir.path().join("build");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), None).with_cross_file(cross);
+        backend.emit(&graph, &builddir, dir.path()).unwrap();
+
+        let variant_len = fs::metadata(builddir.join("a/app")).unwrap().len();
+        assert!(builddir.join("b/app").exists());
+        assert!(!builddir.join("a/app.signed").exists());
+        assert_eq!(
+            fs::metadata(builddir.join("app")).unwrap().len(),
+            variant_len + fs::metadata(builddir.join("b/app")).unwrap().len()
+        );
+        assert!(builddir.join("app.signed").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn optimizes_executables_with_bolt_before_signing() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        fs::write(
+            &manifest_path,
+            r#"[project]
+name = "demo"
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+bolt = { profile = "app.fdata" }
+sign = "cp ${OUTPUT} ${OUTPUT}.signed"
+"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("main.c"), "int main(){return 0;}").unwrap();
+        fs::write(dir.path().join("app.fdata"), "").unwrap();
+        // Stands in for llvm-bolt: `IN -o OUT ARGS...` records its arguments
+        // and copies.
+        let bolt = dir.path().join("fake-bolt");
+        fs::write(
+            &bolt,
+            "#!/bin/sh
+echo \"$@\" > \"$0.args\"\ncp \"$1\" \"$3\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&bolt, fs::Permissions::from_mode(0o755)).unwrap();
+        let cross: CrossFile = toml::from_str(&format!(
+            "[binaries]\nbolt = {:?}\n",
+            bolt.display().to_string()
+        ))
+        .unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let builddir = dir.path().join("build");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), None).with_cross_file(cross);
+        backend.emit(&graph, &builddir, dir.path()).unwrap();
+
+        assert!(builddir.join("app.prebolt").exists());
+        assert!(builddir.join("app.signed").exists());
+        let args = fs::read_to_string(dir.path().join("fake-bolt.args")).unwrap();
+        assert!(args.contains("-data="));
+        assert!(args.contains("-split-functions"));
+        let status = Command::new(builddir.join("app")).status().unwrap();
+        assert!(status.success());
+    }
+
     #[test]
     fn builds_executable_native() {
         let dir = tempdir().unwrap();
@@ -381,7 +2780,7 @@ sources = ["main.c"]
         fs::write(dir.path().join("main.c"), "int main(){return 0;}").unwrap();
 
         let manifest = ProjectManifest::load(&manifest_path).unwrap();
-        let graph = DependencyGraph::from_manifest(&manifest).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
         let builddir = dir.path().join("build");
         let backend = CrustBackend::new(dir.path().to_path_buf(), None);
 
@@ -389,5 +2788,192 @@ sources = ["main.c"]
         let output = &result.files[0];
         assert!(output.exists());
         assert!(output.ends_with("app"));
+
+        let provenance: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(builddir.join("outputs.json")).unwrap())
+                .unwrap();
+        let record = &provenance["artifacts"][0];
+        assert_eq!(record["target"], "app");
+        assert_eq!(record["path"], "app");
+        assert_eq!(record["command_digest"].as_str().unwrap().len(), 64);
+
+        let database: Vec<CompileCommand> = serde_json::from_str(
+            &fs::read_to_string(builddir.join(compdb::COMPILE_DATABASE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(database.len(), 1);
+        assert_eq!(database[0].file, dir.path().join("main.c"));
+        assert_eq!(database[0].arguments[0], "cc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn builds_with_spaces_and_non_ascii_in_paths() {
+        for (project, source, builddir) in [
+            ("plain", "src/main.c", "build"),
+            ("with space", "src dir/main file.c", "build dir"),
+            ("naïve ünïcode", "quellen/größe.c", "baü/ö"),
+            ("mixed 'quote' #1", "a b/c'd $x.c", "out put/$HOME"),
+        ] {
+            let temp = tempdir().unwrap();
+            let dir = temp.path().join(project);
+            fs::create_dir_all(dir.join(source).parent().unwrap()).unwrap();
+            fs::write(dir.join(source), "int main(void){return 7;}\n").unwrap();
+            fs::write(dir.join("data in.txt"), "x").unwrap();
+            let manifest_path = dir.join("crust.build");
+            fs::write(
+                &manifest_path,
+                format!(
+                    r#"[project]
+name = "demo"
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["{source}"]
+
+[[targets]]
+type = "custom_command"
+name = "copy"
+inputs = ["data in.txt"]
+outputs = ["copied out.txt"]
+command = ["cp", "@INPUT@", "@OUTPUT0@"]
+"#
+                ),
+            )
+            .unwrap();
+
+            let manifest = ProjectManifest::load(&manifest_path).unwrap();
+            let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+            let builddir = dir.join(builddir);
+            let backend = CrustBackend::new(dir.clone(), None);
+            backend.emit(&graph, &builddir, &dir).unwrap();
+
+            let status = Command::new(builddir.join("app")).status().unwrap();
+            assert_eq!(status.code(), Some(7), "{project}");
+            assert_eq!(
+                fs::read_to_string(builddir.join("copied out.txt")).unwrap(),
+                "x"
+            );
+            // A second build finds everything up to date.
+            let result = backend.emit(&graph, &builddir, &dir).unwrap();
+            assert!(
+                result.target_summaries.iter().all(|summary| !summary.built),
+                "{project}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn downloads_in_the_hermetic_environment() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempdir().unwrap();
+        std::env::set_var("CRUST_DOWNLOAD_DIR", dir.path().join("downloads"));
+        let manifest_path = dir.path().join("crust.build");
+        fs::write(
+            &manifest_path,
+            format!(
+                r#"[project]
+name = "demo"
+
+[[targets]]
+type = "fetch"
+name = "data"
+url = "https://example.com/data.txt"
+sha256 = "{}"
+"#,
+                sha256_bytes(b"downloaded\n")
+            ),
+        )
+        .unwrap();
+        // Stands in for curl, as the launcher of tools: records its
+        // environment and writes the file given to `--output`.
+        let curl = dir.path().join("fake-curl");
+        fs::write(
+            &curl,
+            "#!/bin/sh\nenv > \"$0.env\"\n\
+             while [ \"$1\" != --output ]; do shift; done\nprintf 'downloaded\\n' > \"$2\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&curl, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let builddir = dir.path().join("build");
+        let backend = CrustBackend::new(dir.path().to_path_buf(), None)
+            .with_hermetic(Some(HermeticEnv::new(&[])))
+            .with_launchers(BTreeMap::from([(Rule::Tool, curl.display().to_string())]));
+        backend.emit(&graph, &builddir, dir.path()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(builddir.join("data/data.txt")).unwrap(),
+            "downloaded\n"
+        );
+        let env = fs::read_to_string(dir.path().join("fake-curl.env")).unwrap();
+        assert!(env.lines().any(|line| line.starts_with("PATH=")), "{env}");
+        assert!(!env.lines().any(|line| line.starts_with("HOME=")), "{env}");
+    }
+
+    #[test]
+    fn copies_files_through_paths_beyond_max_path() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let deep = ["very_long_directory_name"; 12].join("/");
+        fs::create_dir_all(dir.join(&deep)).unwrap();
+        fs::write(
+            dir.join("crust.build"),
+            format!("[project]\nname = \"demo\"\nsubdirs = [\"{deep}\"]\n"),
+        )
+        .unwrap();
+        fs::write(
+            dir.join(&deep).join("crust.build"),
+            r#"
+[[targets]]
+type = "copy_file"
+name = "config_h"
+input = "config.h.in"
+output = "config.h"
+variables = { VERSION = "1.2.0" }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join(&deep).join("config.h.in"),
+            "#define VERSION \"@VERSION@\"\n",
+        )
+        .unwrap();
+
+        let manifest = ProjectManifest::load(&dir.join("crust.build")).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let builddir = dir.join("build");
+        let backend = CrustBackend::new(dir.to_path_buf(), None);
+        backend.emit(&graph, &builddir, dir).unwrap();
+
+        let output = builddir.join(&deep).join("config.h");
+        assert!(output.as_os_str().len() > 260, "{output:?}");
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "#define VERSION \"1.2.0\"\n"
+        );
+        // The state recorded for it is read back on the next build.
+        let result = backend.emit(&graph, &builddir, dir).unwrap();
+        assert!(result.target_summaries.iter().all(|summary| !summary.built));
+    }
+
+    #[test]
+    fn shortens_intermediate_paths_of_deep_targets() {
+        let out_dir = Path::new("/build");
+        assert_eq!(
+            intermediate_path(out_dir, "//lib/util:core", "0.o"),
+            Path::new("/build/lib/util/core_0.o")
+        );
+
+        let deep = format!("//{}:core", ["very_long_directory_name"; 10].join("/"));
+        let object = intermediate_path(out_dir, &deep, "0.o");
+        assert!(object.starts_with("/build/.crust/objects"), "{object:?}");
+        assert!(object.as_os_str().len() <= INTERMEDIATE_PATH_LIMIT);
+        assert_eq!(object, intermediate_path(out_dir, &deep, "0.o"));
+        assert_ne!(object, intermediate_path(out_dir, &deep, "1.o"));
     }
 }
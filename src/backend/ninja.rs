@@ -1,4 +1,6 @@
-use crate::backend::{Backend, BackendEmitResult, TargetBuildSummary};
+use crate::backend::{
+    alias_inputs, alias_rule, dependency_inputs, Backend, BackendEmitResult, TargetBuildSummary,
+};
 use crate::graph::{DependencyGraph, TargetKind};
 use anyhow::Result;
 use std::fs;
@@ -25,25 +27,33 @@ impl Backend for NinjaBackend {
         lines.push("".to_string());
 
         for node in graph.topo_order()? {
-            let deps: Vec<String> = node
-                .dependencies
+            if node.kind == TargetKind::Alias {
+                lines.push(format!(
+                    "build {}: phony {}",
+                    escape_path(&alias_rule(node)),
+                    alias_inputs(graph, node, "${builddir}", escape_path).join(" ")
+                ));
+                lines.push(String::new());
+                continue;
+            }
+            let (deps, aliases) = dependency_inputs(graph, node, "${builddir}", escape_path);
+            let mut inputs: Vec<String> = node
+                .sources
                 .iter()
-                .map(|dep| format!("${{builddir}}/{dep}"))
+                .map(|s| format!("${{srcdir}}/{}", escape_path(s)))
+                .chain(deps)
                 .collect();
-            let inputs = if node.sources.is_empty() {
-                deps.clone()
-            } else {
-                node.sources
-                    .iter()
-                    .map(|s| format!("${{srcdir}}/{s}"))
-                    .chain(deps.into_iter())
-                    .collect()
-            };
+            // Order-only: members of an alias are built first, but nothing
+            // is rebuilt for them.
+            if !aliases.is_empty() {
+                inputs.push("||".to_string());
+                inputs.extend(aliases);
+            }
 
             let outputs: Vec<String> = node
                 .outputs
                 .iter()
-                .map(|o| format!("${{builddir}}/{o}"))
+                .map(|o| format!("${{builddir}}/{}", escape_path(o)))
                 .collect();
 
             let description = match node.kind {
@@ -51,12 +61,39 @@ impl Backend for NinjaBackend {
                 TargetKind::StaticLibrary => format!("archive {name}", name = node.name),
                 TargetKind::SharedLibrary => format!("shared {name}", name = node.name),
                 TargetKind::CustomCommand => format!("custom {name}", name = node.name),
+                TargetKind::Docs => format!("docs {name}", name = node.name),
+                TargetKind::Gettext => format!("gettext {name}", name = node.name),
+                TargetKind::Man => format!("man {name}", name = node.name),
+                TargetKind::VcsVersion => format!("vcs_version {name}", name = node.name),
+                TargetKind::Fetch => format!("fetch {name}", name = node.name),
+                TargetKind::Extract => format!("extract {name}", name = node.name),
+                TargetKind::CopyFile => format!("copy {name}", name = node.name),
+                TargetKind::PythonExtension => {
+                    format!("python_extension {name}", name = node.name)
+                }
+                TargetKind::PythonSources => format!("python_sources {name}", name = node.name),
+                TargetKind::RustExecutable => format!("rustc {name}", name = node.name),
+                TargetKind::RustLibrary => format!("rustc {name}", name = node.name),
+                TargetKind::Alias => format!("alias {name}", name = node.name),
             };
 
             for output in &outputs {
                 lines.push(format!("build {output}: stamp {}", inputs.join(" ")));
-                lines.push(format!("  description = {description}"));
+                lines.push(format!("  description = {}", escape_value(&description)));
                 if let Some(cmd) = &node.command {
+                    // In a command only `$` is special to ninja; the shell
+                    // quoting of list commands takes care of spaces.
+                    let in_command = |dir: &str, paths: &[String]| -> Vec<String> {
+                        paths
+                            .iter()
+                            .map(|path| format!("${{{dir}}}/{}", escape_value(path)))
+                            .collect()
+                    };
+                    let cmd = cmd.expand(
+                        &in_command("srcdir", &node.sources),
+                        &in_command("builddir", &node.outputs),
+                        "${builddir}",
+                    );
                     lines.push(format!("  command = {}", cmd));
                 }
                 lines.push(String::new());
@@ -69,8 +106,8 @@ impl Backend for NinjaBackend {
         let mut content = String::new();
         content.push_str("# Auto-generated by crust\n");
         content.push_str("builddir = ");
-        content.push_str(out_dir.to_string_lossy().as_ref());
-        content.push_str("\n");
+        content.push_str(&escape_value(&out_dir.to_string_lossy()));
+        content.push('\n');
         content.push_str("srcdir = .\n\n");
         content.push_str(&lines.join("\n"));
         content.push('\n');
@@ -84,6 +121,7 @@ impl Backend for NinjaBackend {
                 built: false,
                 outputs: node.outputs.iter().map(|o| out_dir.join(o)).collect(),
                 duration: Duration::default(),
+                diagnostics: Default::default(),
             })
             .collect();
 
@@ -98,10 +136,23 @@ impl Backend for NinjaBackend {
     }
 }
 
+/// `path` as one path of a ninja `build` line, where spaces and colons
+/// separate paths and `$` starts a variable.
+fn escape_path(path: &str) -> String {
+    escape_value(path).replace(' ', "$ ").replace(':', "$:")
+}
+
+/// `value` as the text of a ninja variable or command, where `$` starts a
+/// variable.
+fn escape_value(value: &str) -> String {
+    value.replace('$', "$$")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
     use tempfile::tempdir;
 
     #[test]
@@ -110,19 +161,125 @@ mod tests {
             project: ProjectInfo {
                 name: "demo".into(),
                 version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
             },
             targets: vec![Target::Executable {
                 name: "app".into(),
                 sources: vec!["src/main.c".into()],
                 deps: vec![],
+                labels: Vec::new(),
+                owners: Vec::new(),
+                deprecated: None,
+                options: Default::default(),
             }],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
         };
-        let graph = DependencyGraph::from_manifest(&manifest).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
         let dir = tempdir().unwrap();
         let backend = NinjaBackend;
         let result = backend.emit(&graph, dir.path(), dir.path()).unwrap();
         let content = std::fs::read_to_string(&result.files[0]).unwrap();
         assert!(content.contains("builddir ="));
         assert!(content.contains("build ${builddir}/app"));
+
+        // Paths with spaces, colons, `$`, and non-ASCII characters stay one
+        // path each, and one argument each in the command.
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "custom_command"
+name = "gen"
+inputs = ["in dir/dätä$1.txt"]
+outputs = ["out: ü.txt"]
+command = ["cp", "@INPUT@", "@OUTPUT@"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let dir = dir.path().join("build dir$");
+        let result = backend.emit(&graph, &dir, &dir).unwrap();
+        let content = std::fs::read_to_string(&result.files[0]).unwrap();
+        assert!(content.contains("builddir = "), "{content}");
+        assert!(content.contains("/build dir$$\n"), "{content}");
+        assert!(
+            content
+                .contains("build ${builddir}/out$:$ ü.txt: stamp ${srcdir}/in$ dir/dätä$$1.txt\n"),
+            "{content}"
+        );
+        assert!(
+            content
+                .contains("command = cp '${srcdir}/in dir/dätä$$1.txt' '${builddir}/out: ü.txt'\n"),
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn writes_phony_rules_for_aliases() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "custom_command"
+name = "gen"
+outputs = ["out: ü.txt", "$1.h"]
+command = "touch @OUTPUT@"
+
+[[targets]]
+type = "alias"
+name = "generated"
+deps = ["gen"]
+
+[[targets]]
+type = "alias"
+name = "everything"
+deps = ["generated"]
+
+[[targets]]
+type = "custom_command"
+name = "report"
+inputs = ["report.in"]
+outputs = ["report.txt"]
+command = "touch @OUTPUT@"
+deps = ["generated"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let dir = tempdir().unwrap();
+        let result = NinjaBackend.emit(&graph, dir.path(), dir.path()).unwrap();
+        let content = std::fs::read_to_string(&result.files[0]).unwrap();
+
+        assert!(
+            content.contains("build generated: phony ${builddir}/out$:$ ü.txt ${builddir}/$$1.h\n"),
+            "{content}"
+        );
+        assert!(
+            content.contains("build everything: phony generated\n"),
+            "{content}"
+        );
+        // A target depending on an alias runs after its members.
+        assert!(
+            content
+                .contains("build ${builddir}/report.txt: stamp ${srcdir}/report.in || generated\n"),
+            "{content}"
+        );
     }
 }
@@ -0,0 +1,288 @@
+use crate::backend::{absolute_dir, crust_program, declared_targets, Backend, BackendEmitResult};
+use crate::config;
+use crate::digest::sha256_bytes;
+use crate::graph::{DependencyGraph, TargetNode};
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Project type GUID of C++ projects in a solution.
+const CPP_PROJECT_TYPE: &str = "8BC9CEB8-8B4A-11D0-8D11-00A0C91BC942";
+const CONFIG: &str = "Debug|x64";
+
+/// Generates a Visual Studio solution with one Makefile project per target.
+/// Building a project runs `crust build <target>`; the sources are listed
+/// so IntelliSense and navigation work.
+pub struct VsBackend {
+    /// Name of the `.sln` file.
+    solution: String,
+}
+
+impl VsBackend {
+    /// Names the solution after the directory holding the manifest.
+    pub fn new(manifest_dir: &Path) -> Self {
+        let dir = absolute_dir(manifest_dir).unwrap_or_else(|_| manifest_dir.to_path_buf());
+        let solution = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "crust".into());
+        VsBackend { solution }
+    }
+
+    fn solution_file(&self, out_dir: &Path) -> PathBuf {
+        out_dir.join(format!("{}.sln", self.solution))
+    }
+}
+
+/// Stable project GUID, so regenerating the solution does not churn it.
+fn guid(name: &str) -> String {
+    let hex = sha256_bytes(format!("crust-vs:{name}").as_bytes()).to_uppercase();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn is_header(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|e| e.to_str()),
+        Some("h" | "hh" | "hpp" | "hxx")
+    )
+}
+
+/// Renders the `.vcxproj` of `node`. Paths are absolute; the manifest is
+/// expected at `manifest_dir/crust.build`.
+pub fn project(node: &TargetNode, manifest_dir: &Path, out_dir: &Path) -> String {
+    let crust = format!(
+        "&quot;{}&quot;",
+        escape(&crust_program().display().to_string())
+    );
+    let build = format!(
+        "{crust} build --manifest &quot;{}&quot; -b &quot;{}&quot; {}",
+        escape(&manifest_dir.join("crust.build").display().to_string()),
+        escape(&out_dir.display().to_string()),
+        escape(&node.name)
+    );
+    let mut lines = vec![
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>".to_string(),
+        "<!-- Generated by crust; edit crust.build instead. -->".into(),
+        "<Project DefaultTargets=\"Build\" xmlns=\"http://schemas.microsoft.com/developer/msbuild/2003\">".into(),
+        "  <ItemGroup Label=\"ProjectConfigurations\">".into(),
+        format!("    <ProjectConfiguration Include=\"{CONFIG}\">"),
+        "      <Configuration>Debug</Configuration>".into(),
+        "      <Platform>x64</Platform>".into(),
+        "    </ProjectConfiguration>".into(),
+        "  </ItemGroup>".into(),
+        "  <PropertyGroup Label=\"Globals\">".into(),
+        format!("    <ProjectGuid>{{{}}}</ProjectGuid>", guid(&node.name)),
+        format!("    <RootNamespace>{}</RootNamespace>", escape(&node.name)),
+        "    <Keyword>MakeFileProj</Keyword>".into(),
+        "  </PropertyGroup>".into(),
+        "  <Import Project=\"$(VCTargetsPath)\\Microsoft.Cpp.Default.props\" />".into(),
+        "  <PropertyGroup Label=\"Configuration\">".into(),
+        "    <ConfigurationType>Makefile</ConfigurationType>".into(),
+        "    <PlatformToolset>v143</PlatformToolset>".into(),
+        "  </PropertyGroup>".into(),
+        "  <Import Project=\"$(VCTargetsPath)\\Microsoft.Cpp.props\" />".into(),
+        "  <PropertyGroup>".into(),
+        format!("    <NMakeBuildCommandLine>{build}</NMakeBuildCommandLine>"),
+        // `crust clean` would delete the solution along with the build
+        // directory, so rebuilding just builds and cleaning does nothing.
+        format!("    <NMakeReBuildCommandLine>{build}</NMakeReBuildCommandLine>"),
+    ];
+    if let Some(output) = node.outputs.first() {
+        lines.push(format!(
+            "    <NMakeOutput>{}</NMakeOutput>",
+            escape(&out_dir.join(output).display().to_string())
+        ));
+    }
+    lines.push("  </PropertyGroup>".into());
+    if !node.sources.is_empty() {
+        lines.push("  <ItemGroup>".into());
+        for source in &node.sources {
+            let item = if is_header(source) {
+                "ClInclude"
+            } else {
+                "ClCompile"
+            };
+            lines.push(format!(
+                "    <{item} Include=\"{}\" />",
+                escape(&manifest_dir.join(source).display().to_string())
+            ));
+        }
+        lines.push("  </ItemGroup>".into());
+    }
+    lines.push("  <Import Project=\"$(VCTargetsPath)\\Microsoft.Cpp.targets\" />".into());
+    lines.push("</Project>".into());
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Renders the `.sln` referencing `<target>.vcxproj` next to it, with the
+/// manifest's dependencies as project dependencies.
+pub fn solution(targets: &[&TargetNode]) -> String {
+    let mut lines = vec![
+        String::new(),
+        "Microsoft Visual Studio Solution File, Format Version 12.00".into(),
+        "# Visual Studio Version 17".into(),
+        "VisualStudioVersion = 17.0.31903.59".into(),
+        "MinimumVisualStudioVersion = 10.0.40219.1".into(),
+    ];
+    for node in targets {
+        lines.push(format!(
+            "Project(\"{{{CPP_PROJECT_TYPE}}}\") = \"{}\", \"{}.vcxproj\", \"{{{}}}\"",
+            node.name,
+            config::label_path(&node.name),
+            guid(&node.name)
+        ));
+        let deps: Vec<&String> = node
+            .dependencies
+            .iter()
+            .filter(|dep| targets.iter().any(|t| &t.name == *dep))
+            .collect();
+        if !deps.is_empty() {
+            lines.push("\tProjectSection(ProjectDependencies) = postProject".into());
+            for dep in deps {
+                lines.push(format!("\t\t{{{0}}} = {{{0}}}", guid(dep)));
+            }
+            lines.push("\tEndProjectSection".into());
+        }
+        lines.push("EndProject".into());
+    }
+    lines.push("Global".into());
+    lines.push("\tGlobalSection(SolutionConfigurationPlatforms) = preSolution".into());
+    lines.push(format!("\t\t{CONFIG} = {CONFIG}"));
+    lines.push("\tEndGlobalSection".into());
+    lines.push("\tGlobalSection(ProjectConfigurationPlatforms) = postSolution".into());
+    for node in targets {
+        let id = guid(&node.name);
+        lines.push(format!("\t\t{{{id}}}.{CONFIG}.ActiveCfg = {CONFIG}"));
+        lines.push(format!("\t\t{{{id}}}.{CONFIG}.Build.0 = {CONFIG}"));
+    }
+    lines.push("\tEndGlobalSection".into());
+    lines.push("EndGlobal".into());
+    lines.join("\r\n") + "\r\n"
+}
+
+impl Backend for VsBackend {
+    fn name(&self) -> &str {
+        "vs"
+    }
+
+    fn emit(
+        &self,
+        graph: &DependencyGraph,
+        out_dir: &Path,
+        manifest_dir: &Path,
+    ) -> Result<BackendEmitResult> {
+        fs::create_dir_all(out_dir)?;
+        let manifest_dir = absolute_dir(manifest_dir)?;
+        let absolute_out = absolute_dir(out_dir)?;
+        let targets = declared_targets(graph)?;
+        let mut files = Vec::new();
+        for node in &targets {
+            let path = out_dir.join(format!("{}.vcxproj", config::label_path(&node.name)));
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, project(node, &manifest_dir, &absolute_out))?;
+            files.push(path);
+        }
+        let path = self.solution_file(out_dir);
+        fs::write(&path, solution(&targets))?;
+        files.insert(0, path);
+        Ok(BackendEmitResult {
+            files,
+            target_summaries: Vec::new(),
+        })
+    }
+
+    fn primary_outputs(&self, _graph: &DependencyGraph, out_dir: &Path) -> Vec<PathBuf> {
+        vec![self.solution_file(out_dir)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::xcode::XcodeBackend;
+    use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
+    use tempfile::tempdir;
+
+    #[test]
+    fn writes_solution_and_xcode_project_wrapping_crust() {
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::StaticLibrary {
+                    name: "core".into(),
+                    sources: vec!["core.c".into(), "core.h".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+                Target::Executable {
+                    name: "app".into(),
+                    sources: vec!["main.c".into()],
+                    deps: vec!["core".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("demo");
+        let out = dir.path().join("build");
+
+        let vs = VsBackend::new(&src);
+        let result = vs.emit(&graph, &out, &src).unwrap();
+        assert_eq!(result.files[0], out.join("demo.sln"));
+        let sln = fs::read_to_string(&result.files[0]).unwrap();
+        assert!(sln.contains("\"app\", \"app.vcxproj\""));
+        assert!(sln.contains(&format!("{{{0}}} = {{{0}}}", guid("core"))));
+        let vcxproj = fs::read_to_string(out.join("core.vcxproj")).unwrap();
+        assert!(vcxproj.contains("<ClInclude Include="));
+        assert!(vcxproj.contains(" core</NMakeBuildCommandLine>"));
+
+        let xcode = XcodeBackend::new(&src);
+        let result = xcode.emit(&graph, &out, &src).unwrap();
+        assert_eq!(result.files[0], out.join("demo.xcodeproj/project.pbxproj"));
+        let pbxproj = fs::read_to_string(&result.files[0]).unwrap();
+        assert!(pbxproj.contains("isa = PBXLegacyTarget;"));
+        assert!(pbxproj.contains("/* ALL_BUILD */"));
+        assert!(pbxproj.contains("path = core.h;"));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::backend::{absolute_dir, crust_program, declared_targets, Backend, BackendEmitResult};
+use crate::digest::sha256_bytes;
+use crate::graph::DependencyGraph;
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Generates an Xcode project whose targets are external build tool
+/// (`PBXLegacyTarget`) targets running `crust build <target>`, with the
+/// sources listed for browsing.
+pub struct XcodeBackend {
+    /// Name of the `.xcodeproj` bundle.
+    project: String,
+}
+
+impl XcodeBackend {
+    /// Names the project after the directory holding the manifest.
+    pub fn new(manifest_dir: &Path) -> Self {
+        let dir = absolute_dir(manifest_dir).unwrap_or_else(|_| manifest_dir.to_path_buf());
+        let project = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "crust".into());
+        XcodeBackend { project }
+    }
+
+    fn project_file(&self, out_dir: &Path) -> PathBuf {
+        out_dir
+            .join(format!("{}.xcodeproj", self.project))
+            .join("project.pbxproj")
+    }
+}
+
+/// Stable 24-digit object id, so regenerating the project does not churn it.
+fn object_id(kind: &str, name: &str) -> String {
+    sha256_bytes(format!("crust-xcode:{kind}:{name}").as_bytes())[..24].to_uppercase()
+}
+
+/// Quotes a pbxproj string unless it consists only of safe characters.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_$/.:-".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn file_type(path: &str) -> &'static str {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("c") => "sourcecode.c.c",
+        Some("cc" | "cpp" | "cxx" | "c++") => "sourcecode.cpp.cpp",
+        Some("m") => "sourcecode.c.objc",
+        Some("mm") => "sourcecode.cpp.objcpp",
+        Some("h" | "hh" | "hpp" | "hxx") => "sourcecode.c.h",
+        _ => "text",
+    }
+}
+
+/// Renders `project.pbxproj`. `manifest_dir` and `out_dir` are absolute.
+pub fn render(graph: &DependencyGraph, manifest_dir: &Path, out_dir: &Path) -> Result<String> {
+    let targets = declared_targets(graph)?;
+    let program = crust_program();
+    let sources: BTreeSet<&str> = targets
+        .iter()
+        .flat_map(|node| node.sources.iter().map(String::as_str))
+        .collect();
+
+    let mut objects = vec!["/* Begin PBXFileReference section */".to_string()];
+    for source in &sources {
+        objects.push(format!(
+            "\t\t{} /* {source} */ = {{isa = PBXFileReference; lastKnownFileType = {}; path = {}; sourceTree = SOURCE_ROOT; }};",
+            object_id("file", source),
+            file_type(source),
+            quote(source)
+        ));
+    }
+    objects.push("/* End PBXFileReference section */".into());
+
+    let main_group = object_id("group", "main");
+    objects.push("/* Begin PBXGroup section */".into());
+    objects.push(format!("\t\t{main_group} = {{"));
+    objects.push("\t\t\tisa = PBXGroup;".into());
+    objects.push("\t\t\tchildren = (".into());
+    for source in &sources {
+        objects.push(format!("\t\t\t\t{},", object_id("file", source)));
+    }
+    objects.push("\t\t\t);".into());
+    objects.push("\t\t\tsourceTree = \"<group>\";".into());
+    objects.push("\t\t};".into());
+    objects.push("/* End PBXGroup section */".into());
+
+    // (Xcode name, crust target, id key). The first target builds
+    // everything, like a plain `crust build`; keys cannot clash with the
+    // project's own "project" key.
+    let mut legacy = vec![("ALL_BUILD".to_string(), None, "*".to_string())];
+    legacy.extend(targets.iter().map(|node| {
+        (
+            node.name.clone(),
+            Some(node.name.clone()),
+            format!("target:{}", node.name),
+        )
+    }));
+    objects.push("/* Begin PBXLegacyTarget section */".into());
+    for (name, target, key) in &legacy {
+        let mut arguments = vec![
+            "build".to_string(),
+            "-b".into(),
+            out_dir.display().to_string(),
+        ];
+        arguments.extend(target.iter().cloned());
+        objects.push(format!(
+            "\t\t{} /* {name} */ = {{",
+            object_id("target", key)
+        ));
+        objects.push("\t\t\tisa = PBXLegacyTarget;".into());
+        objects.push(format!(
+            "\t\t\tbuildArgumentsString = {};",
+            quote(&arguments.join(" "))
+        ));
+        objects.push(format!(
+            "\t\t\tbuildConfigurationList = {};",
+            object_id("configlist", key)
+        ));
+        objects.push("\t\t\tbuildPhases = (\n\t\t\t);".into());
+        objects.push(format!(
+            "\t\t\tbuildToolPath = {};",
+            quote(&program.display().to_string())
+        ));
+        objects.push(format!(
+            "\t\t\tbuildWorkingDirectory = {};",
+            quote(&manifest_dir.display().to_string())
+        ));
+        objects.push("\t\t\tdependencies = (\n\t\t\t);".into());
+        objects.push(format!("\t\t\tname = {};", quote(name)));
+        objects.push("\t\t\tpassBuildSettingsInEnvironment = 0;".into());
+        objects.push(format!("\t\t\tproductName = {};", quote(name)));
+        objects.push("\t\t};".into());
+    }
+    objects.push("/* End PBXLegacyTarget section */".into());
+
+    let project = object_id("project", "root");
+    objects.push("/* Begin PBXProject section */".into());
+    objects.push(format!("\t\t{project} /* Project object */ = {{"));
+    objects.push("\t\t\tisa = PBXProject;".into());
+    objects.push(format!(
+        "\t\t\tbuildConfigurationList = {};",
+        object_id("configlist", "project")
+    ));
+    objects.push("\t\t\tcompatibilityVersion = \"Xcode 3.2\";".into());
+    objects.push(format!("\t\t\tmainGroup = {main_group};"));
+    objects.push(format!(
+        "\t\t\tprojectDirPath = {};",
+        quote(&manifest_dir.display().to_string())
+    ));
+    objects.push("\t\t\tprojectRoot = \"\";".into());
+    objects.push("\t\t\ttargets = (".into());
+    for (name, _, key) in &legacy {
+        objects.push(format!(
+            "\t\t\t\t{} /* {name} */,",
+            object_id("target", key)
+        ));
+    }
+    objects.push("\t\t\t);".into());
+    objects.push("\t\t};".into());
+    objects.push("/* End PBXProject section */".into());
+
+    // One Debug configuration per target and for the project; the build
+    // itself is configured by crust.
+    let lists: Vec<&str> = std::iter::once("project")
+        .chain(legacy.iter().map(|(_, _, key)| key.as_str()))
+        .collect();
+    objects.push("/* Begin XCBuildConfiguration section */".into());
+    for key in &lists {
+        objects.push(format!(
+            "\t\t{} /* Debug */ = {{isa = XCBuildConfiguration; buildSettings = {{ }}; name = Debug; }};",
+            object_id("config", key)
+        ));
+    }
+    objects.push("/* End XCBuildConfiguration section */".into());
+    objects.push("/* Begin XCConfigurationList section */".into());
+    for key in &lists {
+        objects.push(format!("\t\t{} = {{", object_id("configlist", key)));
+        objects.push("\t\t\tisa = XCConfigurationList;".into());
+        objects.push(format!(
+            "\t\t\tbuildConfigurations = (\n\t\t\t\t{} /* Debug */,\n\t\t\t);",
+            object_id("config", key)
+        ));
+        objects.push("\t\t\tdefaultConfigurationIsVisible = 0;".into());
+        objects.push("\t\t\tdefaultConfigurationName = Debug;".into());
+        objects.push("\t\t};".into());
+    }
+    objects.push("/* End XCConfigurationList section */".into());
+
+    Ok(format!(
+        "// !$*UTF8*$!\n{{\n\tarchiveVersion = 1;\n\tclasses = {{\n\t}};\n\tobjectVersion = 46;\n\
+         \tobjects = {{\n\n{}\n\t}};\n\trootObject = {project} /* Project object */;\n}}\n",
+        objects.join("\n")
+    ))
+}
+
+impl Backend for XcodeBackend {
+    fn name(&self) -> &str {
+        "xcode"
+    }
+
+    fn emit(
+        &self,
+        graph: &DependencyGraph,
+        out_dir: &Path,
+        manifest_dir: &Path,
+    ) -> Result<BackendEmitResult> {
+        let path = self.project_file(out_dir);
+        if let Some(bundle) = path.parent() {
+            fs::create_dir_all(bundle)?;
+        }
+        let content = render(graph, &absolute_dir(manifest_dir)?, &absolute_dir(out_dir)?)?;
+        fs::write(&path, content)?;
+        Ok(BackendEmitResult {
+            files: vec![path],
+            target_summaries: Vec::new(),
+        })
+    }
+
+    fn primary_outputs(&self, _graph: &DependencyGraph, out_dir: &Path) -> Vec<PathBuf> {
+        vec![self.project_file(out_dir)]
+    }
+}
@@ -0,0 +1,392 @@
+use crate::error::CrustError;
+use crate::graph::DependencyGraph;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use tracing::{debug, info, warn};
+
+/// Directory of a build directory holding crust's own bookkeeping.
+pub const STATE_DIR: &str = ".crust";
+
+/// Lock file below [`STATE_DIR`], holding the pid of the crust using it.
+const LOCK_FILE: &str = "lock";
+
+/// Metadata file below [`STATE_DIR`].
+const META_FILE: &str = "meta.json";
+
+/// Advisory lock on a build directory, held by the crust command writing to
+/// it so that two runs do not interleave their outputs. Released when
+/// dropped, or by the system when crust exits, however it exits.
+#[derive(Debug)]
+pub struct BuildDirLock {
+    _file: File,
+}
+
+impl BuildDirLock {
+    /// Locks `builddir`, creating it. When another crust holds the lock,
+    /// fails naming its pid, or with `wait` waits until it is released.
+    pub fn acquire(builddir: &Path, wait: bool) -> Result<Self> {
+        let dir = builddir.join(STATE_DIR);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                // Unreadable while locked on Windows; the pid is a courtesy.
+                let holder = match fs::read_to_string(&path) {
+                    Ok(pid) if !pid.trim().is_empty() => format!(" (pid {})", pid.trim()),
+                    _ => String::new(),
+                };
+                if !wait {
+                    return Err(anyhow!(
+                        "Another crust is running in {}{holder}; pass --wait to run after it",
+                        builddir.display()
+                    ));
+                }
+                info!(
+                    "Waiting for another crust running in {}{holder}",
+                    builddir.display()
+                );
+                file.lock()
+                    .with_context(|| format!("Failed to lock {}", path.display()))?;
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(err).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", process::id())?;
+        Ok(BuildDirLock { _file: file })
+    }
+}
+
+/// What a build directory was configured for, recorded in
+/// `.crust/meta.json` so that a later command can tell when it is pointed at
+/// the build directory of another project or backend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Meta {
+    pub crust_version: String,
+    /// Manifest the build directory was configured from.
+    pub manifest: PathBuf,
+    /// Directory of that manifest, the project's source directory.
+    pub source_dir: PathBuf,
+    pub backend: String,
+}
+
+impl Meta {
+    /// Metadata of a build of `manifest` with `backend`, with absolute
+    /// paths so that commands run from other directories compare equal.
+    pub fn new(manifest: &Path, backend: &str) -> Result<Self> {
+        let manifest = fs::canonicalize(manifest)
+            .with_context(|| format!("Failed to resolve {}", manifest.display()))?;
+        let source_dir = manifest.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(Meta {
+            crust_version: env!("CARGO_PKG_VERSION").to_string(),
+            manifest,
+            source_dir,
+            backend: backend.to_string(),
+        })
+    }
+
+    /// The metadata recorded in `builddir`, if any.
+    pub fn load(builddir: &Path) -> Option<Self> {
+        let text = fs::read_to_string(builddir.join(STATE_DIR).join(META_FILE)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Records this metadata in `builddir` after checking it against what an
+    /// earlier command recorded. A build directory of another source
+    /// directory is refused, since the builds would overwrite each other's
+    /// outputs; a change of backend is only warned about, as the previous
+    /// backend's files are left behind.
+    pub fn record(&self, builddir: &Path) -> Result<()> {
+        if let Some(previous) = Meta::load(builddir) {
+            if previous.source_dir != self.source_dir {
+                return Err(CrustError::InvalidArgument {
+                    message: format!(
+                        "{} is the build directory of {}, not {}; pass another --builddir, or run `crust clean -b {}` first",
+                        builddir.display(),
+                        previous.source_dir.display(),
+                        self.source_dir.display(),
+                        builddir.display()
+                    ),
+                }
+                .into());
+            }
+            if previous.backend != self.backend {
+                warn!(
+                    "{} was configured for the {} backend, now {}; its files stay until `crust clean`",
+                    builddir.display(),
+                    previous.backend,
+                    self.backend
+                );
+            }
+            if previous == *self {
+                return Ok(());
+            }
+        }
+        let dir = builddir.join(STATE_DIR);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(META_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        debug!(path = %path.display(), "recorded build directory metadata");
+        Ok(())
+    }
+}
+
+/// Checks that building the project in `source_dir` into `builddir` keeps
+/// outputs and sources apart. A build directory that is the source
+/// directory or holds it would scatter outputs among the sources, and one
+/// inside a directory a target reads would make that target's outputs its
+/// own inputs, so it would rebuild on every run.
+pub fn check_placement(graph: &DependencyGraph, source_dir: &Path, builddir: &Path) -> Result<()> {
+    let source_dir = resolve(source_dir);
+    let dir = resolve(builddir);
+    if source_dir.starts_with(&dir) {
+        return Err(CrustError::InvalidArgument {
+            message: format!(
+                "Build directory {} holds the sources in {}, so outputs would be written among them; build out of source with a dedicated directory, such as `-b build`",
+                builddir.display(),
+                source_dir.display()
+            ),
+        }
+        .into());
+    }
+    for node in graph.nodes() {
+        let inputs = node
+            .sources
+            .iter()
+            .chain(&node.options.embed_files)
+            .chain(&node.options.linker_script);
+        for input in inputs {
+            let path = source_dir.join(input);
+            if path.is_dir() && dir.starts_with(resolve(&path)) {
+                return Err(CrustError::InvalidTarget {
+                    target: node.name.clone(),
+                    message: format!(
+                        "it reads the directory {input}, which holds the build directory {}, so its outputs would become its inputs and it would rebuild on every run; choose a build directory outside {input}, or list narrower inputs",
+                        builddir.display()
+                    ),
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `path` made absolute with symbolic links resolved, as far as it exists;
+/// the rest, such as a build directory not created yet, is appended.
+fn resolve(path: &Path) -> PathBuf {
+    // The directory of a bare `crust.build` is empty.
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    while fs::canonicalize(existing).is_err() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return path,
+        }
+    }
+    let mut resolved = fs::canonicalize(existing).unwrap_or_default();
+    for name in rest.into_iter().rev() {
+        resolved.push(name);
+    }
+    resolved
+}
+
+/// Checks that `crust clean` may remove `builddir`, of the project in
+/// `project_dir` unless its metadata names another. A directory holding the
+/// project, the current directory, or the home directory is never removed.
+/// Without `force`, neither is one lacking crust's metadata, which crust
+/// did not create, nor one outside the project, which a typo may name.
+pub fn check_removable(builddir: &Path, project_dir: &Path, force: bool) -> Result<()> {
+    let refuse = |reason: String| -> Result<()> {
+        Err(CrustError::InvalidArgument {
+            message: format!("Refusing to remove {}: {reason}", builddir.display()),
+        }
+        .into())
+    };
+    let dir = fs::canonicalize(builddir)
+        .with_context(|| format!("Failed to resolve {}", builddir.display()))?;
+    let meta = Meta::load(builddir);
+    let root = match &meta {
+        Some(meta) => meta.source_dir.clone(),
+        None => resolve(project_dir),
+    };
+    let cwd = env::current_dir().and_then(fs::canonicalize).ok();
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .and_then(|home| fs::canonicalize(home).ok());
+    for (kept, what) in [
+        (Some(root.clone()), "the project"),
+        (cwd, "the current directory"),
+        (home, "the home directory"),
+    ] {
+        if kept.is_some_and(|kept| kept.starts_with(&dir)) {
+            return refuse(format!("it holds {what}"));
+        }
+    }
+    if force {
+        return Ok(());
+    }
+    if meta.is_none() {
+        return refuse(format!(
+            "it has no {STATE_DIR}/{META_FILE}, so crust did not configure it; pass --force to remove it anyway"
+        ));
+    }
+    if !dir.starts_with(&root) {
+        return refuse(format!(
+            "it is outside the project at {}; pass --force to remove it anyway",
+            root.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProjectManifest;
+    use crate::cross::Platform;
+
+    #[test]
+    fn refuses_a_second_run_until_the_first_is_done() {
+        let dir = tempfile::tempdir().unwrap();
+        let builddir = dir.path().join("build");
+        let lock = BuildDirLock::acquire(&builddir, false).unwrap();
+        let pid = fs::read_to_string(builddir.join(STATE_DIR).join(LOCK_FILE)).unwrap();
+        assert_eq!(pid.trim(), process::id().to_string());
+
+        let err = BuildDirLock::acquire(&builddir, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Another crust is running"), "{err}");
+        assert!(err.contains(&format!("(pid {})", process::id())), "{err}");
+
+        let waiting = std::thread::spawn(move || BuildDirLock::acquire(&builddir, true));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(lock);
+        waiting.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn refuses_the_build_directory_of_another_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let builddir = dir.path().join("build");
+        for project in ["app", "other"] {
+            fs::create_dir_all(dir.path().join(project)).unwrap();
+            fs::write(dir.path().join(project).join("crust.build"), "").unwrap();
+        }
+        let manifest = dir.path().join("app/crust.build");
+        let native = Meta::new(&manifest, "native").unwrap();
+        native.record(&builddir).unwrap();
+        assert_eq!(Meta::load(&builddir).unwrap(), native);
+        assert_eq!(
+            native.source_dir,
+            fs::canonicalize(dir.path().join("app")).unwrap()
+        );
+
+        // Another backend only warns.
+        let ninja = Meta::new(&manifest, "ninja").unwrap();
+        ninja.record(&builddir).unwrap();
+        assert_eq!(Meta::load(&builddir).unwrap().backend, "ninja");
+
+        let other = Meta::new(&dir.path().join("other/crust.build"), "ninja").unwrap();
+        let err = other.record(&builddir).unwrap_err().to_string();
+        assert!(err.contains("is the build directory of"), "{err}");
+        assert_eq!(Meta::load(&builddir).unwrap(), ninja);
+    }
+
+    #[test]
+    fn removes_only_configured_build_directories_inside_the_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("app");
+        fs::create_dir_all(&project).unwrap();
+        fs::write(project.join("crust.build"), "").unwrap();
+        let meta = Meta::new(&project.join("crust.build"), "native").unwrap();
+
+        let builddir = project.join("build");
+        fs::create_dir_all(&builddir).unwrap();
+        let err = check_removable(&builddir, &project, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("crust did not configure it"), "{err}");
+        check_removable(&builddir, &project, true).unwrap();
+        meta.record(&builddir).unwrap();
+        check_removable(&builddir, &project, false).unwrap();
+
+        let outside = dir.path().join("build-app");
+        meta.record(&outside).unwrap();
+        let err = check_removable(&outside, &project, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("outside the project"), "{err}");
+        check_removable(&outside, &project, true).unwrap();
+
+        // Not even with --force.
+        meta.record(&project).unwrap();
+        for holder in [&project, &dir.path().to_path_buf()] {
+            let err = check_removable(holder, &project, true)
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("it holds the project"), "{err}");
+        }
+    }
+
+    #[test]
+    fn keeps_outputs_out_of_the_sources_and_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path();
+        fs::create_dir_all(source_dir.join("assets")).unwrap();
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "custom_command"
+name = "bundle"
+inputs = ["assets"]
+command = "tar -cf $CRUST_BUILDDIR/assets.tar assets"
+outputs = ["assets.tar"]
+"#,
+            &source_dir.join("crust.build"),
+        )
+        .unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+
+        check_placement(&graph, source_dir, &source_dir.join("build")).unwrap();
+        check_placement(&graph, source_dir, &dir.path().join("../elsewhere")).unwrap();
+        for in_source in [source_dir.to_path_buf(), source_dir.join("..")] {
+            let err = check_placement(&graph, source_dir, &in_source).unwrap_err();
+            assert!(err.to_string().contains("holds the sources"), "{err}");
+        }
+        let err = check_placement(&graph, source_dir, &source_dir.join("assets/build"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Invalid target 'bundle'"), "{err}");
+        assert!(err.contains("rebuild on every run"), "{err}");
+    }
+}
@@ -0,0 +1,337 @@
+use crate::config::{CommandLine, ProjectManifest, Target};
+use crate::error::CrustError;
+use anyhow::Result;
+use std::fmt;
+use std::path::Path;
+use tracing::warn;
+
+/// Version of this crust binary.
+pub const CRUST_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A `major.minor.patch` release; missing components count as zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Version> {
+        let mut parts = text.trim().split('.');
+        let mut next = |required: bool| match parts.next() {
+            Some(part) => part.parse().ok(),
+            None if required => None,
+            None => Some(0),
+        };
+        let version = Version::new(next(true)?, next(false)?, next(false)?);
+        parts.next().is_none().then_some(version)
+    }
+
+    pub fn current() -> Version {
+        Version::parse(CRUST_VERSION).expect("crate version is major.minor.patch")
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Exact,
+}
+
+/// A `crust_version` requirement: comma-separated comparisons such as
+/// `">=0.2"` or `">=0.2, <1.0"`. A bare version means `>=`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<(Op, Version)>,
+}
+
+impl VersionReq {
+    pub fn parse(text: &str) -> Option<VersionReq> {
+        let comparators = text
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                let (op, rest) = [
+                    (">=", Op::GreaterEq),
+                    ("<=", Op::LessEq),
+                    ("==", Op::Exact),
+                    (">", Op::Greater),
+                    ("<", Op::Less),
+                    ("=", Op::Exact),
+                ]
+                .into_iter()
+                .find_map(|(prefix, op)| part.strip_prefix(prefix).map(|rest| (op, rest)))
+                .unwrap_or((Op::GreaterEq, part));
+                Some((op, Version::parse(rest)?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(VersionReq { comparators })
+    }
+
+    pub fn matches(&self, version: Version) -> bool {
+        self.comparators.iter().all(|(op, bound)| match op {
+            Op::Greater => version > *bound,
+            Op::GreaterEq => version >= *bound,
+            Op::Less => version < *bound,
+            Op::LessEq => version <= *bound,
+            Op::Exact => version == *bound,
+        })
+    }
+
+    /// Oldest release the requirement admits, i.e. the features a manifest
+    /// may rely on.
+    pub fn minimum(&self) -> Version {
+        self.comparators
+            .iter()
+            .filter_map(|(op, bound)| match op {
+                Op::GreaterEq | Op::Exact => Some(*bound),
+                Op::Greater => Some(Version::new(bound.major, bound.minor, bound.patch + 1)),
+                Op::Less | Op::LessEq => None,
+            })
+            .max()
+            .unwrap_or(Version::new(0, 0, 0))
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self
+            .comparators
+            .iter()
+            .map(|(op, bound)| {
+                let op = match op {
+                    Op::Greater => ">",
+                    Op::GreaterEq => ">=",
+                    Op::Less => "<",
+                    Op::LessEq => "<=",
+                    Op::Exact => "==",
+                };
+                format!("{op}{bound}")
+            })
+            .collect();
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// Release that added the manifest features introduced after 0.1.
+const V0_2: Version = Version::new(0, 2, 0);
+
+/// Manifest features newer than the first release, with the version that
+/// introduced each.
+pub fn used_features(manifest: &ProjectManifest) -> Vec<(&'static str, Version)> {
+    let mut features = Vec::new();
+    let mut add = |used: bool, feature: &'static str, since: Version| {
+        if used && !features.iter().any(|(name, _)| *name == feature) {
+            features.push((feature, since));
+        }
+    };
+    add(!manifest.project.subdirs.is_empty(), "subdirs", V0_2);
+    add(!manifest.profile.is_empty(), "profiles", V0_2);
+    add(!manifest.dependencies.is_empty(), "dependencies", V0_2);
+    add(
+        !manifest.project.passthrough_env.is_empty(),
+        "passthrough_env",
+        V0_2,
+    );
+    add(!manifest.project.launchers.is_empty(), "launchers", V0_2);
+    for target in &manifest.targets {
+        add(!target.labels().is_empty(), "labels", V0_2);
+        add(!target.owners().is_empty(), "owners", V0_2);
+        add(target.deprecated().is_some(), "deprecated", V0_2);
+        add(
+            matches!(
+                target,
+                Target::CustomCommand {
+                    worker: Some(_),
+                    ..
+                }
+            ),
+            "persistent workers",
+            V0_2,
+        );
+        add(
+            matches!(
+                target,
+                Target::CustomCommand {
+                    command: CommandLine::Argv(_),
+                    ..
+                }
+            ),
+            "command lists",
+            V0_2,
+        );
+        add(
+            matches!(target, Target::CustomCommand { programs, .. } if !programs.is_empty()),
+            "programs",
+            V0_2,
+        );
+        add(matches!(target, Target::Docs { .. }), "docs targets", V0_2);
+        add(
+            matches!(target, Target::Gettext { .. }),
+            "gettext targets",
+            V0_2,
+        );
+        add(matches!(target, Target::Man { .. }), "man targets", V0_2);
+        add(
+            matches!(target, Target::Alias { .. }),
+            "alias targets",
+            V0_2,
+        );
+        add(
+            matches!(target, Target::VcsVersion { .. }),
+            "vcs_version targets",
+            V0_2,
+        );
+        add(
+            matches!(target, Target::Fetch { .. }),
+            "fetch targets",
+            V0_2,
+        );
+        add(
+            matches!(target, Target::Extract { .. }),
+            "extract targets",
+            V0_2,
+        );
+        add(
+            matches!(target, Target::CopyFile { .. }),
+            "copy_file targets",
+            V0_2,
+        );
+        add(
+            matches!(
+                target,
+                Target::PythonExtension { .. } | Target::PythonSources { .. }
+            ),
+            "python targets",
+            V0_2,
+        );
+        add(
+            matches!(
+                target,
+                Target::RustExecutable { .. } | Target::RustLibrary { .. }
+            ),
+            "rust targets",
+            V0_2,
+        );
+        if let Some(options) = target.options() {
+            add(options.sign.is_some(), "sign", V0_2);
+            add(!options.post_build.is_empty(), "post_build", V0_2);
+            add(!options.embed_files.is_empty(), "embed_files", V0_2);
+            add(options.linker_script.is_some(), "linker_script", V0_2);
+            add(options.generate_map, "generate_map", V0_2);
+            add(options.size_report, "size_report", V0_2);
+            add(options.test, "test", V0_2);
+            add(options.bolt.is_some(), "bolt", V0_2);
+            add(options.objc_arc, "objc_arc", V0_2);
+            add(!options.frameworks.is_empty(), "frameworks", V0_2);
+            add(!options.cuda_arch.is_empty(), "cuda_arch", V0_2);
+            add(options.cuda_separable, "cuda_separable", V0_2);
+            add(options.c_std.is_some(), "c_std", V0_2);
+            add(!options.c_args.is_empty(), "c_args", V0_2);
+            add(!options.public_c_args.is_empty(), "public_c_args", V0_2);
+            add(options.cpp_std.is_some(), "cpp_std", V0_2);
+            add(!options.architectures.is_empty(), "architectures", V0_2);
+            add(options.install, "install", V0_2);
+            add(!options.launchers.is_empty(), "launchers", V0_2);
+            add(options.install_component.is_some(), "component", V0_2);
+        }
+    }
+    add(!manifest.install_data.is_empty(), "install_data", V0_2);
+    add(
+        !manifest.install_symlink.is_empty(),
+        "install_symlink",
+        V0_2,
+    );
+    add(
+        !manifest.install_emptydir.is_empty(),
+        "install_emptydir",
+        V0_2,
+    );
+    add(
+        manifest.package != Default::default(),
+        "[package] metadata",
+        V0_2,
+    );
+    add(
+        manifest.python != Default::default(),
+        "[python] settings",
+        V0_2,
+    );
+    features
+}
+
+/// Checks the manifest's `crust_version` against this binary, then warns
+/// about features newer than the oldest release the requirement admits.
+pub fn check(manifest: &ProjectManifest, path: &Path) -> Result<()> {
+    let Some(text) = &manifest.project.crust_version else {
+        return Ok(());
+    };
+    let req = VersionReq::parse(text).ok_or_else(|| CrustError::ManifestParse {
+        path: path.to_path_buf(),
+        message: format!("invalid crust_version '{text}', expected a requirement like \">=0.2\""),
+    })?;
+    let current = Version::current();
+    if !req.matches(current) {
+        return Err(CrustError::UnsupportedVersion {
+            required: text.clone(),
+            current: current.to_string(),
+        }
+        .into());
+    }
+    let minimum = req.minimum();
+    for (feature, since) in used_features(manifest) {
+        if since > minimum {
+            warn!(
+                "{} uses {feature}, added in crust {since}, but crust_version '{text}' allows \
+                 crust {minimum}; raise it to \">={since}\"",
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_matches_version_requirements() {
+        let v = |text| Version::parse(text).unwrap();
+        assert_eq!(v("0.4"), Version::new(0, 4, 0));
+        assert!(Version::parse("0.4.x").is_none());
+        assert!(Version::parse("1.2.3.4").is_none());
+
+        let req = VersionReq::parse(">=0.2, <1.0").unwrap();
+        assert!(req.matches(v("0.2.0")));
+        assert!(req.matches(v("0.9.9")));
+        assert!(!req.matches(v("1.0")));
+        assert!(!req.matches(v("0.1.5")));
+        assert_eq!(req.minimum(), v("0.2"));
+
+        assert!(VersionReq::parse("0.3").unwrap().matches(v("0.4")));
+        assert_eq!(VersionReq::parse(">0.1").unwrap().minimum(), v("0.1.1"));
+        assert!(VersionReq::parse(">=banana").is_none());
+        assert!(VersionReq::parse(&format!("={CRUST_VERSION}"))
+            .unwrap()
+            .matches(Version::current()));
+    }
+}
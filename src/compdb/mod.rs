@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of the clang JSON compilation database in the build directory.
+pub const COMPILE_DATABASE: &str = "compile_commands.json";
+
+/// One entry of a JSON compilation database, as read by clang tooling.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompileCommand {
+    /// Working directory the command runs in.
+    pub directory: PathBuf,
+    pub file: PathBuf,
+    pub arguments: Vec<String>,
+    pub output: PathBuf,
+}
+
+/// Whether `path` is a C-family translation unit (C, C++, Objective-C, or
+/// CUDA) that clang tools understand.
+pub fn is_c_family(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("c" | "cc" | "cpp" | "cxx" | "c++" | "C" | "m" | "mm" | "cu")
+    )
+}
+
+/// Writes `builddir/compile_commands.json` and returns its path.
+pub fn write(builddir: &Path, commands: &[CompileCommand]) -> Result<PathBuf> {
+    let path = builddir.join(COMPILE_DATABASE);
+    let content = serde_json::to_string_pretty(commands)?;
+    fs::write(&path, content + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn writes_clang_compatible_database() {
+        let dir = tempdir().unwrap();
+        let entry = CompileCommand {
+            directory: dir.path().to_path_buf(),
+            file: dir.path().join("main.c"),
+            arguments: vec!["cc".into(), "-c".into(), "main.c".into()],
+            output: dir.path().join("app_0.o"),
+        };
+        let path = write(dir.path(), std::slice::from_ref(&entry)).unwrap();
+
+        let parsed: Vec<CompileCommand> =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(parsed, vec![entry]);
+        assert!(is_c_family(Path::new("src/util.cpp")));
+        assert!(!is_c_family(Path::new("schema.json")));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::config::ProjectManifest;
+use crate::error::CrustError;
+use crate::formatter::manifest::format_array;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table};
+
+/// Target kinds `crust add-target` can create: the ones built from sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum NewTargetKind {
+    Executable,
+    StaticLibrary,
+    SharedLibrary,
+}
+
+impl NewTargetKind {
+    /// Value of the target's `type` key.
+    pub fn type_name(self) -> &'static str {
+        match self {
+            NewTargetKind::Executable => "executable",
+            NewTargetKind::StaticLibrary => "static_library",
+            NewTargetKind::SharedLibrary => "shared_library",
+        }
+    }
+}
+
+/// A manifest opened for editing. Changes go through `toml_edit`, so the
+/// comments, key order, and spacing of untouched parts are kept.
+pub struct ManifestEditor {
+    path: PathBuf,
+    doc: DocumentMut,
+}
+
+impl ManifestEditor {
+    pub fn open(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|source| CrustError::ManifestRead {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(path, &content)
+    }
+
+    fn parse(path: &Path, content: &str) -> Result<Self> {
+        let doc =
+            content
+                .parse()
+                .map_err(|err: toml_edit::TomlError| CrustError::ManifestParse {
+                    path: path.to_path_buf(),
+                    message: err.to_string(),
+                })?;
+        Ok(ManifestEditor {
+            path: path.to_path_buf(),
+            doc,
+        })
+    }
+
+    fn targets(&self) -> impl Iterator<Item = &Table> {
+        self.doc
+            .get("targets")
+            .and_then(Item::as_array_of_tables)
+            .into_iter()
+            .flat_map(ArrayOfTables::iter)
+    }
+
+    fn target_mut(&mut self, name: &str) -> Option<&mut Table> {
+        self.doc
+            .get_mut("targets")
+            .and_then(Item::as_array_of_tables_mut)?
+            .iter_mut()
+            .find(|table| table.get("name").and_then(Item::as_str) == Some(name))
+    }
+
+    /// Names of the declared targets, in manifest order.
+    pub fn target_names(&self) -> Vec<String> {
+        self.targets()
+            .filter_map(|table| table.get("name").and_then(Item::as_str))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Appends a `[[targets]]` entry after the existing ones.
+    pub fn add_target(
+        &mut self,
+        kind: NewTargetKind,
+        name: &str,
+        sources: &[String],
+    ) -> Result<()> {
+        if self.target_names().iter().any(|existing| existing == name) {
+            return Err(CrustError::DuplicateTarget { name: name.into() }.into());
+        }
+        let mut sources: Array = sources.iter().collect();
+        format_array(&mut sources, "sources = ".len(), 0);
+        let mut table = Table::new();
+        table.insert("type", toml_edit::value(kind.type_name()));
+        table.insert("name", toml_edit::value(name));
+        table.insert("sources", toml_edit::value(sources));
+        if !self.doc.is_empty() {
+            table.decor_mut().set_prefix("\n");
+        }
+        let targets = self
+            .doc
+            .entry("targets")
+            .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()));
+        let Some(targets) = targets.as_array_of_tables_mut() else {
+            return Err(self.invalid("'targets' is not an array of tables"));
+        };
+        targets.push(table);
+        Ok(())
+    }
+
+    /// Adds `dependency` to the `deps` of `target`. Returns false when it
+    /// is already listed.
+    pub fn add_dep(&mut self, target: &str, dependency: &str) -> Result<bool> {
+        if target == dependency {
+            return Err(CrustError::Cycle {
+                target: target.into(),
+            }
+            .into());
+        }
+        if !self.target_names().iter().any(|name| name == dependency) {
+            return Err(CrustError::UnknownDependency {
+                target: target.into(),
+                dependency: dependency.into(),
+            }
+            .into());
+        }
+        let path = self.path.clone();
+        let table = self
+            .target_mut(target)
+            .ok_or_else(|| CrustError::InvalidArgument {
+                message: format!("No target named '{target}' in {}", path.display()),
+            })?;
+        let deps = table
+            .entry("deps")
+            .or_insert_with(|| toml_edit::value(Array::new()));
+        let Some(deps) = deps.as_array_mut() else {
+            return Err(self.invalid(&format!("deps of '{target}' is not an array")));
+        };
+        if deps.iter().any(|dep| dep.as_str() == Some(dependency)) {
+            return Ok(false);
+        }
+        // Continue the layout already in use: a multi-line array gets the new
+        // element on its own line like the last one.
+        let prefix = deps
+            .iter()
+            .last()
+            .and_then(|last| last.decor().prefix())
+            .and_then(|prefix| prefix.as_str())
+            .filter(|prefix| prefix.contains('\n'))
+            .map(str::to_string);
+        deps.push(dependency);
+        if let (Some(prefix), Some(value)) = (prefix, deps.iter_mut().last()) {
+            value.decor_mut().set_prefix(prefix);
+        } else if deps.len() == 1 {
+            deps.fmt();
+        }
+        Ok(true)
+    }
+
+    fn invalid(&self, message: &str) -> anyhow::Error {
+        CrustError::ManifestParse {
+            path: self.path.clone(),
+            message: message.into(),
+        }
+        .into()
+    }
+
+    /// The edited manifest text.
+    pub fn contents(&self) -> String {
+        self.doc.to_string()
+    }
+
+    /// Checks that the edited manifest still loads, then writes it back.
+    pub fn save(&self) -> Result<()> {
+        let contents = self.contents();
+        toml::from_str::<ProjectManifest>(&contents).map_err(|err| CrustError::ManifestParse {
+            path: self.path.clone(),
+            message: err.to_string(),
+        })?;
+        fs::write(&self.path, contents)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_targets_and_deps_keeping_comments() {
+        let source = "# demo project\n[project]\nname = \"demo\"\n\n[[targets]]\n\
+                      type = \"static_library\" # core code\nname = \"core\"\n\
+                      sources = [\"core.c\"]\n";
+        let mut editor = ManifestEditor::parse(Path::new("crust.build"), source).unwrap();
+        editor
+            .add_target(NewTargetKind::Executable, "app", &["main.c".into()])
+            .unwrap();
+        assert!(editor.add_dep("app", "core").unwrap());
+        assert!(!editor.add_dep("app", "core").unwrap());
+        assert!(editor.add_dep("app", "missing").is_err());
+        assert!(editor
+            .add_target(NewTargetKind::Executable, "core", &[])
+            .is_err());
+
+        assert_eq!(
+            editor.contents(),
+            format!(
+                "{source}\n[[targets]]\ntype = \"executable\"\nname = \"app\"\n\
+                 sources = [\"main.c\"]\ndeps = [\"core\"]\n"
+            )
+        );
+    }
+}
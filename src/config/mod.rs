@@ -1,13 +1,263 @@
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use crate::compat::VersionReq;
+use crate::error::CrustError;
+use crate::settings::Defaults;
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use tracing::warn;
+
+pub mod edit;
 
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 pub struct ProjectInfo {
     pub name: String,
     #[serde(default)]
     pub version: Option<String>,
+    /// Requirement on the crust release, e.g. `">=0.2"`.
+    #[serde(default)]
+    pub crust_version: Option<String>,
+    /// Flags added to every C-family compile of the project.
+    #[serde(default)]
+    pub c_args: Vec<String>,
+    /// Directories, relative to the manifest, whose own `crust.build` adds
+    /// targets named `//<dir>:<name>`.
+    #[serde(default)]
+    pub subdirs: Vec<String>,
+    /// Variables build commands keep under `--hermetic`, besides `PATH`.
+    #[serde(default)]
+    pub passthrough_env: Vec<String>,
+    /// Programs prefixed to the native backend's commands of a kind, such
+    /// as `compile = "ccache"`.
+    #[serde(default)]
+    pub launchers: BTreeMap<Rule, String>,
+}
+
+/// Settings shared by compiled targets (executables and libraries).
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct CompileOptions {
+    /// Signing command run after linking; `${OUTPUT}` expands to the artifact path.
+    #[serde(default)]
+    pub sign: Option<String>,
+    /// Commands run in order after a successful link; `${OUTPUT}` expands to
+    /// the artifact path.
+    #[serde(default)]
+    pub post_build: Vec<String>,
+    /// Extra files (relative to the build directory) produced by `post_build`.
+    #[serde(default)]
+    pub post_build_outputs: Vec<String>,
+    /// Files converted into linkable C arrays (see `crate::embed`).
+    #[serde(default)]
+    pub embed_files: Vec<String>,
+    /// Linker script passed as `-T` (executables only).
+    #[serde(default)]
+    pub linker_script: Option<String>,
+    /// Write `<name>.map` next to the executable via `-Wl,-Map`.
+    #[serde(default)]
+    pub generate_map: bool,
+    /// Print a flash/RAM usage summary from `size` after linking.
+    #[serde(default)]
+    pub size_report: bool,
+    /// Run this executable as part of `crust test`; a nonzero exit fails the run.
+    #[serde(default)]
+    pub test: bool,
+    /// Build the target once per listed architecture, each into `<arch>/`.
+    #[serde(default)]
+    pub architectures: Vec<String>,
+    /// Combine the per-architecture outputs into one universal artifact with
+    /// `lipo` (macOS).
+    #[serde(default)]
+    pub universal: bool,
+    /// Copy the artifact into `bindir` (executables) or `libdir` (libraries)
+    /// during `crust install`.
+    #[serde(default)]
+    pub install: bool,
+    /// Install component of the artifact; defaults to `devel` for static
+    /// libraries and `runtime` otherwise.
+    #[serde(default, rename = "component", alias = "install_component")]
+    pub install_component: Option<Component>,
+    /// Optimize the linked executable with `llvm-bolt` (executables only).
+    #[serde(default)]
+    pub bolt: Option<BoltSettings>,
+    /// Compile Objective-C and Objective-C++ sources with automatic
+    /// reference counting (`-fobjc-arc`).
+    #[serde(default)]
+    pub objc_arc: bool,
+    /// Apple frameworks linked with `-framework`, such as `Cocoa`.
+    #[serde(default)]
+    pub frameworks: Vec<String>,
+    /// GPU architectures `.cu` sources are compiled for, such as `sm_80`.
+    #[serde(default)]
+    pub cuda_arch: Vec<String>,
+    /// Compile `.cu` sources as relocatable device code (`-rdc=true`) and
+    /// device-link them before the host link.
+    #[serde(default)]
+    pub cuda_separable: bool,
+    /// C standard of the target's C and Objective-C sources, such as `c11`.
+    #[serde(default)]
+    pub c_std: Option<String>,
+    /// C++ standard of the target's C++ and Objective-C++ sources, such as
+    /// `c++20`.
+    #[serde(default)]
+    pub cpp_std: Option<String>,
+    /// Flags added to the compiles of this target's C-family sources.
+    #[serde(default)]
+    pub c_args: Vec<String>,
+    /// Flags added to the compiles of this target and of every target
+    /// depending on it, directly or not, such as `-DUSE_ZLIB`.
+    #[serde(default)]
+    pub public_c_args: Vec<String>,
+    /// `[dependencies]` the target compiles and links against when they are
+    /// found; each found one also defines `HAVE_<NAME>`.
+    #[serde(default)]
+    pub external_deps: Vec<String>,
+    /// `[dependencies]` without which the target, and every target depending
+    /// on it, is left out of the build.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Launchers of this target's commands, overriding those of `[project]`
+    /// per kind; an empty one runs the command directly.
+    #[serde(default)]
+    pub launchers: BTreeMap<Rule, String>,
+}
+
+/// Kinds of commands the native backend runs, each with its own launcher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    /// Compiling a source into an object, or a Rust crate.
+    Compile,
+    /// Linking an executable or shared library, and CUDA device linking.
+    Link,
+    /// Archiving a static library.
+    Archive,
+    /// A custom_command target.
+    Custom,
+    /// Anything else: documentation, catalogs, man pages, `lipo`, BOLT,
+    /// post-build steps, and signing.
+    Tool,
+}
+
+impl Rule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Compile => "compile",
+            Rule::Link => "link",
+            Rule::Archive => "archive",
+            Rule::Custom => "custom",
+            Rule::Tool => "tool",
+        }
+    }
+}
+
+/// Post-link optimization of an executable with `llvm-bolt`. The linked
+/// binary is kept as `<output>.prebolt` and the optimized one replaces it.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct BoltSettings {
+    /// Profile relative to the manifest: `perf.data` recorded with
+    /// `perf record -e cycles:u -j any,u`, or a `.fdata` file.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Optimization options passed to `llvm-bolt`.
+    #[serde(default = "default_bolt_args")]
+    pub args: Vec<String>,
+}
+
+fn default_bolt_args() -> Vec<String> {
+    [
+        "-reorder-blocks=ext-tsp",
+        "-reorder-functions=hfsort",
+        "-split-functions",
+        "-split-all-cold",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Target fields crust still reads under an old name, with the name
+/// replacing each.
+pub const DEPRECATED_FIELDS: &[(&str, &str)] = &[("install_component", "component")];
+
+/// A deprecated target field or option a manifest uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeprecatedKey {
+    /// Where it is used, such as `target 'app'` or `profile 'release'`.
+    pub used_in: String,
+    pub key: &'static str,
+    pub replacement: &'static str,
+}
+
+impl fmt::Display for DeprecatedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} uses deprecated key '{}'; use '{}' instead",
+            self.used_in, self.key, self.replacement
+        )
+    }
+}
+
+/// The deprecated target fields and profile options used in the manifest
+/// `content`, whose targets are named relative to `dir`.
+fn deprecated_keys(content: &str, dir: &str) -> Vec<DeprecatedKey> {
+    let Ok(table) = toml::from_str::<toml::Table>(content) else {
+        return Vec::new();
+    };
+    let find =
+        |used_in: String, keys: &toml::Table, deprecated: &[(&'static str, &'static str)]| {
+            deprecated
+                .iter()
+                .filter(|(key, _)| keys.contains_key(*key))
+                .map(|&(key, replacement)| DeprecatedKey {
+                    used_in: used_in.clone(),
+                    key,
+                    replacement,
+                })
+                .collect::<Vec<_>>()
+        };
+    let mut found = Vec::new();
+    let targets = table.get("targets").and_then(toml::Value::as_array);
+    for target in targets
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_table)
+    {
+        let name = target
+            .get("name")
+            .and_then(toml::Value::as_str)
+            .unwrap_or("");
+        let name = if dir.is_empty() {
+            name.to_string()
+        } else {
+            format!("//{dir}:{name}")
+        };
+        found.extend(find(format!("target '{name}'"), target, DEPRECATED_FIELDS));
+    }
+    let profiles = table.get("profile").and_then(toml::Value::as_table);
+    for (name, profile) in profiles.into_iter().flatten() {
+        if let Some(options) = profile.get("options").and_then(toml::Value::as_table) {
+            found.extend(find(
+                format!("profile '{name}'"),
+                options,
+                crate::settings::DEPRECATED_OPTIONS,
+            ));
+        }
+    }
+    found
+}
+
+impl CompileOptions {
+    /// Whether any executable-only settings are present.
+    pub fn has_executable_only_settings(&self) -> bool {
+        self.linker_script.is_some()
+            || self.generate_map
+            || self.size_report
+            || self.test
+            || self.bolt.is_some()
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
@@ -19,6 +269,14 @@ pub enum Target {
         sources: Vec<String>,
         #[serde(default)]
         deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+        #[serde(flatten)]
+        options: CompileOptions,
     },
     #[serde(rename = "static_library")]
     StaticLibrary {
@@ -26,6 +284,14 @@ pub enum Target {
         sources: Vec<String>,
         #[serde(default)]
         deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+        #[serde(flatten)]
+        options: CompileOptions,
     },
     #[serde(rename = "shared_library")]
     SharedLibrary {
@@ -33,26 +299,344 @@ pub enum Target {
         sources: Vec<String>,
         #[serde(default)]
         deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+        #[serde(flatten)]
+        options: CompileOptions,
     },
     #[serde(rename = "custom_command")]
     CustomCommand {
         name: String,
-        command: String,
+        command: CommandLine,
         outputs: Vec<String>,
         #[serde(default)]
         deps: Vec<String>,
         #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+        #[serde(default)]
         inputs: Vec<String>,
+        /// Command starting a persistent worker that performs `command`
+        /// instead of the shell, kept alive across targets naming it.
+        #[serde(default)]
+        worker: Option<String>,
+        /// Programs the command runs, found before the build starts, each
+        /// optionally with a version requirement: `"python3 >=3.8"`.
+        #[serde(default)]
+        programs: Vec<String>,
+    },
+    /// Doxygen documentation generated from the listed directories; built
+    /// only when named, e.g. `crust build docs`.
+    #[serde(rename = "docs")]
+    Docs {
+        name: String,
+        input: Vec<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+        /// Extra Doxyfile keys, such as `EXTRACT_ALL = "YES"`.
+        #[serde(default)]
+        doxygen: BTreeMap<String, String>,
+    },
+    /// Translation catalogs compiled from `<po_dir>/<lang>.po` with msgfmt
+    /// and installed under `localedir`.
+    #[serde(rename = "gettext")]
+    Gettext {
+        name: String,
+        /// Text domain; defaults to the project name.
+        #[serde(default)]
+        package: Option<String>,
+        #[serde(default = "default_po_dir")]
+        po_dir: String,
+        languages: Vec<String>,
+        /// Files scanned by `crust i18n pot`; defaults to all C/C++ sources.
+        #[serde(default)]
+        sources: Vec<String>,
+        #[serde(default)]
+        xgettext_args: Vec<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// Manual pages converted to roff from `<page>.<section>.<markup>`
+    /// sources and installed under `mandir`.
+    #[serde(rename = "man")]
+    Man {
+        name: String,
+        sources: Vec<String>,
+        /// `scdoc`, `asciidoctor`, `pandoc`, or `go-md2man`; detected from
+        /// each source's extension when omitted.
+        #[serde(default)]
+        tool: Option<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// Writes the output of `git describe` into a generated file, so targets
+    /// depending on it can embed the version.
+    #[serde(rename = "vcs_version")]
+    VcsVersion {
+        name: String,
+        /// Template whose `@VCS_TAG@` is replaced; without it a header
+        /// defining `VCS_TAG` is written.
+        #[serde(default)]
+        input: Option<String>,
+        /// Generated file name; defaults to `<name>.h`.
+        #[serde(default)]
+        output: Option<String>,
+        /// Version used outside a git checkout; defaults to the project version.
+        #[serde(default)]
+        fallback: Option<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// Downloads a file at build time, checks it against `sha256`, and
+    /// caches it in the shared download directory.
+    #[serde(rename = "fetch")]
+    Fetch {
+        name: String,
+        url: String,
+        sha256: String,
+        /// Downloaded file name; defaults to the last segment of the URL.
+        #[serde(default)]
+        output: Option<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// Unpacks a tar, gzipped tar, or zip archive into a directory.
+    #[serde(rename = "extract")]
+    Extract {
+        name: String,
+        /// Archive relative to the manifest; defaults to the first output of
+        /// the first dependency, such as a fetch target.
+        #[serde(default)]
+        archive: Option<String>,
+        #[serde(default)]
+        strip_components: usize,
+        /// Directory name in the build directory; defaults to `<name>`.
+        #[serde(default)]
+        output: Option<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// Copies a file into the build directory, optionally replacing
+    /// `@NAME@` placeholders.
+    #[serde(rename = "copy_file")]
+    CopyFile {
+        name: String,
+        input: String,
+        /// Path in the build directory; defaults to the input's file name.
+        #[serde(default)]
+        output: Option<String>,
+        #[serde(default)]
+        variables: BTreeMap<String, String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// A C extension module for the `[python]` interpreter, linked against
+    /// its dependencies and installed into the site-packages directory when
+    /// `install` is set.
+    #[serde(rename = "python_extension")]
+    PythonExtension {
+        name: String,
+        sources: Vec<String>,
+        /// Package directory below site-packages, such as `mypkg`.
+        #[serde(default)]
+        subdir: Option<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+        #[serde(flatten)]
+        options: CompileOptions,
+    },
+    /// Pure-Python modules copied into the build directory and installed
+    /// into the site-packages directory.
+    #[serde(rename = "python_sources")]
+    PythonSources {
+        name: String,
+        sources: Vec<String>,
+        /// Package directory below site-packages, such as `mypkg`.
+        #[serde(default)]
+        subdir: Option<String>,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// A Rust program compiled with rustc from the crate root, the first
+    /// source; the other sources are the modules it includes.
+    #[serde(rename = "rust_executable")]
+    RustExecutable {
+        name: String,
+        sources: Vec<String>,
+        #[serde(default = "default_edition")]
+        edition: String,
+        /// rust_library targets become `--extern` crates; other libraries
+        /// are linked in.
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// A Rust crate compiled with rustc, used by rust_executable targets
+    /// (`rlib`) or linked into C targets (`staticlib`, `cdylib`).
+    #[serde(rename = "rust_library")]
+    RustLibrary {
+        name: String,
+        sources: Vec<String>,
+        #[serde(default = "default_edition")]
+        edition: String,
+        #[serde(default)]
+        crate_type: CrateType,
+        #[serde(default)]
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+    /// A name for the targets in `deps`, which building it builds; it has
+    /// no outputs of its own.
+    #[serde(rename = "alias")]
+    Alias {
+        name: String,
+        deps: Vec<String>,
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        owners: Vec<String>,
+        #[serde(default)]
+        deprecated: Option<String>,
     },
 }
 
+fn default_edition() -> String {
+    "2021".into()
+}
+
+fn default_po_dir() -> String {
+    "po".into()
+}
+
+/// Label of the target `name` defined in the `crust.build` of `dir`, such
+/// as `//libfoo:util`.
+pub fn label(dir: &str, name: &str) -> String {
+    format!("//{dir}:{name}")
+}
+
+/// Directory and bare name of a target: `(Some("libfoo"), "util")` for
+/// `//libfoo:util`, `(None, "app")` for a target of the top manifest.
+pub fn split_label(name: &str) -> (Option<&str>, &str) {
+    match name
+        .strip_prefix("//")
+        .and_then(|rest| rest.rsplit_once(':'))
+    {
+        Some(("", name)) => (None, name),
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, name),
+    }
+}
+
+/// Path, relative to the build directory, that the files named after a
+/// target start with: `libfoo/util` for `//libfoo:util`.
+pub fn label_path(name: &str) -> String {
+    match split_label(name) {
+        (Some(dir), name) => format!("{dir}/{name}"),
+        (None, name) => name.to_string(),
+    }
+}
+
+/// Target named by the dependency `dep` of a target in `dir`: bare names
+/// are targets of the same manifest, `//dir:name` any target, and `//:name`
+/// a target of the top manifest.
+fn resolve_label(dir: &str, dep: &str) -> String {
+    match split_label(dep) {
+        (None, name) if dep.starts_with("//") => name.to_string(),
+        (None, name) => label(dir, name),
+        (Some(_), _) => dep.to_string(),
+    }
+}
+
 impl Target {
     pub fn name(&self) -> &str {
         match self {
             Target::Executable { name, .. }
             | Target::StaticLibrary { name, .. }
             | Target::SharedLibrary { name, .. }
-            | Target::CustomCommand { name, .. } => name,
+            | Target::CustomCommand { name, .. }
+            | Target::Docs { name, .. }
+            | Target::Gettext { name, .. }
+            | Target::Man { name, .. }
+            | Target::VcsVersion { name, .. }
+            | Target::Fetch { name, .. }
+            | Target::Extract { name, .. }
+            | Target::CopyFile { name, .. }
+            | Target::PythonExtension { name, .. }
+            | Target::PythonSources { name, .. }
+            | Target::RustExecutable { name, .. }
+            | Target::RustLibrary { name, .. }
+            | Target::Alias { name, .. } => name,
         }
     }
 
@@ -61,7 +645,108 @@ impl Target {
             Target::Executable { deps, .. }
             | Target::StaticLibrary { deps, .. }
             | Target::SharedLibrary { deps, .. }
-            | Target::CustomCommand { deps, .. } => deps,
+            | Target::CustomCommand { deps, .. }
+            | Target::Docs { deps, .. }
+            | Target::Gettext { deps, .. }
+            | Target::Man { deps, .. }
+            | Target::VcsVersion { deps, .. }
+            | Target::Fetch { deps, .. }
+            | Target::Extract { deps, .. }
+            | Target::CopyFile { deps, .. }
+            | Target::PythonExtension { deps, .. }
+            | Target::PythonSources { deps, .. }
+            | Target::RustExecutable { deps, .. }
+            | Target::RustLibrary { deps, .. }
+            | Target::Alias { deps, .. } => deps,
+        }
+    }
+
+    pub fn dependencies_mut(&mut self) -> &mut Vec<String> {
+        match self {
+            Target::Executable { deps, .. }
+            | Target::StaticLibrary { deps, .. }
+            | Target::SharedLibrary { deps, .. }
+            | Target::CustomCommand { deps, .. }
+            | Target::Docs { deps, .. }
+            | Target::Gettext { deps, .. }
+            | Target::Man { deps, .. }
+            | Target::VcsVersion { deps, .. }
+            | Target::Fetch { deps, .. }
+            | Target::Extract { deps, .. }
+            | Target::CopyFile { deps, .. }
+            | Target::PythonExtension { deps, .. }
+            | Target::PythonSources { deps, .. }
+            | Target::RustExecutable { deps, .. }
+            | Target::RustLibrary { deps, .. }
+            | Target::Alias { deps, .. } => deps,
+        }
+    }
+
+    /// Free-form tags such as `experimental` or `team:infra`, used to select
+    /// targets with `crust build --label` and `crust query`.
+    pub fn labels(&self) -> &[String] {
+        match self {
+            Target::Executable { labels, .. }
+            | Target::StaticLibrary { labels, .. }
+            | Target::SharedLibrary { labels, .. }
+            | Target::CustomCommand { labels, .. }
+            | Target::Docs { labels, .. }
+            | Target::Gettext { labels, .. }
+            | Target::Man { labels, .. }
+            | Target::VcsVersion { labels, .. }
+            | Target::Fetch { labels, .. }
+            | Target::Extract { labels, .. }
+            | Target::CopyFile { labels, .. }
+            | Target::PythonExtension { labels, .. }
+            | Target::PythonSources { labels, .. }
+            | Target::RustExecutable { labels, .. }
+            | Target::RustLibrary { labels, .. }
+            | Target::Alias { labels, .. } => labels,
+        }
+    }
+
+    /// People or teams responsible for the target, such as `@infra`.
+    pub fn owners(&self) -> &[String] {
+        match self {
+            Target::Executable { owners, .. }
+            | Target::StaticLibrary { owners, .. }
+            | Target::SharedLibrary { owners, .. }
+            | Target::CustomCommand { owners, .. }
+            | Target::Docs { owners, .. }
+            | Target::Gettext { owners, .. }
+            | Target::Man { owners, .. }
+            | Target::VcsVersion { owners, .. }
+            | Target::Fetch { owners, .. }
+            | Target::Extract { owners, .. }
+            | Target::CopyFile { owners, .. }
+            | Target::PythonExtension { owners, .. }
+            | Target::PythonSources { owners, .. }
+            | Target::RustExecutable { owners, .. }
+            | Target::RustLibrary { owners, .. }
+            | Target::Alias { owners, .. } => owners,
+        }
+    }
+
+    /// Why the target should no longer be used, such as `"use zlib-ng"`,
+    /// when it is deprecated.
+    pub fn deprecated(&self) -> Option<&str> {
+        match self {
+            Target::Executable { deprecated, .. }
+            | Target::StaticLibrary { deprecated, .. }
+            | Target::SharedLibrary { deprecated, .. }
+            | Target::CustomCommand { deprecated, .. }
+            | Target::Docs { deprecated, .. }
+            | Target::Gettext { deprecated, .. }
+            | Target::Man { deprecated, .. }
+            | Target::VcsVersion { deprecated, .. }
+            | Target::Fetch { deprecated, .. }
+            | Target::Extract { deprecated, .. }
+            | Target::CopyFile { deprecated, .. }
+            | Target::PythonExtension { deprecated, .. }
+            | Target::PythonSources { deprecated, .. }
+            | Target::RustExecutable { deprecated, .. }
+            | Target::RustLibrary { deprecated, .. }
+            | Target::Alias { deprecated, .. } => deprecated.as_deref(),
         }
     }
 
@@ -69,25 +754,778 @@ impl Target {
         match self {
             Target::Executable { sources, .. }
             | Target::StaticLibrary { sources, .. }
-            | Target::SharedLibrary { sources, .. } => sources,
+            | Target::SharedLibrary { sources, .. }
+            | Target::Gettext { sources, .. }
+            | Target::Man { sources, .. }
+            | Target::PythonExtension { sources, .. }
+            | Target::PythonSources { sources, .. }
+            | Target::RustExecutable { sources, .. }
+            | Target::RustLibrary { sources, .. } => sources,
+            Target::VcsVersion { input, .. } | Target::Extract { archive: input, .. } => {
+                input.as_slice()
+            }
             Target::CustomCommand { inputs, .. } => inputs,
+            Target::Docs { input, .. } => input,
+            Target::CopyFile { input, .. } => std::slice::from_ref(input),
+            Target::Fetch { .. } | Target::Alias { .. } => &[],
+        }
+    }
+
+    /// Cleans the spelling of each listed source and drops those naming a
+    /// file listed before, through a symlink or `..`, which would otherwise
+    /// be compiled twice. Returns the dropped spellings.
+    fn dedup_sources(&mut self, root: &Path) -> Vec<String> {
+        let sources = match self {
+            Target::Executable { sources, .. }
+            | Target::StaticLibrary { sources, .. }
+            | Target::SharedLibrary { sources, .. }
+            | Target::Gettext { sources, .. }
+            | Target::Man { sources, .. }
+            | Target::PythonExtension { sources, .. }
+            | Target::PythonSources { sources, .. }
+            | Target::RustExecutable { sources, .. }
+            | Target::RustLibrary { sources, .. }
+            | Target::CustomCommand {
+                inputs: sources, ..
+            }
+            | Target::Docs { input: sources, .. } => sources,
+            _ => return Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        let mut dropped = Vec::new();
+        sources.retain_mut(|source| {
+            *source = crate::paths::clean(source);
+            if seen.insert(crate::paths::identity(root, &*source)) {
+                true
+            } else {
+                dropped.push(source.clone());
+                false
+            }
+        });
+        dropped
+    }
+
+    /// Moves a target read from the `crust.build` of `dir` into the project:
+    /// it is renamed to its label, its dependencies are resolved with
+    /// [`resolve_label`], and its source paths are made relative to the top
+    /// manifest.
+    fn rebase(&mut self, dir: &str) {
+        let in_dir = |path: &mut String| *path = format!("{dir}/{path}");
+        match self {
+            Target::Executable {
+                name,
+                sources,
+                deps,
+                options,
+                ..
+            }
+            | Target::StaticLibrary {
+                name,
+                sources,
+                deps,
+                options,
+                ..
+            }
+            | Target::SharedLibrary {
+                name,
+                sources,
+                deps,
+                options,
+                ..
+            }
+            | Target::PythonExtension {
+                name,
+                sources,
+                deps,
+                options,
+                ..
+            } => {
+                sources.iter_mut().for_each(in_dir);
+                options.embed_files.iter_mut().for_each(in_dir);
+                options.linker_script.iter_mut().for_each(in_dir);
+                if let Some(bolt) = &mut options.bolt {
+                    bolt.profile.iter_mut().for_each(in_dir);
+                }
+                *name = label(dir, name);
+                deps.iter_mut()
+                    .for_each(|dep| *dep = resolve_label(dir, dep));
+            }
+            Target::Gettext {
+                name,
+                sources,
+                po_dir,
+                deps,
+                ..
+            } => {
+                sources.iter_mut().for_each(in_dir);
+                in_dir(po_dir);
+                *name = label(dir, name);
+                deps.iter_mut()
+                    .for_each(|dep| *dep = resolve_label(dir, dep));
+            }
+            Target::Man {
+                name,
+                sources,
+                deps,
+                ..
+            }
+            | Target::PythonSources {
+                name,
+                sources,
+                deps,
+                ..
+            }
+            | Target::RustExecutable {
+                name,
+                sources,
+                deps,
+                ..
+            }
+            | Target::RustLibrary {
+                name,
+                sources,
+                deps,
+                ..
+            }
+            | Target::CustomCommand {
+                name,
+                inputs: sources,
+                deps,
+                ..
+            }
+            | Target::Docs {
+                name,
+                input: sources,
+                deps,
+                ..
+            } => {
+                sources.iter_mut().for_each(in_dir);
+                *name = label(dir, name);
+                deps.iter_mut()
+                    .for_each(|dep| *dep = resolve_label(dir, dep));
+            }
+            Target::VcsVersion {
+                name, input, deps, ..
+            }
+            | Target::Extract {
+                name,
+                archive: input,
+                deps,
+                ..
+            } => {
+                input.iter_mut().for_each(in_dir);
+                *name = label(dir, name);
+                deps.iter_mut()
+                    .for_each(|dep| *dep = resolve_label(dir, dep));
+            }
+            Target::CopyFile {
+                name, input, deps, ..
+            } => {
+                in_dir(input);
+                *name = label(dir, name);
+                deps.iter_mut()
+                    .for_each(|dep| *dep = resolve_label(dir, dep));
+            }
+            Target::Fetch { name, deps, .. } | Target::Alias { name, deps, .. } => {
+                *name = label(dir, name);
+                deps.iter_mut()
+                    .for_each(|dep| *dep = resolve_label(dir, dep));
+            }
+        }
+    }
+
+    /// Compile and link settings, or `None` for targets that compile nothing.
+    pub fn options(&self) -> Option<&CompileOptions> {
+        match self {
+            Target::Executable { options, .. }
+            | Target::StaticLibrary { options, .. }
+            | Target::SharedLibrary { options, .. }
+            | Target::PythonExtension { options, .. } => Some(options),
+            Target::CustomCommand { .. }
+            | Target::Docs { .. }
+            | Target::Gettext { .. }
+            | Target::Man { .. }
+            | Target::VcsVersion { .. }
+            | Target::Fetch { .. }
+            | Target::Extract { .. }
+            | Target::CopyFile { .. }
+            | Target::PythonSources { .. }
+            | Target::RustExecutable { .. }
+            | Target::RustLibrary { .. }
+            | Target::Alias { .. } => None,
+        }
+    }
+}
+
+/// The command of a custom_command target: a string run by the platform's
+/// shell, or an argument list run directly without one.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CommandLine {
+    /// Run with `sh -c`, or `cmd /C` on Windows.
+    Shell(String),
+    /// Program and arguments; `@INPUT@`, `@OUTPUT@`, and friends are
+    /// expanded before it runs.
+    Argv(Vec<String>),
+}
+
+impl CommandLine {
+    /// Expands the placeholders of the list form; a shell string is returned
+    /// unchanged. An argument that is exactly `@INPUT@` or `@OUTPUT@` becomes
+    /// one argument per input or output, while inside a longer argument they
+    /// are replaced by the paths joined with spaces. `@INPUT0@`, `@OUTPUT0@`,
+    /// and so on name a single path, and `@BUILDDIR@` the build directory.
+    pub fn expand(&self, inputs: &[String], outputs: &[String], builddir: &str) -> CommandLine {
+        let CommandLine::Argv(args) = self else {
+            return self.clone();
+        };
+        let mut expanded = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "@INPUT@" => expanded.extend(inputs.iter().cloned()),
+                "@OUTPUT@" => expanded.extend(outputs.iter().cloned()),
+                _ => {
+                    let mut arg = arg
+                        .replace("@INPUT@", &inputs.join(" "))
+                        .replace("@OUTPUT@", &outputs.join(" "))
+                        .replace("@BUILDDIR@", builddir);
+                    for (idx, input) in inputs.iter().enumerate() {
+                        arg = arg.replace(&format!("@INPUT{idx}@"), input);
+                    }
+                    for (idx, output) in outputs.iter().enumerate() {
+                        arg = arg.replace(&format!("@OUTPUT{idx}@"), output);
+                    }
+                    expanded.push(arg);
+                }
+            }
+        }
+        CommandLine::Argv(expanded)
+    }
+}
+
+impl From<&str> for CommandLine {
+    fn from(command: &str) -> Self {
+        CommandLine::Shell(command.to_string())
+    }
+}
+
+impl From<String> for CommandLine {
+    fn from(command: String) -> Self {
+        CommandLine::Shell(command)
+    }
+}
+
+/// Shows the command as a POSIX shell line, quoting arguments of the list
+/// form that need it, for generated build files and logs.
+impl std::fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandLine::Shell(command) => f.write_str(command),
+            CommandLine::Argv(args) => {
+                let quoted: Vec<String> = args
+                    .iter()
+                    .map(|arg| {
+                        let plain = !arg.is_empty()
+                            && arg
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || "@%+,-./:=_".contains(c));
+                        if plain {
+                            arg.clone()
+                        } else {
+                            format!("'{}'", arg.replace('\'', r"'\''"))
+                        }
+                    })
+                    .collect();
+                f.write_str(&quoted.join(" "))
+            }
         }
     }
 }
 
+/// The interpreter python_extension and python_sources targets are built
+/// for, and that `@PYTHON@` in custom commands names.
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct PythonInfo {
+    /// Program name or path; defaults to `python3`.
+    #[serde(default = "default_python")]
+    pub interpreter: String,
+    /// Requirement on the interpreter's version, e.g. `">=3.8"`.
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl Default for PythonInfo {
+    fn default() -> Self {
+        PythonInfo {
+            interpreter: default_python(),
+            version: None,
+        }
+    }
+}
+
+fn default_python() -> String {
+    "python3".into()
+}
+
+/// Optimization and debug info of a build.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildType {
+    /// No flags from crust.
+    Plain,
+    Debug,
+    DebugOptimized,
+    Release,
+    MinSize,
+}
+
+impl BuildType {
+    pub fn flags(self) -> &'static [&'static str] {
+        match self {
+            BuildType::Plain => &[],
+            BuildType::Debug => &["-O0", "-g"],
+            BuildType::DebugOptimized => &["-O2", "-g"],
+            BuildType::Release => &["-O3"],
+            BuildType::MinSize => &["-Os"],
+        }
+    }
+}
+
+/// A `[profile.<name>]` table: flags and option defaults applied together
+/// by `--profile <name>`, such as an ASan or an LTO build.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub buildtype: Option<BuildType>,
+    /// Passed as `-fsanitize=` when compiling and linking, e.g. `address`.
+    #[serde(default)]
+    pub sanitizers: Vec<String>,
+    /// Flags added to every C-family compile.
+    #[serde(default)]
+    pub c_args: Vec<String>,
+    /// Flags added to every executable and shared library link.
+    #[serde(default)]
+    pub c_link_args: Vec<String>,
+    /// Defaults for command-line options, as in a config file.
+    #[serde(default)]
+    pub options: Defaults,
+}
+
+impl Profile {
+    /// Flags the profile adds to every C-family compile.
+    pub fn compile_args(&self) -> Vec<String> {
+        let buildtype = self.buildtype.map_or(&[][..], BuildType::flags);
+        buildtype
+            .iter()
+            .map(|flag| flag.to_string())
+            .chain(self.sanitize_flag())
+            .chain(self.c_args.iter().cloned())
+            .collect()
+    }
+
+    /// Flags the profile adds to every executable and shared library link.
+    pub fn link_args(&self) -> Vec<String> {
+        self.sanitize_flag()
+            .into_iter()
+            .chain(self.c_link_args.iter().cloned())
+            .collect()
+    }
+
+    fn sanitize_flag(&self) -> Option<String> {
+        (!self.sanitizers.is_empty()).then(|| format!("-fsanitize={}", self.sanitizers.join(",")))
+    }
+}
+
+impl ProjectManifest {
+    /// The profile called `name`.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profile.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+            let known = if known.is_empty() {
+                "the manifest defines none".to_string()
+            } else {
+                format!("expected one of: {}", known.join(", "))
+            };
+            CrustError::InvalidArgument {
+                message: format!("unknown profile '{name}', {known}"),
+            }
+            .into()
+        })
+    }
+}
+
+/// A `[dependencies.<name>]` table: a library found outside the project,
+/// and the project target that can stand in for it.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DependencySpec {
+    #[serde(default)]
+    pub method: Method,
+    /// pkg-config module, conan reference (`zlib/1.3.1`), or vcpkg port;
+    /// defaults to the dependency's name.
+    #[serde(default)]
+    pub module: Option<String>,
+    /// Versions of the system library that are accepted, such as `">=1.2.11"`.
+    #[serde(default, deserialize_with = "version_req")]
+    pub version: Option<VersionReq>,
+    #[serde(default)]
+    pub feature: Feature,
+    /// Target of the project built and linked instead when the library is
+    /// not found.
+    #[serde(default)]
+    pub fallback: Option<String>,
+    /// Headers that must exist, such as `zlib.h`, for `method = "find"`.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// Libraries linked as `-l<name>`, for `method = "find"`.
+    #[serde(default)]
+    pub libraries: Vec<String>,
+    /// Directories, relative to the manifest, searched for `headers` before
+    /// the standard ones.
+    #[serde(default)]
+    pub include_dirs: Vec<String>,
+    /// Directories, relative to the manifest, searched for `libraries`
+    /// before the standard ones.
+    #[serde(default)]
+    pub lib_dirs: Vec<String>,
+}
+
+/// How a dependency is found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Method {
+    /// Asks `pkg-config` for the installed library.
+    #[default]
+    PkgConfig,
+    /// Installs the package with `conan install` into the build directory.
+    Conan,
+    /// Reads what `vcpkg install` put under `VCPKG_ROOT`.
+    Vcpkg,
+    /// Searches directories for the `headers` and `libraries`.
+    Find,
+}
+
+impl Method {
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::PkgConfig => "pkg-config",
+            Method::Conan => "conan",
+            Method::Vcpkg => "vcpkg",
+            Method::Find => "find",
+        }
+    }
+}
+
+/// Whether a dependency is used, as set in the manifest or with `--feature`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    /// Always used; the build fails when it is not found.
+    #[default]
+    Enabled,
+    /// Never looked up; targets requiring it are left out.
+    Disabled,
+    /// Used when it is found.
+    Auto,
+}
+
+/// Metadata written into packages built by `crust package`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct PackageInfo {
+    /// One-line summary; defaults to the project name.
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// `Name <email>`; required for deb packages.
+    #[serde(default)]
+    pub maintainer: Option<String>,
+    /// License expression; required for rpm packages.
+    #[serde(default)]
+    pub license: Option<String>,
+    #[serde(default)]
+    pub homepage: Option<String>,
+    /// Runtime dependencies, written as given to `Depends:` / `Requires:`.
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Package architecture; defaults to the build machine's.
+    #[serde(default)]
+    pub architecture: Option<String>,
+    /// RPM release number.
+    #[serde(default)]
+    pub release: Option<String>,
+    /// Release tarball URL for brew formulas; `${VERSION}` expands to the
+    /// project version.
+    #[serde(default)]
+    pub source_url: Option<String>,
+}
+
+/// Part of an installation that `crust install --component` can select, so
+/// packagers can split a project into runtime, development, and
+/// documentation packages.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Component {
+    #[default]
+    Runtime,
+    Devel,
+    Doc,
+}
+
+/// Kind of crate rustc produces, as passed to `--crate-type`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrateType {
+    /// An executable; only rust_executable targets build one.
+    Bin,
+    /// A Rust library other Rust targets use as an `--extern` crate.
+    #[default]
+    Rlib,
+    /// A static library with a C ABI, for C targets to link.
+    Staticlib,
+    /// A shared library with a C ABI, for C targets to link.
+    Cdylib,
+}
+
+impl CrateType {
+    pub fn name(self) -> &'static str {
+        match self {
+            CrateType::Bin => "bin",
+            CrateType::Rlib => "rlib",
+            CrateType::Staticlib => "staticlib",
+            CrateType::Cdylib => "cdylib",
+        }
+    }
+}
+
+/// Files from the source tree copied below `datadir` by `crust install`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct InstallData {
+    pub sources: Vec<String>,
+    /// Directory relative to `datadir`, or an absolute path; defaults to the
+    /// project name.
+    #[serde(default)]
+    pub install_dir: Option<String>,
+    /// Permission bits as an octal string such as `"0644"`.
+    #[serde(default, deserialize_with = "octal_mode")]
+    pub install_mode: Option<u32>,
+    /// Installed file names, one per source.
+    #[serde(default)]
+    pub rename: Vec<String>,
+    #[serde(default)]
+    pub component: Component,
+}
+
+/// A symbolic link created by `crust install`, e.g. `sbin/foo -> ../bin/foo`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct InstallSymlink {
+    /// Link path relative to the prefix, or an absolute path.
+    pub name: String,
+    /// Contents of the link, usually relative to the link's directory.
+    pub pointing_to: String,
+    #[serde(default)]
+    pub component: Component,
+}
+
+/// Directories created empty by `crust install`, such as state directories.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct InstallEmptyDir {
+    /// Paths relative to the prefix, or absolute paths.
+    pub dirs: Vec<String>,
+    #[serde(default, deserialize_with = "octal_mode")]
+    pub install_mode: Option<u32>,
+    #[serde(default)]
+    pub component: Component,
+}
+
+fn octal_mode<'de, D>(deserializer: D) -> std::result::Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mode = String::deserialize(deserializer)?;
+    u32::from_str_radix(&mode, 8)
+        .ok()
+        .filter(|bits| *bits <= 0o7777)
+        .map(Some)
+        .ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid install_mode '{mode}', expected octal like \"0644\""
+            ))
+        })
+}
+
+fn version_req<'de, D>(deserializer: D) -> std::result::Result<Option<VersionReq>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    VersionReq::parse(&text).map(Some).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "invalid version requirement '{text}', expected one like \">=1.2, <2\""
+        ))
+    })
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct ProjectManifest {
     pub project: ProjectInfo,
     #[serde(default)]
     pub targets: Vec<Target>,
+    #[serde(default)]
+    pub install_data: Vec<InstallData>,
+    #[serde(default)]
+    pub install_symlink: Vec<InstallSymlink>,
+    #[serde(default)]
+    pub install_emptydir: Vec<InstallEmptyDir>,
+    #[serde(default)]
+    pub package: PackageInfo,
+    #[serde(default)]
+    pub python: PythonInfo,
+    /// Named bundles of flags and options, selected with `--profile`.
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+    /// Libraries found outside the project, by name.
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, DependencySpec>,
+    /// Deprecated keys the manifest and those of its subdirs use.
+    #[serde(skip)]
+    pub deprecated_keys: Vec<DeprecatedKey>,
+}
+
+/// The `crust.build` of a directory listed in `subdirs`: more targets, and
+/// directories below it with their own manifests.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubdirManifest {
+    #[serde(default)]
+    subdirs: Vec<String>,
+    #[serde(default)]
+    targets: Vec<Target>,
 }
 
 impl ProjectManifest {
+    /// Reads the manifest at `path` together with the manifests of its
+    /// `subdirs`.
     pub fn load(path: &Path) -> Result<Self> {
-        let content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read manifest at {}", path.display()))?;
-        let manifest: ProjectManifest = toml::from_str(&content)
-            .with_context(|| format!("Invalid manifest TOML at {}", path.display()))?;
+        let content = fs::read_to_string(path).map_err(|source| CrustError::ManifestRead {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut manifest = Self::parse(&content, path)?;
+        let subdirs = manifest.project.subdirs.clone();
+        let root = Self::manifest_dir(path);
+        manifest.load_subdirs(&root, "", &subdirs, path)?;
+        for target in &mut manifest.targets {
+            for source in target.dedup_sources(&root) {
+                warn!(
+                    "Target '{}' lists {source} twice, under different paths; it is built once",
+                    target.name()
+                );
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Adds the targets of the `crust.build` in each of `subdirs`, below
+    /// `parent` (relative to the project `root`), and of their own subdirs.
+    fn load_subdirs(
+        &mut self,
+        root: &Path,
+        parent: &str,
+        subdirs: &[String],
+        listed_in: &Path,
+    ) -> Result<()> {
+        for subdir in subdirs {
+            let subdir = subdir.trim_end_matches('/');
+            let inside = Path::new(subdir)
+                .components()
+                .all(|part| matches!(part, std::path::Component::Normal(_)));
+            if subdir.is_empty() || !inside || subdir.contains(':') {
+                return Err(CrustError::ManifestParse {
+                    path: listed_in.to_path_buf(),
+                    message: format!(
+                        "subdir `{subdir}` must be a relative path below the manifest"
+                    ),
+                }
+                .into());
+            }
+            let dir = if parent.is_empty() {
+                subdir.to_string()
+            } else {
+                format!("{parent}/{subdir}")
+            };
+            let path = root.join(&dir).join("crust.build");
+            let content = fs::read_to_string(&path).map_err(|source| CrustError::ManifestRead {
+                path: path.clone(),
+                source,
+            })?;
+            let nested: SubdirManifest =
+                toml::from_str(&content).map_err(|err| CrustError::ManifestParse {
+                    path: path.clone(),
+                    message: err.to_string(),
+                })?;
+            self.deprecated_keys.extend(deprecated_keys(&content, &dir));
+            for mut target in nested.targets {
+                target.rebase(&dir);
+                self.targets.push(target);
+            }
+            self.load_subdirs(root, &dir, &nested.subdirs, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Parses and validates manifest text read from `path`.
+    pub fn parse(content: &str, path: &Path) -> Result<Self> {
+        let mut manifest: ProjectManifest =
+            toml::from_str(content).map_err(|err| CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            })?;
+        manifest.deprecated_keys = deprecated_keys(content, "");
+        crate::compat::check(&manifest, path)?;
+        if let Some(data) = manifest
+            .install_data
+            .iter()
+            .find(|data| !data.rename.is_empty() && data.rename.len() != data.sources.len())
+        {
+            return Err(CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: format!(
+                    "install_data renames {} file(s) but lists {} source(s)",
+                    data.rename.len(),
+                    data.sources.len()
+                ),
+            }
+            .into());
+        }
+        if let Some(link) = manifest
+            .install_symlink
+            .iter()
+            .find(|link| link.name.is_empty() || link.pointing_to.is_empty())
+        {
+            return Err(CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: format!(
+                    "install_symlink '{}' needs both a name and pointing_to",
+                    link.name
+                ),
+            }
+            .into());
+        }
         Ok(manifest)
     }
 
@@ -104,6 +1542,25 @@ mod tests {
     use super::*;
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn expands_placeholders_of_argument_lists() {
+        let command: CommandLine = toml::from_str::<BTreeMap<String, CommandLine>>(
+            r#"command = ["python3", "gen.py", "@INPUT@", "-o", "@OUTPUT0@", "--tmp=@BUILDDIR@/t"]"#,
+        )
+        .unwrap()
+        .remove("command")
+        .unwrap();
+        let inputs = ["a.idl".to_string(), "b.idl".to_string()];
+        let outputs = ["/out/gen.c".to_string(), "/out/gen.h".to_string()];
+        let expanded = command.expand(&inputs, &outputs, "/out");
+        assert_eq!(
+            expanded.to_string(),
+            "python3 gen.py a.idl b.idl -o /out/gen.c --tmp=/out/t"
+        );
+        let shell = CommandLine::from("cp @INPUT@ out");
+        assert_eq!(shell.expand(&inputs, &outputs, "/out"), shell);
+    }
+
     #[test]
     fn parses_manifest_with_multiple_target_types() {
         let mut file = NamedTempFile::new().unwrap();
@@ -117,6 +1574,7 @@ type = "executable"
 name = "app"
 sources = ["src/main.c"]
 deps = ["util"]
+sign = "codesign -s - ${OUTPUT}"
 
 [[targets]]
 type = "static_library"
@@ -129,12 +1587,237 @@ name = "codegen"
 command = "python gen.py"
 outputs = ["generated.h"]
 inputs = ["schema.json"]
+
+[[targets]]
+type = "docs"
+name = "docs"
+input = ["include"]
+doxygen = { EXTRACT_ALL = "YES" }
+
+[[install_data]]
+sources = ["data/hello.desktop"]
+install_dir = "applications"
+install_mode = "0644"
+
+[[install_symlink]]
+name = "sbin/app"
+pointing_to = "../bin/app"
+
+[[install_emptydir]]
+dirs = ["var/lib/demo"]
+install_mode = "0750"
+
+[package]
+maintainer = "Demo Team <demo@example.org>"
+depends = ["libc6"]
 "#;
         std::io::Write::write_all(&mut file, contents.as_bytes()).unwrap();
 
         let manifest = ProjectManifest::load(file.path()).unwrap();
         assert_eq!(manifest.project.name, "demo");
-        assert_eq!(manifest.targets.len(), 3);
+        assert_eq!(manifest.targets.len(), 4);
         assert_eq!(manifest.targets[0].name(), "app");
+        assert_eq!(
+            manifest.targets[0].options().unwrap().sign.as_deref(),
+            Some("codesign -s - ${OUTPUT}")
+        );
+        assert!(manifest.targets[2].options().is_none());
+        assert_eq!(manifest.targets[3].sources(), ["include"]);
+        assert_eq!(manifest.install_data[0].install_mode, Some(0o644));
+        assert_eq!(manifest.install_symlink[0].pointing_to, "../bin/app");
+        assert_eq!(manifest.package.depends, ["libc6"]);
+        assert!(manifest.package.license.is_none());
+        assert_eq!(manifest.install_emptydir[0].install_mode, Some(0o750));
+    }
+
+    #[test]
+    fn tags_install_entries_with_components() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "shared_library"
+name = "demo"
+sources = ["demo.c"]
+install = true
+component = "devel"
+
+[[install_data]]
+sources = ["demo.conf"]
+
+[[install_data]]
+sources = ["README"]
+component = "doc"
+
+[[install_symlink]]
+name = "include/demo.h"
+pointing_to = "demo-1.0/demo.h"
+component = "devel"
+
+[[install_emptydir]]
+dirs = ["var/lib/demo"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.targets[0].options().unwrap().install_component,
+            Some(Component::Devel)
+        );
+        let data: Vec<_> = manifest.install_data.iter().map(|d| d.component).collect();
+        assert_eq!(data, [Component::Runtime, Component::Doc]);
+        assert_eq!(manifest.install_symlink[0].component, Component::Devel);
+        assert_eq!(manifest.install_emptydir[0].component, Component::Runtime);
+    }
+
+    #[test]
+    fn reports_deprecated_fields_and_options() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "shared_library"
+name = "demo"
+sources = ["demo.c"]
+install_component = "devel"
+
+[profile.ci.options]
+colors = "never"
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.targets[0].options().unwrap().install_component,
+            Some(Component::Devel)
+        );
+        assert_eq!(
+            manifest.profile["ci"].options.color.as_deref(),
+            Some("never")
+        );
+        let found: Vec<String> = manifest
+            .deprecated_keys
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "target 'demo' uses deprecated key 'install_component'; use 'component' instead",
+                "profile 'ci' uses deprecated key 'colors'; use 'color' instead",
+            ]
+        );
+    }
+
+    #[test]
+    fn namespaces_targets_of_subdir_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, contents: &str| {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write(
+            "crust.build",
+            r#"
+[project]
+name = "demo"
+subdirs = ["libfoo"]
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c", "alias/main.c"]
+deps = ["//libfoo:util"]
+"#,
+        );
+        write("main.c", "");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("alias")).unwrap();
+        write(
+            "libfoo/crust.build",
+            r#"
+subdirs = ["bar"]
+
+[[targets]]
+type = "static_library"
+name = "util"
+sources = ["util.c"]
+"#,
+        );
+        write(
+            "libfoo/bar/crust.build",
+            r#"
+[[targets]]
+type = "executable"
+name = "util"
+sources = ["./util.c", "../bar//util.c"]
+deps = ["//libfoo:util", "//:app"]
+install_component = "doc"
+"#,
+        );
+
+        let manifest = ProjectManifest::load(&dir.path().join("crust.build")).unwrap();
+        let names: Vec<&str> = manifest.targets.iter().map(Target::name).collect();
+        assert_eq!(names, ["app", "//libfoo:util", "//libfoo/bar:util"]);
+        // Spellings of one file through `.`, `..`, or a symlink are built once.
+        assert_eq!(manifest.targets[2].sources(), ["libfoo/bar/util.c"]);
+        assert_eq!(
+            manifest.deprecated_keys[0].used_in,
+            "target '//libfoo/bar:util'"
+        );
+        #[cfg(unix)]
+        assert_eq!(manifest.targets[0].sources(), ["main.c"]);
+        assert_eq!(manifest.targets[2].dependencies(), ["//libfoo:util", "app"]);
+        assert_eq!(label_path("//libfoo/bar:util"), "libfoo/bar/util");
+        assert_eq!(split_label("app"), (None, "app"));
+    }
+
+    #[test]
+    fn bundles_flags_and_options_in_profiles() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+crust_version = ">=0.2"
+
+[profile.asan]
+buildtype = "debug"
+sanitizers = ["address", "undefined"]
+c_args = ["-fno-omit-frame-pointer"]
+options = { jobs = 2, summary = "full" }
+
+[profile.release-lto]
+buildtype = "release"
+c_link_args = ["-flto"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let asan = manifest.profile("asan").unwrap();
+        assert_eq!(
+            asan.compile_args(),
+            [
+                "-O0",
+                "-g",
+                "-fsanitize=address,undefined",
+                "-fno-omit-frame-pointer"
+            ]
+        );
+        assert_eq!(asan.link_args(), ["-fsanitize=address,undefined"]);
+        assert_eq!(asan.options.jobs, Some(2));
+        let lto = manifest.profile("release-lto").unwrap();
+        assert_eq!(lto.compile_args(), ["-O3"]);
+        assert_eq!(lto.link_args(), ["-flto"]);
+        assert_eq!(
+            manifest.profile("tsan").unwrap_err().to_string(),
+            "unknown profile 'tsan', expected one of: asan, release-lto"
+        );
     }
 }
@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+/// Settings of a copy_file target.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CopySettings {
+    /// Values replacing `@NAME@` placeholders; the file is copied byte for
+    /// byte when empty.
+    pub variables: BTreeMap<String, String>,
+}
+
+impl CopySettings {
+    /// `text` with every `@NAME@` of a known variable replaced by its value.
+    /// Other `@` characters are left alone.
+    pub fn substitute(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('@') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let value = after
+                .find('@')
+                .and_then(|end| Some((end, self.variables.get(&after[..end])?)));
+            match value {
+                Some((end, value)) => {
+                    out.push_str(value);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    out.push('@');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_known_placeholders_only() {
+        let settings = CopySettings {
+            variables: BTreeMap::from([
+                ("VERSION".to_string(), "1.2".to_string()),
+                ("PREFIX".to_string(), "/usr".to_string()),
+            ]),
+        };
+        assert_eq!(
+            settings.substitute("v@VERSION@ in @PREFIX@/lib, mail me@example.com @UNSET@"),
+            "v1.2 in /usr/lib, mail me@example.com @UNSET@"
+        );
+    }
+}
@@ -0,0 +1,577 @@
+use crate::error::CrustError;
+use crate::graph::TargetKind;
+use crate::languages;
+use crate::toolchain::{find_program_in, wrap_command};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub mod native;
+
+/// Tool overrides for building on one machine and running on another.
+///
+/// Loaded from a TOML file passed with `--cross-file`; every field is optional
+/// so the default value describes a native build.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct CrossFile {
+    #[serde(default)]
+    pub binaries: CrossBinaries,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_machine: Option<HostMachine>,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+    /// Per-architecture tools for multi-architecture targets, keyed by the
+    /// names used in a target's `architectures` list.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub architectures: BTreeMap<String, ArchBinaries>,
+    /// Compilers of the registered languages (see `crate::languages`), keyed
+    /// by language name, such as `d = "ldc2"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub compilers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct CrossBinaries {
+    /// C compiler and linker driver (defaults to `cc`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc: Option<String>,
+    /// Archiver used for static libraries (defaults to `ar`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ar: Option<String>,
+    /// Section size tool used by `size_report` (defaults to `size`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    /// Program and arguments prefixed to built executables when running
+    /// them, e.g. `["qemu-aarch64", "-L", "/usr/aarch64-linux-gnu"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exe_wrapper: Vec<String>,
+    /// Tool combining per-architecture outputs into universal binaries
+    /// (defaults to `lipo`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lipo: Option<String>,
+    /// Post-link optimizer used by targets that set `bolt` (defaults to
+    /// `llvm-bolt`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bolt: Option<String>,
+    /// Rust compiler used by rust_executable and rust_library targets
+    /// (defaults to `rustc`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rustc: Option<String>,
+    /// Fortran compiler, also the linker driver of targets containing
+    /// Fortran code (defaults to `gfortran`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fc: Option<String>,
+    /// CUDA compiler used for `.cu` sources and device linking (defaults to
+    /// `nvcc`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvcc: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct ArchBinaries {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ar: Option<String>,
+}
+
+/// Flags applied to every compile and link for the host machine.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct Properties {
+    /// Passed as `--sysroot=<dir>` when compiling and linking.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sys_root: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub c_args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub c_link_args: Vec<String>,
+}
+
+impl Properties {
+    fn is_empty(&self) -> bool {
+        *self == Properties::default()
+    }
+}
+
+/// The machine the build outputs run on.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct HostMachine {
+    pub system: String,
+    pub cpu: String,
+}
+
+/// Target platform families whose artifacts need different names or flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    /// Unix-like targets producing `name`, `libname.a`, and `libname.so`.
+    #[default]
+    Native,
+    /// WebAssembly via Emscripten: executables are `name.js` plus `name.wasm`
+    /// and shared libraries are side modules named `libname.wasm`.
+    Emscripten,
+    /// Android: native naming, but shared libraries are position independent
+    /// and carry a `DT_SONAME` so the loader can find them inside an APK.
+    Android,
+}
+
+impl Platform {
+    /// File names (relative to the build directory) produced by a compiled
+    /// target; the first entry is the artifact other targets link against.
+    pub fn output_names(self, kind: &TargetKind, name: &str) -> Vec<String> {
+        match (self, kind) {
+            (
+                _,
+                TargetKind::CustomCommand
+                | TargetKind::Gettext
+                | TargetKind::Man
+                | TargetKind::VcsVersion
+                | TargetKind::Fetch
+                | TargetKind::Extract
+                | TargetKind::CopyFile
+                | TargetKind::PythonSources
+                | TargetKind::RustExecutable
+                | TargetKind::RustLibrary
+                | TargetKind::Alias,
+            ) => Vec::new(),
+            // CPython imports `<name>.so` on every platform but Windows.
+            (_, TargetKind::PythonExtension) => vec![format!("{name}.so")],
+            (_, TargetKind::Docs) => vec![format!("{name}/html/index.html")],
+            (_, TargetKind::StaticLibrary) => vec![format!("lib{name}.a")],
+            (Platform::Native | Platform::Android, TargetKind::Executable) => {
+                vec![name.to_string()]
+            }
+            (Platform::Native | Platform::Android, TargetKind::SharedLibrary) => {
+                vec![format!("lib{name}.so")]
+            }
+            (Platform::Emscripten, TargetKind::Executable) => {
+                vec![format!("{name}.js"), format!("{name}.wasm")]
+            }
+            (Platform::Emscripten, TargetKind::SharedLibrary) => vec![format!("lib{name}.wasm")],
+        }
+    }
+
+    /// Extra compiler flags for objects of a `kind` target.
+    pub fn compile_flags(self, kind: &TargetKind) -> &'static [&'static str] {
+        match (self, kind) {
+            (
+                Platform::Emscripten | Platform::Android,
+                TargetKind::SharedLibrary | TargetKind::PythonExtension,
+            ) => &["-fPIC"],
+            _ => &[],
+        }
+    }
+
+    /// Extra linker flags for a `kind` target producing `output`;
+    /// `links_shared` is set when it links against shared libraries built in
+    /// this project.
+    pub fn link_flags(self, kind: &TargetKind, output: &Path, links_shared: bool) -> Vec<String> {
+        match (self, kind) {
+            (Platform::Emscripten, TargetKind::SharedLibrary) => vec!["-sSIDE_MODULE=1".into()],
+            (Platform::Emscripten, TargetKind::Executable) if links_shared => {
+                vec!["-sMAIN_MODULE=1".into()]
+            }
+            (Platform::Android, TargetKind::SharedLibrary) => {
+                let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+                vec![format!("-Wl,-soname,{file_name}")]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl CrossFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|source| CrustError::ManifestRead {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let cross: CrossFile =
+            toml::from_str(&content).map_err(|err| CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            })?;
+        cross.check(path)?;
+        Ok(cross)
+    }
+
+    /// Rejects an `exe_wrapper` without a program and compilers of unknown
+    /// languages in the file loaded from `path`.
+    fn check(&self, path: &Path) -> Result<()> {
+        if self
+            .binaries
+            .exe_wrapper
+            .first()
+            .is_some_and(String::is_empty)
+        {
+            return Err(CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: "exe_wrapper must start with a program name".into(),
+            }
+            .into());
+        }
+        if let Some(name) = self
+            .compilers
+            .keys()
+            .find(|name| languages::named(name).is_none())
+        {
+            return Err(CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: format!("[compilers] names unknown language `{name}`"),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Platform family of the host machine, used for output naming.
+    pub fn platform(&self) -> Platform {
+        match &self.host_machine {
+            Some(host) if host.system == "emscripten" => Platform::Emscripten,
+            Some(host) if host.system == "android" => Platform::Android,
+            _ => Platform::Native,
+        }
+    }
+
+    /// Loads `path` when given, otherwise describes a native build.
+    pub fn load_optional(path: Option<&Path>) -> Result<Self> {
+        path.map_or_else(|| Ok(CrossFile::default()), CrossFile::load)
+    }
+
+    pub fn cc(&self) -> &str {
+        self.binaries.cc.as_deref().unwrap_or("cc")
+    }
+
+    pub fn ar(&self) -> &str {
+        self.binaries.ar.as_deref().unwrap_or("ar")
+    }
+
+    pub fn size(&self) -> &str {
+        self.binaries.size.as_deref().unwrap_or("size")
+    }
+
+    pub fn lipo(&self) -> &str {
+        self.binaries.lipo.as_deref().unwrap_or("lipo")
+    }
+
+    pub fn bolt(&self) -> &str {
+        self.binaries.bolt.as_deref().unwrap_or("llvm-bolt")
+    }
+
+    pub fn fc(&self) -> &str {
+        self.binaries.fc.as_deref().unwrap_or("gfortran")
+    }
+
+    pub fn nvcc(&self) -> &str {
+        self.binaries.nvcc.as_deref().unwrap_or("nvcc")
+    }
+
+    pub fn rustc(&self) -> &str {
+        self.binaries.rustc.as_deref().unwrap_or("rustc")
+    }
+
+    /// Compiler configured for the registered language `name`, if any.
+    pub fn language_compiler(&self, name: &str) -> Option<&str> {
+        self.compilers.get(name).map(String::as_str)
+    }
+
+    /// Compiler and extra flags for building for `arch` (the default target when
+    /// `None`). Architectures without their own compiler fall back to the
+    /// default one with Apple's `-arch <arch>` flag.
+    pub fn compiler_for(&self, arch: Option<&str>) -> (&str, Vec<String>) {
+        let (cc, arch_flags) = match arch {
+            None => (self.cc(), Vec::new()),
+            Some(arch) => match self.architectures.get(arch).and_then(|a| a.cc.as_deref()) {
+                Some(cc) => (cc, Vec::new()),
+                None => (self.cc(), vec!["-arch".into(), arch.into()]),
+            },
+        };
+        let (program, mut flags) = split_driver(cc);
+        flags.extend(arch_flags);
+        (program, flags)
+    }
+
+    /// Flags added to every compile command.
+    pub fn compile_args(&self) -> Vec<String> {
+        let props = &self.properties;
+        let sysroot = props.sys_root.iter().map(|dir| format!("--sysroot={dir}"));
+        sysroot.chain(props.c_args.iter().cloned()).collect()
+    }
+
+    /// Flags added to every executable and shared library link command.
+    pub fn link_args(&self) -> Vec<String> {
+        let props = &self.properties;
+        let sysroot = props.sys_root.iter().map(|dir| format!("--sysroot={dir}"));
+        sysroot.chain(props.c_link_args.iter().cloned()).collect()
+    }
+
+    /// Archiver for static libraries built for `arch`.
+    pub fn archiver_for(&self, arch: Option<&str>) -> &str {
+        arch.and_then(|arch| self.architectures.get(arch))
+            .and_then(|a| a.ar.as_deref())
+            .unwrap_or_else(|| self.ar())
+    }
+
+    /// Builds a command that runs the built executable `program`, going
+    /// through `exe_wrapper` when one is configured.
+    pub fn exe_command(&self, program: &Path) -> Command {
+        let inner = Command::new(program);
+        match self.binaries.exe_wrapper.split_first() {
+            Some((wrapper, args)) => wrap_command(wrapper, args, &inner),
+            None => inner,
+        }
+    }
+}
+
+/// Detects an Emscripten SDK in `dirs` (usually `PATH` plus
+/// `$EMSDK/upstream/emscripten`) and describes it as a cross file. Built
+/// executables run through `node` when it is found as well.
+pub fn emscripten_profile<I>(dirs: I) -> Result<CrossFile>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let dirs: Vec<PathBuf> = dirs.into_iter().collect();
+    let find = |program: &str| find_program_in(program, dirs.iter().cloned());
+    let emcc = find("emcc").ok_or_else(|| CrustError::CompilerMissing {
+        program: "emcc".into(),
+    })?;
+    let emar = find("emar").ok_or_else(|| CrustError::CompilerMissing {
+        program: "emar".into(),
+    })?;
+    let path = |p: PathBuf| p.to_string_lossy().into_owned();
+    Ok(CrossFile {
+        binaries: CrossBinaries {
+            cc: Some(path(emcc)),
+            ar: Some(path(emar)),
+            exe_wrapper: find("node").map(path).into_iter().collect(),
+            ..Default::default()
+        },
+        host_machine: Some(HostMachine {
+            system: "emscripten".into(),
+            cpu: "wasm32".into(),
+        }),
+        ..Default::default()
+    })
+}
+
+/// Android ABIs supported by the NDK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AndroidAbi {
+    #[value(name = "arm64-v8a")]
+    Arm64V8a,
+    #[value(name = "armeabi-v7a")]
+    ArmeabiV7a,
+    X86,
+    #[value(name = "x86_64")]
+    X86_64,
+}
+
+impl AndroidAbi {
+    /// Target triple prefix of the NDK's clang wrappers and the CPU name.
+    fn triple_and_cpu(self) -> (&'static str, &'static str) {
+        match self {
+            AndroidAbi::Arm64V8a => ("aarch64-linux-android", "aarch64"),
+            AndroidAbi::ArmeabiV7a => ("armv7a-linux-androideabi", "arm"),
+            AndroidAbi::X86 => ("i686-linux-android", "x86"),
+            AndroidAbi::X86_64 => ("x86_64-linux-android", "x86_64"),
+        }
+    }
+}
+
+/// Describes the NDK at `ndk` as a cross file for `abi` at API level `api`,
+/// using its LLVM toolchain for the current host.
+pub fn android_profile(ndk: &Path, abi: AndroidAbi, api: u32) -> Result<CrossFile> {
+    let host_tag = match std::env::consts::OS {
+        "macos" => "darwin-x86_64",
+        "windows" => "windows-x86_64",
+        _ => "linux-x86_64",
+    };
+    let prebuilt = ndk
+        .join("toolchains")
+        .join("llvm")
+        .join("prebuilt")
+        .join(host_tag);
+    if !prebuilt.is_dir() {
+        return Err(CrustError::InvalidArgument {
+            message: format!(
+                "{} does not look like an Android NDK (missing {})",
+                ndk.display(),
+                prebuilt.display()
+            ),
+        }
+        .into());
+    }
+
+    let bin = prebuilt.join("bin");
+    let (triple, cpu) = abi.triple_and_cpu();
+    let clang = bin.join(format!("{triple}{api}-clang"));
+    if !clang.exists() {
+        return Err(CrustError::InvalidArgument {
+            message: format!(
+                "The NDK has no compiler for API level {api} ({} not found); \
+                 pick an API level this NDK supports",
+                clang.display()
+            ),
+        }
+        .into());
+    }
+    let path = |p: PathBuf| p.to_string_lossy().into_owned();
+    Ok(CrossFile {
+        binaries: CrossBinaries {
+            cc: Some(path(clang)),
+            ar: Some(path(bin.join("llvm-ar"))),
+            size: Some(path(bin.join("llvm-size"))),
+            ..Default::default()
+        },
+        host_machine: Some(HostMachine {
+            system: "android".into(),
+            cpu: cpu.into(),
+        }),
+        properties: Properties {
+            sys_root: Some(path(prebuilt.join("sysroot"))),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+}
+
+/// Splits Zig's C compiler, configured as `zig cc` (or `zig c++`), into the
+/// program and its subcommand. Other compilers are a single path, which
+/// may contain spaces.
+fn split_driver(cc: &str) -> (&str, Vec<String>) {
+    match cc.rsplit_once(' ') {
+        Some((program, sub @ ("cc" | "c++")))
+            if Path::new(program)
+                .file_stem()
+                .is_some_and(|stem| stem == "zig") =>
+        {
+            (program, vec![sub.to_string()])
+        }
+        _ => (cc, Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn loads_binaries_and_wraps_executables() {
+        let mut file = NamedTempFile::new().unwrap();
+        let contents = r#"
+[binaries]
+cc = "aarch64-linux-gnu-gcc"
+ar = "aarch64-linux-gnu-ar"
+exe_wrapper = ["qemu-aarch64", "-L", "/usr/aarch64-linux-gnu"]
+
+[host_machine]
+system = "linux"
+cpu = "aarch64"
+
+[architectures.riscv64]
+cc = "riscv64-linux-gnu-gcc"
+"#;
+        std::io::Write::write_all(&mut file, contents.as_bytes()).unwrap();
+
+        let cross = CrossFile::load(file.path()).unwrap();
+        assert_eq!(cross.cc(), "aarch64-linux-gnu-gcc");
+        assert_eq!(cross.size(), "size");
+        assert_eq!(cross.host_machine.as_ref().unwrap().cpu, "aarch64");
+
+        let cmd = cross.exe_command(Path::new("build/tests"));
+        assert_eq!(cmd.get_program(), "qemu-aarch64");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["-L", "/usr/aarch64-linux-gnu", "build/tests"]);
+
+        let native = CrossFile::default().exe_command(Path::new("build/tests"));
+        assert_eq!(native.get_program(), "build/tests");
+
+        assert_eq!(
+            cross.compiler_for(Some("riscv64")),
+            ("riscv64-linux-gnu-gcc", vec![])
+        );
+        assert_eq!(
+            cross.compiler_for(Some("x86_64")),
+            (
+                "aarch64-linux-gnu-gcc",
+                vec!["-arch".to_string(), "x86_64".to_string()]
+            )
+        );
+        assert_eq!(cross.archiver_for(Some("riscv64")), "aarch64-linux-gnu-ar");
+
+        let zig: CrossFile =
+            toml::from_str("[binaries]\ncc = \"/opt/zig/zig cc\"\n[compilers]\nd = \"ldc2\"\n")
+                .unwrap();
+        assert_eq!(
+            zig.compiler_for(None),
+            ("/opt/zig/zig", vec!["cc".to_string()])
+        );
+        assert_eq!(zig.language_compiler("d"), Some("ldc2"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detects_emscripten_and_names_wasm_outputs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let sdk = tempfile::tempdir().unwrap();
+        for tool in ["emcc", "emar"] {
+            let path = sdk.path().join(tool);
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let profile = emscripten_profile([sdk.path().to_path_buf()]).unwrap();
+        assert_eq!(profile.platform(), Platform::Emscripten);
+        assert!(profile.cc().ends_with("emcc"));
+        let reloaded: CrossFile = toml::from_str(&toml::to_string(&profile).unwrap()).unwrap();
+        assert_eq!(reloaded, profile);
+
+        let platform = profile.platform();
+        assert_eq!(
+            platform.output_names(&TargetKind::Executable, "app"),
+            ["app.js", "app.wasm"]
+        );
+        assert_eq!(
+            platform.output_names(&TargetKind::SharedLibrary, "math"),
+            ["libmath.wasm"]
+        );
+        assert!(emscripten_profile(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn generates_android_profile_from_ndk_layout() {
+        let ndk = tempfile::tempdir().unwrap();
+        let host_tag = match std::env::consts::OS {
+            "macos" => "darwin-x86_64",
+            "windows" => "windows-x86_64",
+            _ => "linux-x86_64",
+        };
+        let prebuilt = ndk.path().join("toolchains/llvm/prebuilt").join(host_tag);
+        fs::create_dir_all(prebuilt.join("bin")).unwrap();
+        fs::write(prebuilt.join("bin/aarch64-linux-android24-clang"), "").unwrap();
+
+        let profile = android_profile(ndk.path(), AndroidAbi::Arm64V8a, 24).unwrap();
+        assert_eq!(profile.platform(), Platform::Android);
+        assert!(profile.cc().ends_with("aarch64-linux-android24-clang"));
+        assert!(profile.ar().ends_with("llvm-ar"));
+        let sysroot = format!("--sysroot={}", prebuilt.join("sysroot").display());
+        assert_eq!(profile.compile_args(), profile.link_args());
+        assert_eq!(profile.link_args(), [sysroot]);
+        assert_eq!(
+            Platform::Android.link_flags(
+                &TargetKind::SharedLibrary,
+                Path::new("out/libcore.so"),
+                false
+            ),
+            ["-Wl,-soname,libcore.so"]
+        );
+
+        assert!(android_profile(ndk.path(), AndroidAbi::Arm64V8a, 19).is_err());
+        assert!(android_profile(&ndk.path().join("missing"), AndroidAbi::X86, 24).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use super::{CrossBinaries, CrossFile, Properties};
+use crate::error::CrustError;
+use crate::settings::Defaults;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Site-specific settings of the machine crust builds on, passed with
+/// `--native-file` for native builds instead of a cross file.
+///
+/// Besides the cross file's `[binaries]`, `[properties]`, and `[compilers]`
+/// it holds `[options]`, defaults for command-line options like those of
+/// the config files, and `[env]`, variables set for everything crust runs.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NativeFile {
+    #[serde(default)]
+    pub binaries: CrossBinaries,
+    #[serde(default)]
+    pub properties: Properties,
+    #[serde(default)]
+    pub compilers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub options: Defaults,
+    /// Variables exported to crust's environment, overriding inherited
+    /// ones, so compilers, tools, and dependency lookups see them.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl NativeFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|source| CrustError::ManifestRead {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let native: NativeFile =
+            toml::from_str(&content).map_err(|err| CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            })?;
+        native.machine().check(path)?;
+        Ok(native)
+    }
+
+    /// The build machine's tools and flags, described like a cross file
+    /// whose host is the build machine.
+    pub fn machine(&self) -> CrossFile {
+        CrossFile {
+            binaries: self.binaries.clone(),
+            properties: self.properties.clone(),
+            compilers: self.compilers.clone(),
+            ..CrossFile::default()
+        }
+    }
+
+    /// Sets the `[env]` variables in crust's environment. Called before any
+    /// thread starts, so commands inherit them.
+    pub fn export_env(&self) {
+        for (name, value) in &self.env {
+            env::set_var(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_machine_options_and_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("native.toml");
+        fs::write(
+            &path,
+            r#"
+[binaries]
+cc = "/opt/gcc-14/bin/gcc"
+
+[properties]
+c_args = ["-march=native"]
+
+[options]
+jobs = 32
+hermetic = true
+
+[env]
+PKG_CONFIG_PATH = "/opt/site/lib/pkgconfig"
+"#,
+        )
+        .unwrap();
+
+        let native = NativeFile::load(&path).unwrap();
+        let machine = native.machine();
+        assert_eq!(machine.cc(), "/opt/gcc-14/bin/gcc");
+        assert_eq!(machine.compile_args(), ["-march=native"]);
+        assert!(machine.host_machine.is_none());
+        assert_eq!(native.options.jobs, Some(32));
+        assert_eq!(native.options.hermetic, Some(true));
+        assert_eq!(native.env["PKG_CONFIG_PATH"], "/opt/site/lib/pkgconfig");
+
+        // The host is the build machine, so there is nothing to describe.
+        fs::write(
+            &path,
+            "[host_machine]\nsystem = \"linux\"\ncpu = \"x86_64\"\n",
+        )
+        .unwrap();
+        let err = NativeFile::load(&path).unwrap_err().to_string();
+        assert!(err.contains("host_machine"), "{err}");
+    }
+}
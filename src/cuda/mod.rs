@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Whether `path` is a CUDA source, compiled with nvcc.
+pub fn is_cuda(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "cu")
+}
+
+/// Object (relative to the build directory) holding the device code of a
+/// target built with `cuda_separable`, linked together with its objects.
+pub fn device_link_object(target: &str) -> String {
+    format!("{target}.dlink.o")
+}
+
+/// Why a `cuda_arch` entry cannot be used, if it cannot. Entries are real
+/// architectures such as `sm_80`, or bare compute capabilities (`80`).
+pub fn arch_error(arch: &str) -> Option<String> {
+    let number = arch.strip_prefix("sm_").unwrap_or(arch);
+    let valid = number.len() >= 2
+        && number
+            .strip_suffix(['a', 'f'])
+            .unwrap_or(number)
+            .chars()
+            .all(|c| c.is_ascii_digit());
+    (!valid).then(|| format!("invalid cuda_arch `{arch}`; expected a value such as \"sm_80\""))
+}
+
+/// `-gencode` flags generating machine code for every architecture in
+/// `archs`, plus PTX for the newest so later GPUs can JIT-compile it. nvcc
+/// picks its default architecture when `archs` is empty.
+pub fn gencode_flags(archs: &[String]) -> Vec<String> {
+    let numbers: Vec<&str> = archs
+        .iter()
+        .map(|arch| arch.strip_prefix("sm_").unwrap_or(arch))
+        .collect();
+    let mut flags: Vec<String> = numbers
+        .iter()
+        .map(|n| format!("-gencode=arch=compute_{n},code=sm_{n}"))
+        .collect();
+    if let Some(newest) = numbers.iter().max_by_key(|n| capability(n)) {
+        flags.push(format!(
+            "-gencode=arch=compute_{newest},code=compute_{newest}"
+        ));
+    }
+    flags
+}
+
+/// Numeric compute capability of an architecture number, ignoring the `a`
+/// and `f` feature suffixes: 90 for `90a`.
+fn capability(number: &str) -> u32 {
+    number
+        .trim_end_matches(['a', 'f'])
+        .parse()
+        .unwrap_or_default()
+}
+
+/// Host compiler flags forwarded through nvcc with `-Xcompiler`.
+pub fn forward_host_flags(flags: &[String]) -> Vec<String> {
+    flags
+        .iter()
+        .map(|flag| format!("-Xcompiler={flag}"))
+        .collect()
+}
+
+/// `nvcc -dlink` invocation resolving the device code of `inputs`, objects
+/// and static libraries compiled with `-rdc=true`, into `output`.
+pub fn device_link_command(
+    nvcc: &str,
+    host_compiler: &str,
+    archs: &[String],
+    inputs: &[PathBuf],
+    output: &Path,
+) -> Command {
+    let mut cmd = Command::new(nvcc);
+    cmd.arg("-ccbin")
+        .arg(host_compiler)
+        .args(gencode_flags(archs))
+        .arg("-dlink")
+        .args(inputs)
+        .arg("-o")
+        .arg(output);
+    cmd
+}
+
+/// Linker flags for the CUDA runtime. The libraries live next to nvcc
+/// (`<cuda>/bin/nvcc`, `<cuda>/lib64`), not on the default search path.
+pub fn runtime_link_flags(nvcc: Option<&Path>, device_link: bool) -> Vec<String> {
+    let mut flags: Vec<String> = nvcc
+        .and_then(|nvcc| nvcc.parent()?.parent().map(Path::to_path_buf))
+        .map(|root| ["lib64", "lib"].map(|dir| root.join(dir)))
+        .into_iter()
+        .flatten()
+        .filter(|dir| dir.is_dir())
+        .take(1)
+        .map(|dir| format!("-L{}", dir.display()))
+        .collect();
+    if device_link {
+        flags.push("-lcudadevrt".to_string());
+    }
+    flags.push("-lcudart".to_string());
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_gencode_and_runtime_flags() {
+        let archs = vec!["sm_75".to_string(), "86".to_string(), "sm_80".to_string()];
+        assert_eq!(
+            gencode_flags(&archs),
+            [
+                "-gencode=arch=compute_75,code=sm_75",
+                "-gencode=arch=compute_86,code=sm_86",
+                "-gencode=arch=compute_80,code=sm_80",
+                "-gencode=arch=compute_86,code=compute_86",
+            ]
+        );
+        assert!(gencode_flags(&[]).is_empty());
+        assert!(arch_error("sm_90a").is_none());
+        assert!(arch_error("compute_80").is_some());
+        assert!(arch_error("sm_").is_some());
+        assert_eq!(
+            forward_host_flags(&["-fPIC".to_string()]),
+            ["-Xcompiler=-fPIC"]
+        );
+        assert_eq!(runtime_link_flags(None, true), ["-lcudadevrt", "-lcudart"]);
+        assert!(is_cuda(Path::new("kernels/saxpy.cu")));
+    }
+}
@@ -0,0 +1,612 @@
+use crate::builddir::STATE_DIR;
+use crate::ui::{self, BuildEvent, Monitor};
+use anyhow::{anyhow, Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Socket below [`STATE_DIR`] the daemon of a build directory listens on.
+pub const SOCKET_FILE: &str = "daemon.sock";
+
+/// A point in a daemon's journal of changes. `epoch` names one run of the
+/// daemon and starts over when its watcher loses events; `seq` counts the
+/// changes seen within it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub epoch: String,
+    pub seq: u64,
+}
+
+/// One line of JSON a client sends the daemon. Fields it does not know,
+/// such as build options it would ignore, make the request invalid.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    /// The files changed after `since`, a token from an earlier answer.
+    Changes { since: Option<Token> },
+    /// Builds `targets` and those carrying `labels`, all but docs targets
+    /// when both are empty, like `crust build`. The daemon answers with the
+    /// build's events as they happen, then with [`Response::Built`].
+    Build {
+        targets: Vec<String>,
+        labels: Vec<String>,
+    },
+}
+
+/// One line of JSON the daemon answers with.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    /// `changed` is `None` when the daemon cannot tell what changed after
+    /// the token asked about: it was handed out by another run, or events
+    /// were lost since.
+    Changes {
+        token: Token,
+        root: PathBuf,
+        excluded: Vec<PathBuf>,
+        changed: Option<Vec<PathBuf>>,
+    },
+    Event {
+        event: BuildEvent,
+    },
+    /// The end of a build: what `crust build` would have printed after it,
+    /// and its error if it failed.
+    Built {
+        report: String,
+        error: Option<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// A build a client asked the daemon for.
+#[derive(Debug)]
+pub struct BuildRequest<'a> {
+    pub targets: &'a [String],
+    pub labels: &'a [String],
+    /// Paths changed since the daemon's previous build, or `None` before
+    /// the first one and after events were lost.
+    pub changed: Option<Vec<PathBuf>>,
+}
+
+/// Runs the builds clients ask for, writing what `crust build` prints after
+/// building to the given writer.
+pub type Builder = dyn Fn(BuildRequest<'_>, &mut dyn Write) -> Result<()> + Send + Sync;
+
+/// Paths changed below a watched project directory after some token. A
+/// file outside it, or below an excluded directory, is never known to be
+/// unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct Changes {
+    root: PathBuf,
+    excluded: Vec<PathBuf>,
+    changed: HashSet<PathBuf>,
+}
+
+impl Changes {
+    pub fn new(root: PathBuf, excluded: Vec<PathBuf>, changed: HashSet<PathBuf>) -> Self {
+        Changes {
+            root,
+            excluded,
+            changed,
+        }
+    }
+
+    /// Whether `path`, absolute with symlinks resolved, is certainly as it
+    /// was at the token: it lies in the watched tree, and neither it nor a
+    /// directory above it changed.
+    pub fn unchanged(&self, path: &Path) -> bool {
+        path.starts_with(&self.root)
+            && !self.excluded.iter().any(|dir| path.starts_with(dir))
+            && !path
+                .ancestors()
+                .take_while(|dir| dir.starts_with(&self.root))
+                .any(|dir| self.changed.contains(dir))
+    }
+}
+
+/// Asks the daemon watching the project of `builddir`, if one runs, what
+/// changed after `since`. Returns the token to ask from next time, with the
+/// changes when the daemon knows them.
+pub fn changes_since(builddir: &Path, since: Option<&Token>) -> Option<(Token, Option<Changes>)> {
+    let request = Request::Changes {
+        since: since.cloned(),
+    };
+    match ask(&builddir.join(STATE_DIR).join(SOCKET_FILE), &request) {
+        Ok(Response::Changes {
+            token,
+            root,
+            excluded,
+            changed,
+        }) => {
+            let changes =
+                changed.map(|changed| Changes::new(root, excluded, changed.into_iter().collect()));
+            Some((token, changes))
+        }
+        Ok(Response::Error { message }) => {
+            debug!(%message, "crust daemon refused the request");
+            None
+        }
+        Ok(other) => {
+            debug!(response = ?other, "unexpected answer of the crust daemon");
+            None
+        }
+        Err(err) => {
+            debug!(error = %err, "no crust daemon to ask for changes");
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+fn ask(socket: &Path, request: &Request) -> Result<Response> {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Has the daemon of `builddir` build `targets` and `labels`, passing each
+/// event of the build to `on_event` as it happens. Returns the build's
+/// report and, when it failed, its error.
+#[cfg(unix)]
+pub fn build(
+    builddir: &Path,
+    targets: &[String],
+    labels: &[String],
+    mut on_event: impl FnMut(BuildEvent),
+) -> Result<(String, Option<String>)> {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+
+    let socket = builddir.join(STATE_DIR).join(SOCKET_FILE);
+    let mut stream = UnixStream::connect(&socket).with_context(|| {
+        format!(
+            "No crust daemon serves {}; start one with 'crust daemon'",
+            builddir.display()
+        )
+    })?;
+    let request = Request::Build {
+        targets: targets.to_vec(),
+        labels: labels.to_vec(),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+    for line in BufReader::new(stream).lines() {
+        match serde_json::from_str(&line?)? {
+            Response::Event { event } => on_event(event),
+            Response::Built { report, error } => return Ok((report, error)),
+            Response::Error { message } => return Err(anyhow!(message)),
+            Response::Changes { .. } => break,
+        }
+    }
+    Err(anyhow!(
+        "The crust daemon stopped before the build finished"
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn build(
+    _builddir: &Path,
+    _targets: &[String],
+    _labels: &[String],
+    _on_event: impl FnMut(BuildEvent),
+) -> Result<(String, Option<String>)> {
+    Err(anyhow!("the crust daemon needs Unix domain sockets"))
+}
+
+#[cfg(not(unix))]
+fn ask(_socket: &Path, _request: &Request) -> Result<Response> {
+    Err(anyhow!("the crust daemon needs Unix domain sockets"))
+}
+
+/// Start of the names of the files the daemon writes into the watched
+/// tree to catch up with its watcher.
+const COOKIE_PREFIX: &str = ".crust-cookie-";
+
+/// How long the daemon waits for the event of a cookie before it gives up
+/// on knowing what changed.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What changed in the watched tree, by path, since the daemon started or
+/// last lost events.
+#[derive(Debug)]
+struct Journal {
+    epoch: String,
+    seq: u64,
+    /// Sequence number of the latest change of each path.
+    changed: HashMap<PathBuf, u64>,
+    /// Cookies written into the tree whose event has not arrived yet.
+    awaited: HashSet<PathBuf>,
+}
+
+impl Journal {
+    fn new() -> Self {
+        Journal {
+            epoch: new_epoch(),
+            seq: 0,
+            changed: HashMap::new(),
+            awaited: HashSet::new(),
+        }
+    }
+
+    fn record(&mut self, path: PathBuf) {
+        self.seq += 1;
+        self.changed.insert(path, self.seq);
+    }
+
+    /// Forgets every change: what happened while events were lost is
+    /// unknown, so every earlier token becomes useless.
+    fn lost_events(&mut self) {
+        self.epoch = new_epoch();
+        self.seq = 0;
+        self.changed.clear();
+    }
+
+    fn since(&self, since: Option<&Token>) -> (Token, Option<Vec<PathBuf>>) {
+        let token = Token {
+            epoch: self.epoch.clone(),
+            seq: self.seq,
+        };
+        let changed = since
+            .filter(|since| since.epoch == self.epoch && since.seq <= self.seq)
+            .map(|since| {
+                self.changed
+                    .iter()
+                    .filter(|(_, &seq)| seq > since.seq)
+                    .map(|(path, _)| path.clone())
+                    .collect()
+            });
+        (token, changed)
+    }
+}
+
+/// The journal of a watched tree, kept up to date by its watcher.
+struct Watch {
+    root: PathBuf,
+    journal: Mutex<Journal>,
+    cookie_arrived: Condvar,
+    cookies: AtomicU64,
+}
+
+impl Watch {
+    /// Starts watching `root`, leaving out changes below `excluded`. The
+    /// watcher stops when it is dropped.
+    fn start(
+        root: PathBuf,
+        excluded: Vec<PathBuf>,
+    ) -> Result<(Arc<Watch>, notify::RecommendedWatcher)> {
+        let watch = Arc::new(Watch {
+            root: root.clone(),
+            journal: Mutex::new(Journal::new()),
+            cookie_arrived: Condvar::new(),
+            cookies: AtomicU64::new(0),
+        });
+        let events = Arc::clone(&watch);
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                events.record(event, &excluded)
+            })?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", root.display()))?;
+        Ok((watch, watcher))
+    }
+
+    fn record(&self, event: notify::Result<notify::Event>, excluded: &[PathBuf]) {
+        let mut journal = self.journal.lock().expect("journal mutex poisoned");
+        match event {
+            Ok(event) if event.need_rescan() => journal.lost_events(),
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+            Ok(event) => {
+                for path in event.paths {
+                    if is_cookie(&path) {
+                        if journal.awaited.remove(&path) {
+                            self.cookie_arrived.notify_all();
+                        }
+                    } else if !excluded.iter().any(|dir| path.starts_with(dir)) {
+                        journal.record(path);
+                    }
+                }
+            }
+            Err(err) => {
+                debug!(error = %err, "file watcher failed; forgetting changes");
+                journal.lost_events();
+            }
+        }
+    }
+
+    /// The journal once it holds every change made before the call. The
+    /// watcher reports events some time after they happen, so a cookie file
+    /// is written into the tree and its event awaited: the watcher reports
+    /// in order, so earlier changes have been recorded by then. When the
+    /// cookie cannot be written or its event does not arrive in time, the
+    /// journal forgets what it knew.
+    fn synced(&self) -> MutexGuard<'_, Journal> {
+        let id = self.cookies.fetch_add(1, Ordering::Relaxed);
+        let cookie = self
+            .root
+            .join(format!("{COOKIE_PREFIX}{}-{id}", std::process::id()));
+        let mut journal = self.journal.lock().expect("journal mutex poisoned");
+        journal.awaited.insert(cookie.clone());
+        if let Err(err) = fs::write(&cookie, "") {
+            debug!(error = %err, "cannot write a cookie; forgetting changes");
+            journal.awaited.remove(&cookie);
+            journal.lost_events();
+            return journal;
+        }
+        let (mut journal, waited) = self
+            .cookie_arrived
+            .wait_timeout_while(journal, SYNC_TIMEOUT, |journal| {
+                journal.awaited.contains(&cookie)
+            })
+            .expect("journal mutex poisoned");
+        if waited.timed_out() {
+            debug!("the watcher did not report a cookie; forgetting changes");
+            journal.awaited.remove(&cookie);
+            journal.lost_events();
+        }
+        let _ = fs::remove_file(&cookie);
+        journal
+    }
+}
+
+fn is_cookie(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(COOKIE_PREFIX))
+}
+
+fn new_epoch() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |age| age.as_nanos());
+    format!("{}-{nanos}", std::process::id())
+}
+
+/// Watches `project_dir` and answers the builds of `builddir` with what
+/// changed since their last build, until interrupted. Changes below the
+/// build directory are ignored. Builds clients ask for run one at a time
+/// through `builder`, in this process.
+#[cfg(unix)]
+pub fn serve(
+    project_dir: &Path,
+    builddir: &Path,
+    builder: impl Fn(BuildRequest<'_>, &mut dyn Write) -> Result<()> + Send + Sync + 'static,
+) -> Result<()> {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::thread;
+
+    // A manifest given by bare file name has an empty directory.
+    let project_dir = if project_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        project_dir
+    };
+    let root = fs::canonicalize(project_dir)
+        .with_context(|| format!("Failed to resolve {}", project_dir.display()))?;
+    let state_dir = builddir.join(STATE_DIR);
+    fs::create_dir_all(&state_dir)
+        .with_context(|| format!("Failed to create {}", state_dir.display()))?;
+    let excluded = vec![fs::canonicalize(builddir)?];
+    let socket = state_dir.join(SOCKET_FILE);
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(anyhow!(
+            "A crust daemon is already running for {}",
+            builddir.display()
+        ));
+    }
+    // Left behind by a daemon that did not exit cleanly.
+    let _ = fs::remove_file(&socket);
+
+    let (watch, _watcher) = Watch::start(root.clone(), excluded.clone())?;
+
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+    info!(
+        "Watching {} for builds in {}; press Ctrl-C to stop",
+        root.display(),
+        builddir.display()
+    );
+    let builder: Arc<Builder> = Arc::new(builder);
+    // Where the journal stood when the last build started.
+    let last_build = Arc::new(Mutex::new(None));
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let (watch, root, excluded) = (Arc::clone(&watch), root.clone(), excluded.clone());
+        let (builder, last_build) = (Arc::clone(&builder), Arc::clone(&last_build));
+        thread::spawn(move || {
+            let mut writer = &stream;
+            for line in BufReader::new(&stream).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                let response = match serde_json::from_str::<Request>(&line) {
+                    Ok(Request::Changes { since }) => {
+                        let (token, changed) = watch.synced().since(since.as_ref());
+                        Response::Changes {
+                            token,
+                            root: root.clone(),
+                            excluded: excluded.clone(),
+                            changed,
+                        }
+                    }
+                    Ok(Request::Build { targets, labels }) => {
+                        // The active monitor is global, so builds take turns.
+                        let mut last_build = last_build
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner());
+                        let (token, changed) = watch.synced().since(last_build.as_ref());
+                        *last_build = Some(token);
+                        let request = BuildRequest {
+                            targets: &targets,
+                            labels: &labels,
+                            changed,
+                        };
+                        run_build(&stream, builder.as_ref(), request)
+                    }
+                    Err(err) => Response::Error {
+                        message: format!("Invalid request: {err}"),
+                    },
+                };
+                let reply = serde_json::to_string(&response).expect("responses serialize");
+                if writeln!(writer, "{reply}").is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Runs the build `request` through `builder` with a monitor streaming its
+/// events to `stream`, and returns the response ending it.
+#[cfg(unix)]
+fn run_build(
+    stream: &std::os::unix::net::UnixStream,
+    builder: &Builder,
+    request: BuildRequest<'_>,
+) -> Response {
+    use std::sync::mpsc;
+    use std::thread;
+
+    let (events, received) = mpsc::channel();
+    let mut report = Vec::new();
+    let result = thread::scope(|scope| {
+        // Ends once the monitor, and with it the sender, is dropped.
+        scope.spawn(move || {
+            let mut writer = stream;
+            for event in received {
+                let line =
+                    serde_json::to_string(&Response::Event { event }).expect("responses serialize");
+                if writeln!(writer, "{line}").is_err() {
+                    break;
+                }
+            }
+        });
+        let _attached = ui::attach(Arc::new(Monitor::streaming(events)));
+        builder(request, &mut report)
+    });
+    Response::Built {
+        report: String::from_utf8_lossy(&report).into_owned(),
+        error: result.err().map(|err| format!("{err:#}")),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn serve(
+    _project_dir: &Path,
+    _builddir: &Path,
+    _builder: impl Fn(BuildRequest<'_>, &mut dyn Write) -> Result<()> + Send + Sync + 'static,
+) -> Result<()> {
+    Err(crate::error::CrustError::InvalidArgument {
+        message: "crust daemon is only available on Unix".into(),
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changes_after_a_token() {
+        let mut journal = Journal::new();
+        let (start, changed) = journal.since(None);
+        assert_eq!(changed, None);
+
+        journal.record(PathBuf::from("/src/app/main.c"));
+        let (after_main, changed) = journal.since(Some(&start));
+        assert_eq!(changed, Some(vec![PathBuf::from("/src/app/main.c")]));
+        journal.record(PathBuf::from("/src/lib"));
+        let (_, changed) = journal.since(Some(&after_main));
+        assert_eq!(changed, Some(vec![PathBuf::from("/src/lib")]));
+
+        let changes = Changes::new(
+            PathBuf::from("/src"),
+            vec![PathBuf::from("/src/build")],
+            changed.unwrap().into_iter().collect(),
+        );
+        assert!(changes.unchanged(Path::new("/src/app/main.c")));
+        // Below a changed directory, outside the tree, or in the build
+        // directory, nothing is known.
+        assert!(!changes.unchanged(Path::new("/src/lib/util.c")));
+        assert!(!changes.unchanged(Path::new("/usr/include/stdio.h")));
+        assert!(!changes.unchanged(Path::new("/src/build/gen.h")));
+
+        journal.lost_events();
+        assert_eq!(journal.since(Some(&after_main)).1, None);
+    }
+
+    #[test]
+    fn sees_edits_made_just_before_asking() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        let source = root.join("src/main.c");
+        fs::write(&source, "int main(void) { return 0; }").unwrap();
+        let (watch, _watcher) = Watch::start(root.clone(), vec![root.join("build")]).unwrap();
+
+        let (mut token, _) = watch.synced().since(None);
+        for round in 0..20 {
+            fs::write(&source, format!("int main(void) {{ return {round}; }}")).unwrap();
+            let (next, changed) = watch.synced().since(Some(&token));
+            assert_eq!(changed, Some(vec![source.clone()]));
+            token = next;
+        }
+        // Cookies are neither reported nor left behind.
+        let (_, changed) = watch.synced().since(Some(&token));
+        assert_eq!(changed, Some(Vec::new()));
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+    }
+    #[cfg(unix)]
+    #[test]
+    fn refuses_requests_with_options_it_would_ignore() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixStream;
+
+        let dir = tempfile::tempdir().unwrap();
+        let builddir = dir.path().join("build");
+        fs::create_dir(&builddir).unwrap();
+        let project = dir.path().to_path_buf();
+        let served = builddir.clone();
+        std::thread::spawn(move || {
+            serve(&project, &served, |_, _| panic!("built an invalid request"))
+        });
+        let socket = builddir.join(STATE_DIR).join(SOCKET_FILE);
+        let mut stream = (0..100)
+            .find_map(|_| {
+                UnixStream::connect(&socket)
+                    .inspect_err(|_| std::thread::sleep(Duration::from_millis(50)))
+                    .ok()
+            })
+            .expect("the daemon listens");
+
+        writeln!(
+            stream,
+            r#"{{"request":"build","targets":["app"],"labels":[],"jobs":4}}"#
+        )
+        .unwrap();
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).unwrap();
+        let Response::Error { message } = serde_json::from_str(&line).unwrap() else {
+            panic!("not an error: {line}");
+        };
+        assert!(message.contains("unknown field `jobs`"), "{message}");
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error::CrustError;
+use crate::toolchain::{find_program, wrap_command};
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Verifies that `strace` is available for `--check-deps`.
+pub fn check_available() -> Result<()> {
+    if find_program("strace").is_none() {
+        return Err(CrustError::CompilerMissing {
+            program: "strace".into(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Wraps `cmd` in `strace`, following child processes and recording every
+/// successful file open into `trace_file`.
+pub fn wrap(cmd: &Command, trace_file: &Path) -> Command {
+    let trace_file = trace_file.as_os_str().to_owned();
+    wrap_command(
+        "strace",
+        [
+            "-f".into(),
+            "-qq".into(),
+            "-z".into(),
+            "-e".into(),
+            "trace=open,openat,openat2".into(),
+            "-o".into(),
+            trace_file,
+            "--".into(),
+        ],
+        cmd,
+    )
+}
+
+/// Extracts the paths of files opened for reading from strace output.
+pub fn parse_trace(content: &str) -> Vec<PathBuf> {
+    content
+        .lines()
+        .filter(|line| line.contains("open") && !line.contains("O_WRONLY"))
+        .filter(|line| !line.contains("O_DIRECTORY") && !line.contains("= -1 "))
+        .filter_map(|line| {
+            let start = line.find('"')? + 1;
+            let len = line[start..].find('"')?;
+            Some(PathBuf::from(&line[start..start + len]))
+        })
+        .collect()
+}
+
+/// Returns files read from inside `project_dir` that are neither declared
+/// inputs, nor below a declared directory, nor located in `out_dir`.
+/// Relative trace entries are resolved against `cwd`.
+pub fn undeclared_reads(
+    reads: &[PathBuf],
+    cwd: &Path,
+    project_dir: &Path,
+    out_dir: &Path,
+    declared: &[PathBuf],
+) -> BTreeSet<PathBuf> {
+    let canonical = |p: &Path| cwd.join(p).canonicalize().ok();
+    let project_dir = canonical(project_dir);
+    let out_dir = canonical(out_dir);
+    let declared: BTreeSet<PathBuf> = declared.iter().filter_map(|p| canonical(p)).collect();
+
+    reads
+        .iter()
+        .filter_map(|p| canonical(p))
+        .filter(|p| p.is_file())
+        .filter(|p| project_dir.as_ref().is_some_and(|dir| p.starts_with(dir)))
+        .filter(|p| !out_dir.as_ref().is_some_and(|dir| p.starts_with(dir)))
+        .filter(|p| !declared.iter().any(|declared| p.starts_with(declared)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn reports_project_files_missing_from_declared_inputs() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("build")).unwrap();
+        for file in ["src/main.c", "src/config.h", "build/gen.h"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let trace = r#"
+101 openat(AT_FDCWD, "src/main.c", O_RDONLY|O_NOCTTY) = 3
+101 openat(AT_FDCWD, "src/config.h", O_RDONLY|O_NOCTTY) = 3
+101 openat(AT_FDCWD, "build/gen.h", O_RDONLY|O_NOCTTY) = 3
+101 openat(AT_FDCWD, "/usr/include/stdio.h", O_RDONLY|O_NOCTTY) = 3
+102 openat(AT_FDCWD, "build/main.o", O_WRONLY|O_CREAT|O_TRUNC, 0666) = 4
+"#;
+        let reads = parse_trace(trace);
+        assert_eq!(reads.len(), 4);
+
+        let undeclared = undeclared_reads(
+            &reads,
+            root,
+            root,
+            &root.join("build"),
+            &[root.join("src/main.c")],
+        );
+        let expected = root.join("src/config.h").canonicalize().unwrap();
+        assert_eq!(undeclared.into_iter().collect::<Vec<_>>(), vec![expected]);
+    }
+}
@@ -0,0 +1,45 @@
+use super::{pkg_config, Found};
+use crate::toolchain;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Directory below the build directory holding each conan package's
+/// generated pkg-config files, in a directory named after the package.
+pub const CONAN_DIR: &str = "conan";
+
+/// Version and flags of the conan package `reference`, such as
+/// `zlib/1.3.1`. The package is installed with `conan install`, building it
+/// when conan has no binary for it, and described by the `.pc` files of its
+/// `PkgConfigDeps` generator. Installing is skipped while the directory
+/// still holds the same reference.
+pub fn find(reference: &str, builddir: &Path) -> Result<Found, String> {
+    let Some((package, _)) = reference.split_once('/') else {
+        return Err(format!(
+            "conan reference '{reference}' has no version, as in `zlib/1.3.1`"
+        ));
+    };
+    let dir = builddir.join(CONAN_DIR).join(package);
+    let stamp = dir.join("reference");
+    if fs::read_to_string(&stamp).ok().as_deref() != Some(reference) {
+        let conan = toolchain::find_program("conan").ok_or("conan is not on PATH")?;
+        let output = Command::new(conan)
+            .arg("install")
+            .arg(format!("--requires={reference}"))
+            .args(["--build=missing", "-g", "PkgConfigDeps", "--output-folder"])
+            .arg(&dir)
+            .output()
+            .map_err(|err| format!("could not run conan: {err}"))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let last = stderr.lines().rev().find(|line| !line.trim().is_empty());
+            return Err(format!(
+                "conan install {reference} failed: {}",
+                last.unwrap_or("no output").trim()
+            ));
+        }
+        fs::write(&stamp, reference)
+            .map_err(|err| format!("could not write {}: {err}", stamp.display()))?;
+    }
+    pkg_config(package, Some(&dir))
+}
@@ -0,0 +1,243 @@
+use super::{Found, Provider};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File in the build directory remembering where searched-for headers and
+/// libraries were found, keyed by dependency name.
+pub const FIND_CACHE: &str = ".crust_find_cache";
+
+/// What to look for, and where: the dependency's own directories first,
+/// then the compiler's environment variables, then the standard prefixes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Search {
+    pub headers: Vec<String>,
+    pub libraries: Vec<String>,
+    pub include_dirs: Vec<PathBuf>,
+    pub lib_dirs: Vec<PathBuf>,
+}
+
+impl Search {
+    /// Searches `include_dirs` and `lib_dirs` before the directories of
+    /// `CPATH`, `C_INCLUDE_PATH`, and `INCLUDE`, of `LIBRARY_PATH` and `LIB`,
+    /// and the host's standard ones.
+    pub fn new(
+        headers: Vec<String>,
+        libraries: Vec<String>,
+        include_dirs: Vec<PathBuf>,
+        lib_dirs: Vec<PathBuf>,
+    ) -> Search {
+        let from_env = |vars: &[&str]| -> Vec<PathBuf> {
+            vars.iter()
+                .filter_map(env::var_os)
+                .flat_map(|value| env::split_paths(&value).collect::<Vec<_>>())
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .collect()
+        };
+        let mut search = Search {
+            headers,
+            libraries,
+            include_dirs,
+            lib_dirs,
+        };
+        search
+            .include_dirs
+            .extend(from_env(&["CPATH", "C_INCLUDE_PATH", "INCLUDE"]));
+        search
+            .include_dirs
+            .extend(standard_include_dirs().into_iter().map(PathBuf::from));
+        search.lib_dirs.extend(from_env(&["LIBRARY_PATH", "LIB"]));
+        search.lib_dirs.extend(standard_lib_dirs());
+        search
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    search: Search,
+    /// Files the result was derived from; the entry is stale once one of
+    /// them is gone.
+    files: Vec<PathBuf>,
+    compile_args: Vec<String>,
+    link_args: Vec<String>,
+}
+
+/// Flags for the headers and libraries of `search`, or which one is
+/// missing. A result cached in `builddir` for dependency `name` is reused
+/// while the search is the same and the files it found still exist.
+pub fn find(name: &str, search: &Search, builddir: &Path) -> Result<Found, String> {
+    let path = builddir.join(FIND_CACHE);
+    let mut cache: BTreeMap<String, CacheEntry> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+    let entry = match cache.get(name) {
+        Some(entry) if entry.search == *search && entry.files.iter().all(|f| f.exists()) => {
+            entry.clone()
+        }
+        _ => {
+            let entry = search_files(search)?;
+            cache.insert(name.to_string(), entry.clone());
+            let written = fs::create_dir_all(builddir).and_then(|()| {
+                fs::write(
+                    &path,
+                    serde_json::to_string_pretty(&cache).unwrap_or_default(),
+                )
+            });
+            if let Err(err) = written {
+                tracing::debug!("Could not write {}: {err}", path.display());
+            }
+            entry
+        }
+    };
+    Ok(Found {
+        provider: Provider::System,
+        version: None,
+        compile_args: entry.compile_args,
+        link_args: entry.link_args,
+    })
+}
+
+fn search_files(search: &Search) -> Result<CacheEntry, String> {
+    let standard_includes = standard_include_dirs();
+    let standard_libs = standard_lib_dirs();
+    let mut files = Vec::new();
+    let mut compile_args = Vec::new();
+    let mut link_args = Vec::new();
+    let push = |args: &mut Vec<String>, arg: String| {
+        if !args.contains(&arg) {
+            args.push(arg);
+        }
+    };
+
+    for header in &search.headers {
+        let dir = search
+            .include_dirs
+            .iter()
+            .find(|dir| dir.join(header).is_file())
+            .ok_or_else(|| format!("header '{header}' was not found"))?;
+        files.push(dir.join(header));
+        // The compiler searches its standard directories already, and
+        // repeating them with -I changes the order it searches them in.
+        if !standard_includes.iter().any(|std| dir == Path::new(std)) {
+            push(&mut compile_args, format!("-I{}", dir.display()));
+        }
+    }
+    for library in &search.libraries {
+        let (dir, file) = search
+            .lib_dirs
+            .iter()
+            .find_map(|dir| {
+                library_files(library)
+                    .into_iter()
+                    .find(|file| dir.join(file).is_file())
+                    .map(|file| (dir, file))
+            })
+            .ok_or_else(|| format!("library '{library}' was not found"))?;
+        let path = dir.join(&file);
+        if file.ends_with(".lib") {
+            push(&mut link_args, path.display().to_string());
+        } else {
+            if !standard_libs.contains(dir) {
+                push(&mut link_args, format!("-L{}", dir.display()));
+            }
+            push(&mut link_args, format!("-l{library}"));
+        }
+        files.push(path);
+    }
+    Ok(CacheEntry {
+        search: search.clone(),
+        files,
+        compile_args,
+        link_args,
+    })
+}
+
+/// File names library `name` may have, shared before static.
+fn library_files(name: &str) -> Vec<String> {
+    ["so", "dylib", "a"]
+        .iter()
+        .map(|ext| format!("lib{name}.{ext}"))
+        .chain([format!("{name}.lib"), format!("lib{name}.lib")])
+        .collect()
+}
+
+fn standard_include_dirs() -> Vec<&'static str> {
+    if cfg!(windows) {
+        Vec::new()
+    } else if cfg!(target_os = "macos") {
+        vec![
+            "/opt/homebrew/include",
+            "/usr/local/include",
+            "/usr/include",
+        ]
+    } else {
+        vec!["/usr/local/include", "/usr/include"]
+    }
+}
+
+fn standard_lib_dirs() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        return Vec::new();
+    }
+    if cfg!(target_os = "macos") {
+        return ["/opt/homebrew/lib", "/usr/local/lib", "/usr/lib"]
+            .map(PathBuf::from)
+            .to_vec();
+    }
+    let multiarch = format!("{}-linux-gnu", env::consts::ARCH);
+    vec![
+        PathBuf::from("/usr/local/lib"),
+        Path::new("/usr/lib").join(&multiarch),
+        PathBuf::from("/usr/lib64"),
+        PathBuf::from("/usr/lib"),
+        Path::new("/lib").join(&multiarch),
+        PathBuf::from("/lib64"),
+        PathBuf::from("/lib"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_headers_and_libraries_and_caches_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let include = dir.path().join("sdk/include");
+        let lib = dir.path().join("sdk/lib");
+        fs::create_dir_all(&include).unwrap();
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(include.join("foo.h"), "").unwrap();
+        fs::write(lib.join("libfoo.a"), "").unwrap();
+        fs::write(lib.join("bar.lib"), "").unwrap();
+        let builddir = dir.path().join("build");
+        let search = Search {
+            headers: vec!["foo.h".into()],
+            libraries: vec!["foo".into(), "bar".into()],
+            include_dirs: vec![include.clone()],
+            lib_dirs: vec![lib.clone()],
+        };
+
+        let found = find("foo", &search, &builddir).unwrap();
+        assert_eq!(found.compile_args, [format!("-I{}", include.display())]);
+        assert_eq!(
+            found.link_args,
+            [
+                format!("-L{}", lib.display()),
+                "-lfoo".into(),
+                lib.join("bar.lib").display().to_string(),
+            ]
+        );
+        assert!(builddir.join(FIND_CACHE).is_file());
+
+        // Reused from the cache until a file it found is gone.
+        let cached = find("foo", &search, &builddir).unwrap();
+        assert_eq!(cached, found);
+        fs::remove_file(lib.join("bar.lib")).unwrap();
+        let err = find("foo", &search, &builddir).unwrap_err();
+        assert_eq!(err, "library 'bar' was not found");
+    }
+}
@@ -0,0 +1,724 @@
+use crate::config::{DependencySpec, Feature, Method, ProjectManifest};
+use crate::error::CrustError;
+use crate::programs;
+use crate::toolchain;
+use anyhow::Result;
+use clap::ValueEnum;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::process::Command;
+
+pub mod conan;
+pub mod find;
+pub mod vcpkg;
+
+/// Where a found dependency comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provider {
+    /// Installed outside the project, found by the dependency's `method`.
+    System,
+    /// Built from this target of the project.
+    Fallback(String),
+}
+
+/// A dependency the build uses, with the version and flags of its system
+/// library (none for a fallback, whose target supplies its own).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub provider: Provider,
+    pub version: Option<String>,
+    pub compile_args: Vec<String>,
+    pub link_args: Vec<String>,
+}
+
+impl Found {
+    /// Flags compiling a target that lists dependency `name`: the library's
+    /// own and `-DHAVE_<NAME>`.
+    pub fn compile_flags(&self, name: &str) -> Vec<String> {
+        let mut flags = self.compile_args.clone();
+        flags.push(format!("-D{}", have_macro(name)));
+        flags
+    }
+}
+
+/// The `HAVE_<NAME>` macro a found dependency defines, such as `HAVE_LIBPNG`
+/// for `libpng` or `HAVE_ZLIB_NG` for `zlib-ng`.
+pub fn have_macro(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("HAVE_{name}")
+}
+
+/// Every `[dependencies]` entry, found or not.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolution {
+    pub found: BTreeMap<String, Found>,
+    /// Dependencies the build goes without, with the reason.
+    pub missing: BTreeMap<String, String>,
+}
+
+/// Whether dependencies may use their `fallback` target, as set with
+/// `--wrap-mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum WrapMode {
+    /// A fallback is used when its dependency is not found.
+    #[default]
+    Default,
+    /// Fallbacks are never used: dependencies come from the system or not
+    /// at all.
+    Nofallback,
+    /// Every dependency with a fallback uses it without being looked up.
+    Forcefallback,
+}
+
+/// Command-line choices overriding the manifest's `[dependencies]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overrides {
+    /// `--feature NAME=VALUE`.
+    pub features: Vec<(String, Feature)>,
+    /// `--force-fallback-for`: dependencies built from their fallback without
+    /// being looked up.
+    pub force_fallback_for: Vec<String>,
+    pub wrap_mode: WrapMode,
+}
+
+/// Parses a `--feature NAME=enabled|disabled|auto` override.
+pub fn parse_feature(text: &str) -> Result<(String, Feature), String> {
+    let Some((name, value)) = text.split_once('=').filter(|(name, _)| !name.is_empty()) else {
+        return Err(format!("expected NAME=enabled|disabled|auto, got `{text}`"));
+    };
+    let feature = match value {
+        "enabled" => Feature::Enabled,
+        "disabled" => Feature::Disabled,
+        "auto" => Feature::Auto,
+        _ => {
+            return Err(format!(
+                "expected enabled, disabled, or auto, got `{value}`"
+            ))
+        }
+    };
+    Ok((name.to_string(), feature))
+}
+
+/// Looks up every dependency of `manifest` by its `method`, under the
+/// command-line `overrides`. A dependency that is not found, or whose
+/// version does not match its `version`, uses its `fallback` target when it
+/// has one; otherwise an `enabled` one fails the build and an `auto` one is
+/// recorded as missing.
+///
+/// A pkg-config dependency that lists `headers` or `libraries` is searched
+/// for when pkg-config does not know it. Conan packages are installed, and
+/// search results cached, below `builddir`.
+pub fn resolve(
+    manifest: &ProjectManifest,
+    overrides: &Overrides,
+    manifest_dir: &Path,
+    builddir: &Path,
+) -> Result<Resolution> {
+    resolve_with(manifest, overrides, |name, spec| {
+        let module = module(name, spec);
+        let search = || {
+            let mut libraries = spec.libraries.clone();
+            if libraries.is_empty() && spec.headers.is_empty() {
+                libraries.push(module.to_string());
+            }
+            let dirs = |dirs: &[String]| {
+                dirs.iter()
+                    .map(|dir| {
+                        let dir = manifest_dir.join(dir);
+                        std::path::absolute(&dir).unwrap_or(dir)
+                    })
+                    .collect()
+            };
+            let search = find::Search::new(
+                spec.headers.clone(),
+                libraries,
+                dirs(&spec.include_dirs),
+                dirs(&spec.lib_dirs),
+            );
+            find::find(name, &search, builddir)
+        };
+        match spec.method {
+            Method::PkgConfig if spec.headers.is_empty() && spec.libraries.is_empty() => {
+                pkg_config(module, None)
+            }
+            Method::PkgConfig => pkg_config(module, None)
+                .or_else(|reason| search().map_err(|other| format!("{reason}, and {other}"))),
+            Method::Conan => conan::find(module, builddir),
+            Method::Vcpkg => vcpkg::find(module),
+            Method::Find => search(),
+        }
+    })
+}
+
+fn resolve_with(
+    manifest: &ProjectManifest,
+    overrides: &Overrides,
+    lookup: impl Fn(&str, &DependencySpec) -> Result<Found, String>,
+) -> Result<Resolution> {
+    let invalid = |flag: &str, name: &str, problem: &str| -> anyhow::Error {
+        CrustError::InvalidArgument {
+            message: format!("{flag} {name}: {problem}"),
+        }
+        .into()
+    };
+    let features: HashMap<&str, Feature> = overrides
+        .features
+        .iter()
+        .map(|(name, feature)| (name.as_str(), *feature))
+        .collect();
+    let undeclared = "the manifest declares no such dependency";
+    if let Some(name) = features
+        .keys()
+        .find(|name| !manifest.dependencies.contains_key(**name))
+    {
+        return Err(invalid("--feature", name, undeclared));
+    }
+    for name in &overrides.force_fallback_for {
+        match manifest.dependencies.get(name) {
+            None => return Err(invalid("--force-fallback-for", name, undeclared)),
+            Some(spec) if spec.fallback.is_none() => {
+                return Err(invalid("--force-fallback-for", name, "it has no fallback"))
+            }
+            Some(_) if overrides.wrap_mode == WrapMode::Nofallback => {
+                return Err(invalid(
+                    "--force-fallback-for",
+                    name,
+                    "fallbacks are off with --wrap-mode nofallback",
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let mut resolution = Resolution::default();
+    for (name, spec) in &manifest.dependencies {
+        let feature = features.get(name.as_str()).copied().unwrap_or(spec.feature);
+        if feature == Feature::Disabled {
+            resolution.missing.insert(name.clone(), "disabled".into());
+            continue;
+        }
+        let fallback = match &spec.fallback {
+            Some(target) if !manifest.targets.iter().any(|t| t.name() == target) => {
+                return Err(CrustError::UnknownDependency {
+                    target: format!("[dependencies.{name}] fallback"),
+                    dependency: target.clone(),
+                }
+                .into());
+            }
+            _ if overrides.wrap_mode == WrapMode::Nofallback => None,
+            fallback => fallback.as_ref(),
+        };
+        let forced = if overrides.wrap_mode == WrapMode::Forcefallback {
+            Some("--wrap-mode forcefallback")
+        } else {
+            overrides
+                .force_fallback_for
+                .contains(name)
+                .then_some("--force-fallback-for")
+        };
+        let found = match (fallback, forced) {
+            (Some(_), Some(flag)) => Err(format!("forced by {flag}")),
+            _ => lookup(name, spec).and_then(|found| check_version(spec, found)),
+        };
+        let found = match found {
+            Ok(found) => found,
+            Err(reason) => match fallback {
+                Some(target) => {
+                    tracing::info!("Building '{target}' for dependency '{name}' ({reason})");
+                    Found {
+                        provider: Provider::Fallback(target.clone()),
+                        version: None,
+                        compile_args: Vec::new(),
+                        link_args: Vec::new(),
+                    }
+                }
+                None if feature == Feature::Auto => {
+                    tracing::info!("Building without dependency '{name}' ({reason})");
+                    resolution.missing.insert(name.clone(), reason);
+                    continue;
+                }
+                None => {
+                    return Err(CrustError::DependencyNotFound {
+                        name: name.clone(),
+                        reason,
+                    }
+                    .into())
+                }
+            },
+        };
+        resolution.found.insert(name.clone(), found);
+    }
+    Ok(resolution)
+}
+
+/// What dependency `name` is looked up as: its `module`, or its name.
+pub fn module<'a>(name: &'a str, spec: &'a DependencySpec) -> &'a str {
+    spec.module.as_deref().unwrap_or(name)
+}
+
+/// `found` when its version satisfies the `version` of `spec`, otherwise why
+/// it does not.
+fn check_version(spec: &DependencySpec, found: Found) -> Result<Found, String> {
+    let Some(req) = &spec.version else {
+        return Ok(found);
+    };
+    let text = found.version.as_deref().unwrap_or("");
+    match programs::version_in(text) {
+        Some(version) if req.matches(version) => Ok(found),
+        Some(_) => Err(format!("found version {text}, but {req} is required")),
+        None => Err(format!("found no version, but {req} is required")),
+    }
+}
+
+/// Version and flags of pkg-config `module`, or why there are none. With
+/// `only_in`, the `.pc` files of that directory are the only ones searched.
+fn pkg_config(module: &str, only_in: Option<&Path>) -> Result<Found, String> {
+    let program = toolchain::find_program("pkg-config").ok_or("pkg-config is not on PATH")?;
+    let query = |flag: &str| {
+        let mut cmd = Command::new(&program);
+        if let Some(dir) = only_in {
+            cmd.env("PKG_CONFIG_LIBDIR", dir)
+                .env_remove("PKG_CONFIG_PATH");
+        }
+        let output = cmd
+            .arg(flag)
+            .arg(module)
+            .output()
+            .map_err(|err| format!("could not run pkg-config: {err}"))?;
+        if !output.status.success() {
+            return Err(format!("pkg-config has no module '{module}'"));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let words = |text: String| text.split_whitespace().map(String::from).collect();
+    Ok(Found {
+        provider: Provider::System,
+        version: Some(query("--modversion")?.trim().to_string()),
+        compile_args: words(query("--cflags")?),
+        link_args: words(query("--libs")?),
+    })
+}
+
+/// What `crust deps` prints: each dependency as declared or, given its
+/// `resolution`, how it was found and the flags it contributes.
+pub fn report(manifest: &ProjectManifest, resolution: Option<&Resolution>) -> String {
+    let width = manifest
+        .dependencies
+        .keys()
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for (name, spec) in &manifest.dependencies {
+        let module = module(name, spec);
+        let Some(resolution) = resolution else {
+            let version = spec
+                .version
+                .as_ref()
+                .map(|req| format!(" {req}"))
+                .unwrap_or_default();
+            let feature = spec.feature.to_possible_value().expect("feature value");
+            let fallback = spec
+                .fallback
+                .as_ref()
+                .map(|target| format!(", fallback '{target}'"))
+                .unwrap_or_default();
+            out += &format!(
+                "{name:<width$}  {} {module}{version}, {}{fallback}\n",
+                spec.method.name(),
+                feature.get_name()
+            );
+            continue;
+        };
+        let Some(found) = resolution.found.get(name) else {
+            let reason = resolution.missing.get(name).map_or("", String::as_str);
+            out += &format!("{name:<width$}  not used: {reason}\n");
+            continue;
+        };
+        out += &match &found.provider {
+            Provider::System => format!(
+                "{name:<width$}  system, {} {module} {}\n",
+                spec.method.name(),
+                found.version.as_deref().unwrap_or("(no version)")
+            ),
+            Provider::Fallback(target) => format!("{name:<width$}  fallback, target '{target}'\n"),
+        };
+        let indent = width + 2;
+        out += &format!(
+            "{:indent$}compile: {}\n",
+            "",
+            found.compile_flags(name).join(" ")
+        );
+        if !found.link_args.is_empty() {
+            out += &format!("{:indent$}link: {}\n", "", found.link_args.join(" "));
+        }
+    }
+    out
+}
+
+/// Fits the targets of `manifest` to `resolution`: targets requiring a
+/// missing dependency are removed, with every target depending on them, and
+/// targets using a dependency provided by a fallback depend on its target.
+pub fn apply(manifest: &mut ProjectManifest, resolution: &Resolution) -> Result<()> {
+    for target in &manifest.targets {
+        let Some(options) = target.options() else {
+            continue;
+        };
+        for name in options.external_deps.iter().chain(&options.requires) {
+            if !manifest.dependencies.contains_key(name) {
+                return Err(CrustError::UnknownDependency {
+                    target: target.name().to_string(),
+                    dependency: name.clone(),
+                }
+                .into());
+            }
+        }
+    }
+
+    let mut dropped: BTreeSet<String> = manifest
+        .targets
+        .iter()
+        .filter(|target| {
+            target.options().is_some_and(|options| {
+                options
+                    .requires
+                    .iter()
+                    .any(|name| resolution.missing.contains_key(name))
+            })
+        })
+        .map(|target| target.name().to_string())
+        .collect();
+    loop {
+        let before = dropped.len();
+        for target in &manifest.targets {
+            if target
+                .dependencies()
+                .iter()
+                .any(|dep| dropped.contains(dep))
+            {
+                dropped.insert(target.name().to_string());
+            }
+        }
+        if dropped.len() == before {
+            break;
+        }
+    }
+    for name in &dropped {
+        tracing::info!("Leaving out target '{name}': a dependency it requires is missing");
+    }
+    manifest
+        .targets
+        .retain(|target| !dropped.contains(target.name()));
+
+    for target in &mut manifest.targets {
+        let fallbacks: Vec<String> = target
+            .options()
+            .map(|options| {
+                options
+                    .external_deps
+                    .iter()
+                    .filter_map(|name| match &resolution.found.get(name)?.provider {
+                        Provider::Fallback(target) => Some(target.clone()),
+                        Provider::System => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let deps = target.dependencies_mut();
+        for fallback in fallbacks {
+            if !deps.contains(&fallback) {
+                deps.push(fallback);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn resolves_features_and_fallbacks() {
+        let mut manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[dependencies.zlib]
+fallback = "bundled_zlib"
+
+[dependencies.png]
+module = "libpng16"
+feature = "auto"
+
+[dependencies.ssl]
+version = ">=3"
+feature = "auto"
+
+[[targets]]
+type = "static_library"
+name = "bundled_zlib"
+sources = ["zlib/inflate.c"]
+
+[[targets]]
+type = "static_library"
+name = "images"
+sources = ["images.c"]
+external_deps = ["png", "zlib"]
+requires = ["png"]
+
+[[targets]]
+type = "executable"
+name = "viewer"
+sources = ["viewer.c"]
+deps = ["images"]
+
+[[targets]]
+type = "executable"
+name = "client"
+sources = ["client.c"]
+external_deps = ["ssl", "zlib"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let system = |version: &str| Found {
+            provider: Provider::System,
+            version: Some(version.into()),
+            compile_args: vec!["-I/opt/ssl".into()],
+            link_args: vec!["-lssl".into()],
+        };
+        let lookup = |name: &str, spec: &DependencySpec| match module(name, spec) {
+            "ssl" => Ok(system("3.0.13")),
+            module => Err(format!("no {module}")),
+        };
+
+        let defaults = Overrides::default();
+        let resolution = resolve_with(&manifest, &defaults, lookup).unwrap();
+        assert_eq!(
+            resolution.found["zlib"].provider,
+            Provider::Fallback("bundled_zlib".into())
+        );
+        assert_eq!(resolution.missing["png"], "no libpng16");
+        assert_eq!(
+            resolution.found["ssl"].compile_flags("ssl"),
+            ["-I/opt/ssl", "-DHAVE_SSL"]
+        );
+        assert_eq!(
+            report(&manifest, Some(&resolution)),
+            "png   not used: no libpng16\n\
+             ssl   system, pkg-config ssl 3.0.13\n\
+             \x20     compile: -I/opt/ssl -DHAVE_SSL\n\
+             \x20     link: -lssl\n\
+             zlib  fallback, target 'bundled_zlib'\n\
+             \x20     compile: -DHAVE_ZLIB\n"
+        );
+        let old = |_: &str, _: &DependencySpec| Ok(system("1.1.1w"));
+        let outdated = resolve_with(&manifest, &defaults, old).unwrap();
+        assert_eq!(
+            outdated.missing["ssl"],
+            "found version 1.1.1w, but >=3.0.0 is required"
+        );
+
+        apply(&mut manifest, &resolution).unwrap();
+        let names: Vec<&str> = manifest.targets.iter().map(|t| t.name()).collect();
+        assert_eq!(names, ["bundled_zlib", "client"]);
+        assert_eq!(manifest.targets[1].dependencies(), ["bundled_zlib"]);
+
+        let disabled = Overrides {
+            features: vec![("ssl".into(), Feature::Disabled)],
+            ..Overrides::default()
+        };
+        let resolution = resolve_with(&manifest, &disabled, lookup).unwrap();
+        assert_eq!(resolution.missing["ssl"], "disabled");
+
+        let enabled = Overrides {
+            features: vec![("png".into(), Feature::Enabled)],
+            ..Overrides::default()
+        };
+        let err = resolve_with(&manifest, &enabled, lookup).unwrap_err();
+        assert_eq!(CrustError::find(&err).unwrap().exit_code(), 4);
+
+        // zlib is installed, but its fallback can still be forced, or ruled
+        // out so that only the system library will do.
+        let everything = |_: &str, _: &DependencySpec| Ok(system("3.2"));
+        let forced = Overrides {
+            force_fallback_for: vec!["zlib".into()],
+            ..Overrides::default()
+        };
+        let resolution = resolve_with(&manifest, &forced, everything).unwrap();
+        assert_eq!(
+            resolution.found["zlib"].provider,
+            Provider::Fallback("bundled_zlib".into())
+        );
+        assert_eq!(resolution.found["ssl"].provider, Provider::System);
+        let no_fallback = Overrides {
+            wrap_mode: WrapMode::Nofallback,
+            ..Overrides::default()
+        };
+        let err = resolve_with(&manifest, &no_fallback, lookup).unwrap_err();
+        assert!(matches!(
+            CrustError::find(&err),
+            Some(CrustError::DependencyNotFound { name, .. }) if name == "zlib"
+        ));
+        let forced_ssl = Overrides {
+            force_fallback_for: vec!["ssl".into()],
+            ..Overrides::default()
+        };
+        let err = resolve_with(&manifest, &forced_ssl, lookup).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--force-fallback-for ssl: it has no fallback"
+        );
+
+        assert_eq!(parse_feature("ssl=auto"), Ok(("ssl".into(), Feature::Auto)));
+        assert!(parse_feature("ssl=maybe").is_err());
+        assert_eq!(have_macro("zlib-ng"), "HAVE_ZLIB_NG");
+    }
+    #[test]
+    fn reports_the_version_checks_of_resolved_dependencies() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[dependencies.zlib]
+version = ">=1.2.11"
+feature = "auto"
+
+[dependencies.ssl]
+version = ">=3, <4"
+feature = "auto"
+
+[dependencies.png]
+version = ">=1.6"
+feature = "auto"
+
+[dependencies.lzma]
+version = ">=5"
+feature = "auto"
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let lookup = |name: &str, _: &DependencySpec| {
+            let version = match name {
+                "zlib" => Some("1.3.1"),
+                "ssl" => Some("1.1.1w"),
+                "lzma" => None,
+                _ => return Err(format!("pkg-config has no module '{name}'")),
+            };
+            Ok(Found {
+                provider: Provider::System,
+                version: version.map(String::from),
+                compile_args: Vec::new(),
+                link_args: vec![format!("-l{name}")],
+            })
+        };
+        let resolution = resolve_with(&manifest, &Overrides::default(), lookup).unwrap();
+        assert_eq!(resolution.found.keys().collect::<Vec<_>>(), ["zlib"]);
+        assert_eq!(
+            report(&manifest, Some(&resolution)),
+            "lzma  not used: found no version, but >=5.0.0 is required\n\
+             png   not used: pkg-config has no module 'png'\n\
+             ssl   not used: found version 1.1.1w, but >=3.0.0, <4.0.0 is required\n\
+             zlib  system, pkg-config zlib 1.3.1\n\
+             \x20     compile: -DHAVE_ZLIB\n\
+             \x20     link: -lzlib\n"
+        );
+    }
+    #[test]
+    fn decides_fallbacks_by_wrap_mode() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[dependencies.zlib]
+fallback = "bundled_zlib"
+
+[dependencies.png]
+fallback = "bundled_png"
+
+[[targets]]
+type = "static_library"
+name = "bundled_zlib"
+sources = ["zlib/inflate.c"]
+
+[[targets]]
+type = "static_library"
+name = "bundled_png"
+sources = ["png/png.c"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        // zlib is installed, png is not.
+        let lookup = |name: &str, _: &DependencySpec| match name {
+            "zlib" => Ok(Found {
+                provider: Provider::System,
+                version: Some("1.3.1".into()),
+                compile_args: Vec::new(),
+                link_args: vec!["-lz".into()],
+            }),
+            _ => Err(format!("pkg-config has no module '{name}'")),
+        };
+        let providers = |overrides: Overrides| {
+            resolve_with(&manifest, &overrides, lookup).map(|resolution| {
+                let provider = |name: &str| resolution.found[name].provider.clone();
+                (provider("zlib"), provider("png"))
+            })
+        };
+        let fallback = |target: &str| Provider::Fallback(target.into());
+
+        assert_eq!(
+            providers(Overrides::default()).unwrap(),
+            (Provider::System, fallback("bundled_png"))
+        );
+        assert_eq!(
+            providers(Overrides {
+                wrap_mode: WrapMode::Forcefallback,
+                ..Overrides::default()
+            })
+            .unwrap(),
+            (fallback("bundled_zlib"), fallback("bundled_png"))
+        );
+        let err = providers(Overrides {
+            wrap_mode: WrapMode::Nofallback,
+            ..Overrides::default()
+        })
+        .unwrap_err();
+        assert!(matches!(
+            CrustError::find(&err),
+            Some(CrustError::DependencyNotFound { name, .. }) if name == "png"
+        ));
+
+        // Forcing the fallback of zlib wins over the installed library.
+        assert_eq!(
+            providers(Overrides {
+                force_fallback_for: vec!["zlib".into()],
+                ..Overrides::default()
+            })
+            .unwrap(),
+            (fallback("bundled_zlib"), fallback("bundled_png"))
+        );
+        let err = providers(Overrides {
+            force_fallback_for: vec!["zlib".into()],
+            wrap_mode: WrapMode::Nofallback,
+            ..Overrides::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--force-fallback-for zlib: fallbacks are off with --wrap-mode nofallback"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use super::{Found, Provider};
+use crate::toolchain;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Version and flags of the vcpkg `port`, read from the list of files
+/// `vcpkg install` recorded for it. The tree is `$VCPKG_ROOT`, or the
+/// directory of the `vcpkg` on `PATH`; the triplet is
+/// `$VCPKG_DEFAULT_TRIPLET`, or the host's.
+pub fn find(port: &str) -> Result<Found, String> {
+    let root = root().ok_or("vcpkg was not found: set VCPKG_ROOT or put vcpkg on PATH")?;
+    let triplet = env::var("VCPKG_DEFAULT_TRIPLET").unwrap_or_else(|_| host_triplet());
+    find_in(&root.join("installed"), port, &triplet)
+}
+
+fn find_in(installed: &Path, port: &str, triplet: &str) -> Result<Found, String> {
+    let not_installed =
+        || format!("vcpkg has not installed '{port}' (run `vcpkg install {port}:{triplet}`)");
+    let prefix = format!("{port}_");
+    let suffix = format!("_{triplet}.list");
+    let entries =
+        fs::read_dir(installed.join("vcpkg").join("info")).map_err(|_| not_installed())?;
+    let (list, version) = entries
+        .flatten()
+        .find_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let version = name.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+            Some((entry.path(), version.to_string()))
+        })
+        .ok_or_else(not_installed)?;
+    let files = fs::read_to_string(&list)
+        .map_err(|err| format!("could not read {}: {err}", list.display()))?;
+
+    let prefix = installed.join(triplet);
+    let lib_dir = format!("{triplet}/lib/");
+    let mut link_args = vec![format!("-L{}", prefix.join("lib").display())];
+    for file in files.lines().filter_map(|line| line.strip_prefix(&lib_dir)) {
+        if file.contains('/') {
+            continue;
+        }
+        let arg = match file.rsplit_once('.') {
+            Some((stem, "a" | "so" | "dylib")) => match stem.strip_prefix("lib") {
+                Some(name) => format!("-l{name}"),
+                None => continue,
+            },
+            Some((_, "lib")) => prefix.join("lib").join(file).display().to_string(),
+            _ => continue,
+        };
+        if !link_args.contains(&arg) {
+            link_args.push(arg);
+        }
+    }
+    Ok(Found {
+        provider: Provider::System,
+        version: Some(version),
+        compile_args: vec![format!("-I{}", prefix.join("include").display())],
+        link_args,
+    })
+}
+
+fn root() -> Option<PathBuf> {
+    if let Some(root) = env::var_os("VCPKG_ROOT") {
+        return Some(PathBuf::from(root));
+    }
+    let program = toolchain::find_program("vcpkg")?;
+    let program = fs::canonicalize(&program).unwrap_or(program);
+    program.parent().map(Path::to_path_buf)
+}
+
+/// vcpkg's default triplet for the machine crust runs on, such as
+/// `x64-linux` or `arm64-osx`.
+fn host_triplet() -> String {
+    let arch = match env::consts::ARCH {
+        "x86_64" => "x64",
+        "x86" => "x86",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        other => other,
+    };
+    let os = match env::consts::OS {
+        "macos" => "osx",
+        other => other,
+    };
+    format!("{arch}-{os}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_installed_port_from_list_file() {
+        let installed = tempfile::tempdir().unwrap();
+        let info = installed.path().join("vcpkg/info");
+        fs::create_dir_all(&info).unwrap();
+        fs::write(
+            info.join("zlib_1.3.1_x64-linux.list"),
+            "x64-linux/\nx64-linux/include/zlib.h\nx64-linux/lib/libz.a\n\
+             x64-linux/lib/pkgconfig/zlib.pc\nx64-linux/debug/lib/libz.a\n",
+        )
+        .unwrap();
+
+        let found = find_in(installed.path(), "zlib", "x64-linux").unwrap();
+        let prefix = installed.path().join("x64-linux");
+        assert_eq!(found.version.as_deref(), Some("1.3.1"));
+        assert_eq!(
+            found.compile_args,
+            [format!("-I{}", prefix.join("include").display())]
+        );
+        assert_eq!(
+            found.link_args,
+            [format!("-L{}", prefix.join("lib").display()), "-lz".into()]
+        );
+
+        let err = find_in(installed.path(), "zlib", "arm64-osx").unwrap_err();
+        assert!(err.contains("vcpkg install zlib:arm64-osx"), "{err}");
+    }
+}
@@ -0,0 +1,151 @@
+use crate::graph::{DependencyGraph, TargetKind};
+use crate::python;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Environment variable searched by the dynamic loader on this platform.
+pub fn library_path_var() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "DYLD_LIBRARY_PATH"
+    } else if cfg!(windows) {
+        "PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    }
+}
+
+/// Directories that must be prepended to search-path variables so binaries can
+/// be run straight from the build directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevEnv {
+    /// `(variable, directories)` pairs in the order they should be exported.
+    pub vars: Vec<(String, Vec<PathBuf>)>,
+}
+
+impl DevEnv {
+    pub fn from_graph(graph: &DependencyGraph, builddir: &Path) -> Result<Self> {
+        let builddir = std::path::absolute(builddir)
+            .with_context(|| format!("Failed to resolve {}", builddir.display()))?;
+        let mut bin_dirs = BTreeSet::new();
+        let mut lib_dirs = BTreeSet::new();
+        let mut python_dirs = BTreeSet::new();
+        for node in graph.nodes() {
+            if node.python.is_some() {
+                python_dirs.insert(builddir.join(python::MODULE_DIR));
+                continue;
+            }
+            let dirs = match node.kind {
+                TargetKind::Executable => &mut bin_dirs,
+                TargetKind::SharedLibrary => &mut lib_dirs,
+                _ => continue,
+            };
+            for output in node.outputs.iter().take(1) {
+                let path = builddir.join(output);
+                dirs.insert(path.parent().unwrap_or(&builddir).to_path_buf());
+            }
+        }
+
+        let lib_var = library_path_var();
+        let mut vars = vec![("PATH".to_string(), bin_dirs.into_iter().collect::<Vec<_>>())];
+        if lib_var == "PATH" {
+            vars[0].1.extend(lib_dirs);
+            vars[0].1.dedup();
+        } else if !lib_dirs.is_empty() {
+            vars.push((lib_var.to_string(), lib_dirs.into_iter().collect()));
+        }
+        vars.push(("PYTHONPATH".to_string(), python_dirs.into_iter().collect()));
+        vars.retain(|(_, dirs)| !dirs.is_empty());
+        Ok(DevEnv { vars })
+    }
+
+    /// Prepends the directories to the current values of each variable in the
+    /// environment of `cmd`.
+    pub fn apply(&self, cmd: &mut Command) -> Result<()> {
+        for (var, dirs) in &self.vars {
+            let existing = std::env::var_os(var).unwrap_or_default();
+            let joined =
+                std::env::join_paths(dirs.iter().cloned().chain(std::env::split_paths(&existing)))
+                    .with_context(|| format!("Failed to build {var} for the child process"))?;
+            cmd.env(var, joined);
+        }
+        Ok(())
+    }
+
+    /// POSIX shell snippet that prepends the directories to each variable.
+    pub fn render_sh(&self) -> String {
+        let mut script = String::new();
+        for (var, dirs) in &self.vars {
+            let joined = dirs
+                .iter()
+                .map(|d| d.display().to_string())
+                .collect::<Vec<_>>()
+                .join(":");
+            script.push_str(&format!(
+                "export {var}=\"{}${{{var}:+:${var}}}\"\n",
+                joined.replace('"', "\\\"")
+            ));
+        }
+        script
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
+
+    #[test]
+    fn exports_binary_and_library_directories() {
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::SharedLibrary {
+                    name: "math".into(),
+                    sources: vec!["math.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+                Target::Executable {
+                    name: "calc".into(),
+                    sources: vec!["main.c".into()],
+                    deps: vec!["math".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let env = DevEnv::from_graph(&graph, Path::new("/work/build")).unwrap();
+        let script = env.render_sh();
+
+        assert!(script.contains("export PATH=\"/work/build${PATH:+:$PATH}\""));
+        if library_path_var() != "PATH" {
+            let var = library_path_var();
+            assert!(script.contains(&format!("export {var}=\"/work/build${{{var}:+:${var}}}\"")));
+        }
+    }
+}
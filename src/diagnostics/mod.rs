@@ -0,0 +1,293 @@
+use std::fmt;
+use std::ops::AddAssign;
+
+/// Warnings and errors a target's commands printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub warnings: usize,
+    pub errors: usize,
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Counts) {
+        self.warnings += other.warnings;
+        self.errors += other.errors;
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}, {} {}",
+            self.warnings,
+            plural(self.warnings, "warning"),
+            self.errors,
+            plural(self.errors, "error")
+        )
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        word.to_string()
+    } else {
+        format!("{word}s")
+    }
+}
+
+/// `target` of the tracing events carrying parsed diagnostics. The console
+/// output skips them, since the compiler's own output is already shown.
+pub const EVENT_TARGET: &str = "crust::diagnostic";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// One warning or error reported by gcc, clang, MSVC, or rustc. Diagnostics
+/// of the driver or linker carry no location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+            for number in [self.line, self.column].into_iter().flatten() {
+                write!(f, "{number}:")?;
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "{}: {}", self.severity.as_str(), self.message)
+    }
+}
+
+/// Severity words after `<location>: `, the longer one first.
+const SEVERITIES: [(&str, Severity); 3] = [
+    ("fatal error", Severity::Error),
+    ("error", Severity::Error),
+    ("warning", Severity::Warning),
+];
+
+/// Parses the diagnostics in captured compiler `output`. Notes and the
+/// source excerpts below a diagnostic are skipped.
+pub fn parse(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut after_header = false;
+    for line in output.lines() {
+        // rustc puts the location on the line after the message.
+        if let Some(location) = line.trim_start().strip_prefix("--> ") {
+            if let Some(last) = diagnostics.last_mut().filter(|_| after_header) {
+                (last.file, last.line, last.column) = parse_location(location.trim());
+            }
+            after_header = false;
+            continue;
+        }
+        after_header = false;
+        if let Some(diagnostic) = parse_located(line) {
+            diagnostics.push(diagnostic);
+        } else if let Some(diagnostic) = parse_header(line) {
+            diagnostics.push(diagnostic);
+            after_header = true;
+        }
+    }
+    diagnostics
+}
+
+/// `file:line:col: warning: message` (gcc, clang) or `file(line,col): warning
+/// C4101: message` (MSVC). Tools without a position, such as `cc1:` or
+/// `LINK :`, give a diagnostic without location.
+fn parse_located(line: &str) -> Option<Diagnostic> {
+    for (at, _) in line.match_indices(": ") {
+        let after = &line[at + 2..];
+        for (word, severity) in SEVERITIES {
+            let Some(rest) = after.strip_prefix(word) else {
+                continue;
+            };
+            let message = match (rest.strip_prefix(':'), rest.strip_prefix(' ')) {
+                (Some(message), _) => message,
+                (None, Some(message)) if is_msvc_code(message) => message,
+                _ => continue,
+            };
+            let (file, line, column) = parse_location(&line[..at]);
+            return Some(Diagnostic {
+                file,
+                line,
+                column,
+                severity,
+                message: message.trim().to_string(),
+            });
+        }
+    }
+    None
+}
+
+/// Whether `text` starts with an MSVC diagnostic code such as `C4101:` or
+/// `LNK2019:`.
+fn is_msvc_code(text: &str) -> bool {
+    text.split_once(':').is_some_and(|(code, _)| {
+        code.starts_with(|c: char| c.is_ascii_uppercase())
+            && code.ends_with(|c: char| c.is_ascii_digit())
+            && code.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// A rustc header (`warning: message`, `error[E0425]: message`), whose
+/// location follows on the next line. rustc's closing tallies repeat what
+/// was already reported and are skipped.
+fn parse_header(line: &str) -> Option<Diagnostic> {
+    if line.starts_with("error: aborting due to")
+        || (line.starts_with("warning: ") && line.ends_with(" emitted"))
+    {
+        return None;
+    }
+    let (severity, rest) = [("error", Severity::Error), ("warning", Severity::Warning)]
+        .into_iter()
+        .find_map(|(word, severity)| Some((severity, line.strip_prefix(word)?)))?;
+    let rest = match rest.strip_prefix('[') {
+        Some(coded) => coded.split_once(']')?.1,
+        None => rest,
+    };
+    Some(Diagnostic {
+        file: None,
+        line: None,
+        column: None,
+        severity,
+        message: rest.strip_prefix(':')?.trim().to_string(),
+    })
+}
+
+/// File, line, and column of `src/a.c:3:9`, `src/a.c:3`, or `a.c(3,9)`.
+/// Without a line number the text names a tool rather than a file.
+fn parse_location(text: &str) -> (Option<String>, Option<u32>, Option<u32>) {
+    if let Some((file, position)) = text
+        .strip_suffix(')')
+        .and_then(|inner| inner.rsplit_once('('))
+    {
+        let mut numbers = position.split(',').map(|n| n.trim().parse().ok());
+        let line = numbers.next().flatten();
+        let column = numbers.next().flatten();
+        return (line.map(|_| file.trim().to_string()), line, column);
+    }
+    let mut file = text;
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        let Some((head, tail)) = file.rsplit_once(':') else {
+            break;
+        };
+        let Ok(number) = tail.parse::<u32>() else {
+            break;
+        };
+        numbers.insert(0, number);
+        file = head;
+    }
+    let line = numbers.first().copied();
+    (
+        line.map(|_| file.to_string()),
+        line,
+        numbers.get(1).copied(),
+    )
+}
+
+/// Counts `diagnostics` by severity.
+pub fn counts(diagnostics: &[Diagnostic]) -> Counts {
+    let mut counts = Counts::default();
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Warning => counts.warnings += 1,
+            Severity::Error => counts.errors += 1,
+        }
+    }
+    counts
+}
+
+/// Emits one event per diagnostic of `target` under [`EVENT_TARGET`], with
+/// the location and severity as fields next to the message, for
+/// `--log-format json`.
+pub fn emit(target: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        let file = diagnostic.file.as_deref();
+        let severity = diagnostic.severity.as_str();
+        match diagnostic.severity {
+            Severity::Warning => tracing::warn!(
+                target: EVENT_TARGET,
+                target_name = target,
+                file,
+                line = diagnostic.line,
+                column = diagnostic.column,
+                severity,
+                "{}",
+                diagnostic.message
+            ),
+            Severity::Error => tracing::error!(
+                target: EVENT_TARGET,
+                target_name = target,
+                file,
+                line = diagnostic.line,
+                column = diagnostic.column,
+                severity,
+                "{}",
+                diagnostic.message
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gcc_msvc_and_rustc_diagnostics() {
+        let output = "\
+src/a.c: In function 'main':
+src/a.c:3:9: warning: unused variable 'x' [-Wunused-variable]
+    3 |     int x;
+      |         ^
+src/b.c:7:1: error: expected ';' before '}' token
+include/c.h:1:10: fatal error: missing.h: No such file or directory
+src\\d.c(12,5): warning C4101: 'y': unreferenced local variable
+LINK : fatal error LNK1181: cannot open input file 'x.obj'
+warning: unused import: `std::fs`
+ --> src/lib.rs:1:5
+error[E0425]: cannot find value `z` in this scope
+warning: 1 warning emitted
+error: aborting due to 1 previous error
+2 warnings generated.
+";
+        let diagnostics = parse(output);
+        let lines: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "src/a.c:3:9: warning: unused variable 'x' [-Wunused-variable]",
+                "src/b.c:7:1: error: expected ';' before '}' token",
+                "include/c.h:1:10: error: missing.h: No such file or directory",
+                "src\\d.c:12:5: warning: C4101: 'y': unreferenced local variable",
+                "error: LNK1181: cannot open input file 'x.obj'",
+                "src/lib.rs:1:5: warning: unused import: `std::fs`",
+                "error: cannot find value `z` in this scope",
+            ]
+        );
+        assert_eq!(diagnostics[3].column, Some(5));
+        assert_eq!(counts(&diagnostics).to_string(), "3 warnings, 4 errors");
+        assert_eq!(counts(&[]).to_string(), "0 warnings, 0 errors");
+    }
+}
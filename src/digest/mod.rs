@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Hex-encoded SHA-256 of `bytes`.
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+/// Hex-encoded SHA-256 of the file at `path`, streamed in fixed-size chunks.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Every file below `dir`, recursively and sorted, for inputs that name a
+/// whole tree. Symlinks to directories are followed, each directory once,
+/// so a link back up the tree does not loop.
+pub fn tree_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if !visited.insert(fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone())) {
+            continue;
+        }
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            if entry.path().is_dir() {
+                pending.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Hex-encoded SHA-256 over the sorted relative paths, modification times,
+/// and sizes of the files below `dir`. Adding, removing, renaming, or
+/// rewriting a file anywhere in the tree changes it without reading any
+/// file's content.
+pub fn tree_digest(dir: &Path) -> Result<String> {
+    let mut listing = String::new();
+    for file in tree_files(dir)? {
+        let meta =
+            fs::metadata(&file).with_context(|| format!("Failed to inspect {}", file.display()))?;
+        let mtime = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |age| age.as_nanos());
+        let relative = file.strip_prefix(dir).unwrap_or(&file);
+        listing.push_str(&format!(
+            "{} {} {}\n",
+            relative.display(),
+            mtime,
+            meta.len()
+        ));
+    }
+    Ok(sha256_bytes(listing.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_bytes_and_files_identically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, b"abc").unwrap();
+        let expected = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_bytes(b"abc"), expected);
+        assert_eq!(sha256_file(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn digests_trees_by_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("assets/icons")).unwrap();
+        fs::write(dir.path().join("assets/logo.svg"), "<svg/>").unwrap();
+        fs::write(dir.path().join("assets/icons/a.png"), "png").unwrap();
+        let assets = dir.path().join("assets");
+
+        assert_eq!(
+            tree_files(&assets).unwrap(),
+            vec![assets.join("icons/a.png"), assets.join("logo.svg")]
+        );
+        let before = tree_digest(&assets).unwrap();
+        assert_eq!(tree_digest(&assets).unwrap(), before);
+        fs::write(assets.join("icons/b.png"), "png").unwrap();
+        assert_ne!(tree_digest(&assets).unwrap(), before);
+
+        // Linked directories are read once, and a link back up ends there.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&assets, assets.join("icons/up")).unwrap();
+            assert_eq!(tree_files(&assets).unwrap().len(), 3);
+        }
+    }
+}
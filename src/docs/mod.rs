@@ -0,0 +1,165 @@
+use crate::compdb;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the generated Doxygen configuration inside a docs target's
+/// output directory.
+pub const DOXYFILE: &str = "Doxyfile";
+
+/// Manifest settings that end up in a docs target's Doxyfile.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DocsSettings {
+    pub project: String,
+    pub version: Option<String>,
+    /// Extra Doxyfile keys; they are written last so they override the
+    /// defaults crust picks.
+    pub doxygen: BTreeMap<String, String>,
+}
+
+/// Renders a Doxyfile documenting `inputs` (relative to `manifest_dir`) into
+/// `output_dir`, which receives the `html/` tree.
+pub fn doxyfile(
+    settings: &DocsSettings,
+    manifest_dir: &Path,
+    inputs: &[String],
+    output_dir: &Path,
+) -> String {
+    let input = inputs
+        .iter()
+        .map(|dir| quote(&manifest_dir.join(dir).to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut lines = vec![
+        "# Generated by crust from the manifest; edit the docs target instead.".to_string(),
+        format!("PROJECT_NAME = {}", quote(&settings.project)),
+        format!(
+            "PROJECT_NUMBER = {}",
+            quote(settings.version.as_deref().unwrap_or(""))
+        ),
+        format!("INPUT = {input}"),
+        "RECURSIVE = YES".into(),
+        format!(
+            "OUTPUT_DIRECTORY = {}",
+            quote(&output_dir.to_string_lossy())
+        ),
+        "GENERATE_HTML = YES".into(),
+        "GENERATE_LATEX = NO".into(),
+        "QUIET = YES".into(),
+    ];
+    lines.extend(
+        settings
+            .doxygen
+            .iter()
+            .map(|(key, value)| format!("{key} = {value}")),
+    );
+    lines.join("\n") + "\n"
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Files Doxygen reads from `inputs`: C/C++ headers and sources plus
+/// Markdown and `.dox` pages, found recursively below each input directory.
+/// Hidden directories are skipped so a build directory named `.build` or a
+/// VCS checkout does not count.
+pub fn input_files(manifest_dir: &Path, inputs: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        collect(&manifest_dir.join(input), &mut files)?;
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    if !path.is_dir() {
+        return Ok(());
+    }
+    let entries =
+        fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            collect(&path, files)?;
+        } else if is_documented(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_documented(path: &Path) -> bool {
+    compdb::is_c_family(path)
+        || matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("h" | "hh" | "hpp" | "hxx" | "h++" | "inl" | "md" | "dox")
+        )
+}
+
+/// Writes `content` to `path` only when it differs, so the file's mtime
+/// changes exactly when the configuration does.
+pub fn write_if_changed(path: &Path, content: &str) -> Result<()> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn renders_doxyfile_and_tracks_documented_files() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("include/demo")).unwrap();
+        fs::create_dir_all(dir.path().join("include/.cache")).unwrap();
+        fs::write(dir.path().join("include/demo/api.h"), "").unwrap();
+        fs::write(dir.path().join("include/notes.txt"), "").unwrap();
+        fs::write(dir.path().join("include/.cache/stale.h"), "").unwrap();
+        fs::write(dir.path().join("README.md"), "").unwrap();
+
+        let files = input_files(dir.path(), &["include".into(), "README.md".into()]).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("README.md"),
+                dir.path().join("include/demo/api.h"),
+            ]
+        );
+
+        let settings = DocsSettings {
+            project: "demo".into(),
+            version: Some("1.2".into()),
+            doxygen: BTreeMap::from([("EXTRACT_ALL".into(), "YES".into())]),
+        };
+        let content = doxyfile(
+            &settings,
+            Path::new("/work"),
+            &["include".into()],
+            Path::new("/work/build/docs"),
+        );
+        assert!(content.contains("PROJECT_NAME = \"demo\"\n"));
+        assert!(content.contains("PROJECT_NUMBER = \"1.2\"\n"));
+        assert!(content.contains("INPUT = \"/work/include\"\n"));
+        assert!(content.contains("OUTPUT_DIRECTORY = \"/work/build/docs\"\n"));
+        assert!(content.ends_with("EXTRACT_ALL = YES\n"));
+    }
+}
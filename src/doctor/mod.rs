@@ -0,0 +1,254 @@
+use crate::toolchain::{find_program, program_version};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A tool crust may invoke, with guidance shown when it cannot be found.
+struct ToolSpec {
+    name: &'static str,
+    required: bool,
+    purpose: &'static str,
+}
+
+const TOOLS: &[ToolSpec] = &[
+    ToolSpec {
+        name: "cc",
+        required: true,
+        purpose: "C compiler and linker used by the native backend",
+    },
+    ToolSpec {
+        name: "c++",
+        required: false,
+        purpose: "C++ compiler for C++ sources",
+    },
+    ToolSpec {
+        name: "ar",
+        required: true,
+        purpose: "archiver used for static libraries",
+    },
+    ToolSpec {
+        name: "ninja",
+        required: false,
+        purpose: "needed to run files generated by --backend ninja",
+    },
+    ToolSpec {
+        name: "make",
+        required: false,
+        purpose: "needed to run files generated by --backend make",
+    },
+    ToolSpec {
+        name: "clang-tidy",
+        required: false,
+        purpose: "default linter for crust lint",
+    },
+    ToolSpec {
+        name: "clang-format",
+        required: false,
+        purpose: "default formatter for crust fmt",
+    },
+    ToolSpec {
+        name: "doxygen",
+        required: false,
+        purpose: "generates documentation for docs targets",
+    },
+    ToolSpec {
+        name: "msgfmt",
+        required: false,
+        purpose: "compiles translations of gettext targets",
+    },
+    ToolSpec {
+        name: "xgettext",
+        required: false,
+        purpose: "extracts strings for crust i18n",
+    },
+    ToolSpec {
+        name: "scdoc",
+        required: false,
+        purpose: "converts .scd sources of man targets",
+    },
+    ToolSpec {
+        name: "asciidoctor",
+        required: false,
+        purpose: "converts .adoc sources of man targets",
+    },
+    ToolSpec {
+        name: "pandoc",
+        required: false,
+        purpose: "converts .md and .rst sources of man targets",
+    },
+    ToolSpec {
+        name: "llvm-bolt",
+        required: false,
+        purpose: "post-link optimizer for executables that set bolt",
+    },
+    ToolSpec {
+        name: "gfortran",
+        required: false,
+        purpose: "compiles and links Fortran sources",
+    },
+    ToolSpec {
+        name: "ldc2",
+        required: false,
+        purpose: "compiles D sources (dmd also works)",
+    },
+    ToolSpec {
+        name: "zig",
+        required: false,
+        purpose: "compiles Zig sources",
+    },
+    ToolSpec {
+        name: "nvcc",
+        required: false,
+        purpose: "compiles CUDA sources",
+    },
+    ToolSpec {
+        name: "rustc",
+        required: false,
+        purpose: "compiles rust_executable and rust_library targets",
+    },
+    ToolSpec {
+        name: "curl",
+        required: false,
+        purpose: "downloads the files of fetch targets",
+    },
+    ToolSpec {
+        name: "dpkg-deb",
+        required: false,
+        purpose: "builds packages for crust package --format deb",
+    },
+    ToolSpec {
+        name: "rpmbuild",
+        required: false,
+        purpose: "builds packages for crust package --format rpm",
+    },
+    ToolSpec {
+        name: "wix",
+        required: false,
+        purpose: "builds installers for crust package --format msi",
+    },
+    ToolSpec {
+        name: "pkg-config",
+        required: false,
+        purpose: "used to resolve external dependencies",
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct ToolCheck {
+    pub name: String,
+    pub required: bool,
+    pub purpose: String,
+    pub path: Option<PathBuf>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DoctorReport {
+    pub tools: Vec<ToolCheck>,
+    /// Outcome of compiling, linking, and running a trivial C program.
+    pub compile_check: Result<(), String>,
+}
+
+impl DoctorReport {
+    /// Number of failed checks that would prevent a native build.
+    pub fn problems(&self) -> usize {
+        let missing = self
+            .tools
+            .iter()
+            .filter(|tool| tool.required && tool.path.is_none())
+            .count();
+        missing + usize::from(self.compile_check.is_err())
+    }
+}
+
+/// Probes the host toolchain and attempts a trivial compile and link in
+/// `scratch_dir`, which is created if needed and removed afterwards.
+pub fn run(scratch_dir: &Path) -> DoctorReport {
+    let tools = TOOLS
+        .iter()
+        .map(|spec| {
+            let path = find_program(spec.name);
+            let version = path.as_deref().and_then(program_version);
+            ToolCheck {
+                name: spec.name.to_string(),
+                required: spec.required,
+                purpose: spec.purpose.to_string(),
+                path,
+                version,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let compile_check = if tools.iter().any(|t| t.name == "cc" && t.path.is_some()) {
+        let result = try_compile_and_link(scratch_dir).map_err(|err| format!("{:#}", err));
+        let _ = fs::remove_dir_all(scratch_dir);
+        result
+    } else {
+        Err("skipped because 'cc' was not found".to_string())
+    };
+
+    DoctorReport {
+        tools,
+        compile_check,
+    }
+}
+
+fn try_compile_and_link(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let source = dir.join("doctor.c");
+    let object = dir.join("doctor.o");
+    let binary = dir.join("doctor");
+    fs::write(&source, "int main(void) { return 0; }\n")?;
+
+    run_step(
+        Command::new("cc")
+            .arg("-c")
+            .arg(&source)
+            .arg("-o")
+            .arg(&object),
+        "compile",
+    )?;
+    run_step(
+        Command::new("cc").arg(&object).arg("-o").arg(&binary),
+        "link",
+    )?;
+    run_step(&mut Command::new(&binary), "run the linked program")?;
+    Ok(())
+}
+
+fn run_step(cmd: &mut Command, step: &str) -> Result<()> {
+    let output = cmd
+        .output()
+        .with_context(|| format!("Failed to {}", step))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("Failed to {}: {}", step, stderr.trim()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_missing_required_tools_and_compile_failures() {
+        let tool = |name: &str, required: bool, found: bool| ToolCheck {
+            name: name.into(),
+            required,
+            purpose: String::new(),
+            path: found.then(|| PathBuf::from(format!("/usr/bin/{name}"))),
+            version: None,
+        };
+        let report = DoctorReport {
+            tools: vec![
+                tool("cc", true, false),
+                tool("ar", true, true),
+                tool("ninja", false, false),
+            ],
+            compile_check: Err("skipped".into()),
+        };
+        assert_eq!(report.problems(), 2);
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt::Write;
+
+/// Stable C identifier for an embedded file: `crust_embed_` followed by the
+/// manifest-relative path with every non-alphanumeric character replaced by `_`.
+pub fn symbol_name(path: &str) -> String {
+    let mut symbol = String::from("crust_embed_");
+    symbol.extend(
+        path.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }),
+    );
+    symbol
+}
+
+/// Renders a C translation unit exposing `bytes` as `<symbol>[]` and its
+/// length as `<symbol>_size`.
+pub fn render_c_source(symbol: &str, bytes: &[u8]) -> String {
+    let mut source = String::with_capacity(bytes.len() * 6 + 256);
+    source.push_str("/* Auto-generated by crust. Do not edit. */\n");
+    source.push_str("#include <stddef.h>\n\n");
+    let _ = writeln!(source, "const unsigned char {symbol}[] = {{");
+    for chunk in bytes.chunks(16) {
+        source.push_str("   ");
+        for byte in chunk {
+            let _ = write!(source, " 0x{byte:02x},");
+        }
+        source.push('\n');
+    }
+    if bytes.is_empty() {
+        // Zero-length arrays are not valid C; keep a terminator byte instead.
+        source.push_str("    0x00\n");
+    }
+    source.push_str("};\n");
+    let _ = writeln!(source, "const size_t {symbol}_size = {};", bytes.len());
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_array_and_size_with_stable_symbol() {
+        let symbol = symbol_name("assets/logo-1.png");
+        assert_eq!(symbol, "crust_embed_assets_logo_1_png");
+
+        let source = render_c_source(&symbol, b"\x01\xff");
+        assert!(source.contains("const unsigned char crust_embed_assets_logo_1_png[] = {"));
+        assert!(source.contains(" 0x01, 0xff,"));
+        assert!(source.contains("const size_t crust_embed_assets_logo_1_png_size = 2;"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Structured failures that callers may want to react to individually.
+///
+/// Errors still travel through `anyhow::Result`; wrap a `CrustError` with
+/// `anyhow::Error::from` (or `?`) and recover it with [`CrustError::find`].
+#[derive(Debug)]
+pub enum CrustError {
+    /// Invalid command-line usage that clap could not catch on its own.
+    InvalidArgument { message: String },
+    /// The manifest file could not be read from disk.
+    ManifestRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The manifest was read but is not valid crust TOML.
+    ManifestParse { path: PathBuf, message: String },
+    /// A target sets options that are not valid for it.
+    InvalidTarget { target: String, message: String },
+    /// The manifest's `crust_version` does not admit this crust release.
+    UnsupportedVersion { required: String, current: String },
+    /// Two targets share the same name.
+    DuplicateTarget { name: String },
+    /// A target lists a dependency that is not declared.
+    UnknownDependency { target: String, dependency: String },
+    /// The dependency graph contains a cycle through `target`.
+    Cycle { target: String },
+    /// A required tool (compiler, archiver, shell) was not found on `PATH`.
+    CompilerMissing { program: String },
+    /// An enabled `[dependencies]` entry was not found and has no fallback.
+    DependencyNotFound { name: String, reason: String },
+    /// A spawned command exited unsuccessfully while building `target`.
+    CommandFailed {
+        target: String,
+        action: String,
+        exit: Option<i32>,
+    },
+    /// Building `target` panicked, a bug in crust; the other targets were
+    /// not affected.
+    TaskPanicked { target: String, message: String },
+}
+
+impl CrustError {
+    /// Process exit code reported by the CLI for this failure category.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CrustError::InvalidArgument { .. } => 2,
+            CrustError::ManifestRead { .. }
+            | CrustError::ManifestParse { .. }
+            | CrustError::InvalidTarget { .. }
+            | CrustError::UnsupportedVersion { .. }
+            | CrustError::DuplicateTarget { .. }
+            | CrustError::UnknownDependency { .. }
+            | CrustError::Cycle { .. } => 3,
+            CrustError::CompilerMissing { .. } | CrustError::DependencyNotFound { .. } => 4,
+            CrustError::CommandFailed { .. } => 5,
+            CrustError::TaskPanicked { .. } => 1,
+        }
+    }
+
+    /// Finds the first `CrustError` in an `anyhow` error chain.
+    pub fn find(err: &anyhow::Error) -> Option<&CrustError> {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<CrustError>())
+    }
+}
+
+impl fmt::Display for CrustError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrustError::InvalidArgument { message } => write!(f, "{}", message),
+            CrustError::ManifestRead { path, .. } => {
+                write!(f, "Failed to read manifest at {}", path.display())
+            }
+            CrustError::ManifestParse { path, message } => {
+                write!(
+                    f,
+                    "Invalid manifest TOML at {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+            CrustError::InvalidTarget { target, message } => {
+                write!(f, "Invalid target '{}': {}", target, message)
+            }
+            CrustError::UnsupportedVersion { required, current } => write!(
+                f,
+                "This project requires crust {}, but this is crust {}; upgrade crust to build it",
+                required, current
+            ),
+            CrustError::DuplicateTarget { name } => write!(f, "Duplicate target name: {}", name),
+            CrustError::UnknownDependency { target, dependency } => write!(
+                f,
+                "Unknown dependency '{}' referenced by '{}'",
+                dependency, target
+            ),
+            CrustError::Cycle { target } => write!(f, "Cycle detected involving '{}'", target),
+            CrustError::CompilerMissing { program } => {
+                write!(
+                    f,
+                    "Required program '{}' was not found on PATH (run `crust doctor` to check your toolchain)",
+                    program
+                )
+            }
+            CrustError::DependencyNotFound { name, reason } => write!(
+                f,
+                "Required dependency '{}' was not found: {} (set `feature = \"auto\"` or a `fallback` to build without it)",
+                name, reason
+            ),
+            CrustError::CommandFailed {
+                target,
+                action,
+                exit,
+            } => {
+                write!(f, "{} failed for target '{}'", action, target)?;
+                match exit {
+                    Some(code) => write!(f, " (exit code {})", code),
+                    None => write!(f, " (terminated by signal)"),
+                }
+            }
+            CrustError::TaskPanicked { target, message } => write!(
+                f,
+                "Internal error while building target '{}': {} (this is a bug in crust)",
+                target, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CrustError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrustError::ManifestRead { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn finds_error_through_context_and_maps_exit_code() {
+        let err: anyhow::Result<()> = Err(CrustError::CommandFailed {
+            target: "app".into(),
+            action: "Linking".into(),
+            exit: Some(1),
+        }
+        .into());
+        let err = err.context("while building").unwrap_err();
+
+        let found = CrustError::find(&err).expect("typed error in chain");
+        assert_eq!(found.exit_code(), 5);
+        assert_eq!(
+            found.to_string(),
+            "Linking failed for target 'app' (exit code 1)"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{BTreeSet, HashSet};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tracing::debug;
+
+/// Process ids of the commands running in any build of this process, killed
+/// with their process groups when crust is interrupted.
+static RUNNING: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Commands of a build, each started in its own process group on Unix so
+/// that killing it also kills the processes it started, such as the
+/// compilers of a shell script. Once the build is cancelled, by a watchdog
+/// or by the first failure of a `--fail-fast` build, every command still
+/// running is killed and no new one starts.
+#[derive(Debug, Default)]
+pub struct Cancellation {
+    cancelled: AtomicBool,
+    /// Set by `--fail-fast`: the first failure cancels the build.
+    fail_fast: bool,
+    /// Process ids of the running commands, each leading its own process
+    /// group on Unix.
+    running: Mutex<HashSet<u32>>,
+}
+
+impl Cancellation {
+    /// Commands of a build that the first failure cancels.
+    pub fn fail_fast() -> Self {
+        Cancellation {
+            fail_fast: true,
+            ..Cancellation::default()
+        }
+    }
+
+    /// Commands of the next build, cancelled like this one's but not yet.
+    pub fn renewed(&self) -> Self {
+        Cancellation {
+            fail_fast: self.fail_fast,
+            ..Cancellation::default()
+        }
+    }
+
+    /// Whether the build was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Sets `cmd` up so that killing it also kills what it starts: on Unix
+    /// it leads a new process group, on Windows its process tree is killed.
+    pub fn prepare(&self, cmd: &mut Command) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+        #[cfg(not(unix))]
+        let _ = cmd;
+    }
+
+    /// Records the spawned command `pid`, killing it right away when the
+    /// build was cancelled while it started.
+    pub fn register(&self, pid: u32) {
+        let mut running = self.running.lock().expect("cancellation mutex poisoned");
+        running.insert(pid);
+        RUNNING.lock().expect("running mutex poisoned").insert(pid);
+        if self.is_cancelled() {
+            kill_group(pid);
+        }
+    }
+
+    /// Forgets `pid` once it exited.
+    pub fn unregister(&self, pid: u32) {
+        self.running
+            .lock()
+            .expect("cancellation mutex poisoned")
+            .remove(&pid);
+        RUNNING.lock().expect("running mutex poisoned").remove(&pid);
+    }
+
+    /// Records that command `pid` failed: whatever it left running in its
+    /// process group is killed, and a `--fail-fast` build is cancelled.
+    /// The command has exited, so its id is only signalled while it is
+    /// still that of a process group of ours.
+    pub fn failed(&self, pid: u32) {
+        if is_our_group(pid) {
+            kill_group(pid);
+        }
+        if self.fail_fast {
+            self.cancel();
+        }
+    }
+
+    /// Cancels a `--fail-fast` build after a failure outside of commands.
+    pub fn target_failed(&self) {
+        if self.fail_fast {
+            self.cancel();
+        }
+    }
+
+    /// Cancels the build, killing every running command and its children.
+    pub fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let running = self.running.lock().expect("cancellation mutex poisoned");
+        debug!(commands = running.len(), "cancelling running commands");
+        for pid in running.iter() {
+            kill_group(*pid);
+        }
+    }
+}
+
+/// Kills every command running in any build together with its process
+/// group, for Ctrl-C, since commands in a group of their own do not get the
+/// terminal's interrupt.
+pub fn interrupt() {
+    let running = RUNNING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for pid in running.iter() {
+        kill_group(*pid);
+    }
+}
+
+/// Whether `pgid` is the id of a process group with a member in crust's
+/// session. A group lives on while any member does; once all are gone its
+/// id may be reused, by a group of another session among others.
+#[cfg(unix)]
+fn is_our_group(pgid: u32) -> bool {
+    let Ok(output) = Command::new("ps")
+        .args(["-A", "-o", "pid=,pgid=,sess="])
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return false;
+    };
+    let processes: Vec<Vec<u32>> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            line.split_whitespace()
+                .filter_map(|field| field.parse().ok())
+                .collect()
+        })
+        .filter(|fields: &Vec<u32>| fields.len() == 3)
+        .collect();
+    let ours = std::process::id();
+    let Some(session) = processes.iter().find(|p| p[0] == ours).map(|p| p[2]) else {
+        return false;
+    };
+    processes.iter().any(|p| p[1] == pgid && p[2] == session)
+}
+
+/// On Windows the caller still holds the exited command's handle, which
+/// keeps its id from being reused.
+#[cfg(not(unix))]
+fn is_our_group(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn kill_group(pid: u32) {
+    let _ = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{pid}")])
+        .stderr(Stdio::null())
+        .status();
+}
+
+#[cfg(windows)]
+fn kill_group(pid: u32) {
+    let _ = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+#[cfg(not(any(unix, windows)))]
+fn kill_group(_pid: u32) {}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::executor::command::run_command;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn kills_commands_running_or_started_after_cancelling() {
+        let cancel = Cancellation::default();
+        let spawn = || {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "sleep 30; true"]);
+            cancel.prepare(&mut cmd);
+            let child = cmd.spawn().unwrap();
+            cancel.register(child.id());
+            child
+        };
+        let start = Instant::now();
+        let running = spawn();
+        cancel.cancel();
+        assert!(cancel.is_cancelled());
+        let late = spawn();
+        for child in [running, late] {
+            let output = child.wait_with_output().unwrap();
+            assert!(!output.status.success());
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn kills_what_a_failed_command_left_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("child.pid");
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(format!(
+            "sleep 30 & echo $! > '{}'; exit 3",
+            pid_file.display()
+        ));
+        let start = Instant::now();
+        assert!(run_command(&mut cmd, "leaky", "Test", &Cancellation::default()).is_err());
+        // The child held stderr open, so the command returned only once it
+        // was killed.
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let child = fs::read_to_string(&pid_file).unwrap();
+        let alive = || {
+            let output = Command::new("ps")
+                .args(["-o", "stat=", "-p", child.trim()])
+                .output()
+                .unwrap();
+            let state = String::from_utf8_lossy(&output.stdout);
+            output.status.success() && !state.trim().starts_with('Z')
+        };
+        while alive() && start.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!alive(), "child {} still runs", child.trim());
+    }
+
+    #[test]
+    fn recognizes_process_groups_of_this_session() {
+        let cancel = Cancellation::default();
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        cancel.prepare(&mut cmd);
+        let mut child = cmd.spawn().unwrap();
+        assert!(is_our_group(child.id()));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(!is_our_group(child.id()));
+    }
+}
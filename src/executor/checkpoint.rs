@@ -0,0 +1,163 @@
+use crate::digest::{sha256_bytes, sha256_file, tree_digest};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+
+/// File in the build directory holding the progress of an unfinished build.
+pub const CHECKPOINT_FILE: &str = ".crust_checkpoint";
+
+/// One completed target, stored as a JSON line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    target: String,
+    fingerprint: String,
+    /// Outputs with the SHA-256 they had when the target finished; `None`
+    /// for directories.
+    outputs: Vec<(PathBuf, Option<String>)>,
+}
+
+/// Progress of a build that has not finished yet. Every completed target is
+/// appended to [`CHECKPOINT_FILE`] as soon as it finishes, so an interrupted
+/// build leaves behind the targets it got through. The next build skips a
+/// recorded target when its fingerprint is unchanged and its outputs still
+/// hash the same, without looking at modification times. A build that
+/// completes removes the file.
+pub struct Checkpoint {
+    path: PathBuf,
+    completed: HashMap<String, Entry>,
+    file: Mutex<File>,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint in `out_dir`, loading what an interrupted build
+    /// recorded. A line cut short by the interruption is ignored.
+    pub fn open(out_dir: &Path) -> Result<Self> {
+        let path = out_dir.join(CHECKPOINT_FILE);
+        let completed: HashMap<String, Entry> = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+            .map(|entry| (entry.target.clone(), entry))
+            .collect();
+        if !completed.is_empty() {
+            info!(
+                targets = completed.len(),
+                "Resuming interrupted build; {} targets were already complete",
+                completed.len()
+            );
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Checkpoint {
+            path,
+            completed,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Fingerprint of a target's work: its command lines and the content of
+    /// every input file or directory tree.
+    pub fn fingerprint(command_digest: &str, inputs: &[PathBuf]) -> String {
+        let mut text = command_digest.to_string();
+        for input in inputs {
+            let hash = if input.is_dir() {
+                tree_digest(input)
+            } else {
+                sha256_file(input)
+            };
+            let hash = hash.unwrap_or_else(|_| "-".into());
+            text.push_str(&format!("\n{} {}", input.display(), hash));
+        }
+        sha256_bytes(text.as_bytes())
+    }
+
+    /// The outputs of `target` when it completed in the interrupted build
+    /// with the same `fingerprint` and its outputs are unchanged since.
+    pub fn completed(&self, target: &str, fingerprint: &str) -> Option<Vec<PathBuf>> {
+        let entry = self.completed.get(target)?;
+        let unchanged = entry.fingerprint == fingerprint
+            && entry.outputs.iter().all(|(path, hash)| match hash {
+                Some(hash) => sha256_file(path).is_ok_and(|current| current == *hash),
+                None => path.exists(),
+            });
+        unchanged.then(|| entry.outputs.iter().map(|(path, _)| path.clone()).collect())
+    }
+
+    /// Records that `target` finished with `outputs`.
+    pub fn record(&self, target: &str, fingerprint: &str, outputs: &[PathBuf]) -> Result<()> {
+        let outputs = outputs
+            .iter()
+            .map(|output| {
+                let hash = if output.is_file() {
+                    Some(sha256_file(output)?)
+                } else {
+                    None
+                };
+                Ok((output.clone(), hash))
+            })
+            .collect::<Result<_>>()?;
+        let entry = Entry {
+            target: target.to_string(),
+            fingerprint: fingerprint.to_string(),
+            outputs,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = self.file.lock().expect("checkpoint mutex poisoned");
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.flush())
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Removes the checkpoint once the build has completed.
+    pub fn finish(self) -> Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_targets_whose_outputs_are_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("schema.json");
+        let output = dir.path().join("schema.c");
+        fs::write(&input, "{}").unwrap();
+        fs::write(&output, "int x;").unwrap();
+        let fingerprint = Checkpoint::fingerprint("gen", std::slice::from_ref(&input));
+
+        let checkpoint = Checkpoint::open(dir.path()).unwrap();
+        checkpoint
+            .record("gen", &fingerprint, std::slice::from_ref(&output))
+            .unwrap();
+        drop(checkpoint);
+
+        // The build was interrupted; the next one picks up the record.
+        let checkpoint = Checkpoint::open(dir.path()).unwrap();
+        assert_eq!(
+            checkpoint.completed("gen", &fingerprint),
+            Some(vec![output.clone()])
+        );
+        assert_eq!(checkpoint.completed("other", &fingerprint), None);
+        fs::write(&input, "{\"v\": 2}").unwrap();
+        let changed = Checkpoint::fingerprint("gen", std::slice::from_ref(&input));
+        assert_eq!(checkpoint.completed("gen", &changed), None);
+        fs::write(&output, "int y;").unwrap();
+        assert_eq!(checkpoint.completed("gen", &fingerprint), None);
+
+        checkpoint.finish().unwrap();
+        assert!(!dir.path().join(CHECKPOINT_FILE).exists());
+    }
+}
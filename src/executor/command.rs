@@ -0,0 +1,366 @@
+use super::cancel::Cancellation;
+use crate::config::Rule;
+use crate::depcheck;
+use crate::error::CrustError;
+use crate::hermetic::HermeticEnv;
+use crate::priority::Priority;
+use crate::sandbox::Sandbox;
+use crate::toolchain::wrap_command;
+use crate::ui;
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::io::AsyncReadExt;
+use tracing::debug;
+
+/// One command of a target, with the files it reads.
+#[derive(Debug)]
+pub struct Action<'a> {
+    pub rule: Rule,
+    pub cmd: Command,
+    /// Declared inputs; the toolchain is not listed.
+    pub inputs: &'a [PathBuf],
+    /// Build directory the command writes below.
+    pub out_dir: &'a Path,
+    pub target: &'a str,
+    /// What the command does, such as `Compilation of main.c`, for messages.
+    pub description: &'a str,
+}
+
+/// Runs the commands of the native backend. The backend decides what to
+/// run and when; a runner decides where and how, so commands can run on this
+/// machine, in a sandbox, or on a remote executor without the target logic
+/// knowing.
+pub trait CommandRunner: Send + Sync {
+    /// Runs `action` to completion and returns its stderr, which it has
+    /// already passed on to the user. A non-zero exit is an error
+    /// ([`CrustError::CommandFailed`]).
+    fn run(&self, action: Action<'_>) -> Result<String>;
+}
+
+/// Spawns commands on this machine at the configured priority and in the
+/// hermetic environment when there is one.
+#[derive(Debug, Clone, Default)]
+pub struct LocalRunner {
+    pub priority: Priority,
+    /// Set by `--hermetic`: commands see only the allowed variables.
+    pub hermetic: Option<HermeticEnv>,
+    /// Commands of the build, killed when it is cancelled.
+    pub cancel: Arc<Cancellation>,
+}
+
+impl LocalRunner {
+    /// `cmd` at the configured priority, and in the hermetic environment
+    /// when there is one. Scrubbing comes last, since wrapping a command
+    /// only carries over the variables set on it.
+    pub fn finish(&self, cmd: Command) -> Command {
+        let mut cmd = self.priority.apply(cmd);
+        if let Some(hermetic) = &self.hermetic {
+            hermetic.apply(&mut cmd);
+        }
+        cmd
+    }
+}
+
+impl LocalRunner {
+    /// Runs `action` like [`CommandRunner::run`], but through
+    /// `tokio::process`, so waiting for it holds no thread.
+    pub async fn run_async(&self, action: Action<'_>) -> Result<String> {
+        run_command_async(
+            self.finish(action.cmd),
+            action.target,
+            action.description,
+            &self.cancel,
+        )
+        .await
+    }
+}
+
+impl CommandRunner for LocalRunner {
+    fn run(&self, action: Action<'_>) -> Result<String> {
+        run_command(
+            &mut self.finish(action.cmd),
+            action.target,
+            action.description,
+            &self.cancel,
+        )
+    }
+}
+
+/// Runs commands through `inner` inside a [`Sandbox`] exposing only their
+/// inputs, read-only, and their build directory.
+pub struct Sandboxed<'a> {
+    pub inner: &'a dyn CommandRunner,
+    /// Whether the commands may reach the network, as downloads must.
+    pub network: bool,
+}
+
+impl CommandRunner for Sandboxed<'_> {
+    fn run(&self, action: Action<'_>) -> Result<String> {
+        self.inner.run(sandboxed(action, self.network)?)
+    }
+}
+
+/// `action` wrapped in a [`Sandbox`] exposing only its inputs, read-only,
+/// its build directory, and with `network`, the network.
+pub fn sandboxed(mut action: Action<'_>, network: bool) -> Result<Action<'_>> {
+    action.cmd = Sandbox::new()
+        .read_only(action.inputs)
+        .writable([action.out_dir])
+        .share_network(network)
+        .wrap(&action.cmd)?;
+    Ok(action)
+}
+
+/// Runs commands through `inner` under `strace`, recording the project
+/// files each target read without declaring them (`--check-deps`).
+pub struct Traced<'a> {
+    pub inner: &'a dyn CommandRunner,
+    pub project_dir: &'a Path,
+    /// Numbers the trace files of commands running at once.
+    pub seq: &'a AtomicUsize,
+    pub undeclared: &'a Mutex<HashMap<String, BTreeSet<PathBuf>>>,
+}
+
+impl CommandRunner for Traced<'_> {
+    fn run(&self, mut action: Action<'_>) -> Result<String> {
+        let cwd = match action.cmd.get_current_dir() {
+            Some(dir) => dir.to_path_buf(),
+            None => std::env::current_dir()?,
+        };
+        let trace_dir = action.out_dir.join(".crust-depcheck");
+        fs::create_dir_all(&trace_dir)?;
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let trace_file = trace_dir.join(format!("{}-{seq}.trace", action.target));
+        action.cmd = depcheck::wrap(&action.cmd, &trace_file);
+        let (target, inputs, out_dir) = (action.target, action.inputs, action.out_dir);
+        let result = self.inner.run(action);
+
+        let trace = fs::read_to_string(&trace_file).unwrap_or_default();
+        let _ = fs::remove_file(&trace_file);
+        let reads = depcheck::parse_trace(&trace);
+        let found = depcheck::undeclared_reads(&reads, &cwd, self.project_dir, out_dir, inputs);
+        if !found.is_empty() {
+            self.undeclared
+                .lock()
+                .expect("undeclared mutex poisoned")
+                .entry(target.to_string())
+                .or_default()
+                .extend(found);
+        }
+        result
+    }
+}
+
+/// `cmd` started through `launcher`, a program and its arguments separated
+/// by spaces, such as `ccache` or `distcc`. An empty launcher leaves `cmd`
+/// as it is.
+pub fn launch(launcher: &str, cmd: Command) -> Command {
+    let mut words = launcher.split_whitespace();
+    match words.next() {
+        Some(program) => wrap_command(program, words, &cmd),
+        None => cmd,
+    }
+}
+
+/// Spawns `cmd` and waits for it, emitting debug events with the full command
+/// line and its exit status. Its stderr is captured, passed through, and
+/// returned. A program missing from `PATH` is reported as
+/// [`CrustError::CompilerMissing`] and a non-zero exit as
+/// [`CrustError::CommandFailed`] attributed to `target`. The command runs
+/// in its own process group, killed when `cancel` is and, with what is left
+/// of it, when the command fails.
+pub fn run_command(
+    cmd: &mut Command,
+    target: &str,
+    action: &str,
+    cancel: &Cancellation,
+) -> Result<String> {
+    if cancel.is_cancelled() {
+        return Err(anyhow!("{action} for '{target}' was cancelled"));
+    }
+    cancel.prepare(cmd);
+    debug!(command = ?cmd, "spawning command");
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let (status, stderr) = match cmd.stderr(Stdio::piped()).spawn() {
+        Ok(mut child) => {
+            let pid = child.id();
+            cancel.register(pid);
+            // Read while waiting, since a full pipe would block the command.
+            let mut pipe = child.stderr.take().expect("piped stderr");
+            let reader = thread::spawn(move || {
+                let mut stderr = Vec::new();
+                let _ = pipe.read_to_end(&mut stderr);
+                stderr
+            });
+            let status = child.wait();
+            cancel.unregister(pid);
+            let status = status.with_context(|| format!("Failed to wait for {}", program))?;
+            if !status.success() {
+                // Before reading to the end, as what the command left behind
+                // may hold its stderr open.
+                cancel.failed(pid);
+            }
+            (status, reader.join().unwrap_or_default())
+        }
+        Err(err) => return Err(spawn_error(err, program)),
+    };
+    debug!(command = ?cmd, exit = ?status.code(), "command finished");
+    command_finished(status, &stderr, target, action)
+}
+
+/// [`run_command`] through `tokio::process`: the command is awaited on the
+/// runtime instead of a thread waiting for it, so many can run at once
+/// without a thread each.
+pub async fn run_command_async(
+    mut cmd: Command,
+    target: &str,
+    action: &str,
+    cancel: &Cancellation,
+) -> Result<String> {
+    if cancel.is_cancelled() {
+        return Err(anyhow!("{action} for '{target}' was cancelled"));
+    }
+    cancel.prepare(&mut cmd);
+    cmd.stderr(Stdio::piped());
+    debug!(command = ?cmd, "spawning command");
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let mut cmd = tokio::process::Command::from(cmd);
+    let mut child = cmd
+        .spawn()
+        .map_err(|err| spawn_error(err, program.clone()))?;
+    let pid = child
+        .id()
+        .expect("a child that was not waited for has its id");
+    cancel.register(pid);
+    let mut pipe = child.stderr.take().expect("piped stderr");
+    let reader = tokio::spawn(async move {
+        let mut stderr = Vec::new();
+        let _ = pipe.read_to_end(&mut stderr).await;
+        stderr
+    });
+    let status = child.wait().await;
+    cancel.unregister(pid);
+    let status = status.with_context(|| format!("Failed to wait for {}", program))?;
+    if !status.success() {
+        cancel.failed(pid);
+    }
+    let stderr = reader.await.unwrap_or_default();
+    debug!(command = ?cmd.as_std(), exit = ?status.code(), "command finished");
+    command_finished(status, &stderr, target, action)
+}
+
+/// The error for `program` failing to start: [`CrustError::CompilerMissing`]
+/// when it is not on `PATH`.
+fn spawn_error(err: io::Error, program: String) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        return CrustError::CompilerMissing { program }.into();
+    }
+    anyhow::Error::new(err).context(format!("Failed to spawn {}", program))
+}
+
+/// Passes on the `stderr` of a command that exited with `status` and
+/// returns it, or the [`CrustError::CommandFailed`] of a non-zero exit.
+fn command_finished(
+    status: ExitStatus,
+    stderr: &[u8],
+    target: &str,
+    action: &str,
+) -> Result<String> {
+    let stderr = String::from_utf8_lossy(stderr).into_owned();
+    ui::command_output(target, &stderr);
+    if !status.success() {
+        return Err(CrustError::CommandFailed {
+            target: target.to_string(),
+            action: action.to_string(),
+            exit: status.code(),
+        }
+        .into());
+    }
+    Ok(stderr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_launched_commands_and_reports_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let action = |cmd: Command| Action {
+            rule: Rule::Compile,
+            cmd,
+            inputs: &[],
+            out_dir: dir.path(),
+            target: "app",
+            description: "Compilation of main.c",
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo launched=$LAUNCHED >&2"]);
+        assert_eq!(launch("", Command::new("cc")).get_program(), "cc");
+        let cmd = launch("env LAUNCHED=yes", cmd);
+        assert_eq!(cmd.get_program(), "env");
+
+        let runner = LocalRunner::default();
+        let stderr = runner.run(action(cmd)).unwrap();
+        assert_eq!(stderr, "launched=yes\n");
+
+        // What a failing command leaves behind is killed with it.
+        let started = std::time::Instant::now();
+        let mut failing = Command::new("sh");
+        failing.args(["-c", "sleep 30 & exit 3"]);
+        let err = runner.run(action(failing)).unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        match CrustError::find(&err) {
+            Some(CrustError::CommandFailed { target, exit, .. }) => {
+                assert_eq!((target.as_str(), *exit), ("app", Some(3)));
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn awaits_commands_through_tokio() {
+        let dir = tempfile::tempdir().unwrap();
+        let action = |script: &str| {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", script]);
+            Action {
+                rule: Rule::Custom,
+                cmd,
+                inputs: &[],
+                out_dir: dir.path(),
+                target: "gen",
+                description: "Custom command",
+            }
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let runner = LocalRunner::default();
+
+        let stderr = runtime
+            .block_on(runner.run_async(action("echo generated >&2")))
+            .unwrap();
+        assert_eq!(stderr, "generated\n");
+
+        let err = runtime
+            .block_on(runner.run_async(action("exit 4")))
+            .unwrap_err();
+        match CrustError::find(&err) {
+            Some(CrustError::CommandFailed { target, exit, .. }) => {
+                assert_eq!((target.as_str(), *exit), ("gen", Some(4)));
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+}
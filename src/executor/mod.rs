@@ -1,17 +1,150 @@
-use crate::graph::{DependencyGraph, TargetNode};
-use anyhow::{anyhow, Result};
+use crate::error::CrustError;
+use crate::graph::{DependencyGraph, TargetId, TargetKind, TargetNode};
+use crate::ui;
+use anyhow::{anyhow, Context, Result};
+use cancel::Cancellation;
+use std::any::Any;
 use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::future::Future;
+use std::panic;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinError, JoinSet};
+use tracing::{debug, info_span, Instrument, Span};
+
+pub mod cancel;
+pub mod checkpoint;
+pub mod command;
+pub mod worker;
+
+/// How often a build held back by `--load-average` checks the load again.
+const LOAD_POLL: Duration = Duration::from_millis(500);
 
 pub struct ExecutionResult {
     pub produced: HashMap<String, TargetRunResult>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct BuildExecutor {
     workers: usize,
+    load: LoadLimit,
+    /// Caps on executables and shared libraries running at once.
+    links: Option<Arc<Semaphore>>,
+    /// Caps on custom commands running at once.
+    customs: Option<Arc<Semaphore>>,
+    /// Cancelled at the first failure, for `--fail-fast`.
+    cancel: Option<Arc<Cancellation>>,
+    /// Set by `--watchdog`: how long the build may go without a task
+    /// starting or finishing before it is aborted as stalled.
+    watchdog: Option<Duration>,
+}
+
+/// `--load-average` throttle shared by everything that starts jobs: no new
+/// job starts while the one-minute load average is at least the limit,
+/// unless nothing is running, like `make -l`.
+#[derive(Debug, Clone, Default)]
+pub struct LoadLimit {
+    max_load: Option<f64>,
+    /// Jobs started by [`LoadLimit::run`] and not yet finished, and the
+    /// condition signalled when one finishes.
+    running: Arc<(Mutex<usize>, Condvar)>,
+}
+
+/// A job counted as running by a [`LoadLimit`] until it is dropped, also
+/// when the job panics.
+struct Running<'a>(&'a LoadLimit);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let (running, finished) = &*self.0.running;
+        *running.lock().unwrap_or_else(PoisonError::into_inner) -= 1;
+        finished.notify_all();
+    }
+}
+
+impl LoadLimit {
+    pub fn new(max_load: Option<f64>) -> Self {
+        LoadLimit {
+            max_load,
+            running: Arc::default(),
+        }
+    }
+
+    /// Whether a new job should wait, with `running` jobs in flight.
+    fn holds(&self, running: usize) -> bool {
+        let Some(max_load) = self.max_load else {
+            return false;
+        };
+        match load_average() {
+            Some(load) if running > 0 && load >= max_load => {
+                debug!(load, max_load, running, "load too high; holding jobs");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs `job` once the load allows it.
+    pub fn run<T>(&self, job: impl FnOnce() -> T) -> T {
+        if self.max_load.is_none() {
+            return job();
+        }
+        let (running, finished) = &*self.running;
+        let mut count = running.lock().unwrap_or_else(PoisonError::into_inner);
+        // A finishing job wakes the waiting ones, but the load also drops
+        // when nothing here finishes, so look at it again now and then.
+        while self.holds(*count) {
+            count = finished
+                .wait_timeout(count, LOAD_POLL)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        *count += 1;
+        drop(count);
+        let _running = Running(self);
+        job()
+    }
+}
+
+/// Runs `job` on the blocking pool of the scheduler's runtime, in the
+/// current span. A panic in `job` is raised again in the awaiting task, so
+/// it fails the node like a panic in an async one.
+pub async fn blocking<T: Send + 'static>(
+    job: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let span = Span::current();
+    match task::spawn_blocking(move || span.in_scope(job)).await {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(anyhow!("Build task was cancelled: {}", err)),
+    }
+}
+
+/// One-minute load average of the system, from `/proc/loadavg` or, on macOS
+/// and the BSDs, `sysctl vm.loadavg`. `None` where neither is available.
+pub fn load_average() -> Option<f64> {
+    let text = match std::fs::read_to_string("/proc/loadavg") {
+        Ok(text) => text,
+        Err(_) => {
+            let output = Command::new("sysctl")
+                .args(["-n", "vm.loadavg"])
+                .output()
+                .ok()?;
+            String::from_utf8(output.stdout).ok()?
+        }
+    };
+    parse_load_average(&text)
+}
+
+/// First number of `/proc/loadavg` (`0.52 0.58 0.59 1/467 1234`) or of
+/// `vm.loadavg` (`{ 0.52 0.58 0.59 }`).
+fn parse_load_average(text: &str) -> Option<f64> {
+    text.split_whitespace()
+        .find(|field| *field != "{")
+        .and_then(|field| field.parse().ok())
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +152,8 @@ pub struct TargetRunResult {
     pub outputs: Vec<std::path::PathBuf>,
     pub built: bool,
     pub duration: Duration,
+    /// SHA-256 over the command lines that produce the outputs, when known.
+    pub command_digest: Option<String>,
 }
 
 impl TargetRunResult {
@@ -27,6 +162,7 @@ impl TargetRunResult {
             outputs,
             built: true,
             duration,
+            command_digest: None,
         }
     }
 
@@ -35,15 +171,411 @@ impl TargetRunResult {
             outputs,
             built: false,
             duration,
+            command_digest: None,
+        }
+    }
+
+    pub fn with_command_digest(mut self, digest: String) -> Self {
+        self.command_digest = Some(digest);
+        self
+    }
+}
+
+impl BuildExecutor {
+    pub fn new(parallelism: Option<usize>) -> Self {
+        let workers = parallelism.unwrap_or_else(|| num_cpus::get().max(1));
+        BuildExecutor {
+            workers,
+            load: LoadLimit::default(),
+            links: None,
+            customs: None,
+            cancel: None,
+            watchdog: None,
+        }
+    }
+
+    /// Runs at most `max` executables and shared libraries at once, on top of
+    /// the overall worker count, since links are memory-bound.
+    pub fn with_max_link_jobs(mut self, max: Option<usize>) -> Self {
+        self.links = max.map(|max| Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Runs at most `max` custom commands at once, for commands that hit
+    /// rate-limited services.
+    pub fn with_max_custom_jobs(mut self, max: Option<usize>) -> Self {
+        self.customs = max.map(|max| Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// A permit to start `node` under its kind's cap: `Some(None)` when the
+    /// kind is uncapped and `None` while the cap is reached.
+    fn permit(&self, node: &TargetNode) -> Option<Option<OwnedSemaphorePermit>> {
+        let semaphore = match node.kind {
+            _ if node.combines_architectures() => None,
+            TargetKind::Executable
+            | TargetKind::SharedLibrary
+            | TargetKind::PythonExtension
+            | TargetKind::RustExecutable => self.links.as_ref(),
+            TargetKind::CustomCommand => self.customs.as_ref(),
+            _ => None,
+        };
+        match semaphore {
+            Some(semaphore) => Arc::clone(semaphore).try_acquire_owned().ok().map(Some),
+            None => Some(None),
+        }
+    }
+
+    /// Cancels `cancel` at the first failure of a `--fail-fast` build, so
+    /// the commands still running are killed instead of waited for, and when
+    /// the watchdog aborts the build.
+    pub fn with_cancellation(mut self, cancel: Option<Arc<Cancellation>>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Aborts the build when no task starts or finishes for `timeout` while
+    /// some are left, as with a hung compiler or a scheduling bug.
+    pub fn with_watchdog(mut self, timeout: Option<Duration>) -> Self {
+        self.watchdog = timeout;
+        self
+    }
+
+    /// Holds back new tasks while the load is over `load`'s limit.
+    pub fn with_load_limit(mut self, load: LoadLimit) -> Self {
+        self.load = load;
+        self
+    }
+
+    fn may_start(&self, running: usize) -> bool {
+        running < self.workers && !self.load.holds(running)
+    }
+
+    /// Runs `run_node` for every node of `graph`, starting each node only
+    /// after all of its dependencies succeeded and at most `workers` at a
+    /// time. `run_node` receives the outputs of the node's dependencies,
+    /// without their byproducts, in the order the node declares them in
+    /// `deps`, each dependency's outputs in its own order, regardless of
+    /// which finished first, so link lines are the same on every run. Each
+    /// node runs on the blocking pool of the scheduler's tokio runtime, so
+    /// `run_node` may spawn and wait for processes; see
+    /// [`BuildExecutor::execute_async`] for the rest.
+    pub fn execute<F>(&self, graph: &DependencyGraph, run_node: F) -> Result<ExecutionResult>
+    where
+        F: Fn(&TargetNode, Vec<PathBuf>) -> Result<TargetRunResult> + Send + Sync + 'static,
+    {
+        let run_node = Arc::new(run_node);
+        self.execute_async(graph, move |node, dep_outputs| {
+            let run_node = Arc::clone(&run_node);
+            blocking(move || run_node(&node, dep_outputs))
+        })
+    }
+
+    /// Like [`BuildExecutor::execute`], with each node run as the future
+    /// `run_node` returns, on the scheduler's single thread; commands
+    /// awaited through `tokio::process` then hold no thread while they run.
+    /// A panic in a node fails only that node, as
+    /// [`CrustError::TaskPanicked`]. The first failure stops new nodes from
+    /// starting and is returned once the running ones finish, or are killed
+    /// when a cancellation is set. A build stalled past the watchdog fails
+    /// with the scheduler's state, without waiting for the running nodes.
+    pub fn execute_async<F, Fut>(
+        &self,
+        graph: &DependencyGraph,
+        run_node: F,
+    ) -> Result<ExecutionResult>
+    where
+        F: Fn(Arc<TargetNode>, Vec<PathBuf>) -> Fut,
+        Fut: Future<Output = Result<TargetRunResult>> + Send + 'static,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .max_blocking_threads(self.workers)
+            .build()
+            .context("Failed to start the build scheduler")?;
+        let result = runtime.block_on(self.schedule(graph, run_node));
+        // A stalled build leaves hung nodes behind; do not wait for them.
+        runtime.shutdown_background();
+        result
+    }
+
+    async fn schedule<F, Fut>(
+        &self,
+        graph: &DependencyGraph,
+        run_node: F,
+    ) -> Result<ExecutionResult>
+    where
+        F: Fn(Arc<TargetNode>, Vec<PathBuf>) -> Fut,
+        Fut: Future<Output = Result<TargetRunResult>> + Send + 'static,
+    {
+        // Shared with the tasks running them rather than copied per task.
+        let nodes: Vec<Arc<TargetNode>> = graph.nodes().map(|n| Arc::new(n.clone())).collect();
+
+        let mut in_degree: Vec<usize> = (0..nodes.len())
+            .map(|id| graph.dependency_ids(id).len())
+            .collect();
+        let mut dependents: Vec<Vec<TargetId>> = vec![Vec::new(); nodes.len()];
+        for id in 0..nodes.len() {
+            for &dep in graph.dependency_ids(id) {
+                dependents[dep].push(id);
+            }
+        }
+
+        let mut ready: VecDeque<TargetId> =
+            (0..nodes.len()).filter(|&id| in_degree[id] == 0).collect();
+
+        debug!(
+            workers = self.workers,
+            targets = nodes.len(),
+            "starting executor"
+        );
+
+        let total = nodes.len();
+        let monitor = ui::monitor();
+        if let Some(monitor) = &monitor {
+            monitor.begin(nodes.iter().map(|node| node.name.clone()), self.workers);
+        }
+        let mut remaining = total;
+        let mut produced: Vec<Option<TargetRunResult>> = vec![None; total];
+        let mut running = JoinSet::new();
+        let mut tasks: HashMap<task::Id, TargetId> = HashMap::new();
+        let mut started: HashMap<TargetId, Instant> = HashMap::new();
+        let mut last_progress = Instant::now();
+        let mut first_error: Option<anyhow::Error> = None;
+
+        while remaining > 0 {
+            while !ready.is_empty() && self.may_start(running.len()) {
+                // The first ready node whose kind is under its cap.
+                let Some((index, permit)) = ready.iter().enumerate().find_map(|(index, &id)| {
+                    let permit = self.permit(&nodes[id])?;
+                    Some((index, permit))
+                }) else {
+                    break;
+                };
+                let id = ready.remove(index).expect("ready task");
+                let node = Arc::clone(&nodes[id]);
+                let dep_outputs: Vec<_> = graph
+                    .dependency_ids(id)
+                    .iter()
+                    .flat_map(|&dep| {
+                        // Byproducts are left out; they are not link inputs.
+                        let byproducts = &nodes[dep].byproducts;
+                        produced[dep]
+                            .iter()
+                            .flat_map(|r| &r.outputs)
+                            .filter(|output| !byproducts.iter().any(|file| output.ends_with(file)))
+                            .cloned()
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                if let Some(monitor) = &monitor {
+                    monitor.started(&node.name);
+                }
+                started.insert(id, Instant::now());
+                last_progress = Instant::now();
+                let span = info_span!("target", target_name = %node.name, kind = ?node.kind);
+                let task = run_node(node, dep_outputs);
+                let handle = running.spawn(
+                    async move {
+                        // Released when the node finishes.
+                        let _permit = permit;
+                        debug!("target started");
+                        task.await
+                    }
+                    .instrument(span),
+                );
+                tasks.insert(handle.id(), id);
+            }
+            // With tasks held back, wake up now and then to look at the load.
+            let mut wait = (!ready.is_empty()).then_some(LOAD_POLL);
+            if let Some(watchdog) = self.watchdog {
+                let left = watchdog.saturating_sub(last_progress.elapsed());
+                wait = Some(wait.map_or(left, |wait| wait.min(left)));
+            }
+            let joined = match wait {
+                None => running.join_next_with_id().await,
+                Some(wait) => match tokio::time::timeout(wait, running.join_next_with_id()).await {
+                    Ok(joined) => joined,
+                    Err(_) => match self.watchdog {
+                        Some(watchdog) if last_progress.elapsed() >= watchdog => {
+                            if let Some(cancel) = &self.cancel {
+                                cancel.cancel();
+                            }
+                            let state = scheduler_state(graph, &started, &ready, &produced);
+                            return Err(anyhow!(
+                                "No target started or finished in {}s; aborting the stalled build\n{}",
+                                watchdog.as_secs_f64(),
+                                state
+                            ));
+                        }
+                        _ => continue,
+                    },
+                },
+            };
+            let (id, result) = match joined {
+                Some(joined) => finished_task(joined, &mut tasks, &nodes),
+                None => {
+                    first_error = Some(anyhow!(
+                        "Executor stopped unexpectedly\n{}",
+                        scheduler_state(graph, &started, &ready, &produced)
+                    ));
+                    break;
+                }
+            };
+            started.remove(&id);
+            last_progress = Instant::now();
+
+            let name = &nodes[id].name;
+            if let Some(monitor) = &monitor {
+                monitor.finished(name, result.as_ref().err());
+            }
+            match result {
+                Ok(outputs) => {
+                    debug!(target = %name, built = outputs.built, "target finished");
+                    produced[id] = Some(outputs);
+
+                    for child in std::mem::take(&mut dependents[id]) {
+                        in_degree[child] -= 1;
+                        if in_degree[child] == 0 {
+                            ready.push_back(child);
+                        }
+                    }
+                }
+                Err(err) => {
+                    debug!(target = %name, "target failed: {:#}", err);
+                    if let Some(cancel) = &self.cancel {
+                        cancel.target_failed();
+                    }
+                    first_error = Some(err);
+                    break;
+                }
+            }
+
+            remaining -= 1;
+        }
+
+        // Blocking tasks cannot be cancelled; let the running ones finish so
+        // no command outlives the build.
+        while let Some(joined) = running.join_next_with_id().await {
+            let (id, result) = finished_task(joined, &mut tasks, &nodes);
+            if let Some(monitor) = &monitor {
+                monitor.finished(&nodes[id].name, result.as_ref().err());
+            }
+        }
+
+        if let Some(err) = first_error {
+            return Err(err);
+        }
+
+        let finished = produced.iter().flatten().count();
+        if finished != total {
+            return Err(anyhow!(
+                "Build did not complete: expected {} nodes, finished {}",
+                total,
+                finished
+            ));
+        }
+
+        let produced = nodes
+            .iter()
+            .zip(produced)
+            .filter_map(|(node, result)| Some((node.name.clone(), result?)))
+            .collect();
+        Ok(ExecutionResult { produced })
+    }
+}
+
+/// What the scheduler is doing, for a stalled build: the running targets,
+/// the longest-running first, the ready ones held back, and the others
+/// with the dependencies they wait for.
+fn scheduler_state(
+    graph: &DependencyGraph,
+    started: &HashMap<TargetId, Instant>,
+    ready: &VecDeque<TargetId>,
+    produced: &[Option<TargetRunResult>],
+) -> String {
+    let name = |id: TargetId| graph.node(id).name.as_str();
+    let mut running: Vec<_> = started
+        .iter()
+        .map(|(&id, since)| (since, name(id)))
+        .collect();
+    running.sort();
+    let running = running
+        .into_iter()
+        .map(|(since, name)| format!("{name} (for {}s)", since.elapsed().as_secs()));
+    let mut held: Vec<String> = ready.iter().map(|&id| name(id).to_string()).collect();
+    held.sort();
+    let mut waiting: Vec<TargetId> = (0..produced.len())
+        .filter(|&id| produced[id].is_none() && !started.contains_key(&id) && !ready.contains(&id))
+        .collect();
+    waiting.sort_by_key(|&id| name(id));
+    let waiting = waiting.into_iter().map(|id| {
+        let deps: Vec<&str> = graph
+            .dependency_ids(id)
+            .iter()
+            .filter(|&&dep| produced[dep].is_none())
+            .map(|&dep| name(dep))
+            .collect();
+        format!("{} (on {})", name(id), deps.join(", "))
+    });
+
+    let mut state = Vec::new();
+    for (heading, lines) in [
+        ("Running:", running.collect::<Vec<_>>()),
+        ("Ready, held back by --load-average or the job caps:", held),
+        ("Waiting for dependencies:", waiting.collect()),
+    ] {
+        if !lines.is_empty() {
+            state.push(heading.to_string());
+            state.extend(lines.into_iter().map(|line| format!("  {line}")));
+        }
+    }
+    state.join("\n")
+}
+
+/// The node a task of the scheduler ran and its result, with a panic in the
+/// task as the node's [`CrustError::TaskPanicked`].
+fn finished_task(
+    joined: Result<(task::Id, Result<TargetRunResult>), JoinError>,
+    tasks: &mut HashMap<task::Id, TargetId>,
+    nodes: &[Arc<TargetNode>],
+) -> (TargetId, Result<TargetRunResult>) {
+    match joined {
+        Ok((task, result)) => (tasks.remove(&task).expect("scheduled task"), result),
+        Err(err) => {
+            let id = tasks.remove(&err.id()).expect("scheduled task");
+            let target = nodes[id].name.clone();
+            let result = match err.try_into_panic() {
+                Ok(payload) => Err(CrustError::TaskPanicked {
+                    target,
+                    message: panic_message(payload.as_ref()),
+                }
+                .into()),
+                Err(err) => Err(anyhow!("Build task of '{}' failed: {}", target, err)),
+            };
+            (id, result)
         }
     }
 }
 
+/// The message a panic was raised with, as `panic!` formats it.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panicked".to_string()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
     use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::{Arc, Mutex};
     use std::time::Duration;
 
@@ -53,6 +585,11 @@ mod tests {
             project: ProjectInfo {
                 name: "demo".into(),
                 version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
             },
             targets: vec![
                 Target::CustomCommand {
@@ -60,26 +597,49 @@ mod tests {
                     command: "touch a".into(),
                     outputs: vec!["a".into()],
                     deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
                     inputs: vec![],
+                    worker: None,
+                    programs: Vec::new(),
                 },
                 Target::CustomCommand {
                     name: "gen".into(),
                     command: "touch b".into(),
                     outputs: vec!["b".into()],
                     deps: vec!["prep".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
                     inputs: vec![],
+                    worker: None,
+                    programs: Vec::new(),
                 },
                 Target::CustomCommand {
                     name: "assemble".into(),
                     command: "touch c".into(),
                     outputs: vec!["c".into()],
                     deps: vec!["gen".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
                     inputs: vec![],
+                    worker: None,
+                    programs: Vec::new(),
                 },
             ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
         };
 
-        let graph = DependencyGraph::from_manifest(&manifest).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
         let executor = BuildExecutor::new(Some(2));
         let completed: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
 
@@ -91,7 +651,7 @@ mod tests {
                 }
                 done.push(node.name.clone());
                 Ok(TargetRunResult::built(
-                    node.outputs.iter().map(|o| PathBuf::from(o)).collect(),
+                    node.outputs.iter().map(PathBuf::from).collect(),
                     Duration::from_secs(0),
                 ))
             })
@@ -99,160 +659,412 @@ mod tests {
 
         assert_eq!(result.produced.len(), 3);
     }
-}
-
-impl BuildExecutor {
-    pub fn new(parallelism: Option<usize>) -> Self {
-        let workers = parallelism.unwrap_or_else(|| num_cpus::get().max(1));
-        BuildExecutor { workers }
-    }
 
-    pub fn execute<F>(&self, graph: &DependencyGraph, run_node: F) -> Result<ExecutionResult>
-    where
-        F: Fn(&TargetNode, Vec<std::path::PathBuf>) -> Result<TargetRunResult>
-            + Send
-            + Sync
-            + 'static,
-    {
-        let nodes: HashMap<String, TargetNode> =
-            graph.nodes().map(|n| (n.name.clone(), n.clone())).collect();
-
-        let mut in_degree: HashMap<String, usize> = HashMap::new();
-        let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
-        for (name, node) in &nodes {
-            in_degree.insert(name.clone(), node.dependencies.len());
-            for dep in &node.dependencies {
-                dependents
-                    .entry(dep.clone())
-                    .or_default()
-                    .push(name.clone());
-            }
-        }
+    #[test]
+    fn passes_dependency_outputs_in_declared_order() {
+        let command = |name: &str, deps: &[&str]| Target::CustomCommand {
+            name: name.into(),
+            command: format!("touch {name}.out").into(),
+            outputs: vec![format!("{name}.out")],
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            inputs: vec![],
+            worker: None,
+            programs: Vec::new(),
+        };
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                command("zeta", &[]),
+                command("alpha", &[]),
+                command("mid", &[]),
+                command("link", &["zeta", "alpha", "mid"]),
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
 
-        let ready: VecDeque<String> = in_degree
-            .iter()
-            .filter_map(|(name, degree)| {
-                if *degree == 0 {
-                    Some(name.clone())
-                } else {
-                    None
+        let seen: Arc<Mutex<Vec<PathBuf>>> = Arc::default();
+        let recorded = Arc::clone(&seen);
+        BuildExecutor::new(Some(3))
+            .execute(&graph, move |node, dep_outputs| {
+                // Finish in the reverse of the declared order.
+                let delay = match node.name.as_str() {
+                    "zeta" => 40,
+                    "alpha" => 20,
+                    _ => 0,
+                };
+                std::thread::sleep(Duration::from_millis(delay));
+                if node.name == "link" {
+                    *recorded.lock().unwrap() = dep_outputs;
                 }
+                Ok(TargetRunResult::built(
+                    node.outputs.iter().map(PathBuf::from).collect(),
+                    Duration::from_secs(0),
+                ))
             })
+            .unwrap();
+
+        let expected: Vec<PathBuf> = ["zeta.out", "alpha.out", "mid.out"]
+            .iter()
+            .map(PathBuf::from)
             .collect();
+        assert_eq!(*seen.lock().unwrap(), expected);
+    }
 
-        let produced: Arc<Mutex<HashMap<String, TargetRunResult>>> =
-            Arc::new(Mutex::new(HashMap::new()));
-        let nodes = Arc::new(nodes);
-        let (task_tx, task_rx) = crossbeam_channel::unbounded::<String>();
-        let (done_tx, done_rx) = crossbeam_channel::unbounded();
-        let run_node = Arc::new(run_node);
+    #[test]
+    fn caps_custom_commands_separately_from_workers() {
+        let command = |name: &str| Target::CustomCommand {
+            name: name.into(),
+            command: format!("touch {name}").into(),
+            outputs: vec![name.into()],
+            deps: vec![],
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            inputs: vec![],
+            worker: None,
+            programs: Vec::new(),
+        };
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![command("a"), command("b"), command("c"), command("d")],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
 
-        let mut handles = Vec::new();
-        for _ in 0..self.workers {
-            let task_rx = task_rx.clone();
-            let done_tx = done_tx.clone();
-            let nodes = Arc::clone(&nodes);
-            let produced = Arc::clone(&produced);
-            let run_node = Arc::clone(&run_node);
-            handles.push(thread::spawn(move || {
-                while let Ok(name) = task_rx.recv() {
-                    let node = match nodes.get(&name) {
-                        Some(node) => node,
-                        None => {
-                            let _ = done_tx.send((name, Err(anyhow!("Unknown node"))));
-                            continue;
-                        }
-                    };
-                    let dep_outputs: Vec<_> = {
-                        let map = produced.lock().expect("produced mutex poisoned");
-                        node.dependencies
-                            .iter()
-                            .flat_map(|d| map.get(d).map(|r| r.outputs.clone()).unwrap_or_default())
-                            .collect()
-                    };
-                    let result = run_node(node, dep_outputs);
-                    let _ = done_tx.send((name, result));
-                }
-            }));
-        }
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let observed = Arc::clone(&peak);
+        BuildExecutor::new(Some(4))
+            .with_max_custom_jobs(Some(2))
+            .execute(&graph, move |node, _| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                observed.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(TargetRunResult::built(
+                    node.outputs.iter().map(PathBuf::from).collect(),
+                    Duration::from_secs(0),
+                ))
+            })
+            .unwrap();
 
-        drop(done_tx);
-        for name in ready {
-            task_tx
-                .send(name)
-                .map_err(|e| anyhow!("Failed to enqueue task: {}", e))?;
-        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
 
-        let total = nodes.len();
-        let mut remaining = total;
-        let mut in_degree = in_degree;
-        let mut dependents = dependents;
-        let mut first_error: Option<anyhow::Error> = None;
+    #[test]
+    fn fails_only_the_panicking_target() {
+        let command = |name: &str, deps: &[&str]| Target::CustomCommand {
+            name: name.into(),
+            command: format!("touch {name}").into(),
+            outputs: vec![name.into()],
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            inputs: vec![],
+            worker: None,
+            programs: Vec::new(),
+        };
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                command("broken", &[]),
+                command("sibling", &[]),
+                command("app", &["broken"]),
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
 
-        while remaining > 0 {
-            let (name, result) = match done_rx.recv() {
-                Ok(msg) => msg,
-                Err(err) => {
-                    first_error = Some(anyhow!("Executor stopped unexpectedly: {}", err));
-                    break;
+        let finished: Arc<Mutex<Vec<String>>> = Arc::default();
+        let recorded = Arc::clone(&finished);
+        let err = BuildExecutor::new(Some(2))
+            .execute(&graph, move |node, _| {
+                if node.name == "broken" {
+                    // Lets the sibling start before the failure stops the build.
+                    std::thread::sleep(Duration::from_millis(20));
+                    panic!("index out of bounds");
                 }
-            };
+                recorded.lock().unwrap().push(node.name.clone());
+                Ok(TargetRunResult::built(Vec::new(), Duration::from_secs(0)))
+            })
+            .err()
+            .unwrap();
 
-            match result {
-                Ok(outputs) => {
-                    produced
-                        .lock()
-                        .expect("produced mutex poisoned")
-                        .insert(name.clone(), outputs);
-
-                    if let Some(children) = dependents.remove(&name) {
-                        for child in children {
-                            if let Some(degree) = in_degree.get_mut(&child) {
-                                if *degree > 0 {
-                                    *degree -= 1;
-                                }
-                                if *degree == 0 {
-                                    task_tx
-                                        .send(child.clone())
-                                        .map_err(|e| anyhow!("Failed to enqueue task: {}", e))?;
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(err) => {
-                    first_error = Some(err);
-                    break;
-                }
+        match CrustError::find(&err) {
+            Some(CrustError::TaskPanicked { target, message }) => {
+                assert_eq!(
+                    (target.as_str(), message.as_str()),
+                    ("broken", "index out of bounds")
+                );
             }
-
-            remaining -= 1;
+            other => panic!("unexpected error: {other:?}"),
         }
+        assert_eq!(*finished.lock().unwrap(), ["sibling"]);
+    }
+
+    #[test]
+    fn fails_only_the_panicking_async_target() {
+        let command = |name: &str, deps: &[&str]| Target::CustomCommand {
+            name: name.into(),
+            command: format!("touch {name}").into(),
+            outputs: vec![name.into()],
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            inputs: vec![],
+            worker: None,
+            programs: Vec::new(),
+        };
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                command("broken", &[]),
+                command("sibling", &[]),
+                command("app", &["broken"]),
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
 
-        drop(task_tx);
-        for handle in handles {
-            if let Err(join_err) = handle.join() {
-                return Err(anyhow!("Worker thread panicked: {:?}", join_err));
+        let finished: Arc<Mutex<Vec<String>>> = Arc::default();
+        let err = BuildExecutor::new(Some(2))
+            .execute_async(&graph, |node, _| {
+                let recorded = Arc::clone(&finished);
+                async move {
+                    if node.name == "broken" {
+                        // The sibling finishes while the failure is joined.
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        panic!("index out of bounds");
+                    }
+                    recorded.lock().unwrap().push(node.name.clone());
+                    Ok(TargetRunResult::built(Vec::new(), Duration::from_secs(0)))
+                }
+            })
+            .err()
+            .unwrap();
+
+        match CrustError::find(&err) {
+            Some(CrustError::TaskPanicked { target, message }) => {
+                assert_eq!(
+                    (target.as_str(), message.as_str()),
+                    ("broken", "index out of bounds")
+                );
             }
+            other => panic!("unexpected error: {other:?}"),
         }
+        assert_eq!(*finished.lock().unwrap(), ["sibling"]);
+    }
 
-        if let Some(err) = first_error {
-            return Err(err);
-        }
+    #[test]
+    fn holds_jobs_over_the_load_limit_in_dependency_order() {
+        let command = |name: &str, deps: &[&str]| Target::CustomCommand {
+            name: name.into(),
+            command: format!("touch {name}").into(),
+            outputs: vec![name.into()],
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            inputs: vec![],
+            worker: None,
+            programs: Vec::new(),
+        };
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                command("a", &[]),
+                command("b", &[]),
+                command("app", &["a", "b"]),
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
 
-        let produced = Arc::into_inner(produced)
-            .unwrap_or_default()
-            .into_inner()
-            .unwrap_or_default();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let order: Arc<Mutex<Vec<String>>> = Arc::default();
+        let (observed, recorded) = (Arc::clone(&peak), Arc::clone(&order));
+        // Any load is over a limit of zero, so one job runs at a time and
+        // the held ones start as the scheduler polls the load again.
+        BuildExecutor::new(Some(4))
+            .with_load_limit(LoadLimit::new(Some(0.0)))
+            .execute(&graph, move |node, _| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                observed.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                recorded.lock().unwrap().push(node.name.clone());
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(TargetRunResult::built(Vec::new(), Duration::from_secs(0)))
+            })
+            .unwrap();
 
-        if produced.len() != total {
-            return Err(anyhow!(
-                "Build did not complete: expected {} nodes, finished {}",
-                total,
-                produced.len()
-            ));
+        if load_average().is_some() {
+            assert_eq!(peak.load(Ordering::SeqCst), 1);
         }
+        assert_eq!(*order.lock().unwrap(), ["a", "b", "app"]);
+    }
 
-        Ok(ExecutionResult { produced })
+    #[test]
+    fn aborts_a_stalled_build_with_its_state() {
+        let command = |name: &str, deps: &[&str]| Target::CustomCommand {
+            name: name.into(),
+            command: format!("touch {name}").into(),
+            outputs: vec![name.into()],
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            inputs: vec![],
+            worker: None,
+            programs: Vec::new(),
+        };
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![command("codegen", &[]), command("app", &["codegen"])],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+
+        let started = Instant::now();
+        let err = BuildExecutor::new(Some(2))
+            .with_watchdog(Some(Duration::from_millis(100)))
+            .execute(&graph, |_, _| {
+                // Hangs far past the watchdog.
+                std::thread::sleep(Duration::from_secs(5));
+                Ok(TargetRunResult::built(Vec::new(), Duration::from_secs(0)))
+            })
+            .err()
+            .unwrap()
+            .to_string();
+
+        assert!(
+            started.elapsed() < Duration::from_secs(4),
+            "waited for the hung task"
+        );
+        assert!(err.contains("aborting the stalled build"), "{err}");
+        assert!(err.contains("Running:\n  codegen (for 0s)"), "{err}");
+        assert!(
+            err.contains("Waiting for dependencies:\n  app (on codegen)"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn reads_load_average_and_runs_first_job_regardless() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 1/467 1234\n"),
+            Some(0.52)
+        );
+        assert_eq!(parse_load_average("{ 1.25 1.10 1.05 }\n"), Some(1.25));
+        assert_eq!(parse_load_average(""), None);
+
+        // Nothing is running, so even a limit of zero lets the job start.
+        let limit = LoadLimit::new(Some(0.0));
+        assert_eq!(limit.run(|| 7), 7);
+        assert_eq!(*limit.running.0.lock().unwrap(), 0);
+
+        // A panicking job is no longer counted either.
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            limit.run(|| panic!("job failed"));
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(*limit.running.0.lock().unwrap(), 0);
+        assert_eq!(limit.run(|| 8), 8);
     }
 }
@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// One task sent to a persistent worker, written as a single JSON line on
+/// its standard input.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkRequest {
+    pub target: String,
+    /// The rule's shell command, which the worker performs instead of the
+    /// shell.
+    pub command: String,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+}
+
+/// The worker's answer to a [`WorkRequest`], read as a single JSON line from
+/// its standard output.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WorkResponse {
+    pub exit_code: i32,
+    /// Diagnostics for the task, shown to the user like a command's stderr.
+    #[serde(default)]
+    pub output: String,
+}
+
+/// A running worker process.
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+    fn spawn(mut cmd: Command) -> Result<Self> {
+        debug!(command = ?cmd, "starting persistent worker");
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start worker {:?}", cmd.get_program()))?;
+        let stdin = child.stdin.take().expect("worker stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("worker stdout is piped"));
+        Ok(Worker {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    fn request(&mut self, request: &WorkRequest) -> Result<WorkResponse> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()?;
+        let mut reply = String::new();
+        if self.stdout.read_line(&mut reply)? == 0 {
+            return Err(anyhow!("worker exited without answering"));
+        }
+        serde_json::from_str(&reply)
+            .with_context(|| format!("worker sent an invalid response: {}", reply.trim_end()))
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Persistent workers kept alive across tasks, keyed by the command that
+/// starts them. A task takes an idle worker started by the same command, or
+/// starts a new one when all are busy, so concurrent tasks never share a
+/// process. Workers are stopped when the last clone of the pool is dropped.
+#[derive(Clone, Default)]
+pub struct WorkerPool {
+    idle: Arc<Mutex<HashMap<String, Vec<Worker>>>>,
+}
+
+impl WorkerPool {
+    /// Sends `request` to a worker for `key`, started with `spawn` when none
+    /// is idle. A worker that fails to answer is discarded rather than
+    /// reused.
+    pub fn run(
+        &self,
+        key: &str,
+        spawn: impl FnOnce() -> Command,
+        request: &WorkRequest,
+    ) -> Result<WorkResponse> {
+        let idle = self
+            .idle
+            .lock()
+            .expect("worker pool mutex poisoned")
+            .get_mut(key)
+            .and_then(Vec::pop);
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => Worker::spawn(spawn())?,
+        };
+        let response = worker
+            .request(request)
+            .with_context(|| format!("Persistent worker `{key}` failed"))?;
+        self.idle
+            .lock()
+            .expect("worker pool mutex poisoned")
+            .entry(key.to_string())
+            .or_default()
+            .push(worker);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn reuses_one_worker_across_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let starts = dir.path().join("starts");
+        // Answers every request line and counts how often it was started.
+        let script = format!(
+            "echo start >> {}; while read -r line; do echo '{{\"exit_code\": 0, \"output\": \"done\"}}'; done",
+            starts.display()
+        );
+        let pool = WorkerPool::default();
+        let request = WorkRequest {
+            target: "gen".into(),
+            command: "gen a.idl".into(),
+            inputs: vec!["a.idl".into()],
+            outputs: vec!["a.c".into()],
+        };
+        for _ in 0..3 {
+            let spawn = || {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(&script);
+                cmd
+            };
+            let response = pool.run("gen-daemon", spawn, &request).unwrap();
+            assert_eq!(
+                response,
+                WorkResponse {
+                    exit_code: 0,
+                    output: "done".into()
+                }
+            );
+        }
+        drop(pool);
+        assert_eq!(std::fs::read_to_string(&starts).unwrap(), "start\n");
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Settings of an extract target.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExtractSettings {
+    /// Leading path components dropped from every entry, like
+    /// `tar --strip-components`.
+    pub strip_components: usize,
+}
+
+/// Archive formats unpacked without external tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detects the format from the archive's file name.
+    pub fn detect(path: &str) -> Option<Self> {
+        let name = path.to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Why `archive` cannot be unpacked by an extract target, if it cannot.
+pub fn archive_error(archive: &str) -> Option<String> {
+    ArchiveFormat::detect(archive).is_none().then(|| {
+        format!("cannot tell the format of `{archive}`; expected .tar, .tar.gz, .tgz, or .zip")
+    })
+}
+
+/// Unpacks `archive` into `dest`, which is emptied first so files dropped
+/// from the archive do not linger. Entries that would land outside `dest`
+/// are rejected, and entries with no more than `strip_components`
+/// components are skipped.
+pub fn extract(archive: &Path, dest: &Path, strip_components: usize) -> Result<()> {
+    let format = ArchiveFormat::detect(&archive.to_string_lossy())
+        .ok_or_else(|| anyhow!("Unknown archive format: {}", archive.display()))?;
+    if dest.exists() {
+        fs::remove_dir_all(dest).with_context(|| format!("Failed to clear {}", dest.display()))?;
+    }
+    fs::create_dir_all(dest)?;
+    let file = BufReader::new(
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?,
+    );
+    let result = match format {
+        ArchiveFormat::Tar => extract_tar(file, dest, strip_components),
+        ArchiveFormat::TarGz => extract_tar(GzDecoder::new(file), dest, strip_components),
+        ArchiveFormat::Zip => extract_zip(file, dest, strip_components),
+    };
+    result.with_context(|| format!("Failed to extract {}", archive.display()))
+}
+
+fn extract_tar(reader: impl Read, dest: &Path, strip_components: usize) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    // Unpacked files count as new, like every other build output.
+    archive.set_preserve_mtime(false);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(relative) = strip(&entry.path()?, strip_components)? else {
+            continue;
+        };
+        let target = dest.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Hard links name another entry of the archive, which has to be
+        // found below `dest` rather than relative to the working directory.
+        if entry.header().entry_type() == tar::EntryType::Link {
+            let source = entry
+                .link_name()?
+                .ok_or_else(|| anyhow!("hard link {} has no target", target.display()))?;
+            if let Some(source) = strip(&source, strip_components)? {
+                fs::hard_link(dest.join(source), &target)?;
+            }
+            continue;
+        }
+        entry.unpack(&target)?;
+    }
+    Ok(())
+}
+
+fn extract_zip(reader: impl Read + io::Seek, dest: &Path, strip_components: usize) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let Some(relative) = strip(Path::new(entry.name()), strip_components)? else {
+            continue;
+        };
+        let target = dest.join(relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = File::create(&target)
+            .with_context(|| format!("Failed to create {}", target.display()))?;
+        io::copy(&mut entry, &mut out)?;
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+    Ok(())
+}
+
+/// `path` without its first `count` components, `None` when nothing is
+/// left, or an error when the entry would escape the destination.
+fn strip(path: &Path, count: usize) -> Result<Option<PathBuf>> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part),
+            Component::CurDir => {}
+            _ => return Err(anyhow!("archive entry {} is not relative", path.display())),
+        }
+    }
+    Ok((parts.len() > count).then(|| parts[count..].iter().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_leading_components_of_tarballs() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("zlib-1.3.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&archive).unwrap(),
+            flate2::Compression::default(),
+        ));
+        for (path, body) in [("zlib-1.3/zlib.h", "h"), ("zlib-1.3/src/inflate.c", "c")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(body.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, body.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let dest = dir.path().join("out");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("stale.c"), "").unwrap();
+        extract(&archive, &dest, 1).unwrap();
+        assert_eq!(fs::read_to_string(dest.join("zlib.h")).unwrap(), "h");
+        assert_eq!(fs::read_to_string(dest.join("src/inflate.c")).unwrap(), "c");
+        assert!(!dest.join("stale.c").exists());
+
+        assert_eq!(
+            strip(Path::new("../etc/passwd"), 0)
+                .unwrap_err()
+                .to_string(),
+            "archive entry ../etc/passwd is not relative"
+        );
+        assert!(archive_error("vendor/lib.rar").is_some());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::digest::sha256_file;
+use anyhow::{anyhow, Result};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Settings of a fetch target.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FetchSettings {
+    pub url: String,
+    /// Expected SHA-256 of the download, lowercase hex.
+    pub sha256: String,
+}
+
+impl FetchSettings {
+    /// The cached copy of the download inside `download_dir`. Files are
+    /// stored by checksum, so projects fetching the same file share it and a
+    /// changed checksum never picks up a stale copy.
+    pub fn cached(&self, download_dir: &Path) -> PathBuf {
+        download_dir.join(&self.sha256)
+    }
+
+    /// `curl` invocation downloading the URL to `dest`, failing on HTTP
+    /// errors and following redirects.
+    pub fn command(&self, dest: &Path) -> Command {
+        let mut cmd = Command::new("curl");
+        cmd.args([
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--location",
+            "--output",
+        ])
+        .arg(dest)
+        .arg(&self.url);
+        cmd
+    }
+
+    /// Checks that the file at `path` has the expected checksum.
+    pub fn verify(&self, path: &Path) -> Result<()> {
+        let actual = sha256_file(path)?;
+        if actual != self.sha256 {
+            return Err(anyhow!(
+                "Checksum mismatch for {}: expected sha256 {}, got {}",
+                self.url,
+                self.sha256,
+                actual
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// File name of a download whose target names no output: the last segment
+/// of the URL's path.
+pub fn default_output(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let path = path.split_once("://").map_or(path, |(_, rest)| rest);
+    let (_, name) = path.rsplit_once('/')?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Why `sha256` cannot be the checksum of a fetch target, if it cannot.
+pub fn checksum_error(sha256: &str) -> Option<String> {
+    let valid = sha256.len() == 64
+        && sha256
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    (!valid).then(|| format!("sha256 must be 64 lowercase hex digits, got `{sha256}`"))
+}
+
+/// Directory shared by all builds for cached downloads: `CRUST_DOWNLOAD_DIR`
+/// when set, otherwise `crust/downloads` in the user's cache directory.
+pub fn download_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("CRUST_DOWNLOAD_DIR").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .unwrap_or_else(env::temp_dir);
+    cache.join("crust").join("downloads")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_downloads_after_the_url() {
+        assert_eq!(
+            default_output("https://example.com/releases/zlib-1.3.tar.gz?raw=1").as_deref(),
+            Some("zlib-1.3.tar.gz")
+        );
+        assert_eq!(default_output("https://example.com/"), None);
+        assert_eq!(default_output("https://example.com"), None);
+        assert!(checksum_error(&"ab".repeat(32)).is_none());
+        assert!(checksum_error(&"AB".repeat(32)).is_some());
+        assert!(checksum_error("abc").is_some());
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+
+/// Where a group of compile flags came from. Later origins take precedence
+/// over earlier ones, in the order listed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// Flags crust needs for the target platform, such as `-fPIC`.
+    Platform,
+    /// `c_args` and `sys_root` of the cross file.
+    CrossFile,
+    /// `buildtype`, `sanitizers`, and `c_args` of the profile selected with
+    /// `--profile`.
+    Profile(String),
+    /// Flags of a found `[dependencies]` entry the target lists in
+    /// `external_deps`, and its `HAVE_<NAME>` define.
+    External(String),
+    /// `c_args` of `[project]`.
+    Project,
+    /// `public_c_args` of a dependency.
+    Dependency(String),
+    /// `c_args` and `public_c_args` of the target itself.
+    Target,
+    /// Flags crust derives from a target setting, such as `c_std`.
+    Setting(&'static str),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Platform => write!(f, "platform"),
+            Origin::CrossFile => write!(f, "cross file"),
+            Origin::Profile(name) => write!(f, "profile '{name}'"),
+            Origin::External(name) => write!(f, "dependency '{name}'"),
+            Origin::Project => write!(f, "[project] c_args"),
+            Origin::Dependency(name) => write!(f, "public_c_args of '{name}'"),
+            Origin::Target => write!(f, "target c_args"),
+            Origin::Setting(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Flags from one origin, in the order they were given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub origin: Origin,
+    pub flags: Vec<String>,
+}
+
+impl Layer {
+    pub fn new(origin: Origin, flags: impl IntoIterator<Item = String>) -> Layer {
+        Layer {
+            origin,
+            flags: flags.into_iter().collect(),
+        }
+    }
+}
+
+/// Flags that set the same thing, of which only the last is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Group {
+    Optimization,
+    Standard,
+}
+
+impl Group {
+    fn of(flag: &str) -> Option<Group> {
+        if flag.starts_with("-O") || flag.starts_with("/O") {
+            Some(Group::Optimization)
+        } else if flag.starts_with("-std=") || flag.starts_with("/std:") {
+            Some(Group::Standard)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Group::Optimization => "optimization level",
+            Group::Standard => "language standard",
+        }
+    }
+}
+
+/// Options taking their value as the next argument, which stay together
+/// when duplicates are removed.
+const WITH_VALUE: &[&str] = &[
+    "-I",
+    "-D",
+    "-U",
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-x",
+    "-arch",
+    "-target",
+    "-Xclang",
+    "-Xpreprocessor",
+    "-Xcompiler",
+    "-ccbin",
+];
+
+/// A flag overridden by one from a later origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    group: &'static str,
+    dropped: (String, Origin),
+    kept: (String, Origin),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "conflicting {}: {} from {} is overridden by {} from {}",
+            self.group, self.dropped.0, self.dropped.1, self.kept.0, self.kept.1
+        )
+    }
+}
+
+/// The merged flags of a compile command and the conflicts resolved on the
+/// way.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Merged {
+    pub flags: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merges `layers`, lowest precedence first. A flag (with its value) that
+/// already appeared is dropped, keeping the first position. Of the
+/// optimization and standard flags, only the last one is kept, and
+/// differing ones are reported as conflicts.
+pub fn merge(layers: &[Layer]) -> Merged {
+    let mut units: Vec<(Vec<String>, &Origin)> = Vec::new();
+    for layer in layers {
+        let mut flags = layer.flags.iter();
+        while let Some(flag) = flags.next() {
+            let mut unit = vec![flag.clone()];
+            if WITH_VALUE.contains(&flag.as_str()) {
+                unit.extend(flags.next().cloned());
+            }
+            units.push((unit, &layer.origin));
+        }
+    }
+
+    let mut merged = Merged::default();
+    let mut last_in_group: Vec<(Group, usize)> = Vec::new();
+    for (idx, (unit, _)) in units.iter().enumerate() {
+        if let Some(group) = Group::of(&unit[0]) {
+            last_in_group.retain(|(g, _)| *g != group);
+            last_in_group.push((group, idx));
+        }
+    }
+    let mut seen: Vec<&[String]> = Vec::new();
+    for (idx, (unit, origin)) in units.iter().enumerate() {
+        if let Some(group) = Group::of(&unit[0]) {
+            let (_, last) = last_in_group
+                .iter()
+                .find(|(g, _)| *g == group)
+                .expect("group recorded");
+            if idx != *last {
+                let (kept, kept_origin) = &units[*last];
+                if kept[0] != unit[0] {
+                    merged.conflicts.push(Conflict {
+                        group: group.name(),
+                        dropped: (unit[0].clone(), (*origin).clone()),
+                        kept: (kept[0].clone(), (*kept_origin).clone()),
+                    });
+                }
+                continue;
+            }
+        }
+        if seen.contains(&unit.as_slice()) {
+            continue;
+        }
+        seen.push(unit);
+        merged.flags.extend(unit.iter().cloned());
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+
+    #[test]
+    fn removes_duplicates_and_keeps_the_last_conflicting_flag() {
+        let merged = merge(&[
+            Layer::new(Origin::CrossFile, strings(&["-O2", "-I", "inc", "-Wall"])),
+            Layer::new(
+                Origin::Dependency("core".into()),
+                strings(&["-DUSE_CORE", "-I", "inc", "-I", "core"]),
+            ),
+            Layer::new(
+                Origin::Target,
+                strings(&["-O0", "-Wall", "-std=c99", "-O0"]),
+            ),
+            Layer::new(Origin::Setting("c_std"), strings(&["-std=c11"])),
+        ]);
+        assert_eq!(
+            merged.flags,
+            strings(&[
+                "-I",
+                "inc",
+                "-Wall",
+                "-DUSE_CORE",
+                "-I",
+                "core",
+                "-O0",
+                "-std=c11"
+            ])
+        );
+        let conflicts: Vec<String> = merged.conflicts.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            conflicts,
+            [
+                "conflicting optimization level: -O2 from cross file is overridden by -O0 \
+                 from target c_args",
+                "conflicting language standard: -std=c99 from target c_args is overridden by \
+                 -std=c11 from c_std",
+            ]
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use std::cmp::Ordering;
+use toml_edit::{Array, DocumentMut, Item, RawString, Table, TomlError, Value};
+
+/// Arrays that fit on a `key = [...]` line this wide stay on one line.
+const WIDTH: usize = 80;
+const INDENT: &str = "    ";
+
+/// Order of the top-level tables in a formatted manifest.
+const SECTIONS: &[&str] = &[
+    "project",
+    "package",
+    "python",
+    "profile",
+    "dependencies",
+    "targets",
+    "install_data",
+    "install_symlink",
+    "install_emptydir",
+];
+
+/// Leading keys of each section; the remaining keys follow alphabetically.
+fn key_order(section: &str) -> &'static [&'static str] {
+    match section {
+        "project" => &[
+            "name",
+            "version",
+            "crust_version",
+            "c_args",
+            "subdirs",
+            "passthrough_env",
+            "launchers",
+        ],
+        "package" => &[
+            "summary",
+            "description",
+            "maintainer",
+            "license",
+            "homepage",
+            "depends",
+            "architecture",
+            "release",
+            "source_url",
+        ],
+        "python" => &["interpreter", "version"],
+        "profile" => &[
+            "buildtype",
+            "sanitizers",
+            "c_args",
+            "c_link_args",
+            "options",
+        ],
+        "dependencies" => &[
+            "method",
+            "module",
+            "version",
+            "feature",
+            "fallback",
+            "headers",
+            "libraries",
+            "include_dirs",
+            "lib_dirs",
+        ],
+        "targets" => &[
+            "type",
+            "name",
+            "sources",
+            "url",
+            "sha256",
+            "archive",
+            "strip_components",
+            "input",
+            "inputs",
+            "command",
+            "worker",
+            "programs",
+            "outputs",
+            "output",
+            "variables",
+            "subdir",
+            "deps",
+            "labels",
+            "owners",
+            "deprecated",
+        ],
+        "install_data" => &["sources", "install_dir", "install_mode", "rename"],
+        "install_symlink" => &["name", "pointing_to"],
+        "install_emptydir" => &["dirs", "install_mode"],
+        _ => &[],
+    }
+}
+
+fn rank(order: &[&str], key: &str) -> (usize, String) {
+    let position = order.iter().position(|k| *k == key);
+    (position.unwrap_or(order.len()), key.to_string())
+}
+
+/// Comment lines found in a piece of whitespace, trimmed.
+fn comments(raw: Option<&RawString>) -> Vec<String> {
+    raw.and_then(RawString::as_str)
+        .unwrap_or("")
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Comment lines, each indented and ending in a newline.
+fn comment_block(lines: &[String], indent: &str) -> String {
+    lines
+        .iter()
+        .map(|line| format!("{indent}{line}\n"))
+        .collect()
+}
+
+/// ` # comment` kept at the end of a line, or nothing.
+fn line_comment(raw: Option<&RawString>) -> String {
+    comments(raw)
+        .first()
+        .map(|comment| format!(" {comment}"))
+        .unwrap_or_default()
+}
+
+/// Canonical form of a `crust.build` manifest: sections in a fixed order,
+/// known keys first in each table, one space around `=`, blank lines only
+/// between tables, and arrays on one line when they fit or one element per
+/// line otherwise. Comments stay attached to the key, table, or array
+/// element they precede.
+pub fn format(source: &str) -> Result<String, TomlError> {
+    let mut doc: DocumentMut = source.parse()?;
+    let trailing = comments(Some(doc.trailing()));
+    let root = doc.as_table_mut();
+    root.sort_values_by(|a, _, b, _| {
+        let section = |key: &str| SECTIONS.iter().position(|s| *s == key);
+        match (section(a.get()), section(b.get())) {
+            (Some(x), Some(y)) => x.cmp(&y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    });
+    format_values(root, "");
+    root.decor_mut().clear();
+
+    let mut position = 1;
+    let mut first = !root.iter().any(|(_, item)| item.is_value());
+    for (key, item) in root.iter_mut() {
+        let section = key.get().to_string();
+        match item {
+            Item::Table(table) => format_table(table, &section, &mut position, &mut first),
+            Item::ArrayOfTables(array) => {
+                for table in array.iter_mut() {
+                    format_table(table, &section, &mut position, &mut first);
+                }
+            }
+            _ => {}
+        }
+    }
+    doc.set_trailing(if trailing.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}", comment_block(&trailing, ""))
+    });
+    Ok(doc.to_string())
+}
+
+/// Formats a table below a `[header]`, numbering it and its sub-tables in
+/// the order they should appear in the file.
+fn format_table(table: &mut Table, section: &str, position: &mut usize, first: &mut bool) {
+    table.set_position(*position);
+    *position += 1;
+    let has_values = table.iter().any(|(_, item)| item.is_value());
+    if !table.is_implicit() || has_values {
+        let leading = comments(table.decor().prefix());
+        let suffix = line_comment(table.decor().suffix());
+        let separator = if *first { "" } else { "\n" };
+        table
+            .decor_mut()
+            .set_prefix(format!("{separator}{}", comment_block(&leading, "")));
+        table.decor_mut().set_suffix(suffix);
+        *first = false;
+    }
+    format_values(table, section);
+    for (_, item) in table.iter_mut() {
+        match item {
+            Item::Table(child) if !child.is_dotted() => {
+                format_table(child, section, position, first)
+            }
+            Item::ArrayOfTables(array) => {
+                for child in array.iter_mut() {
+                    format_table(child, section, position, first);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Orders and normalizes the `key = value` lines of a table.
+fn format_values(table: &mut Table, section: &str) {
+    let order = key_order(section);
+    if !section.is_empty() {
+        table.sort_values_by(|a, _, b, _| rank(order, a.get()).cmp(&rank(order, b.get())));
+    }
+    for (mut key, item) in table.iter_mut() {
+        let Item::Value(value) = item else {
+            continue;
+        };
+        let leading = comments(key.leaf_decor().prefix());
+        let suffix = line_comment(value.decor().suffix());
+        key.leaf_decor_mut().set_prefix(comment_block(&leading, ""));
+        key.leaf_decor_mut().set_suffix(" ");
+        format_value(value, key.get().len() + 3, 0);
+        value.decor_mut().set_prefix(" ");
+        value.decor_mut().set_suffix(suffix);
+    }
+}
+
+/// Normalizes arrays and inline tables inside `value`, which starts
+/// `column` characters into a line nested `depth` arrays deep.
+fn format_value(value: &mut Value, column: usize, depth: usize) {
+    match value {
+        Value::Array(array) => format_array(array, column, depth),
+        Value::InlineTable(table) => {
+            for (_, value) in table.iter_mut() {
+                format_value(value, WIDTH, depth + 1);
+            }
+            table.fmt();
+        }
+        _ => {}
+    }
+}
+
+/// Puts `array` on one line when it fits after `column` characters, or one
+/// element per line with a trailing comma otherwise.
+pub fn format_array(array: &mut Array, column: usize, depth: usize) {
+    let mut leading = Vec::new();
+    for value in array.iter_mut() {
+        let mut lines = comments(value.decor().prefix());
+        lines.extend(comments(value.decor().suffix()));
+        leading.push(lines);
+        format_value(value, WIDTH, depth + 1);
+    }
+    let trailing = comments(Some(array.trailing()));
+
+    for (index, value) in array.iter_mut().enumerate() {
+        value
+            .decor_mut()
+            .set_prefix(if index == 0 { "" } else { " " });
+        value.decor_mut().set_suffix("");
+    }
+    array.set_trailing("");
+    array.set_trailing_comma(false);
+    let commented = !trailing.is_empty() || leading.iter().any(|lines| !lines.is_empty());
+    let single = array.to_string();
+    if !commented && (array.is_empty() || column + single.trim().len() <= WIDTH) {
+        return;
+    }
+
+    let indent = INDENT.repeat(depth + 1);
+    for (value, lines) in array.iter_mut().zip(&leading) {
+        value
+            .decor_mut()
+            .set_prefix(format!("\n{}{indent}", comment_block(lines, &indent)));
+    }
+    array.set_trailing(format!(
+        "\n{}{}",
+        comment_block(&trailing, &indent),
+        INDENT.repeat(depth)
+    ));
+    array.set_trailing_comma(!array.is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_sections_and_keys_keeping_comments() {
+        let source = r#"
+[[targets]]
+deps = [ "util" ]   # linked statically
+name="app"
+# entry point
+type = "executable"
+sources = ["src/main.c", "src/cli.c", "src/config.c", "src/render.c", "src/output.c"]
+
+
+[project]
+version = "1.0"
+name = "demo"
+
+[[targets]]
+type = "static_library"
+name = "util"
+sources = [
+  # shared helpers
+  "src/util.c"
+]
+# trailing note
+"#;
+        let expected = r#"[project]
+name = "demo"
+version = "1.0"
+
+[[targets]]
+# entry point
+type = "executable"
+name = "app"
+sources = [
+    "src/main.c",
+    "src/cli.c",
+    "src/config.c",
+    "src/render.c",
+    "src/output.c",
+]
+deps = ["util"] # linked statically
+
+[[targets]]
+type = "static_library"
+name = "util"
+sources = [
+    # shared helpers
+    "src/util.c",
+]
+
+# trailing note
+"#;
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+}
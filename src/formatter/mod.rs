@@ -0,0 +1,136 @@
+use crate::compdb;
+use crate::graph::{DependencyGraph, TargetKind};
+use crate::runner::spawn_error;
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub mod manifest;
+
+/// Source files of compiled targets that a C/C++ formatter should touch.
+///
+/// Files that a custom command declares as outputs are generated and left
+/// alone, even when they also live in the source tree.
+pub fn sources(graph: &DependencyGraph, manifest_dir: &Path) -> BTreeSet<PathBuf> {
+    let generated: BTreeSet<PathBuf> = graph
+        .nodes()
+        .filter(|node| node.kind == TargetKind::CustomCommand)
+        .flat_map(|node| node.outputs.iter().map(|o| manifest_dir.join(o)))
+        .collect();
+    graph
+        .nodes()
+        .filter(|node| node.kind != TargetKind::CustomCommand)
+        .flat_map(|node| node.sources.iter().map(|s| manifest_dir.join(s)))
+        .filter(|path| compdb::is_c_family(path) || is_header(path))
+        .filter(|path| !generated.contains(path))
+        .collect()
+}
+
+fn is_header(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("h" | "hh" | "hpp" | "hxx" | "h++" | "inl")
+    )
+}
+
+/// Command that rewrites `file` in place, or with `check` only reports
+/// whether it would change. `.clang-format` files are honored either way.
+pub fn command(program: &str, file: &Path, check: bool) -> Command {
+    let mut cmd = Command::new(program);
+    cmd.arg("--style=file");
+    if check {
+        cmd.arg("--dry-run").arg("--Werror");
+    } else {
+        cmd.arg("-i");
+    }
+    cmd.arg(file);
+    cmd
+}
+
+/// Runs `program` over `files` in parallel and returns the files that are
+/// not formatted (in check mode) or could not be formatted.
+pub fn run(program: &str, files: &BTreeSet<PathBuf>, check: bool) -> Result<Vec<PathBuf>> {
+    let results: Vec<Result<Option<PathBuf>>> = files
+        .par_iter()
+        .map(|file| {
+            let mut cmd = command(program, file, check);
+            let output = cmd
+                .output()
+                .map_err(|err| spawn_error(cmd.get_program(), err))?;
+            Ok((!output.status.success()).then(|| file.clone()))
+        })
+        .collect();
+    results.into_iter().filter_map(Result::transpose).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
+
+    #[test]
+    fn enumerates_target_sources_without_generated_files() {
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::CustomCommand {
+                    name: "codegen".into(),
+                    command: "gen".into(),
+                    outputs: vec!["src/gen.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    inputs: vec!["schema.json".into()],
+                    worker: None,
+                    programs: Vec::new(),
+                },
+                Target::Executable {
+                    name: "app".into(),
+                    sources: vec![
+                        "src/main.c".into(),
+                        "src/gen.c".into(),
+                        "src/app.hpp".into(),
+                    ],
+                    deps: vec!["codegen".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let files = sources(&graph, Path::new("/work"));
+        assert_eq!(
+            files.into_iter().collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("/work/src/app.hpp"),
+                PathBuf::from("/work/src/main.c")
+            ]
+        );
+
+        let check = command("clang-format", Path::new("a.c"), true);
+        let args: Vec<_> = check.get_args().collect();
+        assert_eq!(args, ["--style=file", "--dry-run", "--Werror", "a.c"]);
+    }
+}
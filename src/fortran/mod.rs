@@ -0,0 +1,175 @@
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// Whether `path` is a Fortran source, free or fixed form, compiled with
+/// the Fortran compiler.
+pub fn is_fortran(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some(
+            "f" | "for"
+                | "f77"
+                | "f90"
+                | "f95"
+                | "f03"
+                | "f08"
+                | "F"
+                | "F90"
+                | "F95"
+                | "F03"
+                | "F08"
+        )
+    )
+}
+
+/// Directory below the build directory where a target's compiled `.mod`
+/// files are written (`-J`) and where dependents find them (`-I`).
+pub fn module_dir(target: &str) -> String {
+    format!("{target}.modules")
+}
+
+/// Modules a Fortran source defines and uses, lowercased as the compiler
+/// treats them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub provides: BTreeSet<String>,
+    pub uses: BTreeSet<String>,
+}
+
+impl ModuleInfo {
+    /// Scans `module` and `use` statements. Intrinsic modules such as
+    /// `iso_c_binding` come with the compiler and are left out, as are
+    /// `module procedure` and `module function` lines, which do not start a
+    /// module.
+    pub fn scan(text: &str) -> ModuleInfo {
+        let mut info = ModuleInfo::default();
+        for line in text.lines() {
+            let code = line
+                .split('!')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_ascii_lowercase();
+            let mut words = code
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|w| !w.is_empty());
+            match words.next() {
+                Some("module") => {
+                    if let Some(name) = words
+                        .next()
+                        .filter(|name| !matches!(*name, "procedure" | "function" | "subroutine"))
+                    {
+                        info.provides.insert(name.to_string());
+                    }
+                }
+                Some("use") => {
+                    let rest: Vec<&str> = words.collect();
+                    if rest.contains(&"intrinsic") {
+                        continue;
+                    }
+                    let name = rest
+                        .iter()
+                        .find(|w| !matches!(**w, "::" | "non_intrinsic"))
+                        .map(|name| name.trim_start_matches("::"));
+                    if let Some(name) = name.filter(|name| !name.is_empty()) {
+                        info.uses.insert(name.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        let provides = info.provides.clone();
+        info.uses.retain(|name| !provides.contains(name));
+        info
+    }
+}
+
+/// Groups source indices into waves: every source is compiled after the
+/// sources defining the modules it uses, so each wave only needs the
+/// `.mod` files of earlier ones and can be compiled in parallel. Modules
+/// no source defines are assumed to come from dependencies. `names` are
+/// used in the cycle error.
+pub fn compile_waves(infos: &[ModuleInfo], names: &[String]) -> Result<Vec<Vec<usize>>> {
+    let providers = providers(infos);
+    let mut wave_of: Vec<Option<usize>> = vec![None; infos.len()];
+    let mut remaining: Vec<usize> = (0..infos.len()).collect();
+    let mut waves: Vec<Vec<usize>> = Vec::new();
+    while !remaining.is_empty() {
+        let ready: Vec<usize> = remaining
+            .iter()
+            .copied()
+            .filter(|&idx| {
+                infos[idx]
+                    .uses
+                    .iter()
+                    .filter_map(|module| providers.get(module.as_str()))
+                    .all(|&provider| provider == idx || wave_of[provider].is_some())
+            })
+            .collect();
+        if ready.is_empty() {
+            let stuck: Vec<&str> = remaining.iter().map(|&idx| names[idx].as_str()).collect();
+            return Err(anyhow!(
+                "Fortran modules of {} use each other in a cycle",
+                stuck.join(", ")
+            ));
+        }
+        for &idx in &ready {
+            wave_of[idx] = Some(waves.len());
+        }
+        remaining.retain(|idx| !ready.contains(idx));
+        waves.push(ready);
+    }
+    Ok(waves)
+}
+
+/// Source index defining each module.
+pub fn providers(infos: &[ModuleInfo]) -> HashMap<&str, usize> {
+    infos
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, info)| info.provides.iter().map(move |name| (name.as_str(), idx)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_sources_by_the_modules_they_use() {
+        let solver = ModuleInfo::scan(
+            "module Solver\n  use, intrinsic :: iso_c_binding\n  use constants, only: pi\n\
+             contains\n  module procedure step\nend module solver\n",
+        );
+        assert_eq!(solver.provides, BTreeSet::from(["solver".to_string()]));
+        assert_eq!(solver.uses, BTreeSet::from(["constants".to_string()]));
+
+        let infos = vec![
+            ModuleInfo::scan(
+                "program main\n  use solver ! the solver\n  use :: mpi\nend program\n",
+            ),
+            solver,
+            ModuleInfo::scan("MODULE constants\n  real, parameter :: pi = 3.14159\nEND MODULE\n"),
+        ];
+        let names: Vec<String> = ["main.f90", "solver.f90", "constants.f90"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            compile_waves(&infos, &names).unwrap(),
+            vec![vec![2], vec![1], vec![0]]
+        );
+
+        let cycle = vec![
+            ModuleInfo::scan("module a\nuse b\nend module\n"),
+            ModuleInfo::scan("module b\nuse a\nend module\n"),
+        ];
+        let names = vec!["a.f90".to_string(), "b.f90".to_string()];
+        assert_eq!(
+            compile_waves(&cycle, &names).unwrap_err().to_string(),
+            "Fortran modules of a.f90, b.f90 use each other in a cycle"
+        );
+        assert!(is_fortran(Path::new("src/solver.F90")));
+        assert!(!is_fortran(Path::new("src/main.c")));
+    }
+}
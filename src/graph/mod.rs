@@ -1,7 +1,31 @@
-use crate::config::{ProjectManifest, Target};
+use crate::config::{
+    self, CommandLine, CompileOptions, CrateType, DeprecatedKey, ProjectManifest, Target,
+};
+use crate::copy::CopySettings;
+use crate::cross::Platform;
+use crate::cuda;
+use crate::digest::tree_files;
+use crate::docs::{self, DocsSettings};
+use crate::error::CrustError;
+use crate::extract::{self, ExtractSettings};
+use crate::fetch::{self, FetchSettings};
+use crate::flags::{Layer, Origin};
+use crate::fortran;
+use crate::i18n::{self, GettextSettings};
+use crate::languages;
+use crate::man::{self, ManPage, ManSettings};
+use crate::objc;
+use crate::programs::ProgramReq;
+use crate::python::{self, PythonSettings};
+use crate::rust::{self, RustSettings};
+use crate::standards::{self, Lang};
+use crate::vcs::{self, VcsSettings};
 use anyhow::{anyhow, Result};
-use std::collections::{HashMap, HashSet};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
@@ -11,6 +35,80 @@ pub enum TargetKind {
     StaticLibrary,
     SharedLibrary,
     CustomCommand,
+    Docs,
+    Gettext,
+    Man,
+    VcsVersion,
+    Fetch,
+    Extract,
+    CopyFile,
+    PythonExtension,
+    PythonSources,
+    RustExecutable,
+    RustLibrary,
+    Alias,
+}
+
+impl TargetKind {
+    pub const ALL: [TargetKind; 16] = [
+        TargetKind::Executable,
+        TargetKind::StaticLibrary,
+        TargetKind::SharedLibrary,
+        TargetKind::CustomCommand,
+        TargetKind::Docs,
+        TargetKind::Gettext,
+        TargetKind::Man,
+        TargetKind::VcsVersion,
+        TargetKind::Fetch,
+        TargetKind::Extract,
+        TargetKind::CopyFile,
+        TargetKind::PythonExtension,
+        TargetKind::PythonSources,
+        TargetKind::RustExecutable,
+        TargetKind::RustLibrary,
+        TargetKind::Alias,
+    ];
+
+    /// The `type` manifests give targets of this kind.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            TargetKind::Executable => "executable",
+            TargetKind::StaticLibrary => "static_library",
+            TargetKind::SharedLibrary => "shared_library",
+            TargetKind::CustomCommand => "custom_command",
+            TargetKind::Docs => "docs",
+            TargetKind::Gettext => "gettext",
+            TargetKind::Man => "man",
+            TargetKind::VcsVersion => "vcs_version",
+            TargetKind::Fetch => "fetch",
+            TargetKind::Extract => "extract",
+            TargetKind::CopyFile => "copy_file",
+            TargetKind::PythonExtension => "python_extension",
+            TargetKind::PythonSources => "python_sources",
+            TargetKind::RustExecutable => "rust_executable",
+            TargetKind::RustLibrary => "rust_library",
+            TargetKind::Alias => "alias",
+        }
+    }
+}
+
+/// A target depending on a deprecated one.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deprecation {
+    pub target: String,
+    pub deprecated: String,
+    /// The deprecated target's `deprecated` text.
+    pub message: String,
+}
+
+impl fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "target '{}' depends on deprecated target '{}': {}",
+            self.target, self.deprecated, self.message
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -20,162 +118,1215 @@ pub struct TargetNode {
     pub sources: Vec<String>,
     pub dependencies: Vec<String>,
     pub outputs: Vec<String>,
-    pub command: Option<String>,
+    /// The outputs that are not artifacts of the target: the link map, the
+    /// executable before `bolt`, and `post_build_outputs`. Dependents do not
+    /// link them and `crust install` leaves them out.
+    pub byproducts: Vec<String>,
+    pub command: Option<CommandLine>,
+    /// Persistent worker that runs `command` in the native backend.
+    pub worker: Option<String>,
+    /// Programs a custom command runs, resolved before building.
+    pub programs: Vec<ProgramReq>,
+    pub options: CompileOptions,
+    /// Architecture this node is built for when it is one variant of a
+    /// multi-architecture target.
+    pub arch: Option<String>,
+    /// Doxyfile settings of a docs target.
+    pub docs: Option<DocsSettings>,
+    /// Translation settings of a gettext target.
+    pub gettext: Option<GettextSettings>,
+    /// Converter settings of a man target.
+    pub man: Option<ManSettings>,
+    /// Template and fallback of a vcs_version target.
+    pub vcs: Option<VcsSettings>,
+    /// URL and checksum of a fetch target.
+    pub fetch: Option<FetchSettings>,
+    /// Stripped path components of an extract target.
+    pub extract: Option<ExtractSettings>,
+    /// Placeholder values of a copy_file target.
+    pub copy: Option<CopySettings>,
+    /// Interpreter and package directory of a python_extension or
+    /// python_sources target.
+    pub python: Option<PythonSettings>,
+    /// Edition and crate type of a rust_executable or rust_library target.
+    pub rust: Option<RustSettings>,
+    /// Headers (relative to the build directory) generated by `vcs_version`
+    /// dependencies; their directories join the include path.
+    pub generated_headers: Vec<String>,
+    /// Directories (relative to the build directory) holding the Fortran
+    /// `.mod` files of the targets this one depends on, directly or not.
+    pub module_dirs: Vec<String>,
+    /// Frameworks linked into this target: its own and those of the static
+    /// libraries it depends on, directly or not.
+    pub frameworks: Vec<String>,
+    /// Whether linking needs the Objective-C runtime, because this target
+    /// or a static library it depends on compiles Objective-C.
+    pub objc_runtime: bool,
+    /// Whether linking needs the CUDA runtime, because this target or a
+    /// static library it depends on compiles CUDA.
+    pub cuda_runtime: bool,
+    /// GPU architectures relocatable device code, of this target or of the
+    /// static libraries it depends on, is device-linked for before the host
+    /// link; `None` when nothing needs device linking.
+    pub cuda_device_link: Option<Vec<String>>,
+    /// Registered languages (see `crate::languages`) whose runtime the link
+    /// needs, because this target or a static library it depends on has
+    /// sources in them.
+    pub languages: Vec<String>,
+    /// `[dependencies]` whose libraries the link needs: this target's
+    /// `external_deps` and those of the static libraries it depends on,
+    /// directly or not.
+    pub external_deps: Vec<String>,
+    /// Compile flags from `[project] c_args` and from the `public_c_args`
+    /// of the targets this one depends on, directly or not.
+    pub inherited_c_args: Vec<Layer>,
+    /// Labels declared on the target in the manifest.
+    pub labels: Vec<String>,
+    /// Owners declared on the target in the manifest.
+    pub owners: Vec<String>,
+    /// Why the target is deprecated, when it is.
+    pub deprecated: Option<String>,
+}
+
+impl TargetNode {
+    /// The outputs that are artifacts of the target: everything but its
+    /// byproducts.
+    pub fn products(&self) -> impl Iterator<Item = &String> {
+        self.outputs
+            .iter()
+            .filter(|output| !self.byproducts.contains(output))
+    }
+
+    /// Whether this node combines (or groups) the per-architecture variants
+    /// of a target that sets `architectures`.
+    pub fn combines_architectures(&self) -> bool {
+        self.arch.is_none() && !self.options.architectures.is_empty()
+    }
+
+    /// Whether any source is Fortran, compiled with the Fortran compiler.
+    pub fn has_fortran_sources(&self) -> bool {
+        self.compiles_objects()
+            && self
+                .sources
+                .iter()
+                .any(|source| fortran::is_fortran(Path::new(source)))
+    }
+
+    /// Whether any source is Objective-C or Objective-C++.
+    pub fn has_objc_sources(&self) -> bool {
+        self.compiles_objects()
+            && self
+                .sources
+                .iter()
+                .any(|source| objc::is_objc(Path::new(source)))
+    }
+
+    /// Whether any source is CUDA.
+    pub fn has_cuda_sources(&self) -> bool {
+        self.compiles_objects()
+            && self
+                .sources
+                .iter()
+                .any(|source| cuda::is_cuda(Path::new(source)))
+    }
+
+    /// Names of the registered languages of the sources.
+    pub fn source_languages(&self) -> Vec<&'static str> {
+        if !self.compiles_objects() {
+            return Vec::new();
+        }
+        self.sources
+            .iter()
+            .filter_map(|source| languages::for_source(Path::new(source)))
+            .map(|language| language.name)
+            .collect()
+    }
+
+    /// Whether the sources are compiled to objects and linked by the C
+    /// toolchain.
+    fn compiles_objects(&self) -> bool {
+        matches!(
+            self.kind,
+            TargetKind::Executable
+                | TargetKind::StaticLibrary
+                | TargetKind::SharedLibrary
+                | TargetKind::PythonExtension
+        )
+    }
+
+    /// Whether linking needs the Fortran runtime, because this target or a
+    /// dependency compiles Fortran.
+    pub fn links_fortran(&self) -> bool {
+        self.has_fortran_sources() || !self.module_dirs.is_empty()
+    }
+
+    /// Name of the manifest target this node was created from, without the
+    /// `@arch` suffix of per-architecture variants.
+    pub fn base_name(&self) -> &str {
+        match self.arch {
+            Some(_) => self
+                .name
+                .rsplit_once('@')
+                .map_or(&self.name, |(base, _)| base),
+            None => &self.name,
+        }
+    }
 }
 
+/// Index of a target in its [`DependencyGraph`], valid for that graph only.
+pub type TargetId = usize;
+
+/// Targets in manifest order, each named once in `ids`, with their
+/// dependencies as indices, so walking the graph compares no names.
 #[derive(Debug, Default, Clone)]
 pub struct DependencyGraph {
-    nodes: HashMap<String, TargetNode>,
+    nodes: Vec<TargetNode>,
+    ids: HashMap<String, TargetId>,
+    /// Dependencies of each node, in the order of its `dependencies`.
+    edges: Vec<Vec<TargetId>>,
+    /// Deprecated keys the manifest uses.
+    deprecated_keys: Vec<DeprecatedKey>,
+}
+
+/// What the static libraries a target depends on, directly or not, bring to
+/// its link, gathered by [`DependencyGraph::fold_dependencies`]. Libraries
+/// are named so they are visited in name order.
+#[derive(Debug, Clone, Default)]
+struct Linked<'a> {
+    with_frameworks: BTreeSet<&'a str>,
+    with_external_deps: BTreeSet<&'a str>,
+    /// Those compiling CUDA with relocatable device code.
+    device_code: BTreeSet<&'a str>,
+    objc_runtime: bool,
+    cuda_runtime: bool,
+    languages: BTreeSet<&'static str>,
+}
+
+impl<'a> Linked<'a> {
+    fn merge(&mut self, other: &Linked<'a>) {
+        self.with_frameworks.extend(&other.with_frameworks);
+        self.with_external_deps.extend(&other.with_external_deps);
+        self.device_code.extend(&other.device_code);
+        self.objc_runtime |= other.objc_runtime;
+        self.cuda_runtime |= other.cuda_runtime;
+        self.languages.extend(&other.languages);
+    }
+
+    fn add(&mut self, dep: &'a TargetNode) {
+        if dep.kind != TargetKind::StaticLibrary {
+            return;
+        }
+        if !dep.options.frameworks.is_empty() {
+            self.with_frameworks.insert(&dep.name);
+        }
+        if !dep.options.external_deps.is_empty() {
+            self.with_external_deps.insert(&dep.name);
+        }
+        if dep.has_cuda_sources() && dep.options.cuda_separable {
+            self.device_code.insert(&dep.name);
+        }
+        self.objc_runtime |= dep.has_objc_sources();
+        self.cuda_runtime |= dep.has_cuda_sources();
+        self.languages.extend(dep.source_languages());
+    }
 }
 
 impl DependencyGraph {
-    pub fn from_manifest(manifest: &ProjectManifest) -> Result<Self> {
+    /// Builds the graph, naming compiled outputs for `platform`.
+    pub fn from_manifest(manifest: &ProjectManifest, platform: Platform) -> Result<Self> {
         let mut graph = DependencyGraph {
-            nodes: HashMap::new(),
+            deprecated_keys: manifest.deprecated_keys.clone(),
+            ..Default::default()
         };
 
         for target in &manifest.targets {
             let name = target.name().to_string();
-            if graph.nodes.contains_key(&name) {
-                return Err(anyhow!("Duplicate target name: {}", name));
+            if graph.ids.contains_key(&name) {
+                return Err(CrustError::DuplicateTarget { name }.into());
             }
+            // Files are named after the bare name and, for targets of a
+            // subdir manifest, placed below that directory.
+            let (dir, stem) = config::split_label(&name);
+            let (dir, stem) = (dir.map(str::to_string), stem.to_string());
 
-            let (kind, sources, outputs, command) = match target.clone() {
-                Target::Executable { sources, .. } => {
-                    (TargetKind::Executable, sources, vec![name.clone()], None)
+            let kind = match target {
+                Target::Executable { .. } => TargetKind::Executable,
+                Target::StaticLibrary { .. } => TargetKind::StaticLibrary,
+                Target::SharedLibrary { .. } => TargetKind::SharedLibrary,
+                Target::CustomCommand { .. } => TargetKind::CustomCommand,
+                Target::Docs { .. } => TargetKind::Docs,
+                Target::Gettext { .. } => TargetKind::Gettext,
+                Target::Man { .. } => TargetKind::Man,
+                Target::VcsVersion { .. } => TargetKind::VcsVersion,
+                Target::Fetch { .. } => TargetKind::Fetch,
+                Target::Extract { .. } => TargetKind::Extract,
+                Target::CopyFile { .. } => TargetKind::CopyFile,
+                Target::PythonExtension { .. } => TargetKind::PythonExtension,
+                Target::PythonSources { .. } => TargetKind::PythonSources,
+                Target::RustExecutable { .. } => TargetKind::RustExecutable,
+                Target::RustLibrary { .. } => TargetKind::RustLibrary,
+                Target::Alias { .. } => TargetKind::Alias,
+            };
+            if kind == TargetKind::Alias && target.dependencies().is_empty() {
+                return Err(CrustError::InvalidTarget {
+                    target: name,
+                    message: "deps must name at least one target".into(),
                 }
-                Target::StaticLibrary { sources, .. } => (
-                    TargetKind::StaticLibrary,
-                    sources,
-                    vec![format!("lib{name}.a")],
-                    None,
-                ),
-                Target::SharedLibrary { sources, .. } => (
-                    TargetKind::SharedLibrary,
-                    sources,
-                    vec![format!("lib{name}.so")],
-                    None,
-                ),
+                .into());
+            }
+            let rust = match target {
+                Target::RustExecutable { edition, .. } => Some(RustSettings {
+                    edition: edition.clone(),
+                    crate_type: CrateType::Bin,
+                }),
+                Target::RustLibrary {
+                    edition,
+                    crate_type,
+                    ..
+                } => {
+                    if *crate_type == CrateType::Bin {
+                        return Err(CrustError::InvalidTarget {
+                            target: name,
+                            message: "crate_type = \"bin\" needs a rust_executable target".into(),
+                        }
+                        .into());
+                    }
+                    Some(RustSettings {
+                        edition: edition.clone(),
+                        crate_type: *crate_type,
+                    })
+                }
+                _ => None,
+            };
+            if let Some(settings) = &rust {
+                let message = match rust::edition_error(&settings.edition) {
+                    Some(message) => Some(message),
+                    None if target.sources().is_empty() => {
+                        Some("sources must start with the crate root".into())
+                    }
+                    None => None,
+                };
+                if let Some(message) = message {
+                    return Err(CrustError::InvalidTarget {
+                        target: name,
+                        message,
+                    }
+                    .into());
+                }
+            }
+            let python = match target {
+                Target::PythonExtension { subdir, .. } | Target::PythonSources { subdir, .. } => {
+                    Some(PythonSettings {
+                        interpreter: manifest.python.interpreter.clone(),
+                        subdir: subdir.clone(),
+                    })
+                }
+                _ => None,
+            };
+            let mut runs_python = python.is_some();
+            let (outputs, command, worker) = match target.clone() {
                 Target::CustomCommand {
                     outputs,
-                    inputs,
                     command,
+                    worker,
                     ..
-                } => (TargetKind::CustomCommand, inputs, outputs, Some(command)),
+                } => {
+                    if command == CommandLine::Argv(Vec::new()) {
+                        return Err(CrustError::InvalidTarget {
+                            target: name,
+                            message: "command must name a program".into(),
+                        }
+                        .into());
+                    }
+                    let command = match python::substitute(&command, &manifest.python.interpreter) {
+                        Some(command) => {
+                            runs_python = true;
+                            command
+                        }
+                        None => command,
+                    };
+                    (outputs, Some(command), worker)
+                }
+                Target::VcsVersion { output, .. } => (
+                    vec![format!(
+                        "{stem}/{}",
+                        output.unwrap_or_else(|| format!("{stem}.h"))
+                    )],
+                    None,
+                    None,
+                ),
+                Target::Fetch { url, output, .. } => {
+                    let Some(output) = output.or_else(|| fetch::default_output(&url)) else {
+                        return Err(CrustError::InvalidTarget {
+                            target: name,
+                            message: format!("cannot name the download of `{url}`; set output"),
+                        }
+                        .into());
+                    };
+                    (vec![format!("{stem}/{output}")], None, None)
+                }
+                Target::Extract { output, .. } => {
+                    (vec![output.unwrap_or_else(|| stem.clone())], None, None)
+                }
+                Target::CopyFile { input, output, .. } => {
+                    let output = output.or_else(|| {
+                        Path::new(&input)
+                            .file_name()
+                            .map(|file| file.to_string_lossy().into_owned())
+                    });
+                    let Some(output) = output else {
+                        return Err(CrustError::InvalidTarget {
+                            target: name,
+                            message: format!("cannot name the copy of `{input}`; set output"),
+                        }
+                        .into());
+                    };
+                    (vec![output], None, None)
+                }
+                Target::PythonSources { sources, .. } => {
+                    let settings = python.as_ref().expect("python settings");
+                    let mut outputs = Vec::new();
+                    for source in &sources {
+                        let Some(file) = Path::new(source).file_name() else {
+                            return Err(CrustError::InvalidTarget {
+                                target: name,
+                                message: format!("`{source}` does not name a file"),
+                            }
+                            .into());
+                        };
+                        let file = file.to_string_lossy();
+                        outputs.push(format!(
+                            "{}/{}",
+                            python::MODULE_DIR,
+                            settings.module_path(&file)
+                        ));
+                    }
+                    (outputs, None, None)
+                }
+                Target::PythonExtension { .. } => {
+                    let settings = python.as_ref().expect("python settings");
+                    let outputs = platform
+                        .output_names(&kind, &stem)
+                        .iter()
+                        .map(|output| {
+                            format!("{}/{}", python::MODULE_DIR, settings.module_path(output))
+                        })
+                        .collect();
+                    (outputs, None, None)
+                }
+                Target::RustExecutable { .. } | Target::RustLibrary { .. } => {
+                    let settings = rust.as_ref().expect("rust settings");
+                    (vec![settings.output_name(&stem)], None, None)
+                }
+                _ => (platform.output_names(&kind, &stem), None, None),
             };
 
-            let dependencies = target.dependencies().to_vec();
-            graph.nodes.insert(
-                name.clone(),
-                TargetNode {
-                    name,
-                    kind,
+            let gettext = match target {
+                Target::Gettext {
+                    package,
+                    po_dir,
+                    languages,
                     sources,
-                    dependencies,
-                    outputs,
-                    command,
-                },
-            );
+                    xgettext_args,
+                    ..
+                } => Some(GettextSettings {
+                    package: package
+                        .clone()
+                        .unwrap_or_else(|| manifest.project.name.clone()),
+                    po_dir: po_dir.clone(),
+                    languages: languages.clone(),
+                    sources: sources.clone(),
+                    xgettext_args: xgettext_args.clone(),
+                }),
+                _ => None,
+            };
+            if let Some(message) = gettext.as_ref().and_then(i18n::languages_error) {
+                return Err(CrustError::InvalidTarget {
+                    target: name,
+                    message,
+                }
+                .into());
+            }
+            let (sources, outputs) = match &gettext {
+                Some(settings) => (
+                    settings
+                        .languages
+                        .iter()
+                        .map(|lang| settings.po_file(lang))
+                        .collect(),
+                    settings
+                        .languages
+                        .iter()
+                        .map(|lang| settings.catalog(&stem, lang))
+                        .collect(),
+                ),
+                None => (target.sources().to_vec(), outputs),
+            };
+            let man = match target {
+                Target::Man { tool, .. } => Some(ManSettings { tool: tool.clone() }),
+                _ => None,
+            };
+            let mut outputs = outputs;
+            if let Some(settings) = &man {
+                if let Some(message) = sources
+                    .iter()
+                    .find_map(|source| man::source_error(source, settings.tool.as_deref()))
+                {
+                    return Err(CrustError::InvalidTarget {
+                        target: name,
+                        message,
+                    }
+                    .into());
+                }
+                outputs = sources
+                    .iter()
+                    .filter_map(|source| ManPage::parse(source))
+                    .map(|page| format!("{stem}/{}", page.page))
+                    .collect();
+            }
+            let options = target.options().cloned().unwrap_or_default();
+            if kind != TargetKind::Executable && options.has_executable_only_settings() {
+                return Err(CrustError::InvalidTarget {
+                    target: name,
+                    message: "linker_script, generate_map, size_report, test, and bolt apply only \
+                              to executables"
+                        .into(),
+                }
+                .into());
+            }
+            if let Some(message) = architecture_error(&options) {
+                return Err(CrustError::InvalidTarget {
+                    target: name,
+                    message,
+                }
+                .into());
+            }
+            let std_error = [(Lang::C, &options.c_std), (Lang::Cpp, &options.cpp_std)]
+                .into_iter()
+                .find_map(|(lang, name)| standards::name_error(lang, name.as_deref()?));
+            if let Some(message) = std_error {
+                return Err(CrustError::InvalidTarget {
+                    target: name,
+                    message,
+                }
+                .into());
+            }
+            if let Some(message) = options
+                .cuda_arch
+                .iter()
+                .find_map(|arch| cuda::arch_error(arch))
+            {
+                return Err(CrustError::InvalidTarget {
+                    target: name,
+                    message,
+                }
+                .into());
+            }
+            let mut byproducts = Vec::new();
+            if options.generate_map {
+                byproducts.push(map_file(&outputs[0]));
+            }
+            if options.bolt.is_some() {
+                byproducts.push(prebolt_file(&outputs[0]));
+            }
+            byproducts.extend(options.post_build_outputs.iter().cloned());
+            // Python modules share one importable tree.
+            if let Some(dir) = dir.as_ref().filter(|_| python.is_none()) {
+                for output in outputs.iter_mut().chain(&mut byproducts) {
+                    *output = format!("{dir}/{output}");
+                }
+            }
+            outputs.extend(byproducts.iter().cloned());
+            let dependencies = target.dependencies().to_vec();
+            let docs = match target {
+                Target::Docs { doxygen, .. } => Some(DocsSettings {
+                    project: manifest.project.name.clone(),
+                    version: manifest.project.version.clone(),
+                    doxygen: doxygen.clone(),
+                }),
+                _ => None,
+            };
+            let vcs = match target {
+                Target::VcsVersion {
+                    input, fallback, ..
+                } => Some(VcsSettings {
+                    template: input.clone(),
+                    fallback: fallback
+                        .clone()
+                        .or_else(|| manifest.project.version.clone())
+                        .unwrap_or_else(|| "unknown".into()),
+                }),
+                _ => None,
+            };
+            let fetch = match target {
+                Target::Fetch { url, sha256, .. } => Some(FetchSettings {
+                    url: url.clone(),
+                    sha256: sha256.clone(),
+                }),
+                _ => None,
+            };
+            if let Some(message) = fetch
+                .as_ref()
+                .and_then(|settings| fetch::checksum_error(&settings.sha256))
+            {
+                return Err(CrustError::InvalidTarget {
+                    target: name,
+                    message,
+                }
+                .into());
+            }
+            let extract = match target {
+                Target::Extract {
+                    archive,
+                    strip_components,
+                    ..
+                } => {
+                    if let Some(message) = archive.as_deref().and_then(extract::archive_error) {
+                        return Err(CrustError::InvalidTarget {
+                            target: name,
+                            message,
+                        }
+                        .into());
+                    }
+                    if archive.is_none() && target.dependencies().is_empty() {
+                        return Err(CrustError::InvalidTarget {
+                            target: name,
+                            message: "set archive or depend on the target producing it".into(),
+                        }
+                        .into());
+                    }
+                    Some(ExtractSettings {
+                        strip_components: *strip_components,
+                    })
+                }
+                _ => None,
+            };
+            let programs = match target {
+                Target::CustomCommand { programs, .. } => programs
+                    .iter()
+                    .map(|spec| ProgramReq::parse(spec))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|message| CrustError::InvalidTarget {
+                        target: name.clone(),
+                        message,
+                    })?,
+                _ => Vec::new(),
+            };
+            let mut programs = programs;
+            if runs_python {
+                programs.push(python::requirement(&manifest.python).map_err(|message| {
+                    CrustError::InvalidTarget {
+                        target: name.clone(),
+                        message,
+                    }
+                })?);
+            }
+            let copy = match target {
+                Target::CopyFile { variables, .. } => Some(CopySettings {
+                    variables: variables.clone(),
+                }),
+                _ => None,
+            };
+            graph.insert(TargetNode {
+                name,
+                kind,
+                sources,
+                dependencies,
+                outputs,
+                byproducts,
+                command,
+                worker,
+                programs,
+                options,
+                arch: None,
+                docs,
+                gettext,
+                man,
+                vcs,
+                fetch,
+                extract,
+                copy,
+                python,
+                rust,
+                generated_headers: Vec::new(),
+                module_dirs: Vec::new(),
+                frameworks: Vec::new(),
+                objc_runtime: false,
+                cuda_runtime: false,
+                cuda_device_link: None,
+                languages: Vec::new(),
+                external_deps: Vec::new(),
+                inherited_c_args: Vec::new(),
+                labels: target.labels().to_vec(),
+                owners: target.owners().to_vec(),
+                deprecated: target.deprecated().map(str::to_string),
+            });
         }
 
+        graph.connect()?;
         graph.validate_dependencies()?;
-        graph.check_cycles()?;
+        let order = graph.dependency_order()?;
+        graph.link_generated_headers();
+        graph.link_fortran_modules(&order);
+        graph.link_language_runtimes(&order);
+        graph.inherit_c_args(&manifest.project.c_args, &order);
+        graph.expand_architectures()?;
 
         Ok(graph)
     }
 
+    /// Adds `node`, whose dependencies are looked up by [`Self::connect`].
+    fn insert(&mut self, node: TargetNode) {
+        self.ids.insert(node.name.clone(), self.nodes.len());
+        self.nodes.push(node);
+    }
+
+    /// Looks up the dependencies of every node by name.
+    fn connect(&mut self) -> Result<()> {
+        self.edges = self
+            .nodes
+            .iter()
+            .map(|node| {
+                node.dependencies
+                    .iter()
+                    .map(|dep| {
+                        self.ids.get(dep).copied().ok_or_else(|| {
+                            CrustError::UnknownDependency {
+                                target: node.name.clone(),
+                                dependency: dep.clone(),
+                            }
+                            .into()
+                        })
+                    })
+                    .collect::<Result<_>>()
+            })
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
     fn validate_dependencies(&self) -> Result<()> {
-        for node in self.nodes.values() {
-            for dep in &node.dependencies {
-                if !self.nodes.contains_key(dep) {
-                    return Err(anyhow!(
-                        "Unknown dependency '{}' referenced by '{}'",
-                        dep,
-                        node.name
-                    ));
+        for (node, deps) in self.nodes.iter().zip(&self.edges) {
+            for &dep in deps {
+                let dep_node = &self.nodes[dep];
+                let rlib = dep_node
+                    .rust
+                    .as_ref()
+                    .is_some_and(|rust| rust.crate_type == CrateType::Rlib);
+                let links = matches!(
+                    node.kind,
+                    TargetKind::Executable
+                        | TargetKind::SharedLibrary
+                        | TargetKind::PythonExtension
+                );
+                if rlib && links {
+                    return Err(CrustError::InvalidTarget {
+                        target: node.name.clone(),
+                        message: format!(
+                            "cannot link the Rust crate '{}'; give it crate_type = \"staticlib\" \
+                             or \"cdylib\"",
+                            dep_node.name
+                        ),
+                    }
+                    .into());
                 }
             }
         }
         Ok(())
     }
 
-    fn check_cycles(&self) -> Result<()> {
-        fn visit(
-            node: &str,
-            graph: &DependencyGraph,
-            temp: &mut HashSet<String>,
-            perm: &mut HashSet<String>,
-        ) -> Result<()> {
-            if perm.contains(node) {
-                return Ok(());
-            }
-            if !temp.insert(node.to_string()) {
-                return Err(anyhow!("Cycle detected involving '{}'", node));
-            }
-            if let Some(target) = graph.nodes.get(node) {
-                for dep in &target.dependencies {
-                    visit(dep, graph, temp, perm)?;
+    /// Points compiled targets at the headers of the `vcs_version` targets
+    /// they depend on.
+    fn link_generated_headers(&mut self) {
+        for id in 0..self.nodes.len() {
+            let headers = self.edges[id]
+                .iter()
+                .map(|&dep| &self.nodes[dep])
+                .filter(|dep| dep.kind == TargetKind::VcsVersion)
+                .map(|dep| dep.outputs[0].clone())
+                .collect();
+            self.nodes[id].generated_headers = headers;
+        }
+    }
+
+    /// Points every target at the `.mod` directories of the dependencies,
+    /// direct or transitive, that compile Fortran sources.
+    fn link_fortran_modules(&mut self, order: &[TargetId]) {
+        if !self.nodes.iter().any(TargetNode::has_fortran_sources) {
+            return;
+        }
+        let fortran = self.fold_dependencies(
+            order,
+            |below: &mut BTreeSet<&str>, other| below.extend(other),
+            |below, dep| {
+                if dep.has_fortran_sources() {
+                    below.insert(&dep.name);
                 }
-            }
-            temp.remove(node);
-            perm.insert(node.to_string());
-            Ok(())
+            },
+        );
+        let module_dirs: Vec<Vec<String>> = fortran
+            .into_iter()
+            .map(|deps| {
+                deps.into_iter()
+                    .map(|dep| fortran::module_dir(&config::label_path(dep)))
+                    .collect()
+            })
+            .collect();
+        for (node, module_dirs) in self.nodes.iter_mut().zip(module_dirs) {
+            node.module_dirs = module_dirs;
         }
+    }
 
-        let mut temp = HashSet::new();
-        let mut perm = HashSet::new();
-        for node in self.nodes.keys() {
-            visit(node, self, &mut temp, &mut perm)?;
+    /// Gives every target the frameworks and external dependencies it links
+    /// and notes which language runtimes its link needs, adding those of the static libraries it
+    /// depends on, which cannot record them themselves.
+    fn link_language_runtimes(&mut self, order: &[TargetId]) {
+        let linked = self.fold_dependencies(order, Linked::merge, Linked::add);
+        let updates: Vec<_> = self
+            .nodes
+            .iter()
+            .zip(linked)
+            .map(|(node, linked)| {
+                let lib = |name: &str| &self.nodes[self.ids[name]].options;
+                let mut frameworks = node.options.frameworks.clone();
+                for dep in &linked.with_frameworks {
+                    frameworks.extend(lib(dep).frameworks.iter().cloned());
+                }
+                let mut external_deps = node.options.external_deps.clone();
+                for dep in &linked.with_external_deps {
+                    external_deps.extend(lib(dep).external_deps.iter().cloned());
+                }
+                let cuda_runtime = node.has_cuda_sources();
+                let mut device_archs = node.options.cuda_arch.clone();
+                for dep in &linked.device_code {
+                    device_archs.extend(lib(dep).cuda_arch.iter().cloned());
+                }
+                let device_link =
+                    (cuda_runtime && node.options.cuda_separable) || !linked.device_code.is_empty();
+                let mut languages: BTreeSet<&str> = node.source_languages().into_iter().collect();
+                languages.extend(linked.languages);
+                let mut seen = HashSet::new();
+                frameworks.retain(|framework| seen.insert(framework.clone()));
+                let mut seen = HashSet::new();
+                device_archs.retain(|arch| seen.insert(arch.clone()));
+                let mut seen = HashSet::new();
+                external_deps.retain(|name| seen.insert(name.clone()));
+                (
+                    frameworks,
+                    node.has_objc_sources() || linked.objc_runtime,
+                    cuda_runtime || linked.cuda_runtime,
+                    languages.into_iter().map(String::from).collect::<Vec<_>>(),
+                    external_deps,
+                    // Archives keep relocatable device code for whoever links them.
+                    (device_link && node.kind != TargetKind::StaticLibrary).then_some(device_archs),
+                )
+            })
+            .collect();
+        for (node, update) in self.nodes.iter_mut().zip(updates) {
+            (
+                node.frameworks,
+                node.objc_runtime,
+                node.cuda_runtime,
+                node.languages,
+                node.external_deps,
+                node.cuda_device_link,
+            ) = update;
         }
-        Ok(())
     }
 
-    pub fn topo_order(&self) -> Result<Vec<&TargetNode>> {
-        let mut in_degree: HashMap<&str, usize> = HashMap::new();
-        let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    /// Hands every target the project's flags and the `public_c_args` of
+    /// its dependencies, direct or transitive.
+    fn inherit_c_args(&mut self, project: &[String], order: &[TargetId]) {
+        let public = self.fold_dependencies(
+            order,
+            |below: &mut BTreeSet<&str>, other| below.extend(other),
+            |below, dep| {
+                if !dep.options.public_c_args.is_empty() {
+                    below.insert(&dep.name);
+                }
+            },
+        );
+        let layers: Vec<Vec<Layer>> = public
+            .into_iter()
+            .map(|deps| {
+                let mut layers = Vec::new();
+                if !project.is_empty() {
+                    layers.push(Layer::new(Origin::Project, project.iter().cloned()));
+                }
+                for dep in deps {
+                    let flags = self.nodes[self.ids[dep]].options.public_c_args.iter();
+                    layers.push(Layer::new(
+                        Origin::Dependency(dep.to_string()),
+                        flags.cloned(),
+                    ));
+                }
+                layers
+            })
+            .collect();
+        for (node, layers) in self.nodes.iter_mut().zip(layers) {
+            node.inherited_c_args = layers;
+        }
+    }
 
-        for (name, node) in &self.nodes {
-            in_degree.insert(name.as_str(), node.dependencies.len());
-            for dep in &node.dependencies {
-                dependents
-                    .entry(dep.as_str())
-                    .or_default()
-                    .push(name.as_str());
+    /// For every node, what `add` gathers from each node it depends on,
+    /// directly or not. `order` lists dependencies first, so each node only
+    /// merges what its direct dependencies gathered, rather than walking
+    /// everything below it again.
+    fn fold_dependencies<'a, T: Clone + Default>(
+        &'a self,
+        order: &[TargetId],
+        merge: impl Fn(&mut T, &T),
+        add: impl Fn(&mut T, &'a TargetNode),
+    ) -> Vec<T> {
+        let mut below = vec![T::default(); self.nodes.len()];
+        for &id in order {
+            let mut gathered = T::default();
+            for &dep in &self.edges[id] {
+                merge(&mut gathered, &below[dep]);
+                add(&mut gathered, &self.nodes[dep]);
+            }
+            below[id] = gathered;
+        }
+        below
+    }
+
+    /// Every target `id` depends on, directly or not.
+    fn transitive_dependencies(&self, id: TargetId) -> Vec<TargetId> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut found = Vec::new();
+        let mut stack = self.edges[id].clone();
+        while let Some(dep) = stack.pop() {
+            if !std::mem::replace(&mut seen[dep], true) {
+                found.push(dep);
+                stack.extend(&self.edges[dep]);
             }
         }
+        found
+    }
 
-        let mut queue: Vec<&str> = in_degree
+    /// Clones every target that lists `architectures` into one node per
+    /// architecture (`name@arch`, built into `<arch>/`). The original node
+    /// then depends on its variants and either combines them into a universal
+    /// artifact or, without `universal`, just groups their outputs.
+    fn expand_architectures(&mut self) -> Result<()> {
+        let multi: BTreeMap<String, (Vec<String>, bool)> = self
+            .nodes
             .iter()
-            .filter_map(|(name, degree)| if *degree == 0 { Some(*name) } else { None })
+            .filter(|node| !node.options.architectures.is_empty())
+            .map(|node| {
+                let options = &node.options;
+                (
+                    node.name.clone(),
+                    (options.architectures.clone(), options.universal),
+                )
+            })
             .collect();
-        let mut result = Vec::new();
-
-        while let Some(name) = queue.pop() {
-            let node = self
-                .nodes
-                .get(name)
-                .ok_or_else(|| anyhow!("Missing node {} during topo sort", name))?;
-            result.push(node);
-            if let Some(children) = dependents.get(name) {
-                for child in children {
-                    if let Some(degree) = in_degree.get_mut(child) {
-                        *degree -= 1;
-                        if *degree == 0 {
-                            queue.push(child);
-                        }
+        if multi.is_empty() {
+            return Ok(());
+        }
+
+        for node in &self.nodes {
+            if matches!(
+                node.kind,
+                TargetKind::CustomCommand
+                    | TargetKind::Docs
+                    | TargetKind::Gettext
+                    | TargetKind::Man
+                    | TargetKind::VcsVersion
+                    | TargetKind::Fetch
+                    | TargetKind::Extract
+                    | TargetKind::CopyFile
+                    | TargetKind::PythonSources
+                    | TargetKind::RustExecutable
+                    | TargetKind::RustLibrary
+                    | TargetKind::Alias
+            ) {
+                continue;
+            }
+            let own = multi.get(&node.name).map(|(archs, _)| archs);
+            for dep in &node.dependencies {
+                let Some((archs, false)) = multi.get(dep) else {
+                    continue;
+                };
+                if !own.is_some_and(|own| own.iter().all(|arch| archs.contains(arch))) {
+                    return Err(CrustError::InvalidTarget {
+                        target: node.name.clone(),
+                        message: format!(
+                            "depends on '{dep}', which only has per-architecture outputs; \
+                             set universal = true on '{dep}' or build '{}' for the same \
+                             architectures",
+                            node.name
+                        ),
                     }
+                    .into());
+                }
+            }
+        }
+
+        for (name, (archs, universal)) in &multi {
+            let group = self.nodes[self.ids[name]].clone();
+            let primary = &group.outputs[0];
+            for arch in archs {
+                let mut options = group.options.clone();
+                options.architectures.clear();
+                options.universal = false;
+                options.test = false;
+                options.install = false;
+                if *universal {
+                    options.post_build.clear();
+                    options.post_build_outputs.clear();
+                    options.sign = None;
                 }
+                let mut outputs = vec![format!("{arch}/{primary}")];
+                let byproducts: Vec<String> = options
+                    .generate_map
+                    .then(|| map_file(&outputs[0]))
+                    .into_iter()
+                    .collect();
+                outputs.extend(byproducts.iter().cloned());
+                let dependencies = group
+                    .dependencies
+                    .iter()
+                    .map(|dep| match multi.get(dep) {
+                        Some((dep_archs, _)) if dep_archs.contains(arch) => format!("{dep}@{arch}"),
+                        _ => dep.clone(),
+                    })
+                    .collect();
+                self.insert(TargetNode {
+                    name: format!("{name}@{arch}"),
+                    kind: group.kind.clone(),
+                    sources: group.sources.clone(),
+                    dependencies,
+                    outputs,
+                    byproducts,
+                    command: None,
+                    worker: None,
+                    programs: Vec::new(),
+                    options,
+                    arch: Some(arch.clone()),
+                    docs: None,
+                    gettext: None,
+                    man: None,
+                    vcs: None,
+                    fetch: None,
+                    extract: None,
+                    copy: None,
+                    python: group.python.clone(),
+                    rust: None,
+                    generated_headers: group.generated_headers.clone(),
+                    module_dirs: group.module_dirs.clone(),
+                    frameworks: group.frameworks.clone(),
+                    objc_runtime: group.objc_runtime,
+                    cuda_runtime: group.cuda_runtime,
+                    cuda_device_link: group.cuda_device_link.clone(),
+                    languages: group.languages.clone(),
+                    external_deps: group.external_deps.clone(),
+                    inherited_c_args: group.inherited_c_args.clone(),
+                    labels: group.labels.clone(),
+                    owners: group.owners.clone(),
+                    deprecated: group.deprecated.clone(),
+                });
+            }
+
+            let node = &mut self.nodes[self.ids[name]];
+            node.sources.clear();
+            node.dependencies = archs.iter().map(|arch| format!("{name}@{arch}")).collect();
+            // The variants write the link maps; only `post_build_outputs`
+            // of a universal binary stay with the group.
+            let map = map_file(primary);
+            node.byproducts.retain(|file| *universal && *file != map);
+            node.outputs = if *universal {
+                let mut outputs = vec![primary.clone()];
+                outputs.extend(node.byproducts.iter().cloned());
+                outputs
+            } else {
+                archs
+                    .iter()
+                    .map(|arch| format!("{arch}/{primary}"))
+                    .collect()
+            };
+            let options = &mut node.options;
+            options.embed_files.clear();
+            options.linker_script = None;
+            options.generate_map = false;
+            options.size_report = false;
+            if !*universal {
+                options.post_build.clear();
+                options.sign = None;
             }
         }
+        // The variants and the groups now depend on new names.
+        self.connect()
+    }
 
-        if result.len() != self.nodes.len() {
-            return Err(anyhow!("Cycle detected while performing topological sort"));
+    /// Every node, each after the ones it depends on. Fails on the first
+    /// dependency cycle found, naming a target on it.
+    fn dependency_order(&self) -> Result<Vec<TargetId>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            Visiting,
+            Done,
+        }
+        let mut marks = vec![Mark::New; self.nodes.len()];
+        let mut order = Vec::with_capacity(self.nodes.len());
+        for root in 0..self.nodes.len() {
+            if marks[root] != Mark::New {
+                continue;
+            }
+            marks[root] = Mark::Visiting;
+            // Each node on the path, with the number of its dependencies
+            // visited so far; a loop rather than recursion, so deep chains
+            // of targets cannot overflow the stack.
+            let mut path = vec![(root, 0)];
+            while let Some(&(id, visited)) = path.last() {
+                let Some(&dep) = self.edges[id].get(visited) else {
+                    marks[id] = Mark::Done;
+                    order.push(id);
+                    path.pop();
+                    continue;
+                };
+                path.last_mut().expect("path node").1 += 1;
+                match marks[dep] {
+                    Mark::New => {
+                        marks[dep] = Mark::Visiting;
+                        path.push((dep, 0));
+                    }
+                    Mark::Visiting => {
+                        return Err(CrustError::Cycle {
+                            target: self.nodes[dep].name.clone(),
+                        }
+                        .into());
+                    }
+                    Mark::Done => {}
+                }
+            }
         }
-        Ok(result)
+        Ok(order)
+    }
+
+    pub fn topo_order(&self) -> Result<Vec<&TargetNode>> {
+        Ok(self
+            .dependency_order()?
+            .into_iter()
+            .map(|id| &self.nodes[id])
+            .collect())
     }
 
     pub fn nodes(&self) -> impl Iterator<Item = &TargetNode> {
-        self.nodes.values()
+        self.nodes.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TargetNode> {
+        Some(&self.nodes[*self.ids.get(name)?])
+    }
+
+    /// The target `id` stands for.
+    pub fn node(&self, id: TargetId) -> &TargetNode {
+        &self.nodes[id]
+    }
+
+    /// The targets `id` depends on, in the order of its `dependencies`.
+    pub fn dependency_ids(&self, id: TargetId) -> &[TargetId] {
+        &self.edges[id]
+    }
+
+    /// The subgraph that builds `targets` and everything they depend on.
+    /// With no names it selects every target except docs, and aliases of
+    /// docs, which are only built on request.
+    pub fn select(&self, targets: &[String]) -> Result<DependencyGraph> {
+        let mut pending: Vec<TargetId> = if targets.is_empty() {
+            let is_docs = |&dep: &TargetId| self.nodes[dep].kind == TargetKind::Docs;
+            (0..self.nodes.len())
+                .filter(|&id| match self.nodes[id].kind {
+                    TargetKind::Docs => false,
+                    TargetKind::Alias => !self.transitive_dependencies(id).iter().any(is_docs),
+                    _ => true,
+                })
+                .collect()
+        } else {
+            let mut roots = Vec::new();
+            for name in targets {
+                let Some(&id) = self.ids.get(name) else {
+                    return Err(CrustError::InvalidArgument {
+                        message: format!("No target named '{name}'"),
+                    }
+                    .into());
+                };
+                roots.push(id);
+            }
+            roots
+        };
+        let mut selected = vec![false; self.nodes.len()];
+        while let Some(id) = pending.pop() {
+            if !std::mem::replace(&mut selected[id], true) {
+                pending.extend(&self.edges[id]);
+            }
+        }
+        // Kept in their order here, and renumbered.
+        let mut renumbered = vec![0; self.nodes.len()];
+        let mut graph = DependencyGraph {
+            deprecated_keys: self.deprecated_keys.clone(),
+            ..Default::default()
+        };
+        for id in (0..self.nodes.len()).filter(|&id| selected[id]) {
+            renumbered[id] = graph.nodes.len();
+            graph.insert(self.nodes[id].clone());
+        }
+        graph.edges = (0..self.nodes.len())
+            .filter(|&id| selected[id])
+            .map(|id| self.edges[id].iter().map(|&dep| renumbered[dep]).collect())
+            .collect();
+        Ok(graph)
+    }
+
+    /// Dependencies on deprecated targets, once per pair of manifest
+    /// targets rather than per architecture variant.
+    pub fn deprecations(&self) -> Vec<Deprecation> {
+        let mut found = BTreeSet::new();
+        for (node, deps) in self.nodes.iter().zip(&self.edges) {
+            for dep in deps.iter().map(|&dep| &self.nodes[dep]) {
+                let Some(message) = &dep.deprecated else {
+                    continue;
+                };
+                if dep.base_name() != node.base_name() {
+                    found.insert(Deprecation {
+                        target: node.base_name().to_string(),
+                        deprecated: dep.base_name().to_string(),
+                        message: message.clone(),
+                    });
+                }
+            }
+        }
+        found.into_iter().collect()
+    }
+
+    /// Deprecated target fields and options the manifest uses.
+    pub fn deprecated_keys(&self) -> &[DeprecatedKey] {
+        &self.deprecated_keys
+    }
+
+    /// Names of the declared targets carrying any of `labels`. A label no
+    /// target carries is an error, so a typo does not select nothing.
+    pub fn labelled(&self, labels: &[String]) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for label in labels {
+            let before = names.len();
+            names.extend(
+                self.nodes
+                    .iter()
+                    .filter(|node| node.arch.is_none() && node.labels.contains(label))
+                    .map(|node| node.name.clone()),
+            );
+            if names.len() == before {
+                return Err(CrustError::InvalidArgument {
+                    message: format!("No target has the label '{label}'"),
+                }
+                .into());
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
     }
 
     pub fn is_outdated(&self, manifest_path: &Path, backend_outputs: &[PathBuf]) -> Result<bool> {
@@ -188,32 +1339,66 @@ impl DependencyGraph {
             }
         }
 
-        let manifest_meta = fs::metadata(manifest_path)?;
-        let manifest_mtime = manifest_meta.modified()?;
         let manifest_dir = manifest_path
             .parent()
             .map(Path::to_path_buf)
             .unwrap_or_else(|| PathBuf::from("."));
+        // The manifests of subdirs count as well, found by the directories
+        // in target labels.
+        let subdir_manifests: BTreeSet<PathBuf> = self
+            .ids
+            .keys()
+            .filter_map(|name| config::split_label(name).0)
+            .map(|dir| manifest_dir.join(dir).join("crust.build"))
+            .collect();
+        let mut manifest_mtime = fs::metadata(manifest_path)?.modified()?;
+        for path in &subdir_manifests {
+            manifest_mtime = manifest_mtime.max(fs::metadata(path)?.modified()?);
+        }
 
         let latest_input = self.latest_input_time(&manifest_dir, manifest_mtime)?;
         let oldest_output = self.oldest_output_time(backend_outputs)?;
         Ok(latest_input > oldest_output)
     }
 
+    /// Newest modification time among the inputs of every node, and
+    /// `initial`. Each file is looked at once, however many targets share it,
+    /// and the files are examined in parallel; missing ones are skipped.
     fn latest_input_time(&self, manifest_dir: &Path, initial: SystemTime) -> Result<SystemTime> {
-        let mut latest = initial;
-        for node in self.nodes.values() {
-            for src in &node.sources {
-                let path = manifest_dir.join(src);
-                if path.exists() {
-                    let time = fs::metadata(&path)?.modified()?;
-                    if time > latest {
-                        latest = time;
-                    }
+        let mut paths = BTreeSet::new();
+        for node in &self.nodes {
+            if node.kind == TargetKind::Docs {
+                paths.extend(docs::input_files(manifest_dir, &node.sources)?);
+                continue;
+            }
+            if node.kind == TargetKind::VcsVersion {
+                paths.extend(
+                    vcs::git_dir(manifest_dir)
+                        .map(|dir| vcs::tracked_files(&dir))
+                        .unwrap_or_default(),
+                );
+            }
+            let extra_inputs = node
+                .options
+                .embed_files
+                .iter()
+                .chain(&node.options.linker_script);
+            for path in node.sources.iter().chain(extra_inputs) {
+                let path = manifest_dir.join(path);
+                if path.is_dir() {
+                    paths.extend(tree_files(&path)?);
                 }
+                paths.insert(path);
             }
         }
-        Ok(latest)
+        paths
+            .par_iter()
+            .map(|path| match fs::metadata(path) {
+                Ok(meta) => Ok(meta.modified()?),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(initial),
+                Err(err) => Err(err.into()),
+            })
+            .try_reduce(|| initial, |a, b| Ok(a.max(b)))
     }
 
     fn oldest_output_time(&self, outputs: &[PathBuf]) -> Result<SystemTime> {
@@ -227,6 +1412,46 @@ impl DependencyGraph {
     }
 }
 
+/// Describes an invalid combination of `architectures` and `universal`.
+/// Link map the linker writes next to `output` when `generate_map` is set.
+pub fn map_file(output: &str) -> String {
+    format!("{output}.map")
+}
+
+/// Where the executable linked as `output` is kept, unoptimized, when
+/// `bolt` is set.
+pub fn prebolt_file(output: &str) -> String {
+    format!("{output}.prebolt")
+}
+
+fn architecture_error(options: &CompileOptions) -> Option<String> {
+    let archs = &options.architectures;
+    if options.universal && archs.is_empty() {
+        return Some("universal requires a list of architectures".into());
+    }
+    if let Some(arch) = archs
+        .iter()
+        .find(|arch| arch.is_empty() || arch.contains(['/', '\\', '@']))
+    {
+        return Some(format!("invalid architecture name '{arch}'"));
+    }
+    if !archs.is_empty() && !options.universal && !options.post_build_outputs.is_empty() {
+        return Some(
+            "post_build_outputs need universal = true when building for several architectures"
+                .into(),
+        );
+    }
+    if !archs.is_empty() && options.bolt.is_some() {
+        return Some("bolt does not apply to multi-architecture targets".into());
+    }
+    if !archs.is_empty() && !options.universal && options.install {
+        return Some(
+            "install needs universal = true when building for several architectures".into(),
+        );
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -239,26 +1464,47 @@ mod tests {
             project: ProjectInfo {
                 name: "demo".into(),
                 version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
             },
             targets: vec![
                 Target::StaticLibrary {
                     name: "core".into(),
                     sources: vec!["src/core.c".into()],
                     deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
                 },
                 Target::Executable {
                     name: "app".into(),
                     sources: vec!["src/main.c".into()],
                     deps: vec!["core".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
                 },
             ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
         }
     }
 
     #[test]
     fn builds_graph_and_topo_sort() {
         let manifest = sample_manifest();
-        let graph = DependencyGraph::from_manifest(&manifest).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
         let names: Vec<_> = graph
             .topo_order()
             .unwrap()
@@ -268,21 +1514,272 @@ mod tests {
         assert_eq!(names, vec!["core", "app"]);
     }
 
+    #[test]
+    fn selects_targets_by_label() {
+        let mut manifest = sample_manifest();
+        if let Target::StaticLibrary { labels, .. } = &mut manifest.targets[0] {
+            labels.push("team:infra".into());
+        }
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        assert_eq!(graph.labelled(&["team:infra".into()]).unwrap(), ["core"]);
+        assert!(graph.labelled(&["experimental".into()]).is_err());
+    }
+
     #[test]
     fn detects_cycles() {
         let manifest = ProjectManifest {
             project: ProjectInfo {
                 name: "demo".into(),
                 version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
             },
             targets: vec![Target::Executable {
                 name: "app".into(),
                 sources: vec!["src/main.c".into()],
                 deps: vec!["app".into()],
+                labels: Vec::new(),
+                owners: Vec::new(),
+                deprecated: None,
+                options: Default::default(),
             }],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let err = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap_err();
+        assert!(matches!(
+            CrustError::find(&err),
+            Some(CrustError::Cycle { target }) if target == "app"
+        ));
+    }
+
+    #[test]
+    fn builds_docs_targets_only_on_request() {
+        let mut manifest = sample_manifest();
+        manifest.targets.push(Target::Docs {
+            name: "docs".into(),
+            input: vec!["include".into()],
+            deps: vec!["core".into()],
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            doxygen: Default::default(),
+        });
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let docs = graph.get("docs").unwrap();
+        assert_eq!(docs.outputs, vec!["docs/html/index.html"]);
+        assert_eq!(docs.docs.as_ref().unwrap().project, "demo");
+
+        let mut default: Vec<_> = graph
+            .select(&[])
+            .unwrap()
+            .nodes()
+            .map(|n| n.name.clone())
+            .collect();
+        default.sort();
+        assert_eq!(default, vec!["app", "core"]);
+        let mut requested: Vec<_> = graph
+            .select(&["docs".into()])
+            .unwrap()
+            .nodes()
+            .map(|n| n.name.clone())
+            .collect();
+        requested.sort();
+        assert_eq!(requested, vec!["core", "docs"]);
+        assert!(graph.select(&["missing".into()]).is_err());
+    }
+
+    #[test]
+    fn selects_the_members_of_aliases() {
+        let mut manifest = sample_manifest();
+        let alias = |name: &str, deps: &[&str]| Target::Alias {
+            name: name.into(),
+            deps: deps.iter().map(|dep| dep.to_string()).collect(),
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+        };
+        manifest.targets.extend([
+            Target::Docs {
+                name: "api".into(),
+                input: vec!["include".into()],
+                deps: vec![],
+                labels: Vec::new(),
+                owners: Vec::new(),
+                deprecated: None,
+                doxygen: Default::default(),
+            },
+            alias("programs", &["app"]),
+            alias("manual", &["api"]),
+            alias("everything", &["programs", "manual"]),
+        ]);
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let selected = |targets: &[&str]| {
+            let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+            let mut names: Vec<_> = graph
+                .select(&targets)
+                .unwrap()
+                .nodes()
+                .map(|n| n.name.clone())
+                .collect();
+            names.sort();
+            names
         };
-        let result = DependencyGraph::from_manifest(&manifest);
-        assert!(result.is_err());
+
+        assert_eq!(selected(&["programs"]), ["app", "core", "programs"]);
+        // Aliases reaching docs, even through another alias, wait to be named.
+        assert_eq!(selected(&[]), ["app", "core", "programs"]);
+        assert_eq!(
+            selected(&["everything"]),
+            ["api", "app", "core", "everything", "manual", "programs"]
+        );
+        assert!(graph.get("everything").unwrap().outputs.is_empty());
+    }
+
+    #[test]
+    fn exposes_vcs_version_headers_to_dependents() {
+        let mut manifest = sample_manifest();
+        manifest.targets.push(Target::VcsVersion {
+            name: "version".into(),
+            input: None,
+            output: None,
+            fallback: None,
+            deps: vec![],
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+        });
+        if let Target::Executable { deps, .. } = &mut manifest.targets[1] {
+            deps.push("version".into());
+        }
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let version = graph.get("version").unwrap();
+        assert_eq!(version.outputs, vec!["version/version.h"]);
+        assert_eq!(version.vcs.as_ref().unwrap().fallback, "unknown");
+        assert_eq!(
+            graph.get("app").unwrap().generated_headers,
+            vec!["version/version.h"]
+        );
+        assert!(graph.get("core").unwrap().generated_headers.is_empty());
+    }
+
+    #[test]
+    fn reports_dependencies_on_deprecated_targets() {
+        let mut manifest = sample_manifest();
+        if let Target::StaticLibrary { deprecated, .. } = &mut manifest.targets[0] {
+            *deprecated = Some("use core2".into());
+        }
+        manifest.targets.push(Target::Alias {
+            name: "all".into(),
+            deps: vec!["app".into(), "core".into()],
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: Some("build app".into()),
+        });
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let found: Vec<String> = graph
+            .deprecations()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "target 'all' depends on deprecated target 'core': use core2",
+                "target 'app' depends on deprecated target 'core': use core2",
+            ]
+        );
+        assert_eq!(
+            graph.select(&["app".into()]).unwrap().deprecations().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn clones_multi_architecture_targets_per_arch() {
+        let archs = |universal: bool| CompileOptions {
+            architectures: vec!["arm64".into(), "x86_64".into()],
+            universal,
+            ..Default::default()
+        };
+        let mut manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::StaticLibrary {
+                    name: "core".into(),
+                    sources: vec!["src/core.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: archs(false),
+                },
+                Target::Executable {
+                    name: "app".into(),
+                    sources: vec!["src/main.c".into()],
+                    deps: vec!["core".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: archs(true),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+
+        let variant = graph.get("app@arm64").unwrap();
+        assert_eq!(variant.arch.as_deref(), Some("arm64"));
+        assert_eq!(variant.outputs, vec!["arm64/app"]);
+        assert_eq!(variant.dependencies, vec!["core@arm64"]);
+        assert_eq!(variant.base_name(), "app");
+
+        let app = graph.get("app").unwrap();
+        assert!(app.combines_architectures());
+        assert!(app.sources.is_empty());
+        assert_eq!(app.dependencies, vec!["app@arm64", "app@x86_64"]);
+        assert_eq!(app.outputs, vec!["app"]);
+        let core = graph.get("core").unwrap();
+        assert_eq!(core.outputs, vec!["arm64/libcore.a", "x86_64/libcore.a"]);
+
+        manifest.targets[1] = Target::Executable {
+            name: "app".into(),
+            sources: vec!["src/main.c".into()],
+            deps: vec!["core".into()],
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            options: Default::default(),
+        };
+        let err = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap_err();
+        assert!(matches!(
+            CrustError::find(&err),
+            Some(CrustError::InvalidTarget { target, .. }) if target == "app"
+        ));
     }
 
     #[test]
@@ -304,7 +1801,7 @@ sources = ["src/main.c"]
             .unwrap();
 
         let manifest = ProjectManifest::load(&manifest_path).unwrap();
-        let graph = DependencyGraph::from_manifest(&manifest).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
 
         let src_dir = dir.path().join("src");
         std::fs::create_dir_all(&src_dir).unwrap();
@@ -315,7 +1812,7 @@ sources = ["src/main.c"]
         std::fs::write(&backend_out, "# backend").unwrap();
 
         assert!(!graph
-            .is_outdated(&manifest_path, &[backend_out.clone()])
+            .is_outdated(&manifest_path, std::slice::from_ref(&backend_out))
             .unwrap());
 
         std::thread::sleep(std::time::Duration::from_millis(10));
@@ -323,4 +1820,78 @@ sources = ["src/main.c"]
 
         assert!(graph.is_outdated(&manifest_path, &[backend_out]).unwrap());
     }
+
+    #[test]
+    fn incremental_detection_checks_subdir_manifests() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("crust.build");
+        std::fs::write(
+            &manifest_path,
+            "[project]\nname = \"demo\"\nsubdirs = [\"sub\"]\n",
+        )
+        .unwrap();
+        let subdir_manifest = dir.path().join("sub/crust.build");
+        std::fs::create_dir_all(subdir_manifest.parent().unwrap()).unwrap();
+        let target = "[[targets]]\ntype = \"executable\"\nname = \"app\"\nsources = [\"main.c\"]\n";
+        std::fs::write(&subdir_manifest, target).unwrap();
+
+        let manifest = ProjectManifest::load(&manifest_path).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let backend_out = dir.path().join("build.ninja");
+        std::fs::write(&backend_out, "# backend").unwrap();
+        assert!(!graph
+            .is_outdated(&manifest_path, std::slice::from_ref(&backend_out))
+            .unwrap());
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&subdir_manifest, format!("{target}labels = [\"ui\"]\n")).unwrap();
+        assert!(graph.is_outdated(&manifest_path, &[backend_out]).unwrap());
+    }
+
+    #[test]
+    fn handles_deep_dependency_chains() {
+        let mut manifest = sample_manifest();
+        manifest.targets = (0..20_000)
+            .map(|i| Target::StaticLibrary {
+                name: format!("lib{i}"),
+                sources: vec!["src/lib.c".into()],
+                deps: (i > 0)
+                    .then(|| format!("lib{}", i - 1))
+                    .into_iter()
+                    .collect(),
+                labels: Vec::new(),
+                owners: Vec::new(),
+                deprecated: None,
+                options: CompileOptions {
+                    public_c_args: if i % 10_000 == 0 {
+                        vec![format!("-DLIB{i}")]
+                    } else {
+                        Vec::new()
+                    },
+                    ..Default::default()
+                },
+            })
+            .collect();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let order = graph.topo_order().unwrap();
+        assert_eq!(order[0].name, "lib0");
+        assert_eq!(order[19_999].name, "lib19999");
+        // Flags come from every library below, in name order.
+        let origins: Vec<_> = graph
+            .get("lib19999")
+            .unwrap()
+            .inherited_c_args
+            .iter()
+            .map(|layer| layer.origin.clone())
+            .collect();
+        assert_eq!(
+            origins,
+            [
+                Origin::Dependency("lib0".into()),
+                Origin::Dependency("lib10000".into())
+            ]
+        );
+        assert_eq!(graph.select(&["lib5".into()]).unwrap().nodes().count(), 6);
+    }
 }
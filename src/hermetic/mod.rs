@@ -0,0 +1,103 @@
+use std::ffi::OsString;
+use std::process::Command;
+
+/// Variables a hermetic command keeps from crust's environment besides the
+/// project's `passthrough_env`. Windows programs also need to find the
+/// system directory, temporary directory, and shell.
+#[cfg(not(windows))]
+const BASE_VARS: &[&str] = &["PATH"];
+#[cfg(windows)]
+const BASE_VARS: &[&str] = &[
+    "PATH",
+    "PATHEXT",
+    "SYSTEMROOT",
+    "WINDIR",
+    "COMSPEC",
+    "TEMP",
+    "TMP",
+];
+
+/// The environment of build commands under `--hermetic`: instead of
+/// inheriting everything, a command sees `PATH`, the variables the project
+/// lets through in `passthrough_env`, and those crust sets for it.
+#[derive(Debug, Clone, Default)]
+pub struct HermeticEnv {
+    passthrough: Vec<String>,
+}
+
+impl HermeticEnv {
+    pub fn new(passthrough: &[String]) -> Self {
+        HermeticEnv {
+            passthrough: passthrough.to_vec(),
+        }
+    }
+
+    /// Replaces the inherited environment of `cmd` with the allowed
+    /// variables of crust's own.
+    pub fn apply(&self, cmd: &mut Command) {
+        self.scrub(cmd, |name| std::env::var_os(name));
+    }
+
+    fn scrub(&self, cmd: &mut Command, lookup: impl Fn(&str) -> Option<OsString>) {
+        let explicit: Vec<(OsString, OsString)> = cmd
+            .get_envs()
+            .filter_map(|(key, value)| Some((key.to_owned(), value?.to_owned())))
+            .collect();
+        cmd.env_clear();
+        let allowed = BASE_VARS
+            .iter()
+            .copied()
+            .chain(self.passthrough.iter().map(String::as_str));
+        for name in allowed {
+            if let Some(value) = lookup(name) {
+                cmd.env(name, value);
+            }
+        }
+        cmd.envs(explicit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_path_passthrough_and_explicit_variables() {
+        let lookup = |name: &str| match name {
+            "PATH" => Some("/usr/bin:/bin".into()),
+            "CCACHE_DIR" => Some("/cache".into()),
+            "HOME" => Some("/home/me".into()),
+            _ => None,
+        };
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "env"]).env("CRUST_BUILDDIR", "build");
+        let hermetic = HermeticEnv::new(&["CCACHE_DIR".into(), "UNSET".into()]);
+        hermetic.scrub(&mut cmd, lookup);
+
+        let mut envs: Vec<String> = cmd
+            .get_envs()
+            .map(|(key, value)| {
+                format!(
+                    "{}={}",
+                    key.to_string_lossy(),
+                    value.unwrap_or_default().to_string_lossy()
+                )
+            })
+            .collect();
+        envs.sort();
+        assert_eq!(
+            envs,
+            [
+                "CCACHE_DIR=/cache",
+                "CRUST_BUILDDIR=build",
+                "PATH=/usr/bin:/bin"
+            ]
+        );
+        #[cfg(unix)]
+        {
+            let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+            assert!(output.contains("CCACHE_DIR=/cache\n"));
+            assert!(!output.contains("HOME="));
+        }
+    }
+}
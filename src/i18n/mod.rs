@@ -0,0 +1,157 @@
+use crate::compdb;
+use crate::graph::{DependencyGraph, TargetKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Translation settings of a gettext target, mirroring Meson's
+/// `i18n.gettext()`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GettextSettings {
+    /// Text domain; catalogs are installed as `<package>.mo`.
+    pub package: String,
+    /// Directory (relative to the manifest) holding `<lang>.po` files and
+    /// the `<package>.pot` template.
+    pub po_dir: String,
+    pub languages: Vec<String>,
+    /// Files scanned by `xgettext`; empty means every C/C++ source of the
+    /// project's compiled targets.
+    pub sources: Vec<String>,
+    pub xgettext_args: Vec<String>,
+}
+
+impl GettextSettings {
+    pub fn po_file(&self, language: &str) -> String {
+        format!("{}/{language}.po", self.po_dir)
+    }
+
+    pub fn pot_file(&self) -> String {
+        format!("{}/{}.pot", self.po_dir, self.package)
+    }
+
+    /// Compiled catalog of `language`, relative to the build directory.
+    pub fn catalog(&self, target: &str, language: &str) -> String {
+        format!("{target}/{}", self.locale_path(language).display())
+    }
+
+    /// Where the catalog of `language` lives below the locale directory.
+    pub fn locale_path(&self, language: &str) -> PathBuf {
+        Path::new(language)
+            .join("LC_MESSAGES")
+            .join(format!("{}.mo", self.package))
+    }
+}
+
+/// Describes an invalid language list, if any.
+pub fn languages_error(settings: &GettextSettings) -> Option<String> {
+    if settings.languages.is_empty() {
+        return Some("gettext targets need at least one language".into());
+    }
+    settings
+        .languages
+        .iter()
+        .find(|lang| lang.is_empty() || lang.contains(['/', '\\', '.']))
+        .map(|lang| format!("invalid language '{lang}'"))
+}
+
+/// Compiles a `.po` file into a binary `.mo` catalog.
+pub fn msgfmt_command(po: &Path, mo: &Path) -> Command {
+    let mut cmd = Command::new("msgfmt");
+    cmd.arg("--check").arg("-o").arg(mo).arg(po);
+    cmd
+}
+
+/// Extracts translatable strings from `sources` into the `.pot` template,
+/// recognizing the usual `_()` and `N_()` macros.
+pub fn xgettext_command(settings: &GettextSettings, pot: &Path, sources: &[PathBuf]) -> Command {
+    let mut cmd = Command::new("xgettext");
+    cmd.arg(format!("--package-name={}", settings.package))
+        .arg("--from-code=UTF-8")
+        .arg("--add-comments")
+        .arg("--force-po")
+        .arg("--keyword=_")
+        .arg("--keyword=N_")
+        .arg("-o")
+        .arg(pot)
+        .args(&settings.xgettext_args)
+        .args(sources);
+    cmd
+}
+
+/// Merges new template strings into an existing translation.
+pub fn msgmerge_command(po: &Path, pot: &Path) -> Command {
+    let mut cmd = Command::new("msgmerge");
+    cmd.arg("--quiet")
+        .arg("--update")
+        .arg("--backup=none")
+        .arg(po)
+        .arg(pot);
+    cmd
+}
+
+/// Starts a translation for `language` from the template.
+pub fn msginit_command(language: &str, po: &Path, pot: &Path) -> Command {
+    let mut cmd = Command::new("msginit");
+    cmd.arg("--no-translator")
+        .arg(format!("--locale={language}"))
+        .arg("-i")
+        .arg(pot)
+        .arg("-o")
+        .arg(po);
+    cmd
+}
+
+/// Files scanned for translatable strings, relative to the manifest.
+pub fn extraction_sources(graph: &DependencyGraph, settings: &GettextSettings) -> Vec<String> {
+    if !settings.sources.is_empty() {
+        return settings.sources.clone();
+    }
+    let mut sources: Vec<String> = graph
+        .nodes()
+        .filter(|node| {
+            matches!(
+                node.kind,
+                TargetKind::Executable | TargetKind::StaticLibrary | TargetKind::SharedLibrary
+            ) && node.arch.is_none()
+        })
+        .flat_map(|node| node.sources.iter())
+        .filter(|source| compdb::is_c_family(Path::new(source)))
+        .cloned()
+        .collect();
+    sources.sort();
+    sources.dedup();
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_catalogs_and_templates_per_language() {
+        let settings = GettextSettings {
+            package: "hello".into(),
+            po_dir: "po".into(),
+            languages: vec!["fr".into(), "pt_BR".into()],
+            sources: vec![],
+            xgettext_args: vec!["--keyword=tr".into()],
+        };
+        assert_eq!(settings.po_file("pt_BR"), "po/pt_BR.po");
+        assert_eq!(settings.pot_file(), "po/hello.pot");
+        assert_eq!(
+            settings.catalog("translations", "fr"),
+            "translations/fr/LC_MESSAGES/hello.mo"
+        );
+        assert_eq!(languages_error(&settings), None);
+
+        let cmd = xgettext_command(&settings, Path::new("po/hello.pot"), &["main.c".into()]);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args[0], "--package-name=hello");
+        assert_eq!(args[args.len() - 2..], ["--keyword=tr", "main.c"]);
+
+        let bad = GettextSettings {
+            languages: vec!["../fr".into()],
+            ..settings
+        };
+        assert!(languages_error(&bad).is_some());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::compdb::{CompileCommand, COMPILE_DATABASE};
+use crate::devenv::{library_path_var, DevEnv};
+use crate::graph::{DependencyGraph, TargetKind, TargetNode};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// File clangd (and CLion) read compiler flags from when no compilation
+/// database is configured.
+pub const COMPILE_FLAGS: &str = "compile_flags.txt";
+
+fn task_label(target: Option<&str>) -> String {
+    match target {
+        Some(name) => format!("crust: build {name}"),
+        None => "crust: build".into(),
+    }
+}
+
+/// Targets as declared in the manifest (architecture variants folded into
+/// the target they come from).
+fn declared_targets(graph: &DependencyGraph) -> impl Iterator<Item = &TargetNode> {
+    graph.nodes().filter(|node| node.name == node.base_name())
+}
+
+/// `.vscode/tasks.json`: a default build task, one build task per target,
+/// and the default test task. `builddir` is how the workspace refers to the
+/// build directory.
+pub fn vscode_tasks(graph: &DependencyGraph, builddir: &str) -> Value {
+    let task = |target: Option<&str>| {
+        let mut args = vec!["build".to_string(), "-b".into(), builddir.to_string()];
+        args.extend(target.map(str::to_string));
+        json!({
+            "label": task_label(target),
+            "type": "shell",
+            "command": "crust",
+            "args": args,
+            "problemMatcher": ["$gcc"],
+        })
+    };
+    let mut tasks = vec![task(None)];
+    tasks[0]["group"] = json!({ "kind": "build", "isDefault": true });
+    tasks.extend(declared_targets(graph).map(|node| task(Some(&node.name))));
+    tasks.push(json!({
+        "label": "crust: test",
+        "type": "shell",
+        "command": "crust",
+        "args": ["test", "-b", builddir],
+        "group": { "kind": "test", "isDefault": true },
+        "problemMatcher": ["$gcc"],
+    }));
+    json!({ "version": "2.0.0", "tasks": tasks })
+}
+
+/// `.vscode/launch.json`: a debug configuration per executable that builds
+/// it first and can load the project's shared libraries uninstalled.
+/// `workspace_builddir` is the build directory as a `${workspaceFolder}` path.
+pub fn vscode_launch(graph: &DependencyGraph, env: &DevEnv, workspace_builddir: &str) -> Value {
+    let lib_var = library_path_var();
+    let environment: Vec<Value> = env
+        .vars
+        .iter()
+        .filter(|(var, _)| var == lib_var)
+        .map(|(var, dirs)| {
+            let mut parts: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+            parts.push(format!("${{env:{var}}}"));
+            let separator = if cfg!(windows) { ";" } else { ":" };
+            json!({ "name": var, "value": parts.join(separator) })
+        })
+        .collect();
+    let configurations: Vec<Value> = declared_targets(graph)
+        .filter(|node| node.kind == TargetKind::Executable)
+        .filter_map(|node| {
+            let output = node.outputs.first()?;
+            Some(json!({
+                "name": format!("Debug {}", node.name),
+                "type": "cppdbg",
+                "request": "launch",
+                "program": format!("{workspace_builddir}/{output}"),
+                "args": [],
+                "cwd": "${workspaceFolder}",
+                "environment": environment,
+                "MIMode": if cfg!(target_os = "macos") { "lldb" } else { "gdb" },
+                "preLaunchTask": task_label(Some(&node.name)),
+            }))
+        })
+        .collect();
+    json!({ "version": "0.2.0", "configurations": configurations })
+}
+
+/// `.vscode/c_cpp_properties.json` pointing IntelliSense at the
+/// compilation database.
+pub fn vscode_cpp_properties(workspace_builddir: &str) -> Value {
+    json!({
+        "version": 4,
+        "configurations": [{
+            "name": "crust",
+            "compileCommands": format!("{workspace_builddir}/{COMPILE_DATABASE}"),
+        }],
+    })
+}
+
+/// Contents of `compile_flags.txt`: every flag used to compile the
+/// project's sources, once, without the per-file input, output, and
+/// dependency-file arguments.
+pub fn compile_flags(commands: &[CompileCommand]) -> String {
+    let mut flags: Vec<&str> = Vec::new();
+    for command in commands {
+        let mut args = command.arguments.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "-MD" | "-MMD" => {}
+                "-o" | "-MF" | "-MT" | "-MQ" => {
+                    args.next();
+                }
+                _ if Path::new(arg) == command.file => {}
+                _ if !flags.contains(&arg.as_str()) => flags.push(arg),
+                _ => {}
+            }
+        }
+    }
+    flags.iter().map(|flag| format!("{flag}\n")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
+    use std::path::PathBuf;
+
+    #[test]
+    fn generates_tasks_launch_configs_and_flags() {
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::SharedLibrary {
+                    name: "core".into(),
+                    sources: vec!["core.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+                Target::Executable {
+                    name: "app".into(),
+                    sources: vec!["main.c".into()],
+                    deps: vec!["core".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+
+        let tasks = vscode_tasks(&graph, "build");
+        let labels: Vec<&str> = tasks["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|task| task["label"].as_str().unwrap())
+            .collect();
+        assert!(labels.contains(&"crust: build app"));
+        assert!(labels.contains(&"crust: build core"));
+        assert_eq!(tasks["tasks"][0]["group"]["isDefault"], true);
+
+        let env = DevEnv::from_graph(&graph, Path::new("build")).unwrap();
+        let launch = vscode_launch(&graph, &env, "${workspaceFolder}/build");
+        let configs = launch["configurations"].as_array().unwrap();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0]["program"], "${workspaceFolder}/build/app");
+        assert_eq!(configs[0]["preLaunchTask"], "crust: build app");
+
+        let command = |file: &str| CompileCommand {
+            directory: PathBuf::from("/src"),
+            file: PathBuf::from(file),
+            arguments: [
+                "cc",
+                "-Iinclude",
+                "-MMD",
+                "-MF",
+                "x.d",
+                "-c",
+                file,
+                "-o",
+                "x.o",
+            ]
+            .map(String::from)
+            .to_vec(),
+            output: PathBuf::from("x.o"),
+        };
+        assert_eq!(
+            compile_flags(&[command("/src/a.c"), command("/src/b.c")]),
+            "-Iinclude\n"
+        );
+    }
+}
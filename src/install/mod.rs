@@ -0,0 +1,702 @@
+use crate::config::{Component, ProjectManifest};
+use crate::digest::sha256_file;
+use crate::error::CrustError;
+use crate::graph::{DependencyGraph, TargetKind};
+use crate::man::ManPage;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+
+/// File name of the install manifest written into the build directory.
+pub const INSTALL_MANIFEST: &str = "install-manifest.json";
+
+/// Installation layout: everything lands below `destdir` + `prefix`, in the
+/// directories named relative to the prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallDirs {
+    pub prefix: PathBuf,
+    /// Staging root prepended to every destination (`DESTDIR`).
+    pub destdir: Option<PathBuf>,
+    pub bindir: PathBuf,
+    pub libdir: PathBuf,
+    pub datadir: PathBuf,
+    pub localedir: PathBuf,
+    pub mandir: PathBuf,
+    /// site-packages of the `[python]` interpreter, asked from it when the
+    /// project has python targets.
+    pub pythondir: Option<PathBuf>,
+}
+
+impl InstallDirs {
+    pub fn new(prefix: PathBuf, destdir: Option<PathBuf>) -> Self {
+        InstallDirs {
+            prefix,
+            destdir,
+            bindir: "bin".into(),
+            libdir: "lib".into(),
+            datadir: "share".into(),
+            localedir: "share/locale".into(),
+            mandir: "share/man".into(),
+            pythondir: None,
+        }
+    }
+
+    /// Absolute path of `relative` (below the prefix) on this machine,
+    /// including the staging `destdir`.
+    pub fn resolve(&self, relative: &Path) -> PathBuf {
+        let installed = self.prefix.join(relative);
+        match &self.destdir {
+            Some(destdir) => destdir.join(
+                installed
+                    .components()
+                    .filter(|c| !matches!(c, path::Component::RootDir | path::Component::Prefix(_)))
+                    .collect::<PathBuf>(),
+            ),
+            None => installed,
+        }
+    }
+}
+
+/// One file copied from the build directory during `crust install`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallEntry {
+    pub target: String,
+    pub source: PathBuf,
+    /// Destination relative to the prefix.
+    pub destination: PathBuf,
+    /// Signing command run on the installed copy (`${OUTPUT}` expands to it).
+    pub sign: Option<String>,
+    /// Permission bits set on the installed copy instead of the source's.
+    pub mode: Option<u32>,
+    pub component: Component,
+}
+
+/// Every file the project installs: artifacts of compiled targets with
+/// `install = true`, gettext catalogs, and man pages in dependency order,
+/// followed by `install_data` files from the source tree.
+pub fn plan(
+    manifest: &ProjectManifest,
+    graph: &DependencyGraph,
+    manifest_dir: &Path,
+    builddir: &Path,
+    dirs: &InstallDirs,
+) -> Result<Vec<InstallEntry>> {
+    let mut entries = Vec::new();
+    for node in graph.topo_order()? {
+        match node.kind {
+            TargetKind::Executable | TargetKind::StaticLibrary | TargetKind::SharedLibrary
+                if node.options.install =>
+            {
+                let dir = if node.kind == TargetKind::Executable {
+                    &dirs.bindir
+                } else {
+                    &dirs.libdir
+                };
+                for output in node.products() {
+                    let file_name = Path::new(output).file_name().unwrap_or_default();
+                    entries.push(InstallEntry {
+                        target: node.name.clone(),
+                        source: builddir.join(output),
+                        destination: dir.join(file_name),
+                        sign: node.options.sign.clone(),
+                        mode: None,
+                        component: node.options.install_component.unwrap_or(
+                            if node.kind == TargetKind::StaticLibrary {
+                                Component::Devel
+                            } else {
+                                Component::Runtime
+                            },
+                        ),
+                    });
+                }
+            }
+            TargetKind::Gettext => {
+                let Some(settings) = &node.gettext else {
+                    continue;
+                };
+                for (language, output) in settings.languages.iter().zip(&node.outputs) {
+                    entries.push(InstallEntry {
+                        target: node.name.clone(),
+                        source: builddir.join(output),
+                        destination: dirs.localedir.join(settings.locale_path(language)),
+                        sign: None,
+                        mode: None,
+                        component: Component::Runtime,
+                    });
+                }
+            }
+            TargetKind::Man => {
+                for (source, output) in node.sources.iter().zip(&node.outputs) {
+                    let Some(page) = ManPage::parse(source) else {
+                        continue;
+                    };
+                    entries.push(InstallEntry {
+                        target: node.name.clone(),
+                        source: builddir.join(output),
+                        destination: dirs.mandir.join(page.install_subdir()).join(&page.page),
+                        sign: None,
+                        mode: None,
+                        component: Component::Doc,
+                    });
+                }
+            }
+            TargetKind::PythonExtension | TargetKind::PythonSources
+                if node.kind == TargetKind::PythonSources || node.options.install =>
+            {
+                let (Some(pythondir), Some(settings)) = (&dirs.pythondir, &node.python) else {
+                    return Err(anyhow!(
+                        "Cannot install '{}': the Python site-packages directory is unknown",
+                        node.name
+                    ));
+                };
+                for output in node.products() {
+                    let file_name = Path::new(output).file_name().unwrap_or_default();
+                    entries.push(InstallEntry {
+                        target: node.name.clone(),
+                        source: builddir.join(output),
+                        destination: pythondir
+                            .join(settings.module_path(&file_name.to_string_lossy())),
+                        sign: node.options.sign.clone(),
+                        mode: None,
+                        component: node.options.install_component.unwrap_or(Component::Runtime),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for data in &manifest.install_data {
+        let dir = dirs.datadir.join(
+            data.install_dir
+                .as_deref()
+                .unwrap_or(&manifest.project.name),
+        );
+        for (idx, source) in data.sources.iter().enumerate() {
+            let file_name = match data.rename.get(idx) {
+                Some(name) => Path::new(name),
+                None => Path::new(source)
+                    .file_name()
+                    .map(Path::new)
+                    .unwrap_or(Path::new(source)),
+            };
+            entries.push(InstallEntry {
+                target: "install_data".into(),
+                source: manifest_dir.join(source),
+                destination: dir.join(file_name),
+                sign: None,
+                mode: data.install_mode,
+                component: data.component,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// A link or directory `crust install` creates after copying files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallRule {
+    Symlink {
+        /// Link path relative to the prefix.
+        destination: PathBuf,
+        pointing_to: PathBuf,
+        component: Component,
+    },
+    EmptyDir {
+        /// Directory relative to the prefix.
+        destination: PathBuf,
+        mode: Option<u32>,
+        component: Component,
+    },
+}
+
+impl InstallRule {
+    pub fn component(&self) -> Component {
+        match self {
+            InstallRule::Symlink { component, .. } | InstallRule::EmptyDir { component, .. } => {
+                *component
+            }
+        }
+    }
+}
+
+/// `install_emptydir` and `install_symlink` rules, directories first so
+/// links may point into them.
+pub fn rules(manifest: &ProjectManifest) -> Vec<InstallRule> {
+    let dirs = manifest.install_emptydir.iter().flat_map(|rule| {
+        rule.dirs.iter().map(|dir| InstallRule::EmptyDir {
+            destination: dir.into(),
+            mode: rule.install_mode,
+            component: rule.component,
+        })
+    });
+    let links = manifest
+        .install_symlink
+        .iter()
+        .map(|link| InstallRule::Symlink {
+            destination: (&link.name).into(),
+            pointing_to: (&link.pointing_to).into(),
+            component: link.component,
+        });
+    dirs.chain(links).collect()
+}
+
+/// Creates the link or directory of `rule` below `dirs`, replacing an
+/// existing link, and returns how it was recorded.
+pub fn apply_rule(rule: &InstallRule, dirs: &InstallDirs) -> Result<InstalledFile> {
+    match rule {
+        InstallRule::EmptyDir {
+            destination,
+            mode,
+            component,
+        } => {
+            let path = dirs.resolve(destination);
+            fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            if let Some(mode) = mode {
+                set_mode(&path, *mode)?;
+            }
+            Ok(InstalledFile {
+                path,
+                target: "install_emptydir".into(),
+                component: *component,
+                kind: InstalledKind::Directory,
+                sha256: None,
+                pointing_to: None,
+            })
+        }
+        InstallRule::Symlink {
+            destination,
+            pointing_to,
+            component,
+        } => {
+            let path = dirs.resolve(destination);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            if fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to replace {}", path.display()))?;
+            }
+            symlink(pointing_to, &path)?;
+            Ok(InstalledFile {
+                path,
+                target: "install_symlink".into(),
+                component: *component,
+                kind: InstalledKind::Symlink,
+                sha256: None,
+                pointing_to: Some(pointing_to.clone()),
+            })
+        }
+    }
+}
+
+#[cfg(unix)]
+fn symlink(pointing_to: &Path, path: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(pointing_to, path).with_context(|| {
+        format!(
+            "Failed to link {} -> {}",
+            path.display(),
+            pointing_to.display()
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn symlink(_pointing_to: &Path, path: &Path) -> Result<()> {
+    Err(CrustError::InvalidArgument {
+        message: format!(
+            "Cannot install symlink {}: symlinks are only supported on Unix",
+            path.display()
+        ),
+    }
+    .into())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstalledKind {
+    File,
+    Symlink,
+    Directory,
+}
+
+/// An entry placed on disk by `crust install`.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct InstalledFile {
+    /// Final path, including `destdir`.
+    pub path: PathBuf,
+    pub target: String,
+    pub component: Component,
+    pub kind: InstalledKind,
+    /// Digest of regular files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Contents of a symlink.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointing_to: Option<PathBuf>,
+}
+
+impl InstalledFile {
+    pub fn describe(entry: &InstallEntry, path: PathBuf) -> Result<Self> {
+        Ok(InstalledFile {
+            sha256: Some(sha256_file(&path)?),
+            path,
+            target: entry.target.clone(),
+            component: entry.component,
+            kind: InstalledKind::File,
+            pointing_to: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallManifest {
+    pub version: u32,
+    pub prefix: PathBuf,
+    pub files: Vec<InstalledFile>,
+}
+
+/// Copies `entry` into place, keeping the source's permissions unless the
+/// entry sets a mode, and returns the installed path.
+pub fn install_file(entry: &InstallEntry, dirs: &InstallDirs) -> Result<PathBuf> {
+    if !entry.source.is_file() {
+        return Err(CrustError::InvalidArgument {
+            message: format!(
+                "Cannot install {}: file not found (run `crust build` first if it is generated)",
+                entry.source.display()
+            ),
+        }
+        .into());
+    }
+    let path = dirs.resolve(&entry.destination);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    // Replace rather than overwrite so running binaries keep their inode.
+    if path.exists() {
+        fs::remove_file(&path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    }
+    fs::copy(&entry.source, &path).with_context(|| {
+        format!(
+            "Failed to install {} to {}",
+            entry.source.display(),
+            path.display()
+        )
+    })?;
+    if let Some(mode) = entry.mode {
+        set_mode(&path, mode)?;
+    }
+    Ok(path)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set mode {mode:o} on {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Writes `builddir/install-manifest.json` and returns its path.
+pub fn write_manifest(
+    builddir: &Path,
+    dirs: &InstallDirs,
+    files: Vec<InstalledFile>,
+) -> Result<PathBuf> {
+    let path = builddir.join(INSTALL_MANIFEST);
+    let manifest = InstallManifest {
+        version: 2,
+        prefix: dirs.prefix.clone(),
+        files,
+    };
+    let content = serde_json::to_string_pretty(&manifest)?;
+    fs::write(&path, content + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        BoltSettings, CompileOptions, InstallData, InstallEmptyDir, InstallSymlink, ProjectInfo,
+        Target,
+    };
+    use crate::cross::Platform;
+    use tempfile::tempdir;
+
+    #[test]
+    fn installs_marked_artifacts_catalogs_and_man_pages_below_destdir() {
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "hello".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::Executable {
+                    name: "hello".into(),
+                    sources: vec!["main.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: CompileOptions {
+                        install: true,
+                        generate_map: true,
+                        ..Default::default()
+                    },
+                },
+                Target::Executable {
+                    name: "tuned".into(),
+                    sources: vec!["tuned.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: CompileOptions {
+                        install: true,
+                        bolt: Some(BoltSettings {
+                            profile: None,
+                            args: Vec::new(),
+                        }),
+                        ..Default::default()
+                    },
+                },
+                Target::StaticLibrary {
+                    name: "internal".into(),
+                    sources: vec!["util.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+                Target::Gettext {
+                    name: "translations".into(),
+                    package: None,
+                    po_dir: "po".into(),
+                    languages: vec!["fr".into()],
+                    sources: vec![],
+                    xgettext_args: vec![],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                },
+                Target::Man {
+                    name: "manpages".into(),
+                    sources: vec!["doc/hello.1.scd".into()],
+                    tool: None,
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let build = tempdir().unwrap();
+        let stage = tempdir().unwrap();
+        let dirs = InstallDirs::new("/usr".into(), Some(stage.path().to_path_buf()));
+
+        let mut entries = plan(&manifest, &graph, Path::new("."), build.path(), &dirs).unwrap();
+        entries.sort_by(|a, b| a.destination.cmp(&b.destination));
+        let destinations: Vec<_> = entries.iter().map(|e| e.destination.clone()).collect();
+        assert_eq!(
+            destinations,
+            vec![
+                PathBuf::from("bin/hello"),
+                PathBuf::from("bin/tuned"),
+                PathBuf::from("share/locale/fr/LC_MESSAGES/hello.mo"),
+                PathBuf::from("share/man/man1/hello.1"),
+            ]
+        );
+        let components: Vec<_> = entries.iter().map(|e| e.component).collect();
+        assert_eq!(
+            components,
+            [
+                Component::Runtime,
+                Component::Runtime,
+                Component::Runtime,
+                Component::Doc
+            ]
+        );
+
+        assert!(install_file(&entries[0], &dirs).is_err());
+        fs::write(build.path().join("hello"), "binary").unwrap();
+        let installed = install_file(&entries[0], &dirs).unwrap();
+        assert_eq!(installed, stage.path().join("usr/bin/hello"));
+        assert_eq!(fs::read_to_string(&installed).unwrap(), "binary");
+    }
+
+    #[test]
+    fn installs_subdir_targets_without_their_byproducts() {
+        let src = tempdir().unwrap();
+        fs::write(
+            src.path().join("crust.build"),
+            "[project]\nname = \"hello\"\nsubdirs = [\"sub\"]\n",
+        )
+        .unwrap();
+        fs::create_dir(src.path().join("sub")).unwrap();
+        fs::write(
+            src.path().join("sub/crust.build"),
+            r#"[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+install = true
+generate_map = true
+post_build = ["cp ${OUTPUT} ${OUTPUT}.bin"]
+post_build_outputs = ["app.bin"]
+"#,
+        )
+        .unwrap();
+        let manifest = ProjectManifest::load(&src.path().join("crust.build")).unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let build = tempdir().unwrap();
+        let dirs = InstallDirs::new("/usr".into(), None);
+
+        let entries = plan(&manifest, &graph, src.path(), build.path(), &dirs).unwrap();
+        let installed: Vec<_> = entries
+            .iter()
+            .map(|e| (e.source.clone(), e.destination.clone()))
+            .collect();
+        assert_eq!(
+            installed,
+            [(build.path().join("sub/app"), PathBuf::from("bin/app"))]
+        );
+    }
+
+    #[test]
+    fn installs_data_files_with_rename_and_mode() {
+        let src = tempdir().unwrap();
+        let stage = tempdir().unwrap();
+        fs::write(src.path().join("hello.desktop"), "[Desktop Entry]").unwrap();
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "hello".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![],
+            install_data: vec![
+                InstallData {
+                    sources: vec!["hello.desktop".into()],
+                    install_dir: Some("applications".into()),
+                    install_mode: Some(0o600),
+                    rename: vec!["org.example.Hello.desktop".into()],
+                    component: Component::Runtime,
+                },
+                InstallData {
+                    sources: vec!["data/palette.gpl".into()],
+                    install_dir: None,
+                    install_mode: None,
+                    rename: vec![],
+                    component: Component::Doc,
+                },
+            ],
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let dirs = InstallDirs::new("/usr".into(), Some(stage.path().to_path_buf()));
+        let entries = plan(&manifest, &graph, src.path(), Path::new("build"), &dirs).unwrap();
+        assert_eq!(
+            entries[0].destination,
+            PathBuf::from("share/applications/org.example.Hello.desktop")
+        );
+        assert_eq!(
+            entries[1].destination,
+            PathBuf::from("share/hello/palette.gpl")
+        );
+
+        let installed = install_file(&entries[0], &dirs).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&installed).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(install_file(&entries[1], &dirs).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn creates_empty_dirs_and_replaces_symlinks() {
+        let stage = tempdir().unwrap();
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "hello".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![],
+            install_data: Vec::new(),
+            install_symlink: vec![InstallSymlink {
+                name: "sbin/hello".into(),
+                pointing_to: "../bin/hello".into(),
+                component: Component::Runtime,
+            }],
+            install_emptydir: vec![InstallEmptyDir {
+                dirs: vec!["var/lib/hello".into()],
+                install_mode: Some(0o700),
+                component: Component::Runtime,
+            }],
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        let dirs = InstallDirs::new("/usr".into(), Some(stage.path().to_path_buf()));
+        let rules = rules(&manifest);
+        assert!(matches!(rules[0], InstallRule::EmptyDir { .. }));
+
+        let dir = apply_rule(&rules[0], &dirs).unwrap();
+        assert_eq!(dir.kind, InstalledKind::Directory);
+        assert!(stage.path().join("usr/var/lib/hello").is_dir());
+
+        for _ in 0..2 {
+            let link = apply_rule(&rules[1], &dirs).unwrap();
+            assert_eq!(link.path, stage.path().join("usr/sbin/hello"));
+            assert_eq!(
+                fs::read_link(&link.path).unwrap(),
+                PathBuf::from("../bin/hello")
+            );
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::toolchain;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// A compiled language beyond the C family, Fortran, and CUDA, whose
+/// sources are compiled to objects by their own compiler and linked by the
+/// C compiler driver with the rest of the target.
+#[derive(Debug)]
+pub struct Language {
+    /// Name used as the key in a cross file's `[compilers]`.
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Extension of the objects the compiler writes.
+    pub object_extension: &'static str,
+    /// Compilers that can build the language, the preferred one first.
+    pub profiles: &'static [Profile],
+}
+
+/// How one compiler of a language is run.
+#[derive(Debug)]
+pub struct Profile {
+    pub compiler: &'static str,
+    /// Arguments compiling one source, with `{source}` and `{object}`
+    /// replaced by the paths.
+    pub compile: &'static [&'static str],
+    /// Libraries the C driver links when objects of the language are
+    /// linked, such as the language runtime.
+    pub link: &'static [&'static str],
+    /// Directories, relative to the installation holding `bin/<compiler>`,
+    /// searched for those libraries; the first that exists is used.
+    pub lib_dirs: &'static [&'static str],
+}
+
+/// Every registered language.
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        name: "d",
+        extensions: &["d"],
+        object_extension: "o",
+        profiles: &[
+            Profile {
+                compiler: "ldc2",
+                compile: &["-c", "{source}", "-of={object}"],
+                link: &[
+                    "-lphobos2-ldc",
+                    "-ldruntime-ldc",
+                    "-lpthread",
+                    "-lm",
+                    "-ldl",
+                ],
+                lib_dirs: &["lib64", "lib"],
+            },
+            Profile {
+                compiler: "dmd",
+                compile: &["-c", "{source}", "-of{object}"],
+                link: &["-lphobos2", "-lpthread", "-lm"],
+                lib_dirs: &["lib64", "lib"],
+            },
+        ],
+    },
+    Language {
+        name: "zig",
+        extensions: &["zig"],
+        object_extension: "o",
+        profiles: &[Profile {
+            compiler: "zig",
+            // compiler_rt is bundled (with weak symbols) because the C
+            // driver does not know where zig keeps it.
+            compile: &[
+                "build-obj",
+                "{source}",
+                "-femit-bin={object}",
+                "-fPIC",
+                "-fcompiler-rt",
+                "-lc",
+            ],
+            link: &[],
+            lib_dirs: &[],
+        }],
+    },
+];
+
+/// The registered language of `path`, by extension.
+pub fn for_source(path: &Path) -> Option<&'static Language> {
+    let ext = path.extension()?.to_str()?;
+    LANGUAGES
+        .iter()
+        .find(|language| language.extensions.contains(&ext))
+}
+
+/// The registered language called `name`.
+pub fn named(name: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().find(|language| language.name == name)
+}
+
+impl Language {
+    /// Compiler program and profile to use. A `configured` program (from
+    /// the cross file) uses the profile whose compiler its file name starts
+    /// with, such as `ldc2` for `/opt/ldc/bin/ldc2`. Otherwise the first
+    /// profile whose compiler is on `PATH` is used, or the preferred one.
+    pub fn resolve(&self, configured: Option<&str>) -> (String, &Profile) {
+        if let Some(program) = configured {
+            let file_name = Path::new(program)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(program);
+            let profile = self
+                .profiles
+                .iter()
+                .find(|profile| file_name.starts_with(profile.compiler))
+                .unwrap_or(&self.profiles[0]);
+            return (program.to_string(), profile);
+        }
+        let profile = self
+            .profiles
+            .iter()
+            .find(|profile| toolchain::find_program(profile.compiler).is_some())
+            .unwrap_or(&self.profiles[0]);
+        (profile.compiler.to_string(), profile)
+    }
+}
+
+impl Profile {
+    /// Compile arguments for `source`, writing `object`.
+    pub fn compile_args(&self, source: &Path, object: &Path) -> Vec<OsString> {
+        self.compile
+            .iter()
+            .map(|arg| {
+                let mut out = OsString::new();
+                let mut rest = *arg;
+                while let Some(start) = rest.find('{') {
+                    out.push(&rest[..start]);
+                    let after = &rest[start..];
+                    if let Some(tail) = after.strip_prefix("{source}") {
+                        out.push(source);
+                        rest = tail;
+                    } else if let Some(tail) = after.strip_prefix("{object}") {
+                        out.push(object);
+                        rest = tail;
+                    } else {
+                        out.push("{");
+                        rest = &after[1..];
+                    }
+                }
+                out.push(rest);
+                out
+            })
+            .collect()
+    }
+
+    /// Linker flags for the language runtime, searching the library
+    /// directory of the installation holding `compiler` when it is found.
+    pub fn link_flags(&self, compiler: Option<&Path>) -> Vec<String> {
+        if self.link.is_empty() {
+            return Vec::new();
+        }
+        let root = compiler.and_then(|path| path.parent()?.parent());
+        let mut flags: Vec<String> = root
+            .into_iter()
+            .flat_map(|root| self.lib_dirs.iter().map(move |dir| root.join(dir)))
+            .filter(|dir| dir.is_dir())
+            .take(1)
+            .map(|dir| format!("-L{}", dir.display()))
+            .collect();
+        flags.extend(self.link.iter().map(|lib| lib.to_string()));
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_profiles_and_fills_templates() {
+        let d = for_source(Path::new("src/app.d")).unwrap();
+        assert_eq!(d.name, "d");
+        let (program, profile) = d.resolve(Some("/opt/dmd2/linux/bin64/dmd"));
+        assert_eq!(program, "/opt/dmd2/linux/bin64/dmd");
+        assert_eq!(profile.compiler, "dmd");
+        assert_eq!(
+            profile.compile_args(Path::new("src/app.d"), Path::new("build/app_0.o")),
+            ["-c", "src/app.d", "-ofbuild/app_0.o"]
+        );
+        assert_eq!(profile.link_flags(None), ["-lphobos2", "-lpthread", "-lm"]);
+
+        let zig = named("zig").unwrap();
+        let (_, profile) = zig.resolve(Some("zig"));
+        assert_eq!(
+            profile.compile_args(Path::new("hash.zig"), Path::new("build/h_1.o"))[..3],
+            ["build-obj", "hash.zig", "-femit-bin=build/h_1.o"]
+        );
+        assert!(profile.link_flags(None).is_empty());
+        assert!(for_source(Path::new("main.c")).is_none());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::compdb;
+use crate::executor::{BuildExecutor, TargetRunResult};
+use crate::graph::DependencyGraph;
+use crate::runner::spawn_error;
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+/// A single `file:line:col: severity: message` line reported by a linter.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line: u32,
+    pub column: u32,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.severity,
+            self.message
+        )
+    }
+}
+
+/// Extracts clang-style diagnostics from linter output, ignoring the source
+/// excerpts and caret lines printed in between.
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    output.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    let (severity, marker) = [
+        (Severity::Error, ": error: "),
+        (Severity::Warning, ": warning: "),
+        (Severity::Note, ": note: "),
+    ]
+    .into_iter()
+    .find(|(_, marker)| line.contains(marker))?;
+    let (location, message) = line.split_once(marker)?;
+    // Split from the right so Windows drive letters stay in the path.
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line_no = parts.next()?.parse().ok()?;
+    let file = PathBuf::from(parts.next()?);
+    Some(Diagnostic {
+        file,
+        line: line_no,
+        column,
+        severity,
+        message: message.trim().to_string(),
+    })
+}
+
+/// Linter invocation: `<program> <args...> -p <database dir> <file>`.
+#[derive(Debug, Clone)]
+pub struct Linter {
+    pub program: String,
+    pub args: Vec<String>,
+    pub database_dir: PathBuf,
+}
+
+/// Diagnostics grouped by the target whose source produced them.
+#[derive(Debug, Default)]
+pub struct LintReport {
+    pub files: usize,
+    pub by_target: BTreeMap<String, BTreeSet<Diagnostic>>,
+    /// Files the linter failed on without printing an error diagnostic.
+    pub failures: Vec<String>,
+}
+
+impl LintReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.by_target
+            .values()
+            .flatten()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+}
+
+/// Runs `linter` over every C-family source in `graph`, one target per
+/// executor task. Each file is linted once even if several targets list it,
+/// and each diagnostic is reported once even if several files include the
+/// header it points at.
+pub fn run(
+    graph: &DependencyGraph,
+    manifest_dir: &Path,
+    linter: &Linter,
+    parallelism: Option<usize>,
+) -> Result<LintReport> {
+    let report = Arc::new(Mutex::new(LintReport::default()));
+    let claimed = Arc::new(Mutex::new(HashSet::new()));
+    let reported = Arc::new(Mutex::new(HashSet::new()));
+    let manifest_dir = manifest_dir.to_path_buf();
+    let linter = linter.clone();
+    let task_report = Arc::clone(&report);
+
+    BuildExecutor::new(parallelism).execute(graph, move |node, _| {
+        let start = Instant::now();
+        if node.command.is_some() {
+            return Ok(TargetRunResult::skipped(Vec::new(), start.elapsed()));
+        }
+        for source in &node.sources {
+            let file = manifest_dir.join(source);
+            if !compdb::is_c_family(&file)
+                || !claimed.lock().expect("lint mutex").insert(file.clone())
+            {
+                continue;
+            }
+            info!(file = %file.display(), "Linting {}", source);
+            let mut cmd = Command::new(&linter.program);
+            cmd.args(&linter.args)
+                .arg("-p")
+                .arg(&linter.database_dir)
+                .arg(&file);
+            let output = cmd
+                .output()
+                .map_err(|err| spawn_error(cmd.get_program(), err))?;
+            let text = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            let diagnostics = parse_diagnostics(&text);
+            debug!(file = %file.display(), count = diagnostics.len(), "lint finished");
+
+            let mut report = task_report.lock().expect("lint mutex");
+            report.files += 1;
+            if !output.status.success()
+                && !diagnostics.iter().any(|d| d.severity == Severity::Error)
+            {
+                report.failures.push(format!(
+                    "{} exited with {} on {}",
+                    linter.program, output.status, source
+                ));
+            }
+            let mut reported = reported.lock().expect("lint mutex");
+            let fresh: Vec<Diagnostic> = diagnostics
+                .into_iter()
+                .filter(|d| reported.insert(d.clone()))
+                .collect();
+            if !fresh.is_empty() {
+                report
+                    .by_target
+                    .entry(node.base_name().to_string())
+                    .or_default()
+                    .extend(fresh);
+            }
+        }
+        Ok(TargetRunResult::built(Vec::new(), start.elapsed()))
+    })?;
+
+    let report = std::mem::take(&mut *report.lock().expect("lint mutex"));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_clang_tidy_output() {
+        let output = "\
+/work/src/main.c:4:5: warning: Value stored to 'x' is never read [clang-analyzer-deadcode.DeadStores]
+    x = 2;
+    ^   ~
+/work/src/util.h:1:10: error: 'missing.h' file not found [clang-diagnostic-error]
+C:\\work\\win.c:2:1: note: expanded from macro 'FOO'
+1 warning and 1 error generated.
+";
+        let diagnostics = parse_diagnostics(output);
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].file, PathBuf::from("/work/src/main.c"));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (4, 5));
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert!(diagnostics[0]
+            .message
+            .ends_with("[clang-analyzer-deadcode.DeadStores]"));
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(diagnostics[2].file, PathBuf::from("C:\\work\\win.c"));
+    }
+}
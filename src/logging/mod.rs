@@ -0,0 +1,289 @@
+use crate::diagnostics;
+use crate::ui;
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use std::fmt;
+use std::io::IsTerminal;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable console lines that mirror crust's classic output
+    Text,
+    /// One JSON object per event, including the active target/command spans
+    Json,
+}
+
+/// When console output is colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// When writing to a terminal and `NO_COLOR` is unset
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color output written to a stream that is a `terminal`.
+    fn enabled(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Installs the global tracing subscriber. Text output goes to stdout so the
+/// progress lines keep their familiar shape; JSON goes to stderr so it can be
+/// captured separately from the build summary. When stdout carries a
+/// protocol (`crust lsp`) or output meant for scripts (`crust query`), text
+/// output goes to stderr as well. While the build monitor of `crust build
+/// --ui` runs, events go to it instead. `color` decides whether text output
+/// is colored.
+pub fn init(
+    level: LevelFilter,
+    format: LogFormat,
+    color: ColorChoice,
+    stdout_reserved: bool,
+) -> Result<()> {
+    let output: Box<dyn Layer<Registry> + Send + Sync> = match format {
+        LogFormat::Text if stdout_reserved => tracing_subscriber::fmt::layer()
+            .event_format(ConsoleFormat)
+            .with_ansi(color.enabled(std::io::stderr().is_terminal()))
+            .with_writer(std::io::stderr)
+            .boxed(),
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .event_format(ConsoleFormat)
+            .with_ansi(color.enabled(std::io::stdout().is_terminal()))
+            .with_writer(std::io::stdout)
+            .boxed(),
+        LogFormat::Json => json_layer(std::io::stderr).boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(
+            output
+                .with_filter(level)
+                .with_filter(filter_fn(|_| ui::monitor().is_none())),
+        )
+        .with(MonitorLayer.with_filter(level))
+        .try_init()
+        .map_err(|err| anyhow!("Failed to initialize logging: {}", err))
+}
+
+/// The `--log-format json` output: one object per event, with its fields
+/// and the spans it happened in, written to `writer`.
+fn json_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .json()
+        .with_ansi(false)
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(writer)
+}
+
+/// Name of the target a `target` span runs, kept in the span's extensions.
+struct TargetName(String);
+
+#[derive(Default)]
+struct TargetNameVisitor {
+    name: Option<String>,
+}
+
+impl Visit for TargetNameVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "target_name" {
+            self.name = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "target_name" {
+            self.name = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Passes events to the build monitor while it runs, as the current step
+/// of the target whose span they happen in.
+struct MonitorLayer;
+
+impl<S> Layer<S> for MonitorLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != "target" {
+            return;
+        }
+        let mut visitor = TargetNameVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(name), Some(span)) = (visitor.name, ctx.span(id)) {
+            span.extensions_mut().insert(TargetName(name));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(monitor) = ui::monitor() else {
+            return;
+        };
+        // Diagnostics repeat command output the monitor already has.
+        if event.metadata().target() == diagnostics::EVENT_TARGET {
+            return;
+        }
+        let target = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| Some(span.extensions().get::<TargetName>()?.0.clone()))
+        });
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let line = match *event.metadata().level() {
+            Level::WARN => format!("warning: {}", visitor.message),
+            Level::ERROR => format!("error: {}", visitor.message),
+            _ => visitor.message,
+        };
+        monitor.log(target.as_deref(), line);
+    }
+}
+
+/// Console formatter that prints informational events as plain messages,
+/// prefixes warnings/errors, and shows span context and fields for debug output.
+struct ConsoleFormat;
+
+impl<S, N> FormatEvent<S, N> for ConsoleFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // The compiler output these repeat was already passed through.
+        if event.metadata().target() == diagnostics::EVENT_TARGET {
+            return Ok(());
+        }
+        let level = *event.metadata().level();
+        match level {
+            Level::INFO => {}
+            Level::WARN if writer.has_ansi_escapes() => {
+                write!(writer, "\x1b[1;33mwarning\x1b[0m: ")?
+            }
+            Level::WARN => write!(writer, "warning: ")?,
+            Level::ERROR if writer.has_ansi_escapes() => {
+                write!(writer, "\x1b[1;31merror\x1b[0m: ")?
+            }
+            Level::ERROR => write!(writer, "error: ")?,
+            Level::DEBUG | Level::TRACE => {
+                write!(writer, "{}: ", level.as_str().to_ascii_lowercase())?;
+                if let Some(scope) = ctx.event_scope() {
+                    for span in scope.from_root() {
+                        write!(writer, "{}", span.name())?;
+                        let ext = span.extensions();
+                        if let Some(fields) =
+                            ext.get::<tracing_subscriber::fmt::FormattedFields<N>>()
+                        {
+                            if !fields.is_empty() {
+                                write!(writer, "{{{}}}", fields)?;
+                            }
+                        }
+                        write!(writer, ": ")?;
+                    }
+                }
+            }
+        }
+        if matches!(level, Level::DEBUG | Level::TRACE) {
+            ctx.format_fields(writer.by_ref(), event)?;
+        } else {
+            let mut visitor = MessageVisitor::default();
+            event.record(&mut visitor);
+            write!(writer, "{}", visitor.message)?;
+        }
+        writeln!(writer)
+    }
+}
+
+/// Extracts only the `message` field so structured fields attached for the
+/// JSON output do not clutter console lines.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::{Diagnostic, Severity};
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// Collects what the layer writes.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn logs_diagnostics_as_json_with_their_location() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = Registry::default().with(json_layer(move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            diagnostics::emit(
+                "app",
+                &[Diagnostic {
+                    file: Some("src/main.c".into()),
+                    line: Some(3),
+                    column: Some(9),
+                    severity: Severity::Warning,
+                    message: "unused variable 'x'".into(),
+                }],
+            );
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let event: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["target"], diagnostics::EVENT_TARGET);
+        let fields = &event["fields"];
+        assert_eq!(fields["file"], "src/main.c");
+        assert_eq!(fields["line"], 3);
+        assert_eq!(fields["column"], 9);
+        assert_eq!(fields["severity"], "warning");
+        assert_eq!(fields["target_name"], "app");
+        assert_eq!(fields["message"], "unused variable 'x'");
+    }
+}
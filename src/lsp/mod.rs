@@ -0,0 +1,1007 @@
+use crate::config::ProjectManifest;
+use crate::cross::Platform;
+use crate::error::CrustError;
+use crate::graph::DependencyGraph;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item, Table};
+
+const PROJECT_FIELDS: &[(&str, &str)] = &[
+    (
+        "name",
+        "Project name, used for packages and generated files.",
+    ),
+    ("version", "Project version string."),
+    (
+        "crust_version",
+        "Requirement on the crust release, e.g. `\">=0.2\"`.",
+    ),
+    ("c_args", "Flags added to every C-family compile."),
+    (
+        "subdirs",
+        "Directories whose `crust.build` adds targets named `//<dir>:<name>`.",
+    ),
+    (
+        "passthrough_env",
+        "Environment variables build commands keep under `--hermetic`.",
+    ),
+    (
+        "launchers",
+        "Programs starting each kind of command, such as `compile = \"ccache\"`.",
+    ),
+];
+
+const TARGET_FIELDS: &[(&str, &str)] = &[
+    (
+        "type",
+        "Kind of target (`executable`, `static_library`, ...).",
+    ),
+    ("name", "Target name; also controls generated output names."),
+    (
+        "sources",
+        "Source files relative to the manifest directory.",
+    ),
+    ("deps", "Other targets this one depends on."),
+    (
+        "labels",
+        "Tags selecting the target in `crust build --label` and `crust query`.",
+    ),
+    (
+        "owners",
+        "People or teams responsible for the target, named when it fails.",
+    ),
+    (
+        "deprecated",
+        "Why the target should no longer be used; dependents get a warning.",
+    ),
+    (
+        "command",
+        "Shell command, or program and arguments, of a custom_command target.",
+    ),
+    ("outputs", "Files a custom_command produces."),
+    ("inputs", "Files or directory trees a custom_command reads."),
+    (
+        "worker",
+        "Command starting a persistent worker that runs a custom_command.",
+    ),
+    (
+        "programs",
+        "Programs a custom_command runs, e.g. \"python3 >=3.8\"; found before building.",
+    ),
+    (
+        "input",
+        "Doxygen inputs (docs), header template (vcs_version), or copied file (copy_file).",
+    ),
+    (
+        "output",
+        "Generated file (vcs_version, fetch, copy_file) or directory (extract) name.",
+    ),
+    ("url", "Address a fetch target downloads."),
+    (
+        "variables",
+        "Values replacing `@NAME@` in the file a copy_file target copies.",
+    ),
+    ("sha256", "Expected SHA-256 of a fetch target's download."),
+    (
+        "edition",
+        "Rust edition of a rust target; defaults to 2021.",
+    ),
+    (
+        "crate_type",
+        "rlib (default), staticlib, or cdylib for a rust_library.",
+    ),
+    (
+        "subdir",
+        "Package directory below site-packages of a python target.",
+    ),
+    (
+        "archive",
+        "Tar, .tar.gz, or zip archive an extract target unpacks.",
+    ),
+    (
+        "strip_components",
+        "Leading path components an extract target drops.",
+    ),
+    (
+        "fallback",
+        "Version written outside a git checkout (vcs_version).",
+    ),
+    ("doxygen", "Extra Doxyfile settings of a docs target."),
+    ("package", "Gettext domain; defaults to the project name."),
+    (
+        "po_dir",
+        "Directory holding the .po files of a gettext target.",
+    ),
+    ("languages", "Languages compiled by a gettext target."),
+    ("xgettext_args", "Extra arguments passed to xgettext."),
+    ("tool", "Man page converter: scdoc, asciidoctor, or pandoc."),
+    (
+        "sign",
+        "Signing command run after linking; `${OUTPUT}` is the artifact.",
+    ),
+    ("post_build", "Commands run after a successful link."),
+    (
+        "post_build_outputs",
+        "Extra files produced by `post_build`.",
+    ),
+    ("embed_files", "Files converted into linkable C arrays."),
+    (
+        "linker_script",
+        "Linker script passed as `-T` (executables only).",
+    ),
+    ("generate_map", "Write `<name>.map` next to the executable."),
+    (
+        "size_report",
+        "Print a flash/RAM usage summary after linking.",
+    ),
+    ("test", "Run this executable as part of `crust test`."),
+    (
+        "bolt",
+        "Optimize the executable with llvm-bolt: `{ profile = \"perf.data\" }`.",
+    ),
+    ("objc_arc", "Compile Objective-C sources with `-fobjc-arc`."),
+    ("frameworks", "Apple frameworks linked with `-framework`."),
+    ("c_args", "Flags added to this target's C-family compiles."),
+    (
+        "public_c_args",
+        "Flags added to the compiles of this target and its dependents.",
+    ),
+    (
+        "c_std",
+        "C standard of C sources, such as `c11` or `gnu17`.",
+    ),
+    ("cpp_std", "C++ standard of C++ sources, such as `c++20`."),
+    (
+        "external_deps",
+        "`[dependencies]` compiled and linked against when found.",
+    ),
+    (
+        "requires",
+        "`[dependencies]` without which the target is left out.",
+    ),
+    (
+        "launchers",
+        "Launchers of this target's commands, overriding `[project] launchers`.",
+    ),
+    (
+        "cuda_arch",
+        "GPU architectures `.cu` sources are compiled for, such as `sm_80`.",
+    ),
+    (
+        "cuda_separable",
+        "Compile `.cu` sources as relocatable device code and device-link them.",
+    ),
+    (
+        "architectures",
+        "Build the target once per listed architecture.",
+    ),
+    (
+        "universal",
+        "Combine per-architecture outputs with `lipo` (macOS).",
+    ),
+    (
+        "install",
+        "Copy the artifact into bindir or libdir on install.",
+    ),
+    ("component", "Install component: runtime, devel, or doc."),
+];
+
+const PACKAGE_FIELDS: &[(&str, &str)] = &[
+    ("summary", "One-line package description."),
+    ("description", "Longer package description."),
+    (
+        "maintainer",
+        "Maintainer, e.g. `Jane Doe <jane@example.com>`.",
+    ),
+    ("license", "License identifier."),
+    ("homepage", "Project homepage URL."),
+    ("depends", "Packages the built package depends on."),
+    (
+        "architecture",
+        "Package architecture; defaults to the host.",
+    ),
+    ("release", "Package release number."),
+    ("source_url", "Release tarball URL (brew formulas)."),
+];
+
+const PYTHON_FIELDS: &[(&str, &str)] = &[
+    (
+        "interpreter",
+        "Python program name or path; defaults to `python3`.",
+    ),
+    ("version", "Requirement on its version, e.g. `\">=3.8\"`."),
+];
+
+const PROFILE_FIELDS: &[(&str, &str)] = &[
+    (
+        "buildtype",
+        "Optimization and debug info (`debug`, `release`, ...).",
+    ),
+    (
+        "sanitizers",
+        "Sanitizers passed as `-fsanitize=` when compiling and linking.",
+    ),
+    ("c_args", "Flags added to every C-family compile."),
+    (
+        "c_link_args",
+        "Flags added to every executable and shared library link.",
+    ),
+    (
+        "options",
+        "Defaults for command-line options, as in a config file.",
+    ),
+];
+
+const BUILD_TYPES: &[(&str, &str)] = &[
+    ("plain", "No flags from crust."),
+    ("debug", "`-O0 -g`."),
+    ("debugoptimized", "`-O2 -g`."),
+    ("release", "`-O3`."),
+    ("minsize", "`-Os`."),
+];
+
+const DEPENDENCY_FIELDS: &[(&str, &str)] = &[
+    (
+        "method",
+        "How it is found: `pkg-config`, `conan`, `vcpkg`, or `find`.",
+    ),
+    (
+        "module",
+        "pkg-config module, conan reference, or vcpkg port; defaults to the name.",
+    ),
+    (
+        "version",
+        "Accepted versions of the system library, e.g. `\">=1.2.11\"`.",
+    ),
+    (
+        "feature",
+        "Whether it is used: `enabled`, `disabled`, or `auto`.",
+    ),
+    (
+        "fallback",
+        "Target of the project built instead when it is not found.",
+    ),
+    ("headers", "Headers that must exist, such as `zlib.h`."),
+    (
+        "libraries",
+        "Libraries searched for and linked as `-l<name>`.",
+    ),
+    (
+        "include_dirs",
+        "Directories searched for `headers` before the standard ones.",
+    ),
+    (
+        "lib_dirs",
+        "Directories searched for `libraries` before the standard ones.",
+    ),
+];
+
+const METHODS: &[(&str, &str)] = &[
+    ("pkg-config", "Ask pkg-config for the installed library."),
+    ("conan", "Install the package with `conan install`."),
+    ("vcpkg", "Read what `vcpkg install` installed."),
+    (
+        "find",
+        "Search directories for the `headers` and `libraries`.",
+    ),
+];
+
+const FEATURES: &[(&str, &str)] = &[
+    (
+        "enabled",
+        "Always used; the build fails when it is not found.",
+    ),
+    (
+        "disabled",
+        "Never looked up; targets requiring it are left out.",
+    ),
+    ("auto", "Used when it is found."),
+];
+
+const INSTALL_DATA_FIELDS: &[(&str, &str)] = &[
+    (
+        "sources",
+        "Files to install, relative to the manifest directory.",
+    ),
+    (
+        "install_dir",
+        "Destination below the prefix; defaults to datadir.",
+    ),
+    ("install_mode", "Permissions of the installed files."),
+    ("rename", "New file names, one per source."),
+    ("component", "Install component: runtime, devel, or doc."),
+];
+
+const INSTALL_SYMLINK_FIELDS: &[(&str, &str)] = &[
+    ("name", "Path of the link below the prefix."),
+    ("pointing_to", "Target the link points to."),
+    ("component", "Install component: runtime, devel, or doc."),
+];
+
+const INSTALL_EMPTYDIR_FIELDS: &[(&str, &str)] = &[
+    ("dirs", "Directories to create below the prefix."),
+    ("install_mode", "Permissions of the created directories."),
+    ("component", "Install component: runtime, devel, or doc."),
+];
+
+const TARGET_TYPES: &[(&str, &str)] = &[
+    ("executable", "Program linked from sources."),
+    ("static_library", "Archive of objects (`lib<name>.a`)."),
+    ("shared_library", "Shared object (`lib<name>.so`)."),
+    (
+        "custom_command",
+        "Shell command producing declared outputs.",
+    ),
+    ("docs", "Doxygen HTML documentation."),
+    ("gettext", "Compiled message catalogs."),
+    ("man", "Manual pages converted from markup."),
+    ("vcs_version", "Header holding `git describe` output."),
+    ("fetch", "File downloaded and checked against a SHA-256."),
+    ("extract", "Directory unpacked from an archive."),
+    ("copy_file", "File copied into the build directory."),
+    ("alias", "Name building the targets in `deps`."),
+    (
+        "python_extension",
+        "C extension module for the `[python]` interpreter.",
+    ),
+    (
+        "python_sources",
+        "Pure-Python modules installed into site-packages.",
+    ),
+    ("rust_executable", "Rust program compiled with rustc."),
+    ("rust_library", "Rust crate: rlib, staticlib, or cdylib."),
+];
+
+const COMPONENTS: &[(&str, &str)] = &[
+    ("runtime", "Files needed to run the project."),
+    ("devel", "Headers and libraries for building against it."),
+    ("doc", "Documentation."),
+];
+
+/// Keys whose values name files relative to the manifest directory.
+const FILE_KEYS: &[&str] = &["sources", "inputs", "input", "linker_script", "embed_files"];
+
+fn fields(section: &str) -> &'static [(&'static str, &'static str)] {
+    match section {
+        "project" => PROJECT_FIELDS,
+        "targets" => TARGET_FIELDS,
+        "package" => PACKAGE_FIELDS,
+        "python" => PYTHON_FIELDS,
+        "profile" => PROFILE_FIELDS,
+        "dependencies" => DEPENDENCY_FIELDS,
+        "install_data" => INSTALL_DATA_FIELDS,
+        "install_symlink" => INSTALL_SYMLINK_FIELDS,
+        "install_emptydir" => INSTALL_EMPTYDIR_FIELDS,
+        _ => &[],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a manifest, located by byte range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub range: Range<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(range: Range<usize>, message: impl Into<String>) -> Self {
+        Diagnostic {
+            range,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Field,
+    Target,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub detail: String,
+    pub kind: CompletionKind,
+}
+
+/// One line of the manifest with the table it belongs to.
+struct Line<'a> {
+    start: usize,
+    text: &'a str,
+    section: Option<&'a str>,
+}
+
+fn lines(text: &str) -> Vec<Line<'_>> {
+    let mut section = None;
+    let mut start = 0;
+    text.split_inclusive('\n')
+        .map(|raw| {
+            let line = raw.trim_end_matches(['\n', '\r']);
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                section = Some(
+                    trimmed
+                        .trim_start_matches('[')
+                        .split(']')
+                        .next()
+                        .unwrap_or("")
+                        .split('.')
+                        .next()
+                        .unwrap_or("")
+                        .trim(),
+                );
+            }
+            let entry = Line {
+                start,
+                text: line,
+                section,
+            };
+            start += raw.len();
+            entry
+        })
+        .collect()
+}
+
+fn line_at<'a>(lines: &'a [Line<'a>], offset: usize) -> Option<&'a Line<'a>> {
+    lines
+        .iter()
+        .rev()
+        .find(|line| line.start <= offset)
+        .filter(|line| offset <= line.start + line.text.len())
+}
+
+/// Key of a `key = value` line.
+fn line_key<'a>(line: &Line<'a>) -> Option<&'a str> {
+    let (key, _) = line.text.split_once('=')?;
+    let key = key.trim();
+    (!key.is_empty() && !key.starts_with('[') && !key.starts_with('#')).then_some(key)
+}
+
+/// Basic strings on a line as (content, absolute range of the content).
+fn strings(line: &Line<'_>) -> Vec<(String, Range<usize>)> {
+    let mut found = Vec::new();
+    let mut open = None;
+    let mut escaped = false;
+    for (index, ch) in line.text.char_indices() {
+        match (ch, open) {
+            ('#', None) => break,
+            ('"', None) => open = Some(index + 1),
+            ('\\', Some(_)) if !escaped => {
+                escaped = true;
+                continue;
+            }
+            ('"', Some(begin)) if !escaped => {
+                found.push((
+                    line.text[begin..index].to_string(),
+                    line.start + begin..line.start + index,
+                ));
+                open = None;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    found
+}
+
+/// Declared target names with the range of each name string. Works on
+/// text that does not parse yet, so completion keeps working while typing.
+fn target_names(lines: &[Line<'_>]) -> Vec<(String, Range<usize>)> {
+    lines
+        .iter()
+        .filter(|line| line.section == Some("targets") && line_key(line) == Some("name"))
+        .filter_map(|line| strings(line).into_iter().next())
+        .collect()
+}
+
+/// Type of the target whose table contains `line_index`.
+fn target_type(lines: &[Line<'_>], name_range: &Range<usize>) -> Option<String> {
+    let index = lines.iter().position(|line| {
+        line.start <= name_range.start && name_range.end <= line.start + line.text.len()
+    })?;
+    let header = lines[..=index]
+        .iter()
+        .rposition(|line| line.text.trim_start().starts_with("[["))
+        .unwrap_or(0);
+    lines[header..]
+        .iter()
+        .skip(1)
+        .take_while(|line| !line.text.trim_start().starts_with('['))
+        .find(|line| line_key(line) == Some("type"))
+        .and_then(|line| strings(line).into_iter().next())
+        .map(|(kind, _)| kind)
+}
+
+fn span_of(item: &Item) -> Option<Range<usize>> {
+    match item {
+        Item::Value(value) => value.span(),
+        _ => None,
+    }
+}
+
+/// String values of a key that holds a string or an array of strings.
+fn string_values(table: &Table, key: &str) -> Vec<(String, Range<usize>)> {
+    let Some(item) = table.get(key) else {
+        return Vec::new();
+    };
+    let values: Vec<&toml_edit::Value> = match item.as_array() {
+        Some(array) => array.iter().collect(),
+        None => item.as_value().into_iter().collect(),
+    };
+    values
+        .into_iter()
+        .filter_map(|value| Some((value.as_str()?.to_string(), value.span()?)))
+        .collect()
+}
+
+/// Problems in `text`, the contents of the manifest at `path`: TOML and
+/// schema errors, the checks of `ProjectManifest::load` and the dependency
+/// graph, unknown deps, and source files that do not exist.
+pub fn diagnostics(text: &str, path: &Path) -> Vec<Diagnostic> {
+    let doc = match ImDocument::parse(text) {
+        Ok(doc) => doc,
+        Err(err) => return vec![Diagnostic::error(err.span().unwrap_or(0..0), err.message())],
+    };
+    let targets: Vec<&Table> = doc
+        .get("targets")
+        .and_then(Item::as_array_of_tables)
+        .map(|array| array.iter().collect())
+        .unwrap_or_default();
+    let name_span = |target: &str| {
+        targets
+            .iter()
+            .find(|table| table.get("name").and_then(Item::as_str) == Some(target))
+            .and_then(|table| table.get("name").and_then(span_of))
+            .unwrap_or(0..0)
+    };
+    let names: Vec<&str> = targets
+        .iter()
+        .filter_map(|table| table.get("name").and_then(Item::as_str))
+        .collect();
+    let generated: Vec<String> = targets
+        .iter()
+        .flat_map(|table| string_values(table, "outputs"))
+        .map(|(output, _)| output)
+        .collect();
+
+    let mut found = Vec::new();
+    let manifest_dir = ProjectManifest::manifest_dir(path);
+    for table in &targets {
+        for (dep, range) in string_values(table, "deps") {
+            if !names.contains(&dep.as_str()) {
+                found.push(Diagnostic::error(
+                    range,
+                    format!("Unknown dependency '{dep}'"),
+                ));
+            }
+        }
+        for key in FILE_KEYS {
+            for (file, range) in string_values(table, key) {
+                if !generated.contains(&file) && !manifest_dir.join(&file).exists() {
+                    found.push(Diagnostic {
+                        range,
+                        severity: Severity::Warning,
+                        message: format!("File not found: {file}"),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Err(err) = toml::from_str::<ProjectManifest>(text) {
+        found.push(Diagnostic::error(err.span().unwrap_or(0..0), err.message()));
+        return found;
+    }
+    let result = ProjectManifest::parse(text, path)
+        .and_then(|manifest| DependencyGraph::from_manifest(&manifest, Platform::Native));
+    if let Err(err) = result {
+        let range = match CrustError::find(&err) {
+            Some(CrustError::UnknownDependency { .. }) => return found,
+            Some(CrustError::InvalidTarget { target, .. } | CrustError::Cycle { target }) => {
+                name_span(target)
+            }
+            Some(CrustError::DuplicateTarget { name }) => name_span(name),
+            _ => 0..0,
+        };
+        let message = match CrustError::find(&err) {
+            Some(CrustError::ManifestParse { message, .. }) => message.clone(),
+            _ => format!("{err:#}"),
+        };
+        found.push(Diagnostic::error(range, message));
+    }
+    found
+}
+
+/// Suggestions at `offset`: field names at the start of a line, target
+/// names inside `deps`, and the allowed values of `type` and components.
+pub fn completions(text: &str, offset: usize) -> Vec<Completion> {
+    let lines = lines(text);
+    let Some(line) = line_at(&lines, offset) else {
+        return Vec::new();
+    };
+    let section = line.section.unwrap_or("");
+    let before = &line.text[..offset - line.start];
+    let Some((key, _)) = before.split_once('=') else {
+        return fields(section)
+            .iter()
+            .map(|(name, doc)| Completion {
+                label: name.to_string(),
+                detail: doc.to_string(),
+                kind: CompletionKind::Field,
+            })
+            .collect();
+    };
+    let values = |choices: &[(&str, &str)]| {
+        choices
+            .iter()
+            .map(|(name, doc)| Completion {
+                label: name.to_string(),
+                detail: doc.to_string(),
+                kind: CompletionKind::Value,
+            })
+            .collect()
+    };
+    match (section, key.trim()) {
+        ("targets", "deps") | ("dependencies", "fallback") => target_names(&lines)
+            .into_iter()
+            .map(|(name, range)| Completion {
+                detail: target_type(&lines, &range).unwrap_or_default(),
+                label: name,
+                kind: CompletionKind::Target,
+            })
+            .collect(),
+        ("targets", "type") => values(TARGET_TYPES),
+        ("profile", "buildtype") => values(BUILD_TYPES),
+        ("dependencies", "feature") => values(FEATURES),
+        ("dependencies", "method") => values(METHODS),
+        (_, "component" | "install_component") => values(COMPONENTS),
+        _ => Vec::new(),
+    }
+}
+
+/// Range of the `name` of the target referenced by the string at `offset`.
+pub fn definition(text: &str, offset: usize) -> Option<Range<usize>> {
+    let lines = lines(text);
+    let line = line_at(&lines, offset)?;
+    let (word, _) = strings(line)
+        .into_iter()
+        .find(|(_, range)| range.start <= offset && offset <= range.end)?;
+    target_names(&lines)
+        .into_iter()
+        .find(|(name, _)| *name == word)
+        .map(|(_, range)| range)
+}
+
+/// Markdown describing the field, target, or value at `offset`.
+pub fn hover(text: &str, offset: usize) -> Option<String> {
+    let lines = lines(text);
+    let line = line_at(&lines, offset)?;
+    let section = line.section.unwrap_or("");
+    let key = line_key(line);
+    if let Some((word, _)) = strings(line)
+        .into_iter()
+        .find(|(_, range)| range.start <= offset && offset <= range.end)
+    {
+        if let Some((_, range)) = target_names(&lines)
+            .into_iter()
+            .find(|(name, _)| *name == word)
+        {
+            let kind = target_type(&lines, &range).unwrap_or_else(|| "target".into());
+            return Some(format!("**{kind}** `{word}`"));
+        }
+        let choices = match key {
+            Some("type") => TARGET_TYPES,
+            Some("component" | "install_component") => COMPONENTS,
+            _ => &[],
+        };
+        return choices
+            .iter()
+            .find(|(name, _)| *name == word)
+            .map(|(name, doc)| format!("`{name}`: {doc}"));
+    }
+    let key_end = line.start + line.text.find('=')?;
+    if offset > key_end {
+        return None;
+    }
+    fields(section)
+        .iter()
+        .find(|(name, _)| Some(*name) == key)
+        .map(|(name, doc)| format!("`{name}`: {doc}"))
+}
+
+/// Converts between byte offsets and LSP positions (UTF-16 columns).
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        LineIndex { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> Value {
+        let offset = offset.min(self.text.len());
+        let line = self.starts.partition_point(|start| *start <= offset) - 1;
+        let character: usize = self.text[self.starts[line]..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, range: &Range<usize>) -> Value {
+        json!({ "start": self.position(range.start), "end": self.position(range.end) })
+    }
+
+    fn offset(&self, position: &Value) -> usize {
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let Some(start) = self.starts.get(line).copied() else {
+            return self.text.len();
+        };
+        let mut units = position["character"].as_u64().unwrap_or(0) as usize;
+        for (index, ch) in self.text[start..].char_indices() {
+            if units == 0 || ch == '\n' {
+                return start + index;
+            }
+            units = units.saturating_sub(ch.len_utf16());
+        }
+        self.text.len()
+    }
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match decoded {
+            Some(value) => {
+                bytes.push(value);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .context("Invalid Content-Length")?,
+            );
+        }
+    }
+    let length = length.context("Message without Content-Length")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Language server state: the open manifests by URI.
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn publish(&self, uri: &str) -> Value {
+        let items: Vec<Value> = match self.documents.get(uri) {
+            Some(text) => {
+                let index = LineIndex::new(text);
+                diagnostics(text, &uri_to_path(uri))
+                    .iter()
+                    .map(|diagnostic| {
+                        json!({
+                            "range": index.range(&diagnostic.range),
+                            "severity": match diagnostic.severity {
+                                Severity::Error => 1,
+                                Severity::Warning => 2,
+                            },
+                            "source": "crust",
+                            "message": diagnostic.message,
+                        })
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": items },
+        })
+    }
+
+    /// Handles one message and returns the messages to send back.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1, "save": true },
+                    "completionProvider": { "triggerCharacters": ["\"", "["] },
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "crust", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                return vec![self.publish(&uri)];
+            }
+            "textDocument/didChange" => {
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                return vec![self.publish(&uri)];
+            }
+            "textDocument/didSave" => return vec![self.publish(&uri)],
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![self.publish(&uri)];
+            }
+            "textDocument/completion" | "textDocument/hover" | "textDocument/definition" => {
+                let text = self.documents.get(&uri).map(String::as_str).unwrap_or("");
+                let index = LineIndex::new(text);
+                let offset = index.offset(&params["position"]);
+                match method {
+                    "textDocument/completion" => json!(completions(text, offset)
+                        .into_iter()
+                        .map(|item| json!({
+                            "label": item.label,
+                            "detail": item.detail,
+                            "kind": match item.kind {
+                                CompletionKind::Field => 10,
+                                CompletionKind::Target => 18,
+                                CompletionKind::Value => 12,
+                            },
+                        }))
+                        .collect::<Vec<_>>()),
+                    "textDocument/hover" => hover(text, offset)
+                        .map(|doc| json!({ "contents": { "kind": "markdown", "value": doc } }))
+                        .unwrap_or(Value::Null),
+                    _ => definition(text, offset)
+                        .map(|range| json!({ "uri": uri, "range": index.range(&range) }))
+                        .unwrap_or(Value::Null),
+                }
+            }
+            _ if message.get("id").is_some() => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": { "code": -32601, "message": format!("Unsupported method {method}") },
+                })]
+            }
+            _ => return Vec::new(),
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })]
+    }
+}
+
+/// Serves the language server protocol over `input` and `output` until the
+/// client sends `exit`.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    if !server.shutdown {
+        anyhow::bail!("Language client exited without shutdown");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const MANIFEST: &str = "[project]\nname = \"demo\"\n\n[[targets]]\ntype = \"static_library\"\n\
+                            name = \"core\"\nsources = [\"core.c\"]\n\n[[targets]]\n\
+                            type = \"executable\"\nname = \"app\"\nsources = [\"main.c\"]\n\
+                            deps = [\"core\", \"missing\"]\n";
+
+    #[test]
+    fn reports_unknown_deps_and_missing_files() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("core.c"), "").unwrap();
+        let found = diagnostics(MANIFEST, &dir.path().join("crust.build"));
+        let messages: Vec<(&str, Severity)> = found
+            .iter()
+            .map(|d| (&MANIFEST[d.range.clone()], d.severity))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("\"missing\"", Severity::Error),
+                ("\"main.c\"", Severity::Warning)
+            ]
+        );
+    }
+
+    #[test]
+    fn completes_and_resolves_target_names() {
+        let deps = MANIFEST.find("\"core\", ").unwrap() + 2;
+        let labels: Vec<String> = completions(MANIFEST, deps)
+            .into_iter()
+            .map(|item| item.label)
+            .collect();
+        assert_eq!(labels, ["core", "app"]);
+        assert!(completions(MANIFEST, MANIFEST.find("type").unwrap())
+            .iter()
+            .any(|item| item.label == "component"));
+
+        let name = MANIFEST.find("core").unwrap();
+        assert_eq!(definition(MANIFEST, deps), Some(name..name + 4));
+        assert_eq!(
+            hover(MANIFEST, deps).as_deref(),
+            Some("**static_library** `core`")
+        );
+    }
+}
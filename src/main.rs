@@ -1,18 +1,98 @@
+mod analyze;
 mod backend;
+mod builddir;
+mod compat;
+mod compdb;
 mod config;
+mod copy;
+mod cross;
+mod cuda;
+mod daemon;
+mod depcheck;
+mod deps;
+mod devenv;
+mod diagnostics;
+mod digest;
+mod docs;
+mod doctor;
+mod embed;
+mod error;
 mod executor;
+mod extract;
+mod fetch;
+mod flags;
+mod formatter;
+mod fortran;
 mod graph;
+mod hermetic;
+mod i18n;
+mod ide;
+mod install;
+mod languages;
+mod lint;
+mod logging;
+mod lsp;
+mod man;
+mod notify;
+mod objc;
+mod outdated;
+mod package;
+mod paths;
+mod priority;
+mod programs;
+mod provenance;
+mod python;
+mod query;
+mod remote;
+mod runner;
+mod rust;
+mod sandbox;
+mod sbom;
+mod settings;
+mod standards;
+mod state;
+mod stats;
+mod toolchain;
+mod ui;
+mod vcs;
 
 use anyhow::{Context, Result};
 use backend::{
-    make::MakeBackend, native::CrustBackend, ninja::NinjaBackend, Backend, BackendEmitResult,
-    TargetBuildSummary,
+    bazel::{BazelBackend, Dialect},
+    make::MakeBackend,
+    native::CrustBackend,
+    ninja::NinjaBackend,
+    vs::VsBackend,
+    xcode::XcodeBackend,
+    Backend, BackendEmitResult, TargetBuildSummary,
 };
-use clap::{Args, Parser, Subcommand, ValueEnum};
-use config::ProjectManifest;
+use builddir::{BuildDirLock, Meta};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use config::edit::{ManifestEditor, NewTargetKind};
+use config::{Component, Feature, ProjectManifest, Rule};
+use cross::native::NativeFile;
+use cross::{AndroidAbi, CrossFile};
+use deps::{Resolution, WrapMode};
+use devenv::DevEnv;
+use error::CrustError;
 use graph::DependencyGraph;
+use hermetic::HermeticEnv;
+use logging::{ColorChoice, LogFormat};
+use package::{PackageFormat, PackageSpec};
+use priority::{IoPriority, Priority};
+use query::QueryOutput;
+use remote::RemoteExecutor;
+use runner::Runner;
+use sbom::{SbomFormat, SbomInput};
+use standards::StdFlags;
+use std::collections::HashMap;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Parser)]
 #[command(
@@ -24,6 +104,18 @@ use std::time::{Duration, Instant};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Minimum severity of diagnostics to emit
+    #[arg(long, value_enum, global = true, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+
+    /// Format used for diagnostics (json is written to stderr)
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// When to color console output
+    #[arg(long, value_enum, global = true, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
 }
 
 #[derive(Subcommand)]
@@ -31,14 +123,64 @@ enum Commands {
     /// Configure the project before building
     Configure(CommandOptions),
     /// Build the project artifacts
-    Build(CommandOptions),
-    /// Run the project tests
+    Build(BuildOptions),
+    /// Build and run every executable marked `test = true`
     Test(CommandOptions),
+    /// Build and run an executable target
+    Run(RunOptions),
     /// Clean generated build outputs
     Clean {
         #[arg(short = 'b', long, default_value = "build")]
         builddir: PathBuf,
+
+        /// Path to the crust manifest (TOML) of the project the build directory belongs to
+        #[arg(long, default_value = "crust.build")]
+        manifest: PathBuf,
+
+        /// Remove the directory even if crust did not configure it or it is outside the project
+        #[arg(long)]
+        force: bool,
     },
+    /// Check that the host toolchain can build crust projects
+    Doctor,
+    /// Emit a software bill of materials for the built artifacts
+    Sbom(SbomOptions),
+    /// Print (or write) the environment for running binaries from the build directory
+    Env(EnvOptions),
+    /// Generate a cross file for a detected toolchain
+    CrossFile(CrossFileOptions),
+    /// Run clang-tidy (or another linter) over all C/C++ sources
+    Lint(LintOptions),
+    /// Format target sources with clang-format
+    Fmt(FmtOptions),
+    /// Rewrite the manifest in canonical form (key order, arrays, spacing)
+    FmtManifest(FmtManifestOptions),
+    /// Add a compiled target to the manifest
+    AddTarget(AddTargetOptions),
+    /// Add dependencies to a target in the manifest
+    AddDep(AddDepOptions),
+    /// Serve the language server protocol for crust.build files on stdio
+    Lsp,
+    /// Generate IDE project files (VS Code, clangd/CLion compile flags)
+    Ide(IdeOptions),
+    /// Select targets with an expression such as "deps(app)" or "kind(shared_library, //...)"
+    Query(QueryOptions),
+    /// List the targets with their type, outputs, and direct dependencies
+    Targets(TargetsOptions),
+    /// List the [dependencies] of the manifest, or with --resolved how each was found
+    Deps(DepsOptions),
+    /// Report which targets a build would run again, and why, without building
+    Outdated(BuildTargets),
+    /// Show how long each target took in the last build, or compare it with the one before
+    Stats(StatsOptions),
+    /// Watch the project so builds skip looking at unchanged files, and build for clients
+    Daemon(DaemonOptions),
+    /// Build the project and install its artifacts under a prefix
+    Install(InstallOptions),
+    /// Extract and merge translations of gettext targets
+    I18n(I18nOptions),
+    /// Build and stage an install, then package it as a .deb, .rpm, Homebrew formula, or MSI installer
+    Package(PackageOptions),
 }
 
 #[derive(Clone, Debug, Args)]
@@ -55,156 +197,2259 @@ struct CommandOptions {
     #[arg(short = 'j', long)]
     jobs: Option<usize>,
 
+    /// Start no new jobs while the load average is at least this (like make -l)
+    #[arg(short = 'l', long, value_name = "N")]
+    load_average: Option<f64>,
+
+    /// Maximum number of executables and shared libraries built at once
+    #[arg(long, value_name = "N")]
+    max_link_jobs: Option<usize>,
+
+    /// Maximum number of custom commands run at once
+    #[arg(long, value_name = "N")]
+    max_custom_jobs: Option<usize>,
+
+    /// Run compilers and commands at this niceness, from -20 to 19
+    #[arg(long, value_name = "N", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nice: Option<i32>,
+
+    /// Run compilers and commands at this I/O priority
+    #[arg(long, value_enum, value_name = "CLASS")]
+    io_priority: Option<IoPriority>,
+
     /// Backend used to generate build files
     #[arg(long, value_enum, default_value_t = BackendChoice::Native)]
     backend: BackendChoice,
+
+    /// Start compiles through this launcher, such as ccache, unless the manifest names one
+    #[arg(long, value_name = "COMMAND")]
+    launcher: Option<String>,
+
+    /// Run each native command with only its declared inputs visible (Linux, needs bwrap)
+    #[arg(long)]
+    sandbox: bool,
+
+    /// Trace native commands with strace and warn about undeclared inputs
+    #[arg(long, conflicts_with = "sandbox")]
+    check_deps: bool,
+
+    /// Run C-family compiles on this Remote Execution API server (grpc://host:port, experimental)
+    #[arg(long, value_name = "URL", conflicts_with_all = ["sandbox", "check_deps"])]
+    remote_executor: Option<String>,
+
+    /// Instance name sent to the remote executor
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "",
+        requires = "remote_executor"
+    )]
+    remote_instance_name: String,
+
+    /// Platform property remote workers must match, such as OSFamily=linux (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = remote::parse_property, requires = "remote_executor")]
+    remote_property: Vec<(String, String)>,
+
+    /// Run native commands with only PATH and the project's passthrough_env from the environment
+    #[arg(long)]
+    hermetic: bool,
+
+    /// Kill the running native commands as soon as one fails instead of letting them finish
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Wait for another crust using the build directory to finish instead of failing
+    #[arg(long)]
+    wait: bool,
+
+    /// Abort a native build in which no target starts or finishes for SECONDS, showing what it waits on
+    #[arg(long, value_name = "SECONDS")]
+    watchdog: Option<u64>,
+
+    /// Show a full-screen monitor of the workers, progress, and log while building (native backend)
+    #[arg(long)]
+    ui: bool,
+
+    /// Show a desktop notification when a build or test run taking at least SECONDS finishes
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "10")]
+    notify: Option<u64>,
+
+    /// Apply the flags and options of this [profile.NAME] of the manifest
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    #[command(flatten)]
+    dependencies: DependencyArgs,
+
+    /// TOML file naming the compiler, archiver, and exe_wrapper for cross builds
+    #[arg(long)]
+    cross_file: Option<PathBuf>,
+
+    /// TOML file overriding this machine's tools, option defaults, and environment
+    #[arg(long, conflicts_with = "cross_file")]
+    native_file: Option<PathBuf>,
+
+    /// The loaded `--native-file`, see `apply_defaults`.
+    #[arg(skip)]
+    native: NativeFile,
+
+    /// Use PATH for a program custom commands declare (repeatable)
+    #[arg(long = "program-path", value_name = "NAME=PATH",
+          value_parser = programs::parse_override)]
+    program_paths: Vec<(String, PathBuf)>,
+
+    /// How much of the build summary to print
+    #[arg(long, value_enum, default_value_t = SummaryMode::Short)]
+    summary: SummaryMode,
+
+    /// Fail the build when its commands print more than N warnings (native backend)
+    #[arg(long, value_name = "N")]
+    max_warnings: Option<usize>,
+
+    /// Fail instead of warning about deprecated manifest keys and dependencies on deprecated targets
+    #[arg(long)]
+    fatal_deprecations: bool,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-enum BackendChoice {
-    Native,
-    Ninja,
-    Make,
+/// Detail of the summary printed after a build.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum SummaryMode {
+    /// Target counts, elapsed time, and the slowest targets
+    Short,
+    /// Every target with its outputs
+    Full,
+    /// No summary
+    None,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Clone, Debug, Args)]
+struct BuildOptions {
+    #[command(flatten)]
+    build: CommandOptions,
 
-    match cli.command {
-        Commands::Configure(opts) => drive(&opts, false),
-        Commands::Build(opts) => drive(&opts, true),
-        Commands::Test(opts) => drive(&opts, true),
-        Commands::Clean { builddir } => clean(&builddir),
-    }
+    /// Targets to build with their dependencies (defaults to all but docs targets)
+    targets: Vec<String>,
+
+    /// Also build the targets carrying this label (repeatable)
+    #[arg(long = "label")]
+    labels: Vec<String>,
+
+    /// Run the compiler's static analyzer over every C/C++ source instead of building
+    #[arg(long)]
+    analyze: bool,
+
+    /// Have the crust daemon of the build directory build, with the options it was started with
+    #[arg(long, conflicts_with = "analyze")]
+    daemon: bool,
 }
 
-fn drive(opts: &CommandOptions, show_hint: bool) -> Result<()> {
-    let manifest = ProjectManifest::load(&opts.manifest)?;
-    let graph = DependencyGraph::from_manifest(&manifest)?;
-    let manifest_dir = ProjectManifest::manifest_dir(&opts.manifest);
-    if let Some(0) = opts.jobs {
-        return Err(anyhow::anyhow!("--jobs must be at least 1"));
-    }
-    let backend = backend_from_choice(opts.backend, &manifest_dir, opts.jobs);
-    let outputs_to_check = backend.primary_outputs(&graph, &opts.builddir);
-    let outdated =
-        outputs_to_check.is_empty() || graph.is_outdated(&opts.manifest, &outputs_to_check)?;
+#[derive(Clone, Debug, Args)]
+struct RunOptions {
+    #[command(flatten)]
+    build: CommandOptions,
 
-    if !outdated {
-        println!(
-            "{} backend already up-to-date at {}",
-            backend.name(),
-            opts.builddir.display()
-        );
-    } else {
-        let emit_start = Instant::now();
-        let mut result = backend.emit(&graph, &opts.builddir, &manifest_dir)?;
-        let total_elapsed = emit_start.elapsed();
+    /// Executable target to run
+    target: String,
 
-        if result.target_summaries.is_empty() {
-            result.target_summaries = graph
-                .topo_order()?
-                .into_iter()
-                .map(|node| backend_summary_from_graph(node, &opts.builddir))
-                .collect();
-        }
+    /// Arguments passed to the executable (after `--`)
+    #[arg(last = true)]
+    args: Vec<String>,
+}
 
-        print_summary(backend.as_ref(), &result, total_elapsed);
-    }
+#[derive(Clone, Debug, Args)]
+struct InstallOptions {
+    #[command(flatten)]
+    build: CommandOptions,
 
-    if show_hint {
-        if backend.name() == "native" {
-            println!(
-                "Native build complete. Outputs live in {}",
-                opts.builddir.display()
-            );
-        } else {
-            println!(
-                "Backend ready. Invoke '{}' in {} to build.",
-                opts.backend.command_hint(),
-                opts.builddir.display()
-            );
-        }
-    }
+    /// Installation prefix
+    #[arg(long, default_value = "/usr/local")]
+    prefix: PathBuf,
 
-    Ok(())
+    /// Staging directory prepended to every installed path
+    #[arg(long, env = "DESTDIR")]
+    destdir: Option<PathBuf>,
+
+    /// Install only these components (repeatable); all by default
+    #[arg(long = "component", value_enum)]
+    components: Vec<Component>,
 }
 
-fn backend_summary_from_graph(node: &graph::TargetNode, builddir: &Path) -> TargetBuildSummary {
-    TargetBuildSummary {
-        name: node.name.clone(),
-        built: false,
-        outputs: node.outputs.iter().map(|o| builddir.join(o)).collect(),
-        duration: Duration::default(),
+#[derive(Clone, Debug, Args)]
+struct PackageOptions {
+    #[command(flatten)]
+    build: CommandOptions,
+
+    /// Package format to produce
+    #[arg(long, value_enum)]
+    format: PackageFormat,
+
+    /// Installation prefix inside the package (default /usr, or the
+    /// program folder root for msi)
+    #[arg(long)]
+    prefix: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct I18nOptions {
+    #[command(subcommand)]
+    action: I18nAction,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum I18nAction {
+    /// Extract translatable strings into <po_dir>/<package>.pot
+    Pot(I18nArgs),
+    /// Refresh the template and merge it into every <lang>.po, creating missing ones
+    UpdatePo(I18nArgs),
+}
+
+#[derive(Clone, Debug, Args)]
+struct I18nArgs {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Gettext target to process (defaults to all of them)
+    #[arg(long)]
+    target: Option<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct DaemonOptions {
+    /// How the daemon builds when clients ask it to
+    #[command(flatten)]
+    build: CommandOptions,
+
+    /// `build` as given, before `apply_defaults` filled it in.
+    #[arg(skip)]
+    command_line: Option<Box<CommandOptions>>,
+
+    /// Ids of the options of `build` given on the command line.
+    #[arg(skip)]
+    given: Vec<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct StatsOptions {
+    /// Build directory whose builds to show
+    #[arg(short = 'b', long, default_value = "build")]
+    builddir: PathBuf,
+
+    /// Show per-target duration changes against the previous build
+    #[arg(long)]
+    compare: bool,
+}
+
+/// Manifest and build directory selection shared by inspection commands.
+#[derive(Clone, Debug, Args)]
+struct ProjectArgs {
+    /// Path to the crust manifest (TOML)
+    #[arg(long, default_value = "crust.build")]
+    manifest: PathBuf,
+
+    /// Build directory to inspect
+    #[arg(short = 'b', long, default_value = "build")]
+    builddir: PathBuf,
+
+    /// Cross file the build directory was built with (affects output names)
+    #[arg(long)]
+    cross_file: Option<PathBuf>,
+}
+
+impl ProjectArgs {
+    fn load(&self) -> Result<(ProjectManifest, DependencyGraph)> {
+        let (manifest, graph, _) = load_project(&self.manifest, self.cross_file.as_deref())?;
+        Ok((manifest, graph))
     }
 }
 
-fn print_summary(backend: &dyn Backend, result: &BackendEmitResult, total_elapsed: Duration) {
-    let built_count = result.target_summaries.iter().filter(|t| t.built).count();
-    let skipped_count = result.target_summaries.len().saturating_sub(built_count);
+/// Loads the manifest and optional cross file and builds the graph for the
+/// cross file's platform.
+fn load_project(
+    manifest_path: &Path,
+    cross_file: Option<&Path>,
+) -> Result<(ProjectManifest, DependencyGraph, CrossFile)> {
+    let manifest = ProjectManifest::load(manifest_path)?;
+    let cross = CrossFile::load_optional(cross_file)?;
+    let graph = DependencyGraph::from_manifest(&manifest, cross.platform())?;
+    Ok((manifest, graph, cross))
+}
 
-    println!("\nBuild summary");
-    println!("  Backend: {}", backend.name());
-    println!(
-        "  Targets: {} built, {} skipped, {} total",
-        built_count,
-        skipped_count,
-        result.target_summaries.len()
-    );
-    println!("  Elapsed time: {}", format_duration(total_elapsed));
+impl CommandOptions {
+    /// Loads the manifest and builds the graph for the host machine: the
+    /// cross file's, or this one as the native file describes it.
+    fn load(&self) -> Result<(ProjectManifest, DependencyGraph, CrossFile)> {
+        let manifest = ProjectManifest::load(&self.manifest)?;
+        let cross = self.machine()?;
+        let graph = DependencyGraph::from_manifest(&manifest, cross.platform())?;
+        Ok((manifest, graph, cross))
+    }
 
-    if !result.files.is_empty() {
-        println!("  Backend outputs:");
-        for file in &result.files {
-            println!("    - {}", file.display());
+    fn machine(&self) -> Result<CrossFile> {
+        match &self.cross_file {
+            Some(path) => CrossFile::load(path),
+            None => Ok(self.native.machine()),
         }
     }
+}
 
-    if !result.target_summaries.is_empty() {
-        println!("  Target results:");
-        for target in &result.target_summaries {
-            let status = if target.built { "built" } else { "skipped" };
-            println!(
-                "    - {} ({status}, {})",
-                target.name,
-                format_duration(target.duration)
-            );
-            for output in &target.outputs {
-                println!("      -> {}", output.display());
-            }
+/// Loads the project for a build: its `[dependencies]` are looked up first
+/// and the targets fitted to what was found (see `crate::deps`).
+fn load_build(
+    opts: &CommandOptions,
+) -> Result<(ProjectManifest, DependencyGraph, CrossFile, Resolution)> {
+    let mut manifest = ProjectManifest::load(&opts.manifest)?;
+    let cross = opts.machine()?;
+    let dependencies = deps::resolve(
+        &manifest,
+        &opts.dependencies.overrides(),
+        &ProjectManifest::manifest_dir(&opts.manifest),
+        &opts.builddir,
+    )?;
+    deps::apply(&mut manifest, &dependencies)?;
+    let graph = DependencyGraph::from_manifest(&manifest, cross.platform())?;
+    Ok((manifest, graph, cross, dependencies))
+}
+
+#[derive(Clone, Debug, Args)]
+struct SbomOptions {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Document format to emit
+    #[arg(long, value_enum, default_value_t = SbomFormat::Spdx)]
+    format: SbomFormat,
+
+    /// Write the document to this file instead of stdout
+    #[arg(short = 'o', long)]
+    output: Option<PathBuf>,
+
+    /// Native file the build directory was built with (affects the recorded tools)
+    #[arg(long, conflicts_with = "cross_file")]
+    native_file: Option<PathBuf>,
+
+    /// Program path the build used for a program custom commands declare (repeatable)
+    #[arg(long = "program-path", value_name = "NAME=PATH",
+          value_parser = programs::parse_override)]
+    program_paths: Vec<(String, PathBuf)>,
+
+    #[command(flatten)]
+    dependencies: DependencyArgs,
+}
+
+#[derive(Clone, Debug, Args)]
+struct LintOptions {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Linter to run; it is invoked as `<linter> <args> -p <builddir> <file>`
+    #[arg(long, default_value = "clang-tidy")]
+    linter: String,
+
+    /// Extra argument passed to the linter (repeatable)
+    #[arg(long = "linter-arg", allow_hyphen_values = true)]
+    linter_args: Vec<String>,
+
+    /// Maximum number of files linted concurrently (defaults to CPU count)
+    #[arg(short = 'j', long)]
+    jobs: Option<usize>,
+
+    /// Fail when the linter reports warnings, not only errors
+    #[arg(long)]
+    werror: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+struct FmtOptions {
+    /// Path to the crust manifest (TOML)
+    #[arg(long, default_value = "crust.build")]
+    manifest: PathBuf,
+
+    /// Formatter to run (must accept clang-format's options)
+    #[arg(long, default_value = "clang-format")]
+    formatter: String,
+
+    /// Report unformatted files and fail instead of rewriting them
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+struct FmtManifestOptions {
+    /// Path to the crust manifest (TOML)
+    #[arg(long, default_value = "crust.build")]
+    manifest: PathBuf,
+
+    /// Fail if the manifest is not formatted instead of rewriting it
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+struct AddTargetOptions {
+    /// Path to the crust manifest (TOML)
+    #[arg(long, default_value = "crust.build")]
+    manifest: PathBuf,
+
+    /// Kind of target to add
+    #[arg(value_enum)]
+    kind: NewTargetKind,
+
+    /// Name of the new target
+    name: String,
+
+    /// Source files, relative to the manifest directory
+    #[arg(required = true)]
+    sources: Vec<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct AddDepOptions {
+    /// Path to the crust manifest (TOML)
+    #[arg(long, default_value = "crust.build")]
+    manifest: PathBuf,
+
+    /// Target that gains the dependencies
+    target: String,
+
+    /// Targets it should depend on
+    #[arg(required = true)]
+    deps: Vec<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct IdeOptions {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Write .vscode/tasks.json, launch.json, and c_cpp_properties.json
+    #[arg(long)]
+    vscode: bool,
+
+    /// Write compile_flags.txt for clangd and CLion
+    #[arg(long)]
+    compile_flags: bool,
+
+    /// Overwrite existing files instead of leaving them alone
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+struct QueryOptions {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Query expression: names, //..., deps(), rdeps(), kind(), and + - ^
+    expression: String,
+
+    /// How to print the matching targets
+    #[arg(long, value_enum, default_value_t = QueryOutput::Names)]
+    output: QueryOutput,
+}
+
+#[derive(Clone, Debug, Args)]
+struct BuildTargets {
+    #[command(flatten)]
+    build: CommandOptions,
+
+    /// Targets to check with their dependencies (defaults to all but docs targets)
+    targets: Vec<String>,
+
+    /// Also check the targets carrying this label (repeatable)
+    #[arg(long = "label")]
+    labels: Vec<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct TargetsOptions {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Print a JSON array instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+struct DepsOptions {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Look the dependencies up and show how each was found, its version, and its flags
+    #[arg(long)]
+    resolved: bool,
+
+    #[command(flatten)]
+    dependencies: DependencyArgs,
+}
+
+/// Overrides of the manifest's [dependencies], for every command that looks
+/// them up.
+#[derive(Clone, Debug, Args)]
+struct DependencyArgs {
+    /// Set a dependency's feature to enabled, disabled, or auto (repeatable)
+    #[arg(long = "feature", value_name = "NAME=VALUE", value_parser = deps::parse_feature)]
+    features: Vec<(String, Feature)>,
+
+    /// Build the fallback targets of these dependencies without looking them up
+    #[arg(long, value_name = "NAME,...", value_delimiter = ',')]
+    force_fallback_for: Vec<String>,
+
+    /// Whether dependencies may fall back to targets of the project
+    #[arg(long, value_enum, default_value_t = WrapMode::Default)]
+    wrap_mode: WrapMode,
+}
+
+impl DependencyArgs {
+    fn overrides(&self) -> deps::Overrides {
+        deps::Overrides {
+            features: self.features.clone(),
+            force_fallback_for: self.force_fallback_for.clone(),
+            wrap_mode: self.wrap_mode,
         }
     }
 }
 
-fn format_duration(duration: Duration) -> String {
-    format!("{:.2}s", duration.as_secs_f64())
+#[derive(Clone, Debug, Args)]
+struct CrossFileOptions {
+    #[command(subcommand)]
+    profile: CrossProfile,
+
+    /// Write the cross file here instead of stdout
+    #[arg(short = 'o', long, global = true)]
+    output: Option<PathBuf>,
 }
 
-fn clean(builddir: &PathBuf) -> Result<()> {
-    if builddir.exists() {
-        std::fs::remove_dir_all(builddir)
-            .with_context(|| format!("Failed to remove {}", builddir.display()))?;
-        println!("Removed {}", builddir.display());
-    } else {
-        println!("Nothing to clean");
+#[derive(Clone, Debug, Subcommand)]
+enum CrossProfile {
+    /// WebAssembly via Emscripten (emcc/emar from PATH or $EMSDK)
+    Emscripten,
+    /// Android via the NDK's LLVM toolchain
+    Android {
+        /// NDK root directory (defaults to $ANDROID_NDK_HOME)
+        #[arg(long, env = "ANDROID_NDK_HOME")]
+        ndk: PathBuf,
+
+        /// Target ABI
+        #[arg(long, value_enum, default_value_t = AndroidAbi::Arm64V8a)]
+        abi: AndroidAbi,
+
+        /// Minimum Android API level
+        #[arg(long, default_value_t = 24)]
+        api: u32,
+    },
+}
+
+#[derive(Clone, Debug, Args)]
+struct EnvOptions {
+    #[command(flatten)]
+    project: ProjectArgs,
+
+    /// Write a sourceable activation script to this file instead of stdout
+    #[arg(short = 'o', long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
     }
-    Ok(())
 }
 
-fn backend_from_choice(
-    choice: BackendChoice,
-    manifest_dir: &Path,
-    jobs: Option<usize>,
-) -> Box<dyn Backend> {
-    match choice {
-        BackendChoice::Native => Box::new(CrustBackend::new(manifest_dir.to_path_buf(), jobs)),
-        BackendChoice::Ninja => Box::new(NinjaBackend),
-        BackendChoice::Make => Box::new(MakeBackend),
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum BackendChoice {
+    Native,
+    Ninja,
+    Make,
+    Xcode,
+    Vs,
+    Bazel,
+    Buck2,
+}
+
+fn main() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let result = check_daemon_client(&cli, &matches)
+        .and_then(|()| apply_defaults(&mut cli, &matches))
+        .and_then(|()| {
+            // After the defaults, since the native file's environment must be
+            // exported before any thread starts.
+            handle_interrupts();
+            run(cli)
+        });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            ExitCode::from(CrustError::find(&err).map_or(1, CrustError::exit_code))
+        }
     }
 }
 
-trait BackendHint {
-    fn command_hint(&self) -> &'static str;
+/// Kills the commands of running builds when crust is interrupted or
+/// terminated. They lead process groups of their own, so the terminal's
+/// Ctrl-C does not reach them, and would otherwise keep writing into the
+/// build directory after crust exits.
+fn handle_interrupts() {
+    let installed = ctrlc::set_handler(|| {
+        executor::cancel::interrupt();
+        std::process::exit(130);
+    });
+    if let Err(err) = installed {
+        tracing::debug!("cannot handle interrupts: {err}");
+    }
+}
+
+/// Fills the options not given on the command line from the user's and the
+/// repository's config files (see [`settings`]). The repository's is found
+/// next to the manifest.
+fn apply_defaults(cli: &mut Cli, matches: &ArgMatches) -> Result<()> {
+    let sub = matches.subcommand().map_or(matches, |(_, sub)| sub);
+    let given =
+        |matches: &ArgMatches, id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if let Commands::Daemon(daemon) = &mut cli.command {
+        // The daemon applies the defaults again when their files change.
+        daemon.command_line = Some(Box::new(daemon.build.clone()));
+        daemon.given = command_option_args()
+            .iter()
+            .map(|arg| arg.get_id().to_string())
+            .filter(|id| given(sub, id))
+            .collect();
+    }
+    let mut opts = match &mut cli.command {
+        Commands::Configure(opts) | Commands::Test(opts) => Some(opts),
+        Commands::Build(BuildOptions { build, .. })
+        | Commands::Run(RunOptions { build, .. })
+        | Commands::Install(InstallOptions { build, .. })
+        | Commands::Package(PackageOptions { build, .. })
+        | Commands::Outdated(BuildTargets { build, .. })
+        | Commands::Daemon(DaemonOptions { build, .. }) => Some(build),
+        _ => None,
+    };
+    let defaults = match opts.as_deref_mut() {
+        Some(opts) => {
+            let defaults = option_defaults(opts)?;
+            opts.native.export_env();
+            defaults
+        }
+        None => settings::Defaults::load(Path::new("."))?,
+    };
+
+    if let Some(level) = defaults
+        .log_level
+        .as_deref()
+        .filter(|_| !given(sub, "log_level"))
+    {
+        cli.log_level = config_value("log_level", level)?;
+    }
+    if let Some(format) = defaults
+        .log_format
+        .as_deref()
+        .filter(|_| !given(sub, "log_format"))
+    {
+        cli.log_format = config_value("log_format", format)?;
+    }
+    if let Some(color) = defaults.color.as_deref().filter(|_| !given(sub, "color")) {
+        cli.color = config_value("color", color)?;
+    }
+    match opts {
+        Some(opts) => fill_options(opts, &defaults, |id| given(sub, id)),
+        None => Ok(()),
+    }
+}
+
+/// The option defaults for `opts`: those of the user's config file and the
+/// repository's next to its manifest, overridden by its native file's,
+/// which is loaded into `opts.native`, and by its profile's.
+fn option_defaults(opts: &mut CommandOptions) -> Result<settings::Defaults> {
+    let manifest_dir = ProjectManifest::manifest_dir(&opts.manifest);
+    let mut defaults = settings::Defaults::load(&manifest_dir)?;
+    if let Some(path) = &opts.native_file {
+        opts.native = NativeFile::load(path)?;
+        defaults = opts.native.options.clone().or(defaults);
+    }
+    if let Some(name) = &opts.profile {
+        let manifest = ProjectManifest::load(&opts.manifest)?;
+        defaults = manifest.profile(name)?.options.clone().or(defaults);
+    }
+    Ok(defaults)
+}
+
+/// Sets the options of `opts` not `given` on the command line to their
+/// `defaults`.
+fn fill_options(
+    opts: &mut CommandOptions,
+    defaults: &settings::Defaults,
+    given: impl Fn(&str) -> bool,
+) -> Result<()> {
+    if let Some(backend) = defaults.backend.as_deref().filter(|_| !given("backend")) {
+        opts.backend = config_value("backend", backend)?;
+    }
+    if let Some(summary) = defaults.summary.as_deref().filter(|_| !given("summary")) {
+        opts.summary = config_value("summary", summary)?;
+    }
+    opts.jobs = opts.jobs.or(defaults.jobs);
+    opts.load_average = opts.load_average.or(defaults.load_average);
+    opts.max_link_jobs = opts.max_link_jobs.or(defaults.max_link_jobs);
+    opts.max_custom_jobs = opts.max_custom_jobs.or(defaults.max_custom_jobs);
+    opts.fail_fast |= defaults.fail_fast == Some(true);
+    opts.watchdog = opts.watchdog.or(defaults.watchdog);
+    opts.hermetic |= defaults.hermetic == Some(true);
+    opts.launcher = opts.launcher.take().or(defaults.launcher.clone());
+    // Sandboxed and traced builds run locally, as they would with
+    // --remote-executor rejected on the command line.
+    if !opts.sandbox && !opts.check_deps {
+        opts.remote_executor = opts.remote_executor.take().or(defaults.cache_url.clone());
+    }
+    Ok(())
+}
+
+/// The arguments of [`CommandOptions`].
+fn command_option_args() -> Vec<clap::Arg> {
+    CommandOptions::augment_args(clap::Command::new("crust"))
+        .get_arguments()
+        .cloned()
+        .collect()
+}
+
+/// Fails when `crust build --daemon` is given an option of the build, which
+/// the daemon would ignore: it builds with the options it was started with.
+fn check_daemon_client(cli: &Cli, matches: &ArgMatches) -> Result<()> {
+    let Commands::Build(BuildOptions { daemon: true, .. }) = cli.command else {
+        return Ok(());
+    };
+    let sub = matches.subcommand().map_or(matches, |(_, sub)| sub);
+    // The client finds the daemon by its build directory and notifies.
+    let ignored = command_option_args().into_iter().find(|arg| {
+        !matches!(arg.get_id().as_str(), "builddir" | "notify")
+            && sub.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    });
+    match ignored {
+        Some(arg) => Err(CrustError::InvalidArgument {
+            message: format!(
+                "--{} cannot be used with --daemon; the daemon builds with the options it was \
+                 started with",
+                arg.get_long().unwrap_or(arg.get_id().as_str())
+            ),
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// `value` of `key` in a config file, spelled as on the command line.
+fn config_value<T: ValueEnum>(key: &str, value: &str) -> Result<T> {
+    T::from_str(value, false).map_err(|_| {
+        let expected: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|variant| Some(variant.to_possible_value()?.get_name().to_string()))
+            .collect();
+        CrustError::InvalidArgument {
+            message: format!(
+                "invalid {key} '{value}' in the crust config, expected one of: {}",
+                expected.join(", ")
+            ),
+        }
+        .into()
+    })
+}
+
+fn run(cli: Cli) -> Result<()> {
+    logging::init(
+        cli.log_level.into(),
+        cli.log_format,
+        cli.color,
+        matches!(
+            cli.command,
+            Commands::Lsp
+                | Commands::Query(_)
+                | Commands::Targets(_)
+                | Commands::Deps(_)
+                | Commands::Outdated(_)
+                | Commands::Stats(_)
+        ),
+    )?;
+
+    let notify = match &cli.command {
+        Commands::Build(opts) => opts.build.notify.map(|min| ("build", min)),
+        Commands::Test(opts) => opts.notify.map(|min| ("test", min)),
+        _ => None,
+    };
+    let builddir = match &cli.command {
+        Commands::Configure(opts) | Commands::Test(opts) => Some(opts),
+        // The daemon takes the lock itself for each build.
+        Commands::Build(BuildOptions {
+            build,
+            daemon: false,
+            ..
+        })
+        | Commands::Run(RunOptions { build, .. })
+        | Commands::Install(InstallOptions { build, .. })
+        | Commands::Package(PackageOptions { build, .. }) => Some(build),
+        _ => None,
+    };
+    let _lock = builddir
+        .map(|opts| BuildDirLock::acquire(&opts.builddir, opts.wait))
+        .transpose()?;
+    let start = Instant::now();
+    let result = dispatch(cli.command);
+    if let Some((command, min)) = notify {
+        let elapsed = start.elapsed();
+        if elapsed >= Duration::from_secs(min) {
+            notify::finished(command, result.as_ref().err(), elapsed);
+        }
+    }
+    result
+}
+
+fn dispatch(command: Commands) -> Result<()> {
+    match command {
+        Commands::Configure(opts) => drive(&opts, &[], &[], false),
+        Commands::Build(opts) if opts.analyze => {
+            run_analysis(&opts.build, &opts.targets, &opts.labels)
+        }
+        Commands::Build(opts) if opts.daemon => build_in_daemon(&opts),
+        Commands::Build(opts) => drive(&opts.build, &opts.targets, &opts.labels, true),
+        Commands::Test(opts) => run_tests(&opts),
+        Commands::Run(opts) => run_target(&opts),
+        Commands::Clean {
+            builddir,
+            manifest,
+            force,
+        } => clean(&builddir, &manifest, force),
+        Commands::Doctor => run_doctor(),
+        Commands::Sbom(opts) => run_sbom(&opts),
+        Commands::Env(opts) => run_env(&opts),
+        Commands::CrossFile(opts) => run_cross_file(&opts),
+        Commands::Lint(opts) => run_lint(&opts),
+        Commands::Fmt(opts) => run_fmt(&opts),
+        Commands::FmtManifest(opts) => run_fmt_manifest(&opts),
+        Commands::AddTarget(opts) => run_add_target(&opts),
+        Commands::AddDep(opts) => run_add_dep(&opts),
+        Commands::Lsp => lsp::serve(std::io::stdin().lock(), std::io::stdout().lock()),
+        Commands::Ide(opts) => run_ide(&opts),
+        Commands::Query(opts) => run_query(&opts),
+        Commands::Targets(opts) => run_targets(&opts),
+        Commands::Deps(opts) => run_deps(&opts),
+        Commands::Outdated(opts) => run_outdated(&opts),
+        Commands::Stats(opts) => run_stats(&opts),
+        Commands::Daemon(opts) => run_daemon(&opts),
+        Commands::Install(opts) => run_install(&opts),
+        Commands::I18n(opts) => run_i18n(&opts),
+        Commands::Package(opts) => run_package(&opts),
+    }
+}
+
+fn drive(
+    opts: &CommandOptions,
+    targets: &[String],
+    labels: &[String],
+    show_hint: bool,
+) -> Result<()> {
+    let (manifest, graph, cross, dependencies) = load_build(opts)?;
+    let targets = requested_targets(&graph, targets, labels)?;
+    build_graph(
+        opts,
+        &manifest,
+        &graph.select(&targets)?,
+        &cross,
+        dependencies,
+        show_hint,
+    )
+}
+
+/// The targets named on the command line plus those carrying `labels`.
+fn requested_targets(
+    graph: &DependencyGraph,
+    targets: &[String],
+    labels: &[String],
+) -> Result<Vec<String>> {
+    let mut targets = targets.to_vec();
+    targets.extend(graph.labelled(labels)?);
+    Ok(targets)
+}
+
+fn build_graph(
+    opts: &CommandOptions,
+    manifest: &ProjectManifest,
+    graph: &DependencyGraph,
+    cross: &CrossFile,
+    dependencies: Resolution,
+    show_hint: bool,
+) -> Result<()> {
+    let backend = prepare_backend(opts, manifest, graph, cross, dependencies)?;
+    emit_build(
+        opts,
+        graph,
+        backend.as_ref(),
+        show_hint,
+        &mut std::io::stdout(),
+    )
+}
+
+/// Checks the options and sets up the backend for building `graph`, with
+/// the programs its commands run, the Python it compiles against, and the
+/// spelling of its language standards found.
+fn prepare_backend(
+    opts: &CommandOptions,
+    manifest: &ProjectManifest,
+    graph: &DependencyGraph,
+    cross: &CrossFile,
+    dependencies: Resolution,
+) -> Result<Box<dyn Backend>> {
+    for (flag, value) in [
+        ("--jobs", opts.jobs),
+        ("--max-link-jobs", opts.max_link_jobs),
+        ("--max-custom-jobs", opts.max_custom_jobs),
+    ] {
+        if value == Some(0) {
+            return Err(CrustError::InvalidArgument {
+                message: format!("{flag} must be at least 1"),
+            }
+            .into());
+        }
+    }
+    if opts.watchdog == Some(0) {
+        return Err(CrustError::InvalidArgument {
+            message: "--watchdog must be at least 1".into(),
+        }
+        .into());
+    }
+    if opts
+        .load_average
+        .is_some_and(|load| load.is_nan() || load <= 0.0)
+    {
+        return Err(CrustError::InvalidArgument {
+            message: "--load-average must be greater than 0".into(),
+        }
+        .into());
+    }
+    if opts.ui && opts.backend != BackendChoice::Native {
+        return Err(CrustError::InvalidArgument {
+            message: "--ui needs the native backend".into(),
+        }
+        .into());
+    }
+    let programs = programs::resolve(graph, &opts.program_paths)?;
+    let python = python::find(graph, &programs)?;
+    let standards = standards::check(graph, cross)?;
+    backend_from_choice(
+        opts,
+        manifest,
+        cross,
+        dependencies,
+        programs,
+        python,
+        standards,
+    )
+}
+
+/// Builds `graph` with `backend`, writing the summary and, with
+/// `show_hint`, where the outputs are to `out`.
+fn emit_build(
+    opts: &CommandOptions,
+    graph: &DependencyGraph,
+    backend: &dyn Backend,
+    show_hint: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let manifest_dir = ProjectManifest::manifest_dir(&opts.manifest);
+    check_deprecations(graph, opts.fatal_deprecations)?;
+    builddir::check_placement(graph, &manifest_dir, &opts.builddir)?;
+    Meta::new(&opts.manifest, backend.name())?.record(&opts.builddir)?;
+    let outputs_to_check = backend.primary_outputs(graph, &opts.builddir);
+    // The native backend decides per edge, which also catches changed
+    // command lines (a new cross file, say) that leave every mtime alone.
+    let outdated = opts.backend == BackendChoice::Native
+        || outputs_to_check.is_empty()
+        || graph.is_outdated(&opts.manifest, &outputs_to_check)?;
+
+    if !outdated {
+        writeln!(
+            out,
+            "{} backend already up-to-date at {}",
+            backend.name(),
+            opts.builddir.display()
+        )?;
+    } else {
+        let emit_start = Instant::now();
+        let session = opts
+            .ui
+            .then(|| ui::start(stats::Estimate::new(&stats::load(&opts.builddir))))
+            .transpose()?;
+        let emitted = backend.emit(graph, &opts.builddir, &manifest_dir);
+        if let Some(session) = session {
+            session.finish()?;
+        }
+        let mut result = emitted.map_err(|err| attribute_failure(err, graph))?;
+        let total_elapsed = emit_start.elapsed();
+
+        if result.target_summaries.is_empty() {
+            result.target_summaries = graph
+                .topo_order()?
+                .into_iter()
+                .map(|node| backend_summary_from_graph(node, &opts.builddir))
+                .collect();
+        }
+
+        print_summary(backend, &result, total_elapsed, opts.summary, out)?;
+        // Only the native backend times targets; the others just write files.
+        if backend.name() == "native" {
+            stats::record(
+                &opts.builddir,
+                stats::BuildStats::new(&result, total_elapsed),
+            )?;
+        }
+        let warnings = total_diagnostics(&result).warnings;
+        if let Some(max) = opts.max_warnings.filter(|max| warnings > *max) {
+            return Err(anyhow::anyhow!(
+                "too many warnings: the build printed {warnings}, --max-warnings allows {max}"
+            ));
+        }
+    }
+
+    if show_hint {
+        if backend.name() == "native" {
+            writeln!(
+                out,
+                "Native build complete. Outputs live in {}",
+                opts.builddir.display()
+            )?;
+        } else if let Some(file) = backend
+            .primary_outputs(graph, &opts.builddir)
+            .first()
+            .filter(|_| matches!(opts.backend, BackendChoice::Bazel | BackendChoice::Buck2))
+        {
+            writeln!(
+                out,
+                "Exported {}. Copy it next to {} to build with '{}'.",
+                file.display(),
+                opts.manifest.display(),
+                opts.backend.command_hint()
+            )?;
+        } else {
+            writeln!(
+                out,
+                "Backend ready. Invoke '{}' in {} to build.",
+                opts.backend.command_hint(),
+                opts.builddir.display()
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `clang --analyze` or `gcc -fanalyzer` over the compilation database,
+/// writing reports to `builddir/analysis/` and leaving normal objects alone.
+fn run_analysis(opts: &CommandOptions, targets: &[String], labels: &[String]) -> Result<()> {
+    let (_, graph, cross) = opts.load()?;
+    let targets = requested_targets(&graph, targets, labels)?;
+    if let Some(0) = opts.jobs {
+        return Err(CrustError::InvalidArgument {
+            message: "--jobs must be at least 1".into(),
+        }
+        .into());
+    }
+    let manifest_dir = ProjectManifest::manifest_dir(&opts.manifest);
+    let standards = standards::check(&graph, &cross)?;
+    let backend = CrustBackend::new(manifest_dir, None)
+        .with_cross_file(cross)
+        .with_standards(standards);
+    let commands = backend.compile_database(&graph.select(&targets)?, &opts.builddir)?;
+    let report = analyze::run(&commands, &opts.builddir, opts.jobs)?;
+    for diagnostic in &report.diagnostics {
+        println!("  {diagnostic}");
+    }
+    for failure in &report.failures {
+        println!("  {failure}");
+    }
+    println!(
+        "\nAnalyzed {} file(s): {} warning(s); reports in {}",
+        report.files,
+        report.count(lint::Severity::Warning),
+        opts.builddir.join(analyze::ANALYSIS_DIR).display()
+    );
+    if !report.failures.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Static analysis failed on {} file(s)",
+            report.failures.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Builds the project, then runs each test executable and fails if any of
+/// them exits unsuccessfully.
+fn run_tests(opts: &CommandOptions) -> Result<()> {
+    let (manifest, graph, cross, dependencies) = load_build(opts)?;
+    build_graph(
+        opts,
+        &manifest,
+        &graph.select(&[])?,
+        &cross,
+        dependencies,
+        false,
+    )?;
+
+    let outcomes = Runner::new(&graph, &opts.builddir, &cross)?.run_tests()?;
+    if outcomes.is_empty() {
+        println!("No tests defined; mark executables with `test = true` to run them");
+        return Ok(());
+    }
+
+    println!("\nTest summary");
+    for outcome in &outcomes {
+        let status = if outcome.passed() { "ok" } else { "FAIL" };
+        let owners = match graph.get(&outcome.name) {
+            Some(node) if !outcome.passed() && !node.owners.is_empty() => {
+                format!(", owned by {}", node.owners.join(", "))
+            }
+            _ => String::new(),
+        };
+        println!(
+            "  [{status:<4}] {} ({}{owners})",
+            outcome.name,
+            format_duration(outcome.duration)
+        );
+    }
+    let failed: Vec<_> = outcomes.iter().filter(|o| !o.passed()).collect();
+    println!(
+        "  {} passed, {} failed",
+        outcomes.len() - failed.len(),
+        failed.len()
+    );
+    match failed.first() {
+        None => Ok(()),
+        Some(first) => Err(anyhow::Error::from(CrustError::CommandFailed {
+            target: first.name.clone(),
+            action: "Test".into(),
+            exit: first.exit,
+        })
+        .context(format!(
+            "{} of {} tests failed",
+            failed.len(),
+            outcomes.len()
+        ))),
+    }
+}
+
+/// Builds the project, then runs one executable with the given arguments.
+fn run_target(opts: &RunOptions) -> Result<()> {
+    let (manifest, graph, cross, dependencies) = load_build(&opts.build)?;
+    build_graph(
+        &opts.build,
+        &manifest,
+        &graph.select(&[])?,
+        &cross,
+        dependencies,
+        false,
+    )?;
+
+    let mut cmd =
+        Runner::new(&graph, &opts.build.builddir, &cross)?.command(&opts.target, &opts.args)?;
+    let status = cmd
+        .status()
+        .map_err(|err| runner::spawn_error(cmd.get_program(), err))?;
+    if !status.success() {
+        return Err(CrustError::CommandFailed {
+            target: opts.target.clone(),
+            action: "Run".into(),
+            exit: status.code(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Names the owners of the target a command failed for, so broken builds
+/// can be routed to them.
+fn attribute_failure(err: anyhow::Error, graph: &DependencyGraph) -> anyhow::Error {
+    let message = match CrustError::find(&err) {
+        Some(CrustError::CommandFailed { target, .. }) => graph
+            .get(target)
+            .filter(|node| !node.owners.is_empty())
+            .map(|node| {
+                format!(
+                    "Target '{}' owned by {} failed",
+                    node.base_name(),
+                    node.owners.join(", ")
+                )
+            }),
+        _ => None,
+    };
+    match message {
+        Some(message) => err.context(message),
+        None => err,
+    }
+}
+
+fn backend_summary_from_graph(node: &graph::TargetNode, builddir: &Path) -> TargetBuildSummary {
+    TargetBuildSummary {
+        name: node.name.clone(),
+        built: false,
+        outputs: node.outputs.iter().map(|o| builddir.join(o)).collect(),
+        duration: Duration::default(),
+        diagnostics: Default::default(),
+    }
+}
+
+/// Number of targets listed by the short summary.
+const SLOWEST_TARGETS: usize = 10;
+
+fn print_summary(
+    backend: &dyn Backend,
+    result: &BackendEmitResult,
+    total_elapsed: Duration,
+    mode: SummaryMode,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    for line in summary_lines(backend.name(), result, total_elapsed, mode) {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Lines of the summary of a build with the backend named `backend`, in
+/// the detail `mode` asks for.
+fn summary_lines(
+    backend: &str,
+    result: &BackendEmitResult,
+    total_elapsed: Duration,
+    mode: SummaryMode,
+) -> Vec<String> {
+    if mode == SummaryMode::None {
+        return Vec::new();
+    }
+    let built_count = result.target_summaries.iter().filter(|t| t.built).count();
+    let skipped_count = result.target_summaries.len().saturating_sub(built_count);
+
+    let mut lines = vec![
+        String::new(),
+        "Build summary".to_string(),
+        format!("  Backend: {backend}"),
+        format!(
+            "  Targets: {} built, {} skipped, {} total",
+            built_count,
+            skipped_count,
+            result.target_summaries.len()
+        ),
+        format!("  Elapsed time: {}", format_duration(total_elapsed)),
+    ];
+    // Only the native backend runs the compilers itself.
+    if backend == "native" {
+        lines.push(format!("  Diagnostics: {}", total_diagnostics(result)));
+    }
+
+    if mode == SummaryMode::Short {
+        let mut slowest: Vec<&TargetBuildSummary> = result
+            .target_summaries
+            .iter()
+            .filter(|t| t.built && !t.duration.is_zero())
+            .collect();
+        slowest.sort_by_key(|t| std::cmp::Reverse(t.duration));
+        if !slowest.is_empty() {
+            lines.push("  Slowest targets:".to_string());
+            lines.extend(slowest.iter().take(SLOWEST_TARGETS).map(|target| {
+                format!(
+                    "    - {} ({})",
+                    target.name,
+                    format_duration(target.duration)
+                )
+            }));
+        }
+        return lines;
+    }
+
+    if !result.files.is_empty() {
+        lines.push("  Backend outputs:".to_string());
+        lines.extend(
+            result
+                .files
+                .iter()
+                .map(|file| format!("    - {}", file.display())),
+        );
+    }
+
+    if !result.target_summaries.is_empty() {
+        lines.push("  Target results:".to_string());
+        for target in &result.target_summaries {
+            let status = if target.built { "built" } else { "skipped" };
+            let diagnostics = if target.diagnostics == diagnostics::Counts::default() {
+                String::new()
+            } else {
+                format!(", {}", target.diagnostics)
+            };
+            lines.push(format!(
+                "    - {} ({status}, {}{diagnostics})",
+                target.name,
+                format_duration(target.duration)
+            ));
+            lines.extend(
+                target
+                    .outputs
+                    .iter()
+                    .map(|output| format!("      -> {}", output.display())),
+            );
+        }
+    }
+    lines
+}
+
+/// Warnings and errors of every target in `result`.
+fn total_diagnostics(result: &BackendEmitResult) -> diagnostics::Counts {
+    let mut total = diagnostics::Counts::default();
+    for target in &result.target_summaries {
+        total += target.diagnostics;
+    }
+    total
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+fn clean(builddir: &Path, manifest: &Path, force: bool) -> Result<()> {
+    if builddir.exists() {
+        builddir::check_removable(builddir, &ProjectManifest::manifest_dir(manifest), force)?;
+        let _lock = BuildDirLock::acquire(builddir, false)?;
+        std::fs::remove_dir_all(builddir)
+            .with_context(|| format!("Failed to remove {}", builddir.display()))?;
+        println!("Removed {}", builddir.display());
+    } else {
+        println!("Nothing to clean");
+    }
+    Ok(())
+}
+
+fn run_doctor() -> Result<()> {
+    let scratch = std::env::temp_dir().join(format!("crust-doctor-{}", std::process::id()));
+    let report = doctor::run(&scratch);
+
+    println!("Toolchain check");
+    for tool in &report.tools {
+        match &tool.path {
+            Some(path) => println!(
+                "  [ok]      {:<10} {} ({})",
+                tool.name,
+                path.display(),
+                tool.version.as_deref().unwrap_or("unknown version")
+            ),
+            None if tool.required => println!(
+                "  [missing] {:<10} required: {}; install it or add it to PATH",
+                tool.name, tool.purpose
+            ),
+            None => println!("  [absent]  {:<10} optional: {}", tool.name, tool.purpose),
+        }
+    }
+    match &report.compile_check {
+        Ok(()) => println!("  [ok]      compile+link of a trivial C program"),
+        Err(reason) => println!("  [failed]  compile+link of a trivial C program: {reason}"),
+    }
+
+    match report.problems() {
+        0 => {
+            println!("\nToolchain looks ready for native builds.");
+            Ok(())
+        }
+        count => Err(anyhow::anyhow!(
+            "{count} toolchain problem(s) found; native builds will fail until they are fixed"
+        )),
+    }
+}
+
+/// Describes the build directory with the tools and dependencies a build
+/// with the same cross or native file, program paths, and dependency
+/// overrides resolves.
+fn run_sbom(opts: &SbomOptions) -> Result<()> {
+    let project = &opts.project;
+    let mut manifest = ProjectManifest::load(&project.manifest)?;
+    let cross = match &opts.native_file {
+        Some(path) => NativeFile::load(path)?.machine(),
+        None => CrossFile::load_optional(project.cross_file.as_deref())?,
+    };
+    let dependencies = deps::resolve(
+        &manifest,
+        &opts.dependencies.overrides(),
+        &ProjectManifest::manifest_dir(&project.manifest),
+        &project.builddir,
+    )?;
+    deps::apply(&mut manifest, &dependencies)?;
+    let graph = DependencyGraph::from_manifest(&manifest, cross.platform())?;
+    let programs = programs::resolve(&graph, &opts.program_paths)?;
+    let tools = sbom::build_tools(&graph, &cross, &programs);
+    let (input, missing) =
+        SbomInput::collect(&manifest, &graph, &project.builddir, tools, &dependencies)?;
+    for path in &missing {
+        tracing::warn!(
+            "{} has not been built and is omitted from the SBOM; run `crust build` first",
+            path.display()
+        );
+    }
+
+    let document = serde_json::to_string_pretty(&input.render(opts.format))?;
+    match &opts.output {
+        Some(path) => {
+            std::fs::write(path, document + "\n")
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Wrote SBOM to {}", path.display());
+        }
+        None => println!("{document}"),
+    }
+    Ok(())
+}
+
+fn run_env(opts: &EnvOptions) -> Result<()> {
+    let (_, graph) = opts.project.load()?;
+    let env = DevEnv::from_graph(&graph, &opts.project.builddir)?;
+    let script = env.render_sh();
+    match &opts.output {
+        Some(path) => {
+            let content = format!(
+                "# Generated by crust. Source this file to run uninstalled binaries.\n{script}"
+            );
+            std::fs::write(path, content)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Wrote environment script to {}", path.display());
+            println!("Activate it with: . {}", path.display());
+        }
+        None => print!("{script}"),
+    }
+    Ok(())
+}
+
+/// Refreshes the compilation database, lints every C/C++ source through the
+/// executor, and prints the findings grouped by target.
+fn run_lint(opts: &LintOptions) -> Result<()> {
+    let project = &opts.project;
+    let (_, graph, cross) = load_project(&project.manifest, project.cross_file.as_deref())?;
+    if toolchain::find_program(&opts.linter).is_none() {
+        return Err(CrustError::CompilerMissing {
+            program: opts.linter.clone(),
+        }
+        .into());
+    }
+    let manifest_dir = ProjectManifest::manifest_dir(&project.manifest);
+    std::fs::create_dir_all(&project.builddir)
+        .with_context(|| format!("Failed to create {}", project.builddir.display()))?;
+    let standards = standards::check(&graph, &cross)?;
+    let backend = CrustBackend::new(manifest_dir.clone(), None)
+        .with_cross_file(cross)
+        .with_standards(standards);
+    compdb::write(
+        &project.builddir,
+        &backend.compile_database(&graph, &project.builddir)?,
+    )?;
+
+    let linter = lint::Linter {
+        program: opts.linter.clone(),
+        args: opts.linter_args.clone(),
+        database_dir: project.builddir.clone(),
+    };
+    let report = lint::run(&graph, &manifest_dir, &linter, opts.jobs)?;
+    for (target, diagnostics) in &report.by_target {
+        println!("\n{target}:");
+        for diagnostic in diagnostics {
+            println!("  {diagnostic}");
+        }
+    }
+    for failure in &report.failures {
+        println!("  {failure}");
+    }
+
+    let errors = report.count(lint::Severity::Error) + report.failures.len();
+    let warnings = report.count(lint::Severity::Warning);
+    println!(
+        "\nLinted {} file(s): {} error(s), {} warning(s)",
+        report.files, errors, warnings
+    );
+    if errors > 0 || (opts.werror && warnings > 0) {
+        return Err(anyhow::anyhow!(
+            "{} reported {} problem(s)",
+            opts.linter,
+            if opts.werror {
+                errors + warnings
+            } else {
+                errors
+            }
+        ));
+    }
+    Ok(())
+}
+
+fn run_fmt(opts: &FmtOptions) -> Result<()> {
+    let (_, graph, _) = load_project(&opts.manifest, None)?;
+    if toolchain::find_program(&opts.formatter).is_none() {
+        return Err(CrustError::CompilerMissing {
+            program: opts.formatter.clone(),
+        }
+        .into());
+    }
+    let manifest_dir = ProjectManifest::manifest_dir(&opts.manifest);
+    let files = formatter::sources(&graph, &manifest_dir);
+    let failed = formatter::run(&opts.formatter, &files, opts.check)?;
+    for file in &failed {
+        let relative = file.strip_prefix(&manifest_dir).unwrap_or(file);
+        if opts.check {
+            println!("  needs formatting: {}", relative.display());
+        } else {
+            println!("  could not format: {}", relative.display());
+        }
+    }
+    if !failed.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} of {} file(s) {}",
+            failed.len(),
+            files.len(),
+            if opts.check {
+                "need formatting; run `crust fmt`"
+            } else {
+                "could not be formatted"
+            }
+        ));
+    }
+    let verb = if opts.check { "Checked" } else { "Formatted" };
+    println!("{verb} {} file(s)", files.len());
+    Ok(())
+}
+
+fn run_fmt_manifest(opts: &FmtManifestOptions) -> Result<()> {
+    let path = &opts.manifest;
+    let source = std::fs::read_to_string(path).map_err(|source| CrustError::ManifestRead {
+        path: path.clone(),
+        source,
+    })?;
+    let formatted =
+        formatter::manifest::format(&source).map_err(|err| CrustError::ManifestParse {
+            path: path.clone(),
+            message: err.to_string(),
+        })?;
+    if formatted == source {
+        println!("{} is formatted", path.display());
+    } else if opts.check {
+        return Err(anyhow::anyhow!(
+            "{} needs formatting; run `crust fmt-manifest`",
+            path.display()
+        ));
+    } else {
+        std::fs::write(path, formatted)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Formatted {}", path.display());
+    }
+    Ok(())
+}
+
+fn run_add_target(opts: &AddTargetOptions) -> Result<()> {
+    let mut editor = ManifestEditor::open(&opts.manifest)?;
+    editor.add_target(opts.kind, &opts.name, &opts.sources)?;
+    editor.save()?;
+    println!(
+        "Added {} '{}' to {}",
+        opts.kind.type_name(),
+        opts.name,
+        opts.manifest.display()
+    );
+    Ok(())
+}
+
+fn run_add_dep(opts: &AddDepOptions) -> Result<()> {
+    let mut editor = ManifestEditor::open(&opts.manifest)?;
+    for dep in &opts.deps {
+        if editor.add_dep(&opts.target, dep)? {
+            println!("'{}' now depends on '{dep}'", opts.target);
+        } else {
+            println!("'{}' already depends on '{dep}'", opts.target);
+        }
+    }
+    editor.save()
+}
+
+/// Refreshes the compilation database and writes IDE project files next to
+/// the manifest, skipping files that already exist unless `--force` is set.
+/// Prints the targets matching a query expression, dependencies first.
+fn run_query(opts: &QueryOptions) -> Result<()> {
+    let (_, graph) = opts.project.load()?;
+    let expr = query::parse(&opts.expression)?;
+    let nodes = query::evaluate(&expr, &graph)?;
+    match opts.output {
+        QueryOutput::Names => {
+            for node in nodes {
+                println!("{}", node.name);
+            }
+        }
+        QueryOutput::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&query::to_json(&nodes, &opts.project.builddir))?
+        ),
+    }
+    Ok(())
+}
+
+fn run_targets(opts: &TargetsOptions) -> Result<()> {
+    let (_, graph) = opts.project.load()?;
+    quiet_broken_pipe(write_targets(opts, &graph, &mut io::stdout().lock()))
+}
+
+/// Writes the declared targets of `graph` to `out`, as a table or, with
+/// `--json`, as JSON.
+fn write_targets(
+    opts: &TargetsOptions,
+    graph: &DependencyGraph,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut nodes = backend::declared_targets(graph)?;
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+    if opts.json {
+        writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(&query::to_json(&nodes, &opts.project.builddir))?
+        )?;
+    } else {
+        write!(out, "{}", query::table(&nodes, &opts.project.builddir))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// `result`, except that a reader which stopped reading, such as `head`,
+/// is not an error.
+fn quiet_broken_pipe(result: Result<()>) -> Result<()> {
+    match result {
+        Err(err)
+            if err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn run_deps(opts: &DepsOptions) -> Result<()> {
+    let project = &opts.project;
+    let manifest = ProjectManifest::load(&project.manifest)?;
+    let manifest_dir = ProjectManifest::manifest_dir(&project.manifest);
+    let resolution = opts
+        .resolved
+        .then(|| {
+            let overrides = opts.dependencies.overrides();
+            deps::resolve(&manifest, &overrides, &manifest_dir, &project.builddir)
+        })
+        .transpose()?;
+    print!("{}", deps::report(&manifest, resolution.as_ref()));
+    Ok(())
+}
+
+fn run_outdated(opts: &BuildTargets) -> Result<()> {
+    let build = &opts.build;
+    let (_, graph, cross) = build.load()?;
+    let targets = requested_targets(&graph, &opts.targets, &opts.labels)?;
+    let graph = graph.select(&targets)?;
+    let programs = programs::resolve(&graph, &build.program_paths)?;
+    let python = python::find(&graph, &programs)?;
+    let standards = standards::check(&graph, &cross)?;
+    let backend = CrustBackend::new(ProjectManifest::manifest_dir(&build.manifest), None)
+        .with_sandbox(build.sandbox)
+        .with_cross_file(cross)
+        .with_programs(programs)
+        .with_python(python)
+        .with_standards(standards);
+    let statuses = backend.outdated(&graph, &build.builddir)?;
+    print!("{}", outdated::report(&statuses));
+    Ok(())
+}
+
+/// A project as `crust daemon` keeps it between builds: loaded, with its
+/// options filled in, its dependencies, programs, and toolchain found, and
+/// the backend set up.
+struct Warm {
+    opts: CommandOptions,
+    graph: DependencyGraph,
+    backend: Box<dyn Backend>,
+    /// The cross, native, and config files read besides the manifests, with
+    /// their size and modification time when they were read.
+    files: Vec<(PathBuf, Option<(u64, SystemTime)>)>,
+}
+
+impl Warm {
+    /// Loads the project with the options `daemon` was started with, the
+    /// defaults from the files as they are now.
+    fn load(daemon: &DaemonOptions) -> Result<Self> {
+        let mut opts = daemon
+            .command_line
+            .as_deref()
+            .unwrap_or(&daemon.build)
+            .clone();
+        let mut files: Vec<PathBuf> = opts.cross_file.iter().cloned().collect();
+        files.extend(opts.native_file.iter().cloned());
+        files.extend(settings::Defaults::files(&ProjectManifest::manifest_dir(
+            &opts.manifest,
+        )));
+        let files = files
+            .into_iter()
+            .map(|path| {
+                let stamp = file_stamp(&path);
+                (path, stamp)
+            })
+            .collect();
+
+        let defaults = option_defaults(&mut opts)?;
+        // The environment is set once, before any thread starts.
+        if opts.native.env != daemon.build.native.env {
+            return Err(CrustError::InvalidArgument {
+                message: "the [env] of the native file changed; restart crust daemon to apply it"
+                    .into(),
+            }
+            .into());
+        }
+        fill_options(&mut opts, &defaults, |id| {
+            daemon.given.iter().any(|given| given == id)
+        })?;
+        let (manifest, graph, cross, dependencies) = load_build(&opts)?;
+        let backend = prepare_backend(&opts, &manifest, &graph, &cross, dependencies)?;
+        Ok(Warm {
+            opts,
+            graph,
+            backend,
+            files,
+        })
+    }
+
+    /// The project in `warm`, loaded again when `reload` is set, when one
+    /// of the files read besides the manifests changed, or when it is not
+    /// loaded because the last attempt failed.
+    fn current<'a>(
+        warm: &'a mut Option<Warm>,
+        daemon: &DaemonOptions,
+        reload: bool,
+    ) -> Result<&'a Warm> {
+        if reload || warm.as_ref().is_none_or(Warm::stale) {
+            *warm = None;
+            *warm = Some(Warm::load(daemon)?);
+        }
+        Ok(warm.as_ref().expect("project loaded"))
+    }
+
+    /// Whether one of the files read besides the manifests changed since.
+    fn stale(&self) -> bool {
+        self.files
+            .iter()
+            .any(|(path, stamp)| file_stamp(path) != *stamp)
+    }
+}
+
+/// Size and modification time of `path`, `None` when it does not exist.
+fn file_stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.len(), meta.modified().ok()?))
+}
+
+/// Serves the builds of a build directory until interrupted, building for
+/// clients with the project loaded once. It is loaded again when a
+/// manifest, the cross or native file, or a config file changes, and after
+/// a build that could not load it.
+fn run_daemon(opts: &DaemonOptions) -> Result<()> {
+    if opts.build.ui {
+        return Err(CrustError::InvalidArgument {
+            message: "--ui is not available to crust daemon".into(),
+        }
+        .into());
+    }
+    let warm = Mutex::new(Some(Warm::load(opts)?));
+    let manifest = paths::identity(Path::new(""), &opts.build.manifest);
+    let project_dir = ProjectManifest::manifest_dir(&opts.build.manifest);
+    let builddir = opts.build.builddir.clone();
+    let daemon = opts.clone();
+    daemon::serve(&project_dir, &builddir, move |request, out| {
+        let mut warm = warm.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let reload = request.changed.is_none_or(|changed| {
+            changed
+                .iter()
+                .any(|path| *path == manifest || path.file_name() == Some("crust.build".as_ref()))
+        });
+        let Warm {
+            opts,
+            graph,
+            backend,
+            ..
+        } = Warm::current(&mut warm, &daemon, reload)?;
+        let targets = requested_targets(graph, request.targets, request.labels)?;
+        let _lock = BuildDirLock::acquire(&opts.builddir, opts.wait)?;
+        emit_build(opts, &graph.select(&targets)?, backend.as_ref(), true, out)
+    })
+}
+
+/// Builds through the daemon of the build directory, printing the log and
+/// command output it streams back like a build of this process would.
+fn build_in_daemon(opts: &BuildOptions) -> Result<()> {
+    let (report, error) = daemon::build(
+        &opts.build.builddir,
+        &opts.targets,
+        &opts.labels,
+        |event| match event {
+            ui::BuildEvent::Log { line } => println!("{line}"),
+            ui::BuildEvent::Output { text, .. } => eprint!("{text}"),
+            ui::BuildEvent::Started { .. } | ui::BuildEvent::Finished { .. } => {}
+        },
+    )?;
+    print!("{report}");
+    match error {
+        Some(error) => Err(anyhow::anyhow!(error)),
+        None => Ok(()),
+    }
+}
+
+fn run_stats(opts: &StatsOptions) -> Result<()> {
+    let builds = stats::load(&opts.builddir);
+    if opts.compare {
+        let [.., previous, current] = builds.as_slice() else {
+            return Err(anyhow::anyhow!(
+                "{} holds fewer than two native builds to compare",
+                opts.builddir.display()
+            ));
+        };
+        print!("{}", stats::compare(previous, current));
+    } else {
+        let Some(current) = builds.last() else {
+            return Err(anyhow::anyhow!(
+                "{} holds no native build",
+                opts.builddir.display()
+            ));
+        };
+        print!("{}", stats::report(current));
+    }
+    Ok(())
+}
+
+fn run_ide(opts: &IdeOptions) -> Result<()> {
+    if !opts.vscode && !opts.compile_flags {
+        return Err(CrustError::InvalidArgument {
+            message: "Choose at least one of --vscode and --compile-flags".into(),
+        }
+        .into());
+    }
+    let project = &opts.project;
+    let (_, graph, cross) = load_project(&project.manifest, project.cross_file.as_deref())?;
+    let manifest_dir = ProjectManifest::manifest_dir(&project.manifest);
+    std::fs::create_dir_all(&project.builddir)
+        .with_context(|| format!("Failed to create {}", project.builddir.display()))?;
+    let standards = standards::check(&graph, &cross)?;
+    let backend = CrustBackend::new(manifest_dir.clone(), None)
+        .with_cross_file(cross)
+        .with_standards(standards);
+    let commands = backend.compile_database(&graph, &project.builddir)?;
+    compdb::write(&project.builddir, &commands)?;
+
+    // Paths in VS Code files are relative to the workspace, i.e. the
+    // manifest directory, when the build directory lives inside it.
+    let absolute = |path: &Path| {
+        let path = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        std::path::absolute(path).with_context(|| format!("Failed to resolve {}", path.display()))
+    };
+    let builddir = absolute(&project.builddir)?;
+    let (relative, workspace_builddir) = match builddir.strip_prefix(absolute(&manifest_dir)?) {
+        Ok(rel) => (
+            rel.display().to_string(),
+            format!("${{workspaceFolder}}/{}", rel.display()),
+        ),
+        Err(_) => (
+            builddir.display().to_string(),
+            builddir.display().to_string(),
+        ),
+    };
+
+    let pretty = |value: serde_json::Value| -> Result<String> {
+        Ok(serde_json::to_string_pretty(&value)? + "\n")
+    };
+    let mut files = Vec::new();
+    if opts.vscode {
+        let env = DevEnv::from_graph(&graph, &project.builddir)?;
+        let vscode = manifest_dir.join(".vscode");
+        files.push((
+            vscode.join("tasks.json"),
+            pretty(ide::vscode_tasks(&graph, &relative))?,
+        ));
+        files.push((
+            vscode.join("launch.json"),
+            pretty(ide::vscode_launch(&graph, &env, &workspace_builddir))?,
+        ));
+        files.push((
+            vscode.join("c_cpp_properties.json"),
+            pretty(ide::vscode_cpp_properties(&workspace_builddir))?,
+        ));
+    }
+    if opts.compile_flags {
+        files.push((
+            manifest_dir.join(ide::COMPILE_FLAGS),
+            ide::compile_flags(&commands),
+        ));
+    }
+    for (path, content) in files {
+        if path.exists() && !opts.force {
+            tracing::warn!(
+                "{} already exists; pass --force to overwrite it",
+                path.display()
+            );
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Builds the project, then copies every installable file below the prefix
+/// (and `DESTDIR`), signing artifacts that set `sign`, and records the
+/// result in the install manifest.
+fn run_install(opts: &InstallOptions) -> Result<()> {
+    let destdir = opts.destdir.clone().filter(|d| !d.as_os_str().is_empty());
+    let dirs = install::InstallDirs::new(opts.prefix.clone(), destdir);
+    install_project(&opts.build, &dirs, &opts.components)?;
+    Ok(())
+}
+
+/// Builds the default targets and installs the selected components (all
+/// when `components` is empty) into `dirs`, writing the install manifest.
+fn install_project(
+    build: &CommandOptions,
+    dirs: &install::InstallDirs,
+    components: &[Component],
+) -> Result<(ProjectManifest, Vec<install::InstalledFile>)> {
+    let (manifest, graph, cross, dependencies) = load_build(build)?;
+    let graph = graph.select(&[])?;
+    build_graph(build, &manifest, &graph, &cross, dependencies, false)?;
+
+    let manifest_dir = ProjectManifest::manifest_dir(&build.manifest);
+    let selected = |component: Component| components.is_empty() || components.contains(&component);
+    let mut dirs = dirs.clone();
+    if dirs.pythondir.is_none() {
+        let programs = programs::resolve(&graph, &build.program_paths)?;
+        dirs.pythondir = python::find(&graph, &programs)?.map(|python| python.site_dir);
+    }
+    let dirs = &dirs;
+    let plan = install::plan(&manifest, &graph, &manifest_dir, &build.builddir, dirs)?;
+    let signer = CrustBackend::new(manifest_dir, None);
+    let mut installed = Vec::new();
+    for entry in plan.into_iter().filter(|entry| selected(entry.component)) {
+        let path = install::install_file(&entry, dirs)?;
+        if let Some(sign) = &entry.sign {
+            let mut cmd = signer.hook_command(sign, &path, &build.builddir);
+            let status = cmd
+                .status()
+                .map_err(|err| runner::spawn_error(cmd.get_program(), err))?;
+            if !status.success() {
+                return Err(CrustError::CommandFailed {
+                    target: entry.target.clone(),
+                    action: "Sign".into(),
+                    exit: status.code(),
+                }
+                .into());
+            }
+        }
+        println!("  Installing {}", path.display());
+        installed.push(install::InstalledFile::describe(&entry, path)?);
+    }
+    for rule in install::rules(&manifest)
+        .into_iter()
+        .filter(|rule| selected(rule.component()))
+    {
+        let entry = install::apply_rule(&rule, dirs)?;
+        match &entry.pointing_to {
+            Some(pointing_to) => println!(
+                "  Linking {} -> {}",
+                entry.path.display(),
+                pointing_to.display()
+            ),
+            None => println!("  Creating {}", entry.path.display()),
+        }
+        installed.push(entry);
+    }
+    let path = install::write_manifest(&build.builddir, dirs, installed.clone())?;
+    println!(
+        "Installed {} item(s) into {}; manifest at {}",
+        installed.len(),
+        dirs.resolve(Path::new("")).display(),
+        path.display()
+    );
+    Ok((manifest, installed))
+}
+
+/// Installs the project into a fresh staging root below
+/// `builddir/package/<format>/` and packages what the install recorded, or
+/// for brew writes a formula for the dist tarball.
+fn run_package(opts: &PackageOptions) -> Result<()> {
+    let build = &opts.build;
+    let manifest = ProjectManifest::load(&build.manifest)?;
+    let spec = PackageSpec::new(&manifest.project, &manifest.package, opts.format)?;
+    package::check_available(opts.format)?;
+    if !opts.format.stages_install() {
+        let (_, graph, _) = build.load()?;
+        let executables: Vec<String> = graph
+            .nodes()
+            .filter(|node| node.kind == graph::TargetKind::Executable && node.options.install)
+            .map(|node| node.name.clone())
+            .collect();
+        let manifest_dir = ProjectManifest::manifest_dir(&build.manifest);
+        let output = package::build_formula(&spec, &manifest_dir, &executables, &build.builddir)?;
+        println!("Wrote {}", output.display());
+        return Ok(());
+    }
+    let work = std::path::absolute(
+        build
+            .builddir
+            .join(package::PACKAGE_DIR)
+            .join(opts.format.name()),
+    )
+    .context("Failed to resolve package directory")?;
+    let root = work.join("root");
+    if root.exists() {
+        std::fs::remove_dir_all(&root)
+            .with_context(|| format!("Failed to clear {}", root.display()))?;
+    }
+    let prefix = opts.prefix.clone().unwrap_or_else(|| match opts.format {
+        PackageFormat::Msi => PathBuf::from("/"),
+        _ => PathBuf::from("/usr"),
+    });
+    let dirs = install::InstallDirs::new(prefix, Some(root.clone()));
+    let (_, installed) = install_project(build, &dirs, &[])?;
+    let output = package::build(&spec, &root, &installed, &build.builddir)?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Runs xgettext (and for `update-po`, msgmerge or msginit) in the source
+/// tree for each selected gettext target.
+fn run_i18n(opts: &I18nOptions) -> Result<()> {
+    let (args, merge) = match &opts.action {
+        I18nAction::Pot(args) => (args, false),
+        I18nAction::UpdatePo(args) => (args, true),
+    };
+    let (_, graph) = args.project.load()?;
+    let manifest_dir = ProjectManifest::manifest_dir(&args.project.manifest);
+    let mut targets: Vec<_> = graph
+        .nodes()
+        .filter(|node| node.kind == graph::TargetKind::Gettext)
+        .filter(|node| args.target.as_ref().is_none_or(|name| *name == node.name))
+        .collect();
+    targets.sort_by(|a, b| a.name.cmp(&b.name));
+    if targets.is_empty() {
+        return Err(CrustError::InvalidArgument {
+            message: match &args.target {
+                Some(name) => format!("No gettext target named '{name}'"),
+                None => "The manifest declares no gettext targets".into(),
+            },
+        }
+        .into());
+    }
+
+    for node in targets {
+        let Some(settings) = &node.gettext else {
+            continue;
+        };
+        // Paths stay relative to the manifest so `#:` references in the
+        // catalogs do not depend on where crust was run from.
+        let pot = PathBuf::from(settings.pot_file());
+        let sources: Vec<PathBuf> = i18n::extraction_sources(&graph, settings)
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let xgettext = i18n::xgettext_command(settings, &pot, &sources);
+        run_i18n_tool(&node.name, &manifest_dir, xgettext)?;
+        println!("  Wrote {}", manifest_dir.join(&pot).display());
+        if !merge {
+            continue;
+        }
+        for language in &settings.languages {
+            let po = PathBuf::from(settings.po_file(language));
+            let cmd = if manifest_dir.join(&po).exists() {
+                i18n::msgmerge_command(&po, &pot)
+            } else {
+                i18n::msginit_command(language, &po, &pot)
+            };
+            run_i18n_tool(&node.name, &manifest_dir, cmd)?;
+            println!("  Updated {}", manifest_dir.join(&po).display());
+        }
+    }
+    Ok(())
+}
+
+fn run_i18n_tool(target: &str, dir: &Path, mut cmd: std::process::Command) -> Result<()> {
+    if !dir.as_os_str().is_empty() {
+        cmd.current_dir(dir);
+    }
+    let status = cmd
+        .status()
+        .map_err(|err| runner::spawn_error(cmd.get_program(), err))?;
+    if !status.success() {
+        return Err(CrustError::CommandFailed {
+            target: target.to_string(),
+            action: cmd.get_program().to_string_lossy().into_owned(),
+            exit: status.code(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn run_cross_file(opts: &CrossFileOptions) -> Result<()> {
+    let profile = match &opts.profile {
+        CrossProfile::Emscripten => {
+            let mut dirs: Vec<PathBuf> = std::env::var_os("PATH")
+                .map(|path| std::env::split_paths(&path).collect())
+                .unwrap_or_default();
+            if let Some(emsdk) = std::env::var_os("EMSDK") {
+                dirs.push(PathBuf::from(emsdk).join("upstream").join("emscripten"));
+            }
+            cross::emscripten_profile(dirs)?
+        }
+        CrossProfile::Android { ndk, abi, api } => cross::android_profile(ndk, *abi, *api)?,
+    };
+    let content = format!(
+        "# Generated by `crust cross-file`; pass it with --cross-file.\n{}",
+        toml::to_string_pretty(&profile)?
+    );
+    match &opts.output {
+        Some(path) => {
+            std::fs::write(path, content)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Wrote cross file to {}", path.display());
+        }
+        None => print!("{content}"),
+    }
+    Ok(())
+}
+
+/// Warns about deprecated keys the manifest uses and dependencies on
+/// deprecated targets, or fails on the first with `--fatal-deprecations`.
+fn check_deprecations(graph: &DependencyGraph, fatal: bool) -> Result<()> {
+    let keys = graph.deprecated_keys();
+    if let Some(first) = keys.first().filter(|_| fatal) {
+        return Err(CrustError::InvalidArgument {
+            message: format!("{first} (--fatal-deprecations)"),
+        }
+        .into());
+    }
+    let deprecations = graph.deprecations();
+    if let Some(first) = deprecations.first().filter(|_| fatal) {
+        return Err(CrustError::InvalidTarget {
+            target: first.target.clone(),
+            message: format!(
+                "depends on deprecated target '{}': {} (--fatal-deprecations)",
+                first.deprecated, first.message
+            ),
+        }
+        .into());
+    }
+    for key in keys {
+        tracing::warn!(
+            used_in = %key.used_in,
+            deprecated = key.key,
+            replacement = key.replacement,
+            "{key}"
+        );
+    }
+    for deprecation in &deprecations {
+        tracing::warn!(
+            target_name = %deprecation.target,
+            deprecated = %deprecation.deprecated,
+            "{deprecation}"
+        );
+    }
+    Ok(())
+}
+
+fn backend_from_choice(
+    opts: &CommandOptions,
+    manifest: &ProjectManifest,
+    cross: &CrossFile,
+    dependencies: Resolution,
+    programs: HashMap<String, PathBuf>,
+    python: Option<python::Interpreter>,
+    standards: HashMap<String, StdFlags>,
+) -> Result<Box<dyn Backend>> {
+    let manifest_dir = ProjectManifest::manifest_dir(&opts.manifest);
+    let hermetic = opts.hermetic.then(|| {
+        let mut passthrough = manifest.project.passthrough_env.clone();
+        passthrough.extend(opts.native.env.keys().cloned());
+        HermeticEnv::new(&passthrough)
+    });
+    let profile = match &opts.profile {
+        Some(name) => Some((name.clone(), manifest.profile(name)?.clone())),
+        None => None,
+    };
+    let mut launchers = manifest.project.launchers.clone();
+    if let Some(launcher) = &opts.launcher {
+        launchers
+            .entry(Rule::Compile)
+            .or_insert_with(|| launcher.clone());
+    }
+    let remote = match &opts.remote_executor {
+        Some(url) => Some(Arc::new(RemoteExecutor::new(
+            url,
+            &opts.remote_instance_name,
+            opts.remote_property.clone(),
+        )?)),
+        None => None,
+    };
+    Ok(match opts.backend {
+        BackendChoice::Native => Box::new(
+            CrustBackend::new(manifest_dir.clone(), opts.jobs)
+                .with_sandbox(opts.sandbox)
+                .with_check_deps(opts.check_deps)
+                .with_hermetic(hermetic)
+                .with_profile(profile)
+                .with_dependencies(dependencies)
+                .with_fail_fast(opts.fail_fast)
+                .with_watchdog(opts.watchdog.map(Duration::from_secs))
+                .with_load_average(opts.load_average)
+                .with_job_caps(opts.max_link_jobs, opts.max_custom_jobs)
+                .with_priority(Priority::new(opts.nice, opts.io_priority))
+                .with_launchers(launchers)
+                .with_remote(remote)
+                .with_cross_file(cross.clone())
+                .with_programs(programs)
+                .with_python(python)
+                .with_standards(standards),
+        ),
+        BackendChoice::Ninja => Box::new(NinjaBackend),
+        BackendChoice::Make => Box::new(MakeBackend),
+        BackendChoice::Xcode => Box::new(XcodeBackend::new(&manifest_dir)),
+        BackendChoice::Vs => Box::new(VsBackend::new(&manifest_dir)),
+        BackendChoice::Bazel => Box::new(BazelBackend::new(Dialect::Bazel)),
+        BackendChoice::Buck2 => Box::new(BazelBackend::new(Dialect::Buck2)),
+    })
+}
+
+trait BackendHint {
+    fn command_hint(&self) -> &'static str;
 }
 
 impl BackendHint for BackendChoice {
@@ -212,7 +2457,364 @@ impl BackendHint for BackendChoice {
         match self {
             BackendChoice::Ninja => "ninja",
             BackendChoice::Make => "make",
+            BackendChoice::Xcode => "xcodebuild",
+            BackendChoice::Vs => "msbuild",
+            BackendChoice::Bazel => "bazel",
+            BackendChoice::Buck2 => "buck2",
             BackendChoice::Native => "native",
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installs_only_the_selected_components() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("crust.build");
+        std::fs::write(
+            &manifest,
+            r#"
+[project]
+name = "demo"
+
+[[install_data]]
+sources = ["demo.conf"]
+
+[[install_data]]
+sources = ["demo.pc"]
+install_dir = "pkgconfig"
+component = "devel"
+
+[[install_symlink]]
+name = "sbin/demo"
+pointing_to = "../bin/demo"
+
+[[install_symlink]]
+name = "include/demo.h"
+pointing_to = "demo-1.0/demo.h"
+component = "devel"
+
+[[install_emptydir]]
+dirs = ["var/lib/demo"]
+"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("demo.conf"), "verbose = false\n").unwrap();
+        std::fs::write(dir.path().join("demo.pc"), "Name: demo\n").unwrap();
+        let stage = dir.path().join("stage");
+        let builddir = dir.path().join("build");
+        let cli = Cli::try_parse_from([
+            "crust",
+            "install",
+            "--manifest",
+            manifest.to_str().unwrap(),
+            "--builddir",
+            builddir.to_str().unwrap(),
+            "--prefix",
+            "/usr",
+            "--destdir",
+            stage.to_str().unwrap(),
+            "--component",
+            "devel",
+        ])
+        .unwrap();
+        let Commands::Install(opts) = cli.command else {
+            panic!("not an install");
+        };
+
+        run_install(&opts).unwrap();
+
+        let usr = stage.join("usr");
+        assert!(usr.join("share/pkgconfig/demo.pc").is_file());
+        assert!(usr.join("include/demo.h").symlink_metadata().is_ok());
+        for runtime in ["share/demo/demo.conf", "sbin/demo", "var/lib/demo"] {
+            assert!(
+                usr.join(runtime).symlink_metadata().is_err(),
+                "installed {runtime}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_the_dependency_overrides() {
+        let overrides = |args: &[&str]| {
+            let cli = Cli::try_parse_from(["crust", "deps", "--resolved"].iter().chain(args))?;
+            let Commands::Deps(opts) = cli.command else {
+                panic!("not deps");
+            };
+            Ok::<_, clap::Error>(opts.dependencies.overrides())
+        };
+
+        assert_eq!(overrides(&[]).unwrap(), deps::Overrides::default());
+        let parsed = overrides(&[
+            "--force-fallback-for",
+            "zlib,png",
+            "--force-fallback-for",
+            "ssl",
+            "--wrap-mode",
+            "nofallback",
+        ])
+        .unwrap();
+        assert_eq!(parsed.force_fallback_for, ["zlib", "png", "ssl"]);
+        assert_eq!(parsed.wrap_mode, WrapMode::Nofallback);
+        assert_eq!(
+            overrides(&["--wrap-mode", "forcefallback"])
+                .unwrap()
+                .wrap_mode,
+            WrapMode::Forcefallback
+        );
+        assert!(overrides(&["--wrap-mode", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn fills_the_launcher_and_remote_executor_from_defaults() {
+        let defaults = settings::Defaults {
+            launcher: Some("ccache".into()),
+            cache_url: Some("grpc://cache:8980".into()),
+            ..Default::default()
+        };
+        let options = |args: &[&str]| {
+            let cli = Cli::try_parse_from(["crust", "build"].iter().chain(args)).unwrap();
+            let Commands::Build(BuildOptions { mut build, .. }) = cli.command else {
+                panic!("not a build");
+            };
+            fill_options(&mut build, &defaults, |_| false).unwrap();
+            build
+        };
+
+        let opts = options(&[]);
+        assert_eq!(opts.launcher.as_deref(), Some("ccache"));
+        assert_eq!(opts.remote_executor.as_deref(), Some("grpc://cache:8980"));
+
+        let opts = options(&[
+            "--launcher",
+            "distcc",
+            "--remote-executor",
+            "grpc://ci:8980",
+        ]);
+        assert_eq!(opts.launcher.as_deref(), Some("distcc"));
+        assert_eq!(opts.remote_executor.as_deref(), Some("grpc://ci:8980"));
+
+        assert_eq!(options(&["--sandbox"]).remote_executor, None);
+    }
+
+    #[test]
+    fn names_the_owners_of_a_failed_target() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "static_library"
+name = "core"
+sources = ["core.c"]
+owners = ["@infra", "alice"]
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+deps = ["core"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, cross::Platform::Native).unwrap();
+        let failure = |target: &str| {
+            let err = CrustError::CommandFailed {
+                target: target.into(),
+                action: "Linking".into(),
+                exit: Some(1),
+            };
+            format!("{:#}", attribute_failure(err.into(), &graph))
+        };
+
+        assert_eq!(
+            failure("core"),
+            "Target 'core' owned by @infra, alice failed: \
+             Linking failed for target 'core' (exit code 1)"
+        );
+        assert_eq!(
+            failure("app"),
+            "Linking failed for target 'app' (exit code 1)"
+        );
+    }
+
+    #[test]
+    fn reloads_the_daemon_project_when_its_native_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("crust.build");
+        std::fs::write(&manifest, "[project]\nname = \"demo\"\n").unwrap();
+        let native = dir.path().join("native.toml");
+        std::fs::write(&native, "[options]\njobs = 2\n").unwrap();
+        let builddir = dir.path().join("build");
+        let cli = Cli::try_parse_from([
+            "crust",
+            "daemon",
+            "--manifest",
+            manifest.to_str().unwrap(),
+            "--builddir",
+            builddir.to_str().unwrap(),
+            "--native-file",
+            native.to_str().unwrap(),
+        ])
+        .unwrap();
+        let Commands::Daemon(daemon) = cli.command else {
+            panic!("not a daemon");
+        };
+
+        let mut warm = Some(Warm::load(&daemon).unwrap());
+        assert_eq!(
+            Warm::current(&mut warm, &daemon, false).unwrap().opts.jobs,
+            Some(2)
+        );
+        std::fs::write(&native, "[options]\njobs = 12\n").unwrap();
+        assert!(warm.as_ref().unwrap().stale());
+        let reloaded = Warm::current(&mut warm, &daemon, false).unwrap();
+        assert_eq!(reloaded.opts.jobs, Some(12));
+        assert!(!reloaded.stale());
+    }
+
+    #[test]
+    fn fatal_deprecations_cover_deprecated_keys() {
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "static_library"
+name = "core"
+sources = ["core.c"]
+install_component = "devel"
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, cross::Platform::Native).unwrap();
+
+        check_deprecations(&graph, false).unwrap();
+        let err = check_deprecations(&graph, true).unwrap_err().to_string();
+        assert!(
+            err.contains("deprecated key 'install_component'; use 'component' instead"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn short_summary_lists_the_slowest_built_targets() {
+        let target = |name: String, built: bool, millis: u64| TargetBuildSummary {
+            name,
+            built,
+            outputs: vec![PathBuf::from("out")],
+            duration: Duration::from_millis(millis),
+            diagnostics: diagnostics::Counts::default(),
+        };
+        let mut summaries: Vec<_> = (1..=12)
+            .map(|i| target(format!("t{i}"), true, i * 100))
+            .collect();
+        summaries.push(target("cached".into(), false, 5000));
+        summaries.push(target("instant".into(), true, 0));
+        let result = BackendEmitResult {
+            files: vec![PathBuf::from("build.ninja")],
+            target_summaries: summaries,
+        };
+
+        let lines = summary_lines(
+            "native",
+            &result,
+            Duration::from_secs(3),
+            SummaryMode::Short,
+        );
+        assert_eq!(
+            lines[..7],
+            [
+                "",
+                "Build summary",
+                "  Backend: native",
+                "  Targets: 13 built, 1 skipped, 14 total",
+                "  Elapsed time: 3.00s",
+                "  Diagnostics: 0 warnings, 0 errors",
+                "  Slowest targets:",
+            ]
+        );
+        let slowest: Vec<&str> = lines[7..].iter().map(String::as_str).collect();
+        assert_eq!(slowest.len(), SLOWEST_TARGETS);
+        assert_eq!(slowest[0], "    - t12 (1.20s)");
+        assert_eq!(slowest[SLOWEST_TARGETS - 1], "    - t3 (0.30s)");
+
+        let full = summary_lines("ninja", &result, Duration::from_secs(3), SummaryMode::Full);
+        assert!(full.contains(&"  Backend outputs:".to_string()));
+        assert!(full.contains(&"    - cached (skipped, 5.00s)".to_string()));
+        assert!(!full.iter().any(|line| line.starts_with("  Diagnostics")));
+        assert!(summary_lines("ninja", &result, Duration::ZERO, SummaryMode::None).is_empty());
+    }
+
+    #[test]
+    fn lists_targets_as_a_table_or_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("crust.build");
+        std::fs::write(
+            &manifest,
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "static_library"
+name = "core"
+sources = ["core.c"]
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+deps = ["core"]
+"#,
+        )
+        .unwrap();
+        let list = |json: &[&str]| {
+            let args = ["crust", "targets", "--manifest", manifest.to_str().unwrap()];
+            let cli = Cli::try_parse_from(args.iter().chain(json)).unwrap();
+            let Commands::Targets(opts) = cli.command else {
+                panic!("not targets");
+            };
+            let (_, graph) = opts.project.load().unwrap();
+            let mut out = Vec::new();
+            write_targets(&opts, &graph, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let table = list(&[]);
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows.len(), 3, "{table}");
+        assert!(rows[0].starts_with("NAME"), "{table}");
+        assert!(
+            rows[1].starts_with("app ") && rows[1].ends_with("core"),
+            "{table}"
+        );
+        assert!(rows[2].starts_with("core "), "{table}");
+
+        let json: serde_json::Value = serde_json::from_str(&list(&["--json"])).unwrap();
+        let names: Vec<&str> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|target| target["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["app", "core"]);
+        assert_eq!(json[0]["deps"], serde_json::json!(["core"]));
+    }
+
+    #[test]
+    fn stops_quietly_when_the_reader_goes_away() {
+        let closed = io::Error::from(io::ErrorKind::BrokenPipe);
+        assert!(quiet_broken_pipe(Err(closed.into())).is_ok());
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(quiet_broken_pipe(Err(denied.into())).is_err());
+    }
+}
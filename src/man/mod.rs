@@ -0,0 +1,207 @@
+use crate::error::CrustError;
+use crate::graph::{DependencyGraph, TargetKind};
+use crate::toolchain;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Settings of a man target.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ManSettings {
+    /// Converter used for every source instead of detecting one per source.
+    pub tool: Option<String>,
+}
+
+/// Converter that turns a manual source into roff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManTool {
+    Scdoc,
+    Asciidoctor,
+    Pandoc,
+    GoMd2man,
+}
+
+impl ManTool {
+    pub fn program(self) -> &'static str {
+        match self {
+            ManTool::Scdoc => "scdoc",
+            ManTool::Asciidoctor => "asciidoctor",
+            ManTool::Pandoc => "pandoc",
+            ManTool::GoMd2man => "go-md2man",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ManTool> {
+        [
+            ManTool::Scdoc,
+            ManTool::Asciidoctor,
+            ManTool::Pandoc,
+            ManTool::GoMd2man,
+        ]
+        .into_iter()
+        .find(|tool| tool.program() == name)
+    }
+
+    /// Tools able to read a source with markup extension `ext`, in order of
+    /// preference.
+    fn candidates(ext: &str) -> &'static [ManTool] {
+        match ext {
+            "scd" => &[ManTool::Scdoc],
+            "adoc" | "asciidoc" => &[ManTool::Asciidoctor],
+            "md" => &[ManTool::Pandoc, ManTool::GoMd2man],
+            "rst" => &[ManTool::Pandoc],
+            _ => &[],
+        }
+    }
+
+    /// Command converting `input` into the roff page `output`.
+    pub fn command(self, program: &Path, input: &Path, output: &Path) -> Command {
+        match self {
+            // scdoc only filters stdin to stdout.
+            ManTool::Scdoc => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c")
+                    .arg("exec \"$0\" < \"$1\" > \"$2\"")
+                    .arg(program)
+                    .arg(input)
+                    .arg(output);
+                cmd
+            }
+            ManTool::Asciidoctor => {
+                let mut cmd = Command::new(program);
+                cmd.args(["-b", "manpage", "-o"]).arg(output).arg(input);
+                cmd
+            }
+            ManTool::Pandoc => {
+                let mut cmd = Command::new(program);
+                cmd.args(["-s", "-t", "man", "-o"]).arg(output).arg(input);
+                cmd
+            }
+            ManTool::GoMd2man => {
+                let mut cmd = Command::new(program);
+                cmd.arg(format!("-in={}", input.display()))
+                    .arg(format!("-out={}", output.display()));
+                cmd
+            }
+        }
+    }
+}
+
+/// A manual page named by its source, e.g. `doc/hello.1.scd` is page
+/// `hello.1` in section `1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManPage {
+    pub page: String,
+    pub section: String,
+    /// Markup extension of the source (`scd`, `adoc`, `md`, ...).
+    pub markup: String,
+}
+
+impl ManPage {
+    pub fn parse(source: &str) -> Option<ManPage> {
+        let file = Path::new(source).file_name()?.to_str()?;
+        let (page, markup) = file.rsplit_once('.')?;
+        let (_, section) = page.rsplit_once('.')?;
+        if !section.starts_with(|c: char| ('1'..='9').contains(&c)) {
+            return None;
+        }
+        Some(ManPage {
+            page: page.to_string(),
+            section: section.to_string(),
+            markup: markup.to_string(),
+        })
+    }
+
+    /// Directory below `mandir` the page installs into (`man3` for `3p`).
+    pub fn install_subdir(&self) -> String {
+        format!("man{}", &self.section[..1])
+    }
+}
+
+/// Describes why `source` cannot be converted with the configured tool.
+pub fn source_error(source: &str, tool: Option<&str>) -> Option<String> {
+    let Some(page) = ManPage::parse(source) else {
+        return Some(format!(
+            "manual source '{source}' must be named <page>.<section>.<markup>, e.g. hello.1.scd"
+        ));
+    };
+    match tool {
+        Some(name) if ManTool::from_name(name).is_none() => Some(format!(
+            "unknown man page tool '{name}'; use scdoc, asciidoctor, pandoc, or go-md2man"
+        )),
+        None if ManTool::candidates(&page.markup).is_empty() => Some(format!(
+            "no converter for '.{}' manual sources; set tool explicitly",
+            page.markup
+        )),
+        _ => None,
+    }
+}
+
+/// Picks the converter for `source`: the configured tool, or the first
+/// installed tool that understands its markup.
+pub fn detect(source: &str, tool: Option<&str>) -> Result<(ManTool, PathBuf)> {
+    let candidates: Vec<ManTool> = match tool {
+        Some(name) => ManTool::from_name(name).into_iter().collect(),
+        None => ManPage::parse(source)
+            .map(|page| ManTool::candidates(&page.markup).to_vec())
+            .unwrap_or_default(),
+    };
+    candidates
+        .iter()
+        .find_map(|tool| toolchain::find_program(tool.program()).map(|path| (*tool, path)))
+        .ok_or_else(|| {
+            CrustError::CompilerMissing {
+                program: candidates
+                    .iter()
+                    .map(|tool| tool.program())
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+            }
+            .into()
+        })
+}
+
+/// Resolves the converter of every man page in `graph` so a missing tool
+/// fails the configure step instead of surfacing halfway through a build.
+pub fn check_tools(graph: &DependencyGraph) -> Result<()> {
+    for node in graph.nodes().filter(|node| node.kind == TargetKind::Man) {
+        let tool = node.man.as_ref().and_then(|man| man.tool.as_deref());
+        for source in &node.sources {
+            detect(source, tool)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_page_names_and_sections_from_sources() {
+        let page = ManPage::parse("doc/hello.1.scd").unwrap();
+        assert_eq!(page.page, "hello.1");
+        assert_eq!(page.section, "1");
+        assert_eq!(page.markup, "scd");
+        assert_eq!(
+            ManPage::parse("doc/hello_api.3p.md")
+                .unwrap()
+                .install_subdir(),
+            "man3"
+        );
+        assert!(ManPage::parse("doc/README.md").is_none());
+
+        assert!(source_error("doc/hello.5.adoc", None).is_none());
+        assert!(source_error("doc/hello.5.txt", None).is_some());
+        assert!(source_error("doc/hello.5.txt", Some("pandoc")).is_none());
+        assert!(source_error("doc/hello.5.md", Some("groff")).is_some());
+
+        let cmd = ManTool::Asciidoctor.command(
+            Path::new("asciidoctor"),
+            Path::new("hello.5.adoc"),
+            Path::new("out/hello.5"),
+        );
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["-b", "manpage", "-o", "out/hello.5", "hello.5.adoc"]);
+    }
+}
@@ -0,0 +1,57 @@
+use notify_rust::Notification;
+use std::time::Duration;
+use tracing::warn;
+
+/// Summary and body of the notification for `crust <command>` finishing
+/// after `elapsed`, with `error` when it failed.
+fn message(command: &str, error: Option<&anyhow::Error>, elapsed: Duration) -> (String, String) {
+    let elapsed = format!("{:.1}s", elapsed.as_secs_f64());
+    match error {
+        None => (
+            format!("crust {command} succeeded"),
+            format!("Finished in {elapsed}"),
+        ),
+        Some(error) => (
+            format!("crust {command} failed"),
+            format!("Failed after {elapsed}: {error:#}"),
+        ),
+    }
+}
+
+/// Shows a desktop notification that `crust <command>` finished. A desktop
+/// without a notification service only gets a warning.
+pub fn finished(command: &str, error: Option<&anyhow::Error>, elapsed: Duration) {
+    let (summary, body) = message(command, error, elapsed);
+    let shown = Notification::new()
+        .appname("crust")
+        .summary(&summary)
+        .body(&body)
+        .show();
+    if let Err(err) = shown {
+        warn!("Failed to show a desktop notification: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_status_and_elapsed_time() {
+        assert_eq!(
+            message("build", None, Duration::from_millis(83_300)),
+            (
+                "crust build succeeded".to_string(),
+                "Finished in 83.3s".to_string()
+            )
+        );
+        let error = anyhow::anyhow!("exit code 1").context("target 'app' failed");
+        assert_eq!(
+            message("test", Some(&error), Duration::from_secs(12)),
+            (
+                "crust test failed".to_string(),
+                "Failed after 12.0s: target 'app' failed: exit code 1".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::Path;
+
+/// Whether `path` is an Objective-C (`.m`) or Objective-C++ (`.mm`)
+/// source. The C compiler driver picks the language from the extension.
+pub fn is_objc(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("m" | "mm"))
+}
+
+/// Flags added when compiling `source`: `-fobjc-arc` for Objective-C
+/// sources of a target that sets `objc_arc`.
+pub fn compile_flags(source: &Path, arc: bool) -> Vec<String> {
+    if arc && is_objc(source) {
+        vec!["-fobjc-arc".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Flags added when linking: `-framework` for each framework, and, outside
+/// Apple platforms where the runtime is part of libSystem, `-lobjc` when
+/// Objective-C code is linked.
+pub fn link_flags(frameworks: &[String], runtime: bool, apple: bool) -> Vec<String> {
+    let mut flags = Vec::new();
+    for framework in frameworks {
+        flags.push("-framework".to_string());
+        flags.push(framework.clone());
+    }
+    if runtime && !apple {
+        flags.push("-lobjc".to_string());
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_arc_and_framework_flags() {
+        assert_eq!(
+            compile_flags(Path::new("src/AppDelegate.m"), true),
+            ["-fobjc-arc"]
+        );
+        assert!(compile_flags(Path::new("src/Bridge.mm"), false).is_empty());
+        assert!(compile_flags(Path::new("src/main.c"), true).is_empty());
+
+        let frameworks = vec!["Cocoa".to_string(), "Metal".to_string()];
+        assert_eq!(
+            link_flags(&frameworks, true, true),
+            ["-framework", "Cocoa", "-framework", "Metal"]
+        );
+        assert_eq!(link_flags(&[], true, false), ["-lobjc"]);
+        assert!(link_flags(&[], false, false).is_empty());
+    }
+}
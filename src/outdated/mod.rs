@@ -0,0 +1,84 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Why the native backend would run an edge, or a whole target, again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// An output does not exist.
+    MissingOutput(PathBuf),
+    /// The edge declares no outputs, so it runs on every build.
+    NoOutputs,
+    /// The command line differs from the recorded one.
+    ChangedCommand,
+    /// The outputs differ from the recorded ones.
+    ChangedOutputs,
+    /// Inputs were added or removed since the recorded run.
+    ChangedInputs,
+    /// The content of an input differs from the recorded one.
+    ChangedInput(PathBuf),
+    /// An input is newer than the oldest output; used for edges the state
+    /// database has no record of.
+    NewerInput(PathBuf),
+    /// A dependency is out of date, so the target's inputs will change.
+    Dependency(String),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::MissingOutput(path) => write!(f, "output {} is missing", path.display()),
+            Reason::NoOutputs => write!(f, "declares no outputs, so it always runs"),
+            Reason::ChangedCommand => write!(f, "command line changed"),
+            Reason::ChangedOutputs => write!(f, "outputs changed"),
+            Reason::ChangedInputs => write!(f, "inputs were added or removed"),
+            Reason::ChangedInput(path) => write!(f, "input {} changed", path.display()),
+            Reason::NewerInput(path) => {
+                write!(f, "input {} is newer than the outputs", path.display())
+            }
+            Reason::Dependency(name) => write!(f, "depends on '{name}', which is out of date"),
+        }
+    }
+}
+
+/// `crust outdated` report: one line per target, in build order, saying
+/// whether it is up to date or why it is not.
+pub fn report(statuses: &[(String, Option<Reason>)]) -> String {
+    let width = statuses
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    statuses
+        .iter()
+        .map(|(name, reason)| match reason {
+            None => format!("{name:<width$}  up to date\n"),
+            Some(reason) => format!("{name:<width$}  out of date: {reason}\n"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_each_target_with_its_reason() {
+        let statuses = vec![
+            ("core".to_string(), None),
+            (
+                "app".to_string(),
+                Some(Reason::ChangedInput(PathBuf::from("src/main.c"))),
+            ),
+            (
+                "//tools:gen".to_string(),
+                Some(Reason::Dependency("app".into())),
+            ),
+        ];
+        assert_eq!(
+            report(&statuses),
+            "core         up to date\n\
+             app          out of date: input src/main.c changed\n\
+             //tools:gen  out of date: depends on 'app', which is out of date\n"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use crate::package::PackageSpec;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Creates `<name>-<version>.tar.gz` in `out_dir` from the committed tree
+/// with `git archive`, so the tarball matches what a release tag contains.
+pub fn dist_tarball(spec: &PackageSpec, manifest_dir: &Path, out_dir: &Path) -> Result<PathBuf> {
+    let stem = format!("{}-{}", spec.name, spec.version);
+    let tarball = std::path::absolute(out_dir.join(format!("{stem}.tar.gz")))
+        .context("Failed to resolve dist tarball path")?;
+    let mut cmd = Command::new("git");
+    cmd.arg("archive")
+        .arg("--format=tar.gz")
+        .arg(format!("--prefix={stem}/"))
+        .arg("-o")
+        .arg(&tarball)
+        .arg("HEAD");
+    if !manifest_dir.as_os_str().is_empty() {
+        cmd.current_dir(manifest_dir);
+    }
+    super::run(cmd, "git archive").map_err(|err| {
+        err.context("Creating the dist tarball needs a git checkout with commits")
+    })?;
+    Ok(tarball)
+}
+
+/// Ruby class name Homebrew derives from a formula name
+/// (`hello-world` becomes `HelloWorld`).
+fn class_name(name: &str) -> String {
+    name.split(['-', '_', '.'])
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Renders a Homebrew formula that builds the release tarball at `url`
+/// with crust; `executables` are checked for in the formula's test block.
+pub fn formula(spec: &PackageSpec, url: &str, sha256: &str, executables: &[String]) -> String {
+    let mut lines = vec![
+        "# Generated by crust package; edit [package] in the manifest instead.".to_string(),
+        format!("class {} < Formula", class_name(&spec.name)),
+        format!("  desc {}", quote(&spec.summary)),
+    ];
+    if let Some(homepage) = &spec.homepage {
+        lines.push(format!("  homepage {}", quote(homepage)));
+    }
+    lines.push(format!("  url {}", quote(url)));
+    lines.push(format!("  sha256 {}", quote(sha256)));
+    if let Some(license) = &spec.license {
+        lines.push(format!("  license {}", quote(license)));
+    }
+    lines.push(String::new());
+    lines.push("  depends_on \"crust\" => :build".into());
+    lines.extend(
+        spec.depends
+            .iter()
+            .map(|dep| format!("  depends_on {}", quote(dep))),
+    );
+    lines.push(String::new());
+    lines.push("  def install".into());
+    lines.push("    system \"crust\", \"install\", \"--prefix\", prefix".into());
+    lines.push("  end".into());
+    if !executables.is_empty() {
+        lines.push(String::new());
+        lines.push("  test do".into());
+        lines.extend(
+            executables
+                .iter()
+                .map(|exe| format!("    assert_predicate bin/{}, :executable?", quote(exe))),
+        );
+        lines.push("  end".into());
+    }
+    lines.push("end".into());
+    lines.join("\n") + "\n"
+}
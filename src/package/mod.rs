@@ -0,0 +1,501 @@
+use crate::config::{PackageInfo, ProjectInfo};
+use crate::digest::sha256_file;
+use crate::error::CrustError;
+use crate::install::{InstalledFile, InstalledKind};
+use crate::runner::spawn_error;
+use crate::toolchain;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+pub mod brew;
+pub mod wix;
+
+/// Directory under the build directory holding staging roots and the
+/// generated control or spec files.
+pub const PACKAGE_DIR: &str = "package";
+
+/// Binary package format produced by `crust package`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PackageFormat {
+    Deb,
+    Rpm,
+    /// Homebrew formula building the release tarball from source.
+    Brew,
+    /// Windows installer, generated as WiX source.
+    Msi,
+}
+
+impl PackageFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            PackageFormat::Deb => "deb",
+            PackageFormat::Rpm => "rpm",
+            PackageFormat::Brew => "brew",
+            PackageFormat::Msi => "msi",
+        }
+    }
+
+    /// Whether the package is assembled from a staged `crust install`;
+    /// brew formulas instead build from the release tarball.
+    pub fn stages_install(self) -> bool {
+        self != PackageFormat::Brew
+    }
+
+    /// System tool that assembles the package.
+    pub fn program(self) -> &'static str {
+        match self {
+            PackageFormat::Deb => "dpkg-deb",
+            PackageFormat::Rpm => "rpmbuild",
+            PackageFormat::Brew => "git",
+            PackageFormat::Msi => "wix",
+        }
+    }
+
+    /// The build machine's architecture in this format's naming.
+    fn host_architecture(self) -> String {
+        let arch = std::env::consts::ARCH;
+        let name = match (self, arch) {
+            (PackageFormat::Deb, "x86_64") => "amd64",
+            (PackageFormat::Deb, "aarch64") => "arm64",
+            (PackageFormat::Deb, "x86") => "i386",
+            (PackageFormat::Deb, "arm") => "armhf",
+            (PackageFormat::Deb, "powerpc64") => "ppc64el",
+            (PackageFormat::Rpm, "x86") => "i686",
+            (PackageFormat::Rpm, "arm") => "armv7hl",
+            (PackageFormat::Rpm, "powerpc64") => "ppc64le",
+            (PackageFormat::Msi, "x86_64") => "x64",
+            _ => arch,
+        };
+        name.to_string()
+    }
+}
+
+/// Fails early when the tool assembling `format` packages is missing. WiX
+/// is optional: without it the `.wxs` source is still generated.
+pub fn check_available(format: PackageFormat) -> Result<()> {
+    let program = format.program();
+    if format == PackageFormat::Msi {
+        return Ok(());
+    }
+    if toolchain::find_program(program).is_none() {
+        return Err(CrustError::CompilerMissing {
+            program: program.into(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Everything a control or spec file needs, with defaults resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    pub format: PackageFormat,
+    pub name: String,
+    pub version: String,
+    pub release: String,
+    pub architecture: String,
+    pub summary: String,
+    pub description: String,
+    pub maintainer: Option<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    pub depends: Vec<String>,
+    /// Release tarball URL with `${VERSION}` expanded.
+    pub source_url: Option<String>,
+}
+
+impl PackageSpec {
+    /// Resolves the package metadata, failing when a field the format
+    /// requires is missing.
+    pub fn new(project: &ProjectInfo, info: &PackageInfo, format: PackageFormat) -> Result<Self> {
+        let missing = |field: &str| CrustError::InvalidArgument {
+            message: format!(
+                "{} packages need `{field}` in the manifest; add it and rerun `crust package`",
+                format.name()
+            ),
+        };
+        let version = project
+            .version
+            .clone()
+            .ok_or_else(|| missing("[project] version"))?;
+        let required = match format {
+            PackageFormat::Deb | PackageFormat::Msi => {
+                info.maintainer.is_none().then_some("[package] maintainer")
+            }
+            PackageFormat::Rpm => info.license.is_none().then_some("[package] license"),
+            PackageFormat::Brew => info.source_url.is_none().then_some("[package] source_url"),
+        };
+        if let Some(field) = required {
+            return Err(missing(field).into());
+        }
+        let name = match format {
+            // Debian package names are lowercase and may not contain `_`.
+            PackageFormat::Deb => project.name.to_lowercase().replace('_', "-"),
+            PackageFormat::Rpm | PackageFormat::Brew | PackageFormat::Msi => project.name.clone(),
+        };
+        let summary = info.summary.clone().unwrap_or_else(|| project.name.clone());
+        Ok(PackageSpec {
+            format,
+            name,
+            release: info.release.clone().unwrap_or_else(|| "1".into()),
+            architecture: info
+                .architecture
+                .clone()
+                .unwrap_or_else(|| format.host_architecture()),
+            description: info.description.clone().unwrap_or_else(|| summary.clone()),
+            summary,
+            maintainer: info.maintainer.clone(),
+            license: info.license.clone(),
+            homepage: info.homepage.clone(),
+            depends: info.depends.clone(),
+            source_url: info
+                .source_url
+                .as_ref()
+                .map(|url| url.replace("${VERSION}", &version)),
+            version,
+        })
+    }
+
+    /// File name of the finished package, following each format's convention.
+    pub fn file_name(&self) -> String {
+        match self.format {
+            PackageFormat::Deb => {
+                format!("{}_{}_{}.deb", self.name, self.version, self.architecture)
+            }
+            PackageFormat::Rpm => format!(
+                "{}-{}-{}.{}.rpm",
+                self.name, self.version, self.release, self.architecture
+            ),
+            PackageFormat::Brew => format!("{}.rb", self.name),
+            PackageFormat::Msi => {
+                format!("{}-{}-{}.msi", self.name, self.version, self.architecture)
+            }
+        }
+    }
+}
+
+/// Renders `DEBIAN/control` for a package whose files take up
+/// `installed_size` bytes.
+pub fn deb_control(spec: &PackageSpec, installed_size: u64) -> String {
+    let mut lines = vec![
+        format!("Package: {}", spec.name),
+        format!("Version: {}", spec.version),
+        format!("Architecture: {}", spec.architecture),
+        format!("Maintainer: {}", spec.maintainer.as_deref().unwrap_or("")),
+        format!("Installed-Size: {}", installed_size.div_ceil(1024)),
+    ];
+    if !spec.depends.is_empty() {
+        lines.push(format!("Depends: {}", spec.depends.join(", ")));
+    }
+    if let Some(homepage) = &spec.homepage {
+        lines.push(format!("Homepage: {homepage}"));
+    }
+    lines.push(format!("Description: {}", spec.summary));
+    // Extended description lines are indented; blank lines become " .".
+    let extended = (spec.description != spec.summary).then_some(spec.description.as_str());
+    lines.extend(extended.unwrap_or_default().lines().map(|line| {
+        if line.trim().is_empty() {
+            " .".to_string()
+        } else {
+            format!(" {line}")
+        }
+    }));
+    lines.join("\n") + "\n"
+}
+
+/// Renders an RPM spec that copies the staged tree at `root` into the
+/// buildroot and lists every entry of `files`.
+pub fn rpm_spec(spec: &PackageSpec, root: &Path, files: &[InstalledFile]) -> String {
+    let mut lines = vec![
+        "# Generated by crust package; edit [package] in the manifest instead.".to_string(),
+        // The files were built by crust; do not strip them or split debug info.
+        "%global debug_package %{nil}".into(),
+        "%global __os_install_post %{nil}".into(),
+        format!("Name: {}", spec.name),
+        format!("Version: {}", spec.version),
+        format!("Release: {}", spec.release),
+        format!("Summary: {}", spec.summary),
+        format!("License: {}", spec.license.as_deref().unwrap_or("")),
+    ];
+    if let Some(homepage) = &spec.homepage {
+        lines.push(format!("URL: {homepage}"));
+    }
+    lines.extend(spec.depends.iter().map(|dep| format!("Requires: {dep}")));
+    lines.push(String::new());
+    lines.push("%description".into());
+    lines.push(spec.description.clone());
+    lines.push(String::new());
+    lines.push("%install".into());
+    lines.push("mkdir -p %{buildroot}".into());
+    lines.push(format!(
+        "cp -a '{}/.' %{{buildroot}}/",
+        root.display().to_string().replace('\'', "'\\''")
+    ));
+    lines.push(String::new());
+    lines.push("%files".into());
+    for file in files {
+        let path = installed_path(file, root);
+        match file.kind {
+            InstalledKind::Directory => lines.push(format!("%dir \"{}\"", path.display())),
+            InstalledKind::File | InstalledKind::Symlink => {
+                lines.push(format!("\"{}\"", path.display()))
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Path of `file` on the target system, i.e. without the staging root.
+fn installed_path(file: &InstalledFile, root: &Path) -> PathBuf {
+    Path::new("/").join(file.path.strip_prefix(root).unwrap_or(&file.path))
+}
+
+/// Builds the package from the tree staged at `root` (which received
+/// `files`) and returns its path inside `out_dir`. For MSI without WiX
+/// installed, this is the path of the generated `.wxs` instead.
+pub fn build(
+    spec: &PackageSpec,
+    root: &Path,
+    files: &[InstalledFile],
+    out_dir: &Path,
+) -> Result<PathBuf> {
+    let program = spec.format.program();
+    let output = out_dir.join(spec.file_name());
+    let work = root.parent().unwrap_or(out_dir);
+    match spec.format {
+        PackageFormat::Deb => {
+            let mut size = 0;
+            for file in files.iter().filter(|f| f.kind == InstalledKind::File) {
+                size += fs::metadata(&file.path)
+                    .with_context(|| format!("Failed to read {}", file.path.display()))?
+                    .len();
+            }
+            let control = root.join("DEBIAN").join("control");
+            fs::create_dir_all(root.join("DEBIAN"))?;
+            fs::write(&control, deb_control(spec, size))
+                .with_context(|| format!("Failed to write {}", control.display()))?;
+            let mut cmd = Command::new(program);
+            cmd.arg("--root-owner-group")
+                .arg("--build")
+                .arg(root)
+                .arg(&output);
+            run(cmd, program)?;
+        }
+        PackageFormat::Rpm => {
+            let spec_file = work.join(format!("{}.spec", spec.name));
+            fs::write(&spec_file, rpm_spec(spec, root, files))
+                .with_context(|| format!("Failed to write {}", spec_file.display()))?;
+            let topdir = work.join("rpmbuild");
+            let mut cmd = Command::new(program);
+            cmd.arg("-bb")
+                .arg("--target")
+                .arg(&spec.architecture)
+                .arg("--define")
+                .arg(format!("_topdir {}", topdir.display()))
+                .arg(&spec_file);
+            run(cmd, program)?;
+            let built = topdir
+                .join("RPMS")
+                .join(&spec.architecture)
+                .join(spec.file_name());
+            fs::copy(&built, &output).with_context(|| {
+                format!("Failed to copy {} to {}", built.display(), output.display())
+            })?;
+        }
+        PackageFormat::Msi => {
+            let source = work.join(format!("{}.wxs", spec.name));
+            fs::write(&source, wix::source(spec, root, files))
+                .with_context(|| format!("Failed to write {}", source.display()))?;
+            if toolchain::find_program(program).is_none() {
+                warn!(
+                    "{program} not found; run `wix build {}` on Windows to produce the MSI",
+                    source.display()
+                );
+                return Ok(source);
+            }
+            let mut cmd = Command::new(program);
+            cmd.arg("build")
+                .arg("-arch")
+                .arg(&spec.architecture)
+                .arg("-o")
+                .arg(&output)
+                .arg(&source);
+            run(cmd, program)?;
+        }
+        PackageFormat::Brew => {
+            return Err(CrustError::InvalidArgument {
+                message: "brew formulas are generated from the dist tarball, not a staged install"
+                    .into(),
+            }
+            .into());
+        }
+    }
+    Ok(output)
+}
+
+/// Writes the dist tarball and a Homebrew formula pointing at it into
+/// `out_dir`, returning the formula's path.
+pub fn build_formula(
+    spec: &PackageSpec,
+    manifest_dir: &Path,
+    executables: &[String],
+    out_dir: &Path,
+) -> Result<PathBuf> {
+    let tarball = brew::dist_tarball(spec, manifest_dir, out_dir)?;
+    let sha256 = sha256_file(&tarball)?;
+    info!("Dist tarball {} has sha256 {}", tarball.display(), sha256);
+    let output = out_dir.join(spec.file_name());
+    let url = spec.source_url.as_deref().unwrap_or_default();
+    fs::write(&output, brew::formula(spec, url, &sha256, executables))
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    Ok(output)
+}
+
+fn run(mut cmd: Command, program: &str) -> Result<()> {
+    info!("Running {}", program);
+    let status = cmd
+        .status()
+        .map_err(|err| spawn_error(cmd.get_program(), err))?;
+    if !status.success() {
+        return Err(CrustError::CommandFailed {
+            target: "package".into(),
+            action: program.into(),
+            exit: status.code(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Component;
+
+    #[test]
+    fn renders_control_and_spec_from_project_metadata() {
+        let project = ProjectInfo {
+            name: "hello_world".into(),
+            version: Some("1.2".into()),
+            crust_version: None,
+            c_args: Vec::new(),
+            subdirs: Vec::new(),
+            passthrough_env: Vec::new(),
+            launchers: Default::default(),
+        };
+        let info = PackageInfo {
+            summary: Some("Greets the world".into()),
+            description: Some("Prints a greeting.\n\nNothing else.".into()),
+            maintainer: Some("Dev <dev@example.org>".into()),
+            license: Some("MIT".into()),
+            depends: vec!["libc6".into(), "zlib1g".into()],
+            architecture: Some("amd64".into()),
+            ..Default::default()
+        };
+        let deb = PackageSpec::new(&project, &info, PackageFormat::Deb).unwrap();
+        assert_eq!(deb.file_name(), "hello-world_1.2_amd64.deb");
+        let control = deb_control(&deb, 1500);
+        assert!(control.contains("Package: hello-world\n"));
+        assert!(control.contains("Installed-Size: 2\n"));
+        assert!(control.contains("Depends: libc6, zlib1g\n"));
+        assert!(control
+            .ends_with("Description: Greets the world\n Prints a greeting.\n .\n Nothing else.\n"));
+
+        let root = Path::new("/work/build/package/rpm/root");
+        let files = vec![
+            InstalledFile {
+                path: root.join("usr/bin/hello"),
+                target: "hello".into(),
+                component: Component::Runtime,
+                kind: InstalledKind::File,
+                sha256: Some("00".into()),
+                pointing_to: None,
+            },
+            InstalledFile {
+                path: root.join("var/lib/hello"),
+                target: "install_emptydir".into(),
+                component: Component::Runtime,
+                kind: InstalledKind::Directory,
+                sha256: None,
+                pointing_to: None,
+            },
+        ];
+        let rpm = PackageSpec::new(&project, &info, PackageFormat::Rpm).unwrap();
+        assert_eq!(rpm.file_name(), "hello_world-1.2-1.amd64.rpm");
+        let spec = rpm_spec(&rpm, root, &files);
+        assert!(spec.contains("Requires: zlib1g\n"));
+        assert!(spec.contains("cp -a '/work/build/package/rpm/root/.' %{buildroot}/\n"));
+        assert!(spec.ends_with("%files\n\"/usr/bin/hello\"\n%dir \"/var/lib/hello\"\n"));
+
+        let unlicensed = PackageInfo {
+            license: None,
+            ..info
+        };
+        assert!(PackageSpec::new(&project, &unlicensed, PackageFormat::Rpm).is_err());
+    }
+
+    #[test]
+    fn renders_brew_formula_and_wix_source() {
+        let project = ProjectInfo {
+            name: "hello-world".into(),
+            version: Some("1.2.0".into()),
+            crust_version: None,
+            c_args: Vec::new(),
+            subdirs: Vec::new(),
+            passthrough_env: Vec::new(),
+            launchers: Default::default(),
+        };
+        let info = PackageInfo {
+            summary: Some("Greets \"everyone\"".into()),
+            maintainer: Some("Example & Co".into()),
+            license: Some("MIT".into()),
+            source_url: Some("https://example.org/hello-${VERSION}.tar.gz".into()),
+            depends: vec!["zlib".into()],
+            ..Default::default()
+        };
+        let brew = PackageSpec::new(&project, &info, PackageFormat::Brew).unwrap();
+        let formula = brew::formula(
+            &brew,
+            brew.source_url.as_deref().unwrap(),
+            "abc123",
+            &["hello".into()],
+        );
+        assert!(formula.contains("class HelloWorld < Formula\n"));
+        assert!(formula.contains("  desc \"Greets \\\"everyone\\\"\"\n"));
+        assert!(formula.contains("  url \"https://example.org/hello-1.2.0.tar.gz\"\n"));
+        assert!(formula.contains("  sha256 \"abc123\"\n"));
+        assert!(formula.contains("  depends_on \"zlib\"\n"));
+        assert!(formula.contains("    assert_predicate bin/\"hello\", :executable?\n"));
+
+        let msi = PackageSpec::new(&project, &info, PackageFormat::Msi).unwrap();
+        let root = Path::new("/stage");
+        let file = |path: &str, kind| InstalledFile {
+            path: root.join(path),
+            target: "hello".into(),
+            component: Component::Runtime,
+            kind,
+            sha256: None,
+            pointing_to: None,
+        };
+        let wxs = wix::source(
+            &msi,
+            root,
+            &[
+                file("bin/hello.exe", InstalledKind::File),
+                file("data", InstalledKind::Directory),
+                file("bin/hi.exe", InstalledKind::Symlink),
+            ],
+        );
+        assert!(wxs.contains("Manufacturer=\"Example &amp; Co\" Version=\"1.2.0\""));
+        assert!(wxs.contains("<Directory Id=\"Folder1\" Name=\"bin\">"));
+        assert!(wxs.contains("<File Source=\"/stage/bin/hello.exe\" />"));
+        assert!(wxs.contains("<CreateFolder />"));
+        assert!(!wxs.contains("hi.exe"));
+        assert!(wxs.contains("<ComponentRef Id=\"Component4\" />"));
+        assert!(PackageSpec::new(&project, &PackageInfo::default(), PackageFormat::Brew).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::digest::sha256_bytes;
+use crate::install::{InstalledFile, InstalledKind};
+use crate::package::PackageSpec;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Directory of the staged tree as laid out below `INSTALLFOLDER`.
+#[derive(Default)]
+struct Folder {
+    folders: BTreeMap<String, Folder>,
+    files: Vec<PathBuf>,
+    /// Created even when empty (`install_emptydir`).
+    create: bool,
+}
+
+impl Folder {
+    fn at(&mut self, relative: &Path) -> &mut Folder {
+        relative.iter().fold(self, |folder, part| {
+            folder
+                .folders
+                .entry(part.to_string_lossy().into_owned())
+                .or_default()
+        })
+    }
+}
+
+/// Stable `UpgradeCode` derived from the package name, so every version of
+/// a project upgrades the previous one in place.
+fn upgrade_code(name: &str) -> String {
+    let hex = sha256_bytes(format!("crust-msi:{name}").as_bytes()).to_uppercase();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders a WiX v4 source installing `files` (staged below `root`) into
+/// `Program Files\<name>`. MSI has no symlinks, so those entries are skipped
+/// with a warning.
+pub fn source(spec: &PackageSpec, root: &Path, files: &[InstalledFile]) -> String {
+    let mut tree = Folder::default();
+    for file in files {
+        let relative = file.path.strip_prefix(root).unwrap_or(&file.path);
+        match file.kind {
+            InstalledKind::File => tree
+                .at(relative.parent().unwrap_or(Path::new("")))
+                .files
+                .push(file.path.clone()),
+            InstalledKind::Directory => tree.at(relative).create = true,
+            InstalledKind::Symlink => {
+                warn!(
+                    "MSI packages cannot contain symlinks; skipping {}",
+                    relative.display()
+                )
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    let mut components = Vec::new();
+    render(&tree, 4, &mut 0, &mut body, &mut components);
+    let mut lines = vec![
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>".to_string(),
+        "<!-- Generated by crust package; edit [package] in the manifest instead. -->".into(),
+        "<Wix xmlns=\"http://wixtoolset.org/schemas/v4/wxs\">".into(),
+        format!(
+            "  <Package Name=\"{}\" Manufacturer=\"{}\" Version=\"{}\" UpgradeCode=\"{}\">",
+            escape(&spec.name),
+            escape(spec.maintainer.as_deref().unwrap_or("")),
+            escape(&spec.version),
+            upgrade_code(&spec.name)
+        ),
+        "    <MajorUpgrade DowngradeErrorMessage=\"A newer version of [ProductName] is already installed.\" />".into(),
+        "    <MediaTemplate EmbedCab=\"yes\" />".into(),
+        "    <StandardDirectory Id=\"ProgramFiles64Folder\">".into(),
+        format!(
+            "      <Directory Id=\"INSTALLFOLDER\" Name=\"{}\">",
+            escape(&spec.name)
+        ),
+    ];
+    lines.extend(body);
+    lines.push("      </Directory>".into());
+    lines.push("    </StandardDirectory>".into());
+    lines.push(format!(
+        "    <Feature Id=\"Main\" Title=\"{}\">",
+        escape(&spec.summary)
+    ));
+    lines.extend(
+        components
+            .iter()
+            .map(|id| format!("      <ComponentRef Id=\"{id}\" />")),
+    );
+    lines.push("    </Feature>".into());
+    lines.push("  </Package>".into());
+    lines.push("</Wix>".into());
+    lines.join("\n") + "\n"
+}
+
+/// Appends the contents of `folder` at `depth` (in units of two spaces)
+/// and records the ids of the components it declares.
+fn render(
+    folder: &Folder,
+    depth: usize,
+    next_id: &mut usize,
+    lines: &mut Vec<String>,
+    components: &mut Vec<String>,
+) {
+    let indent = "  ".repeat(depth);
+    let mut contents: Vec<String> = folder
+        .files
+        .iter()
+        .map(|file| format!("<File Source=\"{}\" />", escape(&file.to_string_lossy())))
+        .collect();
+    if folder.create && contents.is_empty() && folder.folders.is_empty() {
+        contents.push("<CreateFolder />".into());
+    }
+    for content in contents {
+        *next_id += 1;
+        let id = format!("Component{next_id}");
+        lines.push(format!("{indent}<Component Id=\"{id}\">"));
+        lines.push(format!("{indent}  {content}"));
+        lines.push(format!("{indent}</Component>"));
+        components.push(id);
+    }
+    for (name, child) in &folder.folders {
+        *next_id += 1;
+        lines.push(format!(
+            "{indent}<Directory Id=\"Folder{next_id}\" Name=\"{}\">",
+            escape(name)
+        ));
+        render(child, depth + 1, next_id, lines, components);
+        lines.push(format!("{indent}</Directory>"));
+    }
+}
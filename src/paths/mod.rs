@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// `path` spelled plainly, for display: `.` components and repeated or
+/// trailing separators dropped. `..` stays, since folding `dir/..` away
+/// names another file when `dir` is a symlink; [`identity`] is what decides
+/// whether two spellings are the same file.
+pub fn clean(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let parts: Vec<&str> = path
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    let joined = parts.join("/");
+    match (absolute, joined.is_empty()) {
+        (true, _) => format!("/{joined}"),
+        (false, true) => ".".to_string(),
+        (false, false) => joined,
+    }
+}
+
+/// What `path`, relative to `base`, refers to: its canonical path with every
+/// symlink resolved when it exists, and otherwise `base` made absolute and
+/// joined with `path` cleaned lexically. Two spellings of one existing file
+/// have the same identity, whichever symlinks or `..` they go through.
+pub fn identity(base: &Path, path: impl AsRef<Path>) -> PathBuf {
+    let joined = base.join(path);
+    if let Ok(canonical) = fs::canonicalize(&joined) {
+        return canonical;
+    }
+    let absolute = std::env::current_dir()
+        .map(|cwd| cwd.join(&joined))
+        .unwrap_or(joined);
+    let mut result = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_spellings_and_resolves_symlinks() {
+        assert_eq!(clean("./src//main.c"), "src/main.c");
+        assert_eq!(clean("lib/../src/main.c/"), "lib/../src/main.c");
+        assert_eq!(clean("./"), ".");
+        assert_eq!(clean("/usr/./include"), "/usr/include");
+
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        fs::write(real.join("main.c"), "").unwrap();
+        let same = identity(&real, "main.c");
+        assert_eq!(identity(dir.path(), "./real//main.c"), same);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&real, dir.path().join("link")).unwrap();
+            assert_eq!(identity(dir.path(), "link/main.c"), same);
+            assert_eq!(identity(&dir.path().join("link"), "../real/main.c"), same);
+        }
+        // Missing files are told apart by their cleaned spelling.
+        assert_eq!(identity(&real, "gen/../out.c"), identity(&real, "out.c"));
+    }
+}
@@ -0,0 +1,116 @@
+use clap::ValueEnum;
+use std::process::Command;
+
+/// I/O scheduling class for spawned build commands, from `--io-priority`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IoPriority {
+    /// Only uses the disk when nothing else does
+    Idle,
+    /// Lowest best-effort level
+    Low,
+    /// Default best-effort level
+    Normal,
+    /// Highest best-effort level
+    High,
+}
+
+impl IoPriority {
+    /// `ionice` arguments selecting the class and level.
+    #[cfg(unix)]
+    fn ionice_args(self) -> &'static [&'static str] {
+        match self {
+            IoPriority::Idle => &["-c", "3"],
+            IoPriority::Low => &["-c", "2", "-n", "7"],
+            IoPriority::Normal => &["-c", "2", "-n", "4"],
+            IoPriority::High => &["-c", "2", "-n", "0"],
+        }
+    }
+}
+
+/// CPU and I/O priority applied to every command crust spawns, so a
+/// background build does not starve interactive programs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Priority {
+    nice: Option<i32>,
+    io: Option<IoPriority>,
+}
+
+impl Priority {
+    pub fn new(nice: Option<i32>, io: Option<IoPriority>) -> Self {
+        Priority { nice, io }
+    }
+
+    /// Returns `cmd` set up to run at this priority. On Unix it is wrapped in
+    /// `nice` and, on Linux, `ionice`; on Windows the niceness picks a process
+    /// priority class, and an idle I/O priority selects the idle class, which
+    /// also lowers the process's I/O priority.
+    pub fn apply(&self, cmd: Command) -> Command {
+        if *self == Priority::default() {
+            return cmd;
+        }
+        self.wrap(cmd)
+    }
+
+    #[cfg(unix)]
+    fn wrap(&self, mut cmd: Command) -> Command {
+        use crate::toolchain::wrap_command;
+        if let Some(io) = self.io.filter(|_| cfg!(target_os = "linux")) {
+            cmd = wrap_command("ionice", io.ionice_args(), &cmd);
+        }
+        if let Some(nice) = self.nice {
+            cmd = wrap_command("nice", ["-n".to_string(), nice.to_string()], &cmd);
+        }
+        cmd
+    }
+
+    #[cfg(windows)]
+    fn wrap(&self, mut cmd: Command) -> Command {
+        use std::os::windows::process::CommandExt;
+        const IDLE_PRIORITY_CLASS: u32 = 0x0000_0040;
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+        const ABOVE_NORMAL_PRIORITY_CLASS: u32 = 0x0000_8000;
+        let class = match (self.nice, self.io) {
+            (_, Some(IoPriority::Idle)) => Some(IDLE_PRIORITY_CLASS),
+            (Some(nice), _) if nice >= 15 => Some(IDLE_PRIORITY_CLASS),
+            (Some(nice), _) if nice > 0 => Some(BELOW_NORMAL_PRIORITY_CLASS),
+            (Some(nice), _) if nice < 0 => Some(ABOVE_NORMAL_PRIORITY_CLASS),
+            _ => None,
+        };
+        if let Some(class) = class {
+            cmd.creation_flags(class);
+        }
+        cmd
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn wrap(&self, cmd: Command) -> Command {
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn wraps_commands_in_nice_and_ionice() {
+        let mut cmd = Command::new("cc");
+        cmd.arg("-c")
+            .current_dir("/tmp")
+            .env("CRUST_BUILDDIR", "out");
+
+        let unchanged = Priority::default().apply(Command::new("cc"));
+        assert_eq!(unchanged.get_program(), "cc");
+
+        let wrapped = Priority::new(Some(10), Some(IoPriority::Idle)).apply(cmd);
+        assert_eq!(wrapped.get_program(), "nice");
+        let args: Vec<_> = wrapped.get_args().collect();
+        assert_eq!(args, ["-n", "10", "ionice", "-c", "3", "cc", "-c"]);
+        assert_eq!(
+            wrapped.get_current_dir(),
+            Some(std::path::Path::new("/tmp"))
+        );
+        assert_eq!(wrapped.get_envs().count(), 1);
+    }
+}
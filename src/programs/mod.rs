@@ -0,0 +1,160 @@
+use crate::compat::{Version, VersionReq};
+use crate::error::CrustError;
+use crate::graph::DependencyGraph;
+use crate::toolchain;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+/// A program a custom command runs, from its `programs` list: a name, with
+/// an optional version requirement after whitespace (`"python3 >=3.8"`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramReq {
+    pub name: String,
+    pub version: Option<VersionReq>,
+}
+
+impl ProgramReq {
+    pub fn parse(spec: &str) -> Result<ProgramReq, String> {
+        let spec = spec.trim();
+        let (name, req) = match spec.split_once(char::is_whitespace) {
+            Some((name, req)) => (name, Some(req.trim())),
+            None => (spec, None),
+        };
+        if name.is_empty() {
+            return Err("programs entries must name a program".into());
+        }
+        let version = req
+            .map(|req| {
+                VersionReq::parse(req)
+                    .ok_or_else(|| format!("invalid version requirement `{req}` for {name}"))
+            })
+            .transpose()?;
+        Ok(ProgramReq {
+            name: name.to_string(),
+            version,
+        })
+    }
+}
+
+/// Parses a `--program-path NAME=PATH` override.
+pub fn parse_override(text: &str) -> Result<(String, PathBuf), String> {
+    match text.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("expected NAME=PATH, got `{text}`")),
+    }
+}
+
+/// Finds every program the custom commands in `graph` declare, before
+/// anything is built: in `overrides` first, otherwise on `PATH`. A program
+/// that is missing or too old fails with the target that needs it. Each
+/// program is looked up, and its version checked, once.
+pub fn resolve(
+    graph: &DependencyGraph,
+    overrides: &[(String, PathBuf)],
+) -> Result<HashMap<String, PathBuf>> {
+    let overrides: HashMap<&str, &PathBuf> = overrides
+        .iter()
+        .map(|(name, path)| (name.as_str(), path))
+        .collect();
+    let mut needed: BTreeMap<&str, Vec<(&str, &ProgramReq)>> = BTreeMap::new();
+    for node in graph.nodes() {
+        for program in &node.programs {
+            needed
+                .entry(program.name.as_str())
+                .or_default()
+                .push((node.name.as_str(), program));
+        }
+    }
+
+    let mut found = HashMap::new();
+    for (name, users) in needed {
+        let path = match overrides.get(name) {
+            Some(path) => toolchain::find_program(&path.to_string_lossy()).ok_or_else(|| {
+                anyhow!(
+                    "--program-path {name}={} is not an executable",
+                    path.display()
+                )
+            })?,
+            None => toolchain::find_program(name).ok_or_else(|| {
+                anyhow::Error::from(CrustError::CompilerMissing {
+                    program: name.to_string(),
+                })
+                .context(format!("Target '{}' needs {name}", users[0].0))
+            })?,
+        };
+        let mut version = None;
+        for (target, program) in users {
+            let Some(req) = &program.version else {
+                continue;
+            };
+            let version = version.get_or_insert_with(|| {
+                toolchain::program_version(&path).and_then(|text| version_in(&text))
+            });
+            match version {
+                Some(version) if req.matches(*version) => {}
+                Some(version) => {
+                    return Err(anyhow!(
+                        "Target '{target}' needs {name} {req}, but {} is {version}",
+                        path.display()
+                    ))
+                }
+                None => {
+                    return Err(anyhow!(
+                    "Target '{target}' needs {name} {req}, but `{} --version` printed no version",
+                    path.display()
+                ))
+                }
+            }
+        }
+        found.insert(name.to_string(), path);
+    }
+    Ok(found)
+}
+
+/// The first `major.minor[.patch]` number in a `--version` line, such as
+/// `3.12.1` in `Python 3.12.1` or `25.1` in `libprotoc 25.1`. Suffixes like
+/// `rc1` are ignored.
+pub fn version_in(text: &str) -> Option<Version> {
+    text.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .map(|token| token.trim_matches('.'))
+        .filter(|token| token.contains('.'))
+        .find_map(|token| {
+            let parts: Vec<&str> = token.split('.').take(3).collect();
+            Version::parse(&parts.join("."))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_requirements_and_reported_versions() {
+        let req = ProgramReq::parse("python3 >=3.8, <4").unwrap();
+        assert_eq!(req.name, "python3");
+        assert!(req
+            .version
+            .as_ref()
+            .unwrap()
+            .matches(Version::new(3, 12, 1)));
+        assert!(!req.version.unwrap().matches(Version::new(3, 6, 9)));
+        assert_eq!(ProgramReq::parse("protoc").unwrap().version, None);
+        assert!(ProgramReq::parse("protoc >=x").is_err());
+
+        assert_eq!(version_in("Python 3.12.1rc1"), Some(Version::new(3, 12, 1)));
+        assert_eq!(version_in("libprotoc 25.1"), Some(Version::new(25, 1, 0)));
+        assert_eq!(
+            version_in("GNU Make 4.3.0.1 Built for x86_64"),
+            Some(Version::new(4, 3, 0))
+        );
+        assert_eq!(version_in("no version here"), None);
+        assert_eq!(
+            parse_override("python3=/opt/py/bin/python3"),
+            Ok(("python3".into(), PathBuf::from("/opt/py/bin/python3")))
+        );
+        assert!(parse_override("python3").is_err());
+    }
+}
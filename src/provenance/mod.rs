@@ -0,0 +1,84 @@
+use crate::digest::sha256_file;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of the provenance manifest written into the build directory.
+pub const OUTPUT_MANIFEST: &str = "outputs.json";
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct OutputRecord {
+    /// Path relative to the build directory when possible.
+    pub path: PathBuf,
+    pub target: String,
+    pub size: u64,
+    pub sha256: String,
+    pub command_digest: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputManifest {
+    pub version: u32,
+    pub artifacts: Vec<OutputRecord>,
+}
+
+impl OutputRecord {
+    /// Describes `path`, produced by `target`, as seen from `builddir`.
+    pub fn describe(
+        builddir: &Path,
+        target: &str,
+        path: &Path,
+        command_digest: Option<&str>,
+    ) -> Result<Self> {
+        let meta = fs::metadata(path)
+            .with_context(|| format!("Failed to inspect output {}", path.display()))?;
+        Ok(OutputRecord {
+            path: path.strip_prefix(builddir).unwrap_or(path).to_path_buf(),
+            target: target.to_string(),
+            size: meta.len(),
+            sha256: sha256_file(path)?,
+            command_digest: command_digest.map(str::to_string),
+        })
+    }
+}
+
+/// Writes `builddir/outputs.json` listing `artifacts` and returns its path.
+pub fn write_output_manifest(builddir: &Path, artifacts: Vec<OutputRecord>) -> Result<PathBuf> {
+    let path = builddir.join(OUTPUT_MANIFEST);
+    let manifest = OutputManifest {
+        version: 1,
+        artifacts,
+    };
+    let content = serde_json::to_string_pretty(&manifest)?;
+    fs::write(&path, content + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn writes_relative_paths_sizes_and_hashes() {
+        let dir = tempdir().unwrap();
+        let artifact = dir.path().join("app");
+        fs::write(&artifact, b"abc").unwrap();
+
+        let record = OutputRecord::describe(dir.path(), "app", &artifact, Some("d1")).unwrap();
+        assert_eq!(record.path, PathBuf::from("app"));
+        assert_eq!(record.size, 3);
+
+        let path = write_output_manifest(dir.path(), vec![record]).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(json["artifacts"][0]["target"], "app");
+        assert_eq!(json["artifacts"][0]["command_digest"], "d1");
+        assert_eq!(
+            json["artifacts"][0]["sha256"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::compat::{Version, VersionReq};
+use crate::config::{CommandLine, PythonInfo};
+use crate::graph::DependencyGraph;
+use crate::programs::ProgramReq;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Placeholder in custom commands replaced by the `[python]` interpreter.
+pub const PLACEHOLDER: &str = "@PYTHON@";
+
+/// Directory of the build directory holding every python target's modules,
+/// laid out as in site-packages so it can go on `PYTHONPATH`.
+pub const MODULE_DIR: &str = "python";
+
+/// Settings of a python_extension or python_sources target.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PythonSettings {
+    /// Interpreter named in `[python]`, a key of the resolved programs.
+    pub interpreter: String,
+    /// Package directory below site-packages.
+    pub subdir: Option<String>,
+}
+
+impl PythonSettings {
+    /// Where `file` goes, relative to site-packages or to `MODULE_DIR`.
+    pub fn module_path(&self, file: &str) -> String {
+        match &self.subdir {
+            Some(subdir) => format!("{}/{file}", subdir.trim_end_matches('/')),
+            None => file.to_string(),
+        }
+    }
+}
+
+/// The `[python]` interpreter as a program the build needs, so it is found
+/// and its version checked before anything runs.
+pub fn requirement(info: &PythonInfo) -> Result<ProgramReq, String> {
+    let version = info
+        .version
+        .as_deref()
+        .map(|req| {
+            VersionReq::parse(req)
+                .ok_or_else(|| format!("invalid [python] version requirement `{req}`"))
+        })
+        .transpose()?;
+    Ok(ProgramReq {
+        name: info.interpreter.clone(),
+        version,
+    })
+}
+
+/// `command` with `@PYTHON@` replaced by `interpreter`, or `None` when it
+/// does not mention the placeholder.
+pub fn substitute(command: &CommandLine, interpreter: &str) -> Option<CommandLine> {
+    match command {
+        CommandLine::Shell(line) if line.contains(PLACEHOLDER) => {
+            Some(CommandLine::Shell(line.replace(PLACEHOLDER, interpreter)))
+        }
+        CommandLine::Argv(args) if args.iter().any(|arg| arg.contains(PLACEHOLDER)) => {
+            Some(CommandLine::Argv(
+                args.iter()
+                    .map(|arg| arg.replace(PLACEHOLDER, interpreter))
+                    .collect(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// What the build needs to know about the interpreter, asked from it with
+/// `sysconfig`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interpreter {
+    pub path: PathBuf,
+    pub version: Version,
+    /// Directory holding `Python.h`.
+    pub include_dir: PathBuf,
+    /// site-packages relative to the install prefix, such as
+    /// `lib/python3.12/site-packages`.
+    pub site_dir: PathBuf,
+}
+
+// `posix_prefix` rather than the default scheme, which on Debian adds a
+// `local/` that would end up below the prefix twice.
+const PROBE: &str = "import os, sys, sysconfig
+scheme = 'nt' if os.name == 'nt' else 'posix_prefix'
+print('%d.%d.%d' % sys.version_info[:3])
+print(sysconfig.get_path('include'))
+print(sysconfig.get_path('platlib', scheme, {'base': '', 'platbase': ''}))";
+
+impl Interpreter {
+    /// Runs the interpreter at `path` to learn its version and layout.
+    pub fn probe(path: &Path) -> Result<Interpreter> {
+        let output = Command::new(path)
+            .arg("-c")
+            .arg(PROBE)
+            .output()
+            .with_context(|| format!("Failed to run {}", path.display()))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} could not report its configuration: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Interpreter::parse(path, &stdout)
+            .ok_or_else(|| anyhow!("Unexpected output from {}: {stdout}", path.display()))
+    }
+
+    fn parse(path: &Path, output: &str) -> Option<Interpreter> {
+        let mut lines = output.lines().map(str::trim);
+        let version = Version::parse(lines.next()?)?;
+        let include_dir = PathBuf::from(lines.next()?);
+        let site_dir = PathBuf::from(lines.next()?.trim_start_matches(['/', '\\']));
+        Some(Interpreter {
+            path: path.to_path_buf(),
+            version,
+            include_dir,
+            site_dir,
+        })
+    }
+}
+
+/// The interpreter the python targets of `graph` are built for, at its path
+/// in `programs`, or `None` when the graph has no python targets.
+pub fn find(
+    graph: &DependencyGraph,
+    programs: &HashMap<String, PathBuf>,
+) -> Result<Option<Interpreter>> {
+    let Some(settings) = graph.nodes().find_map(|node| node.python.as_ref()) else {
+        return Ok(None);
+    };
+    let path = programs.get(&settings.interpreter).ok_or_else(|| {
+        anyhow!(
+            "Python interpreter {} was not resolved",
+            settings.interpreter
+        )
+    })?;
+    Interpreter::probe(path).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_interpreter_layout() {
+        let python = Interpreter::parse(
+            Path::new("/usr/bin/python3"),
+            "3.12.1\n/usr/include/python3.12\n/lib/python3.12/site-packages\n",
+        )
+        .unwrap();
+        assert_eq!(python.version, Version::new(3, 12, 1));
+        assert_eq!(python.include_dir, PathBuf::from("/usr/include/python3.12"));
+        assert_eq!(
+            python.site_dir,
+            PathBuf::from("lib/python3.12/site-packages")
+        );
+        assert_eq!(Interpreter::parse(Path::new("python3"), "oops\n"), None);
+
+        let settings = PythonSettings {
+            interpreter: "python3".into(),
+            subdir: Some("mypkg/".into()),
+        };
+        assert_eq!(settings.module_path("_speedups.so"), "mypkg/_speedups.so");
+        assert_eq!(
+            substitute(&CommandLine::from("@PYTHON@ gen.py"), "python3.11"),
+            Some(CommandLine::from("python3.11 gen.py"))
+        );
+        assert_eq!(substitute(&CommandLine::from("make"), "python3"), None);
+    }
+}
@@ -0,0 +1,540 @@
+use crate::config;
+use crate::error::CrustError;
+use crate::graph::{DependencyGraph, TargetKind, TargetNode};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QueryOutput {
+    /// One target name per line
+    Names,
+    /// A JSON array describing each target
+    Json,
+}
+
+/// A parsed `crust query` expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// `//...`: every target.
+    All,
+    /// A target by name; `//name`, `//:name`, and `:name` are accepted too,
+    /// and targets of subdir manifests go by their label, `//dir:name`.
+    Target(String),
+    /// `deps(expr[, depth])`: the targets and what they depend on.
+    Deps(Box<Expr>, Option<usize>),
+    /// `rdeps(expr[, depth])`: the targets and what depends on them.
+    Rdeps(Box<Expr>, Option<usize>),
+    /// `kind(pattern, expr)`: targets whose type contains `pattern`.
+    Kind(String, Box<Expr>),
+    /// `label(name, expr)`: targets carrying the label `name`.
+    Label(String, Box<Expr>),
+    /// `owner(name, expr)`: targets owned by `name`.
+    Owner(String, Box<Expr>),
+    /// `a + b`, `a union b`.
+    Union(Box<Expr>, Box<Expr>),
+    /// `a - b`, `a except b`.
+    Except(Box<Expr>, Box<Expr>),
+    /// `a ^ b`, `a intersect b`.
+    Intersect(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Open,
+    Close,
+    Comma,
+    Plus,
+    Minus,
+    Caret,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.-@/:*~".contains(c)
+}
+
+fn tokenize(input: &str) -> std::result::Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '+' => Token::Plus,
+            '^' => Token::Caret,
+            // A lone `-` is the operator; inside a word it is part of a name.
+            '-' if !chars.peek().is_some_and(|(_, next)| is_word_char(*next)) => Token::Minus,
+            c if is_word_char(c) => {
+                let mut word = c.to_string();
+                while let Some((_, next)) = chars.peek().filter(|(_, next)| is_word_char(*next)) {
+                    word.push(*next);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+            c => return Err(format!("unexpected '{c}' at column {}", at + 1)),
+        };
+        tokens.push((at, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.len, |(at, _)| *at) + 1
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> std::result::Result<(), String> {
+        let column = self.column();
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("expected {what} at column {column}")),
+        }
+    }
+
+    fn expr(&mut self) -> std::result::Result<Expr, String> {
+        let mut left = self.term()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Plus) => Expr::Union,
+                Some(Token::Minus) => Expr::Except,
+                Some(Token::Caret) => Expr::Intersect,
+                Some(Token::Word(word)) if word == "union" => Expr::Union,
+                Some(Token::Word(word)) if word == "except" => Expr::Except,
+                Some(Token::Word(word)) if word == "intersect" => Expr::Intersect,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = operator(Box::new(left), Box::new(self.term()?));
+        }
+    }
+
+    fn depth(&mut self) -> std::result::Result<Option<usize>, String> {
+        if self.peek() != Some(&Token::Comma) {
+            return Ok(None);
+        }
+        self.pos += 1;
+        let column = self.column();
+        match self.next() {
+            Some(Token::Word(word)) => word
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("expected a depth at column {column}")),
+            _ => Err(format!("expected a depth at column {column}")),
+        }
+    }
+
+    fn term(&mut self) -> std::result::Result<Expr, String> {
+        let column = self.column();
+        let word = match self.next() {
+            Some(Token::Open) => {
+                let inner = self.expr()?;
+                self.expect(Token::Close, "')'")?;
+                return Ok(inner);
+            }
+            Some(Token::Word(word)) => word,
+            _ => return Err(format!("expected a target or function at column {column}")),
+        };
+        if self.peek() != Some(&Token::Open) {
+            return Ok(match word.as_str() {
+                "//..." => Expr::All,
+                name if config::split_label(name).0.is_some() => Expr::Target(name.to_string()),
+                name => Expr::Target(
+                    name.strip_prefix("//")
+                        .or_else(|| name.strip_prefix(':'))
+                        .map(|name| name.strip_prefix(':').unwrap_or(name))
+                        .unwrap_or(name)
+                        .to_string(),
+                ),
+            });
+        }
+        self.pos += 1;
+        let expr = match word.as_str() {
+            "deps" | "rdeps" => {
+                let inner = Box::new(self.expr()?);
+                let depth = self.depth()?;
+                if word == "deps" {
+                    Expr::Deps(inner, depth)
+                } else {
+                    Expr::Rdeps(inner, depth)
+                }
+            }
+            "kind" => {
+                let column = self.column();
+                let Some(Token::Word(pattern)) = self.next() else {
+                    return Err(format!("expected a target type at column {column}"));
+                };
+                if !TargetKind::ALL
+                    .iter()
+                    .any(|kind| kind.type_name().contains(pattern.as_str()))
+                {
+                    let types: Vec<&str> = TargetKind::ALL.iter().map(|k| k.type_name()).collect();
+                    return Err(format!(
+                        "'{pattern}' matches no target type (one of {})",
+                        types.join(", ")
+                    ));
+                }
+                self.expect(Token::Comma, "','")?;
+                Expr::Kind(pattern, Box::new(self.expr()?))
+            }
+            "label" | "owner" => {
+                let column = self.column();
+                let Some(Token::Word(value)) = self.next() else {
+                    return Err(format!("expected a {word} at column {column}"));
+                };
+                self.expect(Token::Comma, "','")?;
+                let inner = Box::new(self.expr()?);
+                if word == "label" {
+                    Expr::Label(value, inner)
+                } else {
+                    Expr::Owner(value, inner)
+                }
+            }
+            function => return Err(format!("unknown function '{function}' at column {column}")),
+        };
+        self.expect(Token::Close, "')'")?;
+        Ok(expr)
+    }
+}
+
+/// Parses a query expression.
+pub fn parse(input: &str) -> Result<Expr> {
+    let invalid = |reason: String| CrustError::InvalidArgument {
+        message: format!("Invalid query '{input}': {reason}"),
+    };
+    let tokens = tokenize(input).map_err(invalid)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        len: input.len(),
+    };
+    let expr = parser.expr().map_err(invalid)?;
+    if parser.pos < parser.tokens.len() {
+        return Err(invalid(format!("unexpected input at column {}", parser.column())).into());
+    }
+    Ok(expr)
+}
+
+/// Follows `edges` from `roots`, up to `depth` steps when given.
+fn closure<'a>(
+    roots: BTreeSet<String>,
+    depth: Option<usize>,
+    edges: impl Fn(&str) -> Vec<&'a str>,
+) -> BTreeSet<String> {
+    let mut result = roots.clone();
+    let mut frontier: Vec<String> = roots.into_iter().collect();
+    let mut level = 0;
+    while !frontier.is_empty() && depth.is_none_or(|depth| level < depth) {
+        let mut next = Vec::new();
+        for name in &frontier {
+            for neighbour in edges(name) {
+                if result.insert(neighbour.to_string()) {
+                    next.push(neighbour.to_string());
+                }
+            }
+        }
+        frontier = next;
+        level += 1;
+    }
+    result
+}
+
+fn eval(expr: &Expr, graph: &DependencyGraph) -> Result<BTreeSet<String>> {
+    Ok(match expr {
+        Expr::All => graph.nodes().map(|node| node.name.clone()).collect(),
+        Expr::Target(name) => {
+            if graph.get(name).is_none() {
+                return Err(CrustError::InvalidArgument {
+                    message: format!("No target named '{name}'"),
+                }
+                .into());
+            }
+            BTreeSet::from([name.clone()])
+        }
+        Expr::Deps(inner, depth) => closure(eval(inner, graph)?, *depth, |name| {
+            graph.get(name).map_or_else(Vec::new, |node| {
+                node.dependencies.iter().map(String::as_str).collect()
+            })
+        }),
+        Expr::Rdeps(inner, depth) => {
+            let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+            for node in graph.nodes() {
+                for dep in &node.dependencies {
+                    dependents
+                        .entry(dep.as_str())
+                        .or_default()
+                        .push(node.name.as_str());
+                }
+            }
+            closure(eval(inner, graph)?, *depth, |name| {
+                dependents.get(name).cloned().unwrap_or_default()
+            })
+        }
+        Expr::Kind(pattern, inner) => eval(inner, graph)?
+            .into_iter()
+            .filter(|name| {
+                graph
+                    .get(name)
+                    .is_some_and(|node| node.kind.type_name().contains(pattern.as_str()))
+            })
+            .collect(),
+        Expr::Label(label, inner) => eval(inner, graph)?
+            .into_iter()
+            .filter(|name| {
+                graph
+                    .get(name)
+                    .is_some_and(|node| node.labels.contains(label))
+            })
+            .collect(),
+        Expr::Owner(owner, inner) => eval(inner, graph)?
+            .into_iter()
+            .filter(|name| {
+                graph
+                    .get(name)
+                    .is_some_and(|node| node.owners.contains(owner))
+            })
+            .collect(),
+        Expr::Union(left, right) => &eval(left, graph)? | &eval(right, graph)?,
+        Expr::Except(left, right) => &eval(left, graph)? - &eval(right, graph)?,
+        Expr::Intersect(left, right) => &eval(left, graph)? & &eval(right, graph)?,
+    })
+}
+
+/// Evaluates `expr` and returns the matching targets with dependencies
+/// before their dependents, ties broken by name, so output is stable.
+pub fn evaluate<'a>(expr: &Expr, graph: &'a DependencyGraph) -> Result<Vec<&'a TargetNode>> {
+    fn visit<'a>(
+        name: &str,
+        graph: &'a DependencyGraph,
+        selected: &BTreeSet<String>,
+        seen: &mut BTreeSet<String>,
+        order: &mut Vec<&'a TargetNode>,
+    ) {
+        if !seen.insert(name.to_string()) {
+            return;
+        }
+        let Some(node) = graph.get(name) else {
+            return;
+        };
+        let mut deps: Vec<&String> = node.dependencies.iter().collect();
+        deps.sort();
+        for dep in deps {
+            visit(dep, graph, selected, seen, order);
+        }
+        if selected.contains(name) {
+            order.push(node);
+        }
+    }
+
+    let selected = eval(expr, graph)?;
+    let mut seen = BTreeSet::new();
+    let mut order = Vec::new();
+    for name in &selected {
+        visit(name, graph, &selected, &mut seen, &mut order);
+    }
+    Ok(order)
+}
+
+/// JSON description of `nodes`, with outputs as paths in `builddir`.
+pub fn to_json(nodes: &[&TargetNode], builddir: &Path) -> Value {
+    let targets: Vec<Value> = nodes
+        .iter()
+        .map(|node| {
+            json!({
+                "name": node.name,
+                "type": node.kind.type_name(),
+                "sources": node.sources,
+                "deps": node.dependencies,
+                "labels": node.labels,
+                "owners": node.owners,
+                "outputs": node
+                    .outputs
+                    .iter()
+                    .map(|output| builddir.join(output))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    Value::Array(targets)
+}
+
+/// `crust targets` table: each target's name, type, outputs (paths in
+/// `builddir`), and direct dependencies, in aligned columns.
+pub fn table(nodes: &[&TargetNode], builddir: &Path) -> String {
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "-".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    let mut rows = vec![["NAME", "TYPE", "OUTPUTS", "DEPS"].map(String::from)];
+    for node in nodes {
+        let mut kind = node.kind.type_name().to_string();
+        if node.options.test {
+            kind.push_str(" (test)");
+        }
+        let outputs = node
+            .outputs
+            .iter()
+            .map(|output| builddir.join(output).display().to_string())
+            .collect();
+        rows.push([
+            node.name.clone(),
+            kind,
+            list(outputs),
+            list(node.dependencies.clone()),
+        ]);
+    }
+    let mut widths = [0; 3];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            let line = format!(
+                "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+            );
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
+
+    fn library(name: &str, deps: &[&str]) -> Target {
+        Target::SharedLibrary {
+            name: name.into(),
+            sources: vec![format!("{name}.c")],
+            deps: deps.iter().map(|dep| dep.to_string()).collect(),
+            labels: Vec::new(),
+            owners: Vec::new(),
+            deprecated: None,
+            options: Default::default(),
+        }
+    }
+
+    #[test]
+    fn evaluates_deps_rdeps_kind_and_set_operators() {
+        let mut manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                library("base", &[]),
+                library("core", &["base"]),
+                library("net-io", &["core"]),
+                Target::Executable {
+                    name: "app".into(),
+                    sources: vec!["main.c".into()],
+                    deps: vec!["net-io".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        if let Target::SharedLibrary { labels, owners, .. } = &mut manifest.targets[2] {
+            labels.push("team:infra".into());
+            owners.push("@net".into());
+        }
+        let graph = DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap();
+        let names = |query: &str| -> Vec<String> {
+            evaluate(&parse(query).unwrap(), &graph)
+                .unwrap()
+                .into_iter()
+                .map(|node| node.name.clone())
+                .collect()
+        };
+
+        assert_eq!(names("deps(app)"), ["base", "core", "net-io", "app"]);
+        assert_eq!(names("deps(//app, 1)"), ["net-io", "app"]);
+        assert_eq!(names("rdeps(core) - core"), ["net-io", "app"]);
+        assert_eq!(
+            names("kind(library, //...) except base"),
+            ["core", "net-io"]
+        );
+        assert_eq!(
+            names("kind(executable, deps(app)) + :base"),
+            ["base", "app"]
+        );
+        assert_eq!(names("rdeps(base, 1) ^ deps(net-io)"), ["base", "core"]);
+        assert_eq!(names("label(team:infra, rdeps(core))"), ["net-io"]);
+        assert_eq!(names("//... - owner(@net, //...)"), ["base", "core", "app"]);
+
+        assert!(parse("deps(app").is_err());
+        assert!(parse("kind(widget, //...)").is_err());
+        assert!(evaluate(&parse("deps(missing)").unwrap(), &graph).is_err());
+
+        let nodes = evaluate(&parse("deps(net-io, 1)").unwrap(), &graph).unwrap();
+        assert_eq!(
+            table(&nodes, Path::new("build")),
+            "NAME    TYPE            OUTPUTS             DEPS\n\
+             core    shared_library  build/libcore.so    base\n\
+             net-io  shared_library  build/libnet-io.so  core\n"
+        );
+    }
+
+    #[test]
+    fn parses_subdirectory_labels() {
+        assert_eq!(
+            parse("//sub:app").unwrap(),
+            Expr::Target("//sub:app".into())
+        );
+        assert_eq!(
+            parse("deps(//lib/net:io)").unwrap(),
+            Expr::Deps(Box::new(Expr::Target("//lib/net:io".into())), None)
+        );
+        assert_eq!(parse("//:app").unwrap(), Expr::Target("app".into()));
+        assert_eq!(parse(":app").unwrap(), Expr::Target("app".into()));
+    }
+}
@@ -0,0 +1,836 @@
+use crate::config::Rule;
+use crate::digest::{sha256_bytes, sha256_file};
+use crate::error::CrustError;
+use crate::executor::command::{Action, CommandRunner};
+use crate::standards::Lang;
+use crate::ui;
+use anyhow::{anyhow, Context, Result};
+use prost::Message;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::SystemTime;
+use tonic::client::Grpc;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Endpoint};
+use tracing::debug;
+
+/// The messages of the Remote Execution API
+/// (`build.bazel.remote.execution.v2`) and the Google types it embeds.
+pub mod proto;
+
+/// Most bytes crust puts in one batch request to the CAS; servers accept
+/// at least 4 MiB per request.
+const MAX_BATCH_BYTES: usize = 3 * 1024 * 1024;
+
+const FIND_MISSING: &str =
+    "/build.bazel.remote.execution.v2.ContentAddressableStorage/FindMissingBlobs";
+const BATCH_UPDATE: &str =
+    "/build.bazel.remote.execution.v2.ContentAddressableStorage/BatchUpdateBlobs";
+const BATCH_READ: &str =
+    "/build.bazel.remote.execution.v2.ContentAddressableStorage/BatchReadBlobs";
+const EXECUTE: &str = "/build.bazel.remote.execution.v2.Execution/Execute";
+
+/// Parses a `--remote-property` value, `KEY=VALUE`.
+pub fn parse_property(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got `{text}`")),
+    }
+}
+
+/// Client of a server implementing the Remote Execution API, such as
+/// Buildbarn or BuildBuddy, set with `--remote-executor`. Inputs are
+/// uploaded to its content-addressable storage, the action runs on one of
+/// its workers, and the outputs are downloaded into the build directory.
+pub struct RemoteExecutor {
+    runtime: tokio::runtime::Runtime,
+    client: Arc<Client>,
+    /// Platform properties workers must match, such as `OSFamily=linux`.
+    platform: Vec<(String, String)>,
+    /// Digests of the files hashed so far, while their size and
+    /// modification time are the same.
+    hashed: Mutex<HashMap<PathBuf, (SystemTime, u64, proto::Digest)>>,
+}
+
+struct Client {
+    endpoint: String,
+    channel: Channel,
+    instance_name: String,
+    /// Blobs the CAS is known to hold.
+    stored: Mutex<HashSet<proto::Digest>>,
+}
+
+/// A compile turned into a remote action.
+#[derive(Debug)]
+struct Plan {
+    command: proto::Command,
+    /// Input files by their path relative to the execution root.
+    inputs: BTreeMap<String, PathBuf>,
+}
+
+impl RemoteExecutor {
+    /// Client of the server at `url`, `grpc://host:port`. The connection is
+    /// made with the first action.
+    pub fn new(url: &str, instance_name: &str, platform: Vec<(String, String)>) -> Result<Self> {
+        let address = match url.split_once("://") {
+            Some(("grpc", address)) => address,
+            _ => {
+                return Err(CrustError::InvalidArgument {
+                    message: format!(
+                        "--remote-executor {url}: expected grpc://host:port (TLS is not supported)"
+                    ),
+                }
+                .into())
+            }
+        };
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .context("Failed to start the remote execution runtime")?;
+        let endpoint = Endpoint::from_shared(format!("http://{address}")).map_err(|err| {
+            CrustError::InvalidArgument {
+                message: format!("--remote-executor {url}: {err}"),
+            }
+        })?;
+        let channel = {
+            let _context = runtime.enter();
+            endpoint.connect_lazy()
+        };
+        let mut platform = platform;
+        platform.sort();
+        Ok(RemoteExecutor {
+            runtime,
+            client: Arc::new(Client {
+                endpoint: url.to_string(),
+                channel,
+                instance_name: instance_name.to_string(),
+                stored: Mutex::default(),
+            }),
+            platform,
+            hashed: Mutex::default(),
+        })
+    }
+
+    /// Runs `future` on the client's runtime and waits for it. The calling
+    /// thread may belong to another runtime, which cannot block on this one.
+    fn block<T: Send + 'static>(&self, future: impl Future<Output = T> + Send + 'static) -> T {
+        let (sender, receiver) = mpsc::channel();
+        self.runtime.spawn(async move {
+            let _ = sender.send(future.await);
+        });
+        receiver.recv().expect("remote execution task panicked")
+    }
+
+    fn digest_file(&self, path: &Path) -> Result<proto::Digest> {
+        let metadata =
+            fs::metadata(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let modified = metadata.modified()?;
+        let mut hashed = self.hashed.lock().expect("digest cache poisoned");
+        if let Some((time, size, digest)) = hashed.get(path) {
+            if *time == modified && *size == metadata.len() {
+                return Ok(digest.clone());
+            }
+        }
+        let digest = proto::Digest {
+            hash: sha256_file(path)?,
+            size_bytes: metadata.len() as i64,
+        };
+        hashed.insert(
+            path.to_path_buf(),
+            (modified, metadata.len(), digest.clone()),
+        );
+        Ok(digest)
+    }
+
+    /// Uploads `plan`'s inputs, command, and action, runs the action, and
+    /// writes its outputs below `root`. Returns its stderr.
+    fn execute(&self, plan: Plan, root: &Path, action: &Action<'_>) -> Result<String> {
+        let mut blobs: HashMap<proto::Digest, Blob> = HashMap::new();
+        let mut files = BTreeMap::new();
+        for (name, path) in &plan.inputs {
+            let digest = self.digest_file(path)?;
+            files.insert(name.clone(), (digest.clone(), is_executable(path)));
+            blobs.insert(digest, Blob::File(path.clone()));
+        }
+        let (input_root, directories) = input_root(&files);
+        blobs.extend(
+            directories
+                .into_iter()
+                .map(|(d, data)| (d, Blob::Data(data))),
+        );
+        let mut command = plan.command;
+        command.platform = Some(self.platform());
+        let command = command.encode_to_vec();
+        let command_digest = digest_of(&command);
+        blobs.insert(command_digest.clone(), Blob::Data(command));
+        let remote_action = proto::Action {
+            command_digest: Some(command_digest),
+            input_root_digest: Some(input_root),
+            platform: Some(self.platform()),
+        }
+        .encode_to_vec();
+        let action_digest = digest_of(&remote_action);
+        blobs.insert(action_digest.clone(), Blob::Data(remote_action));
+        self.upload(blobs)?;
+
+        debug!(
+            target_name = action.target,
+            digest = %action_digest.hash,
+            "executing remotely"
+        );
+        let client = Arc::clone(&self.client);
+        let response = self.block(async move { client.execute(action_digest).await })?;
+        if let Some(status) = response.status.as_ref().filter(|s| s.code != 0) {
+            return Err(anyhow!(
+                "Remote execution of {} for '{}' failed: {} (code {})",
+                action.description,
+                action.target,
+                status.message,
+                status.code
+            ));
+        }
+        let result = response.result.unwrap_or_default();
+        let stdout = self.read_output(result.stdout_raw, result.stdout_digest)?;
+        let stderr = self.read_output(result.stderr_raw, result.stderr_digest)?;
+        std::io::stdout().write_all(&stdout)?;
+        let stderr = String::from_utf8_lossy(&stderr).into_owned();
+        ui::command_output(action.target, &stderr);
+        if result.exit_code != 0 {
+            return Err(CrustError::CommandFailed {
+                target: action.target.to_string(),
+                action: action.description.to_string(),
+                exit: Some(result.exit_code),
+            }
+            .into());
+        }
+        self.download(&result.output_files, root)?;
+        Ok(stderr)
+    }
+
+    fn platform(&self) -> proto::Platform {
+        proto::Platform {
+            properties: self
+                .platform
+                .iter()
+                .map(|(name, value)| proto::Property {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Uploads the blobs the CAS does not hold yet, in batches.
+    fn upload(&self, blobs: HashMap<proto::Digest, Blob>) -> Result<()> {
+        let unknown: Vec<proto::Digest> = {
+            let stored = self.client.stored.lock().expect("stored blobs poisoned");
+            blobs
+                .keys()
+                .filter(|digest| !stored.contains(*digest))
+                .cloned()
+                .collect()
+        };
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        let client = Arc::clone(&self.client);
+        let missing = self.block(async move { client.find_missing(unknown).await })?;
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        for digest in missing {
+            let data = match &blobs[&digest] {
+                Blob::File(path) => {
+                    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?
+                }
+                Blob::Data(data) => data.clone(),
+            };
+            if data.len() > MAX_BATCH_BYTES {
+                return Err(anyhow!(
+                    "{} is too large to upload to the remote executor ({} bytes)",
+                    blobs[&digest],
+                    data.len()
+                ));
+            }
+            if batch_size + data.len() > MAX_BATCH_BYTES {
+                let client = Arc::clone(&self.client);
+                let full = std::mem::take(&mut batch);
+                self.block(async move { client.update(full).await })?;
+                batch_size = 0;
+            }
+            batch_size += data.len();
+            batch.push(proto::UpdateBlob {
+                digest: Some(digest),
+                data,
+            });
+        }
+        if !batch.is_empty() {
+            let client = Arc::clone(&self.client);
+            self.block(async move { client.update(batch).await })?;
+        }
+        self.client
+            .stored
+            .lock()
+            .expect("stored blobs poisoned")
+            .extend(blobs.into_keys());
+        Ok(())
+    }
+
+    /// Contents of stdout or stderr, inlined or stored in the CAS.
+    fn read_output(&self, raw: Vec<u8>, digest: Option<proto::Digest>) -> Result<Vec<u8>> {
+        match digest.filter(|d| d.size_bytes > 0 && raw.is_empty()) {
+            Some(digest) => {
+                let client = Arc::clone(&self.client);
+                let mut read = self.block(async move { client.read(vec![digest]).await })?;
+                Ok(read.pop().map(|(_, data)| data).unwrap_or_default())
+            }
+            None => Ok(raw),
+        }
+    }
+
+    /// Writes the action's output files below `root`, fetching those not
+    /// inlined in the result.
+    fn download(&self, outputs: &[proto::OutputFile], root: &Path) -> Result<()> {
+        let mut fetched: HashMap<proto::Digest, Vec<u8>> = HashMap::new();
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        let pending = outputs.iter().filter_map(|output| {
+            let digest = output.digest.clone()?;
+            (output.contents.is_empty() && digest.size_bytes > 0).then_some(digest)
+        });
+        for digest in pending {
+            let size = digest.size_bytes as usize;
+            if !batch.is_empty() && batch_size + size > MAX_BATCH_BYTES {
+                let client = Arc::clone(&self.client);
+                let full = std::mem::take(&mut batch);
+                fetched.extend(self.block(async move { client.read(full).await })?);
+                batch_size = 0;
+            }
+            batch_size += size;
+            batch.push(digest);
+        }
+        if !batch.is_empty() {
+            let client = Arc::clone(&self.client);
+            fetched.extend(self.block(async move { client.read(batch).await })?);
+        }
+        for output in outputs {
+            let path = root.join(&output.path);
+            let data = match &output.digest {
+                Some(digest) if output.contents.is_empty() && digest.size_bytes > 0 => fetched
+                    .get(digest)
+                    .ok_or_else(|| anyhow!("Remote executor did not return {}", output.path))?,
+                _ => &output.contents,
+            };
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, data)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            #[cfg(unix)]
+            if output.is_executable {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Client {
+    async fn call<Req, Resp>(&self, path: &'static str, request: Req) -> Result<Resp>
+    where
+        Req: Message + Send + Sync + 'static,
+        Resp: Message + Default + Send + Sync + 'static,
+    {
+        let mut grpc = Grpc::new(self.channel.clone());
+        grpc.ready()
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.endpoint))?;
+        let response = grpc
+            .unary(
+                tonic::Request::new(request),
+                PathAndQuery::from_static(path),
+                ProstCodec::<Req, Resp>::default(),
+            )
+            .await
+            .map_err(|status| self.error(path, status))?;
+        Ok(response.into_inner())
+    }
+
+    fn error(&self, path: &str, status: tonic::Status) -> anyhow::Error {
+        let method = path.rsplit('/').next().unwrap_or(path);
+        anyhow!(
+            "Remote executor {} failed {method} ({:?}): {}",
+            self.endpoint,
+            status.code(),
+            status.message()
+        )
+    }
+
+    async fn find_missing(&self, digests: Vec<proto::Digest>) -> Result<Vec<proto::Digest>> {
+        let request = proto::FindMissingBlobsRequest {
+            instance_name: self.instance_name.clone(),
+            blob_digests: digests,
+        };
+        let response: proto::FindMissingBlobsResponse = self.call(FIND_MISSING, request).await?;
+        Ok(response.missing_blob_digests)
+    }
+
+    async fn update(&self, blobs: Vec<proto::UpdateBlob>) -> Result<()> {
+        let request = proto::BatchUpdateBlobsRequest {
+            instance_name: self.instance_name.clone(),
+            requests: blobs,
+        };
+        let response: proto::BatchUpdateBlobsResponse = self.call(BATCH_UPDATE, request).await?;
+        for blob in response.responses {
+            if let Some(status) = blob.status.filter(|s| s.code != 0) {
+                return Err(anyhow!(
+                    "Failed to upload blob {} to {}: {}",
+                    blob.digest.map(|d| d.hash).unwrap_or_default(),
+                    self.endpoint,
+                    status.message
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn read(&self, digests: Vec<proto::Digest>) -> Result<Vec<(proto::Digest, Vec<u8>)>> {
+        let request = proto::BatchReadBlobsRequest {
+            instance_name: self.instance_name.clone(),
+            digests,
+        };
+        let response: proto::BatchReadBlobsResponse = self.call(BATCH_READ, request).await?;
+        response
+            .responses
+            .into_iter()
+            .map(|blob| {
+                let digest = blob.digest.unwrap_or_default();
+                match blob.status.filter(|s| s.code != 0) {
+                    Some(status) => Err(anyhow!(
+                        "Failed to download blob {} from {}: {}",
+                        digest.hash,
+                        self.endpoint,
+                        status.message
+                    )),
+                    None => Ok((digest, blob.data)),
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the action and waits for the operation to finish.
+    async fn execute(&self, action_digest: proto::Digest) -> Result<proto::ExecuteResponse> {
+        let mut grpc = Grpc::new(self.channel.clone());
+        grpc.ready()
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.endpoint))?;
+        let request = proto::ExecuteRequest {
+            instance_name: self.instance_name.clone(),
+            skip_cache_lookup: false,
+            action_digest: Some(action_digest),
+        };
+        let status = |status| self.error(EXECUTE, status);
+        let mut operations = grpc
+            .server_streaming(
+                tonic::Request::new(request),
+                PathAndQuery::from_static(EXECUTE),
+                ProstCodec::<proto::ExecuteRequest, proto::Operation>::default(),
+            )
+            .await
+            .map_err(status)?
+            .into_inner();
+        while let Some(operation) = operations.message().await.map_err(status)? {
+            if !operation.done {
+                continue;
+            }
+            if let Some(error) = operation.error {
+                return Err(anyhow!(
+                    "Remote operation {} failed: {}",
+                    operation.name,
+                    error.message
+                ));
+            }
+            let response = operation.response.unwrap_or_default();
+            return proto::ExecuteResponse::decode(response.value.as_slice())
+                .context("Remote executor sent an invalid ExecuteResponse");
+        }
+        Err(anyhow!(
+            "{} closed the execution without a result",
+            self.endpoint
+        ))
+    }
+}
+
+/// Where the contents of a blob to upload come from.
+enum Blob {
+    File(PathBuf),
+    Data(Vec<u8>),
+}
+
+impl std::fmt::Display for Blob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Blob::File(path) => write!(f, "{}", path.display()),
+            Blob::Data(_) => f.write_str("A generated blob"),
+        }
+    }
+}
+
+/// Runs the C-family compiles of targets on a [`RemoteExecutor`] and
+/// every other command with `inner`.
+pub struct Remote<'a> {
+    pub inner: &'a dyn CommandRunner,
+    pub executor: &'a RemoteExecutor,
+}
+
+impl CommandRunner for Remote<'_> {
+    fn run(&self, action: Action<'_>) -> Result<String> {
+        let root = env::current_dir()?;
+        let plan = match action.rule {
+            Rule::Compile => plan(&action.cmd, &root)?,
+            _ => None,
+        };
+        match plan {
+            Some(plan) => self.executor.execute(plan, &root, &action),
+            None => self.inner.run(action),
+        }
+    }
+}
+
+/// The remote action of compile `cmd`, run from `root`, or `None` when it
+/// has to run locally: it is not a C-family compile, or it reads files
+/// outside `root` that are not part of the toolchain. The files it reads
+/// are those the compiler lists with `-M`.
+fn plan(cmd: &Command, root: &Path) -> Result<Option<Plan>> {
+    if cmd.get_current_dir().is_some() {
+        return Ok(None);
+    }
+    let Some(program) = cmd.get_program().to_str() else {
+        return Ok(None);
+    };
+    let Some(args) = cmd
+        .get_args()
+        .map(|arg| arg.to_str().map(str::to_string))
+        .collect::<Option<Vec<String>>>()
+    else {
+        return Ok(None);
+    };
+    let source = args
+        .iter()
+        .position(|arg| arg == "-c")
+        .and_then(|idx| args.get(idx + 1));
+    if source.is_none_or(|source| Lang::of(Path::new(source)).is_none()) {
+        return Ok(None);
+    }
+    let Some(output) = value_of(&args, "-o") else {
+        return Ok(None);
+    };
+    let Some(dependencies) = scan_dependencies(cmd, &args, root)? else {
+        return Ok(None);
+    };
+
+    let mut inputs = BTreeMap::new();
+    for dependency in dependencies {
+        let path = Path::new(&dependency);
+        if path.is_absolute() && !path.starts_with(root) {
+            // A system header, provided by the worker's toolchain.
+            continue;
+        }
+        let Some(relative) = relative_to(root, path) else {
+            debug!(file = %dependency, "reads a file outside the execution root, compiling locally");
+            return Ok(None);
+        };
+        inputs.insert(relative, root.join(path));
+    }
+    let mut outputs = vec![output];
+    outputs.extend(value_of(&args, "-MF"));
+    let Some(outputs) = outputs
+        .iter()
+        .map(|path| relative_to(root, Path::new(path)))
+        .collect::<Option<Vec<String>>>()
+    else {
+        return Ok(None);
+    };
+
+    let mut arguments = vec![program.to_string()];
+    arguments.extend(args.iter().map(|arg| relative_arg(root, arg)));
+    let mut environment_variables: Vec<proto::EnvironmentVariable> = cmd
+        .get_envs()
+        .filter_map(|(name, value)| {
+            Some(proto::EnvironmentVariable {
+                name: name.to_str()?.to_string(),
+                value: value?.to_str()?.to_string(),
+            })
+        })
+        .collect();
+    environment_variables.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Some(Plan {
+        command: proto::Command {
+            arguments,
+            environment_variables,
+            output_files: outputs.clone(),
+            platform: None,
+            working_directory: String::new(),
+            output_paths: outputs,
+        },
+        inputs,
+    }))
+}
+
+fn value_of(args: &[String], flag: &str) -> Option<String> {
+    let idx = args.iter().position(|arg| arg == flag)?;
+    args.get(idx + 1).cloned()
+}
+
+/// The files compile `cmd` reads, from the make rule the compiler prints
+/// with `-M` instead of compiling; `None` when the compiler fails, so the
+/// compile runs locally and reports the error.
+fn scan_dependencies(cmd: &Command, args: &[String], root: &Path) -> Result<Option<Vec<String>>> {
+    let mut scan = Command::new(cmd.get_program());
+    scan.current_dir(root);
+    let mut skip = false;
+    for arg in args {
+        if std::mem::take(&mut skip) {
+            continue;
+        }
+        match arg.as_str() {
+            "-o" | "-MF" | "-MT" | "-MQ" => skip = true,
+            "-MD" | "-MMD" | "-MP" => {}
+            _ => {
+                scan.arg(arg);
+            }
+        }
+    }
+    scan.arg("-M");
+    for (name, value) in cmd.get_envs() {
+        match value {
+            Some(value) => scan.env(name, value),
+            None => scan.env_remove(name),
+        };
+    }
+    let output = scan
+        .output()
+        .with_context(|| format!("Failed to run {}", cmd.get_program().to_string_lossy()))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(parse_make_rule(&String::from_utf8_lossy(
+        &output.stdout,
+    ))))
+}
+
+/// Prerequisites of the make rules in `text`, with line continuations
+/// joined and `\ ` unescaped.
+fn parse_make_rule(text: &str) -> Vec<String> {
+    let text = text.replace("\\\r\n", " ").replace("\\\n", " ");
+    let mut prerequisites = Vec::new();
+    for line in text.lines() {
+        let Some((_, rest)) = line.split_once(": ") else {
+            continue;
+        };
+        let mut current = String::new();
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&' ') => {
+                    current.push(' ');
+                    chars.next();
+                }
+                c if c.is_whitespace() => {
+                    if !current.is_empty() {
+                        prerequisites.push(std::mem::take(&mut current));
+                    }
+                }
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            prerequisites.push(current);
+        }
+    }
+    prerequisites.sort();
+    prerequisites.dedup();
+    prerequisites
+}
+
+/// `path` relative to `root` with `/` separators, when it stays inside it.
+fn relative_to(root: &Path, path: &Path) -> Option<String> {
+    let relative = if path.is_absolute() {
+        path.strip_prefix(root).ok()?
+    } else {
+        path
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// `arg` with an absolute path below `root`, alone or after `-I`, made
+/// relative, since the worker runs the command in a directory of its own.
+fn relative_arg(root: &Path, arg: &str) -> String {
+    for flag in ["", "-I", "-iquote", "-isystem", "-idirafter"] {
+        let Some(path) = arg.strip_prefix(flag) else {
+            continue;
+        };
+        let path = Path::new(path);
+        if path.is_absolute() {
+            if let Some(relative) = relative_to(root, path) {
+                return format!("{flag}{relative}");
+            }
+        }
+    }
+    arg.to_string()
+}
+
+fn digest_of(data: &[u8]) -> proto::Digest {
+    proto::Digest {
+        hash: sha256_bytes(data),
+        size_bytes: data.len() as i64,
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+/// Directory of the input tree.
+#[derive(Default)]
+struct Tree {
+    files: BTreeMap<String, (proto::Digest, bool)>,
+    directories: BTreeMap<String, Tree>,
+}
+
+/// Digest of the Merkle tree of `files`, keyed by relative path, and the
+/// encoded `Directory` messages it is made of.
+fn input_root(
+    files: &BTreeMap<String, (proto::Digest, bool)>,
+) -> (proto::Digest, Vec<(proto::Digest, Vec<u8>)>) {
+    let mut root = Tree::default();
+    for (path, file) in files {
+        let mut parts: Vec<&str> = path.split('/').collect();
+        let name = parts.pop().unwrap_or_default();
+        let dir = parts.into_iter().fold(&mut root, |dir, part| {
+            dir.directories.entry(part.to_string()).or_default()
+        });
+        dir.files.insert(name.to_string(), file.clone());
+    }
+    let mut blobs = Vec::new();
+    let digest = encode_tree(&root, &mut blobs);
+    (digest, blobs)
+}
+
+fn encode_tree(tree: &Tree, blobs: &mut Vec<(proto::Digest, Vec<u8>)>) -> proto::Digest {
+    let directory = proto::Directory {
+        files: tree
+            .files
+            .iter()
+            .map(|(name, (digest, is_executable))| proto::FileNode {
+                name: name.clone(),
+                digest: Some(digest.clone()),
+                is_executable: *is_executable,
+            })
+            .collect(),
+        directories: tree
+            .directories
+            .iter()
+            .map(|(name, subtree)| proto::DirectoryNode {
+                name: name.clone(),
+                digest: Some(encode_tree(subtree, blobs)),
+            })
+            .collect(),
+    };
+    let data = directory.encode_to_vec();
+    let digest = digest_of(&data);
+    blobs.push((digest.clone(), data));
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_compiles_as_remote_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("include")).unwrap();
+        fs::write(root.join("include/util.h"), "#define ANSWER 42\n").unwrap();
+        fs::write(
+            root.join("main.c"),
+            "#include <stdio.h>\n#include \"util.h\"\nint main(void) { return ANSWER; }\n",
+        )
+        .unwrap();
+        let mut cmd = Command::new("cc");
+        cmd.current_dir(root);
+        assert!(plan(&cmd, root).unwrap().is_none());
+
+        let mut cmd = Command::new("cc");
+        cmd.arg(format!("-I{}", root.join("include").display()))
+            .args(["-c", "main.c", "-o", "build/main.o"])
+            .env("LANG", "C");
+        let Some(planned) = plan(&cmd, root).unwrap() else {
+            // No C compiler to scan the dependencies with.
+            return;
+        };
+        assert_eq!(
+            planned.command.arguments,
+            ["cc", "-Iinclude", "-c", "main.c", "-o", "build/main.o"]
+        );
+        assert_eq!(planned.command.output_paths, ["build/main.o"]);
+        assert_eq!(planned.command.environment_variables[0].name, "LANG");
+        // stdio.h comes from the worker's toolchain.
+        assert_eq!(
+            planned.inputs.keys().collect::<Vec<_>>(),
+            ["include/util.h", "main.c"]
+        );
+
+        let files = BTreeMap::from([
+            ("main.c".to_string(), (digest_of(b"a"), false)),
+            ("include/util.h".to_string(), (digest_of(b"b"), false)),
+        ]);
+        let (digest, blobs) = input_root(&files);
+        assert_eq!(blobs.len(), 2);
+        assert_eq!(blobs.last().unwrap().0, digest);
+        let root_dir = proto::Directory::decode(blobs[1].1.as_slice()).unwrap();
+        assert_eq!(root_dir.files[0].name, "main.c");
+        assert_eq!(root_dir.directories[0].name, "include");
+    }
+
+    #[test]
+    fn parses_make_rules_with_continuations() {
+        let rule = "build/main.o: main.c include/util.h \\\n /usr/include/stdio.h my\\ file.h\n";
+        assert_eq!(
+            parse_make_rule(rule),
+            [
+                "/usr/include/stdio.h",
+                "include/util.h",
+                "main.c",
+                "my file.h"
+            ]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use crate::cross::CrossFile;
+use crate::devenv::DevEnv;
+use crate::error::CrustError;
+use crate::graph::{DependencyGraph, TargetKind, TargetNode};
+use anyhow::{Context, Result};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Runs built executables from the build directory, through the cross file's
+/// `exe_wrapper` when one is configured and with the development environment
+/// applied so uninstalled shared libraries resolve.
+pub struct Runner<'a> {
+    graph: &'a DependencyGraph,
+    builddir: PathBuf,
+    cross: &'a CrossFile,
+    env: DevEnv,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestOutcome {
+    pub name: String,
+    /// Exit code of the test program, or `None` if it was killed by a signal.
+    pub exit: Option<i32>,
+    pub duration: Duration,
+}
+
+impl TestOutcome {
+    pub fn passed(&self) -> bool {
+        self.exit == Some(0)
+    }
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(graph: &'a DependencyGraph, builddir: &Path, cross: &'a CrossFile) -> Result<Self> {
+        let builddir = std::path::absolute(builddir)
+            .with_context(|| format!("Failed to resolve {}", builddir.display()))?;
+        let env = DevEnv::from_graph(graph, &builddir)?;
+        Ok(Runner {
+            graph,
+            builddir,
+            cross,
+            env,
+        })
+    }
+
+    /// Builds the command that runs executable target `name` with `args`.
+    pub fn command(&self, name: &str, args: &[String]) -> Result<Command> {
+        let node = self
+            .graph
+            .get(name)
+            .ok_or_else(|| CrustError::InvalidArgument {
+                message: format!("No target named '{name}'"),
+            })?;
+        if node.kind != TargetKind::Executable {
+            return Err(CrustError::InvalidArgument {
+                message: format!("Target '{name}' is not an executable and cannot be run"),
+            }
+            .into());
+        }
+        let program = self.executable_path(node);
+        if !program.is_file() {
+            return Err(CrustError::InvalidArgument {
+                message: format!(
+                    "{} has not been built; run `crust build` first",
+                    program.display()
+                ),
+            }
+            .into());
+        }
+        let mut cmd = self.cross.exe_command(&program);
+        cmd.args(args);
+        self.env.apply(&mut cmd)?;
+        Ok(cmd)
+    }
+
+    /// Runs every executable marked `test = true` in dependency order and
+    /// reports each outcome; failures do not stop the remaining tests.
+    pub fn run_tests(&self) -> Result<Vec<TestOutcome>> {
+        let mut outcomes = Vec::new();
+        for node in self.graph.topo_order()? {
+            if node.kind != TargetKind::Executable || !node.options.test {
+                continue;
+            }
+            let mut cmd = self.command(&node.name, &[])?;
+            cmd.current_dir(&self.builddir);
+            info!(target_name = %node.name, "Running test {}", node.name);
+            let start = Instant::now();
+            let status = cmd
+                .status()
+                .map_err(|err| spawn_error(cmd.get_program(), err))?;
+            let outcome = TestOutcome {
+                name: node.name.clone(),
+                exit: status.code(),
+                duration: start.elapsed(),
+            };
+            if !outcome.passed() {
+                warn!(target_name = %node.name, exit = ?outcome.exit, "Test {} failed", node.name);
+            }
+            outcomes.push(outcome);
+        }
+        Ok(outcomes)
+    }
+
+    fn executable_path(&self, node: &TargetNode) -> PathBuf {
+        self.builddir.join(
+            node.outputs
+                .first()
+                .map_or(node.name.as_str(), String::as_str),
+        )
+    }
+}
+
+/// Maps a failure to start `program` into a crust error, reporting missing
+/// wrappers and executables as [`CrustError::CompilerMissing`].
+pub fn spawn_error(program: &std::ffi::OsStr, err: io::Error) -> anyhow::Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => CrustError::CompilerMissing {
+            program: program.to_string_lossy().into_owned(),
+        }
+        .into(),
+        _ => anyhow::Error::new(err)
+            .context(format!("Failed to spawn {}", program.to_string_lossy())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CompileOptions, ProjectInfo, ProjectManifest, Target};
+    use crate::cross::Platform;
+    use tempfile::tempdir;
+
+    fn graph() -> DependencyGraph {
+        let manifest = ProjectManifest {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: None,
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            targets: vec![
+                Target::StaticLibrary {
+                    name: "util".into(),
+                    sources: vec!["util.c".into()],
+                    deps: vec![],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: Default::default(),
+                },
+                Target::Executable {
+                    name: "unit".into(),
+                    sources: vec!["unit.c".into()],
+                    deps: vec!["util".into()],
+                    labels: Vec::new(),
+                    owners: Vec::new(),
+                    deprecated: None,
+                    options: CompileOptions {
+                        test: true,
+                        ..Default::default()
+                    },
+                },
+            ],
+            install_data: Vec::new(),
+            install_symlink: Vec::new(),
+            install_emptydir: Vec::new(),
+            package: Default::default(),
+            python: Default::default(),
+            profile: Default::default(),
+            dependencies: Default::default(),
+            deprecated_keys: Vec::new(),
+        };
+        DependencyGraph::from_manifest(&manifest, Platform::Native).unwrap()
+    }
+
+    #[test]
+    fn runs_executables_through_exe_wrapper() {
+        let dir = tempdir().unwrap();
+        let graph = graph();
+        let mut cross = CrossFile::default();
+        cross.binaries.exe_wrapper = vec!["qemu-arm".into(), "-L".into(), "/sysroot".into()];
+        let runner = Runner::new(&graph, dir.path(), &cross).unwrap();
+        assert!(runner.command("unit", &[]).is_err());
+
+        let unit = dir.path().join("unit");
+        std::fs::write(&unit, "").unwrap();
+        let cmd = runner.command("unit", &["--verbose".into()]).unwrap();
+        assert_eq!(cmd.get_program(), "qemu-arm");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "-L".as_ref(),
+                "/sysroot".as_ref(),
+                unit.as_os_str(),
+                "--verbose".as_ref()
+            ]
+        );
+
+        let err = runner.command("util", &[]).unwrap_err();
+        assert!(matches!(
+            CrustError::find(&err),
+            Some(CrustError::InvalidArgument { .. })
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::config::CrateType;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Rust editions rustc accepts for `edition`.
+pub const EDITIONS: &[&str] = &["2015", "2018", "2021", "2024"];
+
+/// Settings of a rust_executable or rust_library target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustSettings {
+    pub edition: String,
+    /// `bin` for rust_executable targets.
+    pub crate_type: CrateType,
+}
+
+impl RustSettings {
+    /// File name rustc writes for the crate `name` into the build directory.
+    /// Libraries use the names C targets expect, so `staticlib` and `cdylib`
+    /// crates link like any other library.
+    pub fn output_name(&self, name: &str) -> String {
+        match self.crate_type {
+            CrateType::Bin => name.to_string(),
+            CrateType::Rlib => format!("lib{}.rlib", crate_name(name)),
+            CrateType::Staticlib => format!("lib{name}.a"),
+            CrateType::Cdylib => format!("lib{name}.so"),
+        }
+    }
+}
+
+/// Crate name rustc derives from a target name: `-` is not allowed in
+/// identifiers, so it becomes `_`.
+pub fn crate_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Why `edition` cannot be used, if it cannot.
+pub fn edition_error(edition: &str) -> Option<String> {
+    (!EDITIONS.contains(&edition)).then(|| {
+        format!(
+            "unknown Rust edition `{edition}`; expected one of {}",
+            EDITIONS.join(", ")
+        )
+    })
+}
+
+/// `rustc` invocation compiling the crate rooted at `root` into `output`.
+/// `rlib` dependencies become `--extern` crates, found with their own
+/// dependencies in `dep_dir`; static and shared libraries built by other
+/// targets are handed to the linker.
+pub fn command(
+    rustc: &str,
+    settings: &RustSettings,
+    name: &str,
+    root: &Path,
+    output: &Path,
+    dep_outputs: &[PathBuf],
+    dep_dir: &Path,
+) -> Command {
+    let crate_type = settings.crate_type;
+    let mut cmd = Command::new(rustc);
+    cmd.arg("--crate-name")
+        .arg(crate_name(name))
+        .arg("--crate-type")
+        .arg(crate_type.name())
+        .arg("--edition")
+        .arg(&settings.edition)
+        .arg("-o")
+        .arg(output)
+        .arg(root);
+    let mut dependency_dir = OsString::from("dependency=");
+    dependency_dir.push(dep_dir);
+    cmd.arg("-L").arg(dependency_dir);
+    for dep in dep_outputs {
+        let file_name = dep.file_name().unwrap_or_default().to_string_lossy();
+        match dep.extension().and_then(|ext| ext.to_str()) {
+            Some("rlib") => {
+                let crate_name = file_name
+                    .strip_prefix("lib")
+                    .and_then(|rest| rest.strip_suffix(".rlib"))
+                    .unwrap_or(&file_name);
+                let mut extern_arg = OsString::from(format!("{crate_name}="));
+                extern_arg.push(dep);
+                cmd.arg("--extern").arg(extern_arg);
+            }
+            Some("a" | "so") if crate_type != CrateType::Rlib => {
+                let mut link_arg = OsString::from("link-arg=");
+                link_arg.push(dep);
+                cmd.arg("-C").arg(link_arg);
+            }
+            _ => {}
+        }
+    }
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wires_dependencies_into_rustc() {
+        let settings = RustSettings {
+            edition: "2021".into(),
+            crate_type: CrateType::Rlib,
+        };
+        assert_eq!(settings.output_name("text-utils"), "libtext_utils.rlib");
+
+        let settings = RustSettings {
+            edition: "2021".into(),
+            crate_type: CrateType::Bin,
+        };
+        assert_eq!(settings.output_name("gen-tables"), "gen-tables");
+        let cmd = command(
+            "rustc",
+            &settings,
+            "gen-tables",
+            Path::new("tools/gen/main.rs"),
+            Path::new("build/gen-tables"),
+            &[
+                PathBuf::from("build/libtext_utils.rlib"),
+                PathBuf::from("build/libz.a"),
+                PathBuf::from("build/version/version.h"),
+            ],
+            Path::new("build"),
+        );
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "--crate-name",
+                "gen_tables",
+                "--crate-type",
+                "bin",
+                "--edition",
+                "2021",
+                "-o",
+                "build/gen-tables",
+                "tools/gen/main.rs",
+                "-L",
+                "dependency=build",
+                "--extern",
+                "text_utils=build/libtext_utils.rlib",
+                "-C",
+                "link-arg=build/libz.a",
+            ]
+        );
+        assert!(edition_error("2021").is_none());
+        assert!(edition_error("2020").is_some());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::error::CrustError;
+use crate::toolchain::{find_program, wrap_command};
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Host directories exposed read-only so compilers, shells, and their runtime
+/// libraries keep working inside the sandbox.
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/opt",
+];
+
+/// Describes what a single sandboxed command may see: declared inputs are
+/// mounted read-only, outputs are writable, and everything else in the project
+/// is hidden. Implemented with bubblewrap (`bwrap`) on Linux.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    readable: Vec<PathBuf>,
+    writable: Vec<PathBuf>,
+    network: bool,
+}
+
+impl Sandbox {
+    /// Verifies that sandboxing is available on this host.
+    pub fn check_available() -> Result<()> {
+        if !cfg!(target_os = "linux") {
+            return Err(CrustError::InvalidArgument {
+                message: "--sandbox is only supported on Linux".into(),
+            }
+            .into());
+        }
+        if find_program("bwrap").is_none() {
+            return Err(CrustError::CompilerMissing {
+                program: "bwrap".into(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn new() -> Self {
+        Sandbox::default()
+    }
+
+    pub fn read_only<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.readable
+            .extend(paths.into_iter().map(|p| p.as_ref().to_path_buf()));
+        self
+    }
+
+    pub fn writable<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.writable
+            .extend(paths.into_iter().map(|p| p.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Lets the command reach the network, which is hidden otherwise.
+    pub fn share_network(mut self, network: bool) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Builds a `bwrap` invocation that runs `cmd` (program, arguments, working
+    /// directory, and environment) inside the sandbox.
+    pub fn wrap(&self, cmd: &Command) -> Result<Command> {
+        Ok(wrap_command("bwrap", self.bwrap_args(cmd)?, cmd))
+    }
+
+    fn bwrap_args(&self, cmd: &Command) -> Result<Vec<OsString>> {
+        let mut args: Vec<OsString> = ["--unshare-all", "--die-with-parent"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        if self.network {
+            args.push("--share-net".into());
+        }
+        for dir in SYSTEM_DIRS.iter().map(Path::new).filter(|d| d.exists()) {
+            mount(&mut args, "--ro-bind", dir);
+        }
+        // Mounted before the declared paths so a project living under /tmp is
+        // not shadowed by the fresh tmpfs.
+        args.extend(["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"].map(OsString::from));
+        for path in &self.readable {
+            let path = absolute(path)?;
+            if path.exists() {
+                mount(&mut args, "--ro-bind", &path);
+            }
+        }
+        for path in &self.writable {
+            let path = absolute(path)?;
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            mount(&mut args, "--bind", &path);
+        }
+
+        if let Some(dir) = cmd.get_current_dir() {
+            let dir = absolute(dir)?;
+            args.extend([
+                "--dir".into(),
+                dir.as_os_str().to_owned(),
+                "--chdir".into(),
+                dir.into_os_string(),
+            ]);
+        }
+        args.push("--".into());
+        Ok(args)
+    }
+}
+
+/// Appends a bwrap mount that exposes `path` at the same location inside.
+fn mount(args: &mut Vec<OsString>, flag: &str, path: &Path) {
+    args.push(flag.into());
+    args.push(path.as_os_str().to_owned());
+    args.push(path.as_os_str().to_owned());
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    std::path::absolute(path).with_context(|| format!("Failed to resolve {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn wraps_command_with_declared_mounts() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("main.c");
+        let out = dir.path().join("build");
+        std::fs::write(&input, "int main(){return 0;}").unwrap();
+
+        let mut cmd = Command::new("cc");
+        cmd.arg("-c").arg(&input).current_dir(dir.path());
+        let sandbox = Sandbox::new().read_only([&input]).writable([&out]);
+        let wrapped = sandbox.wrap(&cmd).unwrap();
+        assert_eq!(wrapped.get_program(), "bwrap");
+        let args: Vec<String> = wrapped
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+
+        let input = input.to_string_lossy().into_owned();
+        let out = out.to_string_lossy().into_owned();
+        let pos = |needle: &str| args.iter().position(|a| a == needle).unwrap();
+        assert_eq!(args[pos(&input) - 1], "--ro-bind");
+        assert_eq!(args[pos(&out) - 1], "--bind");
+        assert_eq!(&args[pos("--") + 1..], ["cc", "-c", input.as_str()]);
+        assert!(!args.iter().any(|arg| arg == "--share-net"));
+
+        let wrapped = sandbox.share_network(true).wrap(&cmd).unwrap();
+        assert!(wrapped.get_args().any(|arg| arg == "--share-net"));
+    }
+}
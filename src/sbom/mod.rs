@@ -0,0 +1,539 @@
+use crate::config::{ProjectInfo, ProjectManifest};
+use crate::cross::CrossFile;
+use crate::cuda;
+use crate::deps::{self, Provider, Resolution};
+use crate::digest::{sha256_bytes, sha256_file};
+use crate::fortran;
+use crate::graph::{DependencyGraph, TargetKind};
+use crate::languages;
+use crate::toolchain::{find_program, program_version};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SbomFormat {
+    /// SPDX 2.3 JSON document
+    Spdx,
+    /// CycloneDX 1.5 JSON document
+    Cyclonedx,
+}
+
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub target: String,
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildTool {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+/// A `[dependencies]` entry as the build resolved it.
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    pub name: String,
+    /// pkg-config module, conan reference, or vcpkg port looked up.
+    pub module: String,
+    pub version: Option<String>,
+    /// The target built in place of the library, for a fallback.
+    pub fallback: Option<String>,
+}
+
+/// Everything recorded in a bill of materials, independent of output format.
+#[derive(Debug, Clone)]
+pub struct SbomInput {
+    pub project: ProjectInfo,
+    pub artifacts: Vec<Artifact>,
+    pub tools: Vec<BuildTool>,
+    pub dependencies: Vec<Dependency>,
+    /// Seconds since the Unix epoch used as the document creation time.
+    pub created: u64,
+}
+
+impl SbomInput {
+    /// Collects the graph's existing outputs under `builddir`, with the
+    /// `tools` that build them and the `resolution` of the manifest's
+    /// dependencies. Outputs that have not been built yet are returned
+    /// separately so the caller can warn about them.
+    pub fn collect(
+        manifest: &ProjectManifest,
+        graph: &DependencyGraph,
+        builddir: &Path,
+        tools: Vec<BuildTool>,
+        resolution: &Resolution,
+    ) -> Result<(Self, Vec<PathBuf>)> {
+        let mut artifacts = Vec::new();
+        let mut missing = Vec::new();
+        for node in graph.topo_order()? {
+            for output in &node.outputs {
+                let path = builddir.join(output);
+                if path.is_file() {
+                    artifacts.push(Artifact {
+                        target: node.name.clone(),
+                        sha256: sha256_file(&path)?,
+                        path: PathBuf::from(output),
+                    });
+                } else {
+                    missing.push(path);
+                }
+            }
+        }
+
+        let dependencies = resolution
+            .found
+            .iter()
+            .map(|(name, found)| Dependency {
+                name: name.clone(),
+                module: manifest
+                    .dependencies
+                    .get(name)
+                    .map_or(name.as_str(), |spec| deps::module(name, spec))
+                    .to_string(),
+                version: found.version.clone(),
+                fallback: match &found.provider {
+                    Provider::System => None,
+                    Provider::Fallback(target) => Some(target.clone()),
+                },
+            })
+            .collect();
+
+        let input = SbomInput {
+            project: manifest.project.clone(),
+            artifacts,
+            tools,
+            dependencies,
+            created: creation_time(),
+        };
+        Ok((input, missing))
+    }
+
+    pub fn render(&self, format: SbomFormat) -> Value {
+        match format {
+            SbomFormat::Spdx => self.spdx(),
+            SbomFormat::Cyclonedx => self.cyclonedx(),
+        }
+    }
+
+    fn version(&self) -> &str {
+        self.project.version.as_deref().unwrap_or("NOASSERTION")
+    }
+
+    fn spdx(&self) -> Value {
+        let package_id = format!("SPDXRef-Package-{}", spdx_id(&self.project.name));
+        let mut packages = vec![json!({
+            "name": self.project.name,
+            "SPDXID": package_id,
+            "versionInfo": self.version(),
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "primaryPackagePurpose": "APPLICATION",
+        })];
+        let mut relationships = vec![json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": package_id,
+        })];
+
+        let files: Vec<Value> = self
+            .artifacts
+            .iter()
+            .map(|artifact| {
+                let file_id = format!("SPDXRef-File-{}", spdx_id(&artifact.path.to_string_lossy()));
+                relationships.push(json!({
+                    "spdxElementId": package_id,
+                    "relationshipType": "CONTAINS",
+                    "relatedSpdxElement": file_id,
+                }));
+                json!({
+                    "fileName": format!("./{}", artifact.path.display()),
+                    "SPDXID": file_id,
+                    "checksums": [{ "algorithm": "SHA256", "checksumValue": artifact.sha256 }],
+                    "comment": format!("Produced by target '{}'", artifact.target),
+                })
+            })
+            .collect();
+
+        for tool in &self.tools {
+            let tool_id = format!("SPDXRef-Tool-{}", spdx_id(&tool.name));
+            packages.push(json!({
+                "name": tool.name,
+                "SPDXID": tool_id,
+                "versionInfo": tool.version.as_deref().unwrap_or("NOASSERTION"),
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "primaryPackagePurpose": "APPLICATION",
+            }));
+            relationships.push(json!({
+                "spdxElementId": tool_id,
+                "relationshipType": "BUILD_TOOL_OF",
+                "relatedSpdxElement": package_id,
+            }));
+        }
+
+        for dependency in &self.dependencies {
+            let dependency_id = format!("SPDXRef-Dependency-{}", spdx_id(&dependency.name));
+            let comment = match &dependency.fallback {
+                Some(target) => format!("Built from target '{target}'"),
+                None => format!("System library '{}'", dependency.module),
+            };
+            packages.push(json!({
+                "name": dependency.module,
+                "SPDXID": dependency_id,
+                "versionInfo": dependency.version.as_deref().unwrap_or("NOASSERTION"),
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "primaryPackagePurpose": "LIBRARY",
+                "comment": comment,
+            }));
+            relationships.push(json!({
+                "spdxElementId": package_id,
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": dependency_id,
+            }));
+        }
+
+        let namespace_seed = format!("{}-{}-{}", self.project.name, self.version(), self.created);
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": format!("{}-{}", self.project.name, self.version()),
+            "documentNamespace": format!(
+                "https://spdx.org/spdxdocs/{}-{}",
+                spdx_id(&self.project.name),
+                sha256_bytes(namespace_seed.as_bytes())
+            ),
+            "creationInfo": {
+                "created": rfc3339(self.created),
+                "creators": [format!("Tool: crust-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "files": files,
+            "relationships": relationships,
+        })
+    }
+
+    fn cyclonedx(&self) -> Value {
+        let project_ref = format!("project:{}", self.project.name);
+        let mut components: Vec<Value> = self
+            .artifacts
+            .iter()
+            .map(|artifact| {
+                json!({
+                    "type": "file",
+                    "bom-ref": format!("artifact:{}", artifact.path.display()),
+                    "name": artifact.path.to_string_lossy(),
+                    "hashes": [{ "alg": "SHA-256", "content": artifact.sha256 }],
+                    "properties": [{ "name": "crust:target", "value": artifact.target }],
+                })
+            })
+            .collect();
+        let mut depends_on: Vec<Value> = components.iter().map(|c| c["bom-ref"].clone()).collect();
+
+        for dependency in &self.dependencies {
+            let provider = match &dependency.fallback {
+                Some(target) => format!("fallback:{target}"),
+                None => "system".to_string(),
+            };
+            let bom_ref = format!("dependency:{}", dependency.name);
+            components.push(json!({
+                "type": "library",
+                "bom-ref": bom_ref,
+                "name": dependency.module,
+                "version": dependency.version.as_deref().unwrap_or("unknown"),
+                "properties": [
+                    { "name": "crust:dependency", "value": dependency.name },
+                    { "name": "crust:provider", "value": provider },
+                ],
+            }));
+            depends_on.push(json!(bom_ref));
+        }
+
+        components.extend(self.tools.iter().map(|tool| {
+            json!({
+                "type": "application",
+                "bom-ref": format!("tool:{}", tool.name),
+                "name": tool.name,
+                "version": tool.version.as_deref().unwrap_or("unknown"),
+                "scope": "excluded",
+                "properties": [{ "name": "crust:role", "value": "build-tool" }],
+            })
+        }));
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": rfc3339(self.created),
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": "crust",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {
+                    "type": "application",
+                    "bom-ref": project_ref,
+                    "name": self.project.name,
+                    "version": self.version(),
+                },
+            },
+            "components": components,
+            "dependencies": [{ "ref": project_ref, "dependsOn": depends_on }],
+        })
+    }
+}
+
+/// The tools that build `graph` as `cross` names them: the C compiler and
+/// archiver of each architecture, `lipo`, `rustc`, `nvcc`, the Fortran
+/// compiler, and the compilers of registered languages, for the targets
+/// using them. The programs custom commands declare follow, where they were
+/// found, `--program-path` overrides included.
+pub fn build_tools(
+    graph: &DependencyGraph,
+    cross: &CrossFile,
+    programs: &HashMap<String, PathBuf>,
+) -> Vec<BuildTool> {
+    let mut used = BTreeSet::new();
+    for node in graph.nodes() {
+        let arch = node.arch.as_deref();
+        match node.kind {
+            _ if node.combines_architectures() => {
+                used.insert(cross.lipo().to_string());
+                continue;
+            }
+            TargetKind::Executable | TargetKind::SharedLibrary | TargetKind::PythonExtension => {
+                used.insert(cross.compiler_for(arch).0.to_string());
+            }
+            TargetKind::StaticLibrary => {
+                used.insert(cross.compiler_for(arch).0.to_string());
+                used.insert(cross.archiver_for(arch).to_string());
+            }
+            TargetKind::RustExecutable | TargetKind::RustLibrary => {
+                used.insert(cross.rustc().to_string());
+            }
+            _ => {}
+        }
+        for source in node.sources.iter().map(Path::new) {
+            if let Some(language) = languages::for_source(source) {
+                used.insert(language.resolve(cross.language_compiler(language.name)).0);
+            } else if fortran::is_fortran(source) {
+                used.insert(cross.fc().to_string());
+            } else if cuda::is_cuda(source) {
+                used.insert(cross.nvcc().to_string());
+            }
+        }
+    }
+
+    let mut tools: Vec<BuildTool> = used
+        .into_iter()
+        .map(|name| BuildTool {
+            version: find_program(&name).as_deref().and_then(program_version),
+            name,
+        })
+        .collect();
+    let mut programs: Vec<_> = programs.iter().collect();
+    programs.sort();
+    tools.extend(programs.into_iter().map(|(name, path)| BuildTool {
+        name: name.clone(),
+        version: program_version(path),
+    }));
+    tools
+}
+
+/// Honors `SOURCE_DATE_EPOCH` so documents can be reproduced bit-for-bit.
+fn creation_time() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        })
+}
+
+/// SPDX identifiers may only contain letters, digits, `.` and `-`.
+fn spdx_id(raw: &str) -> String {
+    raw.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Formats seconds since the epoch as an RFC 3339 UTC timestamp.
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3_600,
+        (rem % 3_600) / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SbomInput {
+        SbomInput {
+            project: ProjectInfo {
+                name: "demo".into(),
+                version: Some("1.2.0".into()),
+                crust_version: None,
+                c_args: Vec::new(),
+                subdirs: Vec::new(),
+                passthrough_env: Vec::new(),
+                launchers: Default::default(),
+            },
+            artifacts: vec![Artifact {
+                target: "app".into(),
+                path: PathBuf::from("app"),
+                sha256: "ab".repeat(32),
+            }],
+            tools: vec![BuildTool {
+                name: "cc".into(),
+                version: Some("cc 12.2.0".into()),
+            }],
+            dependencies: vec![
+                Dependency {
+                    name: "zlib".into(),
+                    module: "zlib".into(),
+                    version: Some("1.3.1".into()),
+                    fallback: None,
+                },
+                Dependency {
+                    name: "json".into(),
+                    module: "nlohmann_json".into(),
+                    version: None,
+                    fallback: Some("json_vendored".into()),
+                },
+            ],
+            created: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn renders_spdx_with_checksums_and_build_tools() {
+        let doc = sample().render(SbomFormat::Spdx);
+        assert_eq!(doc["spdxVersion"], "SPDX-2.3");
+        assert_eq!(doc["creationInfo"]["created"], "2023-11-14T22:13:20Z");
+        assert_eq!(
+            doc["files"][0]["checksums"][0]["checksumValue"],
+            "ab".repeat(32)
+        );
+        let relationships = doc["relationships"].as_array().unwrap();
+        assert!(relationships
+            .iter()
+            .any(|r| r["relationshipType"] == "BUILD_TOOL_OF"));
+
+        let zlib = &doc["packages"][2];
+        assert_eq!(
+            (&zlib["name"], &zlib["versionInfo"], &zlib["SPDXID"]),
+            (
+                &json!("zlib"),
+                &json!("1.3.1"),
+                &json!("SPDXRef-Dependency-zlib")
+            )
+        );
+        assert_eq!(
+            doc["packages"][3]["comment"],
+            "Built from target 'json_vendored'"
+        );
+        assert!(relationships
+            .iter()
+            .any(|r| r["relationshipType"] == "DEPENDS_ON"
+                && r["relatedSpdxElement"] == "SPDXRef-Dependency-zlib"));
+    }
+
+    #[test]
+    fn renders_cyclonedx_components() {
+        let doc = sample().render(SbomFormat::Cyclonedx);
+        assert_eq!(doc["bomFormat"], "CycloneDX");
+        assert_eq!(doc["metadata"]["component"]["version"], "1.2.0");
+        assert_eq!(doc["components"].as_array().unwrap().len(), 4);
+        let json = &doc["components"][2];
+        assert_eq!(
+            (&json["type"], &json["name"], &json["version"]),
+            (
+                &json!("library"),
+                &json!("nlohmann_json"),
+                &json!("unknown")
+            )
+        );
+        assert_eq!(json["properties"][1]["value"], "fallback:json_vendored");
+        assert_eq!(
+            doc["dependencies"][0]["dependsOn"],
+            json!(["artifact:app", "dependency:zlib", "dependency:json"])
+        );
+    }
+
+    #[test]
+    fn records_the_tools_the_cross_file_configures() {
+        let dir = tempfile::tempdir().unwrap();
+        let cross_path = dir.path().join("aarch64.toml");
+        std::fs::write(
+            &cross_path,
+            "[binaries]\ncc = \"aarch64-linux-gnu-gcc\"\nar = \"aarch64-linux-gnu-ar\"\n",
+        )
+        .unwrap();
+        let cross = CrossFile::load(&cross_path).unwrap();
+        let manifest = ProjectManifest::parse(
+            r#"
+[project]
+name = "demo"
+
+[[targets]]
+type = "static_library"
+name = "core"
+sources = ["core.c"]
+
+[[targets]]
+type = "executable"
+name = "app"
+sources = ["main.c"]
+deps = ["core"]
+"#,
+            Path::new("crust.build"),
+        )
+        .unwrap();
+        let graph = DependencyGraph::from_manifest(&manifest, cross.platform()).unwrap();
+        let programs = HashMap::from([("protoc".to_string(), PathBuf::from("/opt/bin/protoc"))]);
+
+        let names: Vec<String> = build_tools(&graph, &cross, &programs)
+            .into_iter()
+            .map(|tool| tool.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["aarch64-linux-gnu-ar", "aarch64-linux-gnu-gcc", "protoc"]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::error::CrustError;
+use anyhow::Result;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config file of a repository, relative to the manifest directory.
+pub const REPO_CONFIG: &str = ".crust/config.toml";
+
+/// Option keys crust still reads under an old name, with the key replacing
+/// each.
+pub const DEPRECATED_OPTIONS: &[(&str, &str)] = &[("colors", "color")];
+
+/// Defaults for command-line options, read from the user's and the
+/// repository's config files. Enum values are spelled as on the command
+/// line and checked when applied.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    pub backend: Option<String>,
+    pub jobs: Option<usize>,
+    pub load_average: Option<f64>,
+    pub max_link_jobs: Option<usize>,
+    pub max_custom_jobs: Option<usize>,
+    pub summary: Option<String>,
+    pub fail_fast: Option<bool>,
+    pub watchdog: Option<u64>,
+    pub hermetic: Option<bool>,
+    pub log_level: Option<String>,
+    pub log_format: Option<String>,
+    #[serde(alias = "colors")]
+    pub color: Option<String>,
+    pub launcher: Option<String>,
+    pub cache_url: Option<String>,
+}
+
+impl Defaults {
+    /// The user's defaults overridden by those of the repository whose
+    /// manifest lives in `manifest_dir`.
+    pub fn load(manifest_dir: &Path) -> Result<Self> {
+        let user = match user_config() {
+            Some(path) => Self::load_file(&path)?,
+            None => Defaults::default(),
+        };
+        let repo = Self::load_file(&manifest_dir.join(REPO_CONFIG))?;
+        Ok(repo.or(user))
+    }
+
+    /// The config files [`Defaults::load`] reads, whether they exist or not.
+    pub fn files(manifest_dir: &Path) -> Vec<PathBuf> {
+        user_config()
+            .into_iter()
+            .chain([manifest_dir.join(REPO_CONFIG)])
+            .collect()
+    }
+
+    /// The defaults in `path`; none when it does not exist.
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Defaults::default())
+            }
+            Err(source) => {
+                return Err(CrustError::ManifestRead {
+                    path: path.to_path_buf(),
+                    source,
+                }
+                .into())
+            }
+        };
+        toml::from_str(&content).map_err(|err| {
+            CrustError::ManifestParse {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// These defaults, falling back to `lower` for the options they leave
+    /// unset.
+    pub fn or(self, lower: Defaults) -> Self {
+        Defaults {
+            backend: self.backend.or(lower.backend),
+            jobs: self.jobs.or(lower.jobs),
+            load_average: self.load_average.or(lower.load_average),
+            max_link_jobs: self.max_link_jobs.or(lower.max_link_jobs),
+            max_custom_jobs: self.max_custom_jobs.or(lower.max_custom_jobs),
+            summary: self.summary.or(lower.summary),
+            fail_fast: self.fail_fast.or(lower.fail_fast),
+            watchdog: self.watchdog.or(lower.watchdog),
+            hermetic: self.hermetic.or(lower.hermetic),
+            log_level: self.log_level.or(lower.log_level),
+            log_format: self.log_format.or(lower.log_format),
+            color: self.color.or(lower.color),
+            launcher: self.launcher.or(lower.launcher),
+            cache_url: self.cache_url.or(lower.cache_url),
+        }
+    }
+}
+
+/// `crust/config.toml` in the user's config directory: `XDG_CONFIG_HOME`,
+/// `~/.config`, or `%APPDATA%` on Windows.
+fn user_config() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(dir.join("crust").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repository_defaults_override_the_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("user.toml");
+        fs::write(&user, "backend = \"ninja\"\njobs = 4\nfail_fast = true\n").unwrap();
+        let repo = dir.path().join("repo.toml");
+        fs::write(&repo, "jobs = 16\nsummary = \"full\"\n").unwrap();
+
+        let merged = Defaults::load_file(&repo)
+            .unwrap()
+            .or(Defaults::load_file(&user).unwrap());
+        assert_eq!(merged.backend.as_deref(), Some("ninja"));
+        assert_eq!(merged.jobs, Some(16));
+        assert_eq!(merged.summary.as_deref(), Some("full"));
+        assert_eq!(merged.fail_fast, Some(true));
+        assert_eq!(merged.hermetic, None);
+
+        assert_eq!(
+            Defaults::load_file(&dir.path().join("missing.toml")).unwrap(),
+            Defaults::default()
+        );
+        fs::write(
+            &repo,
+            "launcher = \"ccache\"\ncolor = \"never\"\ncache_url = \"grpc://cache:8980\"\n",
+        )
+        .unwrap();
+        let repo = Defaults::load_file(&repo).unwrap();
+        assert_eq!(repo.launcher.as_deref(), Some("ccache"));
+        assert_eq!(repo.color.as_deref(), Some("never"));
+        assert_eq!(repo.cache_url.as_deref(), Some("grpc://cache:8980"));
+
+        fs::write(&user, "launchr = \"ccache\"\n").unwrap();
+        let err = Defaults::load_file(&user).unwrap_err().to_string();
+        assert!(err.contains("unknown field `launchr`"), "{err}");
+    }
+}
@@ -0,0 +1,392 @@
+use crate::compat::Version;
+use crate::cross::CrossFile;
+use crate::error::CrustError;
+use crate::graph::DependencyGraph;
+use crate::programs;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Which standard setting applies to a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    C,
+    Cpp,
+}
+
+impl Lang {
+    /// The language of a C-family source, `None` for other languages.
+    /// Objective-C follows `c_std` and Objective-C++ `cpp_std`.
+    pub fn of(path: &Path) -> Option<Lang> {
+        match path.extension().and_then(|e| e.to_str())? {
+            "c" | "m" => Some(Lang::C),
+            "cc" | "cpp" | "cxx" | "c++" | "C" | "mm" => Some(Lang::Cpp),
+            _ => None,
+        }
+    }
+
+    pub fn field(self) -> &'static str {
+        match self {
+            Lang::C => "c_std",
+            Lang::Cpp => "cpp_std",
+        }
+    }
+}
+
+/// A standard crust knows: its name in the manifest, the draft name older
+/// compilers accept, and the first GCC and Clang releases accepting each
+/// spelling (`(draft, final)` major versions).
+struct Standard {
+    lang: Lang,
+    name: &'static str,
+    draft: &'static str,
+    gcc: (u64, u64),
+    clang: (u64, u64),
+    /// MSVC `/std:` value and the first `cl` version accepting it, when
+    /// MSVC has one.
+    msvc: Option<(&'static str, Version)>,
+}
+
+const fn standard(
+    lang: Lang,
+    name: &'static str,
+    draft: &'static str,
+    gcc: (u64, u64),
+    clang: (u64, u64),
+    msvc: Option<(&'static str, Version)>,
+) -> Standard {
+    Standard {
+        lang,
+        name,
+        draft,
+        gcc,
+        clang,
+        msvc,
+    }
+}
+
+const MSVC_C11: Version = Version::new(19, 28, 0);
+const MSVC_CPP14: Version = Version::new(19, 0, 0);
+const MSVC_CPP17: Version = Version::new(19, 11, 0);
+const MSVC_CPP20: Version = Version::new(19, 29, 0);
+
+const STANDARDS: &[Standard] = &[
+    standard(Lang::C, "c89", "c89", (0, 0), (0, 0), None),
+    standard(Lang::C, "c90", "c90", (0, 0), (0, 0), None),
+    standard(Lang::C, "c99", "c99", (0, 0), (0, 0), None),
+    standard(
+        Lang::C,
+        "c11",
+        "c1x",
+        (4, 7),
+        (3, 1),
+        Some(("c11", MSVC_C11)),
+    ),
+    standard(
+        Lang::C,
+        "c17",
+        "c17",
+        (8, 8),
+        (6, 6),
+        Some(("c17", MSVC_C11)),
+    ),
+    standard(
+        Lang::C,
+        "c18",
+        "c18",
+        (8, 8),
+        (6, 6),
+        Some(("c17", MSVC_C11)),
+    ),
+    standard(
+        Lang::C,
+        "c23",
+        "c2x",
+        (9, 14),
+        (9, 18),
+        Some(("clatest", Version::new(19, 39, 0))),
+    ),
+    standard(Lang::Cpp, "c++98", "c++98", (0, 0), (0, 0), None),
+    standard(Lang::Cpp, "c++03", "c++03", (0, 0), (0, 0), None),
+    standard(Lang::Cpp, "c++11", "c++0x", (4, 7), (3, 0), None),
+    standard(
+        Lang::Cpp,
+        "c++14",
+        "c++1y",
+        (4, 9),
+        (3, 5),
+        Some(("c++14", MSVC_CPP14)),
+    ),
+    standard(
+        Lang::Cpp,
+        "c++17",
+        "c++1z",
+        (5, 7),
+        (3, 5),
+        Some(("c++17", MSVC_CPP17)),
+    ),
+    standard(
+        Lang::Cpp,
+        "c++20",
+        "c++2a",
+        (8, 10),
+        (6, 10),
+        Some(("c++20", MSVC_CPP20)),
+    ),
+    standard(
+        Lang::Cpp,
+        "c++23",
+        "c++2b",
+        (11, 11),
+        (12, 17),
+        Some(("c++latest", MSVC_CPP20)),
+    ),
+    standard(
+        Lang::Cpp,
+        "c++26",
+        "c++2c",
+        (14, 14),
+        (17, 17),
+        Some(("c++latest", MSVC_CPP20)),
+    ),
+];
+
+/// Looks `name` up for `lang`. GNU dialects (`gnu11`, `gnu++20`) share the
+/// entry of the ISO standard; the flag spells them with `gnu` again.
+fn lookup(lang: Lang, name: &str) -> Option<(&'static Standard, bool)> {
+    let (iso, gnu) = match name.strip_prefix("gnu") {
+        Some(rest) => (format!("c{rest}"), true),
+        None => (name.to_string(), false),
+    };
+    STANDARDS
+        .iter()
+        .find(|standard| standard.lang == lang && standard.name == iso)
+        .map(|standard| (standard, gnu))
+}
+
+/// Why `name` is not a `c_std` or `cpp_std` value, if it is not.
+pub fn name_error(lang: Lang, name: &str) -> Option<String> {
+    lookup(lang, name).is_none().then(|| {
+        let known: Vec<&str> = STANDARDS
+            .iter()
+            .filter(|standard| standard.lang == lang)
+            .map(|standard| standard.name)
+            .collect();
+        format!(
+            "unknown {} `{name}`; expected one of {} (or a gnu dialect)",
+            lang.field(),
+            known.join(", ")
+        )
+    })
+}
+
+/// Compiler families that spell standards differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Gcc,
+    Clang,
+    Msvc,
+    /// A GCC-compatible driver crust does not know the history of, such as
+    /// emcc; standards are spelled `-std=` and not checked.
+    Other,
+}
+
+/// The C compiler a target is built with, as far as standards go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compiler {
+    pub family: Family,
+    pub version: Option<Version>,
+}
+
+impl Compiler {
+    /// Identifies `program` (run with the leading `args` of drivers such as
+    /// `zig cc`) from its name and `--version` banner. A compiler that
+    /// cannot be run counts as `Other`; building reports it missing.
+    pub fn detect(program: &str, args: &[String]) -> Compiler {
+        let stem = Path::new(program)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(program)
+            .to_ascii_lowercase();
+        let banner = Command::new(program)
+            .args(args)
+            .arg("--version")
+            .output()
+            .ok()
+            .map(|output| {
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+                text
+            })
+            .unwrap_or_default();
+        Compiler::from_banner(&stem, &banner)
+    }
+
+    fn from_banner(stem: &str, banner: &str) -> Compiler {
+        let first = banner
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or("");
+        let family = if stem == "cl" || first.contains("Microsoft") {
+            Family::Msvc
+        } else if first.contains("Emscripten") || first.is_empty() {
+            Family::Other
+        } else if first.contains("clang") {
+            Family::Clang
+        } else if stem.contains("gcc")
+            || stem == "cc"
+            || first.contains("GCC")
+            || first.contains("gcc")
+        {
+            Family::Gcc
+        } else {
+            Family::Other
+        };
+        Compiler {
+            family,
+            version: programs::version_in(first),
+        }
+    }
+
+    /// Flag selecting `name`, spelled as this compiler wants it, or why
+    /// this compiler cannot build for it.
+    pub fn flag(&self, lang: Lang, name: &str) -> Result<String, String> {
+        let (standard, gnu) =
+            lookup(lang, name).ok_or_else(|| name_error(lang, name).unwrap_or_default())?;
+        let spell = |spelling: &str| {
+            if gnu {
+                format!("-std=gnu{}", &spelling[1..])
+            } else {
+                format!("-std={spelling}")
+            }
+        };
+        let (releases, family) = match self.family {
+            Family::Msvc => {
+                let Some((value, since)) = standard.msvc.filter(|_| !gnu) else {
+                    return Err(format!("MSVC has no /std: option for {name}"));
+                };
+                return match self.version {
+                    Some(version) if version < since => Err(format!(
+                        "{name} needs MSVC {since} or newer, but cl is {version}"
+                    )),
+                    _ => Ok(format!("/std:{value}")),
+                };
+            }
+            Family::Other => return Ok(spell(standard.name)),
+            Family::Gcc => (standard.gcc, "GCC"),
+            Family::Clang => (standard.clang, "Clang"),
+        };
+        let Some(major) = self.version.map(|version| version.major) else {
+            return Ok(spell(standard.name));
+        };
+        let (draft, final_release) = releases;
+        if major >= final_release {
+            Ok(spell(standard.name))
+        } else if major >= draft {
+            Ok(spell(standard.draft))
+        } else {
+            Err(format!(
+                "{name} needs {family} {draft} or newer, but the compiler is {family} {}",
+                self.version.expect("compiler version")
+            ))
+        }
+    }
+}
+
+/// `c_std` and `cpp_std` of one target as flags for its compiler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StdFlags {
+    pub c: Option<String>,
+    pub cpp: Option<String>,
+}
+
+impl StdFlags {
+    /// The flag for compiling `source`, if its language has one.
+    pub fn for_source(&self, source: &Path) -> Option<&str> {
+        match Lang::of(source)? {
+            Lang::C => self.c.as_deref(),
+            Lang::Cpp => self.cpp.as_deref(),
+        }
+    }
+}
+
+/// Spells the `c_std` and `cpp_std` of every target in `graph` for the
+/// compiler that builds it, failing for a target whose compiler cannot
+/// build for its standard. Each compiler is asked its version once.
+pub fn check(graph: &DependencyGraph, cross: &CrossFile) -> Result<HashMap<String, StdFlags>> {
+    let mut compilers: HashMap<(String, Vec<String>), Compiler> = HashMap::new();
+    let mut flags = HashMap::new();
+    for node in graph.nodes() {
+        let options = &node.options;
+        if options.c_std.is_none() && options.cpp_std.is_none() {
+            continue;
+        }
+        let (program, args) = cross.compiler_for(node.arch.as_deref());
+        // `-arch` only picks the slice, not the compiler.
+        let args: Vec<String> = args.into_iter().take_while(|arg| arg != "-arch").collect();
+        let compiler = compilers
+            .entry((program.to_string(), args))
+            .or_insert_with_key(|(program, args)| Compiler::detect(program, args));
+        let spell = |lang: Lang, name: &Option<String>| {
+            name.as_deref()
+                .map(|name| compiler.flag(lang, name))
+                .transpose()
+                .map_err(|message| CrustError::InvalidTarget {
+                    target: node.name.clone(),
+                    message: format!(
+                        "{} = \"{}\": {message}",
+                        lang.field(),
+                        name.as_deref().unwrap_or("")
+                    ),
+                })
+        };
+        let target_flags = StdFlags {
+            c: spell(Lang::C, &options.c_std)?,
+            cpp: spell(Lang::Cpp, &options.cpp_std)?,
+        };
+        flags.insert(node.name.clone(), target_flags);
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spells_standards_for_each_compiler() {
+        let gcc = Compiler::from_banner("cc", "gcc (Debian 12.2.0-14) 12.2.0\nCopyright (C)\n");
+        assert_eq!(gcc.family, Family::Gcc);
+        assert_eq!(gcc.flag(Lang::Cpp, "c++20"), Ok("-std=c++20".into()));
+        assert_eq!(gcc.flag(Lang::C, "c23"), Ok("-std=c2x".into()));
+        assert_eq!(gcc.flag(Lang::C, "gnu23"), Ok("-std=gnu2x".into()));
+        assert_eq!(
+            gcc.flag(Lang::Cpp, "c++26").unwrap_err(),
+            "c++26 needs GCC 14 or newer, but the compiler is GCC 12.2.0"
+        );
+
+        let clang = Compiler::from_banner("clang", "Ubuntu clang version 14.0.0-1ubuntu1\n");
+        assert_eq!(clang.flag(Lang::Cpp, "gnu++23"), Ok("-std=gnu++2b".into()));
+
+        let msvc = Compiler::from_banner(
+            "cl",
+            "Microsoft (R) C/C++ Optimizing Compiler Version 19.38.33133 for x64\n",
+        );
+        assert_eq!(msvc.family, Family::Msvc);
+        assert_eq!(msvc.flag(Lang::Cpp, "c++20"), Ok("/std:c++20".into()));
+        assert_eq!(msvc.flag(Lang::Cpp, "c++23"), Ok("/std:c++latest".into()));
+        assert!(msvc.flag(Lang::C, "gnu11").is_err());
+        assert!(msvc.flag(Lang::C, "c23").is_err());
+
+        assert!(name_error(Lang::Cpp, "c++20").is_none());
+        assert!(name_error(Lang::C, "c++20").is_some());
+        let flags = StdFlags {
+            c: Some("-std=c11".into()),
+            cpp: None,
+        };
+        assert_eq!(flags.for_source(Path::new("src/main.c")), Some("-std=c11"));
+        assert_eq!(flags.for_source(Path::new("src/app.cpp")), None);
+    }
+}
@@ -0,0 +1,484 @@
+use crate::daemon::{self, Changes, Token};
+use crate::digest::{sha256_bytes, sha256_file, tree_digest};
+use crate::outdated::Reason;
+use crate::paths;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File in the build directory holding the incremental build state.
+pub const STATE_FILE: &str = ".crust_state";
+
+/// Version of the state file layout; a file with another version is ignored.
+const STATE_VERSION: u32 = 2;
+
+/// What an input looked like when an edge last ran. The modification time
+/// and size only decide whether the content has to be hashed again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    mtime_ns: u128,
+    size: u64,
+    /// SHA-256 of the content, the tree digest of a directory, or `-` for a
+    /// missing file.
+    sha256: String,
+}
+
+/// One edge: a command turning inputs into outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EdgeRecord {
+    /// SHA-256 of the full command line.
+    command: String,
+    /// Keyed by [`paths::identity`], so a source tree reached through a
+    /// symlink or `..` matches what was recorded through another path.
+    inputs: BTreeMap<PathBuf, FileStamp>,
+    outputs: Vec<PathBuf>,
+}
+
+/// An input as last seen: what its path resolved to, and its stamp.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KnownFile {
+    identity: PathBuf,
+    stamp: FileStamp,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    /// Edges keyed by their first output.
+    edges: BTreeMap<PathBuf, EdgeRecord>,
+    /// Inputs by absolute path as they were at `watch`, a token of the
+    /// `crust daemon` watching the project.
+    #[serde(default)]
+    files: BTreeMap<PathBuf, KnownFile>,
+    #[serde(default)]
+    watch: Option<Token>,
+}
+
+/// Per-edge record of input hashes, command hash, and outputs from the last
+/// build in a build directory, like `.ninja_log` and `.ninja_deps`. The
+/// native backend uses it to decide rebuilds by content rather than by
+/// modification time.
+#[derive(Debug)]
+pub struct StateDb {
+    path: PathBuf,
+    edges: Mutex<BTreeMap<PathBuf, EdgeRecord>>,
+    /// Stamps taken during this build, so a file shared by many edges is
+    /// hashed once.
+    stamps: Mutex<HashMap<PathBuf, FileStamp>>,
+    /// Inputs as the last build saw them, and as this one does.
+    files: BTreeMap<PathBuf, KnownFile>,
+    seen: Mutex<HashMap<PathBuf, KnownFile>>,
+    /// The daemon's token for this build, and what changed since the last
+    /// one when the daemon knows.
+    watch: Option<Token>,
+    changes: Option<Changes>,
+    /// Modification time and size of the file as the last save left it.
+    written: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl StateDb {
+    /// Loads the state of `out_dir`. A missing, unreadable, or outdated file
+    /// gives an empty state, which makes every edge fall back to timestamps.
+    /// When a `crust daemon` watches the project, inputs it saw no change to
+    /// since the last build are not looked at again.
+    pub fn load(out_dir: &Path) -> Self {
+        let path = out_dir.join(STATE_FILE);
+        let file = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<StateFile>(&text).ok())
+            .filter(|file| file.version == STATE_VERSION)
+            .unwrap_or_default();
+        let (watch, changes) = watch(out_dir, file.watch.as_ref());
+        StateDb {
+            path,
+            edges: Mutex::new(file.edges),
+            stamps: Mutex::default(),
+            files: file.files,
+            seen: Mutex::default(),
+            watch,
+            changes,
+            written: Mutex::default(),
+        }
+    }
+
+    /// The state for the next build of a process that keeps it between
+    /// builds, such as `crust daemon`: what the last save wrote, without
+    /// reading the file back. It is loaded instead when another crust wrote
+    /// it since, or when `out_dir` is another build directory.
+    pub fn reload(&self, out_dir: &Path) -> Self {
+        let path = out_dir.join(STATE_FILE);
+        let written = *self.written.lock().expect("state mutex poisoned");
+        if path != self.path || written.is_none() || written != version(&path) {
+            return StateDb::load(out_dir);
+        }
+        let (watch, changes) = watch(out_dir, self.watch.as_ref());
+        StateDb {
+            path,
+            edges: Mutex::new(self.edges.lock().expect("state mutex poisoned").clone()),
+            stamps: Mutex::default(),
+            files: self.known_files(),
+            seen: Mutex::default(),
+            watch,
+            changes,
+            written: Mutex::new(written),
+        }
+    }
+
+    /// Whether the edge producing `outputs` is stale: `Some(Some(reason))`
+    /// when its command line, inputs, or outputs differ from the last
+    /// recorded run, `Some(None)` when they match, and `None` when the edge
+    /// was never recorded.
+    pub fn stale_reason(
+        &self,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        command: &str,
+    ) -> Option<Option<Reason>> {
+        let record = self
+            .edges
+            .lock()
+            .expect("state mutex poisoned")
+            .get(outputs.first()?)?
+            .clone();
+        if record.command != sha256_bytes(command.as_bytes()) {
+            return Some(Some(Reason::ChangedCommand));
+        }
+        if record.outputs != outputs {
+            return Some(Some(Reason::ChangedOutputs));
+        }
+        if record.inputs.len() != inputs.len() {
+            return Some(Some(Reason::ChangedInputs));
+        }
+        let changed = inputs.par_iter().find_map_first(|input| {
+            let (identity, stamp) = self.current(input, Some(&record));
+            match record.inputs.get(&identity) {
+                Some(previous) if previous.sha256 == stamp.sha256 => None,
+                Some(_) => Some(Reason::ChangedInput(input.clone())),
+                None => Some(Reason::ChangedInputs),
+            }
+        });
+        Some(changed)
+    }
+
+    /// Records that `command` produced `outputs` from `inputs`.
+    pub fn record(&self, inputs: &[PathBuf], outputs: &[PathBuf], command: &str) {
+        let Some(key) = outputs.first() else {
+            return;
+        };
+        let previous = self
+            .edges
+            .lock()
+            .expect("state mutex poisoned")
+            .get(key)
+            .cloned();
+        let inputs = inputs
+            .par_iter()
+            .map(|input| self.current(input, previous.as_ref()))
+            .collect();
+        let record = EdgeRecord {
+            command: sha256_bytes(command.as_bytes()),
+            inputs,
+            outputs: outputs.to_vec(),
+        };
+        self.edges
+            .lock()
+            .expect("state mutex poisoned")
+            .insert(key.clone(), record);
+    }
+
+    /// Writes the state back to the build directory.
+    pub fn save(&self) -> Result<()> {
+        let file = StateFile {
+            version: STATE_VERSION,
+            edges: self.edges.lock().expect("state mutex poisoned").clone(),
+            files: self.known_files(),
+            watch: self.watch.clone(),
+        };
+        fs::write(&self.path, serde_json::to_string(&file)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        *self.written.lock().expect("state mutex poisoned") = version(&self.path);
+        Ok(())
+    }
+
+    /// Inputs as they are at the daemon's token: those seen now, and those
+    /// from the last build that did not change since. Without a daemon
+    /// there is no token, and nothing to keep.
+    fn known_files(&self) -> BTreeMap<PathBuf, KnownFile> {
+        let mut files = BTreeMap::new();
+        if self.watch.is_some() {
+            files.extend(
+                self.files
+                    .keys()
+                    .filter_map(|path| Some((path.clone(), self.trusted(path)?))),
+            );
+            files.extend(self.seen.lock().expect("state mutex poisoned").clone());
+        }
+        files
+    }
+
+    /// What `input` resolves to, by [`paths::identity`], and its current
+    /// stamp, reusing the one `record` holds when the content did not
+    /// change. An input the daemon saw no change to keeps what the last
+    /// build recorded without touching the file system.
+    fn current(&self, input: &Path, record: Option<&EdgeRecord>) -> (PathBuf, FileStamp) {
+        let absolute = std::path::absolute(input).unwrap_or_else(|_| input.to_path_buf());
+        let file = match self.trusted(&absolute) {
+            Some(file) => file,
+            None => {
+                let identity = paths::identity(Path::new(""), input);
+                let known = record.and_then(|record| record.inputs.get(&identity));
+                let stamp = self.stamp(input, known);
+                KnownFile { identity, stamp }
+            }
+        };
+        self.seen
+            .lock()
+            .expect("state mutex poisoned")
+            .insert(absolute, file.clone());
+        (file.identity, file.stamp)
+    }
+
+    /// The last build's view of the file at `absolute` when the daemon
+    /// reports neither its path nor what it resolved to as changed since.
+    /// Directories and missing files are always looked at again.
+    fn trusted(&self, absolute: &Path) -> Option<KnownFile> {
+        let changes = self.changes.as_ref()?;
+        let file = self.files.get(absolute)?;
+        (file.stamp.mtime_ns != 0
+            && changes.unchanged(absolute)
+            && changes.unchanged(&file.identity))
+        .then(|| file.clone())
+    }
+
+    /// The current stamp of `path`, reusing the hash of `known` or of an
+    /// earlier stamp in this build when the modification time and size are
+    /// unchanged. A directory's hash is the digest of its tree, taken every
+    /// time since the directory's own time misses changes deeper down.
+    fn stamp(&self, path: &Path, known: Option<&FileStamp>) -> FileStamp {
+        let meta = fs::metadata(path).ok();
+        if meta.as_ref().is_some_and(|meta| meta.is_dir()) {
+            return FileStamp {
+                mtime_ns: 0,
+                size: 0,
+                sha256: tree_digest(path).unwrap_or_else(|_| "-".into()),
+            };
+        }
+        let mtime_ns = meta
+            .as_ref()
+            .and_then(|meta| meta.modified().ok())
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |age| age.as_nanos());
+        let size = meta.as_ref().map_or(0, |meta| meta.len());
+        let unchanged = |stamp: &&FileStamp| stamp.mtime_ns == mtime_ns && stamp.size == size;
+
+        let cached = self
+            .stamps
+            .lock()
+            .expect("state mutex poisoned")
+            .get(path)
+            .filter(unchanged)
+            .cloned();
+        if let Some(stamp) = cached.or_else(|| known.filter(unchanged).cloned()) {
+            return stamp;
+        }
+        let sha256 = match meta {
+            Some(_) => sha256_file(path).unwrap_or_else(|_| "-".into()),
+            None => "-".into(),
+        };
+        let stamp = FileStamp {
+            mtime_ns,
+            size,
+            sha256,
+        };
+        self.stamps
+            .lock()
+            .expect("state mutex poisoned")
+            .insert(path.to_path_buf(), stamp.clone());
+        stamp
+    }
+}
+
+/// The token of the daemon watching the project of `out_dir`, if one runs,
+/// and what changed after `since` when it knows.
+fn watch(out_dir: &Path, since: Option<&Token>) -> (Option<Token>, Option<Changes>) {
+    match daemon::changes_since(out_dir, since) {
+        Some((token, changes)) => (Some(token), changes),
+        None => (None, None),
+    }
+}
+
+/// Modification time and size of the state file at `path`, which tell
+/// whether another crust wrote it.
+fn version(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decides_staleness_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("main.c");
+        let object = dir.path().join("main.o");
+        fs::write(&source, "int main(void) { return 0; }").unwrap();
+        fs::write(&object, "obj").unwrap();
+        let inputs = vec![source.clone()];
+        let outputs = vec![object.clone()];
+
+        let command = "cc -c main.c -o main.o";
+
+        let state = StateDb::load(dir.path());
+        assert_eq!(state.stale_reason(&inputs, &outputs, command), None);
+        state.record(&inputs, &outputs, command);
+        state.save().unwrap();
+
+        // Rewriting the same content is not a change; new content is.
+        let state = StateDb::load(dir.path());
+        fs::write(&source, "int main(void) { return 0; }").unwrap();
+        assert_eq!(state.stale_reason(&inputs, &outputs, command), Some(None));
+        // So is reaching the source through a symlink to its directory.
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+            let linked = [link.join("main.c")];
+            assert_eq!(state.stale_reason(&linked, &outputs, command), Some(None));
+        }
+        let changed_flags = "cc -O2 -c main.c -o main.o";
+        assert_eq!(
+            state.stale_reason(&inputs, &outputs, changed_flags),
+            Some(Some(Reason::ChangedCommand))
+        );
+        let state = StateDb::load(dir.path());
+        fs::write(&source, "int main(void) { return 10; }").unwrap();
+        assert_eq!(
+            state.stale_reason(&inputs, &outputs, command),
+            Some(Some(Reason::ChangedInput(source)))
+        );
+        assert_eq!(
+            state.stale_reason(&[], &outputs, command),
+            Some(Some(Reason::ChangedInputs))
+        );
+    }
+
+    #[test]
+    fn reuses_hashes_while_mtime_and_size_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("main.c");
+        let object = dir.path().join("main.o");
+        fs::write(&source, "int main(void) { return 0; }").unwrap();
+        fs::write(&object, "obj").unwrap();
+        let (inputs, outputs) = (vec![source.clone()], vec![object]);
+        let command = "cc -c main.c -o main.o";
+        let state = StateDb::load(dir.path());
+        state.record(&inputs, &outputs, command);
+        state.save().unwrap();
+        let mtime = fs::metadata(&source).unwrap().modified().unwrap();
+        let rewrite = |content: &str| {
+            fs::write(&source, content).unwrap();
+            let file = fs::File::options().write(true).open(&source).unwrap();
+            file.set_modified(mtime).unwrap();
+        };
+
+        // New content of the same size and time is not read: the recorded
+        // hash is reused, in this build and in the next.
+        rewrite("int main(void) { return 1; }");
+        let state = StateDb::load(dir.path());
+        assert_eq!(state.stale_reason(&inputs, &outputs, command), Some(None));
+        assert_eq!(state.stale_reason(&inputs, &outputs, command), Some(None));
+
+        // Another size makes both hash the file again.
+        rewrite("int main(void) { return 10; }");
+        assert_eq!(
+            state.stale_reason(&inputs, &outputs, command),
+            Some(Some(Reason::ChangedInput(source.clone())))
+        );
+        let state = StateDb::load(dir.path());
+        assert_eq!(
+            state.stale_reason(&inputs, &outputs, command),
+            Some(Some(Reason::ChangedInput(source.clone())))
+        );
+
+        // So does another time with the recorded size.
+        rewrite("int main(void) { return 2; }");
+        let file = fs::File::options().write(true).open(&source).unwrap();
+        file.set_modified(mtime + std::time::Duration::from_secs(5))
+            .unwrap();
+        let state = StateDb::load(dir.path());
+        assert_eq!(
+            state.stale_reason(&inputs, &outputs, command),
+            Some(Some(Reason::ChangedInput(source)))
+        );
+    }
+
+    #[test]
+    fn trusts_inputs_the_daemon_saw_no_change_to() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().canonicalize().unwrap();
+        let source = dir.join("main.c");
+        let object = dir.join("main.o");
+        fs::write(&source, "int main(void) { return 0; }").unwrap();
+        fs::write(&object, "obj").unwrap();
+        let (inputs, outputs) = (vec![source.clone()], vec![object]);
+        let command = "cc -c main.c -o main.o";
+        let token = Token {
+            epoch: "1".into(),
+            seq: 0,
+        };
+
+        let mut state = StateDb::load(&dir);
+        state.watch = Some(token.clone());
+        state.record(&inputs, &outputs, command);
+        state.save().unwrap();
+
+        // Nothing looks at a file the daemon reports unchanged, so an edit
+        // it missed goes unnoticed; one it saw does not.
+        fs::write(&source, "int main(void) { return 1; }").unwrap();
+        let unchanged = |changed: &[&Path]| {
+            let mut state = StateDb::load(&dir);
+            state.watch = Some(token.clone());
+            let changed = changed.iter().map(|path| path.to_path_buf()).collect();
+            state.changes = Some(Changes::new(dir.clone(), Vec::new(), changed));
+            state.stale_reason(&inputs, &outputs, command)
+        };
+        assert_eq!(unchanged(&[]), Some(None));
+        assert_eq!(
+            unchanged(&[&source]),
+            Some(Some(Reason::ChangedInput(source.clone())))
+        );
+        assert_eq!(
+            unchanged(&[&dir]),
+            Some(Some(Reason::ChangedInput(source.clone())))
+        );
+    }
+
+    #[test]
+    fn reloads_saved_state_unless_another_build_rewrote_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("main.c");
+        let object = dir.path().join("main.o");
+        fs::write(&source, "int main(void) { return 0; }").unwrap();
+        fs::write(&object, "obj").unwrap();
+        let (inputs, outputs) = (vec![source], vec![object]);
+        let command = "cc -c main.c -o main.o";
+
+        let state = StateDb::load(dir.path());
+        state.record(&inputs, &outputs, command);
+        state.save().unwrap();
+        let state = state.reload(dir.path());
+        assert_eq!(state.stale_reason(&inputs, &outputs, command), Some(None));
+        state.save().unwrap();
+
+        fs::write(dir.path().join(STATE_FILE), "{}").unwrap();
+        let state = state.reload(dir.path());
+        assert_eq!(state.stale_reason(&inputs, &outputs, command), None);
+    }
+}
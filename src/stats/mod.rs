@@ -0,0 +1,326 @@
+use crate::backend::BackendEmitResult;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// File in the build directory holding the summaries of the last builds.
+pub const STATS_FILE: &str = ".crust_stats";
+
+/// Version of the stats file layout; a file with another version is ignored.
+const STATS_VERSION: u32 = 1;
+
+/// Number of build summaries kept: the latest and the one before it.
+const KEPT_BUILDS: usize = 2;
+
+/// A target slower than before by more than this fraction, and by at least
+/// `REGRESSION_MIN`, is reported as a regression.
+const REGRESSION_RATIO: f64 = 0.10;
+const REGRESSION_MIN: Duration = Duration::from_millis(50);
+
+/// How long one target took in a build.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TargetStat {
+    pub name: String,
+    /// Whether any of its commands ran; a target found up to date took no
+    /// time worth comparing.
+    pub built: bool,
+    pub seconds: f64,
+}
+
+/// Summary of one build.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildStats {
+    pub seconds: f64,
+    /// Targets in build order.
+    pub targets: Vec<TargetStat>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StatsFile {
+    version: u32,
+    /// Oldest first.
+    builds: Vec<BuildStats>,
+}
+
+impl BuildStats {
+    pub fn new(result: &BackendEmitResult, elapsed: Duration) -> Self {
+        BuildStats {
+            seconds: elapsed.as_secs_f64(),
+            targets: result
+                .target_summaries
+                .iter()
+                .map(|target| TargetStat {
+                    name: target.name.clone(),
+                    built: target.built,
+                    seconds: target.duration.as_secs_f64(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Summaries recorded in `out_dir`, oldest first. A missing, unreadable, or
+/// outdated file gives none.
+pub fn load(out_dir: &Path) -> Vec<BuildStats> {
+    fs::read_to_string(out_dir.join(STATS_FILE))
+        .ok()
+        .and_then(|text| serde_json::from_str::<StatsFile>(&text).ok())
+        .filter(|file| file.version == STATS_VERSION)
+        .map(|file| file.builds)
+        .unwrap_or_default()
+}
+
+/// Appends `build` to the summaries of `out_dir`, dropping the oldest.
+pub fn record(out_dir: &Path, build: BuildStats) -> Result<()> {
+    let mut builds = load(out_dir);
+    builds.push(build);
+    let excess = builds.len().saturating_sub(KEPT_BUILDS);
+    builds.drain(..excess);
+    let path = out_dir.join(STATS_FILE);
+    let file = StatsFile {
+        version: STATS_VERSION,
+        builds,
+    };
+    fs::write(&path, serde_json::to_string(&file)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Expected durations of targets, from the recorded builds, for telling
+/// how long a build still has to go.
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+    seconds: HashMap<String, f64>,
+    /// For targets without history: the mean of the known ones.
+    fallback: f64,
+}
+
+impl Estimate {
+    /// Averages each target over the builds that built it. A target only
+    /// ever found up to date is expected to take what it took then.
+    pub fn new(builds: &[BuildStats]) -> Self {
+        let mut samples: HashMap<&str, (f64, usize)> = HashMap::new();
+        let mut idle: HashMap<&str, f64> = HashMap::new();
+        for target in builds.iter().flat_map(|build| &build.targets) {
+            if target.built {
+                let sample = samples.entry(&target.name).or_default();
+                sample.0 += target.seconds;
+                sample.1 += 1;
+            } else {
+                idle.insert(&target.name, target.seconds);
+            }
+        }
+        let mut seconds: HashMap<String, f64> = idle
+            .into_iter()
+            .map(|(name, seconds)| (name.to_string(), seconds))
+            .collect();
+        for (name, (sum, count)) in samples {
+            seconds.insert(name.to_string(), sum / count as f64);
+        }
+        let fallback = if seconds.is_empty() {
+            0.0
+        } else {
+            seconds.values().sum::<f64>() / seconds.len() as f64
+        };
+        Estimate { seconds, fallback }
+    }
+
+    fn expected(&self, target: &str) -> f64 {
+        self.seconds.get(target).copied().unwrap_or(self.fallback)
+    }
+
+    /// Time left for the `pending` targets and the `running` ones, each with
+    /// how long it has been running, shared out over `workers`, but no less
+    /// than the running target furthest from done needs. `None` without any
+    /// history.
+    pub fn remaining<'a>(
+        &self,
+        pending: impl IntoIterator<Item = &'a str>,
+        running: impl IntoIterator<Item = (&'a str, Duration)>,
+        workers: usize,
+    ) -> Option<Duration> {
+        if self.seconds.is_empty() {
+            return None;
+        }
+        let mut work: f64 = pending.into_iter().map(|name| self.expected(name)).sum();
+        let mut longest: f64 = 0.0;
+        for (name, elapsed) in running {
+            let left = (self.expected(name) - elapsed.as_secs_f64()).max(0.0);
+            work += left;
+            longest = longest.max(left);
+        }
+        Some(Duration::from_secs_f64(
+            (work / workers.max(1) as f64).max(longest),
+        ))
+    }
+}
+
+/// Per-target durations of `build`.
+pub fn report(build: &BuildStats) -> String {
+    let mut rows = vec![["TARGET".to_string(), "TIME".to_string(), String::new()]];
+    for target in &build.targets {
+        let status = if target.built { "built" } else { "up to date" };
+        rows.push([
+            target.name.clone(),
+            format_seconds(target.seconds),
+            status.to_string(),
+        ]);
+    }
+    rows.push([
+        "total".to_string(),
+        format_seconds(build.seconds),
+        String::new(),
+    ]);
+    columns(&rows)
+}
+
+/// Per-target duration deltas of `current` against `previous`. Targets
+/// built in both runs get a delta, marked as a regression when notably
+/// slower; the others say why they cannot be compared.
+pub fn compare(previous: &BuildStats, current: &BuildStats) -> String {
+    let mut rows = vec![[
+        "TARGET".to_string(),
+        "PREVIOUS".to_string(),
+        "CURRENT".to_string(),
+        "CHANGE".to_string(),
+    ]];
+    for target in &current.targets {
+        let before = previous
+            .targets
+            .iter()
+            .find(|prev| prev.name == target.name);
+        let change = match before {
+            None => "new target".to_string(),
+            Some(before) if !before.built || !target.built => "not built in both runs".to_string(),
+            Some(before) => delta(before.seconds, target.seconds),
+        };
+        rows.push([
+            target.name.clone(),
+            before.map_or("-".to_string(), |before| format_seconds(before.seconds)),
+            format_seconds(target.seconds),
+            change,
+        ]);
+    }
+    rows.push([
+        "total".to_string(),
+        format_seconds(previous.seconds),
+        format_seconds(current.seconds),
+        delta(previous.seconds, current.seconds),
+    ]);
+    columns(&rows)
+}
+
+fn delta(before: f64, after: f64) -> String {
+    let diff = after - before;
+    let mut text = format!("{:+.2}s", diff);
+    if before > 0.0 {
+        text.push_str(&format!(" ({:+.0}%)", diff / before * 100.0));
+    }
+    if diff > before * REGRESSION_RATIO && diff >= REGRESSION_MIN.as_secs_f64() {
+        text.push_str("  REGRESSION");
+    }
+    text
+}
+
+fn format_seconds(seconds: f64) -> String {
+    format!("{seconds:.2}s")
+}
+
+/// `rows` as left-aligned columns separated by two spaces.
+fn columns<const N: usize>(rows: &[[String; N]]) -> String {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut out = String::new();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(name: &str, built: bool, seconds: f64) -> TargetStat {
+        TargetStat {
+            name: name.into(),
+            built,
+            seconds,
+        }
+    }
+
+    #[test]
+    fn keeps_two_builds_and_flags_regressions() {
+        let dir = tempfile::tempdir().unwrap();
+        for seconds in [9.0, 2.0, 3.0] {
+            let build = BuildStats {
+                seconds,
+                targets: vec![
+                    stat("core", true, seconds - 1.0),
+                    stat("app", true, 0.5),
+                    stat("tools", seconds < 3.0, 0.25),
+                ],
+            };
+            record(dir.path(), build).unwrap();
+        }
+        let builds = load(dir.path());
+        assert_eq!(builds.len(), 2);
+        assert_eq!(builds[0].seconds, 2.0);
+
+        let mut current = builds[1].clone();
+        current.targets.push(stat("extra", true, 0.1));
+        assert_eq!(
+            compare(&builds[0], &current),
+            "TARGET  PREVIOUS  CURRENT  CHANGE\n\
+             core    1.00s     2.00s    +1.00s (+100%)  REGRESSION\n\
+             app     0.50s     0.50s    +0.00s (+0%)\n\
+             tools   0.25s     0.25s    not built in both runs\n\
+             extra   -         0.10s    new target\n\
+             total   2.00s     3.00s    +1.00s (+50%)  REGRESSION\n"
+        );
+        assert!(report(&current).contains("tools   0.25s  up to date\n"));
+    }
+
+    #[test]
+    fn estimates_remaining_time_from_history() {
+        let builds = [
+            BuildStats {
+                seconds: 5.0,
+                targets: vec![stat("core", true, 4.0), stat("app", true, 1.0)],
+            },
+            BuildStats {
+                seconds: 2.0,
+                targets: vec![stat("core", true, 2.0), stat("app", false, 0.0)],
+            },
+        ];
+        let estimate = Estimate::new(&builds);
+        let secs = |d: Option<Duration>| d.map(|d| d.as_secs_f64());
+        // core 3s, app 1s, and an unknown target the mean of both, 2s.
+        assert_eq!(
+            secs(estimate.remaining(["core", "app", "new"], [], 2)),
+            Some(3.0)
+        );
+        // core has 2.5s to go, more than the 1.75s per worker.
+        assert_eq!(
+            secs(estimate.remaining(["app"], [("core", Duration::from_millis(500))], 2)),
+            Some(2.5)
+        );
+        assert_eq!(
+            secs(estimate.remaining([], [("core", Duration::from_secs(9))], 1)),
+            Some(0.0)
+        );
+        assert_eq!(Estimate::new(&[]).remaining(["core"], [], 1), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Locates `program` on the current `PATH`, returning the first executable match.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    find_program_in(program, env::split_paths(&path))
+}
+
+/// Locates `program` in the given search directories. Names containing a path
+/// separator are checked directly instead of being searched for.
+pub fn find_program_in<I>(program: &str, dirs: I) -> Option<PathBuf>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let candidate = Path::new(program);
+    if candidate.components().count() > 1 {
+        return is_executable(candidate).then(|| candidate.to_path_buf());
+    }
+
+    dirs.into_iter().find_map(|dir| {
+        executable_names(program)
+            .into_iter()
+            .map(|name| dir.join(name))
+            .find(|path| is_executable(path))
+    })
+}
+
+/// Runs `<program> --version` and returns the first non-empty line of output.
+pub fn program_version(program: &Path) -> Option<String> {
+    let output = Command::new(program).arg("--version").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+/// Builds a command that runs `wrapper` with `wrapper_args` followed by the
+/// program and arguments of `inner`, carrying over its working directory and
+/// environment overrides.
+pub fn wrap_command<I, S>(wrapper: &str, wrapper_args: I, inner: &Command) -> Command
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut wrapped = Command::new(wrapper);
+    wrapped
+        .args(wrapper_args)
+        .arg(inner.get_program())
+        .args(inner.get_args());
+    if let Some(dir) = inner.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    for (key, value) in inner.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    wrapped
+}
+
+fn executable_names(program: &str) -> Vec<String> {
+    if cfg!(windows) && Path::new(program).extension().is_none() {
+        vec![format!("{program}.exe"), program.to_string()]
+    } else {
+        vec![program.to_string()]
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(OsStr::to_str)
+            .map_or(true, |ext| ext.eq_ignore_ascii_case("exe"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn finds_program_in_search_dirs() {
+        let empty = tempdir().unwrap();
+        let bin = tempdir().unwrap();
+        let tool = bin.path().join(if cfg!(windows) {
+            "mytool.exe"
+        } else {
+            "mytool"
+        });
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let dirs = vec![empty.path().to_path_buf(), bin.path().to_path_buf()];
+        assert_eq!(find_program_in("mytool", dirs.clone()), Some(tool));
+        assert_eq!(find_program_in("othertool", dirs), None);
+    }
+}
@@ -0,0 +1,532 @@
+use crate::error::CrustError;
+use crate::stats::Estimate;
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Lines kept in the log pane.
+const LOG_LINES: usize = 2000;
+
+/// How often the screen is redrawn while building.
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// Lines moved by Page Up and Page Down in the output of a failed target.
+const PAGE: u16 = 10;
+
+/// The monitor of the running `crust build --ui` or daemon build, if any.
+static MONITOR: Mutex<Option<Arc<Monitor>>> = Mutex::new(None);
+
+/// The active build monitor. The executor reports targets starting and
+/// finishing to it, log events and command output are routed to it instead
+/// of the console.
+pub fn monitor() -> Option<Arc<Monitor>> {
+    MONITOR.lock().expect("monitor mutex poisoned").clone()
+}
+
+/// Passes the captured stderr of a command of `target` to the monitor, or
+/// through to stderr when there is none.
+pub fn command_output(target: &str, text: &str) {
+    match monitor() {
+        Some(monitor) => monitor.output(target, text),
+        None => eprint!("{text}"),
+    }
+}
+
+/// What the build monitor shows.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    state: Mutex<State>,
+    /// Where a monitor without a screen passes the build on, see
+    /// [`Monitor::streaming`].
+    events: Option<Sender<BuildEvent>>,
+}
+
+/// Something that happened in a build, as `crust daemon` streams it to the
+/// client that asked for the build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BuildEvent {
+    Started {
+        target: String,
+    },
+    Finished {
+        target: String,
+        error: Option<String>,
+    },
+    /// A log event, as the console would print it.
+    Log {
+        line: String,
+    },
+    /// Captured stderr of a command of `target`.
+    Output {
+        target: String,
+        text: String,
+    },
+}
+
+#[derive(Debug)]
+struct State {
+    started: Instant,
+    total: usize,
+    done: usize,
+    workers: usize,
+    /// Targets not started yet.
+    pending: HashSet<String>,
+    /// Targets running, the longest-running first.
+    running: Vec<Running>,
+    /// Expected target durations from earlier builds, for the time left.
+    estimate: Estimate,
+    log: VecDeque<String>,
+    /// Command output and errors of each target.
+    output: HashMap<String, String>,
+    failed: Vec<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            started: Instant::now(),
+            total: 0,
+            done: 0,
+            workers: 0,
+            pending: HashSet::new(),
+            running: Vec::new(),
+            estimate: Estimate::default(),
+            log: VecDeque::new(),
+            output: HashMap::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Running {
+    target: String,
+    /// Its latest log line.
+    step: String,
+    since: Instant,
+}
+
+impl State {
+    /// Estimated time until the build is done, once earlier builds were
+    /// recorded.
+    fn time_left(&self) -> Option<Duration> {
+        self.estimate.remaining(
+            self.pending.iter().map(String::as_str),
+            self.running
+                .iter()
+                .map(|task| (task.target.as_str(), task.since.elapsed())),
+            self.workers,
+        )
+    }
+
+    fn log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+}
+
+impl Monitor {
+    /// A monitor estimating the time left from the durations in `estimate`.
+    pub fn new(estimate: Estimate) -> Self {
+        let monitor = Monitor::default();
+        monitor.state().estimate = estimate;
+        monitor
+    }
+
+    /// A monitor that passes everything it is told on to `events`.
+    pub fn streaming(events: Sender<BuildEvent>) -> Self {
+        Monitor {
+            events: Some(events),
+            ..Monitor::default()
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("monitor state mutex poisoned")
+    }
+
+    /// Passes `event` on when streaming; a client that went away misses it.
+    fn send(&self, event: BuildEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    /// Starts a build of `targets` on `workers` workers.
+    pub fn begin(&self, targets: impl IntoIterator<Item = String>, workers: usize) {
+        let mut state = self.state();
+        state.pending = targets.into_iter().collect();
+        state.total = state.pending.len();
+        state.workers = workers;
+    }
+
+    pub fn started(&self, target: &str) {
+        self.send(BuildEvent::Started {
+            target: target.to_string(),
+        });
+        let mut state = self.state();
+        state.pending.remove(target);
+        state.running.push(Running {
+            target: target.to_string(),
+            step: "starting".to_string(),
+            since: Instant::now(),
+        });
+    }
+
+    /// Records `target` as finished, or as failed with `error`.
+    pub fn finished(&self, target: &str, error: Option<&anyhow::Error>) {
+        self.send(BuildEvent::Finished {
+            target: target.to_string(),
+            error: error.map(|error| format!("{error:#}")),
+        });
+        let mut state = self.state();
+        state.running.retain(|task| task.target != target);
+        state.done += 1;
+        if let Some(error) = error {
+            state.failed.push(target.to_string());
+            let message = format!("error: {error:#}");
+            state
+                .output
+                .entry(target.to_string())
+                .or_default()
+                .push_str(&format!("{message}\n"));
+            state.log(format!("[{target}] {message}"));
+        }
+    }
+
+    /// Adds a log line, which becomes the current step of `target` while it
+    /// runs.
+    pub fn log(&self, target: Option<&str>, line: String) {
+        self.send(BuildEvent::Log { line: line.clone() });
+        let mut state = self.state();
+        if let Some(target) = target {
+            if let Some(task) = state.running.iter_mut().find(|task| task.target == target) {
+                task.step.clone_from(&line);
+            }
+        }
+        state.log(line);
+    }
+
+    /// Adds command output of `target` to its output and the log.
+    fn output(&self, target: &str, text: &str) {
+        self.send(BuildEvent::Output {
+            target: target.to_string(),
+            text: text.to_string(),
+        });
+        let mut state = self.state();
+        state
+            .output
+            .entry(target.to_string())
+            .or_default()
+            .push_str(text);
+        for line in text.lines() {
+            state.log(line.to_string());
+        }
+    }
+
+    /// Progress gauge, one row per worker, and the log.
+    fn draw_build(&self, frame: &mut Frame) {
+        let state = self.state();
+        let [progress, workers, log] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(state.workers.max(1) as u16 + 2),
+            Constraint::Min(3),
+        ])
+        .areas(frame.area());
+
+        let ratio = if state.total == 0 {
+            0.0
+        } else {
+            state.done as f64 / state.total as f64
+        };
+        let mut label = format!(
+            "{}/{} targets, {:.0}%, {:.1}s",
+            state.done,
+            state.total,
+            ratio * 100.0,
+            state.started.elapsed().as_secs_f64()
+        );
+        if let Some(left) = state.time_left() {
+            label.push_str(&format!(", about {:.0}s left", left.as_secs_f64().ceil()));
+        }
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(" crust build "))
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(ratio)
+            .label(label);
+        frame.render_widget(gauge, progress);
+
+        let rows: Vec<Line> = (0..state.workers.max(1))
+            .map(|slot| match state.running.get(slot) {
+                Some(task) => Line::from(format!("{}: {}", task.target, task.step)),
+                None => Line::styled("idle", Style::default().add_modifier(Modifier::DIM)),
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(rows).block(Block::bordered().title(" workers ")),
+            workers,
+        );
+
+        let height = log.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = state
+            .log
+            .iter()
+            .skip(state.log.len().saturating_sub(height))
+            .map(|line| Line::from(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" log ")),
+            log,
+        );
+    }
+
+    /// The failed targets, `selected` one highlighted, next to its output
+    /// scrolled down by `scroll` lines.
+    fn draw_failures(&self, frame: &mut Frame, selected: &mut ListState, scroll: u16) {
+        let state = self.state();
+        let [list, output] =
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
+                .areas(frame.area());
+        let title = format!(
+            " {} failed: up/down select, PgUp/PgDn scroll, q quit ",
+            state.failed.len()
+        );
+        let targets = List::new(state.failed.iter().map(String::as_str))
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(targets, list, selected);
+
+        let target = selected
+            .selected()
+            .and_then(|index| state.failed.get(index))
+            .map_or("", String::as_str);
+        let text = state.output.get(target).map_or("", String::as_str);
+        frame.render_widget(
+            Paragraph::new(text)
+                .block(Block::bordered().title(format!(" {target} ")))
+                .scroll((scroll, 0)),
+            output,
+        );
+    }
+}
+
+/// A full-screen build monitor, from [`start`] until [`Session::finish`].
+pub struct Session {
+    monitor: Arc<Monitor>,
+    stop: Arc<AtomicBool>,
+    drawer: Option<JoinHandle<io::Result<DefaultTerminal>>>,
+}
+
+/// Switches the terminal to the build monitor, which then receives the
+/// build's progress, log, and command output until the session finishes.
+/// `estimate` gives the time left in the progress line.
+pub fn start(estimate: Estimate) -> Result<Session> {
+    if !io::stdout().is_terminal() {
+        return Err(CrustError::InvalidArgument {
+            message: "--ui needs stdout to be a terminal".into(),
+        }
+        .into());
+    }
+    let terminal = ratatui::try_init().context("Failed to set up the terminal")?;
+    let monitor = Arc::new(Monitor::new(estimate));
+    *MONITOR.lock().expect("monitor mutex poisoned") = Some(Arc::clone(&monitor));
+    let stop = Arc::new(AtomicBool::new(false));
+    let drawer = thread::spawn({
+        let monitor = Arc::clone(&monitor);
+        let stop = Arc::clone(&stop);
+        move || draw_until(terminal, &monitor, &stop)
+    });
+    Ok(Session {
+        monitor,
+        stop,
+        drawer: Some(drawer),
+    })
+}
+
+/// Redraws the build view until `stop` is set. Raw mode turns Ctrl-C into
+/// a key press, which restores the terminal and interrupts crust and its
+/// commands like the terminal would have.
+fn draw_until(
+    mut terminal: DefaultTerminal,
+    monitor: &Monitor,
+    stop: &AtomicBool,
+) -> io::Result<DefaultTerminal> {
+    while !stop.load(Ordering::SeqCst) {
+        terminal.draw(|frame| monitor.draw_build(frame))?;
+        if event::poll(REFRESH)? {
+            if let Event::Key(key) = event::read()? {
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    ratatui::restore();
+                    crate::executor::cancel::interrupt();
+                    interrupt_process_group();
+                    std::process::exit(130);
+                }
+            }
+        }
+    }
+    terminal.draw(|frame| monitor.draw_build(frame))?;
+    Ok(terminal)
+}
+
+/// A monitor installed without a screen, from [`attach`] until dropped.
+pub struct Attached(());
+
+/// Makes `monitor` the active one, which then receives the build's
+/// progress, log, and command output until the returned guard is dropped.
+pub fn attach(monitor: Arc<Monitor>) -> Attached {
+    *MONITOR.lock().expect("monitor mutex poisoned") = Some(monitor);
+    Attached(())
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        *MONITOR.lock().expect("monitor mutex poisoned") = None;
+    }
+}
+
+/// Sends SIGINT to the process group of crust, which holds the processes it
+/// starts outside of builds, such as tests; build commands lead groups of
+/// their own.
+#[cfg(unix)]
+fn interrupt_process_group() {
+    let _ = std::process::Command::new("kill")
+        .args(["-INT", "0"])
+        .status();
+}
+
+#[cfg(not(unix))]
+fn interrupt_process_group() {}
+
+impl Session {
+    /// Stops the monitor. When targets failed, their output can be browsed
+    /// before the terminal is restored.
+    pub fn finish(mut self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        let drawer = self.drawer.take().expect("monitor drawing");
+        let mut terminal = drawer
+            .join()
+            .map_err(|_| anyhow::anyhow!("Build monitor panicked"))??;
+        *MONITOR.lock().expect("monitor mutex poisoned") = None;
+        if self.monitor.state().failed.is_empty() {
+            return Ok(());
+        }
+        browse_failures(&mut terminal, &self.monitor)?;
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(drawer) = self.drawer.take() {
+            let _ = drawer.join();
+        }
+        *MONITOR.lock().expect("monitor mutex poisoned") = None;
+        ratatui::restore();
+    }
+}
+
+fn browse_failures(terminal: &mut DefaultTerminal, monitor: &Monitor) -> io::Result<()> {
+    let mut selected = ListState::default().with_selected(Some(0));
+    let mut scroll = 0;
+    loop {
+        terminal.draw(|frame| monitor.draw_failures(frame, &mut selected, scroll))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => {
+                selected.select_previous();
+                scroll = 0;
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                selected.select_next();
+                scroll = 0;
+            }
+            KeyCode::PageUp => scroll = scroll.saturating_sub(PAGE),
+            KeyCode::PageDown => scroll = scroll.saturating_add(PAGE),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::{BuildStats, TargetStat};
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn draws_workers_progress_and_failed_output() {
+        let history = BuildStats {
+            seconds: 12.0,
+            targets: ["core", "app", "tools", "docs"]
+                .map(|name| TargetStat {
+                    name: name.into(),
+                    built: true,
+                    seconds: 5.0,
+                })
+                .into(),
+        };
+        let monitor = Monitor::new(Estimate::new(&[history]));
+        monitor.begin(["core", "app", "tools", "docs"].map(String::from), 2);
+        for target in ["core", "app", "tools"] {
+            monitor.started(target);
+        }
+        monitor.log(Some("core"), "Compiling src/core.c".into());
+        monitor.finished("core", None);
+        monitor.output("tools", "tools.c:1:1: error: expected ';'\n");
+        monitor.finished("tools", Some(&anyhow::anyhow!("cc exited with 1")));
+
+        let mut terminal = Terminal::new(TestBackend::new(60, 14)).unwrap();
+        terminal.draw(|frame| monitor.draw_build(frame)).unwrap();
+        let build = screen(&terminal);
+        assert!(build.contains("2/4 targets, 50%"));
+        // docs waits and app runs, 10s of work over two workers.
+        assert!(build.contains("about 5s left"));
+        assert!(build.contains("│app: starting"));
+        assert!(build.contains("│idle"));
+        assert!(build.contains("Compiling src/core.c"));
+
+        let mut selected = ListState::default().with_selected(Some(0));
+        terminal
+            .draw(|frame| monitor.draw_failures(frame, &mut selected, 0))
+            .unwrap();
+        let failures = screen(&terminal);
+        assert!(failures.contains("1 failed"));
+        assert!(failures.contains("│tools"));
+        assert!(failures.contains("tools.c:1:1: error: expected ';'"));
+        assert!(failures.contains("error: cc exited with 1"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Placeholder replaced by the version in a `vcs_version` template.
+pub const REPLACE_STRING: &str = "@VCS_TAG@";
+
+/// Settings of a vcs_version target.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VcsSettings {
+    /// Template (relative to the manifest) containing `@VCS_TAG@`.
+    pub template: Option<String>,
+    /// Version written when the source tree is not a git checkout.
+    pub fallback: String,
+}
+
+fn git(manifest_dir: &Path, args: &[&str]) -> Option<String> {
+    let mut cmd = Command::new("git");
+    cmd.args(args);
+    if !manifest_dir.as_os_str().is_empty() {
+        cmd.current_dir(manifest_dir);
+    }
+    let output = cmd.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// The repository's `.git` directory, or `None` outside a checkout or
+/// without git installed.
+pub fn git_dir(manifest_dir: &Path) -> Option<PathBuf> {
+    git(manifest_dir, &["rev-parse", "--absolute-git-dir"]).map(PathBuf::from)
+}
+
+/// Files whose modification means `git describe` may print something new:
+/// `HEAD`, the branch it points to, packed refs, and the tags directory.
+/// Worktrees keep their refs in the common directory named by `commondir`.
+pub fn tracked_files(git_dir: &Path) -> Vec<PathBuf> {
+    let common = fs::read_to_string(git_dir.join("commondir"))
+        .map(|dir| git_dir.join(dir.trim()))
+        .unwrap_or_else(|_| git_dir.to_path_buf());
+    let mut files = vec![git_dir.join("HEAD")];
+    if let Some(head) = fs::read_to_string(git_dir.join("HEAD"))
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        files.push(common.join(head));
+    }
+    files.push(common.join("packed-refs"));
+    files.push(common.join("refs").join("tags"));
+    files
+}
+
+/// `git describe --tags --always` of the checkout containing `manifest_dir`.
+pub fn describe(manifest_dir: &Path) -> Option<String> {
+    git(manifest_dir, &["describe", "--tags", "--always"])
+}
+
+/// Content of the generated file: `template` with `@VCS_TAG@` replaced, or a
+/// header defining `VCS_TAG`.
+pub fn render(template: Option<&str>, tag: &str) -> String {
+    match template {
+        Some(template) => template.replace(REPLACE_STRING, tag),
+        None => format!(
+            "/* Generated by crust from git describe; do not edit. */\n\
+             #ifndef VCS_TAG\n#define VCS_TAG \"{}\"\n#endif\n",
+            tag.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn renders_tags_and_tracks_the_checked_out_branch() {
+        assert_eq!(
+            render(Some("const char *v = \"@VCS_TAG@\";\n"), "v1.2-3-gabc"),
+            "const char *v = \"v1.2-3-gabc\";\n"
+        );
+        assert!(render(None, "v1.2").contains("#define VCS_TAG \"v1.2\"\n"));
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        let files = tracked_files(dir.path());
+        assert_eq!(files[1], dir.path().join("refs/heads/main"));
+        assert_eq!(files[3], dir.path().join("refs/tags"));
+    }
+}